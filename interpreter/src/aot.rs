@@ -0,0 +1,490 @@
+//! lox build：把编译好的字节码翻译成 C，再交给系统的 C 编译器
+//!
+//! 每个 lox 函数变成一个 C 函数，指令按顺序展开成对运行时（aot_runtime.c）的调用，
+//! 跳转变成 goto，所以语义和字节码解释器一致。
+//! 现在支持的是不带类的那一部分语言：数字、字符串、列表、全局和局部变量、闭包、控制流、
+//! 字符串插值和大部分列表相关的内置函数。碰到不支持的指令在编译的时候就报错。
+
+use crate::bytecode;
+use crate::bytecode_interpreter;
+use crate::compiler;
+use crate::extensions;
+
+use std::collections::{ HashMap, HashSet };
+use std::fmt::Write;
+use std::path::Path;
+use std::process::Command;
+use std::sync::atomic::{ AtomicUsize, Ordering };
+
+static RUNTIME: &str = include_str!("aot_runtime.c");
+
+/**
+ * 运行时里实现了的内置函数：名字、参数个数、可选参数个数、C 函数名
+ */
+static NATIVES: &[(&str, u8, u8, &str)] = &[
+    ("clock", 0, 0, "lox_native_clock"),
+    ("exp", 1, 0, "lox_native_exp"),
+    ("sqrt", 1, 0, "lox_native_sqrt"),
+    ("len", 1, 0, "lox_native_len"),
+    ("push", 2, 0, "lox_native_push"),
+    ("pop", 1, 0, "lox_native_pop"),
+    ("insert", 3, 0, "lox_native_insert"),
+    ("remove", 2, 0, "lox_native_remove"),
+    ("contains", 2, 0, "lox_native_contains"),
+    ("indexOf", 2, 0, "lox_native_index_of"),
+    ("concat", 2, 0, "lox_native_concat"),
+    ("reverse", 1, 0, "lox_native_reverse"),
+    ("freeze", 1, 0, "lox_native_freeze"),
+];
+
+/* ---------- ---------- 入口 ---------- ---------- */
+
+/**
+ * 编译 lox 源码，生成可执行文件 output
+ */
+pub fn build(source: &str, output: &Path) -> Result<(), String> {
+    let extensions = extensions::Extensions { lists: true, lambdas: true };
+    let func = compiler::Compiler::compile(source.to_string(), extensions).map_err(|err| {
+        err.to_string()
+    })?;
+    let c_source = translate(&func)?;
+
+    // 同一个进程里可能同时在编译好几个程序（比如测试）
+    static BUILD_ID: AtomicUsize = AtomicUsize::new(0);
+    let dir = std::env
+        ::temp_dir()
+        .join(
+            format!("lox-build-{}-{}", std::process::id(), BUILD_ID.fetch_add(1, Ordering::Relaxed))
+        );
+    std::fs::create_dir_all(&dir).map_err(|err| err.to_string())?;
+    let c_path = dir.join("main.c");
+    std::fs::write(&c_path, c_source).map_err(|err| err.to_string())?;
+
+    let cc = std::env::var("CC").unwrap_or_else(|_| "cc".to_string());
+    let res = Command::new(&cc)
+        .arg("-O2")
+        .arg("-o")
+        .arg(output)
+        .arg(&c_path)
+        .arg("-lm")
+        .output();
+    let _ = std::fs::remove_dir_all(&dir);
+    let res = res.map_err(|err| format!("failed to run C compiler '{}': {}", cc, err))?;
+    if !res.status.success() {
+        return Err(
+            format!("C compiler failed:\n{}", String::from_utf8_lossy(&res.stderr))
+        );
+    }
+    Ok(())
+}
+
+/**
+ * 把脚本和它里面定义的所有函数翻译成一个完整的 C 程序（运行时也在里面）
+ */
+pub fn translate(script: &bytecode::Function) -> Result<String, String> {
+    let mut translator = Translator::default();
+    translator.collect_function(script);
+    translator.check_builtins()?;
+
+    let mut bodies = String::new();
+    for idx in 0..translator.functions.len() {
+        let function = translator.functions[idx];
+        translator.translate_function(idx, function, &mut bodies)?;
+    }
+
+    let mut out = String::from(RUNTIME);
+    out.push('\n');
+
+    // 先声明所有的函数，闭包的原型里要用到
+    for idx in 0..translator.functions.len() {
+        writeln!(out, "static void lox_fn_{}(Value *base);", idx).unwrap();
+    }
+    out.push('\n');
+    for (idx, function) in translator.functions.iter().enumerate() {
+        let params: Vec<String> = function.param_names
+            .iter()
+            .map(|name| c_string(name))
+            .collect();
+        writeln!(
+            out,
+            "static const char *const lox_params_{}[] = {{ {} }};",
+            idx,
+            if params.is_empty() { "NULL".to_string() } else { params.join(", ") }
+        ).unwrap();
+        writeln!(
+            out,
+            "static const FnProto lox_proto_{} = {{ {}, {}, lox_params_{}, {}, lox_fn_{} }};",
+            idx,
+            c_string(&function.name),
+            function.arity,
+            idx,
+            translator.upvalue_counts[idx],
+            idx
+        ).unwrap();
+    }
+    out.push('\n');
+    out.push_str(&translator.captures);
+    out.push('\n');
+    out.push_str(&bodies);
+
+    // 全局变量的名字、字符串常量、内置函数，然后从脚本开始执行
+    let names: Vec<String> = translator.globals
+        .iter()
+        .map(|name| c_string(name))
+        .collect();
+    writeln!(
+        out,
+        "static const char *const lox_names[] = {{ {} }};\n",
+        if names.is_empty() { "NULL".to_string() } else { names.join(", ") }
+    ).unwrap();
+    out.push_str("int main(void) {\n");
+    writeln!(out, "    lox_global_count = {};", translator.globals.len()).unwrap();
+    out.push_str("    lox_global_names = lox_names;\n");
+    writeln!(out, "    lox_constant_count = {};", translator.strings.len()).unwrap();
+    out.push_str("    lox_init();\n");
+    for (idx, s) in translator.strings.iter().enumerate() {
+        writeln!(
+            out,
+            "    lox_constants[{}] = OBJ_VAL(lox_copy_string({}, {}));",
+            idx,
+            c_string(s),
+            s.len()
+        ).unwrap();
+    }
+    for (name, arity, optional, c_name) in NATIVES {
+        if let Some(global) = translator.global_ids.get(*name) {
+            writeln!(
+                out,
+                "    lox_define_native({}, {}, {}, {}, {});",
+                global,
+                c_string(name),
+                arity,
+                optional,
+                c_name
+            ).unwrap();
+        }
+    }
+    out.push_str("    return lox_run(&lox_proto_0);\n}\n");
+    Ok(out)
+}
+
+/* ---------- ---------- 翻译 ---------- ---------- */
+
+#[derive(Default)]
+struct Translator<'a> {
+    functions: Vec<&'a bytecode::Function>, // 0 号是脚本本身
+    upvalue_counts: Vec<usize>,
+    globals: Vec<String>, // 全局变量在编译的时候分配下标
+    global_ids: HashMap<String, usize>,
+    defined_globals: HashSet<String>,
+    strings: Vec<String>, // 字符串常量
+    string_ids: HashMap<String, usize>,
+    function_ids: HashMap<*const bytecode::Function, usize>,
+    captures: String, // 每个 Closure 指令的上值位置表
+}
+
+impl<'a> Translator<'a> {
+    /**
+     * 给函数和它常量池里的函数编号，顺便收集全局变量的名字
+     */
+    fn collect_function(&mut self, function: &'a bytecode::Function) {
+        let idx = self.functions.len();
+        self.functions.push(function);
+        self.upvalue_counts.push(0);
+        self.function_ids.insert(function as *const _, idx);
+
+        for (op, _) in function.chunk.code.iter() {
+            match op {
+                bytecode::Op::DefineGlobal(name_idx) | bytecode::Op::DefineConstGlobal(name_idx) => {
+                    if let bytecode::Constant::String(name) = &function.chunk.constants[*name_idx] {
+                        self.defined_globals.insert(name.clone());
+                    }
+                    self.global(function, *name_idx);
+                }
+                bytecode::Op::GetGlobal(name_idx) | bytecode::Op::SetGlobal(name_idx) => {
+                    self.global(function, *name_idx);
+                }
+                _ => {}
+            }
+        }
+
+        for constant in function.chunk.constants.iter() {
+            if let bytecode::Constant::Function(closure) = constant {
+                self.collect_function(&closure.function);
+            }
+        }
+    }
+
+    fn global(&mut self, function: &bytecode::Function, name_idx: usize) -> usize {
+        let name = match &function.chunk.constants[name_idx] {
+            bytecode::Constant::String(name) => name.clone(),
+            _ => panic!("expected a string constant for a global name"),
+        };
+        if let Some(id) = self.global_ids.get(&name) {
+            return *id;
+        }
+        self.globals.push(name.clone());
+        self.global_ids.insert(name, self.globals.len() - 1);
+        self.globals.len() - 1
+    }
+
+    fn string(&mut self, s: &str) -> usize {
+        if let Some(id) = self.string_ids.get(s) {
+            return *id;
+        }
+        self.strings.push(s.to_string());
+        self.string_ids.insert(s.to_string(), self.strings.len() - 1);
+        self.strings.len() - 1
+    }
+
+    /**
+     * 用到了解释器的内置函数，但是运行时里没有实现，程序里也没有自己定义
+     */
+    fn check_builtins(&self) -> Result<(), String> {
+        let interp = bytecode_interpreter::Interpreter::default();
+        for name in self.globals.iter() {
+            if
+                interp.globals.contains_key(name) &&
+                !self.defined_globals.contains(name) &&
+                !NATIVES.iter().any(|(native, ..)| native == name)
+            {
+                return Err(format!("builtin '{}' is not supported by lox build yet", name));
+            }
+        }
+        Ok(())
+    }
+
+    fn translate_function(
+        &mut self,
+        idx: usize,
+        function: &bytecode::Function,
+        out: &mut String
+    ) -> Result<(), String> {
+        let unsupported = |what: &str| -> Result<(), String> {
+            Err(format!("{} in function '{}' are not supported by lox build yet", what, function.name))
+        };
+        if function.is_generator {
+            return unsupported("generators");
+        }
+        if function.is_async {
+            return unsupported("async functions");
+        }
+        if function.optional_args > 0 {
+            return unsupported("default parameter values");
+        }
+        if function.has_rest {
+            return unsupported("rest parameters");
+        }
+
+        let code = &function.chunk.code;
+        let target = |ip: usize, offset: usize, forward: bool| -> usize {
+            if forward { ip + 1 + offset } else { ip + 1 - offset }
+        };
+
+        // 只给跳转目标放标签
+        let mut labels = HashSet::new();
+        for (ip, (op, _)) in code.iter().enumerate() {
+            match op {
+                | bytecode::Op::JumpIfFalse(offset)
+                | bytecode::Op::Jump(offset)
+                | bytecode::Op::JumpIfNil(offset) => {
+                    labels.insert(target(ip, *offset, true));
+                }
+                bytecode::Op::ForIter(offset) => {
+                    labels.insert(target(ip, *offset, true));
+                    labels.insert(ip + 2);
+                }
+                bytecode::Op::Loop(offset) => {
+                    labels.insert(target(ip, *offset, false));
+                }
+                _ => {}
+            }
+        }
+
+        writeln!(out, "static void lox_fn_{}(Value *base) {{", idx).unwrap();
+        for (ip, (op, lineno)) in code.iter().enumerate() {
+            if labels.contains(&ip) {
+                writeln!(out, "L{}:;", ip).unwrap();
+            }
+            let line = lineno.value;
+            let stmt = match op {
+                bytecode::Op::Return => "LOX_RETURN();".to_string(),
+                bytecode::Op::Constant(const_idx) =>
+                    match &function.chunk.constants[*const_idx] {
+                        bytecode::Constant::Number(n) => format!("PUSH(NUM_VAL({}));", c_double(*n)),
+                        bytecode::Constant::Integer(n) => format!("PUSH(INT_VAL({}));", c_int(*n)),
+                        bytecode::Constant::String(s) => {
+                            format!("PUSH(lox_constants[{}]);", self.string(s))
+                        }
+                        bytecode::Constant::Function(_) => {
+                            panic!("functions are only loaded by Op::Closure");
+                        }
+                    }
+                bytecode::Op::Closure(const_idx, upvals) => {
+                    let closure = match &function.chunk.constants[*const_idx] {
+                        bytecode::Constant::Function(closure) => closure,
+                        _ => panic!("expected a function constant for Op::Closure"),
+                    };
+                    let proto = self.function_ids[&(&closure.function as *const _)];
+                    self.upvalue_counts[proto] = upvals.len();
+                    let captures: Vec<String> = upvals
+                        .iter()
+                        .map(|upval| {
+                            match upval {
+                                bytecode::UpvalueLoc::Local(local) => format!("{{ true, {} }}", local),
+                                bytecode::UpvalueLoc::Upvalue(upval) => format!("{{ false, {} }}", upval),
+                            }
+                        })
+                        .collect();
+                    writeln!(
+                        self.captures,
+                        "static const UpvalueLoc lox_captures_{}_{}[] = {{ {} }};",
+                        idx,
+                        ip,
+                        if captures.is_empty() { "{ false, 0 }".to_string() } else { captures.join(", ") }
+                    ).unwrap();
+                    format!("lox_make_closure(&lox_proto_{}, lox_captures_{}_{}, base);", proto, idx, ip)
+                }
+                bytecode::Op::Nil => "PUSH(NIL_VAL);".to_string(),
+                bytecode::Op::True => "PUSH(BOOL_VAL(true));".to_string(),
+                bytecode::Op::False => "PUSH(BOOL_VAL(false));".to_string(),
+                bytecode::Op::Negate => format!("lox_negate({});", line),
+                bytecode::Op::Add => format!("lox_add({});", line),
+                bytecode::Op::Subtract => binop("BIN_SUB", line),
+                bytecode::Op::Multiply => binop("BIN_MUL", line),
+                bytecode::Op::Divide => binop("BIN_DIV", line),
+                bytecode::Op::Modulo => binop("BIN_MOD", line),
+                bytecode::Op::Power => binop("BIN_POW", line),
+                bytecode::Op::IntDivide => binop("BIN_INTDIV", line),
+                bytecode::Op::BitAnd => binop("BIN_BITAND", line),
+                bytecode::Op::BitOr => binop("BIN_BITOR", line),
+                bytecode::Op::BitXor => binop("BIN_BITXOR", line),
+                bytecode::Op::ShiftLeft => binop("BIN_SHL", line),
+                bytecode::Op::ShiftRight => binop("BIN_SHR", line),
+                bytecode::Op::Not => format!("lox_not({});", line),
+                bytecode::Op::Equal => "lox_equal();".to_string(),
+                bytecode::Op::Greater => format!("lox_compare(true, {});", line),
+                bytecode::Op::Less => format!("lox_compare(false, {});", line),
+                bytecode::Op::Print => "lox_print(sp[-1]);".to_string(),
+                bytecode::Op::Pop => "sp--;".to_string(),
+                bytecode::Op::Dup(depth) => format!("sp[0] = sp[-1 - {}]; sp++;", depth),
+                bytecode::Op::Rot(depth) => format!("lox_rot({});", depth),
+                bytecode::Op::DefineGlobal(name_idx) => {
+                    format!("lox_define_global({}, false, {});", self.global(function, *name_idx), line)
+                }
+                bytecode::Op::DefineConstGlobal(name_idx) => {
+                    format!("lox_define_global({}, true, {});", self.global(function, *name_idx), line)
+                }
+                bytecode::Op::GetGlobal(name_idx) => {
+                    format!("lox_get_global({}, {});", self.global(function, *name_idx), line)
+                }
+                bytecode::Op::SetGlobal(name_idx) => {
+                    format!("lox_set_global({}, {});", self.global(function, *name_idx), line)
+                }
+                bytecode::Op::GetLocal(slot) => format!("PUSH(base[{}]);", slot),
+                bytecode::Op::SetLocal(slot) => format!("base[{}] = sp[-1];", slot),
+                bytecode::Op::GetUpval(upval) => {
+                    format!("PUSH(*AS_CLOSURE(base[0])->upvalues[{}]->location);", upval)
+                }
+                bytecode::Op::SetUpval(upval) => {
+                    format!("*AS_CLOSURE(base[0])->upvalues[{}]->location = sp[-1];", upval)
+                }
+                bytecode::Op::JumpIfFalse(offset) => {
+                    format!("if (lox_is_falsey(sp[-1])) goto L{};", target(ip, *offset, true))
+                }
+                bytecode::Op::Jump(offset) => format!("goto L{};", target(ip, *offset, true)),
+                bytecode::Op::Loop(offset) => format!("goto L{};", target(ip, *offset, false)),
+                bytecode::Op::GetIter => format!("lox_get_iter({});", line),
+                // 有元素的时候跳过后面的 JumpIfNil
+                bytecode::Op::ForIter(offset) => {
+                    format!("if (!lox_for_iter()) goto L{}; goto L{};", target(ip, *offset, true), ip + 2)
+                }
+                bytecode::Op::JumpIfNil(offset) => {
+                    format!("if (IS_NIL(sp[-1])) {{ sp--; goto L{}; }}", target(ip, *offset, true))
+                }
+                // C 里面不保证尾调用，就当成普通的调用
+                bytecode::Op::Call(arg_count) => format!("lox_call({});", arg_count),
+                bytecode::Op::TailCall(arg_count, names) if names.is_empty() => {
+                    format!("lox_call({});", arg_count)
+                }
+                bytecode::Op::CloseUpvalue => "lox_close_upvalues(sp - 1); sp--;".to_string(),
+                bytecode::Op::BuildList(size) => format!("lox_build_list({});", size),
+                bytecode::Op::MatchList(size) => format!("lox_match_list({});", size),
+                bytecode::Op::Subscr => format!("lox_subscr({});", line),
+                bytecode::Op::SetItem => format!("lox_setitem({});", line),
+                bytecode::Op::Interpolate(num_parts) => format!("lox_interpolate({});", num_parts),
+                bytecode::Op::Slice => format!("lox_slice({});", line),
+                other => {
+                    return Err(
+                        format!(
+                            "{} at line {} are not supported by lox build yet",
+                            unsupported_feature(other),
+                            line
+                        )
+                    );
+                }
+            };
+            writeln!(out, "    {}", stmt).unwrap();
+        }
+        out.push_str("}\n\n");
+        Ok(())
+    }
+}
+
+fn binop(op: &str, line: usize) -> String {
+    format!("lox_numeric_binop({}, {});", op, line)
+}
+
+fn unsupported_feature(op: &bytecode::Op) -> &'static str {
+    match op {
+        bytecode::Op::Yield => "generators",
+        bytecode::Op::Spawn(..) | bytecode::Op::SpawnInvoke(..) | bytecode::Op::YieldFiber => {
+            "fibers"
+        }
+        bytecode::Op::Await => "async functions",
+        bytecode::Op::CallNamed(..) | bytecode::Op::TailCall(..) => "named arguments",
+        bytecode::Op::Enum(_) | bytecode::Op::EnumVariant(..) => "enums",
+        bytecode::Op::Trait(_)
+        | bytecode::Op::TraitMethod(_)
+        | bytecode::Op::RequireMethod(_)
+        | bytecode::Op::Mixin(_) => "traits",
+        _ => "classes and properties",
+    }
+}
+
+/* ---------- ---------- C 字面量 ---------- ---------- */
+
+/**
+ * 转成 C 的字符串字面量：不是普通 ascii 字符的都用八进制转义
+ */
+fn c_string(s: &str) -> String {
+    let mut res = String::from("\"");
+    for byte in s.bytes() {
+        match byte {
+            b'"' | b'\\' | b'?' => {
+                write!(res, "\\{:03o}", byte).unwrap();
+            }
+            b' '..=b'~' => res.push(byte as char),
+            _ => {
+                write!(res, "\\{:03o}", byte).unwrap();
+            }
+        }
+    }
+    res.push('"');
+    res
+}
+
+fn c_double(n: f64) -> String {
+    if n.is_nan() {
+        "NAN".to_string()
+    } else if n.is_infinite() {
+        if n > 0.0 { "HUGE_VAL".to_string() } else { "-HUGE_VAL".to_string() }
+    } else {
+        // {:?} 是最短的能还原回来的写法，而且一定带小数点或者指数
+        format!("{:?}", n)
+    }
+}
+
+fn c_int(n: i64) -> String {
+    if n == i64::MIN { "INT64_MIN".to_string() } else { format!("INT64_C({})", n) }
+}
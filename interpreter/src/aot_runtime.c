@@ -0,0 +1,1238 @@
+/*
+ * lox build 生成的程序的运行时：值的表示、垃圾回收、内置函数。
+ * 行为（包括输出格式和错误信息）和字节码解释器保持一致。
+ * 后面接着的是 aot.rs 为每个 lox 函数生成的 C 函数。
+ */
+
+#include <inttypes.h>
+#include <math.h>
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <float.h>
+#include <sys/time.h>
+
+/* ---------- ---------- 值 ---------- ---------- */
+
+typedef struct Obj Obj;
+
+typedef enum { VAL_NIL, VAL_BOOL, VAL_INT, VAL_NUM, VAL_OBJ } ValueType;
+
+typedef struct {
+    ValueType type;
+    union {
+        bool boolean;
+        int64_t integer;
+        double number;
+        Obj *obj;
+    } as;
+} Value;
+
+#define NIL_VAL ((Value){ VAL_NIL, { .integer = 0 } })
+#define BOOL_VAL(b) ((Value){ VAL_BOOL, { .boolean = (b) } })
+#define INT_VAL(n) ((Value){ VAL_INT, { .integer = (n) } })
+#define NUM_VAL(n) ((Value){ VAL_NUM, { .number = (n) } })
+#define OBJ_VAL(o) ((Value){ VAL_OBJ, { .obj = (Obj *)(o) } })
+
+#define IS_NIL(v) ((v).type == VAL_NIL)
+#define IS_BOOL(v) ((v).type == VAL_BOOL)
+#define IS_INT(v) ((v).type == VAL_INT)
+#define IS_NUM(v) ((v).type == VAL_NUM)
+#define IS_NUMERIC(v) (IS_INT(v) || IS_NUM(v))
+#define IS_OBJ_TYPE(v, t) ((v).type == VAL_OBJ && (v).as.obj->type == (t))
+#define IS_STRING(v) IS_OBJ_TYPE(v, OBJ_STRING)
+#define IS_LIST(v) IS_OBJ_TYPE(v, OBJ_LIST)
+#define IS_CLOSURE(v) IS_OBJ_TYPE(v, OBJ_CLOSURE)
+#define IS_NATIVE(v) IS_OBJ_TYPE(v, OBJ_NATIVE)
+
+#define AS_STRING(v) ((ObjString *)(v).as.obj)
+#define AS_LIST(v) ((ObjList *)(v).as.obj)
+#define AS_CLOSURE(v) ((ObjClosure *)(v).as.obj)
+#define AS_NATIVE(v) ((ObjNative *)(v).as.obj)
+
+typedef enum { OBJ_STRING, OBJ_LIST, OBJ_CLOSURE, OBJ_UPVALUE, OBJ_NATIVE } ObjType;
+
+struct Obj {
+    ObjType type;
+    bool marked;
+    Obj *next; // 所有对象串成一个链表，sweep 的时候遍历
+};
+
+typedef struct {
+    Obj obj;
+    size_t len;
+    char *chars;
+} ObjString;
+
+typedef struct {
+    Obj obj;
+    size_t count;
+    size_t capacity;
+    Value *items;
+    bool frozen;
+} ObjList;
+
+// 外层函数还没返回的时候 location 指向栈上的槽位，关闭以后指向 closed
+typedef struct ObjUpvalue {
+    Obj obj;
+    Value *location;
+    Value closed;
+    struct ObjUpvalue *next;
+} ObjUpvalue;
+
+// base 指向 0 号槽位，返回的时候结果放在 base[0]，sp 指向 base + 1
+typedef void (*LoxCode)(Value *base);
+
+typedef struct {
+    const char *name;
+    int arity;
+    const char *const *params;
+    int upvalue_count;
+    LoxCode code;
+} FnProto;
+
+typedef struct {
+    Obj obj;
+    const FnProto *proto;
+    ObjUpvalue **upvalues;
+} ObjClosure;
+
+typedef Value (*NativeFn)(Value *args, int arg_count);
+
+typedef struct {
+    Obj obj;
+    const char *name;
+    int arity;
+    int optional;
+    NativeFn fn;
+} ObjNative;
+
+typedef struct {
+    bool is_local;
+    int index;
+} UpvalueLoc;
+
+/* ---------- ---------- 全局状态 ---------- ---------- */
+
+#define LOX_STACK_MAX (1 << 20)
+#define LOX_STACK_RESERVE 4096 // 调用的时候至少要留这么多空位
+
+static Value lox_stack[LOX_STACK_MAX];
+static Value *sp = lox_stack;
+
+// 全局变量在编译的时候就分配好了下标
+static Value *lox_globals;
+static bool *lox_global_defined;
+static bool *lox_global_const;
+static const char *const *lox_global_names;
+static int lox_global_count;
+
+// 字符串常量
+static Value *lox_constants;
+static int lox_constant_count;
+
+static Obj *lox_objects = NULL;
+static ObjUpvalue *lox_open_upvalues = NULL;
+static size_t lox_bytes_allocated = 0;
+static size_t lox_next_gc = 1024 * 1024;
+static Obj **lox_gray_stack = NULL;
+static size_t lox_gray_count = 0;
+static size_t lox_gray_capacity = 0;
+
+#define PUSH(v) (*sp++ = (v))
+
+/* ---------- ---------- 错误 ---------- ---------- */
+
+static void lox_error(const char *fmt, ...) {
+    va_list args;
+    fflush(stdout);
+    fputs("Lox runtime error: ", stderr);
+    va_start(args, fmt);
+    vfprintf(stderr, fmt, args);
+    va_end(args);
+    fputc('\n', stderr);
+    exit(70);
+}
+
+/* ---------- ---------- 字符串缓冲 ---------- ---------- */
+
+typedef struct {
+    char *data;
+    size_t len;
+    size_t cap;
+} StrBuf;
+
+static void sb_append(StrBuf *sb, const char *s, size_t n) {
+    if (sb->len + n + 1 > sb->cap) {
+        size_t cap = sb->cap < 64 ? 64 : sb->cap;
+        while (cap < sb->len + n + 1) cap *= 2;
+        sb->data = realloc(sb->data, cap);
+        if (sb->data == NULL) {
+            fputs("out of memory\n", stderr);
+            exit(70);
+        }
+        sb->cap = cap;
+    }
+    memcpy(sb->data + sb->len, s, n);
+    sb->len += n;
+    sb->data[sb->len] = '\0';
+}
+
+static void sb_puts(StrBuf *sb, const char *s) {
+    sb_append(sb, s, strlen(s));
+}
+
+static void sb_repeat(StrBuf *sb, char c, int n) {
+    for (int i = 0; i < n; i++) sb_append(sb, &c, 1);
+}
+
+/*
+ * 和 rust 的 f64 Display 一样：最短的能还原回来的十进制，不用科学计数法
+ */
+static void lox_format_number(StrBuf *sb, double x) {
+    if (isnan(x)) {
+        sb_puts(sb, "NaN");
+        return;
+    }
+    if (isinf(x)) {
+        sb_puts(sb, x < 0 ? "-inf" : "inf");
+        return;
+    }
+    if (x == 0) {
+        sb_puts(sb, signbit(x) ? "-0" : "0");
+        return;
+    }
+
+    char buf[64];
+    for (int precision = 0; precision <= 16; precision++) {
+        snprintf(buf, sizeof buf, "%.*e", precision, x);
+        if (strtod(buf, NULL) == x) break;
+    }
+
+    // buf 形如 -1.2345e+05
+    char digits[32];
+    int nd = 0;
+    const char *p = buf;
+    if (*p == '-') {
+        sb_puts(sb, "-");
+        p++;
+    }
+    for (; *p && *p != 'e'; p++) {
+        if (*p != '.') digits[nd++] = *p;
+    }
+    int exp = atoi(p + 1);
+    while (nd > 1 && digits[nd - 1] == '0') nd--;
+
+    if (exp >= nd - 1) {
+        sb_append(sb, digits, nd);
+        sb_repeat(sb, '0', exp - (nd - 1));
+    } else if (exp >= 0) {
+        sb_append(sb, digits, exp + 1);
+        sb_puts(sb, ".");
+        sb_append(sb, digits + exp + 1, nd - exp - 1);
+    } else {
+        sb_puts(sb, "0.");
+        sb_repeat(sb, '0', -exp - 1);
+        sb_append(sb, digits, nd);
+    }
+}
+
+static const char *lox_type_name(Value v) {
+    switch (v.type) {
+        case VAL_NIL: return "Nil";
+        case VAL_BOOL: return "Bool";
+        case VAL_INT: return "Integer";
+        case VAL_NUM: return "Number";
+        case VAL_OBJ:
+            switch (v.as.obj->type) {
+                case OBJ_STRING: return "String";
+                case OBJ_LIST: return "List";
+                case OBJ_CLOSURE: return "Function";
+                case OBJ_NATIVE: return "NativeFunction";
+                case OBJ_UPVALUE: break;
+            }
+    }
+    return "Nil";
+}
+
+static void lox_format_value(StrBuf *sb, Value v) {
+    char buf[32];
+    switch (v.type) {
+        case VAL_NIL:
+            sb_puts(sb, "nil");
+            return;
+        case VAL_BOOL:
+            sb_puts(sb, v.as.boolean ? "true" : "false");
+            return;
+        case VAL_INT:
+            snprintf(buf, sizeof buf, "%" PRId64, v.as.integer);
+            sb_puts(sb, buf);
+            return;
+        case VAL_NUM:
+            lox_format_number(sb, v.as.number);
+            return;
+        case VAL_OBJ:
+            break;
+    }
+    switch (v.as.obj->type) {
+        case OBJ_STRING:
+            sb_append(sb, AS_STRING(v)->chars, AS_STRING(v)->len);
+            return;
+        case OBJ_LIST: {
+            ObjList *list = AS_LIST(v);
+            sb_puts(sb, "[");
+            for (size_t i = 0; i < list->count; i++) {
+                if (i > 0) sb_puts(sb, ", ");
+                lox_format_value(sb, list->items[i]);
+            }
+            sb_puts(sb, "]");
+            return;
+        }
+        case OBJ_CLOSURE:
+            sb_puts(sb, "<fn '");
+            sb_puts(sb, AS_CLOSURE(v)->proto->name);
+            sb_puts(sb, "'>");
+            return;
+        case OBJ_NATIVE:
+            sb_puts(sb, "<native fn ");
+            sb_puts(sb, AS_NATIVE(v)->name);
+            sb_puts(sb, ">");
+            return;
+        case OBJ_UPVALUE:
+            return;
+    }
+}
+
+/* ---------- ---------- 垃圾回收 ---------- ---------- */
+
+static void lox_collect_garbage(void);
+
+static void *lox_reallocate(void *ptr, size_t old_size, size_t new_size) {
+    lox_bytes_allocated += new_size;
+    lox_bytes_allocated -= old_size;
+    if (new_size == 0) {
+        free(ptr);
+        return NULL;
+    }
+    void *res = realloc(ptr, new_size);
+    if (res == NULL) {
+        fputs("out of memory\n", stderr);
+        exit(70);
+    }
+    return res;
+}
+
+/*
+ * 只有分配对象的时候才会触发回收，调用者要保证用到的值都还在栈上
+ */
+static Obj *lox_new_object(size_t size, ObjType type) {
+    if (lox_bytes_allocated > lox_next_gc) lox_collect_garbage();
+    Obj *obj = lox_reallocate(NULL, 0, size);
+    obj->type = type;
+    obj->marked = false;
+    obj->next = lox_objects;
+    lox_objects = obj;
+    return obj;
+}
+
+static void lox_mark_object(Obj *obj) {
+    if (obj == NULL || obj->marked) return;
+    obj->marked = true;
+    if (lox_gray_count == lox_gray_capacity) {
+        lox_gray_capacity = lox_gray_capacity < 64 ? 64 : lox_gray_capacity * 2;
+        lox_gray_stack = realloc(lox_gray_stack, lox_gray_capacity * sizeof(Obj *));
+        if (lox_gray_stack == NULL) exit(70);
+    }
+    lox_gray_stack[lox_gray_count++] = obj;
+}
+
+static void lox_mark_value(Value v) {
+    if (v.type == VAL_OBJ) lox_mark_object(v.as.obj);
+}
+
+static void lox_blacken(Obj *obj) {
+    switch (obj->type) {
+        case OBJ_STRING:
+        case OBJ_NATIVE:
+            break;
+        case OBJ_LIST: {
+            ObjList *list = (ObjList *)obj;
+            for (size_t i = 0; i < list->count; i++) lox_mark_value(list->items[i]);
+            break;
+        }
+        case OBJ_UPVALUE:
+            lox_mark_value(((ObjUpvalue *)obj)->closed);
+            break;
+        case OBJ_CLOSURE: {
+            ObjClosure *closure = (ObjClosure *)obj;
+            // 刚创建的闭包上值还没填完，空的跳过
+            for (int i = 0; i < closure->proto->upvalue_count; i++) {
+                lox_mark_object((Obj *)closure->upvalues[i]);
+            }
+            break;
+        }
+    }
+}
+
+static void lox_free_object(Obj *obj) {
+    switch (obj->type) {
+        case OBJ_STRING: {
+            ObjString *string = (ObjString *)obj;
+            lox_reallocate(string->chars, string->len + 1, 0);
+            lox_reallocate(obj, sizeof(ObjString), 0);
+            break;
+        }
+        case OBJ_LIST: {
+            ObjList *list = (ObjList *)obj;
+            lox_reallocate(list->items, list->capacity * sizeof(Value), 0);
+            lox_reallocate(obj, sizeof(ObjList), 0);
+            break;
+        }
+        case OBJ_CLOSURE: {
+            ObjClosure *closure = (ObjClosure *)obj;
+            lox_reallocate(closure->upvalues, closure->proto->upvalue_count * sizeof(ObjUpvalue *), 0);
+            lox_reallocate(obj, sizeof(ObjClosure), 0);
+            break;
+        }
+        case OBJ_UPVALUE:
+            lox_reallocate(obj, sizeof(ObjUpvalue), 0);
+            break;
+        case OBJ_NATIVE:
+            lox_reallocate(obj, sizeof(ObjNative), 0);
+            break;
+    }
+}
+
+static void lox_collect_garbage(void) {
+    for (Value *slot = lox_stack; slot < sp; slot++) lox_mark_value(*slot);
+    for (int i = 0; i < lox_global_count; i++) {
+        if (lox_global_defined[i]) lox_mark_value(lox_globals[i]);
+    }
+    for (int i = 0; i < lox_constant_count; i++) lox_mark_value(lox_constants[i]);
+    for (ObjUpvalue *upvalue = lox_open_upvalues; upvalue != NULL; upvalue = upvalue->next) {
+        lox_mark_object((Obj *)upvalue);
+    }
+
+    while (lox_gray_count > 0) lox_blacken(lox_gray_stack[--lox_gray_count]);
+
+    Obj **obj = &lox_objects;
+    while (*obj != NULL) {
+        if ((*obj)->marked) {
+            (*obj)->marked = false;
+            obj = &(*obj)->next;
+        } else {
+            Obj *unreached = *obj;
+            *obj = unreached->next;
+            lox_free_object(unreached);
+        }
+    }
+
+    lox_next_gc = lox_bytes_allocated * 2;
+    if (lox_next_gc < 1024 * 1024) lox_next_gc = 1024 * 1024;
+}
+
+/* ---------- ---------- 对象 ---------- ---------- */
+
+/*
+ * chars 是用 lox_reallocate 分配的 len + 1 个字节，交给字符串管理
+ */
+static ObjString *lox_take_string(char *chars, size_t len) {
+    ObjString *string = (ObjString *)lox_new_object(sizeof(ObjString), OBJ_STRING);
+    string->len = len;
+    string->chars = chars;
+    return string;
+}
+
+static ObjString *lox_copy_string(const char *chars, size_t len) {
+    char *copy = lox_reallocate(NULL, 0, len + 1);
+    memcpy(copy, chars, len);
+    copy[len] = '\0';
+    return lox_take_string(copy, len);
+}
+
+static ObjList *lox_new_list(size_t capacity) {
+    Value *items = capacity > 0 ? lox_reallocate(NULL, 0, capacity * sizeof(Value)) : NULL;
+    ObjList *list = (ObjList *)lox_new_object(sizeof(ObjList), OBJ_LIST);
+    list->count = 0;
+    list->capacity = capacity;
+    list->items = items;
+    list->frozen = false;
+    return list;
+}
+
+static void lox_list_push(ObjList *list, Value v) {
+    if (list->count == list->capacity) {
+        size_t capacity = list->capacity < 8 ? 8 : list->capacity * 2;
+        list->items = lox_reallocate(list->items, list->capacity * sizeof(Value), capacity * sizeof(Value));
+        list->capacity = capacity;
+    }
+    list->items[list->count++] = v;
+}
+
+static void lox_define_native(int global, const char *name, int arity, int optional, NativeFn fn) {
+    ObjNative *native = (ObjNative *)lox_new_object(sizeof(ObjNative), OBJ_NATIVE);
+    native->name = name;
+    native->arity = arity;
+    native->optional = optional;
+    native->fn = fn;
+    lox_globals[global] = OBJ_VAL(native);
+    lox_global_defined[global] = true;
+}
+
+/* ---------- ---------- 上值 ---------- ---------- */
+
+static ObjUpvalue *lox_capture_upvalue(Value *local) {
+    ObjUpvalue *prev = NULL;
+    ObjUpvalue *upvalue = lox_open_upvalues;
+    while (upvalue != NULL && upvalue->location > local) {
+        prev = upvalue;
+        upvalue = upvalue->next;
+    }
+    if (upvalue != NULL && upvalue->location == local) return upvalue;
+
+    ObjUpvalue *created = (ObjUpvalue *)lox_new_object(sizeof(ObjUpvalue), OBJ_UPVALUE);
+    created->location = local;
+    created->closed = NIL_VAL;
+    created->next = upvalue;
+    if (prev == NULL) {
+        lox_open_upvalues = created;
+    } else {
+        prev->next = created;
+    }
+    return created;
+}
+
+static void lox_close_upvalues(Value *last) {
+    while (lox_open_upvalues != NULL && lox_open_upvalues->location >= last) {
+        ObjUpvalue *upvalue = lox_open_upvalues;
+        upvalue->closed = *upvalue->location;
+        upvalue->location = &upvalue->closed;
+        lox_open_upvalues = upvalue->next;
+    }
+}
+
+static void lox_make_closure(const FnProto *proto, const UpvalueLoc *captures, Value *base) {
+    size_t size = proto->upvalue_count * sizeof(ObjUpvalue *);
+    ObjUpvalue **upvalues = size > 0 ? lox_reallocate(NULL, 0, size) : NULL;
+    for (int i = 0; i < proto->upvalue_count; i++) upvalues[i] = NULL;
+    ObjClosure *closure = (ObjClosure *)lox_new_object(sizeof(ObjClosure), OBJ_CLOSURE);
+    closure->proto = proto;
+    closure->upvalues = upvalues;
+    PUSH(OBJ_VAL(closure));
+
+    ObjClosure *enclosing = AS_CLOSURE(base[0]);
+    for (int i = 0; i < proto->upvalue_count; i++) {
+        closure->upvalues[i] = captures[i].is_local
+            ? lox_capture_upvalue(base + captures[i].index)
+            : enclosing->upvalues[captures[i].index];
+    }
+}
+
+#define LOX_RETURN() \
+    do { \
+        Value result_ = *--sp; \
+        lox_close_upvalues(base); \
+        sp = base; \
+        PUSH(result_); \
+        return; \
+    } while (0)
+
+/* ---------- ---------- 运算 ---------- ---------- */
+
+static bool lox_is_falsey(Value v) {
+    switch (v.type) {
+        case VAL_NIL: return true;
+        case VAL_BOOL: return !v.as.boolean;
+        case VAL_INT: return v.as.integer == 0;
+        case VAL_NUM: return v.as.number == 0.0;
+        case VAL_OBJ:
+            if (IS_STRING(v)) return AS_STRING(v)->len == 0;
+            if (IS_LIST(v)) return AS_LIST(v)->count == 0;
+            return false;
+    }
+    return false;
+}
+
+static bool lox_values_equal(Value a, Value b) {
+    if (IS_NUM(a) && IS_NUM(b)) return fabs(a.as.number - b.as.number) < DBL_EPSILON;
+    if (IS_INT(a) && IS_INT(b)) return a.as.integer == b.as.integer;
+    if (IS_INT(a) && IS_NUM(b)) return fabs((double)a.as.integer - b.as.number) < DBL_EPSILON;
+    if (IS_NUM(a) && IS_INT(b)) return fabs((double)b.as.integer - a.as.number) < DBL_EPSILON;
+    if (IS_BOOL(a) && IS_BOOL(b)) return a.as.boolean == b.as.boolean;
+    if (IS_NIL(a) && IS_NIL(b)) return true;
+    if (IS_STRING(a) && IS_STRING(b)) {
+        ObjString *s1 = AS_STRING(a);
+        ObjString *s2 = AS_STRING(b);
+        return s1->len == s2->len && memcmp(s1->chars, s2->chars, s1->len) == 0;
+    }
+    return false;
+}
+
+static double lox_as_double(Value v) {
+    return IS_INT(v) ? (double)v.as.integer : v.as.number;
+}
+
+// rust 的 f64 as i64：NaN 是 0，超出范围的取边界
+static int64_t lox_double_to_i64(double n) {
+    if (isnan(n)) return 0;
+    if (n >= 9223372036854775807.0) return INT64_MAX;
+    if (n <= -9223372036854775808.0) return INT64_MIN;
+    return (int64_t)n;
+}
+
+typedef enum {
+    BIN_ADD,
+    BIN_SUB,
+    BIN_MUL,
+    BIN_DIV,
+    BIN_MOD,
+    BIN_POW,
+    BIN_INTDIV,
+    BIN_BITAND,
+    BIN_BITOR,
+    BIN_BITXOR,
+    BIN_SHL,
+    BIN_SHR,
+} Binop;
+
+static const char *const lox_binop_names[] = {
+    "Add", "Sub", "Mul", "Div", "Mod", "Pow", "IntDiv", "BitAnd", "BitOr", "BitXor", "Shl", "Shr",
+};
+
+static void lox_overflow(Binop op, int line) {
+    lox_error("integer overflow in %s expression (line=%d)", lox_binop_names[op], line);
+}
+
+static Value lox_integer_binop(int64_t left, int64_t right, Binop op, int line) {
+    int64_t res;
+    switch (op) {
+        case BIN_ADD:
+            if (__builtin_add_overflow(left, right, &res)) lox_overflow(op, line);
+            return INT_VAL(res);
+        case BIN_SUB:
+            if (__builtin_sub_overflow(left, right, &res)) lox_overflow(op, line);
+            return INT_VAL(res);
+        case BIN_MUL:
+            if (__builtin_mul_overflow(left, right, &res)) lox_overflow(op, line);
+            return INT_VAL(res);
+        case BIN_DIV:
+            return NUM_VAL((double)left / (double)right);
+        case BIN_MOD:
+        case BIN_INTDIV:
+            if (right == 0) {
+                lox_error("integer division by zero in %s expression (line=%d)", lox_binop_names[op], line);
+            }
+            if (left == INT64_MIN && right == -1) lox_overflow(op, line);
+            if (op == BIN_MOD) return INT_VAL(left % right);
+            res = left / right;
+            // 向下取整，和浮点数的 ~/ 保持一致
+            if (left % right != 0 && (left < 0) != (right < 0)) res -= 1;
+            return INT_VAL(res);
+        case BIN_POW: {
+            if (right < 0) return NUM_VAL(pow((double)left, (double)right));
+            if (right > UINT32_MAX) lox_overflow(op, line);
+            // 和 rust 的 checked_pow 一样的平方求幂
+            uint32_t exp = (uint32_t)right;
+            int64_t base = left;
+            int64_t acc = 1;
+            if (exp == 0) return INT_VAL(1);
+            for (;;) {
+                if (exp & 1) {
+                    if (__builtin_mul_overflow(acc, base, &acc)) lox_overflow(op, line);
+                    if (exp == 1) return INT_VAL(acc);
+                }
+                exp /= 2;
+                if (__builtin_mul_overflow(base, base, &base)) lox_overflow(op, line);
+            }
+        }
+        case BIN_BITAND: return INT_VAL(left & right);
+        case BIN_BITOR: return INT_VAL(left | right);
+        case BIN_BITXOR: return INT_VAL(left ^ right);
+        case BIN_SHL:
+        case BIN_SHR:
+            if (right < 0 || right >= 64) {
+                lox_error(
+                    "Shift amount %" PRId64 " out of range in %s expression (line=%d)",
+                    right,
+                    lox_binop_names[op],
+                    line
+                );
+            }
+            if (op == BIN_SHL) return INT_VAL((int64_t)((uint64_t)left << right));
+            return INT_VAL(left >> right);
+    }
+    return NIL_VAL;
+}
+
+static int64_t lox_float_as_int(double n, Binop op, int line) {
+    if (n == floor(n) && n >= -9223372036854775808.0 && n <= 9223372036854775807.0) {
+        return lox_double_to_i64(n);
+    }
+    StrBuf sb = { 0 };
+    lox_format_number(&sb, n);
+    lox_error("Expected integers in %s expression. Found %s (line=%d)", lox_binop_names[op], sb.data, line);
+    return 0;
+}
+
+static Value lox_float_binop(double left, double right, Binop op, int line) {
+    switch (op) {
+        case BIN_ADD: return NUM_VAL(left + right);
+        case BIN_SUB: return NUM_VAL(left - right);
+        case BIN_MUL: return NUM_VAL(left * right);
+        case BIN_DIV: return NUM_VAL(left / right);
+        case BIN_MOD: return NUM_VAL(fmod(left, right));
+        case BIN_POW: return NUM_VAL(pow(left, right));
+        case BIN_INTDIV: return NUM_VAL(floor(left / right));
+        default: {
+            int64_t l = lox_float_as_int(left, op, line);
+            int64_t r = lox_float_as_int(right, op, line);
+            return lox_integer_binop(l, r, op, line);
+        }
+    }
+}
+
+static void lox_numeric_binop(Binop op, int line) {
+    Value v1 = sp[-1];
+    Value v2 = sp[-2];
+    Value res;
+    if (IS_INT(v1) && IS_INT(v2)) {
+        res = lox_integer_binop(v2.as.integer, v1.as.integer, op, line);
+    } else if (IS_NUMERIC(v1) && IS_NUMERIC(v2)) {
+        res = lox_float_binop(lox_as_double(v2), lox_as_double(v1), op, line);
+    } else {
+        lox_error(
+            "Expected numbers in %s expression. Found %s and %s (line=%d)",
+            lox_binop_names[op],
+            lox_type_name(v1),
+            lox_type_name(v2),
+            line
+        );
+        return;
+    }
+    sp -= 2;
+    PUSH(res);
+}
+
+static void lox_add(int line) {
+    Value v1 = sp[-1];
+    Value v2 = sp[-2];
+    if (IS_NUMERIC(v1) && IS_NUMERIC(v2)) {
+        lox_numeric_binop(BIN_ADD, line);
+    } else if (IS_STRING(v1) && IS_STRING(v2)) {
+        ObjString *s1 = AS_STRING(v2);
+        ObjString *s2 = AS_STRING(v1);
+        size_t len = s1->len + s2->len;
+        char *chars = lox_reallocate(NULL, 0, len + 1);
+        memcpy(chars, s1->chars, s1->len);
+        memcpy(chars + s1->len, s2->chars, s2->len);
+        chars[len] = '\0';
+        ObjString *res = lox_take_string(chars, len);
+        sp -= 2;
+        PUSH(OBJ_VAL(res));
+    } else if (IS_LIST(v1) && IS_LIST(v2)) {
+        ObjList *res = lox_new_list(AS_LIST(v1)->count + AS_LIST(v2)->count);
+        ObjList *l1 = AS_LIST(v2);
+        ObjList *l2 = AS_LIST(v1);
+        for (size_t i = 0; i < l1->count; i++) lox_list_push(res, l1->items[i]);
+        for (size_t i = 0; i < l2->count; i++) lox_list_push(res, l2->items[i]);
+        sp -= 2;
+        PUSH(OBJ_VAL(res));
+    } else {
+        lox_error(
+            "invalid operands of type %s and %s in add expression: "
+            "both operands must be number or string (line=%d)",
+            lox_type_name(v1),
+            lox_type_name(v2),
+            line
+        );
+    }
+}
+
+static void lox_negate(int line) {
+    Value v = sp[-1];
+    if (IS_INT(v)) {
+        if (v.as.integer == INT64_MIN) lox_error("integer overflow in negate expression at line %d", line);
+        sp[-1] = INT_VAL(-v.as.integer);
+    } else if (IS_NUM(v)) {
+        sp[-1] = NUM_VAL(-v.as.number);
+    } else {
+        lox_error(
+            "invalid operand to unary op negate. Expected number, found %s at line %d",
+            lox_type_name(v),
+            line
+        );
+    }
+}
+
+static void lox_not(int line) {
+    Value v = sp[-1];
+    if (!IS_BOOL(v)) {
+        lox_error(
+            "invalid operand in not expression. Expected boolean, found %s at line %d",
+            lox_type_name(v),
+            line
+        );
+    }
+    sp[-1] = BOOL_VAL(!v.as.boolean);
+}
+
+static void lox_equal(void) {
+    Value v1 = *--sp;
+    Value v2 = *--sp;
+    PUSH(BOOL_VAL(lox_values_equal(v1, v2)));
+}
+
+/*
+ * greater 为真是 >，否则是 <；有 NaN 的时候都是 false
+ */
+static void lox_compare(bool greater, int line) {
+    Value v1 = sp[-1];
+    Value v2 = sp[-2];
+    bool res;
+    if (IS_INT(v1) && IS_INT(v2)) {
+        res = greater ? v2.as.integer > v1.as.integer : v2.as.integer < v1.as.integer;
+    } else if (IS_NUMERIC(v1) && IS_NUMERIC(v2)) {
+        double n1 = lox_as_double(v2);
+        double n2 = lox_as_double(v1);
+        res = greater ? n1 > n2 : n1 < n2;
+    } else {
+        lox_error(
+            "invalid operands in %s expression. Expected numbers, found %s and %s at line %d",
+            greater ? "Greater" : "Less",
+            lox_type_name(v1),
+            lox_type_name(v2),
+            line
+        );
+        return;
+    }
+    sp -= 2;
+    PUSH(BOOL_VAL(res));
+}
+
+static void lox_print(Value v) {
+    StrBuf sb = { 0 };
+    lox_format_value(&sb, v);
+    if (sb.data != NULL) fwrite(sb.data, 1, sb.len, stdout);
+    fputc('\n', stdout);
+    free(sb.data);
+}
+
+static void lox_rot(int depth) {
+    Value v = *--sp;
+    Value *at = sp - depth;
+    memmove(at + 1, at, depth * sizeof(Value));
+    *at = v;
+    sp++;
+}
+
+/* ---------- ---------- 全局变量 ---------- ---------- */
+
+static void lox_define_global(int global, bool is_const, int line) {
+    if (lox_global_const[global]) {
+        lox_error("Cannot redefine constant %s at line %d.", lox_global_names[global], line);
+    }
+    if (is_const) lox_global_const[global] = true;
+    lox_globals[global] = *--sp;
+    lox_global_defined[global] = true;
+}
+
+static void lox_get_global(int global, int line) {
+    if (!lox_global_defined[global]) {
+        lox_error("Undefined variable '%s' at line %d.", lox_global_names[global], line);
+    }
+    PUSH(lox_globals[global]);
+}
+
+static void lox_set_global(int global, int line) {
+    if (lox_global_const[global]) {
+        lox_error("Cannot assign to constant %s at line %d.", lox_global_names[global], line);
+    }
+    if (!lox_global_defined[global]) {
+        lox_error(
+            "Use of undefined variable %s in setitem expression at line %d.",
+            lox_global_names[global],
+            line
+        );
+    }
+    lox_globals[global] = sp[-1];
+}
+
+/* ---------- ---------- 调用 ---------- ---------- */
+
+static void lox_call(int arg_count) {
+    Value callee = sp[-1 - arg_count];
+    if (sp - lox_stack > LOX_STACK_MAX - LOX_STACK_RESERVE) lox_error("Stack overflow.");
+
+    if (IS_CLOSURE(callee)) {
+        const FnProto *proto = AS_CLOSURE(callee)->proto;
+        if (arg_count > proto->arity) {
+            lox_error("Expected %d arguments but found %d.", proto->arity, arg_count);
+        }
+        if (arg_count < proto->arity) {
+            StrBuf missing = { 0 };
+            for (int i = arg_count; i < proto->arity; i++) {
+                if (i > arg_count) sb_puts(&missing, ", ");
+                sb_puts(&missing, proto->params[i]);
+            }
+            lox_error("Missing arguments for %s: %s.", proto->name, missing.data);
+        }
+        proto->code(sp - arg_count - 1);
+    } else if (IS_NATIVE(callee)) {
+        ObjNative *native = AS_NATIVE(callee);
+        int max_arity = native->arity + native->optional;
+        if (arg_count < native->arity || arg_count > max_arity) {
+            if (native->optional == 0) {
+                lox_error(
+                    "Native function %s expected %d arguments but found %d.",
+                    native->name,
+                    native->arity,
+                    arg_count
+                );
+            }
+            lox_error(
+                "Native function %s expected %d-%d arguments but found %d.",
+                native->name,
+                native->arity,
+                max_arity,
+                arg_count
+            );
+        }
+        // 参数先留在栈上，内置函数里面分配内存的时候它们不会被回收
+        Value res = native->fn(sp - arg_count, arg_count);
+        sp -= arg_count + 1;
+        PUSH(res);
+    } else {
+        lox_error("attempted to call non-callable value of type %s.", lox_type_name(callee));
+    }
+}
+
+/* ---------- ---------- 列表 ---------- ---------- */
+
+static void lox_build_list(int size) {
+    ObjList *list = lox_new_list(size);
+    for (int i = 0; i < size; i++) lox_list_push(list, sp[i - size]);
+    sp -= size;
+    PUSH(OBJ_VAL(list));
+}
+
+static void lox_match_list(int size) {
+    Value v = *--sp;
+    PUSH(BOOL_VAL(IS_LIST(v) && AS_LIST(v)->count == (size_t)size));
+}
+
+/*
+ * 带小数的下标不会悄悄截断，和 rust 的 n.fract() == 0.0 一样
+ */
+static bool lox_is_integral(double n) {
+    return isfinite(n) && n == trunc(n);
+}
+
+/*
+ * 报错用的，格式和 print 一样；反正马上就退出了，不用释放
+ */
+static const char *lox_number_string(double n) {
+    StrBuf sb = { 0 };
+    lox_format_number(&sb, n);
+    return sb.data;
+}
+
+static size_t lox_inbound_index(size_t len, double index_float, int line) {
+    if (!lox_is_integral(index_float)) {
+        lox_error("Invalid subscript %s in subscript expression", lox_number_string(index_float));
+    }
+    int64_t index = lox_double_to_i64(index_float);
+    if (0 <= index && index < (int64_t)len) return (size_t)index;
+    if (index < 0 && -index <= (int64_t)len) return (size_t)((int64_t)len + index);
+    lox_error("List subscript index out of range at %d", line);
+    return 0;
+}
+
+static void lox_subscr(int line) {
+    Value subscript = *--sp;
+    Value value = *--sp;
+    if (!IS_LIST(value)) {
+        lox_error("Invalid value of type %s in subscript expression", lox_type_name(value));
+    }
+    if (!IS_NUMERIC(subscript)) {
+        lox_error("Invalid subscript of type %s in subscript expression", lox_type_name(value));
+    }
+    ObjList *list = AS_LIST(value);
+    PUSH(list->items[lox_inbound_index(list->count, lox_as_double(subscript), line)]);
+}
+
+static void lox_setitem(int line) {
+    Value rhs = *--sp;
+    Value subscript = *--sp;
+    Value lhs = *--sp;
+    if (!IS_LIST(lhs)) {
+        lox_error("Invalid value of type %s in subscript expression", lox_type_name(subscript));
+    }
+    ObjList *list = AS_LIST(lhs);
+    if (list->frozen) lox_error("Can't modify a frozen list.");
+    if (!IS_NUMERIC(subscript)) {
+        lox_error("Invalid subscript of type %s in subscript expression", lox_type_name(lhs));
+    }
+    list->items[lox_inbound_index(list->count, lox_as_double(subscript), line)] = rhs;
+    PUSH(rhs);
+}
+
+static size_t lox_slice_bound(size_t len, Value bound, size_t def, int line) {
+    if (IS_NIL(bound)) return def;
+    if (!IS_NUMERIC(bound)) {
+        lox_error(
+            "Invalid slice bound of type %s in slice expression at line %d",
+            lox_type_name(bound),
+            line
+        );
+    }
+    if (!IS_INT(bound) && !lox_is_integral(bound.as.number)) {
+        lox_error(
+            "Invalid slice bound %s in slice expression at line %d",
+            lox_number_string(bound.as.number),
+            line
+        );
+    }
+    int64_t index = IS_INT(bound) ? bound.as.integer : lox_double_to_i64(bound.as.number);
+    if (index < 0) index += (int64_t)len;
+    if (index < 0) index = 0;
+    if (index > (int64_t)len) index = (int64_t)len;
+    return (size_t)index;
+}
+
+static void lox_slice(int line) {
+    Value value = sp[-3];
+    if (!IS_LIST(value)) {
+        lox_error("Invalid value of type %s in slice expression", lox_type_name(value));
+    }
+    size_t len = AS_LIST(value)->count;
+    size_t start = lox_slice_bound(len, sp[-2], 0, line);
+    size_t stop = lox_slice_bound(len, sp[-1], len, line);
+    ObjList *res = lox_new_list(start < stop ? stop - start : 0);
+    ObjList *list = AS_LIST(value);
+    for (size_t i = start; i < stop; i++) lox_list_push(res, list->items[i]);
+    sp -= 3;
+    PUSH(OBJ_VAL(res));
+}
+
+static void lox_get_iter(int line) {
+    Value v = sp[-1];
+    if (!IS_LIST(v)) {
+        lox_error(
+            "Cannot iterate over value of type %s at lineno=Lineno { value: %d }",
+            lox_type_name(v),
+            line
+        );
+    }
+}
+
+/*
+ * 栈顶是下标，下面是列表；还有元素就把下标加一、元素压栈，返回 true
+ */
+static bool lox_for_iter(void) {
+    ObjList *list = AS_LIST(sp[-2]);
+    int64_t index = sp[-1].as.integer;
+    if ((size_t)index >= list->count) return false;
+    sp[-1] = INT_VAL(index + 1);
+    PUSH(list->items[index]);
+    return true;
+}
+
+/* ---------- ---------- 字符串插值 ---------- ---------- */
+
+static void lox_interpolate(int num_parts) {
+    StrBuf sb = { 0 };
+    for (Value *part = sp - num_parts; part < sp; part++) lox_format_value(&sb, *part);
+    ObjString *res = lox_copy_string(sb.data != NULL ? sb.data : "", sb.len);
+    free(sb.data);
+    sp -= num_parts;
+    PUSH(OBJ_VAL(res));
+}
+
+/* ---------- ---------- 内置函数 ---------- ---------- */
+
+static void lox_native_error(const char *name, const char *fmt, ...) {
+    char buf[256];
+    va_list args;
+    va_start(args, fmt);
+    vsnprintf(buf, sizeof buf, fmt, args);
+    va_end(args);
+    lox_error("When calling %s: %s.", name, buf);
+}
+
+static Value lox_native_clock(Value *args, int arg_count) {
+    (void)args;
+    (void)arg_count;
+    struct timeval tv;
+    gettimeofday(&tv, NULL);
+    return NUM_VAL((double)((int64_t)tv.tv_sec * 1000 + tv.tv_usec / 1000));
+}
+
+static Value lox_native_exp(Value *args, int arg_count) {
+    (void)arg_count;
+    if (!IS_NUMERIC(args[0])) {
+        lox_native_error("exp", "Invalid call: expected number, got %s.", lox_type_name(args[0]));
+    }
+    return NUM_VAL(exp(lox_as_double(args[0])));
+}
+
+static Value lox_native_sqrt(Value *args, int arg_count) {
+    (void)arg_count;
+    if (!IS_NUMERIC(args[0])) {
+        lox_native_error("sqrt", "Invalid call: expected number, got %s.", lox_type_name(args[0]));
+    }
+    return NUM_VAL(sqrt(lox_as_double(args[0])));
+}
+
+static Value lox_native_len(Value *args, int arg_count) {
+    (void)arg_count;
+    if (IS_STRING(args[0])) {
+        // 按 unicode 字符数，不是字节数
+        ObjString *string = AS_STRING(args[0]);
+        int64_t count = 0;
+        for (size_t i = 0; i < string->len; i++) {
+            if (((unsigned char)string->chars[i] & 0xC0) != 0x80) count++;
+        }
+        return INT_VAL(count);
+    }
+    if (IS_LIST(args[0])) return INT_VAL((int64_t)AS_LIST(args[0])->count);
+    lox_native_error("len", "Ojbect of type %s has no len.", lox_type_name(args[0]));
+    return NIL_VAL;
+}
+
+static ObjList *lox_list_arg(const char *name, Value v) {
+    if (!IS_LIST(v)) {
+        lox_native_error(name, "Can't call %s on value of type %s", name, lox_type_name(v));
+    }
+    return AS_LIST(v);
+}
+
+static ObjList *lox_mutable_list_arg(const char *name, Value v) {
+    ObjList *list = lox_list_arg(name, v);
+    if (list->frozen) lox_native_error(name, "Can't call %s on a frozen list", name);
+    return list;
+}
+
+static size_t lox_list_index(const char *name, size_t len, Value v, bool allow_end) {
+    if (!IS_NUMERIC(v)) {
+        lox_native_error(name, "Invalid index of type %s in %s", lox_type_name(v), name);
+    }
+    if (!IS_INT(v) && !lox_is_integral(v.as.number)) {
+        lox_native_error(name, "Invalid index %s in %s", lox_number_string(v.as.number), name);
+    }
+    int64_t index = IS_INT(v) ? v.as.integer : lox_double_to_i64(v.as.number);
+    int64_t upper = allow_end ? (int64_t)len : (int64_t)len - 1;
+    if (index < 0) index += (int64_t)len;
+    if (index < 0 || index > upper) lox_native_error(name, "List index out of range in %s", name);
+    return (size_t)index;
+}
+
+static Value lox_native_push(Value *args, int arg_count) {
+    (void)arg_count;
+    lox_list_push(lox_mutable_list_arg("push", args[0]), args[1]);
+    return NIL_VAL;
+}
+
+static Value lox_native_pop(Value *args, int arg_count) {
+    (void)arg_count;
+    ObjList *list = lox_mutable_list_arg("pop", args[0]);
+    if (list->count == 0) lox_native_error("pop", "Can't pop from an empty list");
+    return list->items[--list->count];
+}
+
+static Value lox_native_insert(Value *args, int arg_count) {
+    (void)arg_count;
+    ObjList *list = lox_mutable_list_arg("insert", args[0]);
+    size_t index = lox_list_index("insert", list->count, args[1], true);
+    lox_list_push(list, NIL_VAL);
+    memmove(list->items + index + 1, list->items + index, (list->count - 1 - index) * sizeof(Value));
+    list->items[index] = args[2];
+    return NIL_VAL;
+}
+
+static Value lox_native_remove(Value *args, int arg_count) {
+    (void)arg_count;
+    ObjList *list = lox_mutable_list_arg("remove", args[0]);
+    size_t index = lox_list_index("remove", list->count, args[1], false);
+    Value removed = list->items[index];
+    memmove(list->items + index, list->items + index + 1, (list->count - 1 - index) * sizeof(Value));
+    list->count--;
+    return removed;
+}
+
+static Value lox_native_contains(Value *args, int arg_count) {
+    (void)arg_count;
+    ObjList *list = lox_list_arg("contains", args[0]);
+    for (size_t i = 0; i < list->count; i++) {
+        if (lox_values_equal(list->items[i], args[1])) return BOOL_VAL(true);
+    }
+    return BOOL_VAL(false);
+}
+
+static Value lox_native_index_of(Value *args, int arg_count) {
+    (void)arg_count;
+    ObjList *list = lox_list_arg("indexOf", args[0]);
+    for (size_t i = 0; i < list->count; i++) {
+        if (lox_values_equal(list->items[i], args[1])) return INT_VAL((int64_t)i);
+    }
+    return INT_VAL(-1);
+}
+
+static Value lox_native_concat(Value *args, int arg_count) {
+    (void)arg_count;
+    ObjList *l1 = lox_list_arg("concat", args[0]);
+    ObjList *l2 = lox_list_arg("concat", args[1]);
+    ObjList *res = lox_new_list(l1->count + l2->count);
+    for (size_t i = 0; i < l1->count; i++) lox_list_push(res, l1->items[i]);
+    for (size_t i = 0; i < l2->count; i++) lox_list_push(res, l2->items[i]);
+    return OBJ_VAL(res);
+}
+
+static Value lox_native_reverse(Value *args, int arg_count) {
+    (void)arg_count;
+    ObjList *list = lox_list_arg("reverse", args[0]);
+    ObjList *res = lox_new_list(list->count);
+    for (size_t i = list->count; i > 0; i--) lox_list_push(res, list->items[i - 1]);
+    return OBJ_VAL(res);
+}
+
+static Value lox_native_freeze(Value *args, int arg_count) {
+    (void)arg_count;
+    if (!IS_LIST(args[0])) {
+        lox_native_error("freeze", "Can't freeze a value of type %s.", lox_type_name(args[0]));
+    }
+    AS_LIST(args[0])->frozen = true;
+    return args[0];
+}
+
+/* ---------- ---------- 启动 ---------- ---------- */
+
+static void lox_init(void) {
+    const char *trigger = getenv("LOX_GC_TRIGGER_SIZE");
+    if (trigger != NULL) {
+        char *end;
+        unsigned long long size = strtoull(trigger, &end, 10);
+        if (*trigger != '\0' && *end == '\0') lox_next_gc = (size_t)size;
+    }
+    lox_globals = calloc(lox_global_count > 0 ? lox_global_count : 1, sizeof(Value));
+    lox_global_defined = calloc(lox_global_count > 0 ? lox_global_count : 1, sizeof(bool));
+    lox_global_const = calloc(lox_global_count > 0 ? lox_global_count : 1, sizeof(bool));
+    lox_constants = calloc(lox_constant_count > 0 ? lox_constant_count : 1, sizeof(Value));
+}
+
+/*
+ * 和解释器一样，把脚本的闭包放在 0 号槽位上开始执行
+ */
+static int lox_run(const FnProto *script) {
+    ObjClosure *closure = (ObjClosure *)lox_new_object(sizeof(ObjClosure), OBJ_CLOSURE);
+    closure->proto = script;
+    closure->upvalues = NULL;
+    PUSH(OBJ_VAL(closure));
+    script->code(lox_stack);
+    fflush(stdout);
+    return 0;
+}
+
+/* ---------- ---------- 生成的代码 ---------- ---------- */
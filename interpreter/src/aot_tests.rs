@@ -0,0 +1,233 @@
+#[cfg(test)]
+mod tests {
+    use crate::aot;
+    use crate::bytecode_interpreter::*;
+    use crate::compiler::*;
+    use crate::extensions;
+
+    use std::path::PathBuf;
+    use std::process::Command;
+    use std::sync::atomic::{ AtomicUsize, Ordering };
+
+    /**
+     * 两边共用的测试程序，字节码解释器和编译出来的可执行文件必须输出一样的东西
+     */
+    static CORPUS: &[&str] = &[
+        // 整数和浮点数
+        "print 1 + 2 * 3;\n\
+         print 7 / 2;\n\
+         print 7 % 3;\n\
+         print -7 / 2;\n\
+         print 2 ** 10;\n\
+         print 1.5 + 2;\n\
+         print 0.1 + 0.2;\n\
+         print 1.0;\n\
+         print 100000000000000000000.0;\n\
+         print 1e-7;\n\
+         print 3 == 3.0;\n\
+         print 1 < 2.5;\n\
+         print sqrt(2);\n\
+         print exp(0);",
+        // 位运算
+        "print 6 & 3;\n\
+         print 6 | 3;\n\
+         print 6 ^ 3;\n\
+         print 1 << 4;\n\
+         print 256 >> 2;",
+        // 字符串、真假和 nil
+        "var s = \"foo\" + \"bar\";\n\
+         print s;\n\
+         print s == \"foobar\";\n\
+         print !true;\n\
+         print !(1 == 2);\n\
+         print nil;\n\
+         print true and false;\n\
+         print nil or \"default\";\n\
+         var name = \"lox\";\n\
+         print \"hello ${name}, ${1 + 2} ${[1, 2]}\";",
+        // 控制流
+        "var total = 0;\n\
+         for (var i = 0; i < 10; i = i + 1) {\n\
+           if (i == 3) continue;\n\
+           if (i == 8) break;\n\
+           total += i;\n\
+         }\n\
+         print total;\n\
+         var n = 0;\n\
+         while (n < 3) { print n; n = n + 1; }",
+        // 递归和全局变量
+        "fun fib(n) { if (n < 2) return n; return fib(n - 1) + fib(n - 2); }\n\
+         print fib(20);\n\
+         const limit = 3;\n\
+         var counter = 0;\n\
+         fun bump() { counter += limit; return counter; }\n\
+         bump();\n\
+         print bump();\n\
+         print fib;\n\
+         print clock;",
+        // 闭包
+        "fun make_counter() {\n\
+           var n = 0;\n\
+           fun inc() { n = n + 1; return n; }\n\
+           return inc;\n\
+         }\n\
+         var a = make_counter();\n\
+         var b = make_counter();\n\
+         a(); a();\n\
+         print a();\n\
+         print b();\n\
+         var fs = [];\n\
+         for (var x in [1, 2, 3]) {\n\
+           var y = x * 10;\n\
+           push(fs, lambda() { return y; });\n\
+         }\n\
+         for (var f in fs) print f();\n\
+         fun outer() {\n\
+           var x = \"outer\";\n\
+           fun middle() {\n\
+             fun inner() { return x; }\n\
+             return inner;\n\
+           }\n\
+           x = \"changed\";\n\
+           return middle();\n\
+         }\n\
+         print outer()();",
+        // 列表和切片
+        "var xs = [1, 2, 3, 4, 5];\n\
+         print xs[1:3];\n\
+         print xs[-1];\n\
+         print xs[:2];\n\
+         print len(xs);\n\
+         push(xs, 6);\n\
+         print pop(xs);\n\
+         insert(xs, 0, 0);\n\
+         print remove(xs, 1);\n\
+         print xs;\n\
+         print contains(xs, 3);\n\
+         print indexOf(xs, 4);\n\
+         print concat(xs, [\"a\"]);\n\
+         print reverse([1, 2, 3]);\n\
+         xs[0] += 10;\n\
+         print xs;\n\
+         print [1, 2] + [3];\n\
+         print [[1, 2], [3]];",
+        // 模式匹配
+        "fun f(xs) {\n\
+           match (xs) {\n\
+             case [] => print \"empty\";\n\
+             case [x] => print x;\n\
+             case [1, [a, b]] => print a + b;\n\
+             case [first, _, last] => print first + last;\n\
+             case 0 => print \"zero\";\n\
+             case _ => print \"no match\";\n\
+           }\n\
+         }\n\
+         f([]);\n\
+         f([7]);\n\
+         f([1, [2, 3]]);\n\
+         f([1, 2, 3]);\n\
+         f(0);\n\
+         f(\"other\");",
+        // 让 gc 多跑几次
+        "var keep = [];\n\
+         for (var i = 0; i < 2000; i = i + 1) {\n\
+           var s = \"item ${i}\";\n\
+           var pair = [s, [i]];\n\
+           if (i % 100 == 0) push(keep, pair);\n\
+         }\n\
+         print len(keep);\n\
+         print keep[19];",
+    ];
+
+    /**
+     * 两边都要报一样的运行时错误
+     */
+    static ERROR_CORPUS: &[&str] = &[
+        "print undefined_name;",
+        "print 9223372036854775807 + 1;",
+        "print \"a\" - 1;",
+        "fun f(a, b) { return a; }\nf(1);",
+        "var x = 1;\nx();",
+        "var xs = [1];\nprint xs[3];",
+        "len(1, 2);",
+        "var xs = freeze([1]);\npush(xs, 2);",
+        "var xs = [1, 2];\nprint xs[1.7];",
+        "var xs = [1, 2];\nprint xs[0.5:];",
+        "insert([1], 0.5, 2);",
+        "pop([]);",
+    ];
+
+    fn run_vm(code: &str) -> Result<Vec<String>, String> {
+        let extensions = extensions::Extensions { lists: true, lambdas: true };
+        let func = match Compiler::compile(String::from(code), extensions) {
+            Ok(func) => func,
+            Err(err) => panic!("{:?}", err),
+        };
+        let mut interp = Interpreter::default();
+        match interp.interpret(func) {
+            Ok(()) => Ok(interp.output),
+            Err(InterpreterError::Runtime(err)) => Err(err),
+        }
+    }
+
+    fn run_native(code: &str) -> Result<Vec<String>, String> {
+        static TEST_ID: AtomicUsize = AtomicUsize::new(0);
+        let path: PathBuf = std::env::temp_dir().join(format!(
+            "lox-aot-test-{}-{}",
+            std::process::id(),
+            TEST_ID.fetch_add(1, Ordering::SeqCst)
+        ));
+        aot::build(code, &path).unwrap_or_else(|err| panic!("{}", err));
+        // 把 gc 的阈值调小，顺便测一下运行时的 gc
+        let output = Command::new(&path)
+            .env("LOX_GC_TRIGGER_SIZE", "4096")
+            .output()
+            .unwrap();
+        std::fs::remove_file(&path).ok();
+
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        let lines = stdout.lines().map(String::from).collect();
+        if output.status.success() {
+            Ok(lines)
+        } else {
+            assert_eq!(output.status.code(), Some(70));
+            let stderr = String::from_utf8(output.stderr).unwrap();
+            let err = stderr.trim_end().strip_prefix("Lox runtime error: ").unwrap_or(&stderr);
+            Err(err.to_string())
+        }
+    }
+
+    #[test]
+    fn test_corpus_matches_interpreter() {
+        for code in CORPUS {
+            let expected = run_vm(code);
+            assert!(expected.is_ok(), "{:?}", expected);
+            assert_eq!(run_native(code), expected, "{}", code);
+        }
+    }
+
+    #[test]
+    fn test_runtime_errors_match_interpreter() {
+        for code in ERROR_CORPUS {
+            let expected = run_vm(code);
+            assert!(expected.is_err(), "{:?}", expected);
+            assert_eq!(run_native(code), expected, "{}", code);
+        }
+    }
+
+    #[test]
+    fn test_unsupported_features() {
+        let check = |code: &str, what: &str| {
+            let path = std::env::temp_dir().join("lox-aot-test-unsupported");
+            match aot::build(code, &path) {
+                Ok(()) => panic!("{} should not build", code),
+                Err(err) => assert!(err.contains(what), "{}", err),
+            }
+        };
+        check("class A {}", "class");
+        check("fun g() { yield 1; }", "generator");
+        check("print range(0, 3);", "range");
+        check("fun f(a = 1) { return a; }", "default");
+        check("print (;", "line 1");
+    }
+}
@@ -1,7 +1,13 @@
 /// 这个文件管理了一些内置函数
 use std::time::{ SystemTime, UNIX_EPOCH };
+use std::cmp::Ordering;
+use std::collections::HashSet;
 
 use crate::bytecode_interpreter;
+use crate::event_loop;
+use crate::gc;
+use crate::isolate;
+use crate::json;
 use crate::value;
 
 /*
@@ -9,138 +15,731 @@ Arity checking is done in the interpreter prior to calling a builtin function.
 */
 
 pub fn exp(
-    _interp: &mut bytecode_interpreter::Interpreter,
+    interp: &mut bytecode_interpreter::Interpreter,
     args: &[value::Value]
 ) -> Result<value::Value, String> {
-    match args[0] {
-        value::Value::Number(num) => Ok(value::Value::Number(num.exp())),
-        _ => Err(format!("Invalid call: expected number, got {:?}.", value::type_of(&args[0]))),
+    match interp.extract_number(&args[0]) {
+        Some(num) => Ok(value::Value::number(num.exp())),
+        None => Err(format!("Invalid call: expected number, got {:?}.", value::type_of(&args[0]))),
     }
 }
 
 pub fn sqrt(
-    _interp: &mut bytecode_interpreter::Interpreter,
+    interp: &mut bytecode_interpreter::Interpreter,
     args: &[value::Value]
 ) -> Result<value::Value, String> {
-    match args[0] {
-        value::Value::Number(num) => Ok(value::Value::Number(num.sqrt())),
-        _ => Err(format!("Invalid call: expected number, got {:?}.", value::type_of(&args[0]))),
+    match interp.extract_number(&args[0]) {
+        Some(num) => Ok(value::Value::number(num.sqrt())),
+        None => Err(format!("Invalid call: expected number, got {:?}.", value::type_of(&args[0]))),
     }
 }
 
 pub fn clock(
-    _interp: &mut bytecode_interpreter::Interpreter,
+    interp: &mut bytecode_interpreter::Interpreter,
     _args: &[value::Value]
 ) -> Result<value::Value, String> {
+    // 假时钟从 0 开始，和定时器用的是同一个时间
+    if let event_loop::Clock::Fake(now) = interp.event_loop.clock {
+        return Ok(value::Value::number(now));
+    }
     let start = SystemTime::now();
     let since_the_epoch = start.duration_since(UNIX_EPOCH).unwrap();
 
-    Ok(value::Value::Number(since_the_epoch.as_millis() as f64))
+    Ok(value::Value::number(since_the_epoch.as_millis() as f64))
 }
 
 pub fn len(
     interp: &mut bytecode_interpreter::Interpreter,
     args: &[value::Value]
 ) -> Result<value::Value, String> {
-    match &args[0] {
-        value::Value::String(id) => Ok(value::Value::Number(interp.heap.get_str(*id).len() as f64)),
-        value::Value::List(id) =>
-            Ok(value::Value::Number(interp.heap.get_list_elements(*id).len() as f64)),
-        val => Err(format!("Ojbect of type {:?} has no len.", value::type_of(val))),
+    if let Some(method_id) = interp.special_method(&args[0], "__len__") {
+        return interp.call_method_and_wait(&args[0], method_id, &[]);
     }
+    let len = match args[0].unbox(&interp.heap) {
+        value::Unboxed::String(id) => interp.heap.get_str(id).chars().count(),
+        value::Unboxed::List(id) => interp.heap.get_list_elements(id).len(),
+        _ => {
+            return Err(format!("Ojbect of type {:?} has no len.", value::type_of(&args[0])));
+        }
+    };
+    Ok(interp.heap.integer(len as i64))
 }
 
-pub fn for_each(
+/*
+ * isInstance(obj, Class)：和 instanceof 一样沿着父类链找
+ */
+pub fn is_instance(
     interp: &mut bytecode_interpreter::Interpreter,
     args: &[value::Value]
 ) -> Result<value::Value, String> {
-    match &args[0] {
-        value::Value::List(id) => {
-            let list_elements = interp.heap.get_list_elements(*id).clone();
-            let callable = args[1].clone();
-            for element in list_elements.iter() {
-                interp.stack.push(callable.clone());
-                interp.stack.push(element.clone());
+    interp.is_instance(&args[0], &args[1]).map(value::Value::bool)
+}
 
-                // stash the current frame number if we're going to call a pure lox function ...
-                let frame_idx = interp.frames.len();
+/*
+ * 在内置函数里调用一个 lox 的可调用对象，一直跑到它返回，结果从栈上取出来
+ */
+pub fn call_and_wait(
+    interp: &mut bytecode_interpreter::Interpreter,
+    callable: &value::Value,
+    args: &[value::Value]
+) -> Result<value::Value, String> {
+    interp.stack.push(*callable);
+    interp.stack.extend(args.iter().cloned());
 
-                if
-                    let Err(bytecode_interpreter::InterpreterError::Runtime(err)) =
-                        interp.call_value(callable.clone(), 1)
-                {
-                    return Err(err);
-                }
+    // stash the current frame number if we're going to call a pure lox function ...
+    let frame_idx = interp.frames.len();
 
-                // If we're calling a pure lox function, `interp.call_value` doesn't actually
-                // call the value, it just sets up a call frame. We loop the interpreter
-                // until it his an error or returns to the call frame with `frame_idx`.
-                // Unfortunately, this doesn't play well with our current debugger
-                // implementation, which manually calls `interpreter.step()`
-                loop {
-                    if interp.frames.len() == frame_idx {
-                        break;
-                    }
-
-                    if
-                        let Err(bytecode_interpreter::InterpreterError::Runtime(err)) =
-                            interp.step()
-                    {
-                        return Err(err);
-                    }
-                }
+    // 等它返回之前不能换 fiber，不然下面的循环等到的是别的 fiber 的调用帧
+    interp.fibers.nested_calls += 1;
+    let res = run_until_frame(interp, *callable, args.len() as u8, frame_idx);
+    interp.fibers.nested_calls -= 1;
+    res?;
+
+    Ok(interp.pop_stack())
+}
+
+fn run_until_frame(
+    interp: &mut bytecode_interpreter::Interpreter,
+    callable: value::Value,
+    arg_count: u8,
+    frame_idx: usize
+) -> Result<(), String> {
+    if
+        let Err(bytecode_interpreter::InterpreterError::Runtime(err)) =
+            interp.call_value(callable, arg_count)
+    {
+        return Err(err);
+    }
+
+    // If we're calling a pure lox function, `interp.call_value` doesn't actually
+    // call the value, it just sets up a call frame. We loop the interpreter
+    // until it his an error or returns to the call frame with `frame_idx`.
+    // Unfortunately, this doesn't play well with our current debugger
+    // implementation, which manually calls `interpreter.step()`
+    loop {
+        if interp.frames.len() == frame_idx {
+            return Ok(());
+        }
+
+        if let Err(bytecode_interpreter::InterpreterError::Runtime(err)) = interp.step() {
+            return Err(err);
+        }
+    }
+}
+
+pub fn for_each(
+    interp: &mut bytecode_interpreter::Interpreter,
+    args: &[value::Value]
+) -> Result<value::Value, String> {
+    match args[0].unbox(&interp.heap) {
+        value::Unboxed::List(id) => {
+            let list_elements = interp.heap.get_list_elements(id).clone();
+            let callable = args[1];
+            for element in list_elements.iter() {
+                call_and_wait(interp, &callable, std::slice::from_ref(element))?;
             }
-            Ok(value::Value::Nil)
+            Ok(value::Value::NIL)
         }
-        val => Err(format!("Can't call forEach on value of type {:?}.", value::type_of(val))),
+        _ => Err(format!("Can't call forEach on value of type {:?}", value::type_of(&args[0]))),
     }
 }
 
+/*
+ * 列表上的 map 是立即求值的，生成器和迭代器上返回一个惰性的生成器
+ */
 pub fn map(
     interp: &mut bytecode_interpreter::Interpreter,
     args: &[value::Value]
 ) -> Result<value::Value, String> {
-    match &args[1] {
-        value::Value::List(id) => {
-            let list_elements = interp.heap.get_list_elements(*id).clone();
-            let callable = args[0].clone();
+    match args[1].unbox(&interp.heap) {
+        value::Unboxed::List(id) => {
+            let list_elements = interp.heap.get_list_elements(id).clone();
+            let callable = args[0];
             let mut res_elements = Vec::new();
             for element in list_elements.iter() {
-                interp.stack.push(callable.clone());
-                interp.stack.push(element.clone());
-
-                //stash the current frame number if we're going to call a pure lox function ...
-                let frame_idx = interp.frames.len();
+                res_elements.push(call_and_wait(interp, &callable, std::slice::from_ref(element))?);
+            }
+            Ok(value::Value::list(interp.heap.manage_list(res_elements)))
+        }
+        value::Unboxed::Generator(_) | value::Unboxed::Instance(_) =>
+            interp.prelude_generator("lazyMap", args),
+        _ => Err(format!("Can't call forEach on value of type {:?}", value::type_of(&args[1]))),
+    }
+}
 
-                if
-                    let Err(bytecode_interpreter::InterpreterError::Runtime(err)) =
-                        interp.call_value(callable.clone(), 1)
-                {
-                    return Err(err);
+pub fn filter(
+    interp: &mut bytecode_interpreter::Interpreter,
+    args: &[value::Value]
+) -> Result<value::Value, String> {
+    match args[1].unbox(&interp.heap) {
+        value::Unboxed::List(id) => {
+            let list_elements = interp.heap.get_list_elements(id).clone();
+            let callable = args[0];
+            callable_arg(interp, "filter", "first", &callable)?;
+            let mut res_elements = Vec::new();
+            for element in list_elements.iter() {
+                let keep = call_and_wait(interp, &callable, std::slice::from_ref(element))?;
+                if !interp.is_falsey(&keep) {
+                    res_elements.push(*element);
                 }
+            }
+            Ok(value::Value::list(interp.heap.manage_list(res_elements)))
+        }
+        value::Unboxed::Generator(_) | value::Unboxed::Instance(_) =>
+            interp.prelude_generator("lazyFilter", args),
+        _ => Err(format!("Can't call filter on value of type {:?}", value::type_of(&args[1]))),
+    }
+}
+
+/* ---------- ---------- 列表相关的内置函数 ---------- ---------- */
+
+/*
+ * 取出列表参数的 id，不是列表就报错；错误信息和 tree-walk 解释器保持一致
+ */
+fn list_arg(
+    interp: &bytecode_interpreter::Interpreter,
+    name: &str,
+    val: &value::Value
+) -> Result<gc::HeapId, String> {
+    match val.unbox(&interp.heap) {
+        value::Unboxed::List(id) => Ok(id),
+        _ => Err(format!("Can't call {} on value of type {:?}", name, value::type_of(val))),
+    }
+}
+
+/*
+ * push / pop / insert / remove 会修改列表，冻结了的列表不行
+ */
+fn mutable_list_arg(
+    interp: &bytecode_interpreter::Interpreter,
+    name: &str,
+    val: &value::Value
+) -> Result<gc::HeapId, String> {
+    let id = list_arg(interp, name, val)?;
+    if interp.heap.is_frozen(id) {
+        return Err(format!("Can't call {} on a frozen list", name));
+    }
+    Ok(id)
+}
+
+/*
+ * 和下标访问一样，负数从右往左数；insert 的时候 index 可以等于长度
+ */
+fn list_index(
+    interp: &bytecode_interpreter::Interpreter,
+    name: &str,
+    len: usize,
+    val: &value::Value,
+    allow_end: bool
+) -> Result<usize, String> {
+    let index = match val.unbox(&interp.heap) {
+        value::Unboxed::Integer(n) => n,
+        // 带小数的下标不会悄悄截断
+        value::Unboxed::Number(n) if n.fract() == 0.0 => n as i64,
+        value::Unboxed::Number(n) => {
+            return Err(format!("Invalid index {} in {}", n, name));
+        }
+        _ => {
+            return Err(
+                format!("Invalid index of type {:?} in {}", value::type_of(val), name)
+            );
+        }
+    };
+    let len = len as i64;
+    let upper = if allow_end { len } else { len - 1 };
+    let index = if index < 0 { index + len } else { index };
+    if 0 <= index && index <= upper {
+        Ok(index as usize)
+    } else {
+        Err(format!("List index out of range in {}", name))
+    }
+}
 
-                // If we're calling a pure lox function, `interp.call_value` doesn't actually
-                // call the value, it just sets up a call frame. We loop the interpreter
-                // until it his an error or returns to the call frame with `frame_idx`.
-                // Unfortunately, this doesn't play well with our current debugger
-                // implementation, which manually calls `interpreter.step()`
-                loop {
-                    if interp.frames.len() == frame_idx {
-                        break;
-                    }
-
-                    if
-                        let Err(bytecode_interpreter::InterpreterError::Runtime(err)) =
-                            interp.step()
-                    {
-                        return Err(err);
-                    }
+/*
+ * 检查参数是不是可以调用的；错误信息和 tree-walk 解释器保持一致
+ */
+fn callable_arg(
+    interp: &bytecode_interpreter::Interpreter,
+    name: &str,
+    position: &str,
+    val: &value::Value
+) -> Result<(), String> {
+    match val.unbox(&interp.heap) {
+        value::Unboxed::Function(_) |
+        value::Unboxed::NativeFunction(_) |
+        value::Unboxed::BoundMethod(_) |
+        value::Unboxed::Class(_) |
+        value::Unboxed::GeneratorMethod(_, _) => Ok(()),
+        _ =>
+            Err(
+                format!(
+                    "The {} argument to {} must be callable. Found {:?}",
+                    position,
+                    name,
+                    value::type_of(val)
+                )
+            ),
+    }
+}
+
+/*
+ * 稳定的归并排序，比较函数可能会调用 lox 代码，所以允许出错
+ * 不用 slice::sort_by：用户给的比较函数不一定满足全序
+ */
+pub fn merge_sort<T: Clone, F>(items: Vec<T>, less_or_equal: &mut F) -> Result<Vec<T>, String>
+    where F: FnMut(&T, &T) -> Result<bool, String>
+{
+    if items.len() <= 1 {
+        return Ok(items);
+    }
+    let mut left = items;
+    let right = left.split_off(left.len() / 2);
+    let left = merge_sort(left, less_or_equal)?;
+    let right = merge_sort(right, less_or_equal)?;
+
+    let mut res = Vec::with_capacity(left.len() + right.len());
+    let (mut i, mut j) = (0, 0);
+    while i < left.len() && j < right.len() {
+        if less_or_equal(&left[i], &right[j])? {
+            res.push(left[i].clone());
+            i += 1;
+        } else {
+            res.push(right[j].clone());
+            j += 1;
+        }
+    }
+    res.extend_from_slice(&left[i..]);
+    res.extend_from_slice(&right[j..]);
+    Ok(res)
+}
+
+pub fn reduce(
+    interp: &mut bytecode_interpreter::Interpreter,
+    args: &[value::Value]
+) -> Result<value::Value, String> {
+    let id = list_arg(interp, "reduce", &args[1])?;
+    callable_arg(interp, "reduce", "first", &args[0])?;
+    let list_elements = interp.heap.get_list_elements(id).clone();
+    let callable = args[0];
+    let mut acc = args[2];
+    for element in list_elements {
+        acc = call_and_wait(interp, &callable, &[acc, element])?;
+    }
+    Ok(acc)
+}
+
+/*
+ * sort(xs) 或者 sort(xs, cmp)，返回一个新的列表
+ * cmp(a, b) 返回负数表示 a 排在 b 前面
+ */
+pub fn sort(
+    interp: &mut bytecode_interpreter::Interpreter,
+    args: &[value::Value]
+) -> Result<value::Value, String> {
+    let id = list_arg(interp, "sort", &args[0])?;
+    let list_elements = interp.heap.get_list_elements(id).clone();
+    let sorted = match args.get(1).cloned() {
+        Some(comparator) => {
+            callable_arg(interp, "sort", "second", &comparator)?;
+            merge_sort(list_elements, &mut (|a: &value::Value, b: &value::Value| {
+                let res = call_and_wait(interp, &comparator, &[*a, *b])?;
+                match res.unbox(&interp.heap) {
+                    value::Unboxed::Number(n) => Ok(n <= 0.0),
+                    value::Unboxed::Integer(n) => Ok(n <= 0),
+                    _ =>
+                        Err(
+                            format!(
+                                "The comparator passed to sort must return a number. Found {:?}",
+                                value::type_of(&res)
+                            )
+                        ),
                 }
+            }))?
+        }
+        None =>
+            merge_sort(list_elements, &mut (|a: &value::Value, b: &value::Value| {
+                if let Some(ordering) = interp.compare_numbers(a, b) {
+                    return Ok(matches!(ordering, Some(Ordering::Less | Ordering::Equal)));
+                }
+                match (a.unbox(&interp.heap), b.unbox(&interp.heap)) {
+                    (value::Unboxed::String(s1), value::Unboxed::String(s2)) =>
+                        Ok(interp.heap.get_str(s1) <= interp.heap.get_str(s2)),
+                    _ =>
+                        Err(
+                            format!(
+                                "Can't compare values of type {:?} and {:?} in sort",
+                                value::type_of(a),
+                                value::type_of(b)
+                            )
+                        ),
+                }
+            }))?,
+    };
+    Ok(value::Value::list(interp.heap.manage_list(sorted)))
+}
+
+pub fn reverse(
+    interp: &mut bytecode_interpreter::Interpreter,
+    args: &[value::Value]
+) -> Result<value::Value, String> {
+    let id = list_arg(interp, "reverse", &args[0])?;
+    let mut list_elements = interp.heap.get_list_elements(id).clone();
+    list_elements.reverse();
+    Ok(value::Value::list(interp.heap.manage_list(list_elements)))
+}
+
+pub fn push(
+    interp: &mut bytecode_interpreter::Interpreter,
+    args: &[value::Value]
+) -> Result<value::Value, String> {
+    let id = mutable_list_arg(interp, "push", &args[0])?;
+    interp.heap.get_list_elements_mut(id).push(args[1]);
+    Ok(value::Value::NIL)
+}
+
+pub fn pop(
+    interp: &mut bytecode_interpreter::Interpreter,
+    args: &[value::Value]
+) -> Result<value::Value, String> {
+    let id = mutable_list_arg(interp, "pop", &args[0])?;
+    match interp.heap.get_list_elements_mut(id).pop() {
+        Some(val) => Ok(val),
+        None => Err("Can't pop from an empty list".to_string()),
+    }
+}
+
+pub fn insert(
+    interp: &mut bytecode_interpreter::Interpreter,
+    args: &[value::Value]
+) -> Result<value::Value, String> {
+    let id = mutable_list_arg(interp, "insert", &args[0])?;
+    let len = interp.heap.get_list_elements(id).len();
+    let index = list_index(interp, "insert", len, &args[1], true)?;
+    interp.heap.get_list_elements_mut(id).insert(index, args[2]);
+    Ok(value::Value::NIL)
+}
+
+pub fn remove(
+    interp: &mut bytecode_interpreter::Interpreter,
+    args: &[value::Value]
+) -> Result<value::Value, String> {
+    let id = mutable_list_arg(interp, "remove", &args[0])?;
+    let len = interp.heap.get_list_elements(id).len();
+    let index = list_index(interp, "remove", len, &args[1], false)?;
+    Ok(interp.heap.get_list_elements_mut(id).remove(index))
+}
+
+/*
+ * freeze(obj)：以后不能再修改实例的字段或者列表的元素，返回 obj 本身
+ */
+pub fn freeze(
+    interp: &mut bytecode_interpreter::Interpreter,
+    args: &[value::Value]
+) -> Result<value::Value, String> {
+    match args[0].unbox(&interp.heap) {
+        value::Unboxed::Instance(id) | value::Unboxed::List(id) => {
+            interp.heap.freeze(id);
+            Ok(args[0])
+        }
+        _ => Err(format!("Can't freeze a value of type {:?}", value::type_of(&args[0]))),
+    }
+}
+
+/*
+ * tag(v)：变体的名字，比如 Shape.Circle(1) 的 tag 是 "Circle"
+ */
+pub fn tag(
+    interp: &mut bytecode_interpreter::Interpreter,
+    args: &[value::Value]
+) -> Result<value::Value, String> {
+    match args[0].unbox(&interp.heap) {
+        value::Unboxed::Variant(id) => {
+            let variant = interp.heap.get_variant(id);
+            let name = interp.heap.get_enum(variant.enum_id).variants[variant.tag].name.clone();
+            Ok(value::Value::string(interp.heap.manage_str(name)))
+        }
+        _ => Err(format!("Can't get the tag of a value of type {:?}", value::type_of(&args[0]))),
+    }
+}
+
+/*
+ * Channel(capacity)：没有参数就是不带缓冲区的通道，send 要等到有人 recv 才返回
+ */
+pub fn channel(
+    interp: &mut bytecode_interpreter::Interpreter,
+    args: &[value::Value]
+) -> Result<value::Value, String> {
+    let capacity = match args.first().map(|arg| arg.unbox(&interp.heap)) {
+        None => 0,
+        Some(value::Unboxed::Integer(n)) if n >= 0 => n as usize,
+        Some(_) =>
+            {
+                return Err(
+                    format!(
+                        "Channel capacity must be a non-negative integer, got {:?}",
+                        value::type_of(&args[0])
+                    )
+                );
+            }
+    };
+    Ok(
+        value::Value::channel(
+            interp.heap.manage_channel(value::Channel {
+                capacity,
+                ..Default::default()
+            })
+        )
+    )
+}
+
+/* ---------- 事件循环 ---------- */
+
+fn millis_arg(
+    interp: &bytecode_interpreter::Interpreter,
+    name: &str,
+    val: &value::Value
+) -> Result<f64, String> {
+    match interp.extract_number(val) {
+        Some(ms) if ms >= 0.0 => Ok(ms),
+        _ => Err(format!("{} expects a non-negative number of milliseconds", name)),
+    }
+}
+
+/*
+ * setTimeout(fn, ms)：ms 毫秒以后在新的 fiber 上调用 fn，返回定时器的编号
+ */
+pub fn set_timeout(
+    interp: &mut bytecode_interpreter::Interpreter,
+    args: &[value::Value]
+) -> Result<value::Value, String> {
+    let delay = millis_arg(interp, "setTimeout", &args[1])?;
+    let id = interp.event_loop.add_timer(delay, None, event_loop::TimerAction::Call(args[0]));
+    Ok(interp.heap.integer(id as i64))
+}
+
+pub fn set_interval(
+    interp: &mut bytecode_interpreter::Interpreter,
+    args: &[value::Value]
+) -> Result<value::Value, String> {
+    let interval = millis_arg(interp, "setInterval", &args[1])?;
+    if interval == 0.0 {
+        return Err("setInterval expects a positive interval".to_string());
+    }
+    let id = interp.event_loop.add_timer(
+        interval,
+        Some(interval),
+        event_loop::TimerAction::Call(args[0])
+    );
+    Ok(interp.heap.integer(id as i64))
+}
+
+/*
+ * clearTimeout / clearInterval，已经触发过或者取消过的定时器什么都不做
+ */
+pub fn clear_timer(
+    interp: &mut bytecode_interpreter::Interpreter,
+    args: &[value::Value]
+) -> Result<value::Value, String> {
+    match args[0].unbox(&interp.heap) {
+        value::Unboxed::Integer(id) => {
+            interp.event_loop.cancel(id as usize);
+            Ok(value::Value::NIL)
+        }
+        _ => Err(format!("Expected a timer id, got {:?}", value::type_of(&args[0]))),
+    }
+}
+
+pub fn sleep(
+    interp: &mut bytecode_interpreter::Interpreter,
+    args: &[value::Value]
+) -> Result<value::Value, String> {
+    let ms = millis_arg(interp, "sleep", &args[0])?;
+    interp.sleep(ms)?;
+    Ok(value::Value::NIL)
+}
+
+/*
+ * Isolate(path, entry)：在新线程上跑 path 这个模块，entry 是模块跑完以后要调用的函数名
+ */
+pub fn isolate(
+    interp: &mut bytecode_interpreter::Interpreter,
+    args: &[value::Value]
+) -> Result<value::Value, String> {
+    let path = match args[0].unbox(&interp.heap) {
+        value::Unboxed::String(id) => interp.heap.get_str(id).clone(),
+        _ => {
+            return Err(format!("Expected a module path, got {:?}", value::type_of(&args[0])));
+        }
+    };
+    let entry = match args.get(1).map(|arg| arg.unbox(&interp.heap)) {
+        None | Some(value::Unboxed::Nil) => None,
+        Some(value::Unboxed::String(id)) => Some(interp.heap.get_str(id).clone()),
+        Some(_) => {
+            return Err(format!("Expected an entry function name, got {:?}", value::type_of(&args[1])));
+        }
+    };
+    let source = std::fs
+        ::read_to_string(&path)
+        .map_err(|err| format!("could not read {}: {}", path, err))?;
+    let isolate = isolate::Isolate::spawn(&path, source, entry);
+    Ok(value::Value::isolate(interp.heap.manage_isolate(isolate)))
+}
 
-                res_elements.push(interp.pop_stack());
+/* ---------- ---------- json ---------- ---------- */
+
+/*
+ * json.parse(text)：JSON 对象变成 JsonObject 的实例
+ */
+pub fn json_parse(
+    interp: &mut bytecode_interpreter::Interpreter,
+    args: &[value::Value]
+) -> Result<value::Value, String> {
+    let text = match args[0].unbox(&interp.heap) {
+        value::Unboxed::String(id) => interp.heap.get_str(id).clone(),
+        _ => {
+            return Err(format!("Expected a JSON string, got {:?}", value::type_of(&args[0])));
+        }
+    };
+    let parsed = json::parse(&text)?;
+    let class_id = interp.prelude_class("JsonObject");
+    Ok(json_to_value(interp, parsed, class_id))
+}
+
+/*
+ * json.stringify(value, indent)：indent 是空格的个数或者一个字符串，不给就输出在一行里
+ */
+pub fn json_stringify(
+    interp: &mut bytecode_interpreter::Interpreter,
+    args: &[value::Value]
+) -> Result<value::Value, String> {
+    let indent = match args.get(1).map(|arg| arg.unbox(&interp.heap)) {
+        None | Some(value::Unboxed::Nil) => String::new(),
+        Some(value::Unboxed::Integer(n)) if n >= 0 => " ".repeat(n as usize),
+        Some(value::Unboxed::String(id)) => interp.heap.get_str(id).clone(),
+        Some(_) => {
+            return Err(
+                format!(
+                    "Expected the indent to be a number of spaces or a string, got {:?}",
+                    value::type_of(&args[1])
+                )
+            );
+        }
+    };
+    let converted = value_to_json(interp, &args[0], &mut HashSet::new())?;
+    let text = json::stringify(&converted, &indent)?;
+    Ok(value::Value::string(interp.heap.manage_str(text)))
+}
+
+/*
+ * 实例的字段按名字排序，输出是确定的。path 是从最外层到当前位置的列表和实例，用来发现环，也限制了嵌套的深度
+ */
+fn value_to_json(
+    interp: &bytecode_interpreter::Interpreter,
+    val: &value::Value,
+    path: &mut HashSet<gc::HeapId>
+) -> Result<json::Json, String> {
+    match val.unbox(&interp.heap) {
+        value::Unboxed::Nil => Ok(json::Json::Null),
+        value::Unboxed::Bool(b) => Ok(json::Json::Bool(b)),
+        value::Unboxed::Integer(n) => Ok(json::Json::Integer(n)),
+        value::Unboxed::Number(n) => Ok(json::Json::Number(n)),
+        value::Unboxed::String(id) => Ok(json::Json::String(interp.heap.get_str(id).clone())),
+        value::Unboxed::List(id) | value::Unboxed::Instance(id) => {
+            if path.len() == json::MAX_DEPTH {
+                return Err(json::too_deep());
+            }
+            if !path.insert(id) {
+                return Err("Cannot convert a cyclic structure to JSON".to_string());
             }
-            Ok(value::Value::List(interp.heap.manage_list(res_elements)))
+            let res = match val.unbox(&interp.heap) {
+                value::Unboxed::List(_) =>
+                    json::Json::List(
+                        interp
+                            .get_list_elements(id)
+                            .iter()
+                            .map(|element| value_to_json(interp, element, path))
+                            .collect::<Result<Vec<json::Json>, String>>()?
+                    ),
+                _ => {
+                    let mut fields: Vec<(&String, &value::Value)> = interp.heap
+                        .get_instance(id)
+                        .fields.iter()
+                        .collect();
+                    fields.sort_by_key(|(name, _)| *name);
+                    json::Json::Object(
+                        fields
+                            .into_iter()
+                            .map(|(name, field)| Ok((name.clone(), value_to_json(interp, field, path)?)))
+                            .collect::<Result<Vec<(String, json::Json)>, String>>()?
+                    )
+                }
+            };
+            path.remove(&id);
+            Ok(res)
         }
-        val => Err(format!("Can't call forEach on value of type {:?}.", value::type_of(val))),
+        _ => Err(format!("Cannot convert a value of type {:?} to JSON", value::type_of(val))),
     }
 }
+
+fn json_to_value(
+    interp: &mut bytecode_interpreter::Interpreter,
+    parsed: json::Json,
+    class_id: gc::HeapId
+) -> value::Value {
+    match parsed {
+        json::Json::Null => value::Value::NIL,
+        json::Json::Bool(b) => value::Value::bool(b),
+        json::Json::Integer(n) => interp.heap.integer(n),
+        json::Json::Number(n) => value::Value::number(n),
+        json::Json::String(s) => value::Value::string(interp.heap.manage_str(s)),
+        json::Json::List(elements) => {
+            let elements = elements
+                .into_iter()
+                .map(|element| json_to_value(interp, element, class_id))
+                .collect();
+            value::Value::list(interp.heap.manage_list(elements))
+        }
+        json::Json::Object(entries) => {
+            let fields = entries
+                .into_iter()
+                .map(|(name, field)| (name, json_to_value(interp, field, class_id)))
+                .collect();
+            value::Value::instance(interp.heap.manage_instance(value::Instance { class_id, fields }))
+        }
+    }
+}
+
+pub fn contains(
+    interp: &mut bytecode_interpreter::Interpreter,
+    args: &[value::Value]
+) -> Result<value::Value, String> {
+    let id = list_arg(interp, "contains", &args[0])?;
+    let found = interp.heap
+        .get_list_elements(id)
+        .iter()
+        .any(|element| interp.values_equal(element, &args[1]));
+    Ok(value::Value::bool(found))
+}
+
+pub fn index_of(
+    interp: &mut bytecode_interpreter::Interpreter,
+    args: &[value::Value]
+) -> Result<value::Value, String> {
+    let id = list_arg(interp, "indexOf", &args[0])?;
+    let index = interp.heap
+        .get_list_elements(id)
+        .iter()
+        .position(|element| interp.values_equal(element, &args[1]));
+    Ok(interp.heap.integer(index.map_or(-1, |index| index as i64)))
+}
+
+pub fn concat(
+    interp: &mut bytecode_interpreter::Interpreter,
+    args: &[value::Value]
+) -> Result<value::Value, String> {
+    let id1 = list_arg(interp, "concat", &args[0])?;
+    let id2 = list_arg(interp, "concat", &args[1])?;
+    let mut res = interp.heap.get_list_elements(id1).clone();
+    res.extend(interp.heap.get_list_elements(id2).iter().cloned());
+    Ok(value::Value::list(interp.heap.manage_list(res)))
+}
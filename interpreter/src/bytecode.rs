@@ -1,9 +1,11 @@
-/// 定义了一系列的结构和枚举类型
+//! 定义了一系列的结构和枚举类型
 
 use serde::{ Deserialize, Serialize };
 
+use std::cell::{ OnceCell, RefCell };
 use std::f64;
 use std::fmt;
+use std::rc::Rc;
 
 /* ---------- ---------- 记录行号（调试信息） ---------- ---------- */
 
@@ -43,13 +45,25 @@ pub enum Op {
     Subtract,
     Multiply,
     Divide,
+    Modulo,
+    Power,
+    IntDivide, // 整除，结果向下取整
+    BitAnd,
+    BitOr,
+    BitXor,
+    ShiftLeft,
+    ShiftRight,
     Not,
     Equal,
     Greater,
     Less,
+    InstanceOf, // 栈上依次是值和类，沿着超类链往上找
     Print,
     Pop,
+    Dup(usize), // 把距离栈顶 n 个位置的值复制一份压栈，Dup(0) 就是复制栈顶
+    Rot(usize), // 把栈顶的值往下挪 n 个位置，复合赋值的时候用来调整操作数的顺序
     DefineGlobal(usize),
+    DefineConstGlobal(usize), // 和 DefineGlobal 一样，另外记下来这个名字以后不能再赋值
     GetGlobal(usize),
     SetGlobal(usize),
     GetLocal(usize),
@@ -59,19 +73,49 @@ pub enum Op {
     JumpIfFalse(usize),
     Jump(usize),
     Loop(usize),
+    GetIter, // for-in 开始之前把栈顶的值换成迭代器：列表不变，有 iter 方法的实例换成 iter() 的返回值
+    ForIter(usize), // 栈顶是下标，下面是迭代器；列表用完了就跳出循环，否则跳过后面的 JumpIfNil
+    JumpIfNil(usize), // for-in 的迭代器用完了（一般是 next() 返回了 nil）就弹出栈顶然后跳转
+    Yield, // 生成器暂停，把当前的调用帧和栈上的槽位收起来，栈顶的值交给 next() 的调用者
     Call(u8),
+    Spawn(u8, /*names*/ Vec<String>), // spawn f(...)：被调用的值和参数搬到一个新的 fiber 上，轮到它的时候再调用
+    SpawnInvoke(/*method_name*/ String, u8, /*names*/ Vec<String>), // spawn obj.method(...)：轮到它的时候再在接收者上调用方法
+    YieldFiber, // 不带值的 yield：把执行权让给下一个能跑的 fiber
+    Await, // 栈顶的 promise 换成它的结果，还没有结果就阻塞当前的 fiber
+    CallNamed(u8, /*最后几个实参的名字*/ Vec<String>),
+    TailCall(u8, /*names*/ Vec<String>), // return f(...)：被调用的闭包直接用当前的调用帧
     CloseUpvalue,
     Class(usize),
-    SetProperty(usize),
-    GetProperty(usize),
+    SetProperty(usize, /*内联缓存*/ usize),
+    GetProperty(usize, /*内联缓存*/ usize),
     Method(usize),
+    ClassMethod(usize), // 静态方法，和 Method 一样从栈顶取闭包放进栈顶第二个的类里
+    Getter(usize),
+    Setter(usize),
     // 调用
-    Invoke(/*method_name*/ String, /*arg count*/ u8),
+    Invoke(/*method_name*/ String, /*arg count*/ u8, /*内联缓存*/ usize),
+    InvokeNamed(
+        /*method_name*/ String,
+        /*arg count*/ u8,
+        /*names*/ Vec<String>,
+        /*内联缓存*/ usize,
+    ),
     Inherit,
+    Trait(usize),
+    TraitMethod(usize), // 栈顶是闭包，下面是 trait
+    RequireMethod(usize), // 栈顶的 trait 要求混入它的类提供这个方法
+    Mixin(u8), // 栈顶的 n 个 trait 混入下面的类
     GetSuper(usize),
     SuperInvoke(/*method_name*/ String, /*arg count*/ u8),
+    SuperInvokeNamed(/*method_name*/ String, /*arg count*/ u8, /*names*/ Vec<String>),
     BuildList(usize),
+    MatchList(usize), // match 的列表模式：把栈顶换成它是不是长度为 n 的列表
+    HasProperty(usize), // match 的实例模式：把栈顶的实例换成它有没有这个名字的字段、getter 或者方法
+    Enum(usize),
+    EnumVariant(usize, /*字段名*/ Vec<String>), // 给栈顶的枚举加一个变体
     Subscr,
+    Interpolate(usize), // 栈顶的几个值转成字符串拼起来
+    Slice, // list[a:b]，栈上依次是 list、a、b，省略的一端是 nil
     SetItem,
 }
 
@@ -82,9 +126,17 @@ pub enum Op {
  */
 #[derive(Default, Clone, Debug)]
 pub struct Function {
-    pub arity: u8,
+    pub arity: u8, // 必须传的参数个数
+    pub optional_args: u8, // 后面有默认值的参数个数
+    pub has_rest: bool, // 最后有一个 ...rest 参数
+    pub param_names: Vec<String>, // 命名参数按名字找位置
     pub chunk: Chunk,
     pub name: String,
+    pub is_generator: bool, // 函数体里面有 yield，调用的时候返回一个生成器
+    pub is_async: bool, // async fun：调用的时候在新的 fiber 上跑，返回一个 promise
+    #[cfg(feature = "jit")]
+    pub jit: Rc<RefCell<crate::jit::FunctionState>>, // 调用计数和本地代码，和内联缓存一样拷贝之间共用
+    pub registers: Rc<OnceCell<crate::register::Code>>, // 寄存器后端第一次执行的时候翻译，拷贝之间共用
 }
 
 /**
@@ -104,6 +156,7 @@ pub struct Closure {
 #[derive(Debug, Clone)]
 pub enum Constant {
     Number(f64),
+    Integer(i64),
     String(String),
     Function(Closure),
 }
@@ -112,14 +165,48 @@ impl fmt::Display for Constant {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             Constant::Number(n) => write!(f, "{}", n),
+            Constant::Integer(n) => write!(f, "{}", n),
             Constant::String(s) => write!(f, "\"{}\"", s),
             Constant::Function(
-                Closure { function: Function { arity: _, chunk: _, name }, upvalues: _ },
+                Closure { function: Function { name, .. }, upvalues: _ },
             ) => write!(f, "<fn {}>", name),
         }
     }
 }
 
+/* ---------- ---------- 内联缓存 ---------- ---------- */
+
+/**
+ * 属性访问、方法调用的指令各占一个缓存槽，记下上一次见到的类和在这个类上找到的东西。
+ * 类声明完以后方法表就不再变了。HeapId 绕回来以后会复用，一个类被回收以后新的类可能拿到同一个 id，
+ * 所以要 class_id 和 Heap::epoch 都对上缓存才有效
+ */
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub enum InlineCache {
+    #[default]
+    Empty,
+    Method {
+        class_id: usize,
+        epoch: usize,
+        closure_id: usize,
+    },
+    Getter {
+        class_id: usize,
+        epoch: usize,
+        closure_id: usize,
+    },
+    Setter {
+        class_id: usize,
+        epoch: usize,
+        closure_id: usize,
+    },
+    // 类上没有同名的 setter，直接写字段
+    Field {
+        class_id: usize,
+        epoch: usize,
+    },
+}
+
 /* ---------- ---------- 代码块 ---------- ---------- */
 
 /**
@@ -129,9 +216,20 @@ impl fmt::Display for Constant {
 pub struct Chunk {
     pub code: Vec<(Op, Lineno)>,
     pub constants: Vec<Constant>, // 字面量池
+    // 闭包拿到的是 chunk 的拷贝，缓存要共享，不然每次调用都从空的开始
+    pub inline_caches: Rc<RefCell<Vec<InlineCache>>>,
 }
 
 impl Chunk {
+    /**
+     * 分配一个内联缓存槽，返回它的下标
+     */
+    pub fn add_inline_cache(&mut self) -> usize {
+        let mut caches = self.inline_caches.borrow_mut();
+        caches.push(InlineCache::Empty);
+        caches.len() - 1
+    }
+
     /**
      * 添加字面量 数字
      */
@@ -139,6 +237,16 @@ impl Chunk {
         if let Some(id) = self.find_number(c) { id } else { self.add_constant(Constant::Number(c)) }
     }
 
+    /**
+     * 添加字面量 整数
+     */
+    pub fn add_constant_integer(&mut self, c: i64) -> usize {
+        let maybe_id = self.constants
+            .iter()
+            .position(|constant| matches!(constant, Constant::Integer(c2) if *c2 == c));
+        if let Some(id) = maybe_id { id } else { self.add_constant(Constant::Integer(c)) }
+    }
+
     /**
      * 添加字面量 String
      */
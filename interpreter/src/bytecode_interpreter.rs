@@ -1,12 +1,22 @@
 use crate::builtins;
 use crate::bytecode;
+use crate::compiler;
+use crate::event_loop;
+use crate::extensions;
+use crate::fiber;
 use crate::gc;
+#[cfg(feature = "jit")]
+use crate::jit;
+use crate::prelude;
 use crate::value;
 
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::cmp::Ordering;
+use std::collections::{ HashMap, HashSet };
 use std::fmt;
 use std::rc::Rc;
+use std::sync::atomic::{ AtomicBool, Ordering as AtomicOrdering };
+use std::sync::Arc;
 
 /* ---------- ---------- 反汇编 ---------- ---------- */
 
@@ -19,6 +29,7 @@ pub fn disassemble_code(chunk: &bytecode::Chunk) -> Vec<String> {
     for (idx, (op, lineno)) in chunk.code.iter().enumerate() {
         let formatted_op = match op {
             bytecode::Op::Return => "OP_RETURN".to_string(),
+            bytecode::Op::Yield => "OP_YIELD".to_string(),
             bytecode::Op::Constant(const_idx) =>
                 format!("OP_CONSTANT {} (idx={})", chunk.constants[*const_idx], *const_idx),
             bytecode::Op::Nil => "OP_NIL".to_string(),
@@ -29,18 +40,35 @@ pub fn disassemble_code(chunk: &bytecode::Chunk) -> Vec<String> {
             bytecode::Op::Subtract => "OP_SUBTRACT".to_string(),
             bytecode::Op::Multiply => "OP_MULTIPLY".to_string(),
             bytecode::Op::Divide => "OP_DIVIDE".to_string(),
+            bytecode::Op::Modulo => "OP_MODULO".to_string(),
+            bytecode::Op::Power => "OP_POWER".to_string(),
+            bytecode::Op::IntDivide => "OP_INT_DIVIDE".to_string(),
+            bytecode::Op::BitAnd => "OP_BIT_AND".to_string(),
+            bytecode::Op::BitOr => "OP_BIT_OR".to_string(),
+            bytecode::Op::BitXor => "OP_BIT_XOR".to_string(),
+            bytecode::Op::ShiftLeft => "OP_SHIFT_LEFT".to_string(),
+            bytecode::Op::ShiftRight => "OP_SHIFT_RIGHT".to_string(),
             bytecode::Op::Not => "OP_NOT".to_string(),
-            bytecode::Op::Equal => "OP_NOT".to_string(),
+            bytecode::Op::Equal => "OP_EQUAL".to_string(),
             bytecode::Op::Greater => "OP_GREATER".to_string(),
             bytecode::Op::Less => "OP_LESS".to_string(),
+            bytecode::Op::InstanceOf => "OP_INSTANCE_OF".to_string(),
             bytecode::Op::Print => "OP_PRINT".to_string(),
             bytecode::Op::Pop => "OP_POP".to_string(),
+            bytecode::Op::Dup(depth) => format!("OP_DUP {}", depth),
+            bytecode::Op::Rot(depth) => format!("OP_ROT {}", depth),
             bytecode::Op::DefineGlobal(global_idx) =>
                 format!(
                     "OP_DEFINE_GLOBAL {:?} (idx={})",
                     chunk.constants[*global_idx],
                     *global_idx
                 ),
+            bytecode::Op::DefineConstGlobal(global_idx) =>
+                format!(
+                    "OP_DEFINE_CONST_GLOBAL {:?} (idx={})",
+                    chunk.constants[*global_idx],
+                    *global_idx
+                ),
             bytecode::Op::GetGlobal(global_idx) =>
                 format!("OP_GET_GLOBAL {:?} (idx={})", chunk.constants[*global_idx], *global_idx),
             bytecode::Op::SetGlobal(global_idx) =>
@@ -52,23 +80,74 @@ pub fn disassemble_code(chunk: &bytecode::Chunk) -> Vec<String> {
             bytecode::Op::JumpIfFalse(loc) => format!("OP_JUMP_IF_FALSE {}", *loc),
             bytecode::Op::Jump(offset) => format!("OP_JUMP {}", *offset),
             bytecode::Op::Loop(offset) => format!("OP_LOOP {}", *offset),
+            bytecode::Op::GetIter => "OP_GET_ITER".to_string(),
+            bytecode::Op::ForIter(offset) => format!("OP_FOR_ITER {}", *offset),
+            bytecode::Op::JumpIfNil(offset) => format!("OP_JUMP_IF_NIL {}", *offset),
             bytecode::Op::Call(arg_count) => format!("OP_CALL {}", *arg_count),
+            bytecode::Op::Spawn(arg_count, names) => format!("OP_SPAWN {} {:?}", *arg_count, names),
+            bytecode::Op::SpawnInvoke(method_name, arg_count, names) => {
+                format!("OP_SPAWN_INVOKE {} {} {:?}", method_name, *arg_count, names)
+            }
+            bytecode::Op::YieldFiber => "OP_YIELD_FIBER".to_string(),
+            bytecode::Op::Await => "OP_AWAIT".to_string(),
+            bytecode::Op::CallNamed(arg_count, names) => {
+                format!("OP_CALL_NAMED {} names={}", *arg_count, names.join(","))
+            }
+            bytecode::Op::TailCall(arg_count, names) => {
+                format!("OP_TAIL_CALL {} names={}", *arg_count, names.join(","))
+            }
             bytecode::Op::Closure(idx, _) => format!("OP_CLOSURE {}", chunk.constants[*idx]),
             bytecode::Op::CloseUpvalue => "OP_CLOSE_UPVALUE".to_string(),
             bytecode::Op::Class(idx) => format!("OP_CLASS {}", idx),
-            bytecode::Op::SetProperty(idx) => format!("OP_SET_PROPERTY {}", idx),
-            bytecode::Op::GetProperty(idx) => format!("OP_GET_PROPERTY {}", idx),
+            bytecode::Op::SetProperty(idx, cache) => {
+                format!("OP_SET_PROPERTY {} (cache={})", idx, cache)
+            }
+            bytecode::Op::GetProperty(idx, cache) => {
+                format!("OP_GET_PROPERTY {} (cache={})", idx, cache)
+            }
             bytecode::Op::Method(idx) => format!("OP_METHOD {}", idx),
-            bytecode::Op::Invoke(method_name, arg_count) => {
-                format!("OP_INVOKE {} nargs={}", method_name, arg_count)
+            bytecode::Op::ClassMethod(idx) => format!("OP_CLASS_METHOD {}", idx),
+            bytecode::Op::Getter(idx) => format!("OP_GETTER {}", idx),
+            bytecode::Op::Setter(idx) => format!("OP_SETTER {}", idx),
+            bytecode::Op::Trait(idx) => format!("OP_TRAIT {}", idx),
+            bytecode::Op::TraitMethod(idx) => format!("OP_TRAIT_METHOD {}", idx),
+            bytecode::Op::RequireMethod(idx) => format!("OP_REQUIRE_METHOD {}", idx),
+            bytecode::Op::Mixin(count) => format!("OP_MIXIN {}", count),
+            bytecode::Op::Invoke(method_name, arg_count, cache) => {
+                format!("OP_INVOKE {} nargs={} (cache={})", method_name, arg_count, cache)
+            }
+            bytecode::Op::InvokeNamed(method_name, arg_count, names, cache) => {
+                format!(
+                    "OP_INVOKE_NAMED {} nargs={} names={} (cache={})",
+                    method_name,
+                    arg_count,
+                    names.join(","),
+                    cache
+                )
             }
             bytecode::Op::Inherit => "OP_INHERIT".to_string(),
             bytecode::Op::GetSuper(idx) => format!("OP_GET_SUPER {}", idx),
             bytecode::Op::SuperInvoke(method_name, arg_count) => {
                 format!("OP_SUPER_INOKE {} nargs={}", method_name, arg_count)
             }
+            bytecode::Op::SuperInvokeNamed(method_name, arg_count, names) => {
+                format!(
+                    "OP_SUPER_INVOKE_NAMED {} nargs={} names={}",
+                    method_name,
+                    arg_count,
+                    names.join(",")
+                )
+            }
             bytecode::Op::BuildList(size) => format!("OP_BUILD_LIST {}", size),
+            bytecode::Op::MatchList(size) => format!("OP_MATCH_LIST {}", size),
+            bytecode::Op::HasProperty(idx) => format!("OP_HAS_PROPERTY {}", idx),
+            bytecode::Op::Enum(idx) => format!("OP_ENUM {}", idx),
+            bytecode::Op::EnumVariant(idx, fields) => {
+                format!("OP_ENUM_VARIANT {} ({})", idx, fields.join(", "))
+            }
             bytecode::Op::Subscr => "OP_SUBSCR".to_string(),
+            bytecode::Op::Slice => "OP_SLICE".to_string(),
+            bytecode::Op::Interpolate(num_parts) => format!("OP_INTERPOLATE {}", num_parts),
             bytecode::Op::SetItem => "OP_SETITEM".to_string(),
         };
 
@@ -107,11 +186,11 @@ pub fn disassemble_chunk(chunk: &bytecode::Chunk, name: &str) -> String {
  */
 fn dis_builtin(interp: &mut Interpreter, args: &[value::Value]) -> Result<value::Value, String> {
     // arity checking is done in the interpreter
-    match &args[0] {
-        value::Value::Function(closure_handle) => {
-            let closure = interp.heap.get_closure(*closure_handle);
+    match args[0].unbox(&interp.heap) {
+        value::Unboxed::Function(closure_handle) => {
+            let closure = interp.heap.get_closure(closure_handle);
             disassemble_chunk(&closure.function.chunk, "");
-            Ok(value::Value::Nil)
+            Ok(value::Value::NIL)
         }
         _ =>
             Err(
@@ -120,23 +199,46 @@ fn dis_builtin(interp: &mut Interpreter, args: &[value::Value]) -> Result<value:
     }
 }
 
-#[allow(dead_code)]
+/**
+ * 可以省略参数的时候，报错信息里写成 1-2 这样
+ */
+fn format_arity(arity: u8, max_arity: u8) -> String {
+    if arity == max_arity { arity.to_string() } else { format!("{}-{}", arity, max_arity) }
+}
+
 #[derive(Debug)]
 enum Binop {
     Add,
     Sub,
     Mul,
     Div,
+    Mod,
+    Pow,
+    IntDiv,
+    BitAnd,
+    BitOr,
+    BitXor,
+    Shl,
+    Shr,
 }
 
 pub struct Interpreter {
     pub frames: Vec<CallFrame>,
     pub stack: Vec<value::Value>,
-    output: Vec<String>, // 可能是用来记载输出结果的
+    pub output: Vec<String>, // print 的输出，测试里面用来比对结果
     pub globals: HashMap<String, value::Value>, // 全局变量表
+    pub const_globals: HashSet<String>, // 用 const / let 定义的全局变量，不能再赋值或者重新定义
     pub upvalues: Vec<Rc<RefCell<value::Upvalue>>>, // 对闭包的支持
     pub heap: gc::Heap, // 用来管理堆空间
     gray_stack: Vec<gc::HeapId>, // 垃圾回收辅助栈
+    prelude: HashMap<String, value::Value>, // prelude 里不导出的函数，只给内置函数用
+    #[cfg(feature = "jit")]
+    pub jit: jit::Jit, // 把热点函数编译成本地代码
+    pub registers: bool, // 用寄存器指令执行（register.rs），默认是栈式的指令
+    pub instruction_count: usize, // 执行了多少条指令，用来比较两种后端
+    pub fibers: fiber::Scheduler, // 暂停着的 fiber，当前在跑的 fiber 的调用帧和栈就是上面的 frames 和 stack
+    pub event_loop: event_loop::EventLoop, // 定时器和时钟
+    pub interrupted: Arc<AtomicBool>, // 设置了以后下一步就报错停下来，isolate 的 kill 用它
 }
 
 impl Default for Interpreter {
@@ -146,71 +248,60 @@ impl Default for Interpreter {
             stack: Default::default(),
             output: Default::default(),
             globals: Default::default(),
+            const_globals: Default::default(),
             upvalues: Default::default(),
             heap: Default::default(),
             gray_stack: Default::default(),
+            prelude: Default::default(),
+            #[cfg(feature = "jit")]
+            jit: Default::default(),
+            registers: false,
+            instruction_count: 0,
+            fibers: Default::default(),
+            event_loop: Default::default(),
+            interrupted: Default::default(),
         };
         res.stack.reserve(256);
         res.frames.reserve(64);
 
         /* ---------- 添加一些内置的函数 ---------- */
 
-        res.globals.insert(
-            String::from("dis"),
-            value::Value::NativeFunction(value::NativeFunction {
-                arity: 1,
-                name: String::from("dis"),
-                func: dis_builtin,
-            })
-        );
-        res.globals.insert(
-            String::from("clock"),
-            value::Value::NativeFunction(value::NativeFunction {
-                arity: 0,
-                name: String::from("clock"),
-                func: builtins::clock,
-            })
-        );
-        res.globals.insert(
-            String::from("exp"),
-            value::Value::NativeFunction(value::NativeFunction {
-                arity: 1,
-                name: String::from("exp"),
-                func: builtins::exp,
-            })
-        );
-        res.globals.insert(
-            String::from("sqrt"),
-            value::Value::NativeFunction(value::NativeFunction {
-                arity: 1,
-                name: String::from("sqrt"),
-                func: builtins::sqrt,
-            })
-        );
-        res.globals.insert(
-            String::from("len"),
-            value::Value::NativeFunction(value::NativeFunction {
-                arity: 1,
-                name: String::from("len"),
-                func: builtins::len,
-            })
-        );
-        res.globals.insert(
-            String::from("forEach"),
-            value::Value::NativeFunction(value::NativeFunction {
-                arity: 2,
-                name: String::from("forEach"),
-                func: builtins::for_each,
-            })
-        );
-        res.globals.insert(
-            String::from("map"),
-            value::Value::NativeFunction(value::NativeFunction {
-                arity: 2,
-                name: String::from("map"),
-                func: builtins::map,
-            })
-        );
+        res.define_native("dis", 1, 0, dis_builtin);
+        res.define_native("clock", 0, 0, builtins::clock);
+        res.define_native("exp", 1, 0, builtins::exp);
+        res.define_native("sqrt", 1, 0, builtins::sqrt);
+        res.define_native("len", 1, 0, builtins::len);
+        res.define_native("forEach", 2, 0, builtins::for_each);
+        res.define_native("map", 2, 0, builtins::map);
+        res.define_native("filter", 2, 0, builtins::filter);
+
+        /* ---------- 列表相关的内置函数 ---------- */
+        res.define_native("reduce", 3, 0, builtins::reduce);
+        res.define_native("sort", 1, 1, builtins::sort);
+        res.define_native("reverse", 1, 0, builtins::reverse);
+        res.define_native("push", 2, 0, builtins::push);
+        res.define_native("pop", 1, 0, builtins::pop);
+        res.define_native("insert", 3, 0, builtins::insert);
+        res.define_native("remove", 2, 0, builtins::remove);
+        res.define_native("contains", 2, 0, builtins::contains);
+        res.define_native("indexOf", 2, 0, builtins::index_of);
+        res.define_native("concat", 2, 0, builtins::concat);
+
+        res.define_native("isInstance", 2, 0, builtins::is_instance);
+        res.define_native("freeze", 1, 0, builtins::freeze);
+        res.define_native("tag", 1, 0, builtins::tag);
+        res.define_native("Channel", 0, 1, builtins::channel);
+
+        /* ---------- 事件循环 ---------- */
+        res.define_native("setTimeout", 2, 0, builtins::set_timeout);
+        res.define_native("setInterval", 2, 0, builtins::set_interval);
+        res.define_native("clearTimeout", 1, 0, builtins::clear_timer);
+        res.define_native("clearInterval", 1, 0, builtins::clear_timer);
+        res.define_native("sleep", 1, 0, builtins::sleep);
+        res.define_native("Isolate", 1, 1, builtins::isolate);
+
+        res.load_prelude();
+        res.define_json();
 
         res
     }
@@ -234,6 +325,9 @@ pub struct CallFrame {
     pub closure: value::Closure,
     pub ip: usize, // 当前执行指令的位置
     pub slots_offset: usize, // 当前调用帧 在 解释器栈的偏移位置
+    pub generator: Option<gc::HeapId>, // 如果是生成器在跑，记下是哪个生成器
+    pub promise: Option<gc::HeapId>, // async 函数的调用帧，返回值交给这个 promise
+    pub elided_frames: usize, // 尾调用复用了这个帧几次，回溯的时候提一句
 }
 
 impl CallFrame {
@@ -268,7 +362,7 @@ impl Interpreter {
     pub fn prepare_interpret(&mut self, func: bytecode::Function) {
         // 把闭包推入栈中
         self.stack.push(
-            value::Value::Function(
+            value::Value::function(
                 self.heap.manage_closure(value::Closure {
                     function: func.clone(),
                     upvalues: Vec::new(),
@@ -284,12 +378,19 @@ impl Interpreter {
             },
             ip: 0,
             slots_offset: 1,
+            generator: None,
+            promise: None,
+            elided_frames: 0,
         });
     }
 
-    fn run(&mut self) -> Result<(), InterpreterError> {
+    pub fn run(&mut self) -> Result<(), InterpreterError> {
         loop {
             if self.is_done() {
+                // 当前的 fiber 跑完了，换下一个能跑的，都跑完了（或者只剩阻塞着的）才结束
+                if self.finish_fiber()? {
+                    continue;
+                }
                 return Ok(());
             }
 
@@ -303,38 +404,88 @@ impl Interpreter {
      * ip 越界了
      */
     pub fn is_done(&self) -> bool {
-        self.frames.is_empty() || self.frame().ip >= self.frame().closure.function.chunk.code.len()
+        if self.frames.is_empty() {
+            return true;
+        }
+        let frame = self.frame();
+        if self.registers {
+            return frame.closure.function.registers
+                .get()
+                .is_some_and(|code| frame.ip >= code.code.len());
+        }
+        frame.ip >= frame.closure.function.chunk.code.len()
     }
 
     pub fn step(&mut self) -> Result<(), InterpreterError> {
-        let op = self.next_op_and_advance();
+        self.instruction_count += 1;
+
+        if self.interrupted.load(AtomicOrdering::Relaxed) {
+            return Err(InterpreterError::Runtime("Interrupted.".to_string()));
+        }
 
         // 每执行一步，都会判断是是否需要执行 垃圾回收
         if self.heap.should_collect() {
             self.collect_garbage();
         }
 
+        if self.registers {
+            self.step_registers()?;
+        } else {
+            let op = self.next_op_and_advance();
+            self.execute(op)?;
+        }
+
+        // 这一步让出了执行权或者阻塞在通道上，指令执行完了才换 fiber
+        if let Some(switch) = self.fibers.pending.take() {
+            self.switch_fiber(switch)?;
+        }
+        Ok(())
+    }
+
+    /**
+     * 执行一条栈式指令。寄存器后端碰到没有寄存器形式的指令，把操作数摆回栈上以后也交给这里
+     */
+    pub fn execute(&mut self, op: (bytecode::Op, bytecode::Lineno)) -> Result<(), InterpreterError> {
+        // 实例上的运算符、下标访问交给类里面定义的特殊方法
+        if let Some((name, swapped)) = Interpreter::operator_method(&op.0) {
+            if self.call_operator_method(name, swapped)? {
+                return Ok(());
+            }
+        }
+
         match op {
             (bytecode::Op::Return, _) => {
                 // 这个结果在 return 步骤之前就已经计算好并放在栈顶了
                 let result = self.pop_stack();
 
-                // slots_offset..self.stack.len() 就是 当前 函数 所有的局部变量
-                for idx in self.frame().slots_offset..self.stack.len() {
+                // slots_offset - 1 是 0 号槽位（函数本身或者 this），后面的是当前函数所有的局部变量
+                for idx in self.frame().slots_offset - 1..self.stack.len() {
                     self.close_upvalues(idx); // 当外层的函数返回的时候，确实要关闭上值
                 }
 
+                // 生成器跑完了，next() 返回 nil
+                let result = match self.frame().generator {
+                    Some(generator_id) => {
+                        self.heap.get_generator_mut(generator_id).state =
+                            value::GeneratorState::Done;
+                        value::Value::NIL
+                    }
+                    None => result,
+                };
+
+                // async 函数跑完了，结果交给 promise。它的调用帧总是 fiber 上的第一个帧
+                if let Some(promise_id) = self.frame().promise {
+                    self.resolve_promise(promise_id, result);
+                }
+
                 // 如果 frame 只有一个元素，说明程序即将结束
                 if self.frames.len() <= 1 {
                     self.frames.pop();
                     return Ok(());
                 }
 
-                // 计算有多少个局部变量（弹多少次）
-                let num_to_pop =
-                    self.stack.len() -
-                    self.frame().slots_offset +
-                    usize::from(self.frame().closure.function.arity);
+                // 计算有多少个局部变量（弹多少次），加上 0 号槽位
+                let num_to_pop = self.stack.len() - self.frame().slots_offset + 1;
                 self.frames.pop(); // 将当前函数 pop 出 frames
 
                 // 我弹
@@ -343,10 +494,43 @@ impl Interpreter {
                 // 将结果压栈，这里是先将结果给拿了出来，因为结果在栈顶，要操作栈顶下面的元素
                 self.stack.push(result);
             }
+            (bytecode::Op::Yield, _) => {
+                let result = self.pop_stack();
+                let generator_id = match self.frame().generator {
+                    Some(generator_id) => generator_id,
+                    None => panic!("yield outside of a generator frame"),
+                };
+                let slot_zero = self.frame().slots_offset - 1;
+                let ip = self.frame().ip;
+
+                // 指向这些槽位的上值先关闭，记下相对 0 号槽位的位置
+                let mut upvalues = Vec::new();
+                for upval in self.upvalues.iter() {
+                    if let value::Upvalue::Open(idx) = &*upval.borrow() {
+                        if *idx >= slot_zero {
+                            upvalues.push((upval.clone(), *idx - slot_zero));
+                        }
+                    }
+                }
+                for (upval, rel) in upvalues.iter() {
+                    upval.replace(value::Upvalue::Closed(self.stack[slot_zero + rel]));
+                }
+                self.upvalues.retain(|u| u.borrow().is_open());
+
+                // 调用帧和槽位都收进生成器，yield 的值放在原来 0 号槽位的位置
+                let slots = self.stack.split_off(slot_zero);
+                self.frames.pop();
+                let generator = self.heap.get_generator_mut(generator_id);
+                generator.ip = ip;
+                generator.slots = slots;
+                generator.upvalues = upvalues;
+                generator.state = value::GeneratorState::Suspended;
+                self.stack.push(result);
+            }
             // 创建一个闭包
             (bytecode::Op::Closure(idx, upvals), _) => {
                 let constant = self.read_constant(idx); // 期望得到的是一个 Value::Function
-                if let value::Value::Function(closure_handle) = constant {
+                if let value::Unboxed::Function(closure_handle) = constant.unbox(&self.heap) {
                     // 判断常量是不是一个函数，如果是函数，得到他的句柄
 
                     let closure = self.get_closure(closure_handle).clone();
@@ -379,7 +563,7 @@ impl Interpreter {
                         .collect();
 
                     self.stack.push(
-                        value::Value::Function(
+                        value::Value::function(
                             self.heap.manage_closure(value::Closure {
                                 function: closure.function,
                                 upvalues,
@@ -401,255 +585,72 @@ impl Interpreter {
                 self.stack.push(constant);
             }
             (bytecode::Op::Nil, _) => {
-                self.stack.push(value::Value::Nil);
+                self.stack.push(value::Value::NIL);
             }
             (bytecode::Op::True, _) => {
-                self.stack.push(value::Value::Bool(true));
+                self.stack.push(value::Value::bool(true));
             }
             (bytecode::Op::False, _) => {
-                self.stack.push(value::Value::Bool(false));
-            }
-            (bytecode::Op::Negate, lineno) => {
-                let top_stack = self.peek(); // 看一眼栈顶
-                let maybe_number = Interpreter::extract_number(top_stack);
-
-                match maybe_number {
-                    Some(to_negate) => {
-                        self.pop_stack();
-                        self.stack.push(value::Value::Number(-to_negate));
-                    }
-                    None => {
-                        // 如果不是数字，但是前面有一个 negate 那么就有问题
-                        return Err(
-                            InterpreterError::Runtime(
-                                format!(
-                                    "invalid operand to unary op negate. Expected number, found {:?} at line {}",
-                                    value::type_of(top_stack),
-                                    lineno.value
-                                )
-                            )
-                        );
-                    }
-                }
-            }
-            (bytecode::Op::Add, lineno) => {
-                let val1 = self.peek_by(0).clone();
-                let val2 = self.peek_by(1).clone();
-
-                match (&val1, &val2) {
-                    (value::Value::Number(_), value::Value::Number(_)) => {
-                        self.numeric_binop(Binop::Add, lineno)?;
-                    }
-                    (value::Value::String(s1), value::Value::String(s2)) => {
-                        // 弹出 val1 和 val2
-                        self.pop_stack();
-                        self.pop_stack();
-                        self.stack.push(
-                            // 拼接字符串
-                            value::Value::String(
-                                self.heap.manage_str(
-                                    // 注意一下这里的顺序，比较有意思
-                                    format!("{}{}", self.get_str(*s2), self.get_str(*s1))
-                                )
-                            )
-                        );
-                    }
-                    (value::Value::List(id1), value::Value::List(id2)) => {
-                        self.pop_stack();
-                        self.pop_stack();
-                        let mut res = self.get_list_elements(*id2).clone();
-                        res.extend(self.get_list_elements(*id1).clone());
-                        self.stack.push(value::Value::List(self.heap.manage_list(res)));
-                    }
-                    _ => {
-                        return Err(
-                            InterpreterError::Runtime(
-                                format!(
-                                    "invalid operands of type {:?} and {:?} in add expression: \
-                                 both operands must be number or string (line={})",
-                                    value::type_of(&val1),
-                                    value::type_of(&val2),
-                                    lineno.value
-                                )
-                            )
-                        );
-                    }
-                }
-            }
-            (bytecode::Op::Subtract, lineno) =>
-                match self.numeric_binop(Binop::Sub, lineno) {
-                    Ok(()) => {}
-                    Err(err) => {
-                        return Err(err);
-                    }
-                }
-            (bytecode::Op::Multiply, lineno) =>
-                match self.numeric_binop(Binop::Mul, lineno) {
-                    Ok(()) => {}
-                    Err(err) => {
-                        return Err(err);
-                    }
-                }
-            (bytecode::Op::Divide, lineno) =>
-                match self.numeric_binop(Binop::Div, lineno) {
-                    Ok(()) => {}
-                    Err(err) => {
-                        return Err(err);
-                    }
-                }
-            (bytecode::Op::Not, lineno) => {
-                let top_stack = self.peek();
-                let maybe_bool = Interpreter::extract_bool(top_stack); // 看一下栈顶是不是 bool 类型
-
-                match maybe_bool {
-                    Some(b) => {
-                        self.pop_stack();
-                        self.stack.push(value::Value::Bool(!b));
-                    }
-                    None => {
-                        return Err(
-                            InterpreterError::Runtime(
-                                format!(
-                                    "invalid operand in not expression. Expected boolean, found {:?} at line {}",
-                                    value::type_of(top_stack),
-                                    lineno.value
-                                )
-                            )
-                        );
-                    }
-                }
-            }
-            (bytecode::Op::Equal, _) => {
-                let val1 = self.pop_stack();
-                let val2 = self.pop_stack();
-                self.stack.push(value::Value::Bool(self.values_equal(&val1, &val2)));
+                self.stack.push(value::Value::bool(false));
             }
-            (bytecode::Op::Greater, lineno) => {
-                let val1 = self.peek_by(0).clone();
-                let val2 = self.peek_by(1).clone();
-
-                match (&val1, &val2) {
-                    (value::Value::Number(n1), value::Value::Number(n2)) => {
-                        self.pop_stack();
-                        self.pop_stack();
-
-                        self.stack.push(value::Value::Bool(n2 > n1));
-                    }
-                    _ => {
-                        return Err(
-                            InterpreterError::Runtime(
-                                format!(
-                                    "invalid operands in Greater expression. Expected numbers, found {:?} and {:?} at line {}",
-                                    value::type_of(&val1),
-                                    value::type_of(&val2),
-                                    lineno.value
-                                )
-                            )
-                        );
-                    }
-                }
+            (op @ (bytecode::Op::Negate | bytecode::Op::Not), lineno) => {
+                let val = self.pop_stack();
+                let res = self.unary_op(&op, val, lineno)?;
+                self.stack.push(res);
             }
-            (bytecode::Op::Less, lineno) => {
-                let val1 = self.peek_by(0).clone();
-                let val2 = self.peek_by(1).clone();
-
-                match (&val1, &val2) {
-                    (value::Value::Number(n1), value::Value::Number(n2)) => {
-                        self.pop_stack();
-                        self.pop_stack();
-                        self.stack.push(value::Value::Bool(n2 < n1));
-                    }
-                    _ => {
-                        return Err(
-                            InterpreterError::Runtime(
-                                format!(
-                                    "invalid operands in Less expression. Expected numbers, found {:?} and {:?} at line {}",
-                                    value::type_of(&val1),
-                                    value::type_of(&val2),
-                                    lineno.value
-                                )
-                            )
-                        );
-                    }
-                }
+            (
+                op @ (
+                    bytecode::Op::Add
+                    | bytecode::Op::Subtract
+                    | bytecode::Op::Multiply
+                    | bytecode::Op::Divide
+                    | bytecode::Op::Modulo
+                    | bytecode::Op::Power
+                    | bytecode::Op::IntDivide
+                    | bytecode::Op::BitAnd
+                    | bytecode::Op::BitOr
+                    | bytecode::Op::BitXor
+                    | bytecode::Op::ShiftLeft
+                    | bytecode::Op::ShiftRight
+                    | bytecode::Op::Equal
+                    | bytecode::Op::Greater
+                    | bytecode::Op::Less
+                ),
+                lineno,
+            ) => {
+                let right = self.pop_stack();
+                let left = self.pop_stack();
+                let res = self.binary_op(&op, left, right, lineno)?;
+                self.stack.push(res);
             }
             (bytecode::Op::Print, _) => {
-                let to_print = self.peek().clone();
-                self.print_val(&to_print);
+                let to_print = *self.peek();
+                self.print_val(&to_print)?;
             }
             (bytecode::Op::Pop, _) => {
                 self.pop_stack();
             }
-            // 定义一个全局变量
-            (bytecode::Op::DefineGlobal(idx), _) => {
-                // 读取 idx 处的常量值，并检查这个是不是字符串，如果是字符串，那么这个字符串就是 IDENTIFIER
-                if let value::Value::String(name_id) = self.read_constant(idx) {
-                    let val = self.pop_stack();
-                    // 读取出栈顶的元素作为 val，并放到 hash 表中
-                    self.globals.insert(self.get_str(name_id).clone(), val);
-                } else {
-                    panic!(
-                        "expected string when defining global, found {:?}",
-                        value::type_of(&self.read_constant(idx))
-                    );
-                }
+            (bytecode::Op::Dup(depth), _) => {
+                let val = *self.peek_by(depth);
+                self.stack.push(val);
+            }
+            (bytecode::Op::Rot(depth), _) => {
+                let val = self.pop_stack();
+                let idx = self.stack.len() - depth;
+                self.stack.insert(idx, val);
             }
+            // 定义一个全局变量
+            (bytecode::Op::DefineGlobal(idx), lineno) => self.define_global(idx, false, lineno)?,
+            (bytecode::Op::DefineConstGlobal(idx), lineno) => self.define_global(idx, true, lineno)?,
             // 目的是 从全局作用域中，得到一个全局变量的值
             (bytecode::Op::GetGlobal(idx), lineno) => {
-                if let value::Value::String(name_id) = self.read_constant(idx) {
-                    match self.globals.get(self.get_str(name_id)) {
-                        Some(val) => {
-                            self.stack.push(val.clone());
-                        }
-                        None => {
-                            return Err(
-                                InterpreterError::Runtime(
-                                    format!(
-                                        "Undefined variable '{}' at line {}.",
-                                        self.get_str(name_id),
-                                        lineno.value
-                                    )
-                                )
-                            );
-                        }
-                    }
-                } else {
-                    panic!(
-                        "expected string when defining global, found {:?}",
-                        value::type_of(&self.read_constant(idx))
-                    );
-                }
+                let val = self.get_global(idx, lineno)?;
+                self.stack.push(val);
             }
             // 设置全局变量的值
             (bytecode::Op::SetGlobal(idx), lineno) => {
-                if let value::Value::String(name_id) = self.read_constant(idx) {
-                    let name_str = self.get_str(name_id).clone();
-                    let val = self.peek().clone();
-                    if
-                        // hash.entry 返回一个 enum { Occupid | vacant（空的） }
-                        let std::collections::hash_map::Entry::Occupied(mut e) = self.globals.entry(
-                            name_str.clone() // 标识符复制一份
-                        )
-                    {
-                        e.insert(val); // 得到 entry，插入 val
-                    } else {
-                        return Err(
-                            // 否则 如果是 vacant ，那么就是有错误产生
-                            InterpreterError::Runtime(
-                                format!(
-                                    "Use of undefined variable {} in setitem expression at line {}.",
-                                    name_str,
-                                    lineno.value
-                                )
-                            )
-                        );
-                    }
-                } else {
-                    panic!(
-                        "expected string when setting global, found {:?}",
-                        value::type_of(&self.read_constant(idx))
-                    );
-                }
+                let val = *self.peek();
+                self.set_global(idx, val, lineno)?;
             }
 
             /* ---------- get 是放到 stack 顶 ---------- */
@@ -661,38 +662,24 @@ impl Interpreter {
              */
             (bytecode::Op::GetLocal(idx), _) => {
                 let slots_offset = self.frame().slots_offset;
-                let val = self.stack[slots_offset + idx - 1].clone();
+                let val = self.stack[slots_offset + idx - 1];
                 self.stack.push(val);
             }
             (bytecode::Op::SetLocal(idx), _) => {
                 let val = self.peek();
                 let slots_offset = self.frame().slots_offset;
-                self.stack[slots_offset + idx - 1] = val.clone();
+                self.stack[slots_offset + idx - 1] = *val;
             }
             /*
              * 将上值 放到 栈顶
              */
             (bytecode::Op::GetUpval(idx), _) => {
-                // 获取栈顶的 frame，得到 frame 的 closure，并获得 上值
-                let upvalue = self.frame().closure.upvalues[idx].clone();
-                let val = match &*upvalue.borrow() {
-                    value::Upvalue::Closed(value) => value.clone(),
-                    value::Upvalue::Open(stack_index) => self.stack[*stack_index].clone(),
-                };
+                let val = self.get_upval(idx);
                 self.stack.push(val);
             }
             (bytecode::Op::SetUpval(idx), _) => {
-                let new_value = self.peek().clone();
-                let upvalue = self.frame().closure.upvalues[idx].clone();
-                match &mut *upvalue.borrow_mut() {
-                    value::Upvalue::Closed(value) => {
-                        *value = new_value;
-                    }
-                    // outer 函数还没有返回
-                    value::Upvalue::Open(stack_index) => {
-                        self.stack[*stack_index] = new_value;
-                    }
-                };
+                let new_value = *self.peek();
+                self.set_upval(idx, new_value);
             }
             (bytecode::Op::JumpIfFalse(offset), _) => {
                 if self.is_falsey(self.peek()) {
@@ -705,8 +692,90 @@ impl Interpreter {
             (bytecode::Op::Loop(offset), _) => {
                 self.frame_mut().ip -= offset; // 跳回到 loop 的开头
             }
+            (bytecode::Op::GetIter, lineno) => {
+                match self.peek().unbox(&self.heap) {
+                    value::Unboxed::List(_) | value::Unboxed::Generator(_) | value::Unboxed::Channel(_) => {}
+                    value::Unboxed::Instance(instance_id) => {
+                        let has_iter = {
+                            let instance = self.get_instance(instance_id);
+                            instance.fields.contains_key("iter") ||
+                                self.get_class(instance.class_id).methods.contains_key("iter")
+                        };
+                        // 没有 iter 方法的实例自己就是迭代器
+                        if has_iter {
+                            self.invoke("iter", 0, &[], None)?;
+                        }
+                    }
+                    _ => {
+                        return Err(
+                            InterpreterError::Runtime(
+                                format!(
+                                    "Cannot iterate over value of type {:?} at line {}",
+                                    value::type_of(self.peek()),
+                                    lineno.value
+                                )
+                            )
+                        );
+                    }
+                }
+            }
+            (bytecode::Op::ForIter(offset), _) => {
+                match self.peek_by(1).unbox(&self.heap) {
+                    value::Unboxed::List(list_id) => {
+                        let idx = match self.peek().unbox(&self.heap) {
+                            value::Unboxed::Integer(n) => n as usize,
+                            _ => panic!("expected the for-in index on top of the stack"),
+                        };
+                        match self.get_list_elements(list_id).get(idx).cloned() {
+                            Some(elt) => {
+                                // 下标原地加一，然后把元素压栈，跳过后面的 JumpIfNil
+                                let stack_len = self.stack.len();
+                                self.stack[stack_len - 1] = self.heap.integer((idx + 1) as i64);
+                                self.stack.push(elt);
+                                self.frame_mut().ip += 1;
+                            }
+                            None => {
+                                self.frame_mut().ip += offset;
+                            }
+                        }
+                    }
+                    _ => {
+                        // 返回值留在栈顶，交给后面的 JumpIfNil
+                        self.stack.push(*self.peek_by(1));
+                        self.invoke("next", 0, &[], None)?;
+                    }
+                }
+            }
+            (bytecode::Op::JumpIfNil(offset), _) => {
+                // 栈上是迭代器、下标和 next() 的返回值
+                if self.iteration_done(*self.peek_by(2), *self.peek()) {
+                    self.pop_stack();
+                    self.frame_mut().ip += offset;
+                }
+            }
             (bytecode::Op::Call(arg_count), _) => {
-                self.call_value(self.peek_by(arg_count.into()).clone(), arg_count)?;
+                self.call_value(*self.peek_by(arg_count.into()), arg_count)?;
+            }
+            (bytecode::Op::CallNamed(arg_count, names), _) => {
+                let callee = *self.peek_by(arg_count.into());
+                self.call_value_named(callee, arg_count, &names)?;
+            }
+            (bytecode::Op::TailCall(arg_count, names), _) => {
+                self.tail_call(arg_count, &names)?;
+            }
+            (bytecode::Op::Spawn(arg_count, names), _) => {
+                self.spawn(fiber::Start { method: None, arg_count, names });
+            }
+            (bytecode::Op::SpawnInvoke(method_name, arg_count, names), _) => {
+                self.spawn(fiber::Start { method: Some(method_name), arg_count, names });
+            }
+            (bytecode::Op::YieldFiber, _) => {
+                self.request_switch(fiber::Switch::Yield).map_err(InterpreterError::Runtime)?;
+            }
+            (bytecode::Op::Await, _) => {
+                let val = self.pop_stack();
+                let result = self.await_value(val)?;
+                self.stack.push(result);
             }
             // 关闭 上值（outer 返回）
             (bytecode::Op::CloseUpvalue, _) => {
@@ -715,14 +784,19 @@ impl Interpreter {
                 self.stack.pop();
             }
             (bytecode::Op::Class(idx), _) => {
-                if let value::Value::String(name_id) = self.read_constant(idx) {
+                if let value::Unboxed::String(name_id) = self.read_constant(idx).unbox(&self.heap) {
                     // 获取到 类名
                     let name = self.get_str(name_id).clone();
                     self.stack.push(
-                        value::Value::Class(
+                        value::Value::class(
                             self.heap.manage_class(value::Class {
                                 name,
+                                superclass: None,
                                 methods: HashMap::new(), // 没有继承，那么 方法就是空白的
+                                class_methods: HashMap::new(),
+                                getters: HashMap::new(),
+                                setters: HashMap::new(),
+                                traits: Vec::new(),
                             })
                         )
                     );
@@ -733,99 +807,169 @@ impl Interpreter {
                     );
                 }
             }
-            (bytecode::Op::SetProperty(idx), _) => {
-                if let value::Value::String(attr_id) = self.read_constant(idx) {
-                    let val = self.pop_stack();
-                    let instance = self.pop_stack();
+            (bytecode::Op::SetProperty(idx, cache), _) => {
+                let val = self.pop_stack();
+                let instance = self.pop_stack();
+                match self.find_setter(&instance, idx, cache) {
+                    // setter 的返回值不要，赋值表达式的值还是右边的值
+                    Some(setter_id) => {
+                        let args = std::slice::from_ref(&val);
+                        self.call_method_and_wait(&instance, setter_id, args).map_err(
+                            InterpreterError::Runtime
+                        )?;
+                    }
                     // 给对象设置 属性
-                    self.setattr(instance, val.clone(), attr_id)?;
-                    self.stack.push(val);
-                } else {
-                    panic!(
-                        "expected string when setting property, found {:?}",
-                        value::type_of(&self.read_constant(idx))
-                    );
+                    None => self.setattr(instance, val, idx)?,
                 }
+                self.stack.push(val);
             }
-            (bytecode::Op::GetProperty(idx), _) => {
-                if let value::Value::String(attr_id) = self.read_constant(idx) {
-                    let maybe_instance = self.peek().clone();
-
-                    let (class_id, instance_id) = match maybe_instance {
-                        value::Value::Instance(instance_id) => {
-                            let instance = self.heap.get_instance(instance_id).clone();
-                            (instance.class_id, instance_id)
-                        }
-                        _ => panic!(),
-                    };
-
-                    let class = self.heap.get_class(class_id).clone();
-                    // 能到这里 maybe_intance 一定是有一个 instance 的
-                    if let Some(attr) = self.getattr(maybe_instance.clone(), attr_id)? {
+            (bytecode::Op::GetProperty(idx, cache), _) => {
+                let maybe_instance = *self.peek();
+
+                let instance_id = match maybe_instance.unbox(&self.heap) {
+                    value::Unboxed::Instance(instance_id) => instance_id,
+                    value::Unboxed::Generator(generator_id) => {
+                        let method = Interpreter::generator_method(
+                            generator_id,
+                            self.constant_str(idx)
+                        )?;
                         self.pop_stack();
-                        self.stack.push(attr);
-
-                        // 如果绑定失败
-                    } else if !self.bind_method(instance_id, class, attr_id)? {
+                        self.stack.push(method);
+                        return Ok(());
+                    }
+                    value::Unboxed::Channel(channel_id) => {
+                        let method = Interpreter::channel_method(channel_id, self.constant_str(idx))?;
+                        self.pop_stack();
+                        self.stack.push(method);
+                        return Ok(());
+                    }
+                    value::Unboxed::Isolate(isolate_id) => {
+                        let method = Interpreter::isolate_method(isolate_id, self.constant_str(idx))?;
+                        self.pop_stack();
+                        self.stack.push(method);
+                        return Ok(());
+                    }
+                    value::Unboxed::Enum(enum_id) => {
+                        let attr = self.constant_str(idx).to_string();
+                        let member = self.enum_member(enum_id, &attr)?;
+                        self.pop_stack();
+                        self.stack.push(member);
+                        return Ok(());
+                    }
+                    value::Unboxed::Variant(variant_id) => {
+                        let field = self.variant_field(variant_id, self.constant_str(idx))?;
+                        self.pop_stack();
+                        self.stack.push(field);
+                        return Ok(());
+                    }
+                    // 类上面只能取到静态方法，this 绑定到类本身
+                    value::Unboxed::Class(class_id) => {
+                        let attr = self.constant_str(idx);
+                        let closure_id = match self.get_class(class_id).class_methods.get(attr) {
+                            Some(closure_id) => *closure_id,
+                            None => {
+                                return Err(
+                                    InterpreterError::Runtime(
+                                        format!(
+                                            "value {} has no attribute {}.",
+                                            self.format_val(&maybe_instance),
+                                            attr
+                                        )
+                                    )
+                                );
+                            }
+                        };
+                        let bound_method = self.heap.manage_bound_method(value::BoundMethod {
+                            receiver: maybe_instance,
+                            closure_id,
+                        });
+                        self.pop_stack();
+                        self.stack.push(value::Value::bound_method(bound_method));
+                        return Ok(());
+                    }
+                    _ => {
                         return Err(
                             InterpreterError::Runtime(
                                 format!(
-                                    "value {} has no attribute {}.",
-                                    self.format_val(&maybe_instance),
-                                    self.get_str(attr_id)
+                                    "can't get attribute {}  on value of type {:?}. Need class instance.",
+                                    self.constant_str(idx),
+                                    value::type_of(&maybe_instance)
                                 )
                             )
                         );
                     }
-                } else {
-                    panic!(
-                        "expected string when setting property, found {:?}",
-                        value::type_of(&self.read_constant(idx))
-                    );
+                };
+
+                // 字段是每个实例自己的，先看字段，不进缓存
+                let instance = self.get_instance(instance_id);
+                if let Some(field) = instance.fields.get(self.constant_str(idx)).copied() {
+                    self.pop_stack();
+                    self.stack.push(field);
+                    return Ok(());
                 }
-            }
-            // 这段代码是在创建一个成员方法
-            (bytecode::Op::Method(idx), _) => {
-                if let value::Value::String(method_name_id) = self.read_constant(idx) {
-                    let method_name = self.heap.get_str(method_name_id).clone();
-                    let maybe_method = self.peek_by(0).clone();
-                    let maybe_method_id = gc::Heap::extract_id(&maybe_method).unwrap();
-                    let maybe_class = self.peek_by(1).clone();
-                    match maybe_class {
-                        value::Value::Class(class_id) => {
-                            let class = self.heap.get_class_mut(class_id);
-                            class.methods.insert(method_name, maybe_method_id);
-                            self.pop_stack();
-                        }
-                        _ => {
-                            panic!(
-                                "should only define methods on a class! tried on {:?}",
-                                self.format_val(&maybe_class)
-                            );
-                        }
+
+                match self.find_method(instance.class_id, self.constant_str(idx), Some(cache)) {
+                    // 栈顶的实例正好是 getter 的 0 号槽位，返回值会替换掉它
+                    bytecode::InlineCache::Getter { closure_id, .. } => {
+                        self.prepare_call(closure_id, 0, &[])?;
+                    }
+                    bytecode::InlineCache::Method { closure_id, .. } => {
+                        let bound_method = self.heap.manage_bound_method(value::BoundMethod {
+                            receiver: maybe_instance,
+                            closure_id,
+                        });
+                        self.pop_stack();
+                        self.stack.push(value::Value::bound_method(bound_method));
+                    }
+                    _ => {
+                        return Err(
+                            InterpreterError::Runtime(
+                                format!(
+                                    "value {} has no attribute {}.",
+                                    self.format_val(&maybe_instance),
+                                    self.constant_str(idx)
+                                )
+                            )
+                        );
                     }
-                } else {
-                    panic!("expected string when defining a method.");
                 }
             }
+            // 这段代码是在创建一个成员方法
+            (bytecode::Op::Method(idx), _) => self.define_method(idx, |class| &mut class.methods),
+            (bytecode::Op::ClassMethod(idx), _) => {
+                self.define_method(idx, |class| &mut class.class_methods)
+            }
+            (bytecode::Op::Getter(idx), _) => self.define_method(idx, |class| &mut class.getters),
+            (bytecode::Op::Setter(idx), _) => self.define_method(idx, |class| &mut class.setters),
+            (bytecode::Op::InstanceOf, _) => {
+                let class = self.pop_stack();
+                let val = self.pop_stack();
+                let res = self.is_instance(&val, &class).map_err(InterpreterError::Runtime)?;
+                self.stack.push(value::Value::bool(res));
+            }
             // invoke 调用成员函数：方法名 + 参数个数
-            (bytecode::Op::Invoke(method_name, arg_count), _) => {
-                self.invoke(&method_name, arg_count)?;
+            (bytecode::Op::Invoke(method_name, arg_count, cache), _) => {
+                self.invoke(&method_name, arg_count, &[], Some(cache))?;
+            }
+            (bytecode::Op::InvokeNamed(method_name, arg_count, names, cache), _) => {
+                self.invoke(&method_name, arg_count, &names, Some(cache))?;
             }
             // 继承
             (bytecode::Op::Inherit, lineno) => {
                 {
-                    let (superclass_id, subclass_id) = match (self.peek_by(1), self.peek()) {
+                    let (superclass_id, subclass_id) = match
+                        (self.peek_by(1).unbox(&self.heap), self.peek().unbox(&self.heap))
+                    {
                         // subclass 在栈顶，superclass 是栈顶第二个元素
-                        (value::Value::Class(superclass_id), value::Value::Class(subclass_id)) => {
-                            (*superclass_id, *subclass_id)
+                        (value::Unboxed::Class(superclass_id), value::Unboxed::Class(subclass_id)) => {
+                            (superclass_id, subclass_id)
                         }
-                        (not_a_class, value::Value::Class(_)) => {
+                        (_, value::Unboxed::Class(_)) => {
                             return Err(
                                 InterpreterError::Runtime(
                                     format!(
                                         "Superclass must be a class, found {:?} at lineno={:?}",
-                                        value::type_of(not_a_class),
+                                        value::type_of(self.peek_by(1)),
                                         lineno
                                     )
                                 )
@@ -834,15 +978,99 @@ impl Interpreter {
                         _ => panic!("expected classes when interpreting Inherit!"),
                     };
 
-                    let superclass_methods = self.get_class(superclass_id).methods.clone();
+                    let superclass = self.get_class(superclass_id).clone();
                     let subclass = self.get_class_mut(subclass_id); // 通过 id 获取到 子类的 可变借用
 
-                    subclass.methods.extend(superclass_methods);
+                    subclass.superclass = Some(superclass_id);
+                    subclass.methods.extend(superclass.methods);
+                    subclass.class_methods.extend(superclass.class_methods);
+                    subclass.getters.extend(superclass.getters);
+                    subclass.setters.extend(superclass.setters);
                 }
                 self.pop_stack(); //subclass
             }
+            (bytecode::Op::Enum(idx), _) => {
+                if let value::Unboxed::String(name_id) = self.read_constant(idx).unbox(&self.heap) {
+                    let name = self.get_str(name_id).clone();
+                    let enum_id = self.heap.manage_enum(value::Enum {
+                        name,
+                        variants: Vec::new(),
+                    });
+                    self.stack.push(value::Value::enum_(enum_id));
+                } else {
+                    panic!(
+                        "expected string when defining enum, found {:?}",
+                        value::type_of(&self.read_constant(idx))
+                    );
+                }
+            }
+            (bytecode::Op::EnumVariant(idx, fields), _) => {
+                if let value::Unboxed::String(name_id) = self.read_constant(idx).unbox(&self.heap) {
+                    let name = self.get_str(name_id).clone();
+                    match self.peek().unbox(&self.heap) {
+                        value::Unboxed::Enum(enum_id) => {
+                            self.heap
+                                .get_enum_mut(enum_id)
+                                .variants.push(value::EnumVariant { name, fields });
+                        }
+                        _ => panic!("should only define variants on an enum!"),
+                    }
+                } else {
+                    panic!("expected string when defining a variant.");
+                }
+            }
+            (bytecode::Op::Trait(idx), _) => {
+                if let value::Unboxed::String(name_id) = self.read_constant(idx).unbox(&self.heap) {
+                    let name = self.get_str(name_id).clone();
+                    let trait_id = self.heap.manage_trait(value::Trait {
+                        name,
+                        methods: HashMap::new(),
+                        required: Vec::new(),
+                    });
+                    self.stack.push(value::Value::trait_(trait_id));
+                } else {
+                    panic!(
+                        "expected string when defining trait, found {:?}",
+                        value::type_of(&self.read_constant(idx))
+                    );
+                }
+            }
+            (bytecode::Op::TraitMethod(idx), _) => {
+                if let value::Unboxed::String(method_name_id) = self.read_constant(idx).unbox(&self.heap) {
+                    let method_name = self.get_str(method_name_id).clone();
+                    let method_id = match self.pop_stack().unbox(&self.heap) {
+                        value::Unboxed::Function(closure_id) => closure_id,
+                        _ => panic!("expected a closure when defining a trait method."),
+                    };
+                    match self.peek().unbox(&self.heap) {
+                        value::Unboxed::Trait(trait_id) => {
+                            self.heap.get_trait_mut(trait_id).methods.insert(method_name, method_id);
+                        }
+                        _ => panic!("should only define trait methods on a trait!"),
+                    }
+                } else {
+                    panic!("expected string when defining a trait method.");
+                }
+            }
+            (bytecode::Op::RequireMethod(idx), _) => {
+                if let value::Unboxed::String(method_name_id) = self.read_constant(idx).unbox(&self.heap) {
+                    let method_name = self.get_str(method_name_id).clone();
+                    match self.peek().unbox(&self.heap) {
+                        value::Unboxed::Trait(trait_id) => {
+                            self.heap.get_trait_mut(trait_id).required.push(method_name);
+                        }
+                        _ => panic!("should only require methods on a trait!"),
+                    }
+                } else {
+                    panic!("expected string when requiring a trait method.");
+                }
+            }
+            (bytecode::Op::Mixin(count), _) => {
+                self.mix_in_traits(usize::from(count)).map_err(InterpreterError::Runtime)?;
+                self.pop_stack_n_times(usize::from(count));
+            }
             (bytecode::Op::GetSuper(idx), _) => {
-                let method_id = if let value::Value::String(method_id) = self.read_constant(idx) {
+                let method_id = if let value::Unboxed::String(method_id) = self.read_constant(idx).unbox(&self.heap) {
                     method_id
                 } else {
                     panic!();
@@ -850,18 +1078,22 @@ impl Interpreter {
 
                 // 得到父类的 id
                 let maybe_superclass = self.pop_stack();
-                let superclass = match maybe_superclass {
-                    value::Value::Class(class_id) => self.get_class(class_id).clone(),
+                let superclass = match maybe_superclass.unbox(&self.heap) {
+                    value::Unboxed::Class(class_id) => self.get_class(class_id).clone(),
                     _ => panic!(),
                 };
 
                 // 如果 instance 的 class 对应，那么就可以了
-                let maybe_instance = self.peek();
-                let instance_id = match maybe_instance {
-                    value::Value::Instance(instance_id) => *instance_id,
+                let instance_id = match self.peek().unbox(&self.heap) {
+                    value::Unboxed::Instance(instance_id) => instance_id,
                     _ => panic!(),
                 };
 
+                // super.name 是 getter 的话直接调用它，栈顶的 this 就是 0 号槽位
+                if let Some(closure_id) = superclass.getters.get(self.get_str(method_id)).copied() {
+                    return self.prepare_call(closure_id, 0, &[]);
+                }
+
                 // 如果绑定失败
                 if !self.bind_method(instance_id, superclass, method_id)? {
                     return Err(
@@ -879,11 +1111,19 @@ impl Interpreter {
             // 这个有点动态多态的意思
             (bytecode::Op::SuperInvoke(method_name, arg_count), _) => {
                 let maybe_superclass = self.pop_stack();
-                let superclass_id = match maybe_superclass {
-                    value::Value::Class(class_id) => class_id,
+                let superclass_id = match maybe_superclass.unbox(&self.heap) {
+                    value::Unboxed::Class(class_id) => class_id,
                     _ => panic!("{}", self.format_val(&maybe_superclass)),
                 };
-                self.invoke_from_class(superclass_id, &method_name, arg_count)?;
+                self.invoke_from_class(superclass_id, &method_name, arg_count, &[])?;
+            }
+            (bytecode::Op::SuperInvokeNamed(method_name, arg_count, names), _) => {
+                let maybe_superclass = self.pop_stack();
+                let superclass_id = match maybe_superclass.unbox(&self.heap) {
+                    value::Unboxed::Class(class_id) => class_id,
+                    _ => panic!("{}", self.format_val(&maybe_superclass)),
+                };
+                self.invoke_from_class(superclass_id, &method_name, arg_count, &names)?;
             }
 
             // 创建 list
@@ -893,7 +1133,29 @@ impl Interpreter {
                     list_elements.push(self.pop_stack());
                 }
                 list_elements.reverse();
-                self.stack.push(value::Value::List(self.heap.manage_list(list_elements)));
+                self.stack.push(value::Value::list(self.heap.manage_list(list_elements)));
+            }
+            (bytecode::Op::MatchList(size), _) => {
+                let res = match self.pop_stack().unbox(&self.heap) {
+                    value::Unboxed::List(list_id) => self.get_list_elements(list_id).len() == size,
+                    _ => false,
+                };
+                self.stack.push(value::Value::bool(res));
+            }
+            (bytecode::Op::HasProperty(idx), _) => {
+                let res = match self.pop_stack().unbox(&self.heap) {
+                    value::Unboxed::Instance(instance_id) => {
+                        let instance = self.get_instance(instance_id);
+                        let name = self.constant_str(idx);
+                        instance.fields.contains_key(name) ||
+                            !matches!(
+                                self.find_method(instance.class_id, name, None),
+                                bytecode::InlineCache::Empty
+                            )
+                    }
+                    _ => false,
+                };
+                self.stack.push(value::Value::bool(res));
             }
 
             // 访问下标
@@ -903,11 +1165,35 @@ impl Interpreter {
                 let res = self.subscript(value_to_subscript, subscript, lineno)?;
                 self.stack.push(res);
             }
+            (bytecode::Op::Interpolate(num_parts), _) => {
+                // 字符串的 format_val 就是它本身；__str__ 可能会跑 lox 代码，各部分先留在栈上
+                let mut res = String::new();
+                for idx in self.stack.len() - num_parts..self.stack.len() {
+                    let part = self.stack[idx];
+                    res.push_str(&self.stringify(&part)?);
+                }
+                self.pop_stack_n_times(num_parts);
+                self.stack.push(value::Value::string(self.heap.manage_str(res)));
+            }
+            (bytecode::Op::Slice, lineno) => {
+                let stop = self.pop_stack();
+                let start = self.pop_stack();
+                let value_to_slice = self.pop_stack();
+                let res = self.slice(value_to_slice, start, stop, lineno)?;
+                self.stack.push(res);
+            }
             (bytecode::Op::SetItem, lineno) => {
                 let rhs = self.pop_stack(); // 右操作数
                 let subscript = self.pop_stack();
                 let lhs = self.pop_stack();
-                self.setitem(lhs, subscript, rhs.clone(), lineno)?;
+                match self.special_method(&lhs, "__setitem__") {
+                    Some(method_id) => {
+                        self.call_method_and_wait(&lhs, method_id, &[subscript, rhs]).map_err(
+                            InterpreterError::Runtime
+                        )?;
+                    }
+                    None => self.setitem(lhs, subscript, rhs, lineno)?,
+                }
                 self.stack.push(rhs);
             }
         }
@@ -915,15 +1201,30 @@ impl Interpreter {
     }
 
     /**
-     * 获取数字
+     * 获取数字，整数也当成浮点数取出来
      */
-    fn extract_number(val: &value::Value) -> Option<f64> {
-        match val {
-            value::Value::Number(f) => Some(*f),
+    pub fn extract_number(&self, val: &value::Value) -> Option<f64> {
+        match val.unbox(&self.heap) {
+            value::Unboxed::Number(f) => Some(f),
+            value::Unboxed::Integer(n) => Some(n as f64),
             _ => None,
         }
     }
 
+    /**
+     * 比较两个数字：两个整数直接比，有浮点数就都转成浮点数再比
+     * 外层的 None 表示不是数字，内层的 None 表示有 NaN
+     */
+    pub fn compare_numbers(&self, val1: &value::Value, val2: &value::Value) -> Option<Option<Ordering>> {
+        match (val1.unbox(&self.heap), val2.unbox(&self.heap)) {
+            (value::Unboxed::Integer(n1), value::Unboxed::Integer(n2)) => Some(Some(n1.cmp(&n2))),
+            _ => {
+                let (n1, n2) = (self.extract_number(val1)?, self.extract_number(val2)?);
+                Some(n1.partial_cmp(&n2))
+            }
+        }
+    }
+
     /**
      * 看一眼栈顶
      */
@@ -941,11 +1242,11 @@ impl Interpreter {
     /**
      * 关闭上值
      */
-    fn close_upvalues(&mut self, index: usize) {
+    pub fn close_upvalues(&mut self, index: usize) {
         let value = &self.stack[index];
         for upval in &self.upvalues {
             if upval.borrow().is_open_with_index(index) {
-                upval.replace(value::Upvalue::Closed(value.clone()));
+                upval.replace(value::Upvalue::Closed(*value));
             }
         }
 
@@ -966,13 +1267,14 @@ impl Interpreter {
      * 这里 manage_xxx 实际上是分配内存，返回 id
      * idx --> constant --> value::Value
      */
-    fn read_constant(&mut self, idx: usize) -> value::Value {
+    pub fn read_constant(&mut self, idx: usize) -> value::Value {
         let constant = self.frame().read_constant(idx);
         match constant {
-            bytecode::Constant::Number(num) => value::Value::Number(num),
-            bytecode::Constant::String(s) => value::Value::String(self.heap.manage_str(s)),
+            bytecode::Constant::Number(num) => value::Value::number(num),
+            bytecode::Constant::Integer(num) => self.heap.integer(num),
+            bytecode::Constant::String(s) => value::Value::string(self.heap.manage_str(s)),
             bytecode::Constant::Function(f) => {
-                value::Value::Function(
+                value::Value::function(
                     self.heap.manage_closure(value::Closure {
                         function: f.function,
                         upvalues: Vec::new(), // 初始的时候没有上值
@@ -1003,14 +1305,25 @@ impl Interpreter {
     pub fn format_backtrace(&self) -> String {
         let lines: Vec<_> = self.frames
             .iter()
-            .map(|frame| {
+            .flat_map(|frame| {
                 let frame_name = &frame.closure.function.name;
-                let (_, lineno) = frame.closure.function.chunk.code[frame.ip];
-                if frame_name.is_empty() {
+                let lineno = if self.registers {
+                    // 寄存器指令里一个语句可能一条指令都没有，用正在执行的那条
+                    let code = frame.closure.function.registers.get().unwrap();
+                    code.code[frame.ip.saturating_sub(1)].1
+                } else {
+                    frame.closure.function.chunk.code[frame.ip].1
+                };
+                let line = if frame_name.is_empty() {
                     format!("[line {}] in script", lineno.value)
                 } else {
                     format!("[line {}] in {}()", lineno.value, frame_name)
-                }
+                };
+                // 尾调用复用掉的帧已经没有了，只能说一下有几个
+                let elided = (frame.elided_frames > 0).then(|| {
+                    format!("... {} frames elided by tail calls ...", frame.elided_frames)
+                });
+                elided.into_iter().chain(std::iter::once(line))
             })
             .collect();
         format!("Backtrace (most recent call last):\n\n{}", lines.join("\n"))
@@ -1024,31 +1337,88 @@ impl Interpreter {
     }
 
     pub fn format_val(&self, val: &value::Value) -> String {
-        match val {
-            value::Value::Number(num) => num.to_string(),
-            value::Value::Bool(b) => b.to_string(),
-            value::Value::String(str_handle) => self.get_str(*str_handle).clone(),
-            value::Value::Function(closure_handle) => {
-                format!("<fn '{}'>", self.get_closure(*closure_handle).function.name)
+        match val.unbox(&self.heap) {
+            value::Unboxed::Number(num) => num.to_string(),
+            value::Unboxed::Integer(num) => num.to_string(),
+            value::Unboxed::Bool(b) => b.to_string(),
+            value::Unboxed::String(str_handle) => self.get_str(str_handle).clone(),
+            value::Unboxed::Function(closure_handle) => {
+                format!("<fn '{}'>", self.get_closure(closure_handle).function.name)
+            }
+            value::Unboxed::Class(class_handle) => {
+                format!("<class '{}'>", self.get_class(class_handle).name)
             }
-            value::Value::Class(class_handle) => {
-                format!("<class '{}'>", self.get_class(*class_handle).name)
+            value::Unboxed::Trait(trait_handle) => {
+                format!("<trait '{}'>", self.heap.get_trait(trait_handle).name)
             }
-            value::Value::Instance(instance_handle) => {
-                let instance = self.get_instance(*instance_handle);
+            value::Unboxed::Instance(instance_handle) => {
+                let instance = self.get_instance(instance_handle);
                 let class_name = &self.get_class(instance.class_id).name;
                 format!("<{} instance>", class_name)
             }
-            value::Value::NativeFunction(func) => format!("<native fn {}>", func.name),
-            value::Value::BoundMethod(bound_method_id) => {
-                let bound_method = self.get_bound_method(*bound_method_id);
-                let instance = self.get_instance(bound_method.instance_id);
-                let class_name = &self.get_class(instance.class_id).name;
-                format!("<bound method of {} instance>", class_name)
+            value::Unboxed::NativeFunction(id) => {
+                format!("<native fn {}>", self.heap.get_native_function(id).name)
+            }
+            value::Unboxed::BoundMethod(bound_method_id) => {
+                match self.get_bound_method(bound_method_id).receiver.unbox(&self.heap) {
+                    value::Unboxed::Class(class_id) => {
+                        format!("<bound method of class {}>", self.get_class(class_id).name)
+                    }
+                    value::Unboxed::Instance(instance_id) => {
+                        let instance = self.get_instance(instance_id);
+                        let class_name = &self.get_class(instance.class_id).name;
+                        format!("<bound method of {} instance>", class_name)
+                    }
+                    _ => panic!("methods can only be bound to instances and classes"),
+                }
+            }
+            value::Unboxed::Generator(generator_id) => {
+                let generator = self.heap.get_generator(generator_id);
+                format!("<generator '{}'>", generator.closure.function.name)
+            }
+            value::Unboxed::GeneratorMethod(_, method) => {
+                let name = match method {
+                    value::GeneratorMethod::Next => "next",
+                    value::GeneratorMethod::Iter => "iter",
+                };
+                format!("<generator method {}>", name)
+            }
+            value::Unboxed::Channel(_) => "<channel>".to_string(),
+            value::Unboxed::Isolate(isolate_id) => {
+                format!("<isolate {}>", self.heap.get_isolate(isolate_id).name)
+            }
+            value::Unboxed::IsolateMethod(_, method) => {
+                format!("<isolate method {}>", format!("{:?}", method).to_lowercase())
             }
-            value::Value::Nil => "nil".to_string(),
-            value::Value::List(list_id) => {
-                let elements = self.get_list_elements(*list_id);
+            value::Unboxed::Promise(promise_id) => {
+                match self.heap.get_promise(promise_id).value {
+                    Some(_) => "<promise resolved>".to_string(),
+                    None => "<promise pending>".to_string(),
+                }
+            }
+            value::Unboxed::ChannelMethod(_, method) => {
+                let name = match method {
+                    value::ChannelMethod::Send => "send",
+                    value::ChannelMethod::Recv => "recv",
+                    value::ChannelMethod::Close => "close",
+                };
+                format!("<channel method {}>", name)
+            }
+            value::Unboxed::Enum(enum_id) => format!("<enum '{}'>", self.heap.get_enum(enum_id).name),
+            value::Unboxed::VariantConstructor(enum_id, tag) => {
+                format!("<variant {}>", self.variant_name(enum_id, tag))
+            }
+            value::Unboxed::Variant(variant_id) => {
+                let variant = self.heap.get_variant(variant_id);
+                let fields: Vec<String> = variant.fields
+                    .iter()
+                    .map(|field| self.format_val(field))
+                    .collect();
+                self.format_variant(variant.enum_id, variant.tag, &fields)
+            }
+            value::Unboxed::Nil => "nil".to_string(),
+            value::Unboxed::List(list_id) => {
+                let elements = self.get_list_elements(list_id);
                 format!(
                     "[{}]",
                     elements
@@ -1061,6 +1431,14 @@ impl Interpreter {
         }
     }
 
+    /**
+     * 和构造的时候一样的写法：Shape.Circle(1)，没有字段的就是 Shape.Empty
+     */
+    fn format_variant(&self, enum_id: gc::HeapId, tag: usize, fields: &[String]) -> String {
+        let name = self.variant_name(enum_id, tag);
+        if fields.is_empty() { name } else { format!("{}({})", name, fields.join(", ")) }
+    }
+
     /*
      * lhs[subscript] = rhs
      */
@@ -1071,8 +1449,11 @@ impl Interpreter {
         rhs: value::Value,
         lineno: bytecode::Lineno
     ) -> Result<(), InterpreterError> {
-        if let value::Value::List(id) = lhs {
-            if let value::Value::Number(index_float) = subscript {
+        if let value::Unboxed::List(id) = lhs.unbox(&self.heap) {
+            if self.heap.is_frozen(id) {
+                return Err(InterpreterError::Runtime("Can't modify a frozen list.".to_string()));
+            }
+            if let Some(index_float) = self.extract_number(&subscript) {
                 let elements = self.get_list_elements_mut(id);
                 match Interpreter::subscript_to_inbound_index(elements.len(), index_float, lineno) {
                     Ok(index_int) => {
@@ -1113,12 +1494,11 @@ impl Interpreter {
         subscript: value::Value,
         lineno: bytecode::Lineno
     ) -> Result<value::Value, InterpreterError> {
-        if let value::Value::List(id) = value {
-            if let value::Value::Number(index_float) = subscript {
-                // 因为 lox 中的数字只有 float，就连 下标（字面量）也是 float
+        if let value::Unboxed::List(id) = value.unbox(&self.heap) {
+            if let Some(index_float) = self.extract_number(&subscript) {
                 let elements = self.get_list_elements(id);
                 match Interpreter::subscript_to_inbound_index(elements.len(), index_float, lineno) {
-                    Ok(index_int) => Ok(elements[index_int].clone()),
+                    Ok(index_int) => Ok(elements[index_int]),
                     Err(err) => Err(InterpreterError::Runtime(err)),
                 }
             } else {
@@ -1143,6 +1523,80 @@ impl Interpreter {
         }
     }
 
+    /*
+     * 切片 value[start:stop]，和 python 一样，越界的时候截断到列表的范围
+     */
+    fn slice(
+        &mut self,
+        value: value::Value,
+        start: value::Value,
+        stop: value::Value,
+        lineno: bytecode::Lineno
+    ) -> Result<value::Value, InterpreterError> {
+        if let value::Unboxed::List(id) = value.unbox(&self.heap) {
+            let elements = self.get_list_elements(id);
+            let len = elements.len();
+            let start = self.slice_bound(len, &start, 0, lineno)?;
+            let stop = self.slice_bound(len, &stop, len, lineno)?;
+            let res = if start < stop { elements[start..stop].to_vec() } else { Vec::new() };
+            Ok(value::Value::list(self.heap.manage_list(res)))
+        } else {
+            Err(
+                InterpreterError::Runtime(
+                    format!(
+                        "Invalid value of type {:?} in slice expression",
+                        value::type_of(&value)
+                    )
+                )
+            )
+        }
+    }
+
+    /*
+     * 切片的一端：nil 表示省略，负数从右往左数
+     */
+    fn slice_bound(
+        &self,
+        list_len: usize,
+        bound: &value::Value,
+        default: usize,
+        lineno: bytecode::Lineno
+    ) -> Result<usize, InterpreterError> {
+        match bound.unbox(&self.heap) {
+            value::Unboxed::Nil => Ok(default),
+            value::Unboxed::Number(_) | value::Unboxed::Integer(_) => {
+                let len = list_len as i64;
+                let index_int = match bound.unbox(&self.heap) {
+                    value::Unboxed::Integer(n) => n,
+                    value::Unboxed::Number(n) if n.fract() == 0.0 => n as i64,
+                    _ => {
+                        return Err(
+                            InterpreterError::Runtime(
+                                format!(
+                                    "Invalid slice bound {} in slice expression at line {}",
+                                    self.extract_number(bound).unwrap(),
+                                    lineno.value
+                                )
+                            )
+                        );
+                    }
+                };
+                let index_int = if index_int < 0 { index_int + len } else { index_int };
+                Ok(index_int.clamp(0, len) as usize)
+            }
+            _ =>
+                Err(
+                    InterpreterError::Runtime(
+                        format!(
+                            "Invalid slice bound of type {:?} in slice expression at line {}",
+                            value::type_of(bound),
+                            lineno.value
+                        )
+                    )
+                ),
+        }
+    }
+
     /*
      * 接受一个 下标（可以是整数，也可以是负数），转化为 真正的下表
      */
@@ -1151,6 +1605,10 @@ impl Interpreter {
         index_float: f64,
         lineno: bytecode::Lineno
     ) -> Result<usize, String> {
+        // 带小数的下标不会悄悄截断
+        if index_float.fract() != 0.0 {
+            return Err(format!("Invalid subscript {} in subscript expression", index_float));
+        }
         let index_int = index_float as i64; // 先转化为 i64
         if 0 <= index_int && index_int < (list_len as i64) {
             return Ok(index_int as usize);
@@ -1162,28 +1620,171 @@ impl Interpreter {
     }
 
     /*
-     * 调用成员函数
+     * 调用成员函数，cache 是这条 Invoke 指令的内联缓存槽
      */
-    fn invoke(&mut self, method_name: &str, arg_count: u8) -> Result<(), InterpreterError> {
+    pub fn invoke(
+        &mut self,
+        method_name: &str,
+        arg_count: u8,
+        names: &[String],
+        cache: Option<usize>
+    ) -> Result<(), InterpreterError> {
         // 看前几个元素
-        let receiver_id = match self.peek_by(arg_count.into()) {
-            value::Value::Instance(id) => *id, // 得到实例的 id
+        let receiver_id = match self.peek_by(arg_count.into()).unbox(&self.heap) {
+            value::Unboxed::Instance(id) => id, // 得到实例的 id
+            value::Unboxed::Generator(id) => {
+                let method = Interpreter::generator_method(id, method_name)?;
+                return self.call_value_named(method, arg_count, names);
+            }
+            value::Unboxed::Channel(id) => {
+                let method = Interpreter::channel_method(id, method_name)?;
+                return self.call_value_named(method, arg_count, names);
+            }
+            value::Unboxed::Isolate(id) => {
+                let method = Interpreter::isolate_method(id, method_name)?;
+                return self.call_value_named(method, arg_count, names);
+            }
+            // 静态方法：0 号槽位上的类就是 this
+            value::Unboxed::Class(class_id) => {
+                return match self.get_class(class_id).class_methods.get(method_name) {
+                    Some(closure_id) => self.prepare_call(*closure_id, arg_count, names),
+                    None =>
+                        Err(
+                            InterpreterError::Runtime(
+                                format!("Undefined class method {}.", method_name)
+                            )
+                        ),
+                };
+            }
+            // Shape.Circle(1)：先取出变体的构造函数（或者变体上的字段）再调用
+            value::Unboxed::Enum(_) | value::Unboxed::Variant(_) => {
+                let callee = match self.peek_by(arg_count.into()).unbox(&self.heap) {
+                    value::Unboxed::Enum(enum_id) => self.enum_member(enum_id, method_name)?,
+                    value::Unboxed::Variant(variant_id) => self.variant_field(variant_id, method_name)?,
+                    _ => unreachable!(),
+                };
+                let slot_zero = self.stack.len() - usize::from(arg_count) - 1;
+                self.stack[slot_zero] = callee;
+                return self.call_value_named(callee, arg_count, names);
+            }
             _ => {
                 return Err(InterpreterError::Runtime("Only instances have methods.".to_string()));
             }
         };
 
-        if
-            let Some(field) = self
-                .get_instance(receiver_id)
-                .fields.get(&String::from(method_name)) // 得到的是 value::Value::<可调用对象>
-                .cloned()
-        {
-            return self.call_value(field, arg_count);
+        let receiver = self.get_instance(receiver_id);
+        if let Some(field) = receiver.fields.get(method_name).copied() {
+            return self.call_value_named(field, arg_count, names);
+        }
+
+        match self.find_method(receiver.class_id, method_name, cache) {
+            bytecode::InlineCache::Method { closure_id, .. } => {
+                self.call_value_named(value::Value::function(closure_id), arg_count, names)
+            }
+            // getter 返回的值再拿来调用
+            bytecode::InlineCache::Getter { closure_id, .. } => {
+                let receiver = value::Value::instance(receiver_id);
+                let callee = self
+                    .call_method_and_wait(&receiver, closure_id, &[])
+                    .map_err(InterpreterError::Runtime)?;
+                let slot_zero = self.stack.len() - usize::from(arg_count) - 1;
+                self.stack[slot_zero] = callee;
+                self.call_value_named(callee, arg_count, names)
+            }
+            _ => Err(InterpreterError::Runtime(format!("Undefined property {}.", method_name))),
+        }
+    }
+
+    /*
+     * 在类上找 getter 或者方法（getter 优先），找到了记进内联缓存，下次类和 epoch 都对上了直接用
+     */
+    fn find_method(
+        &self,
+        class_id: gc::HeapId,
+        name: &str,
+        cache: Option<usize>
+    ) -> bytecode::InlineCache {
+        let epoch = self.heap.epoch();
+        if let Some(slot) = cache {
+            match self.inline_cache(slot) {
+                | hit @ bytecode::InlineCache::Method { class_id: cached, epoch: cached_epoch, .. }
+                | hit @ bytecode::InlineCache::Getter { class_id: cached, epoch: cached_epoch, .. }
+                    if cached == class_id && cached_epoch == epoch => {
+                    return hit;
+                }
+                _ => {}
+            }
+        }
+
+        let class = self.get_class(class_id);
+        let found = if let Some(closure_id) = class.getters.get(name) {
+            bytecode::InlineCache::Getter { class_id, epoch, closure_id: *closure_id }
+        } else if let Some(closure_id) = class.methods.get(name) {
+            bytecode::InlineCache::Method { class_id, epoch, closure_id: *closure_id }
+        } else {
+            return bytecode::InlineCache::Empty;
+        };
+        if let Some(slot) = cache {
+            self.set_inline_cache(slot, found);
+        }
+        found
+    }
+
+    /*
+     * obj.attr = val 的时候有没有 setter；没有的话也记进缓存，下次直接写字段
+     */
+    fn find_setter(
+        &self,
+        receiver: &value::Value,
+        name_idx: usize,
+        cache: usize
+    ) -> Option<gc::HeapId> {
+        let class_id = match receiver.unbox(&self.heap) {
+            value::Unboxed::Instance(instance_id) => self.get_instance(instance_id).class_id,
+            _ => {
+                return None;
+            }
+        };
+        let epoch = self.heap.epoch();
+        match self.inline_cache(cache) {
+            bytecode::InlineCache::Setter { class_id: cached, epoch: cached_epoch, closure_id }
+                if cached == class_id && cached_epoch == epoch => {
+                return Some(closure_id);
+            }
+            bytecode::InlineCache::Field { class_id: cached, epoch: cached_epoch }
+                if cached == class_id && cached_epoch == epoch => {
+                return None;
+            }
+            _ => {}
         }
 
-        let class_id = self.get_instance(receiver_id).class_id;
-        self.invoke_from_class(class_id, method_name, arg_count)
+        let setter = self.get_class(class_id).setters.get(self.constant_str(name_idx)).copied();
+        self.set_inline_cache(cache, match setter {
+            Some(closure_id) => bytecode::InlineCache::Setter { class_id, epoch, closure_id },
+            None => bytecode::InlineCache::Field { class_id, epoch },
+        });
+        setter
+    }
+
+    /*
+     * 当前调用帧的 chunk 上的内联缓存，同一个函数的所有闭包共用
+     */
+    fn inline_cache(&self, slot: usize) -> bytecode::InlineCache {
+        self.frame().closure.function.chunk.inline_caches.borrow()[slot]
+    }
+
+    fn set_inline_cache(&self, slot: usize, cache: bytecode::InlineCache) {
+        self.frame().closure.function.chunk.inline_caches.borrow_mut()[slot] = cache;
+    }
+
+    /*
+     * 属性名就在常量池里，直接借用，不用每次都在堆上分配一个字符串
+     */
+    fn constant_str(&self, idx: usize) -> &str {
+        match &self.frame().closure.function.chunk.constants[idx] {
+            bytecode::Constant::String(s) => s,
+            constant => panic!("expected string constant for property name, found {}", constant),
+        }
     }
 
     /*
@@ -1193,7 +1794,8 @@ impl Interpreter {
         &mut self,
         class_id: gc::HeapId,
         method_name: &str,
-        arg_count: u8
+        arg_count: u8,
+        names: &[String]
     ) -> Result<(), InterpreterError> {
         let method_id = match self.get_class(class_id).methods.get(&String::from(method_name)) {
             Some(method_id) => *method_id,
@@ -1204,10 +1806,10 @@ impl Interpreter {
             }
         };
 
-        self.call_value(value::Value::Function(method_id), arg_count)
+        self.call_value_named(value::Value::function(method_id), arg_count, names)
     }
 
-    fn frame_mut(&mut self) -> &mut CallFrame {
+    pub fn frame_mut(&mut self) -> &mut CallFrame {
         let frames_len = self.frames.len();
         &mut self.frames[frames_len - 1]
     }
@@ -1234,18 +1836,30 @@ impl Interpreter {
         val_to_call: value::Value,
         arg_count: u8 // 参数的个数
     ) -> Result<(), InterpreterError> {
-        match val_to_call {
-            value::Value::Function(func) => {
-                self.prepare_call(func, arg_count)?;
+        self.call_value_named(val_to_call, arg_count, &[])
+    }
+
+    /*
+     * 调用，最后 names.len() 个实参是命名参数
+     */
+    pub fn call_value_named(
+        &mut self,
+        val_to_call: value::Value,
+        arg_count: u8,
+        names: &[String]
+    ) -> Result<(), InterpreterError> {
+        match val_to_call.unbox(&self.heap) {
+            value::Unboxed::Function(func) => {
+                self.prepare_call(func, arg_count, names)?;
                 Ok(())
             }
             // 也就只有 内置的函数 会真正的调用
-            value::Value::NativeFunction(native_func) => {
-                self.call_native_func(native_func, arg_count)?;
+            value::Unboxed::NativeFunction(native_id) => {
+                self.call_native_func(native_id, arg_count, names)?;
                 Ok(())
             }
-            value::Value::Class(class_id) => {
-                let new_instance = value::Value::Instance(
+            value::Unboxed::Class(class_id) => {
+                let new_instance = value::Value::instance(
                     // 创建了一个空白对象
                     self.heap.manage_instance(value::Instance {
                         class_id,
@@ -1260,11 +1874,11 @@ impl Interpreter {
                 {
                     let maybe_method_id = self
                         .get_class(class_id)
-                        .methods.get(&"init".to_string())
+                        .methods.get("init")
                         .copied(); // 得到构造函数
 
                     if let Some(method_id) = maybe_method_id {
-                        return self.prepare_call(method_id, arg_count);
+                        return self.prepare_call(method_id, arg_count, names);
                     }
                 }
 
@@ -1280,10 +1894,56 @@ impl Interpreter {
                 Ok(())
             }
             // BoundMethod 是一个包含 this 对象的方法
-            value::Value::BoundMethod(method_id) => {
-                self.call_bound_method(method_id, arg_count)?;
+            value::Unboxed::BoundMethod(method_id) => {
+                self.call_bound_method(method_id, arg_count, names)?;
                 Ok(())
             }
+            // 定义了 __call__ 的实例，0 号槽位正好就是 this
+            value::Unboxed::Instance(_) if self.special_method(&val_to_call, "__call__").is_some() => {
+                let method_id = self.special_method(&val_to_call, "__call__").unwrap();
+                self.prepare_call(method_id, arg_count, names)
+            }
+            value::Unboxed::VariantConstructor(enum_id, tag) => {
+                self.construct_variant(enum_id, tag, arg_count, names)
+            }
+            value::Unboxed::GeneratorMethod(generator_id, method) => {
+                if arg_count != 0 {
+                    return Err(
+                        InterpreterError::Runtime(
+                            format!("Expected 0 arguments but found {}.", arg_count)
+                        )
+                    );
+                }
+                match method {
+                    value::GeneratorMethod::Next => self.resume_generator(generator_id),
+                    value::GeneratorMethod::Iter => {
+                        // 生成器的迭代器就是它自己
+                        self.pop_stack();
+                        self.stack.push(value::Value::generator(generator_id));
+                        Ok(())
+                    }
+                }
+            }
+            value::Unboxed::ChannelMethod(channel_id, method) => {
+                if !names.is_empty() {
+                    return Err(
+                        InterpreterError::Runtime(
+                            "Channel methods do not take named arguments.".to_string()
+                        )
+                    );
+                }
+                self.call_channel_method(channel_id, method, arg_count)
+            }
+            value::Unboxed::IsolateMethod(isolate_id, method) => {
+                if !names.is_empty() {
+                    return Err(
+                        InterpreterError::Runtime(
+                            "Isolate methods do not take named arguments.".to_string()
+                        )
+                    );
+                }
+                self.call_isolate_method(isolate_id, method, arg_count)
+            }
             _ =>
                 Err(
                     InterpreterError::Runtime(
@@ -1297,98 +1957,1021 @@ impl Interpreter {
     }
 
     /*
-     * 真正的调用了
+     * 变体的构造函数：字段名就是参数名，所以也可以用命名参数
      */
-    fn call_native_func(
+    fn construct_variant(
         &mut self,
-        native_func: value::NativeFunction,
-        arg_count: u8
+        enum_id: gc::HeapId,
+        tag: usize,
+        arg_count: u8,
+        names: &[String]
     ) -> Result<(), InterpreterError> {
-        // 如果参数不相等
-        if arg_count != native_func.arity {
-            return Err(
-                InterpreterError::Runtime(
-                    format!(
-                        "Native function {} expected {} arguments but found {}.",
-                        native_func.name,
-                        native_func.arity,
-                        arg_count
-                    )
-                )
-            );
+        let fields = self.heap.get_enum(enum_id).variants[tag].fields.clone();
+        let signature = bytecode::Function {
+            name: self.variant_name(enum_id, tag),
+            arity: fields.len() as u8,
+            param_names: fields,
+            ..Default::default()
+        };
+        let num_slots = self.bind_args(&signature, arg_count, names)?;
+        let fields = self.stack.split_off(self.stack.len() - num_slots);
+        self.pop_stack(); // 构造函数本身
+        let variant_id = self.heap.manage_variant(value::Variant { enum_id, tag, fields });
+        self.stack.push(value::Value::variant(variant_id));
+        Ok(())
+    }
+
+    /**
+     * Shape.Circle：没有字段的变体直接就是值，有字段的得到它的构造函数
+     */
+    fn enum_member(
+        &mut self,
+        enum_id: gc::HeapId,
+        name: &str
+    ) -> Result<value::Value, InterpreterError> {
+        let e = self.heap.get_enum(enum_id);
+        match e.variants.iter().position(|variant| variant.name == name) {
+            Some(tag) if e.variants[tag].fields.is_empty() => {
+                let variant_id = self.heap.manage_variant(value::Variant {
+                    enum_id,
+                    tag,
+                    fields: Vec::new(),
+                });
+                Ok(value::Value::variant(variant_id))
+            }
+            Some(tag) => Ok(value::Value::variant_constructor(enum_id, tag)),
+            None =>
+                Err(
+                    InterpreterError::Runtime(format!("Enum {} has no variant {}.", e.name, name))
+                ),
+        }
+    }
+
+    fn variant_field(
+        &self,
+        variant_id: gc::HeapId,
+        name: &str
+    ) -> Result<value::Value, InterpreterError> {
+        let variant = self.heap.get_variant(variant_id);
+        let fields = &self.heap.get_enum(variant.enum_id).variants[variant.tag].fields;
+        match fields.iter().position(|field| field == name) {
+            Some(idx) => Ok(variant.fields[idx]),
+            None =>
+                Err(
+                    InterpreterError::Runtime(
+                        format!(
+                            "Variant {} has no field {}.",
+                            self.variant_name(variant.enum_id, variant.tag),
+                            name
+                        )
+                    )
+                ),
+        }
+    }
+
+    /**
+     * 带上枚举名的全名，比如 Shape.Circle
+     */
+    fn variant_name(&self, enum_id: gc::HeapId, tag: usize) -> String {
+        let e = self.heap.get_enum(enum_id);
+        format!("{}.{}", e.name, e.variants[tag].name)
+    }
+
+    /*
+     * 真正的调用了
+     */
+    fn call_native_func(
+        &mut self,
+        native_id: gc::HeapId,
+        arg_count: u8,
+        names: &[String]
+    ) -> Result<(), InterpreterError> {
+        let native_func = self.heap.get_native_function(native_id);
+        let (arity, optional_args, func) = (native_func.arity, native_func.optional_args, native_func.func);
+
+        // 内置函数没有参数名
+        if !names.is_empty() {
+            return Err(
+                InterpreterError::Runtime(
+                    format!("Native function {} does not take named arguments.", native_func.name)
+                )
+            );
+        }
+
+        // 如果参数个数不在范围内
+        let max_arity = arity + optional_args;
+        if arg_count < arity || arg_count > max_arity {
+            return Err(
+                InterpreterError::Runtime(
+                    format!(
+                        "Native function {} expected {} arguments but found {}.",
+                        native_func.name,
+                        format_arity(arity, max_arity),
+                        arg_count
+                    )
+                )
+            );
+        }
+
+        // 参数先留在栈上，内置函数里面再调用 lox 代码的时候它们也不会被回收
+        let arg_count_usize = usize::from(arg_count);
+        let args = self.stack[self.stack.len() - arg_count_usize..].to_vec();
+
+        // 调用函数
+        let res = func(self, &args);
+        self.pop_stack_n_times(arg_count_usize + 1); // args + native function value
+
+        match res {
+            Ok(result) => {
+                self.stack.push(result);
+                Ok(())
+            }
+            Err(err) =>
+                Err(
+                    InterpreterError::Runtime(
+                        format!(
+                            "When calling {}: {}.",
+                            self.heap.get_native_function(native_id).name,
+                            err
+                        )
+                    )
+                ),
+        }
+    }
+
+    /*
+     * 很奇怪，没有真正的调用
+     */
+    fn call_bound_method(
+        &mut self,
+        method_id: gc::HeapId,
+        arg_count: u8,
+        names: &[String]
+    ) -> Result<(), InterpreterError> {
+        let bound_method = self.get_bound_method(method_id).clone();
+        let closure_id = bound_method.closure_id;
+        let arg_count_usize: usize = arg_count.into(); // 很有意思，有 usize::from 也有 <usize>.into
+        let stack_len = self.stack.len();
+        // 将对象压入 stack 中
+        self.stack[stack_len - arg_count_usize - 1] = bound_method.receiver;
+        self.prepare_call(closure_id, arg_count, names)
+    }
+
+    /*
+     * Set up a few call frame so that on the next interpreter step we'll start executing code inside the function.
+     * 就是在调用函数之前，要准备他的 frame，以及准备他 栈上的数据
+     */
+    fn prepare_call(
+        &mut self,
+        closure_handle: gc::HeapId,
+        arg_count: u8,
+        names: &[String]
+    ) -> Result<(), InterpreterError> {
+        #[cfg(feature = "jit")]
+        if names.is_empty() && self.try_jit_call(closure_handle, arg_count) {
+            return Ok(());
+        }
+
+        let closure = self.get_closure(closure_handle).clone();
+        let num_slots = self.bind_args(&closure.function, arg_count, names)?;
+
+        // 生成器函数先不执行：把 0 号槽位和参数收起来，返回一个生成器
+        if closure.function.is_generator {
+            let slot_zero = self.stack.len() - num_slots - 1;
+            let slots = self.stack.split_off(slot_zero);
+            let generator_id = self.heap.manage_generator(value::Generator {
+                closure,
+                ip: 0,
+                slots,
+                upvalues: Vec::new(),
+                state: value::GeneratorState::Suspended,
+            });
+            self.stack.push(value::Value::generator(generator_id));
+            return Ok(());
+        }
+
+        // async 函数：0 号槽位和参数搬到一个新的 fiber 上，轮到它的时候从头开始跑，这里先返回一个 promise
+        if closure.function.is_async {
+            let slot_zero = self.stack.len() - num_slots - 1;
+            let slots = self.stack.split_off(slot_zero);
+            let promise_id = self.heap.manage_promise(Default::default());
+            let frame = CallFrame {
+                closure,
+                slots_offset: 1,
+                promise: Some(promise_id),
+                ..Default::default()
+            };
+            self.add_fiber(slots, vec![frame], None);
+            self.stack.push(value::Value::promise(promise_id));
+            return Ok(());
+        }
+
+        self.frames.push(CallFrame::default()); // 默认构造一个 frame
+        let frame = self.frames.last_mut().unwrap();
+        frame.closure = closure;
+        frame.slots_offset = self.stack.len() - num_slots; // 给 frame 设置 stack[len - num_slots, len] 的位置
+        Ok(())
+    }
+
+    /*
+     * 被调用的函数有本地代码，参数也都是整数的话直接跑本地代码，把结果放到栈上。
+     * 返回 false 表示这次调用还是交给解释器。
+     */
+    #[cfg(feature = "jit")]
+    fn try_jit_call(&mut self, closure_handle: gc::HeapId, arg_count: u8) -> bool {
+        let function = &self.heap.get_closure(closure_handle).function;
+        // 参数个数不对的错误让解释器去报
+        if function.arity != arg_count {
+            return false;
+        }
+        let Some(compiled) = self.jit.on_call(function) else {
+            return false;
+        };
+
+        let arg_count = usize::from(arg_count);
+        let mut args = [0i64; jit::MAX_ARITY];
+        for (arg, val) in args.iter_mut().zip(&self.stack[self.stack.len() - arg_count..]) {
+            match val.unbox(&self.heap) {
+                value::Unboxed::Integer(n) => {
+                    *arg = n;
+                }
+                _ => {
+                    return false;
+                }
+            }
+        }
+
+        // 本地代码里直接调用自己，全局变量要还是这个函数。
+        // 比较的是函数共享的 jit 状态，不是 HeapId，堆上的 id 复用了也不会认错
+        if compiled.recursive {
+            let same_function = match self.globals.get(&function.name).map(|f| f.unbox(&self.heap)) {
+                Some(value::Unboxed::Function(id)) =>
+                    Rc::ptr_eq(&self.heap.get_closure(id).function.jit, &function.jit),
+                _ => false,
+            };
+            if !same_function {
+                return false;
+            }
+        }
+
+        match compiled.run(&args[..arg_count]) {
+            Some(raw) => {
+                let result = compiled.to_value(raw, &mut self.heap);
+                self.stack.truncate(self.stack.len() - arg_count - 1);
+                self.stack.push(result);
+                true
+            }
+            None => {
+                self.jit.bail(function);
+                false
+            }
+        }
+    }
+
+    /*
+     * return f(...)：当前函数的槽位不要了，被调用的闭包和参数挪到 0 号槽位上，复用当前的调用帧。
+     * 生成器里面的 return 还要把生成器标记成结束，内置函数、类之类的也没有帧可以复用，
+     * 这些都按普通的调用处理，后面的 Return 把结果带回去
+     */
+    fn tail_call(&mut self, arg_count: u8, names: &[String]) -> Result<(), InterpreterError> {
+        let callee = *self.peek_by(arg_count.into());
+        let closure_handle = match callee.unbox(&self.heap) {
+            value::Unboxed::Function(closure_handle) if
+                self.frame().generator.is_none() && self.frame().promise.is_none()
+            => {
+                closure_handle
+            }
+            _ => {
+                return self.call_value_named(callee, arg_count, names);
+            }
+        };
+        let closure = self.get_closure(closure_handle).clone();
+        if closure.function.is_generator || closure.function.is_async {
+            return self.prepare_call(closure_handle, arg_count, names);
+        }
+        let num_slots = self.bind_args(&closure.function, arg_count, names)?;
+
+        // 当前函数的局部变量马上就要被覆盖掉，指向它们的上值先关闭
+        let slot_zero = self.frame().slots_offset - 1;
+        let callee_slot = self.stack.len() - num_slots - 1;
+        for idx in slot_zero..callee_slot {
+            self.close_upvalues(idx);
+        }
+        self.stack.drain(slot_zero..callee_slot);
+
+        let frame = self.frame_mut();
+        frame.closure = closure;
+        frame.ip = 0;
+        frame.elided_frames += 1;
+        Ok(())
+    }
+
+    /*
+     * 把栈顶的实参按形参的顺序重新摆好，返回参数占了几个槽位。
+     * 有默认值的参数后面跟一个 bool，表示它没有传，函数开头的代码据此算默认值；
+     * 多出来的位置参数收进一个列表，放在 ...rest 的槽位上
+     */
+    fn bind_args(
+        &mut self,
+        func: &bytecode::Function,
+        arg_count: u8,
+        names: &[String]
+    ) -> Result<usize, InterpreterError> {
+        let arg_count = usize::from(arg_count);
+        // 最常见的情况，参数已经摆好了
+        if
+            names.is_empty() &&
+            func.optional_args == 0 &&
+            !func.has_rest &&
+            arg_count == usize::from(func.arity)
+        {
+            return Ok(arg_count);
+        }
+
+        let arity = usize::from(func.arity);
+        let max_arity = arity + usize::from(func.optional_args);
+        let named_args = self.stack.split_off(self.stack.len() - names.len());
+        let mut positional_args = self.stack.split_off(self.stack.len() - (arg_count - names.len()));
+        if positional_args.len() > max_arity && !func.has_rest {
+            return Err(
+                InterpreterError::Runtime(
+                    format!(
+                        "Expected {} arguments but found {}.",
+                        format_arity(func.arity, max_arity as u8),
+                        positional_args.len()
+                    )
+                )
+            );
+        }
+
+        let rest = if positional_args.len() > max_arity {
+            positional_args.split_off(max_arity)
+        } else {
+            Vec::new()
+        };
+        let mut bound: Vec<Option<value::Value>> = positional_args.into_iter().map(Some).collect();
+        bound.resize(max_arity, None);
+        for (name, arg) in names.iter().zip(named_args) {
+            match func.param_names.iter().position(|param| param == name) {
+                Some(idx) if bound[idx].is_some() => {
+                    return Err(
+                        InterpreterError::Runtime(
+                            format!(
+                                "Got multiple values for argument '{}' in call to {}.",
+                                name,
+                                func.name
+                            )
+                        )
+                    );
+                }
+                Some(idx) => {
+                    bound[idx] = Some(arg);
+                }
+                None => {
+                    return Err(
+                        InterpreterError::Runtime(
+                            format!("{} has no parameter named '{}'.", func.name, name)
+                        )
+                    );
+                }
+            }
+        }
+
+        let missing: Vec<&str> = func.param_names
+            .iter()
+            .zip(bound.iter())
+            .take(arity)
+            .filter(|(_, arg)| arg.is_none())
+            .map(|(name, _)| name.as_str())
+            .collect();
+        if !missing.is_empty() {
+            return Err(
+                InterpreterError::Runtime(
+                    format!("Missing arguments for {}: {}.", func.name, missing.join(", "))
+                )
+            );
+        }
+
+        for (idx, arg) in bound.into_iter().enumerate() {
+            let is_missing = arg.is_none();
+            self.stack.push(arg.unwrap_or(value::Value::NIL));
+            if idx >= arity {
+                self.stack.push(value::Value::bool(is_missing));
+            }
+        }
+        let mut num_slots = max_arity + usize::from(func.optional_args);
+        if func.has_rest {
+            let rest_id = self.heap.manage_list(rest);
+            self.stack.push(value::Value::list(rest_id));
+            num_slots += 1;
+        }
+        Ok(num_slots)
+    }
+
+    /**
+     * for-in 里 next() 返回以后是不是该结束了。生成器要跑完了才算，中间 yield 出来的 nil 也是元素；
+     * 通道要看 recv 是不是因为关闭才返回的；实例的 next() 返回 nil 就是结束
+     */
+    pub fn iteration_done(&self, iterator: value::Value, next: value::Value) -> bool {
+        match iterator.unbox(&self.heap) {
+            value::Unboxed::Generator(generator_id) => {
+                self.heap.get_generator(generator_id).state == value::GeneratorState::Done
+            }
+            value::Unboxed::Channel(_) => self.fibers.recv_closed,
+            _ => next.is_nil(),
+        }
+    }
+
+    /*
+     * next()：栈顶是被调用的 next 方法（或者 for-in 里的生成器本身），
+     * 把生成器的槽位放回栈上，从上次 yield 的地方接着跑
+     */
+    fn resume_generator(&mut self, generator_id: gc::HeapId) -> Result<(), InterpreterError> {
+        let generator = self.heap.get_generator_mut(generator_id);
+        match generator.state {
+            value::GeneratorState::Done => {
+                self.pop_stack();
+                self.stack.push(value::Value::NIL);
+                return Ok(());
+            }
+            value::GeneratorState::Running => {
+                return Err(
+                    InterpreterError::Runtime(
+                        format!(
+                            "generator {} is already running",
+                            generator.closure.function.name
+                        )
+                    )
+                );
+            }
+            value::GeneratorState::Suspended => {}
+        }
+
+        generator.state = value::GeneratorState::Running;
+        let closure = generator.closure.clone();
+        let ip = generator.ip;
+        let slots = std::mem::take(&mut generator.slots);
+        let upvalues = std::mem::take(&mut generator.upvalues);
+
+        self.pop_stack();
+        let slot_zero = self.stack.len();
+        self.stack.extend(slots);
+        for (upval, rel) in upvalues {
+            // 暂停期间闭包可能改过这个上值，先写回栈上
+            if let value::Upvalue::Closed(val) = &*upval.borrow() {
+                self.stack[slot_zero + rel] = *val;
+            }
+            upval.replace(value::Upvalue::Open(slot_zero + rel));
+            self.upvalues.push(upval);
+        }
+
+        self.frames.push(CallFrame {
+            closure,
+            ip,
+            slots_offset: slot_zero + 1,
+            generator: Some(generator_id),
+            promise: None,
+            elided_frames: 0,
+        });
+        Ok(())
+    }
+
+    fn generator_method(
+        generator_id: gc::HeapId,
+        name: &str
+    ) -> Result<value::Value, InterpreterError> {
+        match name {
+            "next" => Ok(value::Value::generator_method(generator_id, value::GeneratorMethod::Next)),
+            "iter" => Ok(value::Value::generator_method(generator_id, value::GeneratorMethod::Iter)),
+            _ => Err(InterpreterError::Runtime(format!("Undefined property {}.", name))),
+        }
+    }
+
+    /*
+     * 用 prelude 里的生成器函数创建一个生成器，不会执行任何代码
+     * 内置的 map / filter 对生成器和迭代器是惰性的，就靠这个
+     */
+    pub fn prelude_generator(
+        &mut self,
+        name: &str,
+        args: &[value::Value]
+    ) -> Result<value::Value, String> {
+        let closure = match self.prelude.get(name) {
+            Some(closure) => *closure,
+            None => panic!("prelude function {} is missing", name),
+        };
+        self.stack.push(closure);
+        self.stack.extend(args.iter().cloned());
+        if let Err(InterpreterError::Runtime(err)) = self.call_value(closure, args.len() as u8) {
+            return Err(err);
+        }
+        Ok(self.pop_stack())
+    }
+
+    /* ---------- ---------- 特殊方法 ---------- ---------- */
+
+    /**
+     * 实例的类上定义了 __add__ 这样的特殊方法，就返回方法的闭包（父类的方法在继承的时候已经拷贝过来了）
+     */
+    pub fn special_method(&self, val: &value::Value, name: &str) -> Option<gc::HeapId> {
+        self.class_member(val, name, |class| &class.methods)
+    }
+
+    /**
+     * 在实例的类上找方法、getter 或者 setter
+     */
+    fn class_member(
+        &self,
+        val: &value::Value,
+        name: &str,
+        members: fn(&value::Class) -> &HashMap<String, gc::HeapId>
+    ) -> Option<gc::HeapId> {
+        match val.unbox(&self.heap) {
+            value::Unboxed::Instance(instance_id) => {
+                let class_id = self.get_instance(instance_id).class_id;
+                members(self.get_class(class_id)).get(name).copied()
+            }
+            _ => None,
+        }
+    }
+
+    /**
+     * 栈顶的 count 个 trait 混入下面的类。类自己的方法优先，然后是 trait，最后才是父类；
+     * 两个 trait 提供了同名的方法、类自己又没有定义的时候报错
+     */
+    fn mix_in_traits(&mut self, count: usize) -> Result<(), String> {
+        let class_id = match self.peek_by(count).unbox(&self.heap) {
+            value::Unboxed::Class(class_id) => class_id,
+            _ => panic!("expected a class below the traits when interpreting Mixin!"),
+        };
+        let class = self.get_class(class_id).clone();
+        // 继承的时候父类的方法已经拷贝进来了，闭包和父类的一样就不是类自己定义的
+        let inherited = match class.superclass {
+            Some(superclass_id) => self.get_class(superclass_id).methods.clone(),
+            None => HashMap::new(),
+        };
+        let is_own = |name: &String, id: &gc::HeapId| inherited.get(name) != Some(id);
+
+        let mut methods = class.methods.clone();
+        let mut providers: HashMap<String, String> = HashMap::new(); // 方法名 -> 提供它的 trait
+        let mut required = Vec::new();
+        let mut trait_ids = Vec::new();
+        for depth in (0..count).rev() {
+            let trait_id = match self.peek_by(depth).unbox(&self.heap) {
+                value::Unboxed::Trait(trait_id) => trait_id,
+                _ => {
+                    return Err(
+                        format!(
+                            "Only traits can be mixed into a class. Found {:?}.",
+                            value::type_of(self.peek_by(depth))
+                        )
+                    );
+                }
+            };
+            trait_ids.push(trait_id);
+            let lox_trait = self.heap.get_trait(trait_id);
+
+            let mut trait_methods: Vec<(&String, &gc::HeapId)> = lox_trait.methods.iter().collect();
+            trait_methods.sort();
+            for (method_name, method_id) in trait_methods {
+                if class.methods.get(method_name).is_some_and(|id| is_own(method_name, id)) {
+                    continue;
+                }
+                if let Some(other) = providers.get(method_name) {
+                    return Err(
+                        format!(
+                            "Conflicting method '{}' from traits {} and {} in class {}.",
+                            method_name,
+                            other,
+                            lox_trait.name,
+                            class.name
+                        )
+                    );
+                }
+                providers.insert(method_name.clone(), lox_trait.name.clone());
+                methods.insert(method_name.clone(), *method_id);
+            }
+            for method_name in lox_trait.required.iter() {
+                required.push((method_name.clone(), lox_trait.name.clone()));
+            }
+        }
+
+        for (method_name, trait_name) in required {
+            if !methods.contains_key(&method_name) {
+                return Err(
+                    format!(
+                        "Class {} must provide method '{}' required by trait {}.",
+                        class.name,
+                        method_name,
+                        trait_name
+                    )
+                );
+            }
+        }
+
+        let class = self.get_class_mut(class_id);
+        class.methods = methods;
+        class.traits.extend(trait_ids);
+        Ok(())
+    }
+
+    /**
+     * val instanceof class：沿着 val 的类的超类链往上找，混入的 trait 也算
+     */
+    pub fn is_instance(&self, val: &value::Value, class: &value::Value) -> Result<bool, String> {
+        let target_id = match class.unbox(&self.heap) {
+            value::Unboxed::Class(class_id) | value::Unboxed::Trait(class_id) => class_id,
+            _ => {
+                return Err(
+                    format!(
+                        "Can't check instanceof against a value of type {:?}.",
+                        value::type_of(class)
+                    )
+                );
+            }
+        };
+        let mut maybe_class_id = match val.unbox(&self.heap) {
+            value::Unboxed::Instance(instance_id) => Some(self.get_instance(instance_id).class_id),
+            _ => None,
+        };
+        while let Some(class_id) = maybe_class_id {
+            let class = self.get_class(class_id);
+            if class_id == target_id || class.traits.contains(&target_id) {
+                return Ok(true);
+            }
+            maybe_class_id = class.superclass;
+        }
+        Ok(false)
+    }
+
+    /**
+     * 可以交给特殊方法的操作，以及接收者是不是栈顶（右操作数）。
+     * 比较都归结到 __lt__ 上：a > b 就是 b < a，<= 和 >= 在编译的时候已经变成了 > 和 < 再取反
+     */
+    pub fn operator_method(op: &bytecode::Op) -> Option<(&'static str, bool)> {
+        match op {
+            bytecode::Op::Add => Some(("__add__", false)),
+            bytecode::Op::Subtract => Some(("__sub__", false)),
+            bytecode::Op::Multiply => Some(("__mul__", false)),
+            bytecode::Op::Equal => Some(("__eq__", false)),
+            bytecode::Op::Less => Some(("__lt__", false)),
+            bytecode::Op::Greater => Some(("__lt__", true)),
+            bytecode::Op::Subscr => Some(("__getitem__", false)),
+            _ => None,
+        }
+    }
+
+    /**
+     * 栈上是 [lhs, rhs]，接收者定义了这个特殊方法的话，直接在原地调用，返回值会替换掉两个操作数。
+     * __eq__ 和 __lt__ 的结果后面可能还要 Not，必须是 bool，所以等它返回以后检查一下类型
+     */
+    fn call_operator_method(&mut self, name: &str, swapped: bool) -> Result<bool, InterpreterError> {
+        let receiver = if swapped { self.peek_by(0) } else { self.peek_by(1) };
+        let method_id = match self.special_method(receiver, name) {
+            Some(method_id) => method_id,
+            None => {
+                return Ok(false);
+            }
+        };
+        if swapped {
+            let stack_len = self.stack.len();
+            self.stack.swap(stack_len - 1, stack_len - 2);
+        }
+        if name == "__eq__" || name == "__lt__" {
+            // 操作数先留在栈上，调用的时候不会被回收
+            let (receiver, arg) = (*self.peek_by(1), *self.peek());
+            let res = self
+                .call_method_and_wait(&receiver, method_id, &[arg])
+                .map_err(InterpreterError::Runtime)?;
+            if value::type_of(&res) != value::Type::Bool {
+                return Err(
+                    InterpreterError::Runtime(
+                        format!("{} must return a bool. Found {:?}.", name, value::type_of(&res))
+                    )
+                );
+            }
+            self.pop_stack_n_times(2);
+            self.stack.push(res);
+            return Ok(true);
+        }
+        self.prepare_call(method_id, 1, &[])?;
+        Ok(true)
+    }
+
+    /**
+     * 在 rust 代码里调用实例的方法（特殊方法、setter），一直跑到它返回
+     */
+    pub fn call_method_and_wait(
+        &mut self,
+        receiver: &value::Value,
+        method_id: gc::HeapId,
+        args: &[value::Value]
+    ) -> Result<value::Value, String> {
+        let bound_method = value::Value::bound_method(
+            self.heap.manage_bound_method(value::BoundMethod {
+                receiver: *receiver,
+                closure_id: method_id,
+            })
+        );
+        builtins::call_and_wait(self, &bound_method, args)
+    }
+
+    /**
+     * print 和插值字符串用的格式：定义了 __str__ 的实例用它的返回值，列表里面的元素也一样
+     */
+    fn stringify(&mut self, val: &value::Value) -> Result<String, InterpreterError> {
+        match val.unbox(&self.heap) {
+            value::Unboxed::Instance(_) => {
+                match self.special_method(val, "__str__") {
+                    Some(method_id) => {
+                        let res = self
+                            .call_method_and_wait(val, method_id, &[])
+                            .map_err(InterpreterError::Runtime)?;
+                        match res.unbox(&self.heap) {
+                            value::Unboxed::String(id) => Ok(self.get_str(id).clone()),
+                            _ =>
+                                Err(
+                                    InterpreterError::Runtime(
+                                        format!(
+                                            "__str__ must return a string. Found {:?}.",
+                                            value::type_of(&res)
+                                        )
+                                    )
+                                ),
+                        }
+                    }
+                    None => Ok(self.format_val(val)),
+                }
+            }
+            value::Unboxed::List(list_id) => {
+                let elements = self.get_list_elements(list_id).clone();
+                let mut parts = Vec::new();
+                for element in elements.iter() {
+                    parts.push(self.stringify(element)?);
+                }
+                Ok(format!("[{}]", parts.join(", ")))
+            }
+            value::Unboxed::Variant(variant_id) => {
+                let variant = self.heap.get_variant(variant_id).clone();
+                let mut fields = Vec::new();
+                for field in variant.fields.iter() {
+                    fields.push(self.stringify(field)?);
+                }
+                Ok(self.format_variant(variant.enum_id, variant.tag, &fields))
+            }
+            _ => Ok(self.format_val(val)),
         }
+    }
 
-        let mut args = Vec::new();
-        // args = stack 栈顶的几个参数
-        for _ in 0..arg_count {
-            args.push(self.pop_stack()); // pop args
+    /**
+     * 栈顶是闭包，下面是类：把闭包放进类的某一张方法表里
+     */
+    fn define_method(
+        &mut self,
+        idx: usize,
+        members: fn(&mut value::Class) -> &mut HashMap<String, gc::HeapId>
+    ) {
+        if let value::Unboxed::String(method_name_id) = self.read_constant(idx).unbox(&self.heap) {
+            let method_name = self.heap.get_str(method_name_id).clone();
+            let maybe_method = *self.peek_by(0);
+            let maybe_method_id = gc::Heap::extract_id(&maybe_method).unwrap();
+            let maybe_class = *self.peek_by(1);
+            match maybe_class.unbox(&self.heap) {
+                value::Unboxed::Class(class_id) => {
+                    let class = self.heap.get_class_mut(class_id);
+                    members(class).insert(method_name, maybe_method_id);
+                    self.pop_stack();
+                }
+                _ => {
+                    panic!(
+                        "should only define methods on a class! tried on {:?}",
+                        self.format_val(&maybe_class)
+                    );
+                }
+            }
+        } else {
+            panic!("expected string when defining a method.");
         }
-        args.reverse(); // 栈顶相反
-        let args = args; // 设置为不变
-        self.pop_stack(); // native function value
+    }
 
-        // 调用函数
-        let res = (native_func.func)(self, &args);
+    /**
+     * 读取 idx 处的常量值作为变量名，栈顶的值放到全局变量表里；已经定义过的常量不能被覆盖
+     */
+    fn define_global(
+        &mut self,
+        idx: usize,
+        is_const: bool,
+        lineno: bytecode::Lineno
+    ) -> Result<(), InterpreterError> {
+        if let value::Unboxed::String(name_id) = self.read_constant(idx).unbox(&self.heap) {
+            let name = self.get_str(name_id).clone();
+            if self.const_globals.contains(&name) {
+                return Err(
+                    InterpreterError::Runtime(
+                        format!("Cannot redefine constant {} at line {}.", name, lineno.value)
+                    )
+                );
+            }
+            if is_const {
+                self.const_globals.insert(name.clone());
+            }
+            let val = self.pop_stack();
+            self.globals.insert(name, val);
+            Ok(())
+        } else {
+            panic!(
+                "expected string when defining global, found {:?}",
+                value::type_of(&self.read_constant(idx))
+            );
+        }
+    }
 
-        match res {
-            Ok(result) => {
-                self.stack.push(result);
-                Ok(())
+    /**
+     * 读全局变量，idx 是变量名在常量池里的下标
+     */
+    pub fn get_global(
+        &mut self,
+        idx: usize,
+        lineno: bytecode::Lineno
+    ) -> Result<value::Value, InterpreterError> {
+        if let value::Unboxed::String(name_id) = self.read_constant(idx).unbox(&self.heap) {
+            match self.globals.get(self.get_str(name_id)) {
+                Some(val) => Ok(*val),
+                None =>
+                    Err(
+                        InterpreterError::Runtime(
+                            format!(
+                                "Undefined variable '{}' at line {}.",
+                                self.get_str(name_id),
+                                lineno.value
+                            )
+                        )
+                    ),
             }
-            Err(err) =>
+        } else {
+            panic!(
+                "expected string when defining global, found {:?}",
+                value::type_of(&self.read_constant(idx))
+            );
+        }
+    }
+
+    /**
+     * 给已经定义过的全局变量赋值，常量不能赋值
+     */
+    pub fn set_global(
+        &mut self,
+        idx: usize,
+        val: value::Value,
+        lineno: bytecode::Lineno
+    ) -> Result<(), InterpreterError> {
+        if let value::Unboxed::String(name_id) = self.read_constant(idx).unbox(&self.heap) {
+            let name_str = self.get_str(name_id).clone();
+            if self.const_globals.contains(&name_str) {
+                return Err(
+                    InterpreterError::Runtime(
+                        format!("Cannot assign to constant {} at line {}.", name_str, lineno.value)
+                    )
+                );
+            }
+            if
+                // hash.entry 返回一个 enum { Occupid | vacant（空的） }
+                let std::collections::hash_map::Entry::Occupied(mut e) = self.globals.entry(
+                    name_str.clone() // 标识符复制一份
+                )
+            {
+                e.insert(val); // 得到 entry，插入 val
+                Ok(())
+            } else {
+                // 否则 如果是 vacant ，那么就是有错误产生
                 Err(
                     InterpreterError::Runtime(
-                        format!("When calling {}: {}.", native_func.name, err)
+                        format!(
+                            "Use of undefined variable {} in setitem expression at line {}.",
+                            name_str,
+                            lineno.value
+                        )
                     )
-                ),
+                )
+            }
+        } else {
+            panic!(
+                "expected string when setting global, found {:?}",
+                value::type_of(&self.read_constant(idx))
+            );
         }
     }
 
-    /*
-     * 很奇怪，没有真正的调用
+    /**
+     * 读当前闭包的第 idx 个上值
      */
-    fn call_bound_method(
+    pub fn get_upval(&self, idx: usize) -> value::Value {
+        // 获取栈顶的 frame，得到 frame 的 closure，并获得 上值
+        let upvalue = &self.frame().closure.upvalues[idx];
+        let val = match &*upvalue.borrow() {
+            value::Upvalue::Closed(value) => *value,
+            value::Upvalue::Open(stack_index) => self.stack[*stack_index],
+        };
+        val
+    }
+
+    pub fn set_upval(&mut self, idx: usize, new_value: value::Value) {
+        let upvalue = self.frame().closure.upvalues[idx].clone();
+        match &mut *upvalue.borrow_mut() {
+            value::Upvalue::Closed(value) => {
+                *value = new_value;
+            }
+            // outer 函数还没有返回
+            value::Upvalue::Open(stack_index) => {
+                self.stack[*stack_index] = new_value;
+            }
+        };
+    }
+
+    fn define_native(
         &mut self,
-        method_id: gc::HeapId,
-        arg_count: u8
-    ) -> Result<(), InterpreterError> {
-        let bound_method = self.get_bound_method(method_id).clone();
-        let closure_id = bound_method.closure_id;
-        let arg_count_usize: usize = arg_count.into(); // 很有意思，有 usize::from 也有 <usize>.into
-        let stack_len = self.stack.len();
-        // 将对象压入 stack 中
-        self.stack[stack_len - arg_count_usize - 1] = value::Value::Instance(
-            bound_method.instance_id
+        name: &str,
+        arity: u8,
+        optional_args: u8,
+        func: fn(&mut Interpreter, &[value::Value]) -> Result<value::Value, String>
+    ) {
+        self.globals.insert(
+            String::from(name),
+            value::Value::native_function(
+                self.heap.manage_native_function(value::NativeFunction {
+                    arity,
+                    optional_args,
+                    name: String::from(name),
+                    func,
+                })
+            )
         );
-        self.prepare_call(closure_id, arg_count)
     }
 
     /*
-     * Set up a few call frame so that on the next interpreter step we'll start executing code inside the function.
-     * 就是在调用函数之前，要准备他的 frame，以及准备他 栈上的数据
+     * 先把 prelude 跑一遍，导出的函数留在全局变量里，其余的收起来
      */
-    fn prepare_call(
-        &mut self,
-        closure_handle: gc::HeapId,
-        arg_count: u8
-    ) -> Result<(), InterpreterError> {
-        let closure = self.get_closure(closure_handle).clone();
-        let func = &closure.function;
+    fn load_prelude(&mut self) {
+        let func = match
+            compiler::Compiler::compile(
+                prelude::SOURCE.to_string(),
+                extensions::Extensions::default()
+            )
+        {
+            Ok(func) => func,
+            Err(err) => panic!("failed to compile the prelude: {:?}", err),
+        };
+        let builtin_names: Vec<String> = self.globals.keys().cloned().collect();
+        if let Err(err) = self.interpret(func) {
+            panic!("failed to run the prelude: {}", err);
+        }
+        self.stack.clear();
 
-        // 如果 参数个数不相匹配，那么就返回 Err
-        if arg_count != func.arity {
-            return Err(
-                InterpreterError::Runtime(
-                    format!("Expected {} arguments but found {}.", func.arity, arg_count)
-                )
+        let defined: Vec<String> = self.globals
+            .keys()
+            .filter(|name| !builtin_names.contains(name) && !prelude::EXPORTS.contains(&name.as_str()))
+            .cloned()
+            .collect();
+        for name in defined {
+            if let Some(val) = self.globals.remove(&name) {
+                self.prelude.insert(name, val);
+            }
+        }
+    }
+
+    /*
+     * prelude 里的 json 是个空的实例，把 parse / stringify 放到它的字段上，然后冻结
+     */
+    fn define_json(&mut self) {
+        let json_id = match self.globals.get("json").map(|json| json.unbox(&self.heap)) {
+            Some(value::Unboxed::Instance(json_id)) => json_id,
+            _ => panic!("prelude json is missing"),
+        };
+        for (name, arity, optional_args, func) in [
+            ("parse", 1, 0, builtins::json_parse as fn(&mut Interpreter, &[value::Value]) -> _),
+            ("stringify", 1, 1, builtins::json_stringify),
+        ] {
+            let native = value::Value::native_function(
+                self.heap.manage_native_function(value::NativeFunction {
+                    arity,
+                    optional_args,
+                    name: format!("json.{}", name),
+                    func,
+                })
             );
+            self.heap.get_instance_mut(json_id).fields.insert(name.to_string(), native);
         }
+        self.heap.freeze(json_id);
+    }
 
-        self.frames.push(CallFrame::default()); // 默认构造一个 frame
-        let mut frame = self.frames.last_mut().unwrap();
-        frame.closure = closure;
-        frame.slots_offset = self.stack.len() - usize::from(arg_count); // 给 frame 设置 stack[len - arg_count, len] 的位置
-        Ok(())
+    /*
+     * prelude 里定义、没有导出的类，比如 json.parse 用的 JsonObject
+     */
+    pub fn prelude_class(&self, name: &str) -> gc::HeapId {
+        match self.prelude.get(name).map(|class| class.unbox(&self.heap)) {
+            Some(value::Unboxed::Class(class_id)) => class_id,
+            _ => panic!("prelude class {} is missing", name),
+        }
     }
 
     fn create_instance(&mut self, class_id: gc::HeapId) {
@@ -1397,7 +2980,7 @@ impl Interpreter {
             class_id,
             fields: HashMap::new(),
         });
-        self.stack.push(value::Value::Instance(instance_id));
+        self.stack.push(value::Value::instance(instance_id));
     }
 
     fn pop_stack_n_times(&mut self, num_to_pop: usize) {
@@ -1409,79 +2992,213 @@ impl Interpreter {
     /*
      * 判断是不是 false
      */
-    fn is_falsey(&self, val: &value::Value) -> bool {
-        match val {
-            value::Value::Nil => true,
-            value::Value::Bool(b) => !*b,
-            value::Value::Number(f) => *f == 0.0,
-            value::Value::Function(_) => false,
-            value::Value::NativeFunction(_) => false,
-            value::Value::Class(_) => false,
-            value::Value::Instance(_) => false,
-            value::Value::BoundMethod(_) => false,
-            value::Value::String(id) => self.get_str(*id).is_empty(),
-            value::Value::List(id) => self.get_list_elements(*id).is_empty(),
+    pub fn is_falsey(&self, val: &value::Value) -> bool {
+        match val.unbox(&self.heap) {
+            value::Unboxed::Nil => true,
+            value::Unboxed::Bool(b) => !b,
+            value::Unboxed::Number(f) => f == 0.0,
+            value::Unboxed::Integer(n) => n == 0,
+            value::Unboxed::Function(_) => false,
+            value::Unboxed::NativeFunction(_) => false,
+            value::Unboxed::Class(_) => false,
+            value::Unboxed::Trait(_) => false,
+            value::Unboxed::Instance(_) => false,
+            value::Unboxed::BoundMethod(_) => false,
+            value::Unboxed::Generator(_) => false,
+            value::Unboxed::GeneratorMethod(_, _) => false,
+            value::Unboxed::Enum(_) => false,
+            value::Unboxed::Variant(_) => false,
+            value::Unboxed::VariantConstructor(_, _) => false,
+            value::Unboxed::Channel(_) => false,
+            value::Unboxed::ChannelMethod(_, _) => false,
+            value::Unboxed::Promise(_) => false,
+            value::Unboxed::Isolate(_) => false,
+            value::Unboxed::IsolateMethod(_, _) => false,
+            value::Unboxed::String(id) => self.get_str(id).is_empty(),
+            value::Unboxed::List(id) => self.get_list_elements(id).is_empty(),
         }
     }
 
     /**
      * 打印 Value
      */
-    fn print_val(&mut self, val: &value::Value) {
-        let output = self.format_val(val);
+    pub fn print_val(&mut self, val: &value::Value) -> Result<(), InterpreterError> {
+        let output = self.stringify(val)?;
         println!("{}", output);
         self.output.push(output);
+        Ok(())
     }
 
     /**
      * 判断是不是相等
      */
-    fn values_equal(&self, val1: &value::Value, val2: &value::Value) -> bool {
-        match (val1, val2) {
-            (value::Value::Number(n1), value::Value::Number(n2)) => (n1 - n2).abs() < f64::EPSILON,
-            (value::Value::Bool(b1), value::Value::Bool(b2)) => b1 == b2,
-            (value::Value::String(s1), value::Value::String(s2)) => {
-                self.get_str(*s1) == self.get_str(*s2)
+    pub fn values_equal(&self, val1: &value::Value, val2: &value::Value) -> bool {
+        match (val1.unbox(&self.heap), val2.unbox(&self.heap)) {
+            (value::Unboxed::Number(n1), value::Unboxed::Number(n2)) => (n1 - n2).abs() < f64::EPSILON,
+            (value::Unboxed::Integer(n1), value::Unboxed::Integer(n2)) => n1 == n2,
+            (value::Unboxed::Integer(n1), value::Unboxed::Number(n2))
+            | (value::Unboxed::Number(n2), value::Unboxed::Integer(n1)) => {
+                ((n1 as f64) - n2).abs() < f64::EPSILON
+            }
+            (value::Unboxed::Bool(b1), value::Unboxed::Bool(b2)) => b1 == b2,
+            (value::Unboxed::String(s1), value::Unboxed::String(s2)) => {
+                self.get_str(s1) == self.get_str(s2)
+            }
+            (value::Unboxed::Nil, value::Unboxed::Nil) => true,
+            // 变体按值比较：同一个枚举的同一个变体，字段也都相等
+            (value::Unboxed::Variant(id1), value::Unboxed::Variant(id2)) => {
+                let (variant1, variant2) = (self.heap.get_variant(id1), self.heap.get_variant(id2));
+                variant1.enum_id == variant2.enum_id &&
+                    variant1.tag == variant2.tag &&
+                    variant1.fields
+                        .iter()
+                        .zip(variant2.fields.iter())
+                        .all(|(field1, field2)| self.values_equal(field1, field2))
             }
-            (value::Value::Nil, value::Value::Nil) => true,
             (_, _) => false,
         }
     }
 
     /**
-     * 数字的二元操作，但是我对 优先级有点困惑
+     * 二元运算，left 是先压栈的左操作数。栈式和寄存器两种后端都用这个，报错也一样
      */
-    fn numeric_binop(
+    pub fn binary_op(
         &mut self,
-        binop: Binop,
+        op: &bytecode::Op,
+        left: value::Value,
+        right: value::Value,
         lineno: bytecode::Lineno
-    ) -> Result<(), InterpreterError> {
-        let val1 = self.peek_by(0).clone();
-        let val2 = self.peek_by(1).clone();
+    ) -> Result<value::Value, InterpreterError> {
+        let binop = match op {
+            bytecode::Op::Add => {
+                return self.add_values(left, right, lineno);
+            }
+            bytecode::Op::Equal => {
+                return Ok(value::Value::bool(self.values_equal(&right, &left)));
+            }
+            bytecode::Op::Greater | bytecode::Op::Less => {
+                let expected = match op {
+                    bytecode::Op::Greater => Ordering::Greater,
+                    _ => Ordering::Less,
+                };
+                return match self.compare_numbers(&left, &right) {
+                    Some(ordering) => Ok(value::Value::bool(ordering == Some(expected))),
+                    None =>
+                        Err(
+                            InterpreterError::Runtime(
+                                format!(
+                                    "invalid operands in {:?} expression. Expected numbers, found {:?} and {:?} at line {}",
+                                    op,
+                                    value::type_of(&right),
+                                    value::type_of(&left),
+                                    lineno.value
+                                )
+                            )
+                        ),
+                };
+            }
+            bytecode::Op::Subtract => Binop::Sub,
+            bytecode::Op::Multiply => Binop::Mul,
+            bytecode::Op::Divide => Binop::Div,
+            bytecode::Op::Modulo => Binop::Mod,
+            bytecode::Op::Power => Binop::Pow,
+            bytecode::Op::IntDivide => Binop::IntDiv,
+            bytecode::Op::BitAnd => Binop::BitAnd,
+            bytecode::Op::BitOr => Binop::BitOr,
+            bytecode::Op::BitXor => Binop::BitXor,
+            bytecode::Op::ShiftLeft => Binop::Shl,
+            bytecode::Op::ShiftRight => Binop::Shr,
+            _ => panic!("{:?} is not a binary operator", op),
+        };
+        self.numeric_binop(binop, left, right, lineno)
+    }
 
-        match (&val1, &val2) {
-            (value::Value::Number(n1), value::Value::Number(n2)) => {
-                self.pop_stack();
-                self.pop_stack();
-                self.stack.push(
-                    value::Value::Number(
-                        Interpreter::apply_numeric_binop(
-                            *n2,
-                            *n1,
-                            binop // note the order!
-                        )
-                    )
-                );
-                Ok(())
+    /**
+     * 一元运算：取负和取反
+     */
+    pub fn unary_op(
+        &mut self,
+        op: &bytecode::Op,
+        val: value::Value,
+        lineno: bytecode::Lineno
+    ) -> Result<value::Value, InterpreterError> {
+        match op {
+            bytecode::Op::Negate => {
+                if let value::Unboxed::Integer(n) = val.unbox(&self.heap) {
+                    return n
+                        .checked_neg()
+                        .map(|n| self.heap.integer(n))
+                        .ok_or_else(|| {
+                            InterpreterError::Runtime(
+                                format!("integer overflow in negate expression at line {}", lineno.value)
+                            )
+                        });
+                }
+                match self.extract_number(&val) {
+                    Some(to_negate) => Ok(value::Value::number(-to_negate)),
+                    // 如果不是数字，但是前面有一个 negate 那么就有问题
+                    None =>
+                        Err(
+                            InterpreterError::Runtime(
+                                format!(
+                                    "invalid operand to unary op negate. Expected number, found {:?} at line {}",
+                                    value::type_of(&val),
+                                    lineno.value
+                                )
+                            )
+                        ),
+                }
+            }
+            bytecode::Op::Not =>
+                match self.extract_bool(&val) {
+                    Some(b) => Ok(value::Value::bool(!b)),
+                    None =>
+                        Err(
+                            InterpreterError::Runtime(
+                                format!(
+                                    "invalid operand in not expression. Expected boolean, found {:?} at line {}",
+                                    value::type_of(&val),
+                                    lineno.value
+                                )
+                            )
+                        ),
+                }
+            _ => panic!("{:?} is not a unary operator", op),
+        }
+    }
+
+    /**
+     * 加法除了数字，还能拼接字符串和列表
+     */
+    fn add_values(
+        &mut self,
+        left: value::Value,
+        right: value::Value,
+        lineno: bytecode::Lineno
+    ) -> Result<value::Value, InterpreterError> {
+        match (left.unbox(&self.heap), right.unbox(&self.heap)) {
+            (
+                value::Unboxed::Number(_) | value::Unboxed::Integer(_),
+                value::Unboxed::Number(_) | value::Unboxed::Integer(_),
+            ) => self.numeric_binop(Binop::Add, left, right, lineno),
+            (value::Unboxed::String(s1), value::Unboxed::String(s2)) => {
+                // 拼接字符串
+                let res = format!("{}{}", self.get_str(s1), self.get_str(s2));
+                Ok(value::Value::string(self.heap.manage_str(res)))
+            }
+            (value::Unboxed::List(id1), value::Unboxed::List(id2)) => {
+                let mut res = self.get_list_elements(id1).clone();
+                res.extend(self.get_list_elements(id2).clone());
+                Ok(value::Value::list(self.heap.manage_list(res)))
             }
             _ =>
                 Err(
                     InterpreterError::Runtime(
                         format!(
-                            "Expected numbers in {:?} expression. Found {:?} and {:?} (line={})",
-                            binop,
-                            value::type_of(&val1),
-                            value::type_of(&val2),
+                            "invalid operands of type {:?} and {:?} in add expression: \
+                             both operands must be number or string (line={})",
+                            value::type_of(&right),
+                            value::type_of(&left),
                             lineno.value
                         )
                     )
@@ -1489,13 +3206,120 @@ impl Interpreter {
         }
     }
 
-    fn apply_numeric_binop(left: f64, right: f64, binop: Binop) -> f64 {
-        match binop {
+    /**
+     * 数字的二元操作，但是我对 优先级有点困惑
+     */
+    fn numeric_binop(
+        &mut self,
+        binop: Binop,
+        left: value::Value,
+        right: value::Value,
+        lineno: bytecode::Lineno
+    ) -> Result<value::Value, InterpreterError> {
+        let res = match (left.unbox(&self.heap), right.unbox(&self.heap)) {
+            (value::Unboxed::Integer(n1), value::Unboxed::Integer(n2)) =>
+                self.apply_integer_binop(n1, n2, &binop),
+            _ =>
+                match (self.extract_number(&left), self.extract_number(&right)) {
+                    // 有一边是浮点数，整个表达式按浮点数算
+                    (Some(n1), Some(n2)) => self.apply_numeric_binop(n1, n2, &binop),
+                    _ => {
+                        return Err(
+                            InterpreterError::Runtime(
+                                format!(
+                                    "Expected numbers in {:?} expression. Found {:?} and {:?} (line={})",
+                                    binop,
+                                    value::type_of(&right),
+                                    value::type_of(&left),
+                                    lineno.value
+                                )
+                            )
+                        );
+                    }
+                }
+        };
+        res.map_err(|err| { InterpreterError::Runtime(format!("{} (line={})", err, lineno.value)) })
+    }
+
+    fn apply_numeric_binop(&mut self, left: f64, right: f64, binop: &Binop) -> Result<value::Value, String> {
+        let res = match binop {
             Binop::Add => left + right,
             Binop::Sub => left - right,
             Binop::Mul => left * right,
             Binop::Div => left / right,
-        }
+            Binop::Mod => left % right,
+            Binop::Pow => left.powf(right),
+            Binop::IntDiv => (left / right).floor(),
+            Binop::BitAnd | Binop::BitOr | Binop::BitXor | Binop::Shl | Binop::Shr => {
+                let as_int = |n: f64| -> Result<i64, String> {
+                    if n.fract() == 0.0 && n >= (i64::MIN as f64) && n <= (i64::MAX as f64) {
+                        Ok(n as i64)
+                    } else {
+                        Err(format!("Expected integers in {:?} expression. Found {}", binop, n))
+                    }
+                };
+                return self.apply_integer_binop(as_int(left)?, as_int(right)?, binop);
+            }
+        };
+        Ok(value::Value::number(res))
+    }
+
+    /**
+     * 两个整数的二元操作：溢出报错而不是回绕，/ 和负指数的 ** 得到浮点数
+     */
+    fn apply_integer_binop(&mut self, left: i64, right: i64, binop: &Binop) -> Result<value::Value, String> {
+        let overflow = || format!("integer overflow in {:?} expression", binop);
+        let nonzero_divisor = || -> Result<(), String> {
+            if right == 0 {
+                Err(format!("integer division by zero in {:?} expression", binop))
+            } else {
+                Ok(())
+            }
+        };
+        let shift_amount = || -> Result<u32, String> {
+            if (0..64).contains(&right) {
+                Ok(right as u32)
+            } else {
+                Err(format!("Shift amount {} out of range in {:?} expression", right, binop))
+            }
+        };
+        let res = match binop {
+            Binop::Add => left.checked_add(right).ok_or_else(overflow)?,
+            Binop::Sub => left.checked_sub(right).ok_or_else(overflow)?,
+            Binop::Mul => left.checked_mul(right).ok_or_else(overflow)?,
+            Binop::Div => {
+                return Ok(value::Value::number((left as f64) / (right as f64)));
+            }
+            Binop::Mod => {
+                nonzero_divisor()?;
+                left.checked_rem(right).ok_or_else(overflow)?
+            }
+            Binop::IntDiv => {
+                nonzero_divisor()?;
+                let quotient = left.checked_div(right).ok_or_else(overflow)?;
+                // 向下取整，和浮点数的 ~/ 保持一致
+                if left % right != 0 && (left < 0) != (right < 0) {
+                    quotient - 1
+                } else {
+                    quotient
+                }
+            }
+            Binop::Pow => {
+                if right < 0 {
+                    return Ok(value::Value::number((left as f64).powf(right as f64)));
+                }
+                u32::try_from(right)
+                    .ok()
+                    .and_then(|exp| left.checked_pow(exp))
+                    .ok_or_else(overflow)?
+            }
+            Binop::BitAnd => left & right,
+            Binop::BitOr => left | right,
+            Binop::BitXor => left ^ right,
+            Binop::Shl => left << shift_amount()?,
+            Binop::Shr => left >> shift_amount()?,
+        };
+        Ok(self.heap.integer(res))
     }
 
     /**
@@ -1505,11 +3329,17 @@ impl Interpreter {
         &mut self,
         maybe_instance: value::Value,
         val: value::Value,
-        attr_id: gc::HeapId
+        name_idx: usize
     ) -> Result<(), InterpreterError> {
-        let attr_name = self.get_str(attr_id).clone();
-        match maybe_instance {
-            value::Value::Instance(instance_id) => {
+        let attr_name = self.constant_str(name_idx).to_string();
+        match maybe_instance.unbox(&self.heap) {
+            value::Unboxed::Instance(instance_id) if self.heap.is_frozen(instance_id) =>
+                Err(
+                    InterpreterError::Runtime(
+                        format!("Can't set attribute {} on a frozen instance.", attr_name)
+                    )
+                ),
+            value::Unboxed::Instance(instance_id) => {
                 let instance = self.heap.get_instance_mut(instance_id);
                 instance.fields.insert(attr_name, val);
                 Ok(())
@@ -1527,33 +3357,6 @@ impl Interpreter {
         }
     }
 
-    fn getattr(
-        &self,
-        maybe_instance: value::Value,
-        attr_id: gc::HeapId
-    ) -> Result<Option<value::Value>, InterpreterError> {
-        let attr_name = self.get_str(attr_id).clone();
-        match maybe_instance {
-            value::Value::Instance(instance_id) => {
-                let instance = self.heap.get_instance(instance_id);
-                match instance.fields.get(&attr_name) {
-                    Some(val) => Ok(Some(val.clone())),
-                    None => Ok(None),
-                }
-            }
-            _ =>
-                Err(
-                    InterpreterError::Runtime(
-                        format!(
-                            "can't get attribute {}  on value of type {:?}. Need class instance.",
-                            attr_name,
-                            value::type_of(&maybe_instance)
-                        )
-                    )
-                ),
-        }
-    }
-
     /*
      * 将闭包与 对象绑定
      */
@@ -1567,9 +3370,9 @@ impl Interpreter {
         if let Some(closure_id) = class.methods.get(&attr_name) {
             self.pop_stack();
             self.stack.push(
-                value::Value::BoundMethod(
+                value::Value::bound_method(
                     self.heap.manage_bound_method(value::BoundMethod {
-                        instance_id,
+                        receiver: value::Value::instance(instance_id),
                         closure_id: *closure_id,
                     })
                 )
@@ -1595,9 +3398,9 @@ impl Interpreter {
         self.frame_mut().next_op_and_advance()
     }
 
-    fn extract_bool(val: &value::Value) -> Option<bool> {
-        match val {
-            value::Value::Bool(b) => Some(*b),
+    fn extract_bool(&self, val: &value::Value) -> Option<bool> {
+        match val.unbox(&self.heap) {
+            value::Unboxed::Bool(b) => Some(b),
             _ => None,
         }
     }
@@ -1628,7 +3431,7 @@ impl Interpreter {
         self.heap.get_bound_method(method_handle)
     }
 
-    fn get_list_elements(&self, list_handle: gc::HeapId) -> &Vec<value::Value> {
+    pub fn get_list_elements(&self, list_handle: gc::HeapId) -> &Vec<value::Value> {
         self.heap.get_list_elements(list_handle)
     }
 
@@ -1644,7 +3447,7 @@ impl Interpreter {
      * 垃圾回收（但是这样的实现，感觉还是太操蛋了）
      * 你说这种递归，他真的能比得过 AST 的版本吗？
      */
-    fn collect_garbage(&mut self) {
+    pub fn collect_garbage(&mut self) {
         self.heap.unmark(); // 将 heap 所有的元素 mark=false
         self.mark_roots(); // 重新标记
         self.trace_references();
@@ -1686,15 +3489,29 @@ impl Interpreter {
             .flat_map(|frame| self.heap.closure_children(&frame.closure))
             .collect();
 
+        let frame_generators: Vec<gc::HeapId> = self.frames
+            .iter()
+            .flat_map(|frame| frame.generator.into_iter().chain(frame.promise))
+            .collect();
+
         let globals_to_mark: Vec<gc::HeapId> = self.globals
             .values()
+            .chain(self.prelude.values())
             .flat_map(gc::Heap::extract_id)
             .collect();
 
+        let fiber_roots: Vec<gc::HeapId> = self.fibers
+            .roots(&self.heap)
+            .into_iter()
+            .chain(self.event_loop.roots())
+            .collect();
+
         for val in stack_vals_to_mark
             .iter()
             .chain(frame_closure_children.iter())
-            .chain(globals_to_mark.iter()) {
+            .chain(frame_generators.iter())
+            .chain(globals_to_mark.iter())
+            .chain(fiber_roots.iter()) {
             // chain 起来，然后 mark=true
             self.mark_value(*val);
         }
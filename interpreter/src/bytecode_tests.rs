@@ -1,7 +1,7 @@
 #[cfg(test)]
 mod tests {
     macro_rules! vec_of_strings {
-        ($($x:expr),*) => (vec![$($x.to_string()),*]);
+        ($($x:expr),*) => ([$($x.to_string()),*]);
     }
 
     use crate::bytecode_interpreter::*;
@@ -9,11 +9,28 @@ mod tests {
     use crate::extensions;
 
     fn evaluate(code: &str, extensions: extensions::Extensions) -> Result<Vec<String>, String> {
+        let res = evaluate_with(code, extensions, false);
+        // 寄存器后端跑同一个程序，输出和报错都要一样
+        assert_eq!(evaluate_with(code, extensions, true), res, "register backend differs on:\n{}", code);
+        res
+    }
+
+    fn evaluate_with(
+        code: &str,
+        extensions: extensions::Extensions,
+        registers: bool
+    ) -> Result<Vec<String>, String> {
         let func_or_err = Compiler::compile(String::from(code), extensions);
 
         match func_or_err {
             Ok(func) => {
                 let mut interp = Interpreter::default();
+                // 带着 jit 特性跑测试的时候强制打开 JIT，能编译的函数第一次调用就编译
+                #[cfg(feature = "jit")]
+                interp.jit.force();
+                interp.registers = registers;
+                // 定时器和 sleep 不真的等
+                interp.event_loop.use_fake_clock();
                 let res = interp.interpret(func);
                 match res {
                     Ok(()) => Ok(interp.output),
@@ -51,7 +68,7 @@ mod tests {
         );
     }
 
-    fn check_error(code: &str, extensions: extensions::Extensions, f: &dyn Fn(&str) -> ()) {
+    fn check_error(code: &str, extensions: extensions::Extensions, f: &dyn Fn(&str)) {
         let res = evaluate(code, extensions);
 
         match res {
@@ -60,7 +77,7 @@ mod tests {
         }
     }
 
-    fn check_error_default(code: &str, f: &dyn Fn(&str) -> ()) {
+    fn check_error_default(code: &str, f: &dyn Fn(&str)) {
         check_error(code, extensions::Extensions::default(), f);
     }
 
@@ -309,7 +326,7 @@ mod tests {
             if n <= 1 {
                 return 1;
             }
-            return n * fact(n - 1);
+            n * fact(n - 1)
         }
 
         check_output_default(
@@ -428,7 +445,7 @@ mod tests {
             if n <= 1 {
                 return 1;
             }
-            return n * fact(n - 1);
+            n * fact(n - 1)
         }
 
         check_output_default(
@@ -469,7 +486,7 @@ mod tests {
              \n\
              var start = clock();\n\
              print fib(5);\n\
-             print clock() - start;\n\
+             print clock() - start >= 0;\n\
              print 42;",
             extensions::Extensions::default()
         );
@@ -478,6 +495,7 @@ mod tests {
             Ok(output) => {
                 assert_eq!(output.len(), 3);
                 assert_eq!(output[0], "5");
+                assert_eq!(output[1], "true");
                 assert_eq!(output[2], "42");
             }
             Err(err) => {
@@ -971,4 +989,2096 @@ mod tests {
             &vec_of_strings!["[1337]"]
         )
     }
+
+    #[test]
+    fn test_call_keeps_caller_locals() {
+        check_output_default(
+            "fun add(a, b) { return a + b; }\n\
+             fun f() {\n\
+               var x = 10;\n\
+               var y = add(1, 2);\n\
+               print x + y;\n\
+             }\n\
+             f();",
+            &vec_of_strings!["13"],
+        );
+    }
+
+    #[test]
+    fn test_method_closure_captures_this() {
+        check_output_default(
+            "class Foo {\n\
+               init(x) { this.x = x; }\n\
+               getter() { fun g() { return this.x; } return g; }\n\
+             }\n\
+             var g = Foo(42).getter();\n\
+             print g();",
+            &vec_of_strings!["42"],
+        );
+    }
+
+    #[test]
+    fn test_arithmetic_operators() {
+        check_output_default(
+            "print 7 % 3;\n\
+             print 2 ** 10;\n\
+             print 7 ~/ 2;\n\
+             print -7 ~/ 2;\n\
+             print 2 ** -1;",
+            &vec_of_strings!["1", "1024", "3", "-4", "0.5"]
+        );
+    }
+
+    #[test]
+    fn test_bitwise_operators() {
+        check_output_default(
+            "print 6 & 3;\n\
+             print 6 | 3;\n\
+             print 6 ^ 3;\n\
+             print 1 << 4;\n\
+             print 256 >> 2;\n\
+             print -8 >> 1;",
+            &vec_of_strings!["2", "7", "5", "16", "64", "-4"]
+        );
+    }
+
+    #[test]
+    fn test_bitwise_requires_integers() {
+        check_error_default(
+            "print 1.5 & 1;",
+            &(|err: &str| assert!(err.starts_with("Expected integers in BitAnd expression.")))
+        );
+    }
+
+    #[test]
+    fn test_operator_precedence() {
+        check_output_default(
+            "print 2 + 3 * 4 ** 2;\n\
+             print -2 ** 2;\n\
+             print 2 ** 3 ** 2;\n\
+             print 1 | 2 ^ 3 & 4 << 1;\n\
+             print 6 & 3 == 2;\n\
+             print 1 + 1 << 2;\n\
+             print 10 - 7 % 4 * 2;",
+            &vec_of_strings!["50", "-4", "512", "3", "true", "8", "4"]
+        );
+    }
+
+    #[test]
+    fn test_compound_assignment() {
+        check_output_default(
+            "var x = 10;\n\
+             x += 5;\n\
+             x -= 3;\n\
+             x *= 2;\n\
+             print x /= 4;\n\
+             {\n\
+               var s = \"foo\";\n\
+               s += \"bar\";\n\
+               print s;\n\
+             }",
+            &vec_of_strings!["6", "foobar"]
+        );
+    }
+
+    #[test]
+    fn test_compound_assignment_upvalue() {
+        check_output_default(
+            "fun counter() {\n\
+               var n = 0;\n\
+               fun inc() { n += 2; return n; }\n\
+               return inc;\n\
+             }\n\
+             var c = counter();\n\
+             c();\n\
+             print c();",
+            &vec_of_strings!["4"]
+        );
+    }
+
+    #[test]
+    fn test_compound_assignment_property() {
+        check_output_default(
+            "class Counter {\n\
+               init() { this.n = 1; }\n\
+               bump() { this.n *= 3; return this; }\n\
+             }\n\
+             var c = Counter();\n\
+             c.bump().n += 2;\n\
+             print c.n;",
+            &vec_of_strings!["5"]
+        );
+    }
+
+    #[test]
+    fn test_compound_assignment_subscript() {
+        check_output_lists(
+            "var calls = 0;\n\
+             fun idx() { calls += 1; return 1; }\n\
+             var xs = [1, 2, 3];\n\
+             xs[idx()] += 10;\n\
+             print xs;\n\
+             print calls;",
+            &vec_of_strings!["[1, 12, 3]", "1"]
+        );
+    }
+
+    #[test]
+    fn test_increment_decrement() {
+        check_output_default(
+            "var x = 1;\n\
+             print x++;\n\
+             print x;\n\
+             print ++x;\n\
+             print x--;\n\
+             print --x;\n\
+             {\n\
+               var y = 5;\n\
+               y++;\n\
+               print y;\n\
+             }",
+            &vec_of_strings!["1", "2", "3", "3", "1", "6"]
+        );
+    }
+
+    #[test]
+    fn test_increment_property_and_subscript() {
+        check_output_lists(
+            "class Box {}\n\
+             var b = Box();\n\
+             b.v = 1;\n\
+             print b.v++;\n\
+             print ++b.v;\n\
+             var xs = [5];\n\
+             print xs[0]--;\n\
+             print --xs[0];\n\
+             print b.v + xs[0];",
+            &vec_of_strings!["1", "3", "5", "3", "6"]
+        );
+    }
+
+    #[test]
+    fn test_invalid_compound_assignment_target() {
+        check_error_default(
+            "var a = 1; var b = 2; a + b += 1;",
+            &(|err: &str| assert_eq!(err, "Invalid assignment target."))
+        );
+    }
+
+    #[test]
+    fn test_invalid_increment_target() {
+        check_error_default(
+            "fun f() { return 1; } ++f();",
+            &(|err: &str| assert_eq!(err, "Invalid increment target."))
+        );
+    }
+
+    #[test]
+    fn test_break_and_continue() {
+        check_output_default(
+            "var i = 0;\n\
+             while (true) {\n\
+               i = i + 1;\n\
+               if (i == 2) continue;\n\
+               if (i > 4) break;\n\
+               print i;\n\
+             }\n\
+             for (var j = 0; j < 10; j = j + 1) {\n\
+               var k = j;\n\
+               if (k % 2 == 0) continue;\n\
+               if (k > 5) break;\n\
+               print k;\n\
+             }\n\
+             print i;",
+            &vec_of_strings!["1", "3", "4", "1", "3", "5", "5"]
+        );
+    }
+
+    #[test]
+    fn test_nested_loops_break_inner_only() {
+        check_output_default(
+            "for (var i = 0; i < 3; i = i + 1) {\n\
+               var a = i * 10;\n\
+               for (var j = 0; j < 3; j = j + 1) {\n\
+                 var b = a + j;\n\
+                 if (j == 1) break;\n\
+                 print b;\n\
+               }\n\
+               print a;\n\
+             }",
+            &vec_of_strings!["0", "0", "10", "10", "20", "20"]
+        );
+    }
+
+    #[test]
+    fn test_break_closes_upvalues() {
+        check_output_default(
+            "var f;\n\
+             while (true) {\n\
+               var x = \"captured\";\n\
+               fun get() { return x; }\n\
+               f = get;\n\
+               break;\n\
+             }\n\
+             var y = \"other\";\n\
+             print f();",
+            &vec_of_strings!["captured"]
+        );
+    }
+
+    #[test]
+    fn test_do_while() {
+        check_output_default(
+            "var i = 10;\n\
+             do {\n\
+               print i;\n\
+               i = i + 1;\n\
+             } while (i < 3);\n\
+             var j = 0;\n\
+             do {\n\
+               j = j + 1;\n\
+               if (j == 2) continue;\n\
+               print j;\n\
+             } while (j < 4);",
+            &vec_of_strings!["10", "1", "3", "4"]
+        );
+    }
+
+    #[test]
+    fn test_for_in_list() {
+        check_output_lists(
+            "var total = 0;\n\
+             for (var x in [1, 2, 3, 4]) {\n\
+               if (x == 3) continue;\n\
+               total = total + x;\n\
+             }\n\
+             print total;\n\
+             for (var s in []) print s;\n\
+             for (var n in [nil, 1]) print n;",
+            &vec_of_strings!["7", "nil", "1"]
+        );
+    }
+
+    #[test]
+    fn test_for_in_iterator_protocol() {
+        check_output_default(
+            "class Countdown {\n\
+               init(n) { this.n = n; }\n\
+               next() {\n\
+                 if (this.n == 0) return nil;\n\
+                 this.n = this.n - 1;\n\
+                 return this.n + 1;\n\
+               }\n\
+             }\n\
+             class Range {\n\
+               init(n) { this.n = n; }\n\
+               iter() { return Countdown(this.n); }\n\
+             }\n\
+             for (var i in Range(3)) print i;\n\
+             for (var i in Countdown(5)) {\n\
+               if (i == 3) break;\n\
+               print i;\n\
+             }",
+            &vec_of_strings!["3", "2", "1", "5", "4"]
+        );
+    }
+
+    #[test]
+    fn test_for_in_closures_capture_each_iteration() {
+        check_output_lists(
+            "var fns = [nil, nil, nil];\n\
+             for (var x in [1, 2, 3]) {\n\
+               fun get() { return x; }\n\
+               fns[x - 1] = get;\n\
+             }\n\
+             for (var f in fns) print f();",
+            &vec_of_strings!["1", "2", "3"]
+        );
+    }
+
+    #[test]
+    fn test_for_in_not_iterable() {
+        check_error_default(
+            "for (var x in 42) print x;",
+            &(|err: &str| assert_eq!(err, "Cannot iterate over value of type Integer at line 1"))
+        );
+    }
+
+    #[test]
+    fn test_break_outside_loop() {
+        check_error_default(
+            "fun f() { break; }",
+            &(|err: &str| assert_eq!(err, "Cannot use break outside of a loop."))
+        );
+        check_error_default(
+            "while (true) { fun f() { continue; } }",
+            &(|err: &str| assert_eq!(err, "Cannot use continue outside of a loop."))
+        );
+    }
+
+    #[test]
+    fn test_generator_next() {
+        check_output_default(
+            "fun count(n) {\n\
+               print \"start\";\n\
+               for (var i = 0; i < n; i = i + 1) yield i;\n\
+             }\n\
+             var g = count(2);\n\
+             print \"created\";\n\
+             print g.next();\n\
+             print g.next();\n\
+             print g.next();\n\
+             print g.next();",
+            &vec_of_strings!["created", "start", "0", "1", "nil", "nil"]
+        );
+    }
+
+    #[test]
+    fn test_generator_resumes_nested_statements() {
+        check_output_default(
+            "fun gen() {\n\
+               var total = 0;\n\
+               while (true) {\n\
+                 total = total + 1;\n\
+                 if (total > 4) return;\n\
+                 {\n\
+                   var doubled = total * 2;\n\
+                   if (total % 2 == 0) {\n\
+                     yield doubled;\n\
+                   } else {\n\
+                     yield -doubled;\n\
+                   }\n\
+                   doubled = doubled + 100;\n\
+                   yield doubled;\n\
+                 }\n\
+               }\n\
+             }\n\
+             for (var x in gen()) print x;",
+            &vec_of_strings!["-2", "102", "4", "104", "-6", "106", "8", "108"]
+        );
+    }
+
+    #[test]
+    fn test_generator_for_in_nested() {
+        check_output_lists(
+            "fun pairs(xs) {\n\
+               for (var x in xs) {\n\
+                 for (var y in xs) {\n\
+                   if (x < y) yield x * 10 + y;\n\
+                 }\n\
+               }\n\
+             }\n\
+             for (var p in pairs([1, 2, 3])) print p;",
+            &vec_of_strings!["12", "13", "23"]
+        );
+    }
+
+    #[test]
+    fn test_generator_methods_and_iter_protocol() {
+        check_output_lists(
+            "class Bag {\n\
+               init(items) { this.items = items; }\n\
+               iter() {\n\
+                 for (var item in this.items) yield item;\n\
+               }\n\
+             }\n\
+             for (var x in Bag([3, 1, 2])) print x;\n\
+             var g = Bag([7]).iter();\n\
+             var next = g.next;\n\
+             print next();\n\
+             print next();",
+            &vec_of_strings!["3", "1", "2", "7", "nil"]
+        );
+    }
+
+    #[test]
+    fn test_generator_closure_survives_yield() {
+        check_output_default(
+            "fun counter() {\n\
+               var n = 0;\n\
+               fun bump() { n = n + 1; return n; }\n\
+               while (true) {\n\
+                 yield bump;\n\
+                 yield n;\n\
+               }\n\
+             }\n\
+             var g = counter();\n\
+             var bump = g.next();\n\
+             print bump();\n\
+             print bump();\n\
+             print g.next();\n\
+             g.next();\n\
+             print bump();\n\
+             print g.next();",
+            &vec_of_strings!["1", "2", "2", "3", "3"]
+        );
+    }
+
+    #[test]
+    fn test_infinite_generator_with_break() {
+        check_output_default(
+            "fun naturals() {\n\
+               var n = 0;\n\
+               while (true) {\n\
+                 yield n;\n\
+                 n = n + 1;\n\
+               }\n\
+             }\n\
+             for (var n in naturals()) {\n\
+               if (n == 3) break;\n\
+               print n;\n\
+             }",
+            &vec_of_strings!["0", "1", "2"]
+        );
+    }
+
+    #[test]
+    fn test_lazy_builtins() {
+        check_output(
+            "for (var x in range(0, 3)) print x;\n\
+             var evens = filter(lambda(x) { return x % 2 == 0; }, range(0, 100000000));\n\
+             var squares = map(lambda(x) { return x * x; }, evens);\n\
+             print squares.next();\n\
+             print squares.next();\n\
+             print squares.next();\n\
+             print filter(lambda(x) { return x > 1; }, [1, 2, 3]);\n\
+             print map(lambda(x) { return x + 1; }, [1, 2]);",
+            extensions::Extensions {
+                lists: true,
+                lambdas: true,
+            },
+            &vec_of_strings!["0", "1", "2", "0", "4", "16", "[2, 3]", "[2, 3]"]
+        );
+    }
+
+    #[test]
+    fn test_nil_elements_do_not_end_iteration() {
+        check_output(
+            "fun gen() { yield 1; yield nil; yield 3; }\n\
+             for (var x in gen()) print x;\n\
+             for (var x in map(lambda(x) { if (x != 2) return x; }, range(1, 4))) print x;",
+            extensions::Extensions {
+                lists: true,
+                lambdas: true,
+            },
+            &vec_of_strings!["1", "nil", "3", "1", "nil", "3"]
+        );
+    }
+
+    #[test]
+    fn test_generator_already_running() {
+        check_error_default(
+            "fun gen() { yield g.next(); }\n\
+             var g = gen();\n\
+             g.next();",
+            &(|err: &str| assert!(err.starts_with("generator gen is already running")))
+        );
+    }
+
+    #[test]
+    fn test_invalid_yield() {
+        check_error_default(
+            "yield 1;",
+            &(|err: &str| assert_eq!(err, "Cannot yield from top-level code."))
+        );
+        check_error_default(
+            "fun f() { yield 1; return 2; }",
+            &(|err: &str| assert_eq!(err, "Cannot return a value from a generator."))
+        );
+        check_error_default(
+            "class A { init() { yield 1; } }",
+            &(|err: &str| assert_eq!(err, "Cannot yield from an initializer."))
+        );
+    }
+
+    /* ---------- ---------- fiber 和通道 ---------- ---------- */
+
+    fn check_fibers(code: &str, expected_output: &[String]) {
+        check_output(code, extensions::Extensions { lists: true, lambdas: true }, expected_output);
+    }
+
+    fn check_fibers_error(code: &str, f: &dyn Fn(&str)) {
+        check_error(code, extensions::Extensions { lists: true, lambdas: true }, f);
+    }
+
+    #[test]
+    fn test_fibers_round_robin() {
+        check_fibers(
+            "fun worker(name) {\n\
+               for (var i = 0; i < 3; i = i + 1) { print \"${name} ${i}\"; yield; }\n\
+             }\n\
+             spawn worker(\"a\");\n\
+             spawn worker(\"b\");\n\
+             print \"main\";",
+            &vec_of_strings!["main", "a 0", "b 0", "a 1", "b 1", "a 2", "b 2"]
+        );
+    }
+
+    #[test]
+    fn test_channel_pipeline() {
+        check_fibers(
+            "fun produce(out) { for (var i = 1; i < 6; i = i + 1) out.send(i); out.close(); }\n\
+             fun square(inp, out) { for (var x in inp) out.send(x * x); out.close(); }\n\
+             var numbers = Channel();\n\
+             var squares = Channel();\n\
+             spawn produce(numbers);\n\
+             spawn square(numbers, squares);\n\
+             var total = 0;\n\
+             for (var x in squares) total = total + x;\n\
+             print total;",
+            &vec_of_strings!["55"]
+        );
+    }
+
+    #[test]
+    fn test_channel_sends_nil() {
+        check_fibers(
+            "fun produce(out) { out.send(1); out.send(nil); out.send(3); out.close(); }\n\
+             var ch = Channel();\n\
+             spawn produce(ch);\n\
+             for (var x in ch) print x;\n\
+             var buffered = Channel(3);\n\
+             buffered.send(nil);\n\
+             buffered.send(2);\n\
+             buffered.close();\n\
+             for (var x in buffered) print x;",
+            &vec_of_strings!["1", "nil", "3", "nil", "2"]
+        );
+    }
+
+    #[test]
+    fn test_buffered_channel_blocks_when_full() {
+        check_fibers(
+            "var ch = Channel(2);\n\
+             fun producer() {\n\
+               for (var i = 0; i < 4; i = i + 1) { ch.send(i); print \"sent ${i}\"; }\n\
+               ch.close();\n\
+             }\n\
+             spawn producer();\n\
+             yield;\n\
+             print \"main resumes\";\n\
+             print ch.recv();\n\
+             print ch.recv();\n\
+             print ch.recv();\n\
+             print ch.recv();\n\
+             print ch.recv();",
+            &vec_of_strings![
+                "sent 0",
+                "sent 1",
+                "main resumes",
+                "0",
+                "1",
+                "2",
+                "sent 2",
+                "sent 3",
+                "3",
+                "nil"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_fibers_share_captured_locals() {
+        // count 还在 f 的栈上，别的 fiber 通过上值改它
+        check_fibers(
+            "fun f() {\n\
+               var count = 0;\n\
+               fun bump(n) { for (var i = 0; i < n; i = i + 1) { count = count + 1; yield; } }\n\
+               spawn bump(3);\n\
+               spawn bump(2);\n\
+               count = count + 100;\n\
+               for (var i = 0; i < 10; i = i + 1) yield;\n\
+               return count;\n\
+             }\n\
+             print f();",
+            &vec_of_strings!["105"]
+        );
+    }
+
+    #[test]
+    fn test_fibers_left_blocked_when_main_finishes() {
+        check_fibers(
+            "var ch = Channel();\n\
+             spawn lambda() { print ch.recv(); }();\n\
+             print \"done\";",
+            &vec_of_strings!["done"]
+        );
+    }
+
+    #[test]
+    fn test_channel_values_survive_gc() {
+        // 暂停着的 fiber 栈上的字符串、通道里的值都不能被回收
+        check_fibers(
+            "var ch = Channel(1);\n\
+             fun producer() {\n\
+               var keep = \"x\";\n\
+               for (var i = 0; i < 12; i = i + 1) keep = keep + keep;\n\
+               for (var i = 0; i < 400; i = i + 1) ch.send([keep + \"${i}\"]);\n\
+               ch.close();\n\
+             }\n\
+             spawn producer();\n\
+             var total = 0;\n\
+             for (var item in ch) total = total + len(item[0]);\n\
+             print total;",
+            &vec_of_strings!["1639490"]
+        );
+    }
+
+    #[test]
+    fn test_spawn_method_call() {
+        check_fibers(
+            "class Worker {\n\
+               init(name) { this.name = name; }\n\
+               run(n) { for (var i = 0; i < n; i = i + 1) { print \"${this.name} ${i}\"; yield; } }\n\
+             }\n\
+             var w = Worker(\"w\");\n\
+             spawn w.run(2);\n\
+             print \"main\";",
+            &vec_of_strings!["main", "w 0", "w 1"]
+        );
+    }
+
+    #[test]
+    fn test_spawn_named_arguments() {
+        check_fibers(
+            "fun greet(greeting = \"hi\", name = \"you\") { print \"${greeting} ${name}\"; }\n\
+             class Greeter {\n\
+               greet(greeting = \"hello\", name = \"there\") { print \"${greeting} ${name}\"; }\n\
+             }\n\
+             spawn greet(name: \"ada\");\n\
+             spawn Greeter().greet(name: \"bob\", greeting: \"hey\");\n\
+             print \"main\";",
+            &vec_of_strings!["main", "hi ada", "hey bob"]
+        );
+    }
+
+    #[test]
+    fn test_fiber_errors() {
+        check_fibers_error(
+            "var ch = Channel();\nch.recv();",
+            &(|err: &str| assert_eq!(err, "Deadlock: all fibers are blocked."))
+        );
+        check_fibers_error(
+            "var ch = Channel(1);\nch.close();\nch.send(1);",
+            &(|err: &str| assert_eq!(err, "Send on a closed channel."))
+        );
+        check_fibers_error(
+            "fun bad() { return nil + 1; }\nspawn bad();\nprint 1;",
+            &(|err: &str| assert!(err.starts_with("invalid operands"), "{}", err))
+        );
+        check_fibers_error(
+            "var ch = Channel();\nmap(lambda(x) { return ch.recv(); }, [1]);",
+            &(|err: &str| assert_eq!(err, "When calling map: Cannot switch fibers inside a native call."))
+        );
+        check_fibers_error(
+            "fun f() {}\nspawn f;",
+            &(|err: &str| assert_eq!(err, "Expected a function call after spawn."))
+        );
+        check_fibers_error(
+            "class A {}\nspawn A().missing();\nprint 1;",
+            &(|err: &str| assert_eq!(err, "Undefined property missing."))
+        );
+    }
+
+    /* ---------- ---------- 事件循环 ---------- ---------- */
+
+    #[test]
+    fn test_timers_fire_in_order() {
+        check_fibers(
+            "setTimeout(lambda() { print \"b 20\"; }, 20);\n\
+             setTimeout(lambda() { print \"a 10\"; }, 10);\n\
+             setTimeout(lambda() { print \"c 10\"; }, 10);\n\
+             var cancelled = setTimeout(lambda() { print \"never\"; }, 5);\n\
+             clearTimeout(cancelled);\n\
+             print \"main\";",
+            &vec_of_strings!["main", "a 10", "c 10", "b 20"]
+        );
+    }
+
+    #[test]
+    fn test_set_interval() {
+        check_fibers(
+            "var n = 0;\n\
+             var id = setInterval(lambda() {\n\
+               n = n + 1;\n\
+               print \"tick ${n} at ${clock()}\";\n\
+               if (n == 3) clearInterval(id);\n\
+             }, 100);",
+            &vec_of_strings!["tick 1 at 100", "tick 2 at 200", "tick 3 at 300"]
+        );
+    }
+
+    #[test]
+    fn test_sleep_interleaves_fibers() {
+        check_fibers(
+            "fun worker(name, delay) {\n\
+               for (var i = 0; i < 2; i = i + 1) { sleep(delay); print \"${name} ${clock()}\"; }\n\
+             }\n\
+             spawn worker(\"slow\", 30);\n\
+             spawn worker(\"fast\", 20);\n\
+             sleep(100);\n\
+             print \"main ${clock()}\";",
+            &vec_of_strings!["fast 20", "slow 30", "fast 40", "slow 60", "main 100"]
+        );
+    }
+
+    #[test]
+    fn test_async_await() {
+        check_fibers(
+            "async fun fetch(x, delay) { sleep(delay); return x * 2; }\n\
+             async fun both() {\n\
+               var a = fetch(1, 50);\n\
+               var b = fetch(2, 10);\n\
+               return await a + await b;\n\
+             }\n\
+             var p = both();\n\
+             print p;\n\
+             print await p;\n\
+             print clock();\n\
+             print p;\n\
+             print await p;\n\
+             print await 7;",
+            &vec_of_strings!["<promise pending>", "6", "50", "<promise resolved>", "6", "7"]
+        );
+    }
+
+    #[test]
+    fn test_event_loop_errors() {
+        check_fibers_error(
+            "async fun f() { return Channel().recv(); }\nawait f();",
+            &(|err: &str| assert_eq!(err, "Deadlock: all fibers are blocked."))
+        );
+        check_fibers_error(
+            "async fun f() { return 1; }\n\
+             var p = f();\n\
+             map(lambda(x) { return await p; }, [1]);",
+            &(|err: &str| assert_eq!(err, "When calling map: Cannot switch fibers inside a native call."))
+        );
+        check_fibers_error(
+            "async fun f() { yield 1; }",
+            &(|err: &str| assert_eq!(err, "Cannot yield from an async function."))
+        );
+        check_fibers_error(
+            "sleep(-1);",
+            &(|err: &str| assert!(err.contains("non-negative number of milliseconds"), "{}", err))
+        );
+    }
+
+    #[test]
+    fn test_list_mutation() {
+        check_output_lists(
+            "var xs = [1, 2];\n\
+             push(xs, 3);\n\
+             print xs;\n\
+             print pop(xs);\n\
+             insert(xs, 0, 0);\n\
+             insert(xs, -1, 9);\n\
+             print xs;\n\
+             print remove(xs, 2);\n\
+             print xs;\n\
+             print contains(xs, 2);\n\
+             print contains(xs, 5);\n\
+             print indexOf(xs, 1);\n\
+             print indexOf(xs, 5);\n\
+             print concat(xs, [7]);\n\
+             print reverse(xs);\n\
+             print xs;",
+            &vec_of_strings![
+                "[1, 2, 3]",
+                "3",
+                "[0, 1, 9, 2]",
+                "9",
+                "[0, 1, 2]",
+                "true",
+                "false",
+                "1",
+                "-1",
+                "[0, 1, 2, 7]",
+                "[2, 1, 0]",
+                "[0, 1, 2]"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_reduce_and_sort() {
+        check_output(
+            "var xs = [3, 1, 2];\n\
+             print reduce(lambda(acc, x) { return acc + x; }, xs, 0);\n\
+             print sort(xs);\n\
+             print sort(xs, lambda(a, b) { return b - a; });\n\
+             print xs;\n\
+             print sort([\"pear\", \"apple\"]);\n\
+             var pairs = [[1, \"b\"], [0, \"a\"], [1, \"a\"]];\n\
+             print sort(pairs, lambda(p, q) { return p[0] - q[0]; });",
+            extensions::Extensions {
+                lists: true,
+                lambdas: true,
+            },
+            &vec_of_strings![
+                "6",
+                "[1, 2, 3]",
+                "[3, 2, 1]",
+                "[3, 1, 2]",
+                "[apple, pear]",
+                "[[0, a], [1, b], [1, a]]"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_list_slices() {
+        check_output_lists(
+            "var xs = [0, 1, 2, 3, 4];\n\
+             print xs[1:3];\n\
+             print xs[:2];\n\
+             print xs[3:];\n\
+             print xs[:];\n\
+             print xs[-2:];\n\
+             print xs[3:1];\n\
+             print xs[2:100];",
+            &vec_of_strings![
+                "[1, 2]",
+                "[0, 1]",
+                "[3, 4]",
+                "[0, 1, 2, 3, 4]",
+                "[3, 4]",
+                "[]",
+                "[2, 3, 4]"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_list_library_errors() {
+        let lists = extensions::Extensions {
+            lists: true,
+            ..Default::default()
+        };
+        check_error(
+            "pop([]);",
+            lists,
+            &(|err: &str| assert_eq!(err, "When calling pop: Can't pop from an empty list."))
+        );
+        check_error(
+            "sort([1, \"a\"]);",
+            lists,
+            &(|err: &str| assert_eq!(err, "When calling sort: Can't compare values of type Integer and String in sort."))
+        );
+        check_error(
+            "remove([1], 1);",
+            lists,
+            &(|err: &str| assert_eq!(err, "When calling remove: List index out of range in remove."))
+        );
+        check_error(
+            "push(1, 2);",
+            lists,
+            &(|err: &str| assert_eq!(err, "When calling push: Can't call push on value of type Integer."))
+        );
+        check_error(
+            "sort([1], 2);",
+            lists,
+            &(|err: &str| assert_eq!(err, "When calling sort: The second argument to sort must be callable. Found Integer."))
+        );
+        check_error(
+            "insert([1, 2], 1.5, 0);",
+            lists,
+            &(|err: &str| assert_eq!(err, "When calling insert: Invalid index 1.5 in insert."))
+        );
+        check_error(
+            "var xs = [1, 2]; print xs[1.0]; print xs[1.7];",
+            lists,
+            &(|err: &str| assert_eq!(err, "Invalid subscript 1.7 in subscript expression"))
+        );
+        check_error(
+            "var xs = [1, 2]; print xs[0.5:];",
+            lists,
+            &(|err: &str| assert_eq!(err, "Invalid slice bound 0.5 in slice expression at line 1"))
+        );
+        check_error(
+            "sort([], nil, nil);",
+            lists,
+            &(|err: &str| assert_eq!(err, "Native function sort expected 1-2 arguments but found 3."))
+        );
+        check_error(
+            "var xs = [1]; xs[0:1] = 2;",
+            lists,
+            &(|err: &str| assert_eq!(err, "Invalid assignment target."))
+        );
+    }
+
+    #[test]
+    fn test_unicode_source() {
+        check_output_default(
+            "var 问候 = \"你好\\t世界\";\n\
+             /* 块注释 */\n\
+             print 问候;\n\
+             print len(问候);\n\
+             print 0xff + 1_000;",
+            &vec_of_strings!["你好\t世界", "5", "1255"]
+        );
+    }
+
+    #[test]
+    fn test_unterminated_string_is_an_error() {
+        check_error_default(
+            "print \"abc;",
+            &(|err: &str| assert_eq!(err, "Unterminated string"))
+        );
+    }
+
+    #[test]
+    fn test_string_interpolation() {
+        check_output(
+            "var name = \"bob\";\n\
+             var items = [1, 2, 3];\n\
+             print \"hello ${name}, you have ${len(items)} items\";\n\
+             print \"${1 + 2}${nil} ${true}\";\n\
+             print \"outer ${\"inner ${name + \"!\"}\"} done\";\n\
+             print \"not \\${name}\";\n\
+             var f = lambda(x) { return x * 2; };\n\
+             print \"\"\"say \"${f(21)}\" now\"\"\";\n\
+             print \"${items}\";",
+            extensions::Extensions {
+                lists: true,
+                lambdas: true,
+            },
+            &vec_of_strings![
+                "hello bob, you have 3 items",
+                "3nil true",
+                "outer inner bob! done",
+                "not ${name}",
+                "say \"42\" now",
+                "[1, 2, 3]"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_integer_arithmetic() {
+        check_output_default(
+            "print 9007199254740993 + 2;\n\
+             print 7 / 2;\n\
+             print 7 ~/ 2;\n\
+             print -7 ~/ 2;\n\
+             print -7 % 2;\n\
+             print 2 ** 62;\n\
+             print 2 ** -1;\n\
+             print 1 + 0.5;\n\
+             print 3 == 3.0;\n\
+             print 2 < 2.5;\n\
+             print 6.0 & 3;",
+            &vec_of_strings![
+                "9007199254740995",
+                "3.5",
+                "3",
+                "-4",
+                "-1",
+                "4611686018427387904",
+                "0.5",
+                "1.5",
+                "true",
+                "true",
+                "2"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_integer_overflow() {
+        check_error_default(
+            "print 9223372036854775807 + 1;",
+            &(|err: &str| assert!(err.starts_with("integer overflow in Add expression")))
+        );
+        check_error_default(
+            "print 2 ** 63;",
+            &(|err: &str| assert!(err.starts_with("integer overflow in Pow expression")))
+        );
+        check_error_default(
+            "print 7 % 0;",
+            &(|err: &str| assert!(err.starts_with("integer division by zero in Mod expression")))
+        );
+    }
+
+    #[test]
+    fn test_operator_overloading() {
+        check_output_lists(
+            "class Vec {\n\
+               init(x, y) { this.x = x; this.y = y; }\n\
+               __add__(other) { return Vec(this.x + other.x, this.y + other.y); }\n\
+               __sub__(other) { return Vec(this.x - other.x, this.y - other.y); }\n\
+               __mul__(k) { return Vec(this.x * k, this.y * k); }\n\
+               __eq__(other) { return this.x == other.x and this.y == other.y; }\n\
+               __lt__(other) { return this.x + this.y < other.x + other.y; }\n\
+               __str__() { return \"Vec(${this.x}, ${this.y})\"; }\n\
+             }\n\
+             var a = Vec(1, 2);\n\
+             var b = Vec(3, 4);\n\
+             print a + b;\n\
+             print b - a;\n\
+             print a * 3;\n\
+             print a == Vec(1, 2);\n\
+             print a != b;\n\
+             print a < b;\n\
+             print a > b;\n\
+             print a <= a;\n\
+             print b >= a;\n\
+             a += b;\n\
+             print [a, b];\n\
+             print \"sum is ${a + b}\";",
+            &vec_of_strings![
+                "Vec(4, 6)",
+                "Vec(2, 2)",
+                "Vec(3, 6)",
+                "true",
+                "true",
+                "true",
+                "false",
+                "true",
+                "true",
+                "[Vec(4, 6), Vec(3, 4)]",
+                "sum is Vec(7, 10)"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_container_special_methods() {
+        check_output_lists("class Bag {\n\
+               __getitem__(i) { return this.items[i]; }\n\
+               __setitem__(i, v) { this.items[i] = v; }\n\
+               __len__() { return len(this.items); }\n\
+               __call__(x) { return x * 10; }\n\
+             }\n\
+             class Sub < Bag {}\n\
+             var bag = Sub();\n\
+             bag.items = [0, 0, 0];\n\
+             bag[1] = 5;\n\
+             bag[0] += 2;\n\
+             print bag[0] + bag[1];\n\
+             print len(bag);\n\
+             print bag(4);", &vec_of_strings!["7", "3", "40"]);
+    }
+
+    #[test]
+    fn test_str_must_return_string() {
+        check_error_default(
+            "class A { __str__() { return 1; } }\n\
+             print A();",
+            &(|err: &str| assert!(err.contains("__str__ must return a string. Found Integer.")))
+        );
+    }
+
+    #[test]
+    fn test_comparison_methods_must_return_bool() {
+        // != <= >= 要把结果取反，两个引擎都要求 __eq__ 和 __lt__ 返回 bool
+        check_error_default(
+            "class A { __eq__(other) { return 1; } }\n\
+             print A() != A();",
+            &(|err: &str| assert_eq!(err, "__eq__ must return a bool. Found Integer."))
+        );
+        check_error_default(
+            "class A { __eq__(other) { return nil; } }\n\
+             print A() == A();",
+            &(|err: &str| assert_eq!(err, "__eq__ must return a bool. Found Nil."))
+        );
+        check_error_default(
+            "class A { __lt__(other) { return \"yes\"; } }\n\
+             print A() <= A();",
+            &(|err: &str| assert_eq!(err, "__lt__ must return a bool. Found String."))
+        );
+        check_output_default(
+            "class A { __eq__(other) { return false; } __lt__(other) { return true; } }\n\
+             print A() != A();\n\
+             print A() >= A();",
+            &vec_of_strings!["true", "false"]
+        );
+    }
+
+    #[test]
+    fn test_class_methods_getters_setters() {
+        check_output_default(
+            "class Shape {\n\
+               init(size) { this.size = size; }\n\
+               class unit() { return this(1); }\n\
+               class describe() { return \"shape\"; }\n\
+               area { return this.size * this.size; }\n\
+               set side(v) { this.size = v; }\n\
+             }\n\
+             class Square < Shape {}\n\
+             var s = Shape.unit();\n\
+             print s.area;\n\
+             s.side = 3;\n\
+             print s.area;\n\
+             print s.size;\n\
+             print Square.describe();\n\
+             var describe = Square.describe;\n\
+             print describe();",
+            &vec_of_strings!["1", "9", "3", "shape", "shape"]
+        );
+    }
+
+    #[test]
+    fn test_super_getter() {
+        check_output_default(
+            "class Named {\n\
+               name { return this.first; }\n\
+             }\n\
+             class Titled < Named {\n\
+               name { return \"Dr. \" + super.name; }\n\
+               plain() { return super.name; }\n\
+             }\n\
+             class Formal < Titled {\n\
+               name { return super.name + \", PhD\"; }\n\
+             }\n\
+             var t = Titled();\n\
+             t.first = \"Ada\";\n\
+             print t.name;\n\
+             print t.plain();\n\
+             var f = Formal();\n\
+             f.first = \"Ada\";\n\
+             print f.name;",
+            &vec_of_strings!["Dr. Ada", "Ada", "Dr. Ada, PhD"]
+        );
+    }
+
+    #[test]
+    fn test_getter_result_is_callable() {
+        check_output_default(
+            "class Counter {\n\
+               init() { this.n = 0; }\n\
+               bump { var self = this; fun f() { self.n = self.n + 1; return self.n; } return f; }\n\
+             }\n\
+             var c = Counter();\n\
+             c.bump();\n\
+             print c.bump();",
+            &vec_of_strings!["2"]
+        );
+    }
+
+    #[test]
+    fn test_instanceof() {
+        check_output_default(
+            "class A {}\n\
+             class B < A {}\n\
+             class C {}\n\
+             var b = B();\n\
+             print b instanceof B;\n\
+             print b instanceof A;\n\
+             print b instanceof C;\n\
+             print A() instanceof B;\n\
+             print 1 instanceof A;\n\
+             print isInstance(b, A);\n\
+             print isInstance(nil, A);",
+            &vec_of_strings!["true", "true", "false", "false", "false", "true", "false"]
+        );
+    }
+
+    #[test]
+    fn test_instanceof_non_class() {
+        check_error_default(
+            "class A {}\n\
+             print A() instanceof 1;",
+            &(|err: &str| {
+                assert!(err.contains("Can't check instanceof against a value of type Integer."))
+            })
+        );
+    }
+
+    #[test]
+    fn test_setter_arity() {
+        check_error_default(
+            "class A { set x(a, b) {} }",
+            &(|err: &str| assert!(err.contains("A setter must take exactly one parameter.")))
+        );
+    }
+
+    #[test]
+    fn test_traits() {
+        check_output_default(
+            "trait Greets {\n\
+               greet() { return \"hello from \" + this.name(); }\n\
+               name();\n\
+             }\n\
+             trait Shouts {\n\
+               shout() { return this.greet() + \"!\"; }\n\
+             }\n\
+             class Base { name() { return \"base\"; } }\n\
+             class Dog < Base with Greets, Shouts {}\n\
+             class Puppy < Dog { name() { return \"puppy\"; } }\n\
+             print Dog().greet();\n\
+             print Puppy().shout();\n\
+             print Puppy() instanceof Greets;\n\
+             print isInstance(Dog(), Shouts);\n\
+             print Base() instanceof Greets;",
+            &vec_of_strings!["hello from base", "hello from puppy!", "true", "true", "false"]
+        );
+    }
+
+    #[test]
+    fn test_trait_method_resolution_order() {
+        check_output_default(
+            "trait Loud { speak() { return \"LOUD\"; } }\n\
+             class Animal { speak() { return \"animal\"; } }\n\
+             class Cat < Animal with Loud {}\n\
+             class Lion < Animal with Loud { speak() { return \"roar\"; } }\n\
+             trait A { f() { return \"A\"; } }\n\
+             trait B { f() { return \"B\"; } }\n\
+             class C with A, B { f() { return \"C\"; } }\n\
+             print Cat().speak();\n\
+             print Lion().speak();\n\
+             print C().f();",
+            &vec_of_strings!["LOUD", "roar", "C"]
+        );
+    }
+
+    #[test]
+    fn test_trait_init_returns_this() {
+        check_output_default(
+            "trait Counted { init(n) { this.n = n; } }\n\
+             class C with Counted {}\n\
+             var c = C(2);\n\
+             print c instanceof C;\n\
+             print c.n;\n\
+             print c.init(3) instanceof C;\n\
+             print c.n;",
+            &vec_of_strings!["true", "2", "true", "3"]
+        );
+    }
+
+    #[test]
+    fn test_trait_conflict() {
+        check_error_default(
+            "trait A { f() {} }\n\
+             trait B { f() {} }\n\
+             class C with A, B {}",
+            &(|err: &str| {
+                assert!(err.contains("Conflicting method 'f' from traits A and B in class C."))
+            })
+        );
+    }
+
+    #[test]
+    fn test_trait_required_method() {
+        check_error_default(
+            "trait T { g(x); }\n\
+             class C with T {}",
+            &(|err: &str| {
+                assert!(err.contains("Class C must provide method 'g' required by trait T."))
+            })
+        );
+    }
+
+    #[test]
+    fn test_match_literals_and_wildcard() {
+        check_output_default(
+            "fun describe(x) {\n\
+               match (x) {\n\
+                 case 0 => return \"zero\";\n\
+                 case -1 => return \"minus one\";\n\
+                 case \"hi\" => return \"greeting\";\n\
+                 case nil => return \"nothing\";\n\
+                 case _ => return \"other\";\n\
+               }\n\
+             }\n\
+             print describe(0);\n\
+             print describe(-1);\n\
+             print describe(\"hi\");\n\
+             print describe(nil);\n\
+             print describe(true);",
+            &vec_of_strings!["zero", "minus one", "greeting", "nothing", "other"]
+        );
+    }
+
+    #[test]
+    fn test_match_no_arm_matches() {
+        check_output_default(
+            "var x = \"before\";\n\
+             match (3) {\n\
+               case 1 => print \"one\";\n\
+               case 2 => print \"two\";\n\
+             }\n\
+             print x;",
+            &vec_of_strings!["before"]
+        );
+    }
+
+    #[test]
+    fn test_match_list_patterns() {
+        check_output_lists(
+            "fun f(xs) {\n\
+               match (xs) {\n\
+                 case [] => print \"empty\";\n\
+                 case [x] => print x;\n\
+                 case [1, [a, b]] => print a + b;\n\
+                 case [first, _, last] => print first + last;\n\
+                 case _ => print \"no match\";\n\
+               }\n\
+             }\n\
+             f([]);\n\
+             f([7]);\n\
+             f([1, [2, 3]]);\n\
+             f([10, 20, 30]);\n\
+             f([1, 2, 3, 4]);\n\
+             f(5);",
+            &vec_of_strings!["empty", "7", "5", "40", "no match", "no match"]
+        );
+    }
+
+    #[test]
+    fn test_match_instance_and_guard() {
+        check_output_default(
+            "class Point { init(x, y) { this.x = x; this.y = y; } }\n\
+             fun where(p) {\n\
+               match (p) {\n\
+                 case Point(x: 0, y: 0) => print \"origin\";\n\
+                 case Point(x, y: 0) => print x;\n\
+                 case Point(x, y) if x == y => print \"diagonal\";\n\
+                 case Point(x, y) => print x * y;\n\
+                 case other => print other;\n\
+               }\n\
+             }\n\
+             where(Point(0, 0));\n\
+             where(Point(4, 0));\n\
+             where(Point(2, 2));\n\
+             where(Point(2, 3));\n\
+             where(\"else\");",
+            &vec_of_strings!["origin", "4", "diagonal", "6", "else"]
+        );
+    }
+
+    #[test]
+    fn test_match_inside_loop() {
+        check_output_lists(
+            "for (var item in [[1, 2], 3, [4, 5]]) {\n\
+               match (item) {\n\
+                 case [a, b] if a > 3 => break;\n\
+                 case [a, b] => print a + b;\n\
+                 case n => { var doubled = n * 2; print doubled; continue; }\n\
+               }\n\
+               print \"after\";\n\
+             }\n\
+             print \"end\";",
+            &vec_of_strings!["3", "after", "6", "end"]
+        );
+    }
+
+    #[test]
+    fn test_match_missing_field_fails_arm() {
+        check_output_default(
+            "class Shape { area { return 1; } }\n\
+             fun describe(s) {\n\
+               match (s) {\n\
+                 case Shape(radius) => print radius;\n\
+                 case Shape(w, h: 2) => print w;\n\
+                 case Shape(area) => print area;\n\
+               }\n\
+             }\n\
+             var circle = Shape();\n\
+             circle.radius = 3;\n\
+             describe(circle);\n\
+             var rect = Shape();\n\
+             rect.w = 4;\n\
+             rect.h = 2;\n\
+             describe(rect);\n\
+             describe(Shape());",
+            &vec_of_strings!["3", "4", "1"]
+        );
+    }
+
+    #[test]
+    fn test_match_duplicate_binding() {
+        check_error(
+            "match ([1, 2]) { case [x, x] => print x; }",
+            extensions::Extensions {
+                lists: true,
+                ..Default::default()
+            },
+            &(|err: &str| assert!(err.contains("Duplicate binding 'x' in pattern.")))
+        );
+    }
+
+    #[test]
+    fn test_default_parameters() {
+        check_output_default(
+            "var calls = 0;\n\
+             fun next() { calls = calls + 1; return calls; }\n\
+             fun greet(name, greeting = \"hello\") { return greeting + \" \" + name; }\n\
+             fun f(a, b = a * 2, c = next()) { print a + b + c; }\n\
+             print greet(\"bob\");\n\
+             print greet(\"bob\", \"hi\");\n\
+             f(1);\n\
+             f(1, 1);\n\
+             f(1, 1, 100);\n\
+             print calls;",
+            &vec_of_strings!["hello bob", "hi bob", "4", "4", "102", "2"]
+        );
+    }
+
+    #[test]
+    fn test_default_parameters_closures_and_generators() {
+        check_output(
+            "fun make(a, get = lambda() { return a; }) { a = a + 1; return get(); }\n\
+             fun count(to, from = 1) { for (var i = from; i <= to; i = i + 1) yield i; }\n\
+             print make(1);\n\
+             for (var x in count(from: 2, to: 3)) print x;",
+            extensions::Extensions {
+                lambdas: true,
+                ..Default::default()
+            },
+            &vec_of_strings!["2", "2", "3"]
+        );
+    }
+
+    #[test]
+    fn test_rest_parameters() {
+        check_output_default(
+            "fun sum(first, ...rest) {\n\
+               var total = first;\n\
+               for (var x in rest) total = total + x;\n\
+               return total;\n\
+             }\n\
+             fun count(...xs) { return len(xs); }\n\
+             print sum(1);\n\
+             print sum(1, 2, 3);\n\
+             print count();\n\
+             print count(nil, nil);",
+            &vec_of_strings!["1", "6", "0", "2"]
+        );
+    }
+
+    #[test]
+    fn test_named_arguments() {
+        check_output_default(
+            "fun volume(width, height = 1, depth = 1) { return width * height * depth; }\n\
+             class Point {\n\
+               init(x = 0, y = 0) { this.x = x; this.y = y; }\n\
+               moved(dx = 0, dy = 0) { return Point(x: this.x + dx, y: this.y + dy); }\n\
+             }\n\
+             class Point3 < Point {\n\
+               moved(dx = 0, dy = 0) { return super.moved(dy: dy * 10, dx: dx); }\n\
+             }\n\
+             print volume(2, depth: 3);\n\
+             print volume(height: 2, width: 5);\n\
+             var p = Point(y: 5).moved(dy: 1);\n\
+             print p.x;\n\
+             print p.y;\n\
+             var q = Point3(1, 1).moved(dy: 2);\n\
+             print q.y;",
+            &vec_of_strings!["6", "10", "0", "6", "21"]
+        );
+    }
+
+    #[test]
+    fn test_argument_errors() {
+        check_error_default(
+            "fun f(a, b, c = 1) {}\nf(c: 2);",
+            &(|err: &str| assert!(err.contains("Missing arguments for f: a, b."), "{}", err))
+        );
+        check_error_default(
+            "fun f(a, b = 1) {}\nf(1, 2, 3);",
+            &(|err: &str| assert!(err.contains("Expected 1-2 arguments but found 3.")))
+        );
+        check_error_default(
+            "fun f(a) {}\nf(1, b: 2);",
+            &(|err: &str| assert!(err.contains("f has no parameter named 'b'.")))
+        );
+        check_error_default(
+            "fun f(a) {}\nf(1, a: 2);",
+            &(|err: &str| assert!(err.contains("Got multiple values for argument 'a' in call to f.")))
+        );
+        check_error_default(
+            "clock(x: 1);",
+            &(|err: &str| assert!(err.contains("Native function clock does not take named arguments.")))
+        );
+        check_error_default(
+            "fun f(a = 1, b) {}",
+            &(|err: &str| {
+                assert!(
+                    err.contains(
+                        "Parameter 'b' without a default cannot follow a parameter with a default."
+                    )
+                )
+            })
+        );
+        check_error_default(
+            "fun f(...a, b) {}",
+            &(|err: &str| assert!(err.contains("Rest parameter must be the last parameter.")))
+        );
+        check_error_default(
+            "fun f(a, b) {}\nf(a: 1, 2);",
+            &(|err: &str| assert!(err.contains("Positional argument cannot follow named arguments.")))
+        );
+    }
+
+    #[test]
+    fn test_const_declarations() {
+        check_output_default(
+            "const x = 1;\n\
+             let y = 2;\n\
+             fun f() { return x + y; }\n\
+             fun g() {\n\
+               const z = 3;\n\
+               {\n\
+                 var z = 10;\n\
+                 z = z + 1;\n\
+                 print z;\n\
+               }\n\
+               fun h() { return z; }\n\
+               return h();\n\
+             }\n\
+             print f();\n\
+             print g();",
+            &vec_of_strings!["3", "11", "3"]
+        );
+    }
+
+    #[test]
+    fn test_const_errors() {
+        check_error_default(
+            "const x = 1;\nx = 2;",
+            &(|err: &str| assert!(err.contains("Cannot assign to constant x."), "{}", err))
+        );
+        check_error_default(
+            "fun f() {\n  let x = 1;\n  fun g() { x += 1; }\n}",
+            &(|err: &str| assert!(err.contains("Cannot assign to constant x."), "{}", err))
+        );
+        check_error_default(
+            "fun f() {\n  const x = 1;\n  ++x;\n}",
+            &(|err: &str| assert!(err.contains("Cannot assign to constant x."), "{}", err))
+        );
+        check_error_default(
+            "const x = 1;\nx--;",
+            &(|err: &str| assert!(err.contains("Cannot assign to constant x."), "{}", err))
+        );
+        check_error_default(
+            "const x = 1;\nfun x() {}",
+            &(|err: &str| assert!(err.contains("Cannot redefine constant x."), "{}", err))
+        );
+        check_error_default(
+            "const x;",
+            &(|err: &str| assert!(err.contains("Constants must be initialized."), "{}", err))
+        );
+        // 函数编译的时候还不知道 x 是常量，留给运行时检查
+        check_error_default(
+            "fun f() { x = 2; }\nconst x = 1;\nf();",
+            &(|err: &str| assert!(err.contains("Cannot assign to constant x at line 1."), "{}", err))
+        );
+    }
+
+    #[test]
+    fn test_freeze() {
+        check_output_lists(
+            "class Point { init(x, y) { this.x = x; this.y = y; } }\n\
+             var p = freeze(Point(1, 2));\n\
+             var xs = freeze([1, 2, 3]);\n\
+             print p.x + p.y;\n\
+             print xs[1];\n\
+             print reverse(xs);",
+            &vec_of_strings!["3", "2", "[3, 2, 1]"]
+        );
+        check_error(
+            "class Point { init(x) { this.x = x; } }\nvar p = freeze(Point(1));\np.x = 2;",
+            extensions::Extensions { lists: true, ..Default::default() },
+            &(|err: &str| assert!(err.contains("Can't set attribute x on a frozen instance."), "{}", err))
+        );
+        check_error(
+            "var xs = freeze([1, 2]);\nxs[0] = 3;",
+            extensions::Extensions { lists: true, ..Default::default() },
+            &(|err: &str| assert!(err.contains("Can't modify a frozen list."), "{}", err))
+        );
+        check_error(
+            "var xs = freeze([1, 2]);\npush(xs, 3);",
+            extensions::Extensions { lists: true, ..Default::default() },
+            &(|err: &str| assert_eq!(err, "When calling push: Can't call push on a frozen list."))
+        );
+        check_error(
+            "freeze(1);",
+            extensions::Extensions { lists: true, ..Default::default() },
+            &(|err: &str| assert_eq!(err, "When calling freeze: Can't freeze a value of type Integer."))
+        );
+    }
+
+    #[test]
+    fn test_enums() {
+        check_output_default(
+            "enum Shape { Circle(r), Rect(w, h), Empty }\n\
+             var c = Shape.Circle(1);\n\
+             var r = Shape.Rect(h: 3, w: 2);\n\
+             print c;\n\
+             print r.w * r.h;\n\
+             print Shape.Empty;\n\
+             print tag(r);\n\
+             print c == Shape.Circle(1);\n\
+             print c == Shape.Circle(2);\n\
+             print Shape.Empty == Shape.Empty;\n\
+             print Shape.Empty == Shape.Circle(1);",
+            &vec_of_strings![
+                "Shape.Circle(1)", "6", "Shape.Empty", "Rect", "true", "false", "true", "false"
+            ]
+        );
+        check_output_default(
+            "enum Color { Red, Green, }\n\
+             enum Other { Red }\n\
+             print Color.Red == Other.Red;\n\
+             print Color.Green;",
+            &vec_of_strings!["false", "Color.Green"]
+        );
+    }
+
+    #[test]
+    fn test_enum_errors() {
+        check_error_default(
+            "enum Shape { Circle(r) }\nShape.Square;",
+            &(|err: &str| assert!(err.contains("Enum Shape has no variant Square."), "{}", err))
+        );
+        check_error_default(
+            "enum Shape { Circle(r) }\nShape.Circle(1).x;",
+            &(|err: &str| assert!(err.contains("Variant Shape.Circle has no field x."), "{}", err))
+        );
+        check_error_default(
+            "enum Shape { Circle(r) }\nShape.Circle(1, 2);",
+            &(|err: &str| assert!(err.contains("Expected 1 arguments"), "{}", err))
+        );
+        check_error_default(
+            "enum Shape { Circle(r), Circle }",
+            &(|err: &str| assert!(err.contains("Duplicate variant Circle in enum Shape."), "{}", err))
+        );
+        check_error_default(
+            "enum Shape { Rect(w, w) }",
+            &(|err: &str| assert!(err.contains("Duplicate field w in variant Rect."), "{}", err))
+        );
+        check_error_default(
+            "tag(1);",
+            &(|err: &str| assert_eq!(err, "When calling tag: Can't get the tag of a value of type Integer."))
+        );
+    }
+
+    #[test]
+    fn test_inline_caches() {
+        // 同一条指令先后见到不同的类，缓存要跟着换
+        check_output_default(
+            "class A { name() { return \"A\"; } }\n\
+             class B < A { name() { return \"B\"; } }\n\
+             class C < A {}\n\
+             fun show(x) { print x.name(); var f = x.name; print f(); }\n\
+             show(A()); show(B()); show(C()); show(A());",
+            &vec_of_strings!["A", "A", "B", "B", "A", "A", "A", "A"]
+        );
+        // 缓存里是方法，实例上后来加的同名字段还是优先
+        check_output_default(
+            "class A { f() { return 1; } }\n\
+             fun two() { return 2; }\n\
+             fun call(a) { return a.f(); }\n\
+             var a = A();\n\
+             print call(a);\n\
+             a.f = two;\n\
+             print call(a);\n\
+             print call(A());",
+            &vec_of_strings!["1", "2", "1"]
+        );
+        // getter、setter 和普通字段在同一条指令上交替出现
+        check_output_default(
+            "class P { init() { this._x = 0; } x { return this._x + 100; } set x(v) { this._x = v; } }\n\
+             class Q { init() { this.x = 0; } }\n\
+             fun bump(o) { o.x = 5; return o.x; }\n\
+             print bump(P()); print bump(Q()); print bump(P());",
+            &vec_of_strings!["105", "5", "105"]
+        );
+    }
+
+    #[test]
+    fn test_inline_cache_after_heap_id_reuse() {
+        // 第一次调用 name_of 把 A 记进缓存，A 被回收以后假装 id 绕回来了，
+        // 让 B 拿到同一个 id。B 的 name 前面多了一个方法，缓存里旧的闭包 id 对不上 B 的 name，
+        // 同一个调用点要认出这已经是另一个类了
+        fn run(interp: &mut Interpreter, code: &str) {
+            let func = Compiler::compile(String::from(code), extensions::Extensions::default()).unwrap();
+            assert!(interp.interpret(func).is_ok());
+        }
+        fn cached_class(interp: &Interpreter) -> usize {
+            let name_of = interp.heap.get_closure(interp.globals["name_of"].heap_id().unwrap());
+            match name_of.function.chunk.inline_caches.borrow()[..] {
+                [crate::bytecode::InlineCache::Method { class_id, .. }] => class_id,
+                ref caches => panic!("{:?}", caches),
+            }
+        }
+
+        let mut reused = false;
+        for registers in [false, true] {
+            // B 前面还会分配别的对象，从 A 往前几个位置开始找，总有一次 B 正好落在 A 上面
+            for offset in 0..16 {
+                let mut interp = Interpreter::default();
+                interp.registers = registers;
+                run(
+                    &mut interp,
+                    "fun name_of(o) { return o.name(); }\n\
+                     fun make_a() { class A { name() { return \"A\"; } } return A(); }\n\
+                     fun make_b() { class B { other() { return \"other\"; } name() { return \"B\"; } } return B(); }\n\
+                     print name_of(make_a());"
+                );
+                let first = cached_class(&interp);
+                interp.collect_garbage();
+                interp.heap.wrap_ids_at(first - offset);
+                run(&mut interp, "print name_of(make_b());");
+                assert_eq!(interp.output, vec_of_strings!["A", "B"]);
+                reused |= cached_class(&interp) == first;
+            }
+        }
+        assert!(reused);
+    }
+
+    #[test]
+    fn test_tail_calls() {
+        check_output_default(
+            "fun sum(n, acc) { if (n == 0) return acc; return sum(n - 1, acc + n); }\n\
+             print sum(20000, 0);\n\
+             fun isEven(n) { if (n == 0) return true; return isOdd(n - 1); }\n\
+             fun isOdd(n) { if (n == 0) return false; return isEven(n - 1); }\n\
+             print isEven(20001);\n\
+             fun countdown(n, step = 1) { if (n <= 0) return n; return countdown(step: step, n: n - step); }\n\
+             print countdown(10, 3);\n\
+             fun outer() { var x = 10; fun inner() { return x; } return inner(); }\n\
+             print outer();\n\
+             fun size(s) { return len(s); }\n\
+             print size(\"abc\");\n\
+             fun numbers() { yield 1; yield 2; }\n\
+             fun make() { return numbers(); }\n\
+             var g = make();\n\
+             print g.next() + g.next();",
+            &vec_of_strings!["200010000", "false", "-2", "10", "3", "3"]
+        );
+    }
+
+    #[test]
+    fn test_tail_call_backtrace() {
+        let func = Compiler::compile(
+            String::from(
+                "fun down(n) { if (n == 0) return nil + 1; return down(n - 1); }\n\
+                 fun start() { var x = down(5); return x; }\n\
+                 start();"
+            ),
+            extensions::Extensions::default()
+        ).unwrap();
+        let mut interp = Interpreter::default();
+        assert!(interp.interpret(func).is_err());
+        assert_eq!(interp.frames.len(), 3);
+        assert_eq!(
+            interp.format_backtrace(),
+            "Backtrace (most recent call last):\n\n\
+             [line 3] in script\n\
+             [line 2] in start()\n\
+             ... 5 frames elided by tail calls ...\n\
+             [line 1] in down()"
+        );
+    }
+
+    #[test]
+    fn test_value_is_copy_and_small() {
+        // NaN-boxing 以后 Value 就是一个 u64
+        fn assert_copy<T: Copy>() {}
+        assert_copy::<crate::value::Value>();
+        assert_eq!(std::mem::size_of::<crate::value::Value>(), 8);
+    }
+
+    #[test]
+    fn test_integers_around_the_boxing_limit() {
+        // 2 ** 49 开始放不进 NaN 的负载，要放到堆上，算出来的结果不受影响
+        check_output_lists(
+            "var small = 562949953421311;\n\
+             var big = small + 1;\n\
+             print big;\n\
+             print -big - 1;\n\
+             print big - 1 == small;\n\
+             print big == 2 ** 49;\n\
+             print big > small;\n\
+             var xs = [big, -big];\n\
+             print xs;\n\
+             print xs[1] + big;",
+            &vec_of_strings![
+                "562949953421312",
+                "-562949953421313",
+                "true",
+                "true",
+                "true",
+                "[562949953421312, -562949953421312]",
+                "0"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_big_integers_survive_gc() {
+        // 每次加法都在堆上分配一个大整数，跑够了次数会触发垃圾回收
+        check_output_default(
+            "var keep = 2 ** 62;\n\
+             var total = 0;\n\
+             for (var i = 0; i < 150000; i = i + 1) { total = keep + i; }\n\
+             print total;\n\
+             print keep;",
+            &vec_of_strings!["4611686018427537903", "4611686018427387904"]
+        );
+    }
+
+    #[test]
+    fn test_nan_is_still_a_number() {
+        check_output_default(
+            "var nan = 0.0 / 0.0;\n\
+             print nan;\n\
+             print nan == nan;\n\
+             print -(1.0 / 0.0);",
+            &vec_of_strings!["NaN", "false", "-inf"]
+        );
+    }
+
+    #[test]
+    fn test_too_many_variants() {
+        let variants: Vec<String> = (0..=crate::value::MAX_VARIANTS).map(|i| format!("V{}", i)).collect();
+        check_error_default(
+            &format!("enum Big {{ {} }}", variants.join(", ")),
+            &(|err: &str| assert_eq!(err, "Too many variants in enum Big."))
+        );
+    }
+
+    /* ---------- ---------- JIT ---------- ---------- */
+    // cargo test --features jit 的时候上面所有的测试都强制打开了 JIT，这里再检查确实编译了
+
+    #[cfg(feature = "jit")]
+    fn evaluate_jit(code: &str) -> (Result<Vec<String>, String>, usize) {
+        let func = Compiler::compile(String::from(code), extensions::Extensions::default()).unwrap();
+        let mut interp = Interpreter::default();
+        interp.jit.force();
+        let res = match interp.interpret(func) {
+            Ok(()) => Ok(interp.output.clone()),
+            Err(InterpreterError::Runtime(err)) => Err(err),
+        };
+        (res, interp.jit.compiled_count())
+    }
+
+    #[cfg(feature = "jit")]
+    #[test]
+    fn test_jit_compiles_numeric_functions() {
+        let (res, compiled) = evaluate_jit(
+            "fun fib(n) { if (n < 2) return n; return fib(n - 1) + fib(n - 2); }\n\
+             fun sum(n) { var total = 0; for (var i = 1; i <= n; i = i + 1) total = total + i; return total; }\n\
+             fun less(a, b) { return a < b and !(a == b); }\n\
+             fun greet() { print \"hi\"; }\n\
+             print fib(20);\n\
+             print sum(100);\n\
+             print less(1, 2);\n\
+             greet();"
+        );
+        assert_eq!(res, Ok(vec_of_strings!["6765", "5050", "true", "hi"].to_vec()));
+        // greet 里有 print，不编译
+        assert_eq!(compiled, 3);
+    }
+
+    #[cfg(feature = "jit")]
+    #[test]
+    fn test_jit_deoptimizes() {
+        // 溢出：退回解释器，报解释器自己的错
+        let (res, _) = evaluate_jit(
+            "fun square(n) { return n * n; }\n\
+             print square(3);\n\
+             print square(4611686018427387904);"
+        );
+        assert!(res.unwrap_err().starts_with("integer overflow"));
+
+        // 浮点数参数、递归太深都交给解释器
+        let (res, _) = evaluate_jit(
+            "fun count(n) { if (n == 0) return 0; return 1 + count(n - 1); }\n\
+             print count(1.5 - 0.5);\n\
+             print count(5000);"
+        );
+        assert_eq!(res, Ok(vec_of_strings!["1", "5000"].to_vec()));
+
+        // 本地代码直接调用自己，全局变量被换掉以后就不能再用
+        let (res, compiled) = evaluate_jit(
+            "fun count(n) { if (n == 0) return 0; return 1 + count(n - 1); }\n\
+             print count(5);\n\
+             var f = count;\n\
+             fun other(n) { return 100; }\n\
+             count = other;\n\
+             print f(5);"
+        );
+        assert_eq!(res, Ok(vec_of_strings!["5", "101"].to_vec()));
+        assert_eq!(compiled, 2);
+    }
+
+    /* ---------- ---------- 性能测试 ---------- ---------- */
+    // 默认不跑：cargo test --release bench_ -- --ignored --nocapture
+
+    fn bench(name: &str, code: &str, expected_output: &[String]) {
+        // 两种后端各跑一遍，比较耗时和执行了多少条指令
+        for registers in [false, true] {
+            let extensions = extensions::Extensions { lists: true, ..Default::default() };
+            let func = Compiler::compile(String::from(code), extensions).unwrap();
+            let mut interp = Interpreter::default();
+            #[cfg(feature = "jit")]
+            interp.jit.force();
+            interp.registers = registers;
+            let start = std::time::Instant::now();
+            assert!(interp.interpret(func).is_ok());
+            assert_eq!(interp.output, expected_output);
+            println!(
+                "{} ({}): {:?}, {} instructions",
+                name,
+                if registers { "register" } else { "stack" },
+                start.elapsed(),
+                interp.instruction_count
+            );
+        }
+    }
+
+    #[test]
+    #[ignore]
+    fn bench_fib() {
+        bench(
+            "fib",
+            "fun fib(n) { if (n < 2) return n; return fib(n - 1) + fib(n - 2); }\n\
+             print fib(25);",
+            &vec_of_strings!["75025"]
+        );
+    }
+
+    #[test]
+    #[ignore]
+    fn bench_binary_trees() {
+        bench(
+            "binary-trees",
+            "class Tree {\n\
+               init(depth) {\n\
+                 if (depth > 0) { this.left = Tree(depth - 1); this.right = Tree(depth - 1); }\n\
+                 else { this.left = nil; this.right = nil; }\n\
+               }\n\
+               check() {\n\
+                 if (this.left == nil) return 1;\n\
+                 return 1 + this.left.check() + this.right.check();\n\
+               }\n\
+             }\n\
+             var total = 0;\n\
+             for (var i = 0; i < 10; i = i + 1) { total = total + Tree(10).check(); }\n\
+             print total;",
+            &vec_of_strings!["20470"]
+        );
+    }
+
+    #[test]
+    #[ignore]
+    fn bench_string_concat() {
+        bench(
+            "string-concat",
+            "var total = 0;\n\
+             for (var i = 0; i < 400; i = i + 1) {\n\
+               var s = \"\";\n\
+               for (var j = 0; j < 50; j = j + 1) { s = s + \"ab\"; }\n\
+               total = total + len(s);\n\
+             }\n\
+             print total;",
+            &vec_of_strings!["40000"]
+        );
+    }
+
+    #[test]
+    #[ignore]
+    fn bench_method_calls() {
+        bench(
+            "method-calls",
+            "class Counter {\n\
+               init() { this.count = 0; }\n\
+               inc() { this.count = this.count + 1; return this; }\n\
+               get() { return this.count; }\n\
+             }\n\
+             class Loud < Counter {\n\
+               inc() { super.inc(); return this; }\n\
+             }\n\
+             var a = Counter();\n\
+             var b = Loud();\n\
+             for (var i = 0; i < 30000; i = i + 1) {\n\
+               a.inc().inc();\n\
+               b.inc();\n\
+               var get = a.get;\n\
+               get();\n\
+             }\n\
+             print a.get() + b.get();",
+            &vec_of_strings!["90000"]
+        );
+    }
+
+    #[test]
+    fn test_json_round_trip() {
+        check_output_lists(
+            "var o = json.parse(r\"\"\"{\"name\": \"lox\", \"tags\": [\"a\", \"b\"], \"version\": 1.5,\n\
+               \"count\": 3, \"ok\": true, \"none\": null, \"nested\": {\"empty\": [], \"obj\": {}}}\"\"\");\n\
+             print o.name;\n\
+             print o.tags;\n\
+             print o.count + 1;\n\
+             print o.version;\n\
+             print json.stringify(o);\n\
+             print json.stringify(json.parse(json.stringify(o))) == json.stringify(o);\n\
+             class Point { init(x, y) { this.x = x; this.y = y; } }\n\
+             print json.stringify([Point(1, 2), nil, 2.0, [\"\\\"q\\\"\\n\"]], 2);\n\
+             print json.stringify([[]], \"\\t\");\n\
+             print json.parse(\" [1e2, -0.5, \\\"\\\\u00e9\\\"] \");",
+            &vec_of_strings![
+                "lox",
+                "[a, b]",
+                "4",
+                "1.5",
+                "{\"count\":3,\"name\":\"lox\",\"nested\":{\"empty\":[],\"obj\":{}},\"none\":null,\"ok\":true,\"tags\":[\"a\",\"b\"],\"version\":1.5}",
+                "true",
+                "[\n  {\n    \"x\": 1,\n    \"y\": 2\n  },\n  null,\n  2.0,\n  [\n    \"\\\"q\\\"\\n\"\n  ]\n]",
+                "[\n\t[]\n]",
+                "[100, -0.5, é]"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_json_errors() {
+        let check = |code: &str, expected: &str| {
+            check_error(
+                code,
+                extensions::Extensions { lists: true, ..Default::default() },
+                &(|err: &str| assert_eq!(err, expected))
+            );
+        };
+        check(
+            "json.parse(\"{\\\"a\\\": 1,\\n  \\\"b\\\": tru}\");",
+            "When calling json.parse: Invalid JSON at line 2, column 11: unexpected character '}'."
+        );
+        check(
+            "json.parse(\"[1, 2\");",
+            "When calling json.parse: Invalid JSON at line 1, column 6: unexpected end of input."
+        );
+        check(
+            "json.parse(\"01\");",
+            "When calling json.parse: Invalid JSON at line 1, column 2: unexpected character '1'."
+        );
+        check(
+            "var s = \"\";\nfor (var i = 0; i < 5000; i = i + 1) s = s + \"[\";\njson.parse(s);",
+            "When calling json.parse: Invalid JSON at line 1, column 513: nesting too deep."
+        );
+        check(
+            "var xs = [1];\npush(xs, xs);\njson.stringify(xs);",
+            "When calling json.stringify: Cannot convert a cyclic structure to JSON."
+        );
+        check(
+            "class Node {}\nvar n = Node();\nn.next = [n];\njson.stringify(n);",
+            "When calling json.stringify: Cannot convert a cyclic structure to JSON."
+        );
+        check(
+            "var xs = [];\nfor (var i = 0; i < 600; i = i + 1) xs = [xs];\njson.stringify(xs);",
+            "When calling json.stringify: Cannot convert a structure nested more than 512 levels deep to JSON."
+        );
+        check(
+            "json.stringify(clock);",
+            "When calling json.stringify: Cannot convert a value of type NativeFunction to JSON."
+        );
+        check(
+            "json.stringify(1, true);",
+            "When calling json.stringify: Expected the indent to be a number of spaces or a string, got Bool."
+        );
+        check("json.parse = nil;", "Can't set attribute parse on a frozen instance.");
+    }
 }
@@ -0,0 +1,865 @@
+/// 可选的静态类型检查：只检查有类型标注的地方，没有标注的代码仍然是动态类型
+/// 另外 const / let 声明的变量不能再赋值，也在这里检查
+use crate::expr;
+use crate::extensions;
+use crate::parser;
+use crate::scanner;
+
+use std::collections::{ HashMap, HashSet };
+use std::fmt;
+
+static INIT: &str = "init";
+
+/**
+ * checker 推导出来的静态类型，Any 表示不知道（动态类型）
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub enum Type {
+    Any,
+    Number,
+    String,
+    Bool,
+    Nil,
+    List,
+    Function(Option<Box<Signature>>),
+    Class(String), // 类本身
+    Instance(String), // 类的实例
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Signature {
+    pub params: Vec<Type>,
+    pub ret: Type,
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Type::Any => write!(f, "any"),
+            Type::Number => write!(f, "number"),
+            Type::String => write!(f, "string"),
+            Type::Bool => write!(f, "bool"),
+            Type::Nil => write!(f, "nil"),
+            Type::List => write!(f, "list"),
+            Type::Function(_) => write!(f, "fun"),
+            Type::Class(name) => write!(f, "class {}", name),
+            Type::Instance(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+impl Type {
+    fn from_annotation(annotation: &Option<expr::TypeAnnotation>) -> Type {
+        match annotation {
+            None | Some(expr::TypeAnnotation::Any) => Type::Any,
+            Some(expr::TypeAnnotation::Number) => Type::Number,
+            Some(expr::TypeAnnotation::String) => Type::String,
+            Some(expr::TypeAnnotation::Bool) => Type::Bool,
+            Some(expr::TypeAnnotation::Nil) => Type::Nil,
+            Some(expr::TypeAnnotation::List) => Type::List,
+            Some(expr::TypeAnnotation::Function) => Type::Function(None),
+            Some(expr::TypeAnnotation::Class(name, _)) => Type::Instance(name.clone()),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Error {
+    pub what: String,
+    pub line: usize,
+    pub col: i64,
+}
+
+#[derive(Default)]
+struct ClassInfo {
+    superclass: Option<String>,
+    fields: HashMap<String, Type>,
+    methods: HashMap<String, Signature>,
+    class_methods: HashMap<String, Signature>,
+    getters: HashMap<String, Type>,
+    setters: HashMap<String, Type>, // setter 参数的类型
+}
+
+#[derive(Default)]
+struct Checker {
+    scopes: Vec<HashMap<String, Type>>,
+    consts: Vec<HashSet<String>>, // 和 scopes 一一对应，记录每一层里面哪些名字是常量
+    classes: HashMap<String, ClassInfo>,
+    traits: HashMap<String, HashMap<String, Signature>>, // trait 提供的方法
+    return_types: Vec<Option<Type>>, // None 表示 init，不检查返回值
+    current_class: Option<String>,
+    enums: HashSet<String>,
+    annotated_classes: Vec<(String, expr::SourceLocation)>, // 类型标注里用到的类名，全部检查完再看有没有声明
+    errors: Vec<Error>,
+}
+
+/**
+ * 检查一段源码。扫描或者解析出错的时候不报，语法错误留给真正执行的那一边去报
+ */
+pub fn check_source(source: &str, extensions: extensions::Extensions) -> Result<(), Vec<Error>> {
+    let tokens = match scanner::scan_tokens(source.to_string()) {
+        Ok(tokens) => tokens,
+        Err(_) => {
+            return Ok(());
+        }
+    };
+    match parser::parse(extensions, tokens) {
+        Ok(stmts) => check(&stmts),
+        Err(_) => Ok(()),
+    }
+}
+
+/**
+ * 在执行之前检查类型标注，返回所有发现的类型错误
+ */
+pub fn check(stmts: &[expr::Stmt]) -> Result<(), Vec<Error>> {
+    let mut checker = Checker {
+        scopes: vec![HashMap::new()],
+        consts: vec![HashSet::new()],
+        ..Default::default()
+    };
+
+    for stmt in stmts {
+        checker.check_stmt(stmt);
+    }
+    checker.check_annotated_classes();
+
+    if checker.errors.is_empty() {
+        Ok(())
+    } else {
+        Err(checker.errors)
+    }
+}
+
+impl Checker {
+    fn error(&mut self, what: String, line: usize, col: i64) {
+        self.errors.push(Error {
+            what: format!("TypeError: {} at line={},col={}", what, line, col),
+            line,
+            col,
+        });
+    }
+
+    /**
+     * 不是类型错误，不加 TypeError 前缀
+     */
+    fn binding_error(&mut self, what: String, line: usize, col: i64) {
+        self.errors.push(Error {
+            what: format!("{} at line={},col={}", what, line, col),
+            line,
+            col,
+        });
+    }
+
+    /**
+     * 类型标注转成 Type，顺便记下用到的类名。同一个标注可能转好几次，按位置去重
+     */
+    fn annotation_type(&mut self, annotation: &Option<expr::TypeAnnotation>) -> Type {
+        if let Some(expr::TypeAnnotation::Class(name, loc)) = annotation {
+            if !self.annotated_classes.iter().any(|(_, seen)| seen == loc) {
+                self.annotated_classes.push((name.clone(), *loc));
+            }
+        }
+        Type::from_annotation(annotation)
+    }
+
+    /**
+     * 类可以在声明之前用在标注里，所以等所有的类都登记过以后再检查
+     */
+    fn check_annotated_classes(&mut self) {
+        let annotated = std::mem::take(&mut self.annotated_classes);
+        for (name, loc) in annotated {
+            if !self.classes.contains_key(&name) && !self.enums.contains(&name) {
+                self.error(format!("Unknown type '{}'", name), loc.line, loc.col);
+            }
+        }
+    }
+
+    fn begin_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+        self.consts.push(HashSet::new());
+    }
+
+    fn end_scope(&mut self) {
+        self.scopes.pop();
+        self.consts.pop();
+    }
+
+    fn define(&mut self, name: &str, ty: Type) {
+        self.scopes.last_mut().unwrap().insert(name.to_string(), ty);
+        self.consts.last_mut().unwrap().remove(name);
+    }
+
+    fn define_const(&mut self, name: &str, ty: Type) {
+        self.define(name, ty);
+        self.consts.last_mut().unwrap().insert(name.to_string());
+    }
+
+    /**
+     * 找到最近的一层定义了 name 的作用域，看它是不是常量
+     * 闭包里面的作用域也是嵌套在外面的，所以闭包里给外面的常量赋值也能查出来
+     */
+    fn is_const(&self, name: &str) -> bool {
+        for (scope, consts) in self.scopes.iter().zip(self.consts.iter()).rev() {
+            if scope.contains_key(name) {
+                return consts.contains(name);
+            }
+        }
+        false
+    }
+
+    /**
+     * 同一层作用域里面的常量不能再声明一次
+     */
+    fn check_redefinition(&mut self, sym: &expr::Symbol) {
+        if self.consts.last().unwrap().contains(&sym.name) {
+            self.binding_error(
+                format!("cannot redefine constant '{}'", sym.name),
+                sym.line,
+                sym.col
+            );
+        }
+    }
+
+    fn check_assign_target(&mut self, sym: &expr::Symbol) {
+        if self.is_const(&sym.name) {
+            self.binding_error(
+                format!("cannot assign to constant '{}'", sym.name),
+                sym.line,
+                sym.col
+            );
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Type {
+        for scope in self.scopes.iter().rev() {
+            if let Some(ty) = scope.get(name) {
+                return ty.clone();
+            }
+        }
+        Type::Any
+    }
+
+    /**
+     * 最近的一层定义了 name 的作用域里，把它的类型改成 any
+     */
+    fn forget_type(&mut self, name: &str) {
+        for scope in self.scopes.iter_mut().rev() {
+            if let Some(ty) = scope.get_mut(name) {
+                *ty = Type::Any;
+                return;
+            }
+        }
+    }
+
+    /**
+     * found 能不能放到 expected 的位置上
+     */
+    fn is_assignable(&self, expected: &Type, found: &Type) -> bool {
+        match (expected, found) {
+            (Type::Any, _) | (_, Type::Any) => true,
+            (Type::Function(_), Type::Function(_)) => true,
+            (Type::Instance(expected_name), Type::Instance(found_name)) => {
+                self.is_subclass(found_name, expected_name)
+            }
+            _ => expected == found,
+        }
+    }
+
+    /**
+     * 沿着父类链往上找
+     */
+    fn is_subclass(&self, class_name: &str, ancestor: &str) -> bool {
+        let mut current = Some(class_name.to_string());
+        while let Some(name) = current {
+            if name == ancestor {
+                return true;
+            }
+            current = self.classes.get(&name).and_then(|info| info.superclass.clone());
+        }
+        false
+    }
+
+    fn find_field(&self, class_name: &str, attr: &str) -> Option<Type> {
+        self.find_member(class_name, attr, |info| &info.fields)
+    }
+
+    fn find_method(&self, class_name: &str, method: &str) -> Option<Signature> {
+        self.find_member(class_name, method, |info| &info.methods)
+    }
+
+    /**
+     * 在类（或者父类）的某一张表里面找：字段、方法、静态方法、getter、setter
+     */
+    fn find_member<T: Clone>(
+        &self,
+        class_name: &str,
+        member: &str,
+        members: fn(&ClassInfo) -> &HashMap<String, T>
+    ) -> Option<T> {
+        let mut current = Some(class_name.to_string());
+        while let Some(name) = current {
+            let info = self.classes.get(&name)?;
+            if let Some(found) = members(info).get(member) {
+                return Some(found.clone());
+            }
+            current = info.superclass.clone();
+        }
+        None
+    }
+
+    /**
+     * 生成器调用以后返回的是生成器对象，返回值的类型标注不起作用
+     */
+    fn signature(
+        &mut self,
+        params: &[expr::Symbol],
+        return_ty: &Option<expr::TypeAnnotation>,
+        is_generator: bool
+    ) -> Signature {
+        Signature {
+            params: params
+                .iter()
+                .map(|param| self.annotation_type(&param.ty))
+                .collect(),
+            ret: if is_generator { Type::Any } else { self.annotation_type(return_ty) },
+        }
+    }
+
+    /**
+     * 函数体里面 return 语句应该返回的类型，None 表示不检查
+     */
+    fn body_return_type(sig: &Signature, is_generator: bool) -> Option<Type> {
+        if is_generator { None } else { Some(sig.ret.clone()) }
+    }
+
+    /* ---------- ---------- statements ---------- ---------- */
+
+    fn check_stmt(&mut self, stmt: &expr::Stmt) {
+        match stmt {
+            expr::Stmt::Expr(e) | expr::Stmt::Print(e) => {
+                self.check_expr(e);
+            }
+            expr::Stmt::VarDecl(sym, maybe_init) => {
+                let declared = self.annotation_type(&sym.ty);
+                if let Some(init) = maybe_init {
+                    self.check_initializer(sym, &declared, init);
+                }
+                self.check_redefinition(sym);
+                self.define(&sym.name, declared);
+            }
+            expr::Stmt::ConstDecl(sym, init) => {
+                let declared = self.annotation_type(&sym.ty);
+                self.check_initializer(sym, &declared, init);
+                self.check_redefinition(sym);
+                self.define_const(&sym.name, declared);
+            }
+            expr::Stmt::FunDecl(fun_decl) => {
+                self.check_redefinition(&fun_decl.name);
+                let sig = self.signature(
+                    &fun_decl.params,
+                    &fun_decl.return_ty,
+                    fun_decl.is_generator
+                );
+                self.define(&fun_decl.name.name, Type::Function(Some(Box::new(sig.clone()))));
+                let ret = Checker::body_return_type(&sig, fun_decl.is_generator);
+                self.check_function(
+                    &fun_decl.params,
+                    &fun_decl.defaults,
+                    &fun_decl.rest,
+                    ret,
+                    &fun_decl.body
+                );
+            }
+            expr::Stmt::ClassDecl(class_decl) => self.check_class(class_decl),
+            expr::Stmt::TraitDecl(trait_decl) => self.check_trait(trait_decl),
+            expr::Stmt::EnumDecl(enum_decl) => {
+                self.check_redefinition(&enum_decl.name);
+                self.enums.insert(enum_decl.name.name.clone());
+                self.define(&enum_decl.name.name, Type::Any);
+            }
+            expr::Stmt::If(cond, if_true, maybe_if_false) => {
+                self.check_expr(cond);
+                self.check_stmt(if_true);
+                if let Some(if_false) = maybe_if_false {
+                    self.check_stmt(if_false);
+                }
+            }
+            expr::Stmt::Block(stmts) => {
+                self.begin_scope();
+                for stmt in stmts {
+                    self.check_stmt(stmt);
+                }
+                self.end_scope();
+            }
+            expr::Stmt::Return(loc, maybe_res) => {
+                let found = match maybe_res {
+                    Some(res) => self.check_expr(res),
+                    None => Type::Nil,
+                };
+                if let Some(Some(expected)) = self.return_types.last().cloned() {
+                    if !self.is_assignable(&expected, &found) {
+                        self.error(
+                            format!("function declared to return {} but returns {}", expected, found),
+                            loc.line,
+                            loc.col
+                        );
+                    }
+                }
+            }
+            expr::Stmt::While(cond, body, maybe_increment) => {
+                self.check_expr(cond);
+                self.check_stmt(body);
+                if let Some(increment) = maybe_increment {
+                    self.check_expr(increment);
+                }
+            }
+            expr::Stmt::DoWhile(body, cond) => {
+                self.check_stmt(body);
+                self.check_expr(cond);
+            }
+            expr::Stmt::ForIn(sym, iterable, body) => {
+                self.check_expr(iterable);
+                // 元素的类型不知道，就当作 any
+                self.begin_scope();
+                self.define(&sym.name, Type::Any);
+                self.check_stmt(body);
+                self.end_scope();
+            }
+            expr::Stmt::Yield(_, val) => {
+                self.check_expr(val);
+            }
+            expr::Stmt::Match(subject, arms) => {
+                let subject_ty = self.check_expr(subject);
+                for arm in arms {
+                    self.begin_scope();
+                    self.define_pattern(&arm.pattern, subject_ty.clone());
+                    if let Some(guard) = &arm.guard {
+                        self.check_expr(guard);
+                    }
+                    self.check_stmt(&arm.body);
+                    self.end_scope();
+                }
+            }
+            expr::Stmt::Break(_) | expr::Stmt::Continue(_) => {}
+        }
+    }
+
+    fn check_initializer(&mut self, sym: &expr::Symbol, declared: &Type, init: &expr::Expr) {
+        let found = self.check_expr(init);
+        if !self.is_assignable(declared, &found) {
+            self.error(
+                format!(
+                    "variable '{}' declared as {} but initialized with {}",
+                    sym.name,
+                    declared,
+                    found
+                ),
+                sym.line,
+                sym.col
+            );
+        }
+    }
+
+    /**
+     * ret 为 None 的时候不检查 return 语句（init 方法）
+     * 默认值可以用到前面的参数，所以参数要一个一个定义
+     */
+    fn check_function(
+        &mut self,
+        params: &[expr::Symbol],
+        defaults: &[expr::Expr],
+        rest: &Option<expr::Symbol>,
+        ret: Option<Type>,
+        body: &[expr::Stmt]
+    ) {
+        self.begin_scope();
+        let num_required = params.len() - defaults.len();
+        for (idx, param) in params.iter().enumerate() {
+            let declared = self.annotation_type(&param.ty);
+            if idx >= num_required {
+                let found = self.check_expr(&defaults[idx - num_required]);
+                if !self.is_assignable(&declared, &found) {
+                    self.error(
+                        format!(
+                            "parameter '{}' declared as {} but defaults to {}",
+                            param.name,
+                            declared,
+                            found
+                        ),
+                        param.line,
+                        param.col
+                    );
+                }
+            }
+            self.define(&param.name, declared);
+        }
+        if let Some(rest) = rest {
+            self.define(&rest.name, Type::List);
+        }
+        self.return_types.push(ret);
+        for stmt in body {
+            self.check_stmt(stmt);
+        }
+        self.return_types.pop();
+        self.end_scope();
+    }
+
+    /**
+     * 模式里面绑定的变量：实例模式的字段有类型标注就用它，其他的都当作 any
+     */
+    fn define_pattern(&mut self, pattern: &expr::Pattern, ty: Type) {
+        match pattern {
+            expr::Pattern::Wildcard | expr::Pattern::Literal(_, _) => {}
+            expr::Pattern::Binding(sym) => self.define(&sym.name, ty),
+            expr::Pattern::List(elements) => {
+                for element in elements {
+                    self.define_pattern(element, Type::Any);
+                }
+            }
+            expr::Pattern::Instance(class_sym, fields) => {
+                for (field, field_pattern) in fields {
+                    let field_ty = self.find_field(&class_sym.name, &field.name).unwrap_or(Type::Any);
+                    self.define_pattern(field_pattern, field_ty);
+                }
+            }
+        }
+    }
+
+    fn check_class(&mut self, class_decl: &expr::ClassDecl) {
+        let class_name = class_decl.name.name.clone();
+
+        let mut info = ClassInfo {
+            superclass: class_decl.superclass.as_ref().map(|sym| sym.name.clone()),
+            fields: class_decl.fields
+                .iter()
+                .map(|field| (field.name.clone(), self.annotation_type(&field.ty)))
+                .collect(),
+            methods: class_decl.methods
+                .iter()
+                .map(|method| (
+                    method.name.name.clone(),
+                    self.signature(&method.params, &method.return_ty, method.is_generator),
+                ))
+                .collect(),
+            class_methods: class_decl.class_methods
+                .iter()
+                .map(|method| (
+                    method.name.name.clone(),
+                    self.signature(&method.params, &method.return_ty, method.is_generator),
+                ))
+                .collect(),
+            getters: class_decl.getters
+                .iter()
+                .map(|getter| (getter.name.name.clone(), self.annotation_type(&getter.return_ty)))
+                .collect(),
+            setters: class_decl.setters
+                .iter()
+                .map(|setter| (setter.name.name.clone(), self.annotation_type(&setter.params[0].ty)))
+                .collect(),
+        };
+        // 类自己的方法优先，trait 的方法补在后面
+        for trait_sym in class_decl.traits.iter() {
+            if let Some(trait_methods) = self.traits.get(&trait_sym.name) {
+                for (name, sig) in trait_methods.iter() {
+                    info.methods.entry(name.clone()).or_insert_with(|| sig.clone());
+                }
+            }
+        }
+        self.classes.insert(class_name.clone(), info);
+        self.check_redefinition(&class_decl.name);
+        self.define(&class_name, Type::Class(class_name.clone()));
+
+        // 静态方法里面的 this 是类本身，不是实例
+        let saved_class = self.current_class.take();
+        for method in class_decl.class_methods.iter() {
+            let sig = self.signature(&method.params, &method.return_ty, method.is_generator);
+            let ret = Checker::body_return_type(&sig, method.is_generator);
+            self.check_function(&method.params, &method.defaults, &method.rest, ret, &method.body);
+        }
+
+        self.current_class = Some(class_name);
+        for method in class_decl.methods.iter().chain(&class_decl.getters).chain(&class_decl.setters) {
+            let ret = if method.name.name == INIT || method.is_generator {
+                None
+            } else {
+                Some(self.annotation_type(&method.return_ty))
+            };
+            self.check_function(&method.params, &method.defaults, &method.rest, ret, &method.body);
+        }
+        self.current_class = saved_class;
+    }
+
+    /**
+     * trait 可以混入不同的类，方法体里面的 this 不知道是哪个类
+     */
+    fn check_trait(&mut self, trait_decl: &expr::TraitDecl) {
+        let methods = trait_decl.methods
+            .iter()
+            .map(|method| (
+                method.name.name.clone(),
+                self.signature(&method.params, &method.return_ty, method.is_generator),
+            ))
+            .collect();
+        self.traits.insert(trait_decl.name.name.clone(), methods);
+        self.check_redefinition(&trait_decl.name);
+        self.define(&trait_decl.name.name, Type::Any);
+
+        let saved_class = self.current_class.take();
+        for method in trait_decl.methods.iter() {
+            let sig = self.signature(&method.params, &method.return_ty, method.is_generator);
+            let ret = Checker::body_return_type(&sig, method.is_generator);
+            self.check_function(&method.params, &method.defaults, &method.rest, ret, &method.body);
+        }
+        self.current_class = saved_class;
+    }
+
+    /* ---------- ---------- expressions ---------- ---------- */
+
+    fn check_expr(&mut self, e: &expr::Expr) -> Type {
+        match e {
+            expr::Expr::Literal(lit) =>
+                match lit {
+                    expr::Literal::Number(_) | expr::Literal::Integer(_) => Type::Number,
+                    expr::Literal::String(_) => Type::String,
+                    expr::Literal::True | expr::Literal::False => Type::Bool,
+                    expr::Literal::Nil => Type::Nil,
+                }
+            expr::Expr::This(_) =>
+                match &self.current_class {
+                    Some(class_name) => Type::Instance(class_name.clone()),
+                    None => Type::Any,
+                }
+            expr::Expr::Unary(op, operand) => {
+                let operand_ty = self.check_expr(operand);
+                match (op.ty, operand_ty) {
+                    (expr::UnaryOpTy::Minus, Type::Number) => Type::Number,
+                    (expr::UnaryOpTy::Minus, _) => Type::Any,
+                    (expr::UnaryOpTy::Bang, _) => Type::Bool,
+                }
+            }
+            expr::Expr::Binary(lhs, op, rhs) => self.check_binary(lhs, *op, rhs),
+            expr::Expr::Call(callee, loc, args, named_args) => {
+                self.check_call(callee, loc, args, named_args)
+            }
+            expr::Expr::Get(lhs, attr) => {
+                let lhs_ty = self.check_expr(lhs);
+                if let Type::Instance(class_name) = lhs_ty {
+                    if let Some(field_ty) = self.find_field(&class_name, &attr.name) {
+                        return field_ty;
+                    }
+                    if let Some(ty) = self.find_member(&class_name, &attr.name, |info| &info.getters) {
+                        return ty;
+                    }
+                    if let Some(sig) = self.find_method(&class_name, &attr.name) {
+                        return Type::Function(Some(Box::new(sig)));
+                    }
+                } else if let Type::Class(class_name) = lhs_ty {
+                    let static_sig = self.find_member(&class_name, &attr.name, |info| {
+                        &info.class_methods
+                    });
+                    if let Some(sig) = static_sig {
+                        return Type::Function(Some(Box::new(sig)));
+                    }
+                }
+                Type::Any
+            }
+            expr::Expr::Set(lhs, attr, rhs) => {
+                let lhs_ty = self.check_expr(lhs);
+                let rhs_ty = self.check_expr(rhs);
+                if let Type::Instance(class_name) = lhs_ty {
+                    if let Some(field_ty) = self.find_field(&class_name, &attr.name) {
+                        if !self.is_assignable(&field_ty, &rhs_ty) {
+                            self.error(
+                                format!(
+                                    "field '{}' of {} declared as {} but assigned {}",
+                                    attr.name,
+                                    class_name,
+                                    field_ty,
+                                    rhs_ty
+                                ),
+                                attr.line,
+                                attr.col
+                            );
+                        }
+                    } else if
+                        let Some(param_ty) = self.find_member(&class_name, &attr.name, |info| &info.setters)
+                    {
+                        if !self.is_assignable(&param_ty, &rhs_ty) {
+                            self.error(
+                                format!(
+                                    "setter '{}' of {} takes {} but assigned {}",
+                                    attr.name,
+                                    class_name,
+                                    param_ty,
+                                    rhs_ty
+                                ),
+                                attr.line,
+                                attr.col
+                            );
+                        }
+                    }
+                }
+                rhs_ty
+            }
+            expr::Expr::Grouping(inner) => self.check_expr(inner),
+            expr::Expr::Variable(sym) => self.lookup(&sym.name),
+            expr::Expr::Assign(sym, val_expr) => {
+                self.check_assign_target(sym);
+                let found = self.check_expr(val_expr);
+                let declared = self.lookup(&sym.name);
+                // fun / class 声明本身没有类型标注，名字可以重新赋成别的值，以后也不再按原来的签名检查
+                if let Type::Function(Some(_)) | Type::Class(_) = declared {
+                    self.forget_type(&sym.name);
+                } else if !self.is_assignable(&declared, &found) {
+                    self.error(
+                        format!(
+                            "variable '{}' declared as {} but assigned {}",
+                            sym.name,
+                            declared,
+                            found
+                        ),
+                        sym.line,
+                        sym.col
+                    );
+                }
+                found
+            }
+            expr::Expr::Logical(lhs, _, rhs) => {
+                let lhs_ty = self.check_expr(lhs);
+                let rhs_ty = self.check_expr(rhs);
+                if lhs_ty == rhs_ty { lhs_ty } else { Type::Any }
+            }
+            expr::Expr::Super(_, _) => Type::Any,
+            expr::Expr::List(elements) => {
+                for element in elements {
+                    self.check_expr(element);
+                }
+                Type::List
+            }
+            expr::Expr::Interpolate(parts) => {
+                // 什么值都可以插进字符串里
+                for part in parts {
+                    self.check_expr(part);
+                }
+                Type::String
+            }
+            expr::Expr::Subscript { value, slice: expr::Slice::Index(index), .. } => {
+                self.check_expr(value);
+                self.check_expr(index);
+                Type::Any
+            }
+            expr::Expr::Subscript { value, slice: expr::Slice::Range(start, stop), .. } => {
+                self.check_expr(value);
+                for bound in start.iter().chain(stop.iter()) {
+                    self.check_expr(bound);
+                }
+                Type::List
+            }
+            expr::Expr::SetItem { lhs, slice, rhs, .. } => {
+                self.check_expr(lhs);
+                self.check_expr(slice);
+                self.check_expr(rhs)
+            }
+            expr::Expr::CompoundAssign { target, op, rhs, postfix } => {
+                // 结果的类型和对应的二元运算一样，是否出错留给运行时
+                if let expr::Expr::Variable(sym) = &**target {
+                    self.check_assign_target(sym);
+                }
+                let target_ty = self.check_expr(target);
+                let rhs_ty = self.check_expr(rhs);
+                if *postfix {
+                    target_ty
+                } else {
+                    Checker::binary_type(&target_ty, *op, &rhs_ty)
+                }
+            }
+            expr::Expr::Lambda(lambda_decl) => {
+                let sig = self.signature(
+                    &lambda_decl.params,
+                    &lambda_decl.return_ty,
+                    lambda_decl.is_generator
+                );
+                let ret = Checker::body_return_type(&sig, lambda_decl.is_generator);
+                self.check_function(
+                    &lambda_decl.params,
+                    &lambda_decl.defaults,
+                    &lambda_decl.rest,
+                    ret,
+                    &lambda_decl.body
+                );
+                Type::Function(Some(Box::new(sig)))
+            }
+        }
+    }
+
+    /**
+     * 运算符的错误留给运行时去报告，这里只推导结果的类型
+     */
+    fn check_binary(&mut self, lhs: &expr::Expr, op: expr::BinaryOp, rhs: &expr::Expr) -> Type {
+        let lhs_ty = self.check_expr(lhs);
+        let rhs_ty = self.check_expr(rhs);
+        Checker::binary_type(&lhs_ty, op, &rhs_ty)
+    }
+
+    fn binary_type(lhs_ty: &Type, op: expr::BinaryOp, rhs_ty: &Type) -> Type {
+        match (lhs_ty, op.ty, rhs_ty) {
+            (_, expr::BinaryOpTy::EqualEqual | expr::BinaryOpTy::NotEqual, _) => Type::Bool,
+            (
+                _,
+                | expr::BinaryOpTy::Less
+                | expr::BinaryOpTy::LessEqual
+                | expr::BinaryOpTy::Greater
+                | expr::BinaryOpTy::GreaterEqual
+                | expr::BinaryOpTy::InstanceOf,
+                _,
+            ) => Type::Bool,
+            (Type::Number, _, Type::Number) => Type::Number,
+            (Type::String, expr::BinaryOpTy::Plus, Type::String) => Type::String,
+            (Type::List, expr::BinaryOpTy::Plus, Type::List) => Type::List,
+            _ => Type::Any,
+        }
+    }
+
+    fn check_call(
+        &mut self,
+        callee: &expr::Expr,
+        loc: &expr::SourceLocation,
+        args: &[expr::Expr],
+        named_args: &[(expr::Symbol, expr::Expr)]
+    ) -> Type {
+        let callee_ty = self.check_expr(callee);
+        let arg_tys: Vec<Type> = args
+            .iter()
+            .map(|arg| self.check_expr(arg))
+            .collect();
+        // 签名里面没有参数名，命名参数只检查表达式本身
+        for (_, arg) in named_args {
+            self.check_expr(arg);
+        }
+
+        let (maybe_params, res) = match &callee_ty {
+            Type::Function(Some(sig)) => (Some(sig.params.clone()), sig.ret.clone()),
+            Type::Class(class_name) => (
+                self.find_method(class_name, INIT).map(|sig| sig.params),
+                Type::Instance(class_name.clone()),
+            ),
+            _ => (None, Type::Any),
+        };
+
+        // 参数个数不对的情况留给运行时报告
+        if let Some(params) = maybe_params {
+            for (idx, (expected, found)) in params.iter().zip(arg_tys.iter()).enumerate() {
+                if !self.is_assignable(expected, found) {
+                    self.error(
+                        format!("argument {} expected {} but found {}", idx + 1, expected, found),
+                        loc.line,
+                        loc.col
+                    );
+                }
+            }
+        }
+
+        res
+    }
+}
@@ -0,0 +1,2711 @@
+//! 单趟编译器：直接从 token 序列生成字节码（clox 的做法），不经过 expr 里的语法树
+use crate::bytecode;
+use crate::extensions;
+use crate::scanner;
+use crate::value;
+
+use std::collections::HashSet;
+use std::fmt;
+
+/* ---------- ---------- 错误处理 ---------- ---------- */
+
+#[derive(Debug)]
+pub struct ErrorInfo {
+    pub what: String,
+    pub line: usize,
+    pub col: i64,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Lexical(scanner::Error),
+    Parse(ErrorInfo),
+    Semantic(ErrorInfo),
+    Internal(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Lexical(err) => write!(f, "{} at line {}", err.what, err.line),
+            Error::Parse(err) | Error::Semantic(err) => write!(f, "{} at line {}", err.what, err.line),
+            Error::Internal(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+/* ---------- ---------- 优先级 ---------- ---------- */
+
+#[derive(Eq, PartialEq, PartialOrd, Copy, Clone, Debug)]
+enum Precedence {
+    None,
+    Assignment, // =
+    Or, // or
+    And, // and
+    Equality, // == !=
+    Comparison, // < > <= >=
+    BitOr, // |
+    BitXor, // ^
+    BitAnd, // &
+    Shift, // << >>
+    Term, // + -
+    Factor, // * / % ~/
+    Unary, // ! -
+    Power, // ** 右结合
+    Call, // . () ++ --
+    Primary,
+}
+
+impl Precedence {
+    fn next(&self) -> Precedence {
+        match self {
+            Precedence::None => Precedence::Assignment,
+            Precedence::Assignment => Precedence::Or,
+            Precedence::Or => Precedence::And,
+            Precedence::And => Precedence::Equality,
+            Precedence::Equality => Precedence::Comparison,
+            Precedence::Comparison => Precedence::BitOr,
+            Precedence::BitOr => Precedence::BitXor,
+            Precedence::BitXor => Precedence::BitAnd,
+            Precedence::BitAnd => Precedence::Shift,
+            Precedence::Shift => Precedence::Term,
+            Precedence::Term => Precedence::Factor,
+            Precedence::Factor => Precedence::Unary,
+            Precedence::Unary => Precedence::Power,
+            Precedence::Power => Precedence::Call,
+            Precedence::Call => Precedence::Primary,
+            Precedence::Primary => panic!("primary has no next precedence!"),
+        }
+    }
+}
+
+/**
+ * 每一种 token 作为 前缀 / 中缀 的时候，用哪个函数去解析
+ */
+#[derive(Debug, Copy, Clone)]
+enum ParseFn {
+    Grouping,
+    Unary,
+    Binary,
+    Number,
+    Literal,
+    String,
+    Interpolation,
+    Variable,
+    And,
+    Or,
+    Call,
+    Dot,
+    This,
+    Super,
+    List,
+    Subscript,
+    Lambda,
+    PreIncrement,
+}
+
+struct ParseRule {
+    prefix: Option<ParseFn>,
+    infix: Option<ParseFn>,
+    precedence: Precedence,
+}
+
+/* ---------- ---------- 编译期的作用域信息 ---------- ---------- */
+
+#[derive(Debug)]
+struct Local {
+    name: String,
+    depth: i64, // -1 表示声明了但是还没有初始化
+    is_captured: bool, // 是否被闭包捕获，退出作用域的时候要关闭上值
+    is_const: bool, // const / let 声明的，不能再赋值
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum FunctionType {
+    Function,
+    Async,
+    Getter,
+    Initializer,
+    Method,
+    Script,
+}
+
+/**
+ * 正在编译的循环，break / continue 的跳转要等循环编译完了才能 patch
+ */
+struct LoopInfo {
+    continue_target: Option<usize>, // do-while 的条件在循环体后面，编译循环体的时候还不知道
+    continue_jumps: Vec<usize>,
+    break_jumps: Vec<usize>,
+    scope_depth: i64, // 循环外面的作用域深度，跳出去的时候比它深的局部变量都要弹出
+}
+
+/**
+ * 每一层函数对应一个 Level
+ */
+struct Level {
+    function: bytecode::Function,
+    function_type: FunctionType,
+    locals: Vec<Local>,
+    scope_depth: i64,
+    upvals: Vec<bytecode::UpvalueLoc>,
+    loops: Vec<LoopInfo>,
+    value_return: Option<scanner::Token>, // 第一个带返回值的 return，生成器里面不允许
+}
+
+impl Level {
+    fn new(name: String, function_type: FunctionType) -> Level {
+        // 0 号槽位是被调用的函数本身，方法里面就是 this
+        let slot_zero_name = match function_type {
+            FunctionType::Method | FunctionType::Getter | FunctionType::Initializer => "this",
+            _ => "",
+        };
+        Level {
+            function: bytecode::Function {
+                name,
+                ..Default::default()
+            },
+            function_type,
+            locals: vec![Local {
+                name: slot_zero_name.to_string(),
+                depth: 0,
+                is_captured: false,
+                is_const: false,
+            }],
+            scope_depth: 0,
+            upvals: Vec::new(),
+            loops: Vec::new(),
+            value_return: None,
+        }
+    }
+}
+
+struct ClassCompiler {
+    has_superclass: bool,
+}
+
+/**
+ * 可以被赋值的位置，Property 和 Item 的操作数（对象、列表和下标）已经在栈上了
+ */
+#[derive(Copy, Clone, Debug)]
+enum LValue {
+    Local(usize),
+    Upval(usize),
+    Global(usize),
+    Property(usize),
+    Item,
+}
+
+impl LValue {
+    /**
+     * 读写之前已经在栈上的操作数个数
+     */
+    fn num_operands(&self) -> usize {
+        match self {
+            LValue::Local(_) | LValue::Upval(_) | LValue::Global(_) => 0,
+            LValue::Property(_) => 1,
+            LValue::Item => 2,
+        }
+    }
+}
+
+/**
+ * match 的模式要先整个解析出来，里面绑定的变量在匹配之前就要声明成局部变量
+ */
+enum Pattern {
+    Wildcard,
+    Literal(scanner::Token, /*negated*/ bool),
+    Binding(scanner::Token),
+    List(Vec<Pattern>),
+    Instance(scanner::Token, Vec<(String, Pattern)>),
+}
+
+/**
+ * 从 match 的值走到子模式对应的值：列表下标或者实例字段
+ */
+enum PatternStep {
+    Index(usize),
+    Field(String),
+}
+
+#[derive(Default)]
+pub struct Compiler {
+    tokens: Vec<scanner::Token>,
+    token_idx: usize,
+    levels: Vec<Level>,
+    classes: Vec<ClassCompiler>,
+    pending_increment: Option<scanner::Token>, // 前缀 ++x / --x 还没有找到它的目标
+    const_globals: HashSet<String>, // 目前为止见过的全局常量，别的编译单元里定义的留给运行时检查
+    extensions: extensions::Extensions,
+}
+
+impl Compiler {
+    pub fn compile(
+        input: String,
+        extensions: extensions::Extensions
+    ) -> Result<bytecode::Function, Error> {
+        let tokens = match scanner::scan_tokens(input) {
+            Ok(tokens) => tokens,
+            Err(err) => {
+                return Err(Error::Lexical(err));
+            }
+        };
+
+        let mut compiler = Compiler {
+            tokens,
+            levels: vec![Level::new(String::new(), FunctionType::Script)],
+            extensions,
+            ..Default::default()
+        };
+
+        while !compiler.is_at_end() {
+            compiler.declaration()?;
+        }
+        compiler.emit_return();
+
+        match compiler.levels.pop() {
+            Some(level) => Ok(level.function),
+            None => Err(Error::Internal("no function left after compilation".to_string())),
+        }
+    }
+
+    /* ---------- ---------- declarations ---------- ---------- */
+
+    fn declaration(&mut self) -> Result<(), Error> {
+        if self.matches(scanner::TokenType::Class) {
+            self.class_decl()
+        } else if self.matches(scanner::TokenType::Trait) {
+            self.trait_decl()
+        } else if self.matches(scanner::TokenType::Enum) {
+            self.enum_decl()
+        } else if self.matches(scanner::TokenType::Fun) {
+            self.fun_decl(FunctionType::Function)
+        } else if self.matches(scanner::TokenType::Async) {
+            self.consume(scanner::TokenType::Fun, "Expected fun after async.")?;
+            self.fun_decl(FunctionType::Async)
+        } else if self.matches(scanner::TokenType::Var) {
+            self.var_decl()
+        } else if
+            self.matches(scanner::TokenType::Const) ||
+            self.matches(scanner::TokenType::Let)
+        {
+            self.const_decl()
+        } else {
+            self.statement()
+        }
+    }
+
+    fn class_decl(&mut self) -> Result<(), Error> {
+        self.consume(scanner::TokenType::Identifier, "Expected class name.")?;
+        let class_name_tok = self.previous().clone();
+        let class_name = Compiler::identifier_name(&class_name_tok);
+        let name_constant = self.identifier_constant(class_name.clone());
+        self.declare_variable()?;
+
+        self.emit_op(bytecode::Op::Class(name_constant), class_name_tok.line);
+        self.define_variable(name_constant);
+
+        self.classes.push(ClassCompiler { has_superclass: false });
+
+        if self.matches(scanner::TokenType::Less) {
+            self.consume(scanner::TokenType::Identifier, "Expected superclass name.")?;
+            let superclass_tok = self.previous().clone();
+            if Compiler::identifier_name(&superclass_tok) == class_name {
+                return Err(
+                    Error::Semantic(ErrorInfo {
+                        what: "A class cannot inherit from itself.".to_string(),
+                        line: superclass_tok.line,
+                        col: superclass_tok.col,
+                    })
+                );
+            }
+            self.named_variable(superclass_tok.clone())?;
+
+            // super 作为一个局部变量，方法通过上值访问它
+            self.begin_scope();
+            self.add_local("super".to_string());
+            self.define_variable(0);
+
+            self.named_variable(class_name_tok.clone())?;
+            self.emit_op(bytecode::Op::Inherit, superclass_tok.line);
+            self.classes.last_mut().unwrap().has_superclass = true;
+        }
+
+        // trait 要等类自己的方法都定义好了再混入，类的方法优先
+        let mut trait_toks = Vec::new();
+        if self.matches(scanner::TokenType::With) {
+            loop {
+                self.consume(scanner::TokenType::Identifier, "Expected trait name.")?;
+                trait_toks.push(self.previous().clone());
+                if !self.matches(scanner::TokenType::Comma) {
+                    break;
+                }
+            }
+        }
+        if trait_toks.len() > 255 {
+            return Err(
+                Error::Parse(ErrorInfo {
+                    what: "Cannot mix in more than 255 traits.".to_string(),
+                    line: class_name_tok.line,
+                    col: class_name_tok.col,
+                })
+            );
+        }
+
+        self.named_variable(class_name_tok)?;
+        self.consume(scanner::TokenType::LeftBrace, "Expected { before class body.")?;
+        while !self.check(scanner::TokenType::RightBrace) && !self.is_at_end() {
+            if
+                self.check(scanner::TokenType::Identifier) &&
+                self.check_next(scanner::TokenType::Colon)
+            {
+                // 字段的类型声明只给 checker 用，字节码里面什么都不做
+                self.advance();
+                self.skip_type_annotation()?;
+                self.consume(
+                    scanner::TokenType::Semicolon,
+                    "Expected ; after field declaration."
+                )?;
+            } else {
+                self.method()?;
+            }
+        }
+        self.consume(scanner::TokenType::RightBrace, "Expected } after class body.")?;
+        let line = self.previous().line;
+        if !trait_toks.is_empty() {
+            let count = trait_toks.len() as u8;
+            for trait_tok in trait_toks {
+                self.named_variable(trait_tok)?;
+            }
+            self.emit_op(bytecode::Op::Mixin(count), line);
+        }
+        self.emit_op(bytecode::Op::Pop, line);
+
+        if self.classes.last().unwrap().has_superclass {
+            self.end_scope();
+        }
+        self.classes.pop();
+
+        Ok(())
+    }
+
+    /**
+     * trait 里面是带函数体的方法，以及只有签名的 name(params); 要求混入它的类提供
+     */
+    fn trait_decl(&mut self) -> Result<(), Error> {
+        self.consume(scanner::TokenType::Identifier, "Expected trait name.")?;
+        let trait_name_tok = self.previous().clone();
+        let name_constant = self.identifier_constant(Compiler::identifier_name(&trait_name_tok));
+        self.declare_variable()?;
+
+        self.emit_op(bytecode::Op::Trait(name_constant), trait_name_tok.line);
+        self.define_variable(name_constant);
+
+        self.classes.push(ClassCompiler { has_superclass: false });
+
+        self.named_variable(trait_name_tok)?;
+        self.consume(scanner::TokenType::LeftBrace, "Expected { before trait body.")?;
+        while !self.check(scanner::TokenType::RightBrace) && !self.is_at_end() {
+            self.consume(scanner::TokenType::Identifier, "Expected method name.")?;
+            let method_name_tok = self.previous().clone();
+            let method_name = Compiler::identifier_name(&method_name_tok);
+            let constant = self.identifier_constant(method_name.clone());
+
+            if self.is_method_signature() {
+                // 参数列表只是给人看的，直接跳过去
+                while !self.matches(scanner::TokenType::Semicolon) {
+                    self.advance();
+                }
+                self.emit_op(bytecode::Op::RequireMethod(constant), method_name_tok.line);
+            } else {
+                // 混入以后 init 就是类的初始化函数，同样要返回 this
+                let function_type = if method_name == "init" {
+                    FunctionType::Initializer
+                } else {
+                    FunctionType::Method
+                };
+                self.function(method_name, function_type)?;
+                self.emit_op(bytecode::Op::TraitMethod(constant), method_name_tok.line);
+            }
+        }
+        self.consume(scanner::TokenType::RightBrace, "Expected } after trait body.")?;
+        let line = self.previous().line;
+        self.emit_op(bytecode::Op::Pop, line);
+
+        self.classes.pop();
+
+        Ok(())
+    }
+
+    /**
+     * enum Shape { Circle(r), Rect(w, h), Empty }
+     * 变体没有函数体，枚举留在栈上把变体一个一个加进去，最后再定义变量
+     */
+    fn enum_decl(&mut self) -> Result<(), Error> {
+        self.consume(scanner::TokenType::Identifier, "Expected enum name.")?;
+        let enum_name_tok = self.previous().clone();
+        let enum_name = Compiler::identifier_name(&enum_name_tok);
+        let name_constant = self.identifier_constant(enum_name.clone());
+        self.declare_variable()?;
+
+        self.emit_op(bytecode::Op::Enum(name_constant), enum_name_tok.line);
+
+        self.consume(scanner::TokenType::LeftBrace, "Expected { before enum body.")?;
+        let mut variant_names: Vec<String> = Vec::new();
+        while !self.check(scanner::TokenType::RightBrace) && !self.is_at_end() {
+            self.consume(scanner::TokenType::Identifier, "Expected variant name.")?;
+            let variant_tok = self.previous().clone();
+            let variant_name = Compiler::identifier_name(&variant_tok);
+            if variant_names.contains(&variant_name) {
+                return Err(
+                    Error::Semantic(ErrorInfo {
+                        what: format!("Duplicate variant {} in enum {}.", variant_name, enum_name),
+                        line: variant_tok.line,
+                        col: variant_tok.col,
+                    })
+                );
+            }
+            // 变体的下标要放进 Value 的附加位里
+            if variant_names.len() == value::MAX_VARIANTS {
+                return Err(
+                    Error::Semantic(ErrorInfo {
+                        what: format!("Too many variants in enum {}.", enum_name),
+                        line: variant_tok.line,
+                        col: variant_tok.col,
+                    })
+                );
+            }
+            variant_names.push(variant_name.clone());
+
+            let mut fields: Vec<String> = Vec::new();
+            if self.matches(scanner::TokenType::LeftParen) {
+                if !self.check(scanner::TokenType::RightParen) {
+                    loop {
+                        self.consume(scanner::TokenType::Identifier, "Expected field name.")?;
+                        let field_tok = self.previous().clone();
+                        let field = Compiler::identifier_name(&field_tok);
+                        if fields.contains(&field) {
+                            return Err(
+                                Error::Semantic(ErrorInfo {
+                                    what: format!(
+                                        "Duplicate field {} in variant {}.",
+                                        field,
+                                        variant_name
+                                    ),
+                                    line: field_tok.line,
+                                    col: field_tok.col,
+                                })
+                            );
+                        }
+                        fields.push(field);
+                        if !self.matches(scanner::TokenType::Comma) {
+                            break;
+                        }
+                    }
+                }
+                self.consume(scanner::TokenType::RightParen, "Expected ) after variant fields.")?;
+            }
+
+            let constant = self.identifier_constant(variant_name);
+            self.emit_op(bytecode::Op::EnumVariant(constant, fields), variant_tok.line);
+            if !self.matches(scanner::TokenType::Comma) {
+                break;
+            }
+        }
+        self.consume(scanner::TokenType::RightBrace, "Expected } after enum body.")?;
+        self.define_variable(name_constant);
+        Ok(())
+    }
+
+    /**
+     * 方法名后面的参数列表以 ; 结束（而不是函数体）就只是一个签名
+     */
+    fn is_method_signature(&self) -> bool {
+        for tok in &self.tokens[self.token_idx..] {
+            match tok.ty {
+                scanner::TokenType::Semicolon => {
+                    return true;
+                }
+                scanner::TokenType::LeftBrace | scanner::TokenType::Eof => {
+                    return false;
+                }
+                _ => {}
+            }
+        }
+        false
+    }
+
+    /**
+     * 方法、静态方法（class name() {}）、getter（name {}）和 setter（set name(v) {}）
+     */
+    fn method(&mut self) -> Result<(), Error> {
+        let is_class_method = self.matches(scanner::TokenType::Class);
+        // set 不是关键字，后面跟着名字才是 setter，否则就是一个叫 set 的方法
+        let is_setter =
+            !is_class_method &&
+            self.check(scanner::TokenType::Identifier) &&
+            Compiler::identifier_name(self.peek()) == "set" &&
+            self.check_next(scanner::TokenType::Identifier);
+        if is_setter {
+            self.advance();
+        }
+
+        self.consume(scanner::TokenType::Identifier, "Expected method name.")?;
+        let method_name_tok = self.previous().clone();
+        let method_name = Compiler::identifier_name(&method_name_tok);
+        let constant = self.identifier_constant(method_name.clone());
+        let line = method_name_tok.line;
+
+        if is_class_method {
+            // 静态方法里的 this 就是类本身
+            self.function(method_name, FunctionType::Method)?;
+            self.emit_op(bytecode::Op::ClassMethod(constant), line);
+        } else if is_setter {
+            self.function(method_name, FunctionType::Method)?;
+            if self.last_function_params() != (1, false) {
+                return Err(
+                    Error::Semantic(ErrorInfo {
+                        what: "A setter must take exactly one parameter.".to_string(),
+                        line,
+                        col: method_name_tok.col,
+                    })
+                );
+            }
+            self.emit_op(bytecode::Op::Setter(constant), line);
+        } else if self.check(scanner::TokenType::LeftBrace) {
+            self.function(method_name, FunctionType::Getter)?;
+            self.emit_op(bytecode::Op::Getter(constant), line);
+        } else {
+            let function_type = if method_name == "init" {
+                FunctionType::Initializer
+            } else {
+                FunctionType::Method
+            };
+            self.function(method_name, function_type)?;
+            self.emit_op(bytecode::Op::Method(constant), line);
+        }
+
+        Ok(())
+    }
+
+    fn fun_decl(&mut self, function_type: FunctionType) -> Result<(), Error> {
+        let global_idx = self.parse_variable("Expected function name.")?;
+        self.mark_initialized();
+        let name = Compiler::identifier_name(self.previous());
+        self.function(name, function_type)?;
+        self.define_variable(global_idx);
+        Ok(())
+    }
+
+    /**
+     * 编译一个函数体，结果作为闭包常量放到外层函数里面
+     */
+    fn function(&mut self, name: String, function_type: FunctionType) -> Result<(), Error> {
+        let line = self.previous().line;
+        self.levels.push(Level::new(name, function_type));
+        self.current_function_mut().is_async = function_type == FunctionType::Async;
+        self.begin_scope();
+
+        // getter 没有参数列表
+        if function_type != FunctionType::Getter {
+            self.parameters()?;
+        }
+
+        self.consume(scanner::TokenType::LeftBrace, "Expected { before function body.")?;
+        self.block()?;
+        self.emit_return();
+
+        let level = self.levels.pop().unwrap();
+        // yield 可能出现在 return 后面，所以函数编译完了才能检查
+        if let (true, Some(return_tok)) = (level.function.is_generator, &level.value_return) {
+            return Err(
+                Error::Semantic(ErrorInfo {
+                    what: "Cannot return a value from a generator.".to_string(),
+                    line: return_tok.line,
+                    col: return_tok.col,
+                })
+            );
+        }
+        let const_idx = self
+            .current_chunk()
+            .add_constant(
+                bytecode::Constant::Function(bytecode::Closure {
+                    function: level.function,
+                    upvalues: level.upvals.clone(),
+                })
+            );
+        self.emit_op(bytecode::Op::Closure(const_idx, level.upvals), line);
+
+        Ok(())
+    }
+
+    /**
+     * 参数列表和返回类型标注，参数作为局部变量定义在函数体的作用域里。
+     * 有默认值的参数只能放在后面，...rest 只能放在最后
+     */
+    fn parameters(&mut self) -> Result<(), Error> {
+        self.consume(scanner::TokenType::LeftParen, "Expected ( after function name.")?;
+        if !self.check(scanner::TokenType::RightParen) {
+            loop {
+                if self.current_function().param_names.len() == 255 {
+                    return Err(
+                        Error::Parse(ErrorInfo {
+                            what: "Cannot have more than 255 parameters.".to_string(),
+                            line: self.peek().line,
+                            col: self.peek().col,
+                        })
+                    );
+                }
+
+                let is_rest = self.matches(scanner::TokenType::DotDotDot);
+                let param_const_idx = self.parse_variable("Expected parameter name.")?;
+                let param_tok = self.previous().clone();
+                self.skip_maybe_type_annotation()?;
+                self.define_variable(param_const_idx);
+
+                if is_rest {
+                    self.current_function_mut().has_rest = true;
+                    if !self.check(scanner::TokenType::RightParen) {
+                        return Err(
+                            Error::Parse(ErrorInfo {
+                                what: "Rest parameter must be the last parameter.".to_string(),
+                                line: param_tok.line,
+                                col: param_tok.col,
+                            })
+                        );
+                    }
+                    break;
+                }
+
+                let param_name = Compiler::identifier_name(&param_tok);
+                self.current_function_mut().param_names.push(param_name.clone());
+                if self.matches(scanner::TokenType::Equal) {
+                    self.default_value()?;
+                    self.current_function_mut().optional_args += 1;
+                } else if self.current_function().optional_args > 0 {
+                    return Err(
+                        Error::Parse(ErrorInfo {
+                            what: format!(
+                                "Parameter '{}' without a default cannot follow a parameter with a default.",
+                                param_name
+                            ),
+                            line: param_tok.line,
+                            col: param_tok.col,
+                        })
+                    );
+                } else {
+                    self.current_function_mut().arity += 1;
+                }
+
+                if !self.matches(scanner::TokenType::Comma) {
+                    break;
+                }
+            }
+        }
+        self.consume(scanner::TokenType::RightParen, "Expected ) after parameter list.")?;
+        self.skip_maybe_type_annotation()?;
+
+        Ok(())
+    }
+
+    /**
+     * 有默认值的参数后面紧跟着一个隐藏的槽位，调用的时候 VM 在里面放这个参数是不是没有传，
+     * 没有传就在函数开头算出默认值，默认值里面可以用到前面的参数
+     */
+    fn default_value(&mut self) -> Result<(), Error> {
+        let line = self.previous().line;
+        let param_slot = self.locals().len() - 1;
+        self.add_local("(missing)".to_string());
+        self.mark_initialized();
+
+        self.emit_op(bytecode::Op::GetLocal(param_slot + 1), line);
+        let passed_jump = self.emit_jump(bytecode::Op::JumpIfFalse(0));
+        self.emit_op(bytecode::Op::Pop, line);
+        self.expression()?;
+        self.emit_op(bytecode::Op::SetLocal(param_slot), line);
+        self.emit_op(bytecode::Op::Pop, line);
+        let end_jump = self.emit_jump(bytecode::Op::Jump(0));
+        self.patch_jump(passed_jump);
+        self.emit_op(bytecode::Op::Pop, line);
+        self.patch_jump(end_jump);
+        Ok(())
+    }
+
+    /**
+     * 刚编译完的函数（外层函数的最后一个常量）的参数个数，...rest 不算
+     */
+    fn last_function_params(&mut self) -> (usize, /*has_rest*/ bool) {
+        match self.current_chunk().constants.last() {
+            Some(bytecode::Constant::Function(closure)) => {
+                (closure.function.param_names.len(), closure.function.has_rest)
+            }
+            _ => panic!("expected a function constant after compiling a function"),
+        }
+    }
+
+    fn var_decl(&mut self) -> Result<(), Error> {
+        let global_idx = self.parse_variable("Expected variable name.")?;
+        self.skip_maybe_type_annotation()?;
+
+        if self.matches(scanner::TokenType::Equal) {
+            self.expression()?;
+        } else {
+            let line = self.previous().line;
+            self.emit_op(bytecode::Op::Nil, line);
+        }
+
+        self.consume(scanner::TokenType::Semicolon, "Expected ; after variable declaration.")?;
+        self.define_variable(global_idx);
+        Ok(())
+    }
+
+    fn const_decl(&mut self) -> Result<(), Error> {
+        let global_idx = self.parse_variable("Expected constant name.")?;
+        let name = Compiler::identifier_name(self.previous());
+        self.skip_maybe_type_annotation()?;
+
+        self.consume(scanner::TokenType::Equal, "Constants must be initialized.")?;
+        self.expression()?;
+        self.consume(scanner::TokenType::Semicolon, "Expected ; after constant declaration.")?;
+
+        if self.scope_depth() > 0 {
+            self.locals_mut().last_mut().unwrap().is_const = true;
+            self.mark_initialized();
+            return Ok(());
+        }
+        let line = self.previous().line;
+        self.emit_op(bytecode::Op::DefineConstGlobal(global_idx), line);
+        self.const_globals.insert(name);
+        Ok(())
+    }
+
+    /**
+     * 类型标注只给 checker 用，编译器直接跳过去
+     */
+    fn skip_maybe_type_annotation(&mut self) -> Result<(), Error> {
+        if self.matches(scanner::TokenType::Colon) {
+            self.skip_type_annotation()?;
+        }
+        Ok(())
+    }
+
+    fn skip_type_annotation(&mut self) -> Result<(), Error> {
+        if self.matches(scanner::TokenType::Colon) || self.previous().ty == scanner::TokenType::Colon {
+            if
+                !self.matches(scanner::TokenType::Nil) &&
+                !self.matches(scanner::TokenType::Fun)
+            {
+                self.consume(scanner::TokenType::Identifier, "Expected type after ':'.")?;
+            }
+            Ok(())
+        } else {
+            Err(
+                Error::Parse(ErrorInfo {
+                    what: "Expected ':' before type.".to_string(),
+                    line: self.peek().line,
+                    col: self.peek().col,
+                })
+            )
+        }
+    }
+
+    /* ---------- ---------- statements ---------- ---------- */
+
+    fn statement(&mut self) -> Result<(), Error> {
+        if self.matches(scanner::TokenType::Print) {
+            self.print_statement()
+        } else if self.matches(scanner::TokenType::For) {
+            self.for_statement()
+        } else if self.matches(scanner::TokenType::If) {
+            self.if_statement()
+        } else if self.matches(scanner::TokenType::Return) {
+            self.return_statement()
+        } else if self.matches(scanner::TokenType::While) {
+            self.while_statement()
+        } else if self.matches(scanner::TokenType::Do) {
+            self.do_while_statement()
+        } else if self.matches(scanner::TokenType::Break) {
+            self.break_statement()
+        } else if self.matches(scanner::TokenType::Continue) {
+            self.continue_statement()
+        } else if self.matches(scanner::TokenType::Yield) {
+            self.yield_statement()
+        } else if self.matches(scanner::TokenType::Spawn) {
+            self.spawn_statement()
+        } else if self.matches(scanner::TokenType::Match) {
+            self.match_statement()
+        } else if self.matches(scanner::TokenType::LeftBrace) {
+            self.begin_scope();
+            self.block()?;
+            self.end_scope();
+            Ok(())
+        } else {
+            self.expression_statement()
+        }
+    }
+
+    fn print_statement(&mut self) -> Result<(), Error> {
+        self.expression()?;
+        self.consume(scanner::TokenType::Semicolon, "Expected ; after value.")?;
+        let line = self.previous().line;
+        self.emit_op(bytecode::Op::Print, line);
+        self.emit_op(bytecode::Op::Pop, line);
+        Ok(())
+    }
+
+    fn expression_statement(&mut self) -> Result<(), Error> {
+        self.expression()?;
+        self.consume(scanner::TokenType::Semicolon, "Expected ; after expression.")?;
+        let line = self.previous().line;
+        self.emit_op(bytecode::Op::Pop, line);
+        Ok(())
+    }
+
+    fn if_statement(&mut self) -> Result<(), Error> {
+        self.consume(scanner::TokenType::LeftParen, "Expected ( after if.")?;
+        self.expression()?;
+        self.consume(scanner::TokenType::RightParen, "Expected ) after condition.")?;
+
+        let then_jump = self.emit_jump(bytecode::Op::JumpIfFalse(0));
+        self.emit_op(bytecode::Op::Pop, self.previous().line);
+        self.statement()?;
+        let else_jump = self.emit_jump(bytecode::Op::Jump(0));
+
+        self.patch_jump(then_jump);
+        self.emit_op(bytecode::Op::Pop, self.previous().line);
+
+        if self.matches(scanner::TokenType::Else) {
+            self.statement()?;
+        }
+        self.patch_jump(else_jump);
+
+        Ok(())
+    }
+
+    fn while_statement(&mut self) -> Result<(), Error> {
+        let loop_start = self.current_chunk().code.len();
+
+        self.consume(scanner::TokenType::LeftParen, "Expected ( after while.")?;
+        self.expression()?;
+        self.consume(scanner::TokenType::RightParen, "Expected ) after condition.")?;
+
+        let exit_jump = self.emit_jump(bytecode::Op::JumpIfFalse(0));
+        self.emit_op(bytecode::Op::Pop, self.previous().line);
+        self.begin_loop(Some(loop_start));
+        self.statement()?;
+        self.emit_loop(loop_start);
+
+        self.patch_jump(exit_jump);
+        self.emit_op(bytecode::Op::Pop, self.previous().line);
+        self.end_loop();
+
+        Ok(())
+    }
+
+    fn do_while_statement(&mut self) -> Result<(), Error> {
+        let loop_start = self.current_chunk().code.len();
+
+        self.begin_loop(None);
+        self.statement()?;
+
+        self.consume(scanner::TokenType::While, "Expected while after do body.")?;
+        self.consume(scanner::TokenType::LeftParen, "Expected ( after while.")?;
+        self.start_continue_target();
+        self.expression()?;
+        self.consume(scanner::TokenType::RightParen, "Expected ) after condition.")?;
+        self.consume(scanner::TokenType::Semicolon, "Expected ; after do-while condition.")?;
+
+        let exit_jump = self.emit_jump(bytecode::Op::JumpIfFalse(0));
+        self.emit_op(bytecode::Op::Pop, self.previous().line);
+        self.emit_loop(loop_start);
+
+        self.patch_jump(exit_jump);
+        self.emit_op(bytecode::Op::Pop, self.previous().line);
+        self.end_loop();
+
+        Ok(())
+    }
+
+    /**
+     * for (var x in iterable) body
+     * 列表和迭代器都放在一个隐藏的局部变量里，后面再跟一个隐藏的下标，
+     * ForIter 取出下一个元素压栈（列表用完了直接跳出），迭代器用完了 JumpIfNil 跳出（见 Interpreter::iteration_done）
+     */
+    fn for_in_statement(&mut self) -> Result<(), Error> {
+        self.consume(scanner::TokenType::Identifier, "Expected loop variable name.")?;
+        let var_name = Compiler::identifier_name(self.previous());
+        self.consume(scanner::TokenType::In, "Expected in after loop variable.")?;
+
+        self.expression()?;
+        self.consume(scanner::TokenType::RightParen, "Expected ) after for clauses.")?;
+        let line = self.previous().line;
+        self.emit_op(bytecode::Op::GetIter, line);
+        self.add_local("(iter)".to_string());
+        self.mark_initialized();
+        let zero = self.current_chunk().add_constant_integer(0);
+        self.emit_op(bytecode::Op::Constant(zero), line);
+        self.add_local("(index)".to_string());
+        self.mark_initialized();
+
+        let loop_start = self.current_chunk().code.len();
+        let exhausted_jump = self.emit_jump(bytecode::Op::ForIter(0));
+        let nil_jump = self.emit_jump(bytecode::Op::JumpIfNil(0));
+
+        self.begin_loop(Some(loop_start));
+        // 循环变量放在循环体的作用域里面，每一轮都是一个新的变量，闭包捕获到的值不会被下一轮覆盖
+        self.begin_scope();
+        self.add_local(var_name);
+        self.mark_initialized();
+        self.statement()?;
+        self.end_scope();
+        self.emit_loop(loop_start);
+
+        self.patch_jump(exhausted_jump);
+        self.patch_jump(nil_jump);
+        self.end_loop();
+
+        Ok(())
+    }
+
+    fn break_statement(&mut self) -> Result<(), Error> {
+        let loop_scope_depth = self.enclosing_loop("break")?.scope_depth;
+        self.consume(scanner::TokenType::Semicolon, "Expected ; after break.")?;
+
+        self.discard_locals_deeper_than(loop_scope_depth);
+        let jump = self.emit_jump(bytecode::Op::Jump(0));
+        self.current_loop_mut().break_jumps.push(jump);
+        Ok(())
+    }
+
+    fn continue_statement(&mut self) -> Result<(), Error> {
+        let (loop_scope_depth, continue_target) = {
+            let info = self.enclosing_loop("continue")?;
+            (info.scope_depth, info.continue_target)
+        };
+        self.consume(scanner::TokenType::Semicolon, "Expected ; after continue.")?;
+
+        self.discard_locals_deeper_than(loop_scope_depth);
+        match continue_target {
+            Some(target) => self.emit_loop(target),
+            None => {
+                let jump = self.emit_jump(bytecode::Op::Jump(0));
+                self.current_loop_mut().continue_jumps.push(jump);
+            }
+        }
+        Ok(())
+    }
+
+    fn for_statement(&mut self) -> Result<(), Error> {
+        self.begin_scope();
+        self.consume(scanner::TokenType::LeftParen, "Expected ( after for.")?;
+
+        if self.matches(scanner::TokenType::Semicolon) {
+            // 没有初始化语句
+        } else if self.matches(scanner::TokenType::Var) {
+            if self.check(scanner::TokenType::Identifier) && self.check_next(scanner::TokenType::In) {
+                self.for_in_statement()?;
+                self.end_scope();
+                return Ok(());
+            }
+            self.var_decl()?;
+        } else {
+            self.expression_statement()?;
+        }
+
+        let mut loop_start = self.current_chunk().code.len();
+
+        let mut maybe_exit_jump = None;
+        if !self.matches(scanner::TokenType::Semicolon) {
+            self.expression()?;
+            self.consume(scanner::TokenType::Semicolon, "Expected ; after loop condition.")?;
+            maybe_exit_jump = Some(self.emit_jump(bytecode::Op::JumpIfFalse(0)));
+            self.emit_op(bytecode::Op::Pop, self.previous().line);
+        }
+        let maybe_exit_jump = maybe_exit_jump;
+        self.begin_loop(None);
+
+        if !self.matches(scanner::TokenType::RightParen) {
+            // 增量语句先跳过，循环体执行完了以后再跳回来执行它
+            let body_jump = self.emit_jump(bytecode::Op::Jump(0));
+
+            let increment_start = self.current_chunk().code.len();
+            self.expression()?;
+            self.emit_op(bytecode::Op::Pop, self.previous().line);
+            self.consume(scanner::TokenType::RightParen, "Expected ) after for clauses.")?;
+
+            self.emit_loop(loop_start);
+            loop_start = increment_start;
+            self.patch_jump(body_jump);
+        }
+        // continue 跳到增量语句，没有增量语句的时候跳到条件
+        self.current_loop_mut().continue_target = Some(loop_start);
+
+        self.statement()?;
+        self.emit_loop(loop_start);
+
+        if let Some(exit_jump) = maybe_exit_jump {
+            self.patch_jump(exit_jump);
+            self.emit_op(bytecode::Op::Pop, self.previous().line);
+        }
+        self.end_loop();
+
+        self.end_scope();
+
+        Ok(())
+    }
+
+    fn return_statement(&mut self) -> Result<(), Error> {
+        let return_tok = self.previous().clone();
+
+        if self.function_type() == FunctionType::Script {
+            return Err(
+                Error::Semantic(ErrorInfo {
+                    what: "Cannot return from top-level code.".to_string(),
+                    line: return_tok.line,
+                    col: return_tok.col,
+                })
+            );
+        }
+
+        if self.matches(scanner::TokenType::Semicolon) {
+            self.emit_return();
+            return Ok(());
+        }
+
+        if self.function_type() == FunctionType::Initializer {
+            return Err(
+                Error::Semantic(ErrorInfo {
+                    what: "Cannot return a value from an initializer.".to_string(),
+                    line: return_tok.line,
+                    col: return_tok.col,
+                })
+            );
+        }
+
+        if self.current_level().value_return.is_none() {
+            self.current_level_mut().value_return = Some(return_tok.clone());
+        }
+
+        self.expression()?;
+        self.consume(scanner::TokenType::Semicolon, "Expected ; after return value.")?;
+        self.mark_tail_call();
+        self.emit_op(bytecode::Op::Return, return_tok.line);
+        Ok(())
+    }
+
+    /*
+     * return 的表达式最后一步是调用的话就是尾调用。后面的 Return 保留着：
+     * 跳转可能落在它上面，调用的不是闭包的时候也还要靠它返回
+     */
+    fn mark_tail_call(&mut self) {
+        if let Some((op, _)) = self.current_chunk().code.last_mut() {
+            match op {
+                bytecode::Op::Call(arg_count) => {
+                    *op = bytecode::Op::TailCall(*arg_count, Vec::new());
+                }
+                bytecode::Op::CallNamed(arg_count, names) => {
+                    *op = bytecode::Op::TailCall(*arg_count, std::mem::take(names));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn yield_statement(&mut self) -> Result<(), Error> {
+        let yield_tok = self.previous().clone();
+
+        // 不带值的 yield 是把执行权让给别的 fiber，哪里都能写，也不会把函数变成生成器
+        if self.matches(scanner::TokenType::Semicolon) {
+            self.emit_op(bytecode::Op::YieldFiber, yield_tok.line);
+            return Ok(());
+        }
+
+        let what = match self.function_type() {
+            FunctionType::Script => Some("Cannot yield from top-level code."),
+            FunctionType::Initializer => Some("Cannot yield from an initializer."),
+            FunctionType::Async => Some("Cannot yield from an async function."),
+            FunctionType::Function | FunctionType::Method | FunctionType::Getter => None,
+        };
+        if let Some(what) = what {
+            return Err(
+                Error::Semantic(ErrorInfo {
+                    what: what.to_string(),
+                    line: yield_tok.line,
+                    col: yield_tok.col,
+                })
+            );
+        }
+        self.current_function_mut().is_generator = true;
+
+        self.expression()?;
+        self.consume(scanner::TokenType::Semicolon, "Expected ; after yield value.")?;
+        self.emit_op(bytecode::Op::Yield, yield_tok.line);
+        Ok(())
+    }
+
+    /**
+     * spawn f(...);  照常编译这个调用，再把最后的 Call 换成 Spawn。
+     * spawn obj.method(...); 编译出来是 Invoke，换成 SpawnInvoke，新的 fiber 开始的时候再找方法
+     */
+    fn spawn_statement(&mut self) -> Result<(), Error> {
+        let spawn_tok = self.previous().clone();
+        self.parse_precedence(Precedence::Call)?;
+        self.consume(scanner::TokenType::Semicolon, "Expected ; after spawn.")?;
+
+        if let Some((op, _)) = self.current_chunk().code.last_mut() {
+            let spawn = match op {
+                bytecode::Op::Call(arg_count) => Some(bytecode::Op::Spawn(*arg_count, Vec::new())),
+                bytecode::Op::CallNamed(arg_count, names) => {
+                    Some(bytecode::Op::Spawn(*arg_count, std::mem::take(names)))
+                }
+                bytecode::Op::Invoke(method_name, arg_count, _) => {
+                    Some(bytecode::Op::SpawnInvoke(std::mem::take(method_name), *arg_count, Vec::new()))
+                }
+                bytecode::Op::InvokeNamed(method_name, arg_count, names, _) => {
+                    Some(
+                        bytecode::Op::SpawnInvoke(
+                            std::mem::take(method_name),
+                            *arg_count,
+                            std::mem::take(names)
+                        )
+                    )
+                }
+                _ => None,
+            };
+            if let Some(spawn) = spawn {
+                *op = spawn;
+                return Ok(());
+            }
+        }
+        Err(
+            Error::Semantic(ErrorInfo {
+                what: "Expected a function call after spawn.".to_string(),
+                line: spawn_tok.line,
+                col: spawn_tok.col,
+            })
+        )
+    }
+
+    /* ---------- ---------- match ---------- ---------- */
+
+    /**
+     * 被匹配的值放在一个隐藏的局部变量里，每个分支是一个作用域，绑定的变量先初始化成 nil。
+     * 每一步检查都留下一个 bool，不满足就跳到下一个分支，满足就执行分支然后跳到最后
+     */
+    fn match_statement(&mut self) -> Result<(), Error> {
+        self.consume(scanner::TokenType::LeftParen, "Expected ( after match.")?;
+        self.expression()?;
+        self.consume(scanner::TokenType::RightParen, "Expected ) after match subject.")?;
+        self.consume(scanner::TokenType::LeftBrace, "Expected { before match arms.")?;
+
+        self.begin_scope();
+        self.add_local("(match)".to_string());
+        self.mark_initialized();
+        let subject_slot = self.locals().len() - 1;
+
+        let mut end_jumps = Vec::new();
+        while self.matches(scanner::TokenType::Case) {
+            self.begin_scope();
+            let pattern = self.pattern()?;
+
+            let mut bindings = Vec::new();
+            Compiler::pattern_bindings(&pattern, &mut bindings);
+            let mut names: Vec<String> = Vec::new();
+            for tok in bindings {
+                let name = Compiler::identifier_name(&tok);
+                if names.contains(&name) {
+                    return Err(
+                        Error::Semantic(ErrorInfo {
+                            what: format!("Duplicate binding '{}' in pattern.", name),
+                            line: tok.line,
+                            col: tok.col,
+                        })
+                    );
+                }
+                self.emit_op(bytecode::Op::Nil, tok.line);
+                self.add_local(name.clone());
+                self.mark_initialized();
+                names.push(name);
+            }
+
+            let mut fail_jumps = Vec::new();
+            self.match_pattern(&pattern, subject_slot, &mut Vec::new(), &mut fail_jumps)?;
+            if self.matches(scanner::TokenType::If) {
+                self.expression()?;
+                let line = self.previous().line;
+                fail_jumps.push(self.emit_jump(bytecode::Op::JumpIfFalse(0)));
+                self.emit_op(bytecode::Op::Pop, line);
+            }
+            self.consume(scanner::TokenType::FatArrow, "Expected => after pattern.")?;
+            self.statement()?;
+
+            let line = self.previous().line;
+            let scope_depth = self.scope_depth();
+            self.discard_locals_deeper_than(scope_depth - 1);
+            end_jumps.push(self.emit_jump(bytecode::Op::Jump(0)));
+
+            // 没匹配上的时候栈顶还留着那个 false
+            for jump in fail_jumps {
+                self.patch_jump(jump);
+            }
+            self.emit_op(bytecode::Op::Pop, line);
+            self.end_scope();
+        }
+        self.consume(scanner::TokenType::RightBrace, "Expected } after match arms.")?;
+
+        for jump in end_jumps {
+            self.patch_jump(jump);
+        }
+        self.end_scope();
+        Ok(())
+    }
+
+    /**
+     * pattern → "_" | literal | "-" NUMBER | IDENTIFIER
+     *         | "[" ( pattern ( "," pattern )* )? "]"
+     *         | IDENTIFIER "(" ( IDENTIFIER ( ":" pattern )? ( "," ... )* )? ")" ;
+     */
+    fn pattern(&mut self) -> Result<Pattern, Error> {
+        let tok = self.advance().clone();
+        match tok.ty {
+            scanner::TokenType::Identifier if tok.lexeme == b"_" => Ok(Pattern::Wildcard),
+            scanner::TokenType::Identifier => {
+                if !self.matches(scanner::TokenType::LeftParen) {
+                    return Ok(Pattern::Binding(tok));
+                }
+                let mut fields = Vec::new();
+                if !self.check(scanner::TokenType::RightParen) {
+                    loop {
+                        self.consume(
+                            scanner::TokenType::Identifier,
+                            "Expected field name in pattern."
+                        )?;
+                        let field_tok = self.previous().clone();
+                        let sub_pattern = if self.matches(scanner::TokenType::Colon) {
+                            self.pattern()?
+                        } else {
+                            Pattern::Binding(field_tok.clone())
+                        };
+                        fields.push((Compiler::identifier_name(&field_tok), sub_pattern));
+                        if !self.matches(scanner::TokenType::Comma) {
+                            break;
+                        }
+                    }
+                }
+                self.consume(scanner::TokenType::RightParen, "Expected ) after field patterns.")?;
+                Ok(Pattern::Instance(tok, fields))
+            }
+            scanner::TokenType::LeftBracket => {
+                let mut elements = Vec::new();
+                if !self.check(scanner::TokenType::RightBracket) {
+                    loop {
+                        elements.push(self.pattern()?);
+                        if !self.matches(scanner::TokenType::Comma) {
+                            break;
+                        }
+                    }
+                }
+                self.consume(scanner::TokenType::RightBracket, "Expected ] after list pattern.")?;
+                Ok(Pattern::List(elements))
+            }
+            scanner::TokenType::Minus if self.check(scanner::TokenType::Number) => {
+                let num_tok = self.advance().clone();
+                Ok(Pattern::Literal(num_tok, true))
+            }
+            | scanner::TokenType::Number
+            | scanner::TokenType::String
+            | scanner::TokenType::True
+            | scanner::TokenType::False
+            | scanner::TokenType::Nil => Ok(Pattern::Literal(tok, false)),
+            _ =>
+                Err(
+                    Error::Parse(ErrorInfo {
+                        what: format!("Expected pattern, found {:?}.", tok.ty),
+                        line: tok.line,
+                        col: tok.col,
+                    })
+                ),
+        }
+    }
+
+    fn pattern_bindings(pattern: &Pattern, bindings: &mut Vec<scanner::Token>) {
+        match pattern {
+            Pattern::Wildcard | Pattern::Literal(_, _) => {}
+            Pattern::Binding(tok) => bindings.push(tok.clone()),
+            Pattern::List(elements) => {
+                for element in elements {
+                    Compiler::pattern_bindings(element, bindings);
+                }
+            }
+            Pattern::Instance(_, fields) => {
+                for (_, field_pattern) in fields {
+                    Compiler::pattern_bindings(field_pattern, bindings);
+                }
+            }
+        }
+    }
+
+    fn match_pattern(
+        &mut self,
+        pattern: &Pattern,
+        subject_slot: usize,
+        path: &mut Vec<PatternStep>,
+        fail_jumps: &mut Vec<usize>
+    ) -> Result<(), Error> {
+        let line = self.previous().line;
+        match pattern {
+            Pattern::Wildcard => {}
+            Pattern::Binding(tok) => {
+                let name = Compiler::identifier_name(tok);
+                let slot = self.resolve_local(&name, tok)?.unwrap();
+                self.load_match_path(subject_slot, path, tok.line);
+                self.emit_op(bytecode::Op::SetLocal(slot), tok.line);
+                self.emit_op(bytecode::Op::Pop, tok.line);
+            }
+            Pattern::Literal(tok, negated) => {
+                self.load_match_path(subject_slot, path, tok.line);
+                self.pattern_literal(tok, *negated)?;
+                self.emit_op(bytecode::Op::Equal, tok.line);
+                self.emit_match_check(fail_jumps, tok.line);
+            }
+            Pattern::List(elements) => {
+                self.load_match_path(subject_slot, path, line);
+                self.emit_op(bytecode::Op::MatchList(elements.len()), line);
+                self.emit_match_check(fail_jumps, line);
+                for (idx, element) in elements.iter().enumerate() {
+                    path.push(PatternStep::Index(idx));
+                    self.match_pattern(element, subject_slot, path, fail_jumps)?;
+                    path.pop();
+                }
+            }
+            Pattern::Instance(class_tok, fields) => {
+                self.load_match_path(subject_slot, path, class_tok.line);
+                self.named_variable(class_tok.clone())?;
+                self.emit_op(bytecode::Op::InstanceOf, class_tok.line);
+                self.emit_match_check(fail_jumps, class_tok.line);
+                for (field, field_pattern) in fields {
+                    // 没有这个字段的实例匹配失败，不报错
+                    self.load_match_path(subject_slot, path, class_tok.line);
+                    let name_idx = self.identifier_constant(field.clone());
+                    self.emit_op(bytecode::Op::HasProperty(name_idx), class_tok.line);
+                    self.emit_match_check(fail_jumps, class_tok.line);
+                    path.push(PatternStep::Field(field.clone()));
+                    self.match_pattern(field_pattern, subject_slot, path, fail_jumps)?;
+                    path.pop();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn load_match_path(&mut self, subject_slot: usize, path: &[PatternStep], line: usize) {
+        self.emit_op(bytecode::Op::GetLocal(subject_slot), line);
+        for step in path {
+            match step {
+                PatternStep::Index(idx) => {
+                    let const_idx = self.current_chunk().add_constant_integer(*idx as i64);
+                    self.emit_op(bytecode::Op::Constant(const_idx), line);
+                    self.emit_op(bytecode::Op::Subscr, line);
+                }
+                PatternStep::Field(name) => {
+                    let name_idx = self.identifier_constant(name.clone());
+                    let cache = self.current_chunk().add_inline_cache();
+                    self.emit_op(bytecode::Op::GetProperty(name_idx, cache), line);
+                }
+            }
+        }
+    }
+
+    fn emit_match_check(&mut self, fail_jumps: &mut Vec<usize>, line: usize) {
+        fail_jumps.push(self.emit_jump(bytecode::Op::JumpIfFalse(0)));
+        self.emit_op(bytecode::Op::Pop, line);
+    }
+
+    fn pattern_literal(&mut self, tok: &scanner::Token, negated: bool) -> Result<(), Error> {
+        let sign = if negated { -1 } else { 1 };
+        match (&tok.ty, &tok.literal) {
+            (scanner::TokenType::Number, Some(scanner::Literal::Number(n))) => {
+                let const_idx = self.current_chunk().add_constant_number((sign as f64) * n);
+                self.emit_op(bytecode::Op::Constant(const_idx), tok.line);
+            }
+            (scanner::TokenType::Number, Some(scanner::Literal::Integer(n))) => {
+                let const_idx = self.current_chunk().add_constant_integer(sign * n);
+                self.emit_op(bytecode::Op::Constant(const_idx), tok.line);
+            }
+            (scanner::TokenType::String, Some(scanner::Literal::Str(s))) => {
+                let const_idx = self.current_chunk().add_constant_string(s.clone());
+                self.emit_op(bytecode::Op::Constant(const_idx), tok.line);
+            }
+            (scanner::TokenType::Nil, _) => self.emit_op(bytecode::Op::Nil, tok.line),
+            (scanner::TokenType::True, _) => self.emit_op(bytecode::Op::True, tok.line),
+            (scanner::TokenType::False, _) => self.emit_op(bytecode::Op::False, tok.line),
+            _ => {
+                return Err(Error::Internal(format!("Invalid token {:?} in pattern", tok.ty)));
+            }
+        }
+        Ok(())
+    }
+
+    fn block(&mut self) -> Result<(), Error> {
+        while !self.check(scanner::TokenType::RightBrace) && !self.is_at_end() {
+            self.declaration()?;
+        }
+
+        self.consume(scanner::TokenType::RightBrace, "Expected } after block.")?;
+        Ok(())
+    }
+
+    /* ---------- ---------- expressions ---------- ---------- */
+
+    fn expression(&mut self) -> Result<(), Error> {
+        // 嵌套的表达式（下标、参数……）不能拿走外面的前缀 ++ / --
+        let pending_increment = self.pending_increment.take();
+        self.parse_precedence(Precedence::Assignment)?;
+        self.pending_increment = pending_increment;
+        Ok(())
+    }
+
+    fn parse_precedence(&mut self, precedence: Precedence) -> Result<(), Error> {
+        self.advance();
+
+        let can_assign = precedence <= Precedence::Assignment;
+
+        match self.get_rule(self.previous().ty).prefix {
+            Some(parse_fn) => self.apply_parse_fn(parse_fn, can_assign)?,
+            None => {
+                return Err(self.error_at_previous("Expected expression."));
+            }
+        }
+
+        while precedence <= self.get_rule(self.peek().ty).precedence {
+            self.advance();
+            match self.get_rule(self.previous().ty).infix {
+                Some(parse_fn) => self.apply_parse_fn(parse_fn, can_assign)?,
+                None => {
+                    return Err(
+                        Error::Internal(
+                            format!(
+                                "Expected infix operator, found {:?} at line={},col={}",
+                                self.previous().ty,
+                                self.previous().line,
+                                self.previous().col
+                            )
+                        )
+                    );
+                }
+            }
+        }
+
+        if
+            can_assign &&
+            (self.matches(scanner::TokenType::Equal) || self.match_compound_assign().is_some())
+        {
+            return Err(self.error_at_previous("Invalid assignment target."));
+        }
+
+        Ok(())
+    }
+
+    fn apply_parse_fn(&mut self, parse_fn: ParseFn, can_assign: bool) -> Result<(), Error> {
+        match parse_fn {
+            ParseFn::Grouping => self.grouping(),
+            ParseFn::Unary => self.unary(),
+            ParseFn::Binary => self.binary(),
+            ParseFn::Number => self.number(),
+            ParseFn::Literal => self.literal(),
+            ParseFn::String => self.string(),
+            ParseFn::Interpolation => self.interpolation(),
+            ParseFn::Variable => self.variable(can_assign),
+            ParseFn::And => self.and(),
+            ParseFn::Or => self.or(),
+            ParseFn::Call => self.call(),
+            ParseFn::Dot => self.dot(can_assign),
+            ParseFn::This => self.this(),
+            ParseFn::Super => self.super_(),
+            ParseFn::List => self.list(),
+            ParseFn::Subscript => self.subscript(can_assign),
+            ParseFn::Lambda => self.lambda(),
+            ParseFn::PreIncrement => self.pre_increment(),
+        }
+    }
+
+    fn grouping(&mut self) -> Result<(), Error> {
+        self.expression()?;
+        self.consume(scanner::TokenType::RightParen, "Expected ) after expression.")?;
+        Ok(())
+    }
+
+    fn unary(&mut self) -> Result<(), Error> {
+        let operator = self.previous().clone();
+
+        self.parse_precedence(Precedence::Unary)?;
+
+        match operator.ty {
+            scanner::TokenType::Minus => {
+                self.emit_op(bytecode::Op::Negate, operator.line);
+                Ok(())
+            }
+            scanner::TokenType::Bang => {
+                self.emit_op(bytecode::Op::Not, operator.line);
+                Ok(())
+            }
+            scanner::TokenType::Await => {
+                self.emit_op(bytecode::Op::Await, operator.line);
+                Ok(())
+            }
+            _ =>
+                Err(
+                    Error::Internal(
+                        format!("Invalid token {:?} in unary expression", operator.ty)
+                    )
+                ),
+        }
+    }
+
+    fn binary(&mut self) -> Result<(), Error> {
+        let operator = self.previous().clone();
+        let rule = self.get_rule(operator.ty);
+
+        if operator.ty == scanner::TokenType::StarStar {
+            // 右结合，右边还可以是一元表达式：2 ** -1
+            self.parse_precedence(Precedence::Unary)?;
+        } else {
+            self.parse_precedence(rule.precedence.next())?;
+        }
+
+        let line = operator.line;
+        match operator.ty {
+            scanner::TokenType::Plus => self.emit_op(bytecode::Op::Add, line),
+            scanner::TokenType::Minus => self.emit_op(bytecode::Op::Subtract, line),
+            scanner::TokenType::Star => self.emit_op(bytecode::Op::Multiply, line),
+            scanner::TokenType::Slash => self.emit_op(bytecode::Op::Divide, line),
+            scanner::TokenType::Percent => self.emit_op(bytecode::Op::Modulo, line),
+            scanner::TokenType::StarStar => self.emit_op(bytecode::Op::Power, line),
+            scanner::TokenType::TildeSlash => self.emit_op(bytecode::Op::IntDivide, line),
+            scanner::TokenType::Ampersand => self.emit_op(bytecode::Op::BitAnd, line),
+            scanner::TokenType::Pipe => self.emit_op(bytecode::Op::BitOr, line),
+            scanner::TokenType::Caret => self.emit_op(bytecode::Op::BitXor, line),
+            scanner::TokenType::LessLess => self.emit_op(bytecode::Op::ShiftLeft, line),
+            scanner::TokenType::GreaterGreater => self.emit_op(bytecode::Op::ShiftRight, line),
+            scanner::TokenType::BangEqual => {
+                self.emit_op(bytecode::Op::Equal, line);
+                self.emit_op(bytecode::Op::Not, line);
+            }
+            scanner::TokenType::EqualEqual => self.emit_op(bytecode::Op::Equal, line),
+            scanner::TokenType::Greater => self.emit_op(bytecode::Op::Greater, line),
+            scanner::TokenType::GreaterEqual => {
+                self.emit_op(bytecode::Op::Less, line);
+                self.emit_op(bytecode::Op::Not, line);
+            }
+            scanner::TokenType::Less => self.emit_op(bytecode::Op::Less, line),
+            scanner::TokenType::InstanceOf => self.emit_op(bytecode::Op::InstanceOf, line),
+            scanner::TokenType::LessEqual => {
+                self.emit_op(bytecode::Op::Greater, line);
+                self.emit_op(bytecode::Op::Not, line);
+            }
+            _ => {
+                return Err(
+                    Error::Internal(
+                        format!("Invalid token {:?} in binary expression", operator.ty)
+                    )
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    fn number(&mut self) -> Result<(), Error> {
+        let tok = self.previous().clone();
+        match tok.literal {
+            Some(scanner::Literal::Number(n)) => {
+                let const_idx = self.current_chunk().add_constant_number(n);
+                self.emit_op(bytecode::Op::Constant(const_idx), tok.line);
+                Ok(())
+            }
+            Some(scanner::Literal::Integer(n)) => {
+                let const_idx = self.current_chunk().add_constant_integer(n);
+                self.emit_op(bytecode::Op::Constant(const_idx), tok.line);
+                Ok(())
+            }
+            _ =>
+                Err(
+                    Error::Internal(
+                        format!(
+                            "Expected number at line={},col={}. current token {:?}",
+                            tok.line,
+                            tok.col,
+                            tok
+                        )
+                    )
+                ),
+        }
+    }
+
+    fn literal(&mut self) -> Result<(), Error> {
+        let tok = self.previous().clone();
+        match tok.ty {
+            scanner::TokenType::Nil => self.emit_op(bytecode::Op::Nil, tok.line),
+            scanner::TokenType::True => self.emit_op(bytecode::Op::True, tok.line),
+            scanner::TokenType::False => self.emit_op(bytecode::Op::False, tok.line),
+            _ => {
+                return Err(Error::Internal(format!("Invalid token {:?} in literal", tok.ty)));
+            }
+        }
+        Ok(())
+    }
+
+    /**
+     * "a ${b} c"：每一段依次压栈，最后用 Interpolate 拼起来，空的字符串片段不压栈
+     */
+    fn interpolation(&mut self) -> Result<(), Error> {
+        let line = self.previous().line;
+        let mut num_parts = 0;
+        loop {
+            num_parts += self.interpolation_segment();
+            self.expression()?;
+            num_parts += 1;
+
+            if self.matches(scanner::TokenType::Interpolation) {
+                continue;
+            }
+            self.consume(scanner::TokenType::String, "Expected end of string interpolation.")?;
+            num_parts += self.interpolation_segment();
+            self.emit_op(bytecode::Op::Interpolate(num_parts), line);
+            return Ok(());
+        }
+    }
+
+    fn interpolation_segment(&mut self) -> usize {
+        let tok = self.previous().clone();
+        match tok.literal {
+            Some(scanner::Literal::Str(s)) if !s.is_empty() => {
+                let const_idx = self.current_chunk().add_constant_string(s);
+                self.emit_op(bytecode::Op::Constant(const_idx), tok.line);
+                1
+            }
+            _ => 0,
+        }
+    }
+
+    fn string(&mut self) -> Result<(), Error> {
+        let tok = self.previous().clone();
+        match tok.literal {
+            Some(scanner::Literal::Str(s)) => {
+                let const_idx = self.current_chunk().add_constant_string(s);
+                self.emit_op(bytecode::Op::Constant(const_idx), tok.line);
+                Ok(())
+            }
+            _ =>
+                Err(
+                    Error::Internal(
+                        format!("Expected string at line={},col={}", tok.line, tok.col)
+                    )
+                ),
+        }
+    }
+
+    fn variable(&mut self, can_assign: bool) -> Result<(), Error> {
+        let tok = self.previous().clone();
+        let lvalue = self.resolve_variable(&tok)?;
+        let name = Compiler::identifier_name(&tok);
+        if self.assignment_follows(can_assign) && self.is_const_variable(&name) {
+            return Err(
+                Error::Semantic(ErrorInfo {
+                    what: format!("Cannot assign to constant {}.", name),
+                    line: tok.line,
+                    col: tok.col,
+                })
+            );
+        }
+        self.finish_lvalue(lvalue, can_assign, tok.line)
+    }
+
+    /**
+     * 和 finish_lvalue 的判断保持一致：接下来是不是要给刚才的变量赋值
+     */
+    fn assignment_follows(&self, can_assign: bool) -> bool {
+        if
+            can_assign &&
+            (self.check(scanner::TokenType::Equal) ||
+                Compiler::compound_assign_op(self.peek().ty).is_some())
+        {
+            return true;
+        }
+        match self.pending_increment {
+            Some(_) =>
+                !self.check(scanner::TokenType::Dot) &&
+                    !self.check(scanner::TokenType::LeftParen) &&
+                    !self.check(scanner::TokenType::LeftBracket),
+            None =>
+                self.check(scanner::TokenType::PlusPlus) ||
+                    self.check(scanner::TokenType::MinusMinus),
+        }
+    }
+
+    /**
+     * 按 resolve_variable 的顺序找：当前函数的局部变量，外层函数的局部变量（上值），最后是全局变量
+     */
+    fn is_const_variable(&self, name: &str) -> bool {
+        for level in self.levels.iter().rev() {
+            if let Some(local) = level.locals.iter().rev().find(|local| local.name == name) {
+                return local.is_const;
+            }
+        }
+        self.const_globals.contains(name)
+    }
+
+    /**
+     * 只读取变量，编译 class / super 的时候用
+     */
+    fn named_variable(&mut self, tok: scanner::Token) -> Result<(), Error> {
+        let lvalue = self.resolve_variable(&tok)?;
+        self.emit_get(lvalue, tok.line);
+        Ok(())
+    }
+
+    fn resolve_variable(&mut self, tok: &scanner::Token) -> Result<LValue, Error> {
+        let name = Compiler::identifier_name(tok);
+
+        if let Some(idx) = self.resolve_local(&name, tok)? {
+            return Ok(LValue::Local(idx));
+        }
+        if let Some(idx) = self.resolve_upval(&name, tok)? {
+            return Ok(LValue::Upval(idx));
+        }
+        Ok(LValue::Global(self.identifier_constant(name)))
+    }
+
+    /**
+     * 目标已经确定了，看后面跟的是 = 、复合赋值、++ / -- 还是单纯的读取
+     */
+    fn finish_lvalue(&mut self, lvalue: LValue, can_assign: bool, line: usize) -> Result<(), Error> {
+        if can_assign && self.matches(scanner::TokenType::Equal) {
+            self.expression()?;
+            self.emit_set(lvalue, line);
+            return Ok(());
+        }
+
+        if can_assign {
+            if let Some(op) = self.match_compound_assign() {
+                return self.emit_update(lvalue, op, false, false, line);
+            }
+        }
+
+        // ++a.b[0]：后面还有 . ( [ 的话，目标还没到
+        if
+            self.pending_increment.is_some() &&
+            !self.check(scanner::TokenType::Dot) &&
+            !self.check(scanner::TokenType::LeftParen) &&
+            !self.check(scanner::TokenType::LeftBracket)
+        {
+            let operator = self.pending_increment.take().unwrap();
+            return self.emit_update(lvalue, Compiler::increment_op(operator.ty), true, false, line);
+        }
+
+        if
+            self.pending_increment.is_none() &&
+            (self.matches(scanner::TokenType::PlusPlus) ||
+                self.matches(scanner::TokenType::MinusMinus))
+        {
+            let op = Compiler::increment_op(self.previous().ty);
+            return self.emit_update(lvalue, op, true, true, line);
+        }
+
+        self.emit_get(lvalue, line);
+        Ok(())
+    }
+
+    /**
+     * 读出旧值，和右边做运算，再写回去
+     * 复合赋值的右边是一个表达式，++ / -- 的右边就是常量 1
+     */
+    fn emit_update(
+        &mut self,
+        lvalue: LValue,
+        op: bytecode::Op,
+        increment: bool,
+        postfix: bool,
+        line: usize
+    ) -> Result<(), Error> {
+        let num_operands = lvalue.num_operands();
+
+        // 操作数复制一份，一份给读，一份给写
+        for _ in 0..num_operands {
+            self.emit_op(bytecode::Op::Dup(num_operands - 1), line);
+        }
+        self.emit_get(lvalue, line);
+
+        if postfix {
+            // 旧的值挪到操作数下面，最后留在栈上作为整个表达式的值
+            if num_operands > 0 {
+                self.emit_op(bytecode::Op::Rot(num_operands), line);
+            }
+            self.emit_op(bytecode::Op::Dup(num_operands), line);
+        }
+
+        if increment {
+            let const_idx = self.current_chunk().add_constant_integer(1);
+            self.emit_op(bytecode::Op::Constant(const_idx), line);
+        } else {
+            self.expression()?;
+        }
+        self.emit_op(op, line);
+        self.emit_set(lvalue, line);
+
+        if postfix {
+            self.emit_op(bytecode::Op::Pop, line);
+        }
+        Ok(())
+    }
+
+    fn emit_get(&mut self, lvalue: LValue, line: usize) {
+        let op = match lvalue {
+            LValue::Local(idx) => bytecode::Op::GetLocal(idx),
+            LValue::Upval(idx) => bytecode::Op::GetUpval(idx),
+            LValue::Global(idx) => bytecode::Op::GetGlobal(idx),
+            LValue::Property(idx) => {
+                bytecode::Op::GetProperty(idx, self.current_chunk().add_inline_cache())
+            }
+            LValue::Item => bytecode::Op::Subscr,
+        };
+        self.emit_op(op, line);
+    }
+
+    fn emit_set(&mut self, lvalue: LValue, line: usize) {
+        let op = match lvalue {
+            LValue::Local(idx) => bytecode::Op::SetLocal(idx),
+            LValue::Upval(idx) => bytecode::Op::SetUpval(idx),
+            LValue::Global(idx) => bytecode::Op::SetGlobal(idx),
+            LValue::Property(idx) => {
+                bytecode::Op::SetProperty(idx, self.current_chunk().add_inline_cache())
+            }
+            LValue::Item => bytecode::Op::SetItem,
+        };
+        self.emit_op(op, line);
+    }
+
+    fn match_compound_assign(&mut self) -> Option<bytecode::Op> {
+        let op = Compiler::compound_assign_op(self.peek().ty)?;
+        self.advance();
+        Some(op)
+    }
+
+    fn compound_assign_op(ty: scanner::TokenType) -> Option<bytecode::Op> {
+        match ty {
+            scanner::TokenType::PlusEqual => Some(bytecode::Op::Add),
+            scanner::TokenType::MinusEqual => Some(bytecode::Op::Subtract),
+            scanner::TokenType::StarEqual => Some(bytecode::Op::Multiply),
+            scanner::TokenType::SlashEqual => Some(bytecode::Op::Divide),
+            _ => None,
+        }
+    }
+
+    fn increment_op(ty: scanner::TokenType) -> bytecode::Op {
+        if ty == scanner::TokenType::PlusPlus {
+            bytecode::Op::Add
+        } else {
+            bytecode::Op::Subtract
+        }
+    }
+
+    /**
+     * ++x / --x：先记下来，等 variable / dot / subscript 找到目标以后再生成代码
+     */
+    fn pre_increment(&mut self) -> Result<(), Error> {
+        if self.pending_increment.is_some() {
+            return Err(self.error_at_previous("Invalid increment target."));
+        }
+        self.pending_increment = Some(self.previous().clone());
+        self.parse_precedence(Precedence::Call)?;
+        if self.pending_increment.take().is_some() {
+            return Err(self.error_at_previous("Invalid increment target."));
+        }
+        Ok(())
+    }
+
+    fn and(&mut self) -> Result<(), Error> {
+        let end_jump = self.emit_jump(bytecode::Op::JumpIfFalse(0));
+        self.emit_op(bytecode::Op::Pop, self.previous().line);
+        self.parse_precedence(Precedence::And)?;
+        self.patch_jump(end_jump);
+        Ok(())
+    }
+
+    fn or(&mut self) -> Result<(), Error> {
+        let else_jump = self.emit_jump(bytecode::Op::JumpIfFalse(0));
+        let end_jump = self.emit_jump(bytecode::Op::Jump(0));
+
+        self.patch_jump(else_jump);
+        self.emit_op(bytecode::Op::Pop, self.previous().line);
+
+        self.parse_precedence(Precedence::Or)?;
+        self.patch_jump(end_jump);
+        Ok(())
+    }
+
+    fn call(&mut self) -> Result<(), Error> {
+        let line = self.previous().line;
+        let (arg_count, names) = self.argument_list()?;
+        if names.is_empty() {
+            self.emit_op(bytecode::Op::Call(arg_count), line);
+        } else {
+            self.emit_op(bytecode::Op::CallNamed(arg_count, names), line);
+        }
+        Ok(())
+    }
+
+    /**
+     * 命名参数 name: value 只能放在位置参数后面，返回实参个数和命名参数的名字
+     */
+    fn argument_list(&mut self) -> Result<(u8, Vec<String>), Error> {
+        let mut num_args: usize = 0;
+        let mut names: Vec<String> = Vec::new();
+        if !self.check(scanner::TokenType::RightParen) {
+            loop {
+                if
+                    self.check(scanner::TokenType::Identifier) &&
+                    self.check_next(scanner::TokenType::Colon)
+                {
+                    let name = Compiler::identifier_name(self.advance());
+                    if names.contains(&name) {
+                        return Err(
+                            self.error_at_previous(
+                                &format!("Argument '{}' passed more than once.", name)
+                            )
+                        );
+                    }
+                    names.push(name);
+                    self.advance();
+                } else if !names.is_empty() {
+                    return Err(
+                        Error::Parse(ErrorInfo {
+                            what: "Positional argument cannot follow named arguments.".to_string(),
+                            line: self.peek().line,
+                            col: self.peek().col,
+                        })
+                    );
+                }
+                self.expression()?;
+                if num_args == 255 {
+                    return Err(self.error_at_previous("Cannot have more than 255 arguments."));
+                }
+                num_args += 1;
+                if !self.matches(scanner::TokenType::Comma) {
+                    break;
+                }
+            }
+        }
+        self.consume(scanner::TokenType::RightParen, "Expected ) after argument list.")?;
+        Ok((num_args as u8, names))
+    }
+
+    fn dot(&mut self, can_assign: bool) -> Result<(), Error> {
+        self.consume(scanner::TokenType::Identifier, "Expected property name after '.'.")?;
+        let property_tok = self.previous().clone();
+        let property_name = Compiler::identifier_name(&property_tok);
+        let property_constant = self.identifier_constant(property_name.clone());
+
+        if self.matches(scanner::TokenType::LeftParen) {
+            // obj.method(args) 直接用 Invoke，省掉一个 BoundMethod
+            let (arg_count, names) = self.argument_list()?;
+            let cache = self.current_chunk().add_inline_cache();
+            let op = if names.is_empty() {
+                bytecode::Op::Invoke(property_name, arg_count, cache)
+            } else {
+                bytecode::Op::InvokeNamed(property_name, arg_count, names, cache)
+            };
+            self.emit_op(op, property_tok.line);
+            return Ok(());
+        }
+
+        self.finish_lvalue(LValue::Property(property_constant), can_assign, property_tok.line)
+    }
+
+    fn this(&mut self) -> Result<(), Error> {
+        if self.classes.is_empty() {
+            return Err(self.error_at_previous("Cannot use 'this' outside of a class."));
+        }
+        let tok = self.previous().clone();
+        self.named_variable(tok)
+    }
+
+    fn super_(&mut self) -> Result<(), Error> {
+        let super_tok = self.previous().clone();
+        match self.classes.last() {
+            None => {
+                return Err(self.error_at_previous("Cannot use 'super' outside of a class."));
+            }
+            Some(class) if !class.has_superclass => {
+                return Err(
+                    self.error_at_previous("Cannot use 'super' in a class with no superclass.")
+                );
+            }
+            _ => {}
+        }
+
+        self.consume(scanner::TokenType::Dot, "Expected '.' after 'super'.")?;
+        self.consume(scanner::TokenType::Identifier, "Expected superclass method name.")?;
+        let method_name = Compiler::identifier_name(self.previous());
+        let name_constant = self.identifier_constant(method_name.clone());
+
+        self.named_variable(Compiler::synthetic_token(&super_tok, "this"))?;
+        if self.matches(scanner::TokenType::LeftParen) {
+            let (arg_count, names) = self.argument_list()?;
+            self.named_variable(Compiler::synthetic_token(&super_tok, "super"))?;
+            let op = if names.is_empty() {
+                bytecode::Op::SuperInvoke(method_name, arg_count)
+            } else {
+                bytecode::Op::SuperInvokeNamed(method_name, arg_count, names)
+            };
+            self.emit_op(op, super_tok.line);
+        } else {
+            self.named_variable(Compiler::synthetic_token(&super_tok, "super"))?;
+            self.emit_op(bytecode::Op::GetSuper(name_constant), super_tok.line);
+        }
+        Ok(())
+    }
+
+    fn list(&mut self) -> Result<(), Error> {
+        let line = self.previous().line;
+        let mut num_elements = 0;
+        if !self.check(scanner::TokenType::RightBracket) {
+            loop {
+                self.expression()?;
+                num_elements += 1;
+                if !self.matches(scanner::TokenType::Comma) {
+                    break;
+                }
+            }
+        }
+        self.consume(scanner::TokenType::RightBracket, "Expected ].")?;
+        self.emit_op(bytecode::Op::BuildList(num_elements), line);
+        Ok(())
+    }
+
+    fn subscript(&mut self, can_assign: bool) -> Result<(), Error> {
+        // list[a:b]，两端都可以省略，省略的一端用 nil 占位
+        if self.matches(scanner::TokenType::Colon) {
+            self.emit_op(bytecode::Op::Nil, self.previous().line);
+            return self.finish_slice();
+        }
+
+        self.expression()?;
+        if self.matches(scanner::TokenType::Colon) {
+            return self.finish_slice();
+        }
+        self.consume(scanner::TokenType::RightBracket, "Expected ] after subscript.")?;
+        let line = self.previous().line;
+        self.finish_lvalue(LValue::Item, can_assign, line)
+    }
+
+    /**
+     * 切片的结果是一个新的列表，不能赋值
+     */
+    fn finish_slice(&mut self) -> Result<(), Error> {
+        if self.check(scanner::TokenType::RightBracket) {
+            self.emit_op(bytecode::Op::Nil, self.peek().line);
+        } else {
+            self.expression()?;
+        }
+        self.consume(scanner::TokenType::RightBracket, "Expected ] after slice.")?;
+        self.emit_op(bytecode::Op::Slice, self.previous().line);
+        Ok(())
+    }
+
+    fn lambda(&mut self) -> Result<(), Error> {
+        self.function("lambda".to_string(), FunctionType::Function)
+    }
+
+    /* ---------- ---------- 变量与作用域 ---------- ---------- */
+
+    fn parse_variable(&mut self, error_msg: &str) -> Result<usize, Error> {
+        self.consume(scanner::TokenType::Identifier, error_msg)?;
+        self.declare_variable()?;
+
+        if self.scope_depth() > 0 {
+            return Ok(0);
+        }
+
+        let name = Compiler::identifier_name(self.previous());
+        Ok(self.identifier_constant(name))
+    }
+
+    fn identifier_constant(&mut self, name: String) -> usize {
+        self.current_chunk().add_constant_string(name)
+    }
+
+    fn declare_variable(&mut self) -> Result<(), Error> {
+        let tok = self.previous().clone();
+        let name = Compiler::identifier_name(&tok);
+
+        // 全局变量是动态绑定的，不需要声明，只是常量不能被覆盖
+        if self.scope_depth() == 0 {
+            if self.const_globals.contains(&name) {
+                return Err(
+                    Error::Semantic(ErrorInfo {
+                        what: format!("Cannot redefine constant {}.", name),
+                        line: tok.line,
+                        col: tok.col,
+                    })
+                );
+            }
+            return Ok(());
+        }
+
+        let scope_depth = self.scope_depth();
+        let has_redeclaration = self
+            .locals()
+            .iter()
+            .rev()
+            .take_while(|local| local.depth == -1 || local.depth >= scope_depth)
+            .any(|local| local.name == name);
+        if has_redeclaration {
+            return Err(
+                Error::Semantic(ErrorInfo {
+                    what: format!(
+                        "Redeclaration of variable {} in the same scope.",
+                        name
+                    ),
+                    line: tok.line,
+                    col: tok.col,
+                })
+            );
+        }
+
+        self.add_local(name);
+        Ok(())
+    }
+
+    fn add_local(&mut self, name: String) {
+        self.locals_mut().push(Local {
+            name,
+            depth: -1,
+            is_captured: false,
+            is_const: false,
+        });
+    }
+
+    fn define_variable(&mut self, global_idx: usize) {
+        if self.scope_depth() > 0 {
+            self.mark_initialized();
+            return;
+        }
+        let line = self.previous().line;
+        self.emit_op(bytecode::Op::DefineGlobal(global_idx), line);
+    }
+
+    fn mark_initialized(&mut self) {
+        let scope_depth = self.scope_depth();
+        if scope_depth == 0 {
+            return;
+        }
+        self.locals_mut().last_mut().unwrap().depth = scope_depth;
+    }
+
+    fn begin_scope(&mut self) {
+        self.current_level_mut().scope_depth += 1;
+    }
+
+    fn end_scope(&mut self) {
+        self.current_level_mut().scope_depth -= 1;
+        let scope_depth = self.scope_depth();
+        let line = self.previous().line;
+
+        loop {
+            let is_captured = match self.locals().last() {
+                Some(local) if local.depth > scope_depth => local.is_captured,
+                _ => {
+                    break;
+                }
+            };
+            if is_captured {
+                self.emit_op(bytecode::Op::CloseUpvalue, line);
+            } else {
+                self.emit_op(bytecode::Op::Pop, line);
+            }
+            self.locals_mut().pop();
+        }
+    }
+
+    fn begin_loop(&mut self, continue_target: Option<usize>) {
+        let scope_depth = self.scope_depth();
+        self.current_level_mut().loops.push(LoopInfo {
+            continue_target,
+            continue_jumps: Vec::new(),
+            break_jumps: Vec::new(),
+            scope_depth,
+        });
+    }
+
+    /**
+     * do-while 的条件从这里开始，之前的 continue 都跳到这里
+     */
+    fn start_continue_target(&mut self) {
+        let target = self.current_chunk().code.len();
+        let continue_jumps = std::mem::take(&mut self.current_loop_mut().continue_jumps);
+        for jump in continue_jumps {
+            self.patch_jump(jump);
+        }
+        self.current_loop_mut().continue_target = Some(target);
+    }
+
+    /**
+     * 循环已经编译完了，break 跳到这里
+     */
+    fn end_loop(&mut self) {
+        if let Some(info) = self.current_level_mut().loops.pop() {
+            for jump in info.break_jumps {
+                self.patch_jump(jump);
+            }
+        }
+    }
+
+    fn enclosing_loop(&self, keyword: &str) -> Result<&LoopInfo, Error> {
+        match self.current_level().loops.last() {
+            Some(info) => Ok(info),
+            None => {
+                let tok = self.previous();
+                Err(
+                    Error::Semantic(ErrorInfo {
+                        what: format!("Cannot use {} outside of a loop.", keyword),
+                        line: tok.line,
+                        col: tok.col,
+                    })
+                )
+            }
+        }
+    }
+
+    fn current_loop_mut(&mut self) -> &mut LoopInfo {
+        self.current_level_mut().loops.last_mut().unwrap()
+    }
+
+    /**
+     * break / continue 跳出作用域之前把里面的局部变量弹出去（被捕获的要关闭上值），
+     * 编译器里面的 locals 不动，后面的代码还在这些作用域里面
+     */
+    fn discard_locals_deeper_than(&mut self, scope_depth: i64) {
+        let line = self.previous().line;
+        let captured: Vec<bool> = self
+            .locals()
+            .iter()
+            .rev()
+            .take_while(|local| local.depth > scope_depth)
+            .map(|local| local.is_captured)
+            .collect();
+        for is_captured in captured {
+            if is_captured {
+                self.emit_op(bytecode::Op::CloseUpvalue, line);
+            } else {
+                self.emit_op(bytecode::Op::Pop, line);
+            }
+        }
+    }
+
+    fn resolve_local(&self, name: &str, tok: &scanner::Token) -> Result<Option<usize>, Error> {
+        Compiler::resolve_local_in(self.current_level(), name, tok)
+    }
+
+    fn resolve_local_in(
+        level: &Level,
+        name: &str,
+        tok: &scanner::Token
+    ) -> Result<Option<usize>, Error> {
+        for (idx, local) in level.locals.iter().enumerate().rev() {
+            if local.name == name {
+                if local.depth == -1 {
+                    return Err(
+                        Error::Semantic(ErrorInfo {
+                            what: "Cannot read local variable in its own initializer.".to_string(),
+                            line: tok.line,
+                            col: tok.col,
+                        })
+                    );
+                }
+                return Ok(Some(idx));
+            }
+        }
+        Ok(None)
+    }
+
+    fn resolve_upval(&mut self, name: &str, tok: &scanner::Token) -> Result<Option<usize>, Error> {
+        let level_idx = self.levels.len() - 1;
+        self.resolve_upval_in(level_idx, name, tok)
+    }
+
+    /**
+     * 从 level_idx 往外一层一层的找，找到了就一路把上值登记下来
+     */
+    fn resolve_upval_in(
+        &mut self,
+        level_idx: usize,
+        name: &str,
+        tok: &scanner::Token
+    ) -> Result<Option<usize>, Error> {
+        if level_idx == 0 {
+            return Ok(None);
+        }
+
+        if let Some(local_idx) = Compiler::resolve_local_in(&self.levels[level_idx - 1], name, tok)? {
+            self.levels[level_idx - 1].locals[local_idx].is_captured = true;
+            return Ok(Some(self.add_upval(level_idx, bytecode::UpvalueLoc::Local(local_idx))));
+        }
+
+        if let Some(upval_idx) = self.resolve_upval_in(level_idx - 1, name, tok)? {
+            return Ok(Some(self.add_upval(level_idx, bytecode::UpvalueLoc::Upvalue(upval_idx))));
+        }
+
+        Ok(None)
+    }
+
+    fn add_upval(&mut self, level_idx: usize, upval: bytecode::UpvalueLoc) -> usize {
+        let upvals = &mut self.levels[level_idx].upvals;
+        if let Some(idx) = upvals.iter().position(|existing| *existing == upval) {
+            return idx;
+        }
+        upvals.push(upval);
+        upvals.len() - 1
+    }
+
+    /* ---------- ---------- 生成字节码 ---------- ---------- */
+
+    fn emit_op(&mut self, op: bytecode::Op, line: usize) {
+        self.current_chunk().code.push((op, bytecode::Lineno(line)));
+    }
+
+    fn emit_return(&mut self) {
+        let line = self.previous().line;
+        if self.function_type() == FunctionType::Initializer {
+            self.emit_op(bytecode::Op::GetLocal(0), line);
+        } else {
+            self.emit_op(bytecode::Op::Nil, line);
+        }
+        self.emit_op(bytecode::Op::Return, line);
+    }
+
+    /**
+     * 先写一个占位的跳转，返回它的位置，等目标位置确定了以后再 patch
+     */
+    fn emit_jump(&mut self, op: bytecode::Op) -> usize {
+        let line = self.previous().line;
+        self.emit_op(op, line);
+        self.current_chunk().code.len() - 1
+    }
+
+    fn patch_jump(&mut self, jump_location: usize) {
+        let true_jump = self.current_chunk().code.len() - jump_location - 1;
+        let (maybe_jump, lineno) = &self.current_chunk().code[jump_location];
+        let lineno = *lineno;
+        let patched = match maybe_jump {
+            bytecode::Op::JumpIfFalse(_) => bytecode::Op::JumpIfFalse(true_jump),
+            bytecode::Op::Jump(_) => bytecode::Op::Jump(true_jump),
+            bytecode::Op::ForIter(_) => bytecode::Op::ForIter(true_jump),
+            bytecode::Op::JumpIfNil(_) => bytecode::Op::JumpIfNil(true_jump),
+            _ => panic!("attempted to patch a non-jump op at {}", jump_location),
+        };
+        self.current_chunk().code[jump_location] = (patched, lineno);
+    }
+
+    fn emit_loop(&mut self, loop_start: usize) {
+        let offset = self.current_chunk().code.len() - loop_start + 1;
+        let line = self.previous().line;
+        self.emit_op(bytecode::Op::Loop(offset), line);
+    }
+
+    /* ---------- ---------- 辅助函数 ---------- ---------- */
+
+    fn get_rule(&self, operator: scanner::TokenType) -> ParseRule {
+        match operator {
+            scanner::TokenType::LeftParen =>
+                ParseRule {
+                    prefix: Some(ParseFn::Grouping),
+                    infix: Some(ParseFn::Call),
+                    precedence: Precedence::Call,
+                },
+            scanner::TokenType::LeftBracket if self.extensions.lists =>
+                ParseRule {
+                    prefix: Some(ParseFn::List),
+                    infix: Some(ParseFn::Subscript),
+                    precedence: Precedence::Call,
+                },
+            scanner::TokenType::Dot =>
+                ParseRule {
+                    prefix: None,
+                    infix: Some(ParseFn::Dot),
+                    precedence: Precedence::Call,
+                },
+            scanner::TokenType::Minus =>
+                ParseRule {
+                    prefix: Some(ParseFn::Unary),
+                    infix: Some(ParseFn::Binary),
+                    precedence: Precedence::Term,
+                },
+            scanner::TokenType::Plus =>
+                ParseRule {
+                    prefix: None,
+                    infix: Some(ParseFn::Binary),
+                    precedence: Precedence::Term,
+                },
+            | scanner::TokenType::Slash
+            | scanner::TokenType::Star
+            | scanner::TokenType::Percent
+            | scanner::TokenType::TildeSlash =>
+                ParseRule {
+                    prefix: None,
+                    infix: Some(ParseFn::Binary),
+                    precedence: Precedence::Factor,
+                },
+            scanner::TokenType::StarStar =>
+                ParseRule {
+                    prefix: None,
+                    infix: Some(ParseFn::Binary),
+                    precedence: Precedence::Power,
+                },
+            scanner::TokenType::Pipe =>
+                ParseRule {
+                    prefix: None,
+                    infix: Some(ParseFn::Binary),
+                    precedence: Precedence::BitOr,
+                },
+            scanner::TokenType::Caret =>
+                ParseRule {
+                    prefix: None,
+                    infix: Some(ParseFn::Binary),
+                    precedence: Precedence::BitXor,
+                },
+            scanner::TokenType::Ampersand =>
+                ParseRule {
+                    prefix: None,
+                    infix: Some(ParseFn::Binary),
+                    precedence: Precedence::BitAnd,
+                },
+            scanner::TokenType::LessLess | scanner::TokenType::GreaterGreater =>
+                ParseRule {
+                    prefix: None,
+                    infix: Some(ParseFn::Binary),
+                    precedence: Precedence::Shift,
+                },
+            scanner::TokenType::PlusPlus | scanner::TokenType::MinusMinus =>
+                ParseRule {
+                    prefix: Some(ParseFn::PreIncrement),
+                    infix: None,
+                    precedence: Precedence::None,
+                },
+            scanner::TokenType::Bang | scanner::TokenType::Await =>
+                ParseRule {
+                    prefix: Some(ParseFn::Unary),
+                    infix: None,
+                    precedence: Precedence::None,
+                },
+            scanner::TokenType::BangEqual | scanner::TokenType::EqualEqual =>
+                ParseRule {
+                    prefix: None,
+                    infix: Some(ParseFn::Binary),
+                    precedence: Precedence::Equality,
+                },
+            | scanner::TokenType::Greater
+            | scanner::TokenType::GreaterEqual
+            | scanner::TokenType::Less
+            | scanner::TokenType::LessEqual
+            | scanner::TokenType::InstanceOf =>
+                ParseRule {
+                    prefix: None,
+                    infix: Some(ParseFn::Binary),
+                    precedence: Precedence::Comparison,
+                },
+            scanner::TokenType::Identifier =>
+                ParseRule {
+                    prefix: Some(ParseFn::Variable),
+                    infix: None,
+                    precedence: Precedence::None,
+                },
+            scanner::TokenType::String =>
+                ParseRule {
+                    prefix: Some(ParseFn::String),
+                    infix: None,
+                    precedence: Precedence::None,
+                },
+            scanner::TokenType::Interpolation =>
+                ParseRule {
+                    prefix: Some(ParseFn::Interpolation),
+                    infix: None,
+                    precedence: Precedence::None,
+                },
+            scanner::TokenType::Number =>
+                ParseRule {
+                    prefix: Some(ParseFn::Number),
+                    infix: None,
+                    precedence: Precedence::None,
+                },
+            scanner::TokenType::And =>
+                ParseRule {
+                    prefix: None,
+                    infix: Some(ParseFn::And),
+                    precedence: Precedence::And,
+                },
+            scanner::TokenType::Or =>
+                ParseRule {
+                    prefix: None,
+                    infix: Some(ParseFn::Or),
+                    precedence: Precedence::Or,
+                },
+            scanner::TokenType::False | scanner::TokenType::True | scanner::TokenType::Nil =>
+                ParseRule {
+                    prefix: Some(ParseFn::Literal),
+                    infix: None,
+                    precedence: Precedence::None,
+                },
+            scanner::TokenType::This =>
+                ParseRule {
+                    prefix: Some(ParseFn::This),
+                    infix: None,
+                    precedence: Precedence::None,
+                },
+            scanner::TokenType::Super =>
+                ParseRule {
+                    prefix: Some(ParseFn::Super),
+                    infix: None,
+                    precedence: Precedence::None,
+                },
+            scanner::TokenType::Lambda if self.extensions.lambdas =>
+                ParseRule {
+                    prefix: Some(ParseFn::Lambda),
+                    infix: None,
+                    precedence: Precedence::None,
+                },
+            _ =>
+                ParseRule {
+                    prefix: None,
+                    infix: None,
+                    precedence: Precedence::None,
+                },
+        }
+    }
+
+    fn identifier_name(tok: &scanner::Token) -> String {
+        String::from_utf8(tok.lexeme.clone()).unwrap()
+    }
+
+    /**
+     * 编译 super 的时候需要凭空造出 this / super 两个标识符
+     */
+    fn synthetic_token(tok: &scanner::Token, text: &str) -> scanner::Token {
+        scanner::Token {
+            ty: scanner::TokenType::Identifier,
+            lexeme: text.as_bytes().to_vec(),
+            literal: Some(scanner::Literal::Identifier(text.to_string())),
+            line: tok.line,
+            col: tok.col,
+        }
+    }
+
+    fn error_at_previous(&self, what: &str) -> Error {
+        let tok = self.previous();
+        Error::Parse(ErrorInfo {
+            what: what.to_string(),
+            line: tok.line,
+            col: tok.col,
+        })
+    }
+
+    fn current_level(&self) -> &Level {
+        self.levels.last().unwrap()
+    }
+
+    fn current_level_mut(&mut self) -> &mut Level {
+        self.levels.last_mut().unwrap()
+    }
+
+    fn current_function(&self) -> &bytecode::Function {
+        &self.current_level().function
+    }
+
+    fn current_function_mut(&mut self) -> &mut bytecode::Function {
+        &mut self.current_level_mut().function
+    }
+
+    fn current_chunk(&mut self) -> &mut bytecode::Chunk {
+        &mut self.current_function_mut().chunk
+    }
+
+    fn function_type(&self) -> FunctionType {
+        self.current_level().function_type
+    }
+
+    fn scope_depth(&self) -> i64 {
+        self.current_level().scope_depth
+    }
+
+    fn locals(&self) -> &Vec<Local> {
+        &self.current_level().locals
+    }
+
+    fn locals_mut(&mut self) -> &mut Vec<Local> {
+        &mut self.current_level_mut().locals
+    }
+
+    fn consume(&mut self, tok: scanner::TokenType, on_err_str: &str) -> Result<(), Error> {
+        if self.check(tok) {
+            self.advance();
+            return Ok(());
+        }
+
+        let peek = self.peek().clone();
+        Err(
+            Error::Parse(ErrorInfo {
+                what: format!(
+                    "Expected token {:?}, but found token {:?} at line={},col={}: {}",
+                    tok,
+                    peek.ty,
+                    peek.line,
+                    peek.col,
+                    on_err_str
+                ),
+                line: peek.line,
+                col: peek.col,
+            })
+        )
+    }
+
+    fn matches(&mut self, ty: scanner::TokenType) -> bool {
+        if self.check(ty) {
+            self.advance();
+            return true;
+        }
+        false
+    }
+
+    fn check(&self, ty: scanner::TokenType) -> bool {
+        if self.is_at_end() {
+            return false;
+        }
+        self.peek().ty == ty
+    }
+
+    fn check_next(&self, ty: scanner::TokenType) -> bool {
+        match self.tokens.get(self.token_idx + 1) {
+            Some(tok) => tok.ty == ty,
+            None => false,
+        }
+    }
+
+    fn advance(&mut self) -> &scanner::Token {
+        if !self.is_at_end() {
+            self.token_idx += 1;
+        }
+        self.previous()
+    }
+
+    fn is_at_end(&self) -> bool {
+        self.peek().ty == scanner::TokenType::Eof
+    }
+
+    fn peek(&self) -> &scanner::Token {
+        &self.tokens[self.token_idx]
+    }
+
+    fn previous(&self) -> &scanner::Token {
+        &self.tokens[self.token_idx - 1]
+    }
+}
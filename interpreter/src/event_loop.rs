@@ -0,0 +1,197 @@
+//! 事件循环：定时器、sleep 和 async / await，都建立在 fiber 上。
+//! 定时器到点了在新的 fiber 上调用回调，sleep 和 await 阻塞当前的 fiber。
+//! 没有 fiber 能跑的时候把时钟拨到最早的定时器：真时钟要真的等，假时钟（测试用）直接跳过去
+
+use crate::bytecode_interpreter::{ Interpreter, InterpreterError };
+use crate::fiber;
+use crate::gc;
+use crate::value;
+
+use std::time::{ Duration, Instant };
+
+pub enum Clock {
+    Real(Instant), // 从这个时刻开始算的毫秒数
+    Fake(f64), // 现在是第几毫秒，只有事件循环空闲的时候才往前走
+}
+
+impl Default for Clock {
+    fn default() -> Clock {
+        Clock::Real(Instant::now())
+    }
+}
+
+pub enum TimerAction {
+    Call(value::Value), // setTimeout / setInterval 的回调
+    Wake(usize), // sleep 的 fiber
+}
+
+pub struct Timer {
+    pub id: usize,
+    pub due: f64,
+    pub interval: Option<f64>, // setInterval：到点以后再等这么久
+    pub action: TimerAction,
+}
+
+#[derive(Default)]
+pub struct EventLoop {
+    pub clock: Clock,
+    pub timers: Vec<Timer>,
+    next_id: usize,
+}
+
+impl EventLoop {
+    pub fn use_fake_clock(&mut self) {
+        self.clock = Clock::Fake(0.0);
+    }
+
+    pub fn now(&self) -> f64 {
+        match self.clock {
+            Clock::Real(start) => start.elapsed().as_secs_f64() * 1000.0,
+            Clock::Fake(now) => now,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.timers.is_empty()
+    }
+
+    /**
+     * 返回定时器的编号，clearTimeout / clearInterval 用它取消
+     */
+    pub fn add_timer(&mut self, delay: f64, interval: Option<f64>, action: TimerAction) -> usize {
+        self.next_id += 1;
+        self.timers.push(Timer {
+            id: self.next_id,
+            due: self.now() + delay,
+            interval,
+            action,
+        });
+        self.next_id
+    }
+
+    pub fn cancel(&mut self, id: usize) {
+        self.timers.retain(|timer| timer.id != id);
+    }
+
+    pub fn roots(&self) -> Vec<gc::HeapId> {
+        self.timers
+            .iter()
+            .filter_map(|timer| {
+                match &timer.action {
+                    TimerAction::Call(callback) => gc::Heap::extract_id(callback),
+                    TimerAction::Wake(_) => None,
+                }
+            })
+            .collect()
+    }
+
+    /**
+     * 时钟拨到最早的定时器
+     */
+    fn advance(&mut self) {
+        let due = self.timers
+            .iter()
+            .map(|timer| timer.due)
+            .fold(f64::INFINITY, f64::min);
+        let now = self.now();
+        match &mut self.clock {
+            Clock::Real(_) => {
+                if due > now {
+                    std::thread::sleep(Duration::from_secs_f64((due - now) / 1000.0));
+                }
+            }
+            Clock::Fake(fake_now) => {
+                *fake_now = fake_now.max(due);
+            }
+        }
+    }
+
+    /**
+     * 取出到点的定时器，先到点的在前面，同时到点的按创建的顺序。
+     * setInterval 的定时器重新排上，这一轮里不会再到点
+     */
+    fn take_due(&mut self) -> Vec<TimerAction> {
+        let now = self.now();
+        let mut due: Vec<(f64, usize, TimerAction)> = Vec::new();
+        let mut idx = 0;
+        while idx < self.timers.len() {
+            let timer = &mut self.timers[idx];
+            if timer.due > now {
+                idx += 1;
+                continue;
+            }
+            match (timer.interval, &timer.action) {
+                (Some(interval), TimerAction::Call(callback)) => {
+                    due.push((timer.due, timer.id, TimerAction::Call(*callback)));
+                    timer.due += interval;
+                    idx += 1;
+                }
+                _ => {
+                    let timer = self.timers.remove(idx);
+                    due.push((timer.due, timer.id, timer.action));
+                }
+            }
+        }
+        due.sort_by(|(due1, id1, _), (due2, id2, _)| due1.total_cmp(due2).then(id1.cmp(id2)));
+        due.into_iter()
+            .map(|(_, _, action)| action)
+            .collect()
+    }
+}
+
+impl Interpreter {
+    /**
+     * 到点的定时器：回调放到新的 fiber 上，sleep 的 fiber 叫醒。wait 的时候先等到最早的定时器
+     */
+    pub fn fire_timers(&mut self, wait: bool) {
+        if wait {
+            self.event_loop.advance();
+        }
+        for action in self.event_loop.take_due() {
+            match action {
+                TimerAction::Call(callback) => {
+                    self.add_fiber(vec![callback], Vec::new(), Some(fiber::Start::call(0)));
+                }
+                TimerAction::Wake(fiber_id) => self.wake_fiber(fiber_id, None),
+            }
+        }
+    }
+
+    /**
+     * sleep(ms)：当前的 fiber 阻塞到时钟走过 ms 毫秒
+     */
+    pub fn sleep(&mut self, ms: f64) -> Result<(), String> {
+        self.request_switch(fiber::Switch::Block)?;
+        let current = self.fibers.current;
+        self.event_loop.add_timer(ms, None, TimerAction::Wake(current));
+        Ok(())
+    }
+
+    /**
+     * await：不是 promise 的值直接就是结果。promise 还没有结果的时候阻塞，先返回一个占位的 nil
+     */
+    pub fn await_value(&mut self, val: value::Value) -> Result<value::Value, InterpreterError> {
+        let promise_id = match val.unbox(&self.heap) {
+            value::Unboxed::Promise(promise_id) => promise_id,
+            _ => {
+                return Ok(val);
+            }
+        };
+        if let Some(result) = self.heap.get_promise(promise_id).value {
+            return Ok(result);
+        }
+
+        self.request_switch(fiber::Switch::Block).map_err(InterpreterError::Runtime)?;
+        let current = self.fibers.current;
+        self.heap.get_promise_mut(promise_id).waiters.push(current);
+        Ok(value::Value::NIL)
+    }
+
+    pub fn resolve_promise(&mut self, promise_id: gc::HeapId, result: value::Value) {
+        let promise = self.heap.get_promise_mut(promise_id);
+        promise.value = Some(result);
+        for waiter in std::mem::take(&mut promise.waiters) {
+            self.wake_fiber(waiter, Some(result));
+        }
+    }
+}
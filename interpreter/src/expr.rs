@@ -7,7 +7,7 @@ pub enum Expr {
     This(SourceLocation), // 面向对象语言中的 this
     Unary(UnaryOp, Box<Expr>), // 一元操作符表达式
     Binary(Box<Expr>, BinaryOp, Box<Expr>), //  这是一个递归结构
-    Call(Box<Expr>, SourceLocation, Vec<Expr>),
+    Call(Box<Expr>, SourceLocation, Vec<Expr>, Vec<(Symbol, Expr)>), // 位置参数 + 命名参数 f(1, b: 2)
     Get(Box<Expr>, Symbol), // 字段
     Grouping(Box<Expr>),
     Variable(Symbol),
@@ -18,7 +18,7 @@ pub enum Expr {
     List(Vec<Expr>),
     Subscript { // 下标访问
         value: Box<Expr>,
-        slice: Box<Expr>,
+        slice: Slice,
         source_location: SourceLocation,
     },
     // 这个是对集合中的某个元素赋值
@@ -29,12 +29,30 @@ pub enum Expr {
         source_location: SourceLocation,
     },
     Lambda(LambdaDecl),
+    // 插值字符串 "a ${b} c"，每一段都转成字符串以后拼起来
+    Interpolate(Vec<Expr>),
+    // 复合赋值 x += 1，以及 x++ / --x 这些语法糖；target 只能是 Variable、Get 或者 Subscript
+    CompoundAssign {
+        target: Box<Expr>,
+        op: BinaryOp,
+        rhs: Box<Expr>,
+        postfix: bool, // x++ 的值是修改之前的值
+    },
+}
+
+/**
+ * 方括号里面的东西：一个下标，或者一个切片 list[a:b]
+ */
+#[derive(Debug, Clone)]
+pub enum Slice {
+    Index(Box<Expr>),
+    Range(Option<Box<Expr>>, Option<Box<Expr>>), // 两端都可以省略
 }
 
 /**
  * SourceLocation 行列
  */
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Eq, PartialEq, Hash, Clone, Copy)]
 pub struct SourceLocation {
     pub line: usize,
     pub col: i64,
@@ -54,26 +72,99 @@ pub struct Symbol {
     pub name: String,
     pub line: usize,
     pub col: i64,
+    pub ty: Option<TypeAnnotation>, // 可选的类型标注，例如 var x: number
+}
+
+/**
+ * 类型标注，只给 checker 使用，运行时仍然是动态类型
+ */
+#[derive(Debug, Eq, PartialEq, Hash, Clone)]
+pub enum TypeAnnotation {
+    Any,
+    Number,
+    String,
+    Bool,
+    Nil,
+    List,
+    Function,
+    Class(String, SourceLocation), // 用户定义的类名，位置用来报告找不到的类
 }
 
 #[derive(Debug, Clone)]
 pub struct FunDecl {
     pub name: Symbol,
     pub params: Vec<Symbol>,
+    pub defaults: Vec<Expr>, // 最后几个参数的默认值，调用的时候没有传才求值
+    pub rest: Option<Symbol>, // ...rest 把多出来的位置参数收进一个列表
+    pub return_ty: Option<TypeAnnotation>,
     pub body: Vec<Stmt>,
+    pub is_generator: bool, // 函数体里面有 yield，调用的时候不执行，返回一个生成器
 }
 
 #[derive(Debug, Clone)]
 pub struct LambdaDecl {
     pub params: Vec<Symbol>,
+    pub defaults: Vec<Expr>,
+    pub rest: Option<Symbol>,
+    pub return_ty: Option<TypeAnnotation>,
     pub body: Vec<Stmt>,
+    pub is_generator: bool,
 }
 
 #[derive(Debug, Clone)]
 pub struct ClassDecl {
     pub name: Symbol,
     pub superclass: Option<Symbol>,
+    pub traits: Vec<Symbol>, // class A < B with T1, T2
+    pub fields: Vec<Symbol>, // 字段声明，例如 x: number;
     pub methods: Vec<FunDecl>,
+    pub class_methods: Vec<FunDecl>, // class name() {}，在类上调用
+    pub getters: Vec<FunDecl>, // name {}，没有参数列表
+    pub setters: Vec<FunDecl>, // set name(v) {}
+}
+
+/**
+ * trait 里面是带函数体的方法，以及只有签名、要求类自己提供的方法
+ */
+#[derive(Debug, Clone)]
+pub struct TraitDecl {
+    pub name: Symbol,
+    pub methods: Vec<FunDecl>,
+    pub required: Vec<Symbol>,
+}
+
+/**
+ * enum Shape { Circle(r), Rect(w, h), Empty }
+ */
+#[derive(Debug, Clone)]
+pub struct EnumDecl {
+    pub name: Symbol,
+    pub variants: Vec<EnumVariant>,
+}
+
+#[derive(Debug, Clone)]
+pub struct EnumVariant {
+    pub name: Symbol,
+    pub fields: Vec<Symbol>, // 没有字段的变体本身就是一个值，不用调用
+}
+
+/**
+ * match 语句里 case 后面的模式
+ */
+#[derive(Debug, Clone)]
+pub enum Pattern {
+    Wildcard, // _
+    Literal(SourceLocation, Literal),
+    Binding(Symbol), // 匹配任何值，绑定到这个名字上
+    List(Vec<Pattern>), // 长度必须一样，元素再分别匹配
+    Instance(Symbol, Vec<(Symbol, Pattern)>), // Point(x, y: 0)：是这个类的实例，字段再分别匹配
+}
+
+#[derive(Debug, Clone)]
+pub struct MatchArm {
+    pub pattern: Pattern,
+    pub guard: Option<Expr>, // case p if cond => ...
+    pub body: Stmt,
 }
 
 #[derive(Debug, Clone)]
@@ -81,12 +172,21 @@ pub enum Stmt {
     Expr(Expr),
     FunDecl(FunDecl),
     ClassDecl(ClassDecl),
+    TraitDecl(TraitDecl),
+    EnumDecl(EnumDecl),
     If(Expr, Box<Stmt>, Option<Box<Stmt>>),
     Print(Expr),
     VarDecl(Symbol, Option<Expr>),
+    ConstDecl(Symbol, Expr), // const / let 声明的变量不能再赋值，所以一定要有初始值
     Block(Vec<Stmt>),
     Return(SourceLocation, Option<Expr>),
-    While(Expr, Box<Stmt>),
+    While(Expr, Box<Stmt>, Option<Expr>), // 条件、循环体、for 循环的增量（continue 以后也要执行）
+    DoWhile(Box<Stmt>, Expr),
+    ForIn(Symbol, Expr, Box<Stmt>), // for (var x in xs) body
+    Break(SourceLocation),
+    Continue(SourceLocation),
+    Yield(SourceLocation, Expr), // 只能出现在函数里面，生成器在这里暂停
+    Match(Expr, Vec<MatchArm>), // 按顺序找第一个匹配的分支，都不匹配就什么也不做
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -110,10 +210,19 @@ pub enum BinaryOpTy {
     LessEqual,
     Greater,
     GreaterEqual,
+    InstanceOf,
     Plus,
     Minus,
     Star,
     Slash,
+    Percent, // 取余
+    StarStar, // 乘方
+    TildeSlash, // 整除
+    Ampersand,
+    Pipe,
+    Caret,
+    LessLess,
+    GreaterGreater,
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -129,6 +238,7 @@ pub struct BinaryOp {
 #[derive(Debug, Clone)]
 pub enum Literal {
     Number(f64),
+    Integer(i64),
     String(String),
     True,
     False,
@@ -0,0 +1,371 @@
+//! fiber 和通道。每个 fiber 有自己的调用帧、栈和开放的上值，在跑的那个直接用 Interpreter 上的
+//! frames / stack / upvalues，其余的暂停在 Scheduler 里，按轮转的顺序一个一个换上来。
+//! 只在一条指令执行完以后换：让出（不带值的 yield）或者阻塞（通道、sleep、await）的时候先记下来，step 最后再换。
+//! 没有 fiber 能跑的时候由事件循环（event_loop.rs）处理定时器
+
+use crate::bytecode_interpreter::{ CallFrame, Interpreter, InterpreterError };
+use crate::gc;
+use crate::value;
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+/**
+ * 主程序也是一个 fiber，编号是 0
+ */
+const MAIN_FIBER: usize = 0;
+
+pub struct Fiber {
+    pub id: usize,
+    pub frames: Vec<CallFrame>,
+    pub stack: Vec<value::Value>,
+    // 暂停的时候指向栈上的上值先关闭（别的 fiber 读写的就是关闭以后的值），记下位置，换回来的时候重新打开
+    pub upvalues: Vec<(Rc<RefCell<value::Upvalue>>, usize)>,
+    pub blocked: bool, // 阻塞在通道、定时器或者 promise 上，被唤醒之前不会轮到它
+    pub start: Option<Start>, // 还没开始跑
+    pub recv_closed: bool, // 最近一次 recv 拿到的 nil 是因为通道关了，for-in 靠它判断结束
+}
+
+/**
+ * 还没开始跑的 fiber 怎么开始：栈上是被调用的值（或者方法的接收者）和 arg_count 个参数
+ */
+pub struct Start {
+    pub method: Option<String>, // spawn obj.method(...)：在接收者上调用这个方法
+    pub arg_count: u8,
+    pub names: Vec<String>, // 命名参数，和 CallNamed 一样
+}
+
+impl Start {
+    pub fn call(arg_count: u8) -> Start {
+        Start { method: None, arg_count, names: Vec::new() }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Switch {
+    Yield,
+    Block,
+}
+
+#[derive(Default)]
+pub struct Scheduler {
+    pub current: usize, // 正在跑的 fiber
+    pub next_id: usize,
+    pub waiting: VecDeque<Fiber>, // 暂停着的 fiber，队头的先跑
+    pub pending: Option<Switch>, // 当前这条指令执行完以后要换 fiber
+    pub nested_calls: usize, // 内置函数里面调用 lox 代码的层数，这时候不能换 fiber
+    pub recv_closed: bool, // 当前 fiber 的，见 Fiber::recv_closed
+}
+
+impl Scheduler {
+    /**
+     * 暂停着的 fiber 上的值都是 gc 的根
+     */
+    pub fn roots(&self, heap: &gc::Heap) -> Vec<gc::HeapId> {
+        let mut res = Vec::new();
+        for fiber in self.waiting.iter() {
+            res.extend(fiber.stack.iter().filter_map(gc::Heap::extract_id));
+            for frame in fiber.frames.iter() {
+                res.extend(heap.closure_children(&frame.closure));
+                res.extend(frame.generator);
+                res.extend(frame.promise);
+            }
+            for (upval, _) in fiber.upvalues.iter() {
+                if let value::Upvalue::Closed(val) = &*upval.borrow() {
+                    res.extend(gc::Heap::extract_id(val));
+                }
+            }
+        }
+        res
+    }
+
+    fn is_alive(&self, id: usize) -> bool {
+        self.current == id || self.waiting.iter().any(|fiber| fiber.id == id)
+    }
+}
+
+impl Interpreter {
+    /**
+     * spawn f(...) / spawn obj.method(...)：被调用的值（或者接收者）和参数从栈上搬走，放到队尾等着
+     */
+    pub fn spawn(&mut self, start: Start) {
+        let stack = self.stack.split_off(self.stack.len() - usize::from(start.arg_count) - 1);
+        self.add_fiber(stack, Vec::new(), Some(start));
+    }
+
+    pub fn add_fiber(&mut self, stack: Vec<value::Value>, frames: Vec<CallFrame>, start: Option<Start>) {
+        self.fibers.next_id += 1;
+        self.fibers.waiting.push_back(Fiber {
+            id: self.fibers.next_id,
+            frames,
+            stack,
+            upvalues: Vec::new(),
+            blocked: false,
+            start,
+            recv_closed: false,
+        });
+    }
+
+    pub fn request_switch(&mut self, switch: Switch) -> Result<(), String> {
+        if self.fibers.nested_calls > 0 {
+            return Err("Cannot switch fibers inside a native call".to_string());
+        }
+        self.fibers.pending = Some(switch);
+        Ok(())
+    }
+
+    /**
+     * 让出或者阻塞：当前的 fiber 排到队尾，换上第一个能跑的（让出的时候可能还是它自己）
+     */
+    pub fn switch_fiber(&mut self, switch: Switch) -> Result<(), InterpreterError> {
+        let current = self.suspend_fiber(switch == Switch::Block);
+        let current_id = current.id;
+        self.fibers.waiting.push_back(current);
+        if let Some(next) = self.next_runnable() {
+            return self.resume_fiber(next);
+        }
+
+        // 谁都跑不了，当前的 fiber 先换回来，报错的时候回溯里还有它的调用帧
+        let pos = self.fibers.waiting
+            .iter()
+            .position(|fiber| fiber.id == current_id)
+            .unwrap();
+        let current = self.fibers.waiting.remove(pos).unwrap();
+        self.resume_fiber(current)?;
+        if self.fibers.is_alive(MAIN_FIBER) {
+            return Err(InterpreterError::Runtime("Deadlock: all fibers are blocked.".to_string()));
+        }
+        // 主程序已经跑完了，剩下的 fiber 永远等不到了，直接结束
+        self.frames.clear();
+        Ok(())
+    }
+
+    /**
+     * 当前的 fiber 跑完了。还有能跑的 fiber 就换上来，返回 false 表示程序结束
+     */
+    pub fn finish_fiber(&mut self) -> Result<bool, InterpreterError> {
+        match self.next_runnable() {
+            Some(next) => {
+                for upval in self.upvalues.drain(..) {
+                    let val = match &*upval.borrow() {
+                        value::Upvalue::Open(idx) => self.stack[*idx],
+                        value::Upvalue::Closed(val) => *val,
+                    };
+                    upval.replace(value::Upvalue::Closed(val));
+                }
+                self.stack.clear();
+                self.resume_fiber(next)?;
+                Ok(true)
+            }
+            None if self.fibers.current != MAIN_FIBER && self.fibers.is_alive(MAIN_FIBER) => {
+                Err(InterpreterError::Runtime("Deadlock: all fibers are blocked.".to_string()))
+            }
+            None => {
+                self.fibers.waiting.clear();
+                Ok(false)
+            }
+        }
+    }
+
+    /**
+     * 按轮转的顺序取出第一个能跑的 fiber。都跑不了的时候等下一个定时器，连定时器都没有了才返回 None
+     */
+    fn next_runnable(&mut self) -> Option<Fiber> {
+        // 已经到点的定时器先处理，不用等
+        self.fire_timers(false);
+        loop {
+            if let Some(pos) = self.fibers.waiting.iter().position(|fiber| !fiber.blocked) {
+                return self.fibers.waiting.remove(pos);
+            }
+            if self.event_loop.is_empty() {
+                return None;
+            }
+            self.fire_timers(true);
+        }
+    }
+
+    fn suspend_fiber(&mut self, blocked: bool) -> Fiber {
+        let mut upvalues = Vec::new();
+        for upval in self.upvalues.drain(..) {
+            let idx = match &*upval.borrow() {
+                value::Upvalue::Open(idx) => *idx,
+                value::Upvalue::Closed(_) => continue,
+            };
+            upval.replace(value::Upvalue::Closed(self.stack[idx]));
+            upvalues.push((upval, idx));
+        }
+        Fiber {
+            id: self.fibers.current,
+            frames: std::mem::take(&mut self.frames),
+            stack: std::mem::take(&mut self.stack),
+            upvalues,
+            blocked,
+            start: None,
+            recv_closed: self.fibers.recv_closed,
+        }
+    }
+
+    fn resume_fiber(&mut self, fiber: Fiber) -> Result<(), InterpreterError> {
+        self.fibers.current = fiber.id;
+        self.fibers.recv_closed = fiber.recv_closed;
+        self.frames = fiber.frames;
+        self.stack = fiber.stack;
+        for (upval, idx) in fiber.upvalues {
+            // 暂停期间别的 fiber 可能改过这个上值，先写回栈上
+            if let value::Upvalue::Closed(val) = &*upval.borrow() {
+                self.stack[idx] = *val;
+            }
+            upval.replace(value::Upvalue::Open(idx));
+            self.upvalues.push(upval);
+        }
+        match fiber.start {
+            Some(Start { method: Some(method_name), arg_count, names }) => {
+                self.invoke(&method_name, arg_count, &names, None)
+            }
+            Some(Start { method: None, arg_count, names }) => {
+                self.call_value_named(self.stack[0], arg_count, &names)
+            }
+            None => Ok(()),
+        }
+    }
+
+    /**
+     * 被阻塞的 fiber 可以跑了。recv、await 上阻塞的 fiber 栈顶是占位的 nil，换成收到的值
+     */
+    pub fn wake_fiber(&mut self, id: usize, received: Option<value::Value>) {
+        let fiber = self.fibers.waiting
+            .iter_mut()
+            .find(|fiber| fiber.id == id)
+            .expect("woke up a fiber that is not waiting");
+        fiber.blocked = false;
+        if let Some(val) = received {
+            *fiber.stack.last_mut().unwrap() = val;
+        }
+    }
+
+    /* ---------- ---------- 通道 ---------- ---------- */
+
+    pub fn channel_method(
+        channel_id: gc::HeapId,
+        name: &str
+    ) -> Result<value::Value, InterpreterError> {
+        match name {
+            "send" => Ok(value::Value::channel_method(channel_id, value::ChannelMethod::Send)),
+            "recv" | "next" => Ok(value::Value::channel_method(channel_id, value::ChannelMethod::Recv)),
+            "close" => Ok(value::Value::channel_method(channel_id, value::ChannelMethod::Close)),
+            _ => Err(InterpreterError::Runtime(format!("Undefined property {}.", name))),
+        }
+    }
+
+    /**
+     * 栈上是方法（或者 for-in 里的通道本身）和参数，换成返回值
+     */
+    pub fn call_channel_method(
+        &mut self,
+        channel_id: gc::HeapId,
+        method: value::ChannelMethod,
+        arg_count: u8
+    ) -> Result<(), InterpreterError> {
+        let expected = match method {
+            value::ChannelMethod::Send => 1,
+            value::ChannelMethod::Recv | value::ChannelMethod::Close => 0,
+        };
+        if arg_count != expected {
+            return Err(
+                InterpreterError::Runtime(
+                    format!("Expected {} arguments but found {}.", expected, arg_count)
+                )
+            );
+        }
+
+        let result = match method {
+            value::ChannelMethod::Send => {
+                let val = self.pop_stack();
+                self.channel_send(channel_id, val)?;
+                value::Value::NIL
+            }
+            value::ChannelMethod::Recv => self.channel_recv(channel_id)?,
+            value::ChannelMethod::Close => {
+                self.channel_close(channel_id)?;
+                value::Value::NIL
+            }
+        };
+        self.pop_stack();
+        self.stack.push(result);
+        Ok(())
+    }
+
+    fn channel_send(
+        &mut self,
+        channel_id: gc::HeapId,
+        val: value::Value
+    ) -> Result<(), InterpreterError> {
+        let channel = self.heap.get_channel_mut(channel_id);
+        if channel.closed {
+            return Err(InterpreterError::Runtime("Send on a closed channel.".to_string()));
+        }
+        // 有人在等着收，直接交给它
+        if let Some(receiver) = channel.receivers.pop_front() {
+            self.wake_fiber(receiver, Some(val));
+            return Ok(());
+        }
+        if channel.buffer.len() < channel.capacity {
+            channel.buffer.push_back(val);
+            return Ok(());
+        }
+
+        self.request_switch(Switch::Block).map_err(InterpreterError::Runtime)?;
+        let current = self.fibers.current;
+        self.heap.get_channel_mut(channel_id).senders.push_back((current, val));
+        Ok(())
+    }
+
+    /**
+     * 阻塞的时候先返回一个占位的 nil，发送的 fiber 会把它换成真正的值。
+     * 通道关了返回 nil，同时记下 recv_closed，发过来的 nil 和关闭才能分开
+     */
+    fn channel_recv(&mut self, channel_id: gc::HeapId) -> Result<value::Value, InterpreterError> {
+        self.fibers.recv_closed = false;
+        let channel = self.heap.get_channel_mut(channel_id);
+        if let Some(val) = channel.buffer.pop_front() {
+            // 缓冲区空出一个位置，阻塞着的发送者可以把值放进来了
+            if let Some((sender, pending)) = channel.senders.pop_front() {
+                channel.buffer.push_back(pending);
+                self.wake_fiber(sender, None);
+            }
+            return Ok(val);
+        }
+        if let Some((sender, val)) = channel.senders.pop_front() {
+            self.wake_fiber(sender, None);
+            return Ok(val);
+        }
+        if channel.closed {
+            self.fibers.recv_closed = true;
+            return Ok(value::Value::NIL);
+        }
+
+        self.request_switch(Switch::Block).map_err(InterpreterError::Runtime)?;
+        let current = self.fibers.current;
+        self.heap.get_channel_mut(channel_id).receivers.push_back(current);
+        Ok(value::Value::NIL)
+    }
+
+    /**
+     * 关闭以后阻塞在 recv 上的 fiber 都收到 nil
+     */
+    fn channel_close(&mut self, channel_id: gc::HeapId) -> Result<(), InterpreterError> {
+        let channel = self.heap.get_channel_mut(channel_id);
+        if channel.closed {
+            return Err(InterpreterError::Runtime("Channel is already closed.".to_string()));
+        }
+        channel.closed = true;
+        let receivers: Vec<usize> = channel.receivers.drain(..).collect();
+        for receiver in receivers {
+            self.wake_fiber(receiver, None);
+            let fiber = self.fibers.waiting.iter_mut().find(|fiber| fiber.id == receiver).unwrap();
+            fiber.recv_closed = true;
+        }
+        Ok(())
+    }
+}
@@ -1,3 +1,4 @@
+use crate::isolate;
 use crate::value;
 
 use std::collections::HashMap;
@@ -7,11 +8,20 @@ use std::collections::HashMap;
  */
 enum GCData {
     String(String),
+    Integer(i64), // NaN 的负载放不下的整数
     Closure(value::Closure),
     Class(value::Class),
+    Trait(value::Trait),
     Instance(value::Instance),
     BoundMethod(value::BoundMethod),
     List(Vec<value::Value>),
+    Generator(value::Generator),
+    Enum(value::Enum),
+    Variant(value::Variant),
+    NativeFunction(value::NativeFunction),
+    Channel(value::Channel),
+    Promise(value::Promise),
+    Isolate(isolate::Isolate),
 }
 
 /**
@@ -24,6 +34,12 @@ impl GCData {
             _ => None,
         }
     }
+    fn as_integer(&self) -> Option<i64> {
+        match self {
+            GCData::Integer(n) => Some(*n),
+            _ => None,
+        }
+    }
     fn as_list(&self) -> Option<&Vec<value::Value>> {
         match self {
             GCData::List(elements) => Some(elements),
@@ -63,6 +79,18 @@ impl GCData {
             _ => None,
         }
     }
+    fn as_trait(&self) -> Option<&value::Trait> {
+        match self {
+            GCData::Trait(t) => Some(t),
+            _ => None,
+        }
+    }
+    fn as_trait_mut(&mut self) -> Option<&mut value::Trait> {
+        match self {
+            GCData::Trait(t) => Some(t),
+            _ => None,
+        }
+    }
     fn as_instance(&self) -> Option<&value::Instance> {
         match self {
             GCData::Instance(inst) => Some(inst),
@@ -75,18 +103,88 @@ impl GCData {
             _ => None,
         }
     }
+    fn as_generator(&self) -> Option<&value::Generator> {
+        match self {
+            GCData::Generator(generator) => Some(generator),
+            _ => None,
+        }
+    }
+    fn as_generator_mut(&mut self) -> Option<&mut value::Generator> {
+        match self {
+            GCData::Generator(generator) => Some(generator),
+            _ => None,
+        }
+    }
+    fn as_enum(&self) -> Option<&value::Enum> {
+        match self {
+            GCData::Enum(e) => Some(e),
+            _ => None,
+        }
+    }
+    fn as_enum_mut(&mut self) -> Option<&mut value::Enum> {
+        match self {
+            GCData::Enum(e) => Some(e),
+            _ => None,
+        }
+    }
+    fn as_variant(&self) -> Option<&value::Variant> {
+        match self {
+            GCData::Variant(variant) => Some(variant),
+            _ => None,
+        }
+    }
+    fn as_channel_mut(&mut self) -> Option<&mut value::Channel> {
+        match self {
+            GCData::Channel(channel) => Some(channel),
+            _ => None,
+        }
+    }
+    fn as_promise(&self) -> Option<&value::Promise> {
+        match self {
+            GCData::Promise(promise) => Some(promise),
+            _ => None,
+        }
+    }
+    fn as_promise_mut(&mut self) -> Option<&mut value::Promise> {
+        match self {
+            GCData::Promise(promise) => Some(promise),
+            _ => None,
+        }
+    }
+    fn as_isolate(&self) -> Option<&isolate::Isolate> {
+        match self {
+            GCData::Isolate(isolate) => Some(isolate),
+            _ => None,
+        }
+    }
+    fn as_isolate_mut(&mut self) -> Option<&mut isolate::Isolate> {
+        match self {
+            GCData::Isolate(isolate) => Some(isolate),
+            _ => None,
+        }
+    }
+    fn as_native_function(&self) -> Option<&value::NativeFunction> {
+        match self {
+            GCData::NativeFunction(func) => Some(func),
+            _ => None,
+        }
+    }
 }
 
 /// 除了存放数据，还可以判断数据是否有效
 struct GCVal {
     is_marked: bool, // 用来标记，这个对象是否还有可能被调用
+    is_frozen: bool, // freeze 以后实例的字段、列表的元素都不能再修改
+    size: usize, // 分配的时候记进 bytes_allocated 的字节数，回收的时候再减掉
     data: GCData,
 }
 
 impl GCVal {
-    fn from(data: GCData) -> GCVal {
+    fn from(data: GCData, size: usize) -> GCVal {
         GCVal {
             is_marked: false,
+            is_frozen: false,
+            size,
             data,
         }
     }
@@ -98,6 +196,7 @@ pub struct Heap {
     bytes_allocated: usize, // 已经分配的字节个数
     next_gc: usize, // 下一次垃圾回收的阈值
     id_counter: usize, // 用于管理对象的
+    epoch: usize, // id 每绕回去一次加一，之后分配出去的 id 可能是以前用过的
     values: HashMap<HeapId, GCVal>,
 }
 
@@ -112,6 +211,7 @@ impl Default for Heap {
             bytes_allocated: 0,
             next_gc,
             id_counter: 0,
+            epoch: 0,
             values: Default::default(),
         }
     }
@@ -138,74 +238,147 @@ impl Heap {
      * 给对象分配 id
      */
     fn generate_id(&mut self) -> HeapId {
-        self.id_counter += 1; // ++
-        loop {
+        // Value 里只有 32 位放 HeapId，用完了从头再找空出来的。
+        // 绕回去以后 id 会复用，按 HeapId 记东西的缓存要连着 epoch 一起比较
+        for _ in 0..value::MAX_HEAP_ID {
+            if self.id_counter == value::MAX_HEAP_ID {
+                self.id_counter = 1;
+                self.epoch += 1;
+            } else {
+                self.id_counter += 1;
+            }
             if !self.values.contains_key(&self.id_counter) {
                 return self.id_counter;
             }
-            self.id_counter += 1;
         }
+        panic!("Out of heap ids: {} objects are alive.", self.values.len())
     }
 
     /**
-     * 在堆区上分配字符串
+     * 同一个 epoch 里面 id 是递增分配的，不会重复，(HeapId, epoch) 可以当作对象的身份
      */
-    pub fn manage_str(&mut self, s: String) -> HeapId {
-        self.bytes_allocated += s.len();
+    pub fn epoch(&self) -> usize {
+        self.epoch
+    }
+
+    /**
+     * 测试里用：当作 id 已经绕回来了，下一次从 id 开始找空位，不用真的分配 40 亿个对象
+     */
+    pub fn wrap_ids_at(&mut self, id: HeapId) {
+        self.id_counter = id.saturating_sub(1);
+        self.epoch += 1;
+    }
+
+    /**
+     * 放到堆上，记下它占了多少字节
+     */
+    fn insert(&mut self, data: GCData, size: usize) -> HeapId {
+        self.bytes_allocated += size;
         let id = self.generate_id();
-        self.values.insert(id, GCVal::from(GCData::String(s)));
+        self.values.insert(id, GCVal::from(data, size));
         id
     }
 
+    /**
+     * 装箱一个整数，放不进 NaN 的负载的才在堆区上分配
+     */
+    pub fn integer(&mut self, n: i64) -> value::Value {
+        value::Value::small_integer(n).unwrap_or_else(|| {
+            value::Value::big_integer(self.insert(GCData::Integer(n), std::mem::size_of::<i64>()))
+        })
+    }
+
+    /**
+     * 在堆区上分配字符串
+     */
+    pub fn manage_str(&mut self, s: String) -> HeapId {
+        let size = s.len();
+        self.insert(GCData::String(s), size)
+    }
+
     /**
      * 在堆区上分配数组
      */
     pub fn manage_list(&mut self, elements: Vec<value::Value>) -> HeapId {
-        self.bytes_allocated += elements.len();
-        let id = self.generate_id();
-        self.values.insert(id, GCVal::from(GCData::List(elements)));
-        id
+        let size = elements.len();
+        self.insert(GCData::List(elements), size)
     }
 
     /**
      * 在堆区上分配闭包
      */
     pub fn manage_closure(&mut self, c: value::Closure) -> HeapId {
-        self.bytes_allocated += c.function.chunk.code.len();
-        self.bytes_allocated += c.function.chunk.constants.len();
-        let id = self.generate_id();
-        self.values.insert(id, GCVal::from(GCData::Closure(c)));
-        id
+        let size = c.function.chunk.code.len() + c.function.chunk.constants.len();
+        self.insert(GCData::Closure(c), size)
     }
 
     /**
      * 在堆区上分配 类
      */
     pub fn manage_class(&mut self, c: value::Class) -> HeapId {
-        let id = self.generate_id();
-        self.bytes_allocated += c.name.len();
-        self.bytes_allocated += c.methods
-            .keys() // 遍历所有的方法名
-            .map(|method_name| method_name.len())
-            .sum::<usize>();
-        self.values.insert(id, GCVal::from(GCData::Class(c)));
-        id
+        let size =
+            c.name.len() +
+            c.methods
+                .keys() // 遍历所有的方法名
+                .map(|method_name| method_name.len())
+                .sum::<usize>();
+        self.insert(GCData::Class(c), size)
+    }
+
+    pub fn manage_trait(&mut self, t: value::Trait) -> HeapId {
+        let size = t.name.len();
+        self.insert(GCData::Trait(t), size)
     }
 
     pub fn manage_instance(&mut self, inst: value::Instance) -> HeapId {
-        let id = self.generate_id();
-        self.bytes_allocated += inst.fields
+        let size = inst.fields
             .keys()
             .map(|attr| attr.len())
             .sum::<usize>();
-        self.values.insert(id, GCVal::from(GCData::Instance(inst)));
-        id
+        self.insert(GCData::Instance(inst), size)
     }
 
     pub fn manage_bound_method(&mut self, method: value::BoundMethod) -> HeapId {
-        let id = self.generate_id();
-        self.values.insert(id, GCVal::from(GCData::BoundMethod(method)));
-        id
+        self.insert(GCData::BoundMethod(method), 0)
+    }
+
+    pub fn manage_generator(&mut self, generator: value::Generator) -> HeapId {
+        let size = generator.slots.len();
+        self.insert(GCData::Generator(generator), size)
+    }
+
+    pub fn manage_enum(&mut self, e: value::Enum) -> HeapId {
+        let size = e.name.len();
+        self.insert(GCData::Enum(e), size)
+    }
+
+    pub fn manage_variant(&mut self, variant: value::Variant) -> HeapId {
+        let size = variant.fields.len();
+        self.insert(GCData::Variant(variant), size)
+    }
+
+    pub fn manage_channel(&mut self, channel: value::Channel) -> HeapId {
+        let size = channel.capacity;
+        self.insert(GCData::Channel(channel), size)
+    }
+
+    pub fn manage_promise(&mut self, promise: value::Promise) -> HeapId {
+        self.insert(GCData::Promise(promise), 0)
+    }
+
+    /**
+     * 回收的时候线程不会停，只是通道断开，join 不到了
+     */
+    pub fn manage_isolate(&mut self, isolate: isolate::Isolate) -> HeapId {
+        self.insert(GCData::Isolate(isolate), 0)
+    }
+
+    /**
+     * 内置函数也放在堆上，这样 Value 里面只剩下 HeapId，可以直接 Copy
+     */
+    pub fn manage_native_function(&mut self, func: value::NativeFunction) -> HeapId {
+        let size = func.name.len();
+        self.insert(GCData::NativeFunction(func), size)
     }
 
     /* ---------- 根据 HeapId，获取堆区上的数据 ---------- */
@@ -214,6 +387,10 @@ impl Heap {
         self.values.get(&id).unwrap().data.as_str().unwrap()
     }
 
+    pub fn get_integer(&self, id: HeapId) -> i64 {
+        self.values.get(&id).unwrap().data.as_integer().unwrap()
+    }
+
     /**
      * HeapId --> Closure
      */
@@ -241,6 +418,14 @@ impl Heap {
         self.values.get_mut(&id).unwrap().data.as_class_mut().unwrap()
     }
 
+    pub fn get_trait(&self, id: HeapId) -> &value::Trait {
+        self.values.get(&id).unwrap().data.as_trait().unwrap()
+    }
+
+    pub fn get_trait_mut(&mut self, id: HeapId) -> &mut value::Trait {
+        self.values.get_mut(&id).unwrap().data.as_trait_mut().unwrap()
+    }
+
     pub fn get_instance(&self, id: HeapId) -> &value::Instance {
         self.values.get(&id).unwrap().data.as_instance().unwrap()
     }
@@ -249,6 +434,60 @@ impl Heap {
         self.values.get_mut(&id).unwrap().data.as_instance_mut().unwrap()
     }
 
+    pub fn get_generator(&self, id: HeapId) -> &value::Generator {
+        self.values.get(&id).unwrap().data.as_generator().unwrap()
+    }
+
+    pub fn get_generator_mut(&mut self, id: HeapId) -> &mut value::Generator {
+        self.values.get_mut(&id).unwrap().data.as_generator_mut().unwrap()
+    }
+
+    pub fn get_enum(&self, id: HeapId) -> &value::Enum {
+        self.values.get(&id).unwrap().data.as_enum().unwrap()
+    }
+
+    pub fn get_enum_mut(&mut self, id: HeapId) -> &mut value::Enum {
+        self.values.get_mut(&id).unwrap().data.as_enum_mut().unwrap()
+    }
+
+    pub fn get_variant(&self, id: HeapId) -> &value::Variant {
+        self.values.get(&id).unwrap().data.as_variant().unwrap()
+    }
+
+    pub fn get_channel_mut(&mut self, id: HeapId) -> &mut value::Channel {
+        self.values.get_mut(&id).unwrap().data.as_channel_mut().unwrap()
+    }
+
+    pub fn get_promise(&self, id: HeapId) -> &value::Promise {
+        self.values.get(&id).unwrap().data.as_promise().unwrap()
+    }
+
+    pub fn get_promise_mut(&mut self, id: HeapId) -> &mut value::Promise {
+        self.values.get_mut(&id).unwrap().data.as_promise_mut().unwrap()
+    }
+
+    pub fn get_isolate(&self, id: HeapId) -> &isolate::Isolate {
+        self.values.get(&id).unwrap().data.as_isolate().unwrap()
+    }
+
+    pub fn get_isolate_mut(&mut self, id: HeapId) -> &mut isolate::Isolate {
+        self.values.get_mut(&id).unwrap().data.as_isolate_mut().unwrap()
+    }
+
+    pub fn get_native_function(&self, id: HeapId) -> &value::NativeFunction {
+        self.values.get(&id).unwrap().data.as_native_function().unwrap()
+    }
+
+    /* ---------- freeze ---------- */
+
+    pub fn freeze(&mut self, id: HeapId) {
+        self.values.get_mut(&id).unwrap().is_frozen = true;
+    }
+
+    pub fn is_frozen(&self, id: HeapId) -> bool {
+        self.values.get(&id).unwrap().is_frozen
+    }
+
     /* ---------- mark ---------- */
 
     pub fn unmark(&mut self) {
@@ -274,11 +513,31 @@ impl Heap {
     pub fn children(&self, id: HeapId) -> Vec<HeapId> {
         match &self.values.get(&id).unwrap().data {
             GCData::String(_) => Vec::new(),
+            GCData::Integer(_) => Vec::new(),
             GCData::Closure(closure) => self.closure_children(closure),
             GCData::Class(class) => self.class_children(class),
+            GCData::Trait(t) => t.methods.values().copied().collect(),
             GCData::Instance(instance) => self.instance_children(instance),
             GCData::BoundMethod(method) => self.bound_method_children(method),
             GCData::List(elements) => self.list_children(elements),
+            GCData::Generator(generator) => self.generator_children(generator),
+            GCData::Enum(_) => Vec::new(),
+            GCData::NativeFunction(_) => Vec::new(),
+            // 缓冲区里的值和阻塞着的发送者手上的值
+            GCData::Channel(channel) => {
+                channel.buffer
+                    .iter()
+                    .chain(channel.senders.iter().map(|(_, val)| val))
+                    .filter_map(Heap::extract_id)
+                    .collect()
+            }
+            GCData::Promise(promise) => promise.value.iter().filter_map(Heap::extract_id).collect(),
+            GCData::Isolate(_) => Vec::new(),
+            GCData::Variant(variant) => {
+                let mut res = vec![variant.enum_id];
+                res.extend(self.list_children(&variant.fields));
+                res
+            }
         }
     }
 
@@ -297,22 +556,19 @@ impl Heap {
 
     // class 的所有成员方法中，涉及到了哪些值，都复制一份，然后搜集起来
     pub fn class_children(&self, class: &value::Class) -> Vec<HeapId> {
-        class.methods.values().copied().collect()
+        class.methods
+            .values()
+            .chain(class.class_methods.values())
+            .chain(class.getters.values())
+            .chain(class.setters.values())
+            .chain(class.superclass.iter())
+            .chain(class.traits.iter())
+            .copied()
+            .collect()
     }
 
     pub fn extract_id(val: &value::Value) -> Option<HeapId> {
-        match val {
-            value::Value::Number(_) => None,
-            value::Value::Bool(_) => None,
-            value::Value::String(id) => Some(*id),
-            value::Value::Function(id) => Some(*id),
-            value::Value::Instance(id) => Some(*id),
-            value::Value::BoundMethod(id) => Some(*id),
-            value::Value::Class(id) => Some(*id),
-            value::Value::NativeFunction(_) => None,
-            value::Value::Nil => None,
-            value::Value::List(id) => Some(*id),
-        }
+        val.heap_id()
     }
 
     pub fn instance_children(&self, instance: &value::Instance) -> Vec<HeapId> {
@@ -328,7 +584,7 @@ impl Heap {
     }
 
     pub fn bound_method_children(&self, method: &value::BoundMethod) -> Vec<HeapId> {
-        vec![method.instance_id, method.closure_id]
+        Heap::extract_id(&method.receiver).into_iter().chain([method.closure_id]).collect()
     }
 
     pub fn list_children(&self, elements: &[value::Value]) -> Vec<HeapId> {
@@ -343,9 +599,32 @@ impl Heap {
         res
     }
 
+    /**
+     * 生成器暂停的时候，栈上的槽位和关闭的上值都在它自己身上
+     */
+    pub fn generator_children(&self, generator: &value::Generator) -> Vec<HeapId> {
+        let mut res = self.closure_children(&generator.closure);
+        res.extend(self.list_children(&generator.slots));
+        for (upval, _) in generator.upvalues.iter() {
+            if let value::Upvalue::Closed(value) = &*upval.borrow() {
+                res.extend(Heap::extract_id(value));
+            }
+        }
+        res
+    }
+
     pub fn sweep(&mut self) {
         // 遍历hash表，一元谓词，如果是 true ---> 保留，如果是 false ---> sweep
-        self.values.retain(|_, val| val.is_marked)
+        let mut freed = 0;
+        self.values.retain(|_, val| {
+            if !val.is_marked {
+                freed += val.size;
+            }
+            val.is_marked
+        });
+        self.bytes_allocated -= freed;
+        // 活着的对象翻倍了才回收下一次，不然活着的对象一多，每一步都要回收
+        self.next_gc = self.next_gc.max(self.bytes_allocated * 2);
     }
 
     /**
@@ -0,0 +1,289 @@
+//! isolate：在另一个系统线程上跑一个字节码解释器，有自己的堆和全局变量，和别的 isolate 什么都不共享。
+//! 两边只能通过通道传 Message：发送的时候把值深拷贝成 Message，收到以后在自己的堆上重新分配。
+//! isolate 里面的全局变量 parent 就是回到创建者的那一头
+
+use crate::bytecode_interpreter::{ Interpreter, InterpreterError };
+use crate::compiler;
+use crate::extensions;
+use crate::gc;
+use crate::value;
+
+use serde::{ Deserialize, Serialize };
+
+use std::collections::HashSet;
+use std::sync::atomic::{ AtomicBool, Ordering };
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/**
+ * 可以在 isolate 之间传的值：不含任何 HeapId，可以跨线程，也可以序列化
+ */
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Message {
+    Nil,
+    Bool(bool),
+    Integer(i64),
+    Number(f64),
+    String(String),
+    List(Vec<Message>),
+}
+
+/**
+ * 通道的一头。创建者手上的 Isolate 和 isolate 里面的 parent 各拿一头
+ */
+pub struct Isolate {
+    pub name: String,
+    sender: mpsc::Sender<Message>,
+    receiver: mpsc::Receiver<Message>,
+    thread: Option<thread::JoinHandle<Result<Vec<String>, String>>>, // parent 那一头没有线程
+    interrupted: Arc<AtomicBool>, // kill 设置它，isolate 里的解释器每一步都检查
+}
+
+impl Isolate {
+    /**
+     * 在新线程上编译、运行 source，entry 不是 None 的话模块跑完以后再调用这个全局函数
+     */
+    pub fn spawn(name: &str, source: String, entry: Option<String>) -> Isolate {
+        let (to_child, child_inbox) = mpsc::channel();
+        let (to_parent, parent_inbox) = mpsc::channel();
+        let interrupted = Arc::new(AtomicBool::new(false));
+
+        let parent = Isolate {
+            name: "parent".to_string(),
+            sender: to_parent,
+            receiver: child_inbox,
+            thread: None,
+            interrupted: interrupted.clone(),
+        };
+        let thread = thread::spawn(move || Isolate::run(source, entry, parent));
+
+        Isolate {
+            name: name.to_string(),
+            sender: to_child,
+            receiver: parent_inbox,
+            thread: Some(thread),
+            interrupted,
+        }
+    }
+
+    fn run(source: String, entry: Option<String>, parent: Isolate) -> Result<Vec<String>, String> {
+        let extensions = extensions::Extensions { lists: true, lambdas: true };
+        let func = compiler::Compiler::compile(source, extensions).map_err(|err| err.to_string())?;
+
+        let mut interp = Interpreter::default();
+        interp.interrupted = parent.interrupted.clone();
+        let parent = value::Value::isolate(interp.heap.manage_isolate(parent));
+        interp.globals.insert("parent".to_string(), parent);
+
+        let res = interp.interpret(func).and_then(|()| {
+            match entry {
+                Some(entry) => interp.call_global(&entry),
+                None => Ok(()),
+            }
+        });
+        match res {
+            Ok(()) => Ok(interp.output),
+            Err(InterpreterError::Runtime(err)) => Err(err),
+        }
+    }
+
+    /**
+     * 对面已经结束了就返回 false
+     */
+    pub fn send(&self, message: Message) -> bool {
+        self.sender.send(message).is_ok()
+    }
+
+    /**
+     * 等到对面发来一个值。对面结束了，或者等的时候自己被 kill 了，返回 None
+     */
+    pub fn recv(&self) -> Option<Message> {
+        loop {
+            match self.receiver.recv_timeout(Duration::from_millis(10)) {
+                Ok(message) => {
+                    return Some(message);
+                }
+                Err(mpsc::RecvTimeoutError::Disconnected) => {
+                    return None;
+                }
+                Err(mpsc::RecvTimeoutError::Timeout) => {
+                    if self.is_killed() {
+                        return None;
+                    }
+                }
+            }
+        }
+    }
+
+    /**
+     * 让 isolate 在下一条指令停下来，join 的时候得到 Interrupted. 错误
+     */
+    pub fn kill(&self) {
+        self.interrupted.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_killed(&self) -> bool {
+        self.interrupted.load(Ordering::Relaxed)
+    }
+
+    /**
+     * 等线程结束，返回 isolate 的输出或者它的运行时错误。只能 join 一次，parent 那一头不能 join
+     */
+    pub fn join(&mut self) -> Result<Vec<String>, String> {
+        match self.thread.take() {
+            Some(thread) => thread.join().unwrap_or_else(|_| Err("isolate panicked".to_string())),
+            None => Err(format!("Isolate {} cannot be joined.", self.name)),
+        }
+    }
+}
+
+impl Interpreter {
+    /**
+     * 调用一个没有参数的全局函数，一直跑到它（以及它启动的 fiber）结束
+     */
+    pub fn call_global(&mut self, name: &str) -> Result<(), InterpreterError> {
+        let callee = match self.globals.get(name) {
+            Some(callee) => *callee,
+            None => {
+                return Err(InterpreterError::Runtime(format!("Undefined variable '{}'.", name)));
+            }
+        };
+        self.stack.push(callee);
+        self.call_value(callee, 0)?;
+        self.run()
+    }
+
+    /**
+     * 深拷贝成 Message。函数、实例这些带着堆上引用的值不能发，列表里面不能有环
+     */
+    pub fn to_message(&self, val: &value::Value) -> Result<Message, String> {
+        self.to_message_inner(val, &mut HashSet::new())
+    }
+
+    fn to_message_inner(
+        &self,
+        val: &value::Value,
+        path: &mut HashSet<gc::HeapId>
+    ) -> Result<Message, String> {
+        match val.unbox(&self.heap) {
+            value::Unboxed::Nil => Ok(Message::Nil),
+            value::Unboxed::Bool(b) => Ok(Message::Bool(b)),
+            value::Unboxed::Integer(n) => Ok(Message::Integer(n)),
+            value::Unboxed::Number(n) => Ok(Message::Number(n)),
+            value::Unboxed::String(id) => Ok(Message::String(self.heap.get_str(id).clone())),
+            value::Unboxed::List(id) => {
+                if !path.insert(id) {
+                    return Err("Cannot send a cyclic list to another isolate.".to_string());
+                }
+                let elements = self
+                    .get_list_elements(id)
+                    .iter()
+                    .map(|element| self.to_message_inner(element, path))
+                    .collect::<Result<Vec<Message>, String>>()?;
+                path.remove(&id);
+                Ok(Message::List(elements))
+            }
+            _ =>
+                Err(
+                    format!(
+                        "Cannot send a value of type {:?} to another isolate.",
+                        value::type_of(val)
+                    )
+                ),
+        }
+    }
+
+    pub fn from_message(&mut self, message: Message) -> value::Value {
+        match message {
+            Message::Nil => value::Value::NIL,
+            Message::Bool(b) => value::Value::bool(b),
+            Message::Integer(n) => self.heap.integer(n),
+            Message::Number(n) => value::Value::number(n),
+            Message::String(s) => value::Value::string(self.heap.manage_str(s)),
+            Message::List(elements) => {
+                let elements = elements
+                    .into_iter()
+                    .map(|element| self.from_message(element))
+                    .collect();
+                value::Value::list(self.heap.manage_list(elements))
+            }
+        }
+    }
+
+    /* ---------- ---------- isolate 上的方法 ---------- ---------- */
+
+    pub fn isolate_method(
+        isolate_id: gc::HeapId,
+        name: &str
+    ) -> Result<value::Value, InterpreterError> {
+        let method = match name {
+            "send" => value::IsolateMethod::Send,
+            "recv" => value::IsolateMethod::Recv,
+            "join" => value::IsolateMethod::Join,
+            "kill" => value::IsolateMethod::Kill,
+            _ => {
+                return Err(InterpreterError::Runtime(format!("Undefined property {}.", name)));
+            }
+        };
+        Ok(value::Value::isolate_method(isolate_id, method))
+    }
+
+    /**
+     * 栈上是方法和参数，换成返回值。recv 和 join 会阻塞整个线程，包括这个解释器里所有的 fiber
+     */
+    pub fn call_isolate_method(
+        &mut self,
+        isolate_id: gc::HeapId,
+        method: value::IsolateMethod,
+        arg_count: u8
+    ) -> Result<(), InterpreterError> {
+        let expected = match method {
+            value::IsolateMethod::Send => 1,
+            _ => 0,
+        };
+        if arg_count != expected {
+            return Err(
+                InterpreterError::Runtime(
+                    format!("Expected {} arguments but found {}.", expected, arg_count)
+                )
+            );
+        }
+
+        let result = match method {
+            value::IsolateMethod::Send => {
+                let val = self.pop_stack();
+                let message = self.to_message(&val).map_err(InterpreterError::Runtime)?;
+                value::Value::bool(self.heap.get_isolate(isolate_id).send(message))
+            }
+            value::IsolateMethod::Recv => {
+                match self.heap.get_isolate(isolate_id).recv() {
+                    Some(message) => self.from_message(message),
+                    None => value::Value::NIL,
+                }
+            }
+            value::IsolateMethod::Join => {
+                let isolate = self.heap.get_isolate_mut(isolate_id);
+                match isolate.join() {
+                    Ok(_) => value::Value::NIL,
+                    Err(_) if isolate.is_killed() => value::Value::NIL,
+                    Err(err) => {
+                        return Err(
+                            InterpreterError::Runtime(
+                                format!("Isolate {} failed: {}", isolate.name, err)
+                            )
+                        );
+                    }
+                }
+            }
+            value::IsolateMethod::Kill => {
+                self.heap.get_isolate(isolate_id).kill();
+                value::Value::NIL
+            }
+        };
+        self.pop_stack();
+        self.stack.push(result);
+        Ok(())
+    }
+}
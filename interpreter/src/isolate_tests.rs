@@ -0,0 +1,163 @@
+#[cfg(test)]
+mod tests {
+    use crate::bytecode_interpreter::*;
+    use crate::compiler::*;
+    use crate::extensions;
+    use crate::isolate::*;
+
+    use std::sync::atomic::{ AtomicUsize, Ordering };
+
+    fn evaluate(code: &str) -> Result<Vec<String>, String> {
+        let extensions = extensions::Extensions { lists: true, lambdas: true };
+        let func = Compiler::compile(String::from(code), extensions).unwrap();
+        let mut interp = Interpreter::default();
+        match interp.interpret(func) {
+            Ok(()) => Ok(interp.output),
+            Err(InterpreterError::Runtime(err)) => Err(err),
+        }
+    }
+
+    /**
+     * 把模块写到临时文件里，返回路径，Lox 里的 Isolate(path) 要读文件
+     */
+    fn write_module(code: &str) -> String {
+        static MODULE_ID: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "lox-isolate-test-{}-{}.lox",
+            std::process::id(),
+            MODULE_ID.fetch_add(1, Ordering::SeqCst)
+        ));
+        std::fs::write(&path, code).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn test_echo_from_rust() {
+        let mut isolate = Isolate::spawn(
+            "echo",
+            "print \"started\";\n\
+             fun main() {\n\
+               var x = parent.recv();\n\
+               while (x != nil) {\n\
+                 parent.send([x, x * 2]);\n\
+                 x = parent.recv();\n\
+               }\n\
+             }".to_string(),
+            Some("main".to_string())
+        );
+        for n in 1..4 {
+            assert!(isolate.send(Message::Integer(n)));
+            assert_eq!(
+                isolate.recv(),
+                Some(Message::List(vec![Message::Integer(n), Message::Integer(n * 2)]))
+            );
+        }
+        assert!(isolate.send(Message::Nil));
+        assert_eq!(isolate.join(), Ok(vec!["started".to_string()]));
+        assert_eq!(isolate.join(), Err("Isolate echo cannot be joined.".to_string()));
+    }
+
+    #[test]
+    fn test_kill() {
+        let mut isolate = Isolate::spawn("spin", "while (true) {}".to_string(), None);
+        isolate.kill();
+        assert_eq!(isolate.join(), Err("Interrupted.".to_string()));
+        // 线程已经结束了，通道断开
+        assert_eq!(isolate.recv(), None);
+        assert!(!isolate.send(Message::Nil));
+    }
+
+    #[test]
+    fn test_messages_are_copied() {
+        let path = write_module(
+            "fun main() {\n\
+               var xs = parent.recv();\n\
+               push(xs[1], 9);\n\
+               push(xs, 4);\n\
+               parent.send(xs);\n\
+             }"
+        );
+        let res = evaluate(
+            &format!(
+                "var iso = Isolate(\"{}\", \"main\");\n\
+                 var xs = [1, [2, 3], \"s\"];\n\
+                 print iso.send(xs);\n\
+                 var back = iso.recv();\n\
+                 print xs;\n\
+                 print back;\n\
+                 print iso.join();\n\
+                 print iso.recv();",
+                path
+            )
+        );
+        std::fs::remove_file(&path).ok();
+        assert_eq!(
+            res,
+            Ok(
+                vec![
+                    "true".to_string(),
+                    "[1, [2, 3], s]".to_string(),
+                    "[1, [2, 3, 9], s, 4]".to_string(),
+                    "nil".to_string(),
+                    "nil".to_string()
+                ]
+            )
+        );
+    }
+
+    #[test]
+    fn test_kill_from_lox() {
+        let path = write_module("while (true) {}");
+        let res = evaluate(
+            &format!("var iso = Isolate(\"{}\");\nprint iso;\niso.kill();\nprint iso.join();", path)
+        );
+        std::fs::remove_file(&path).ok();
+        assert_eq!(res, Ok(vec![format!("<isolate {}>", path), "nil".to_string()]));
+    }
+
+    #[test]
+    fn test_isolate_errors() {
+        let path = write_module("fun main() { parent.recv(); }");
+
+        let res = evaluate(&format!("var iso = Isolate(\"{}\", \"main\");\niso.send(clock);", path));
+        assert_eq!(
+            res,
+            Err("Cannot send a value of type NativeFunction to another isolate.".to_string())
+        );
+
+        let res = evaluate(
+            &format!(
+                "var iso = Isolate(\"{}\", \"main\");\n\
+                 var xs = [1];\n\
+                 push(xs, xs);\n\
+                 iso.send(xs);",
+                path
+            )
+        );
+        assert_eq!(res, Err("Cannot send a cyclic list to another isolate.".to_string()));
+
+        // 同一个列表出现两次不算环
+        let res = evaluate(
+            &format!(
+                "var iso = Isolate(\"{}\", \"main\");\n\
+                 var xs = [1];\n\
+                 print iso.send([xs, xs]);\n\
+                 print iso.join();",
+                path
+            )
+        );
+        assert_eq!(res, Ok(vec!["true".to_string(), "nil".to_string()]));
+        std::fs::remove_file(&path).ok();
+
+        let path = write_module("fun main() { return nil + 1; }");
+        let res = evaluate(&format!("var iso = Isolate(\"{}\", \"main\");\niso.join();", path));
+        std::fs::remove_file(&path).ok();
+        assert!(res.unwrap_err().starts_with(&format!("Isolate {} failed: ", path)));
+
+        let res = evaluate("Isolate(\"/nonexistent/module.lox\");");
+        assert!(res.unwrap_err().contains("could not read /nonexistent/module.lox"));
+
+        let res = evaluate("parent;");
+        assert!(res.is_err());
+    }
+}
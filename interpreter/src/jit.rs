@@ -0,0 +1,675 @@
+//! 可选的 JIT（cargo 特性 `jit`）：调用次数到了阈值的函数用 Cranelift 编译成本地代码
+//!
+//! 现在只编译"纯整数"函数：参数都是整数，函数体里只有局部变量、整数常量、
+//! 加减乘、比较、跳转，以及通过全局变量调用自己（比如 fib）。
+//! 这种函数没有副作用，本地代码里任何一个守卫失败（整数溢出、递归太深）
+//! 都可以直接放弃，让解释器从头再执行一遍这次调用。
+
+use crate::bytecode;
+use crate::gc;
+use crate::value;
+
+use cranelift_codegen::ir::condcodes::IntCC;
+use cranelift_codegen::ir::{
+    types,
+    AbiParam,
+    Block,
+    InstBuilder,
+    MemFlags,
+    StackSlotData,
+    StackSlotKind,
+    UserFuncName,
+};
+use cranelift_codegen::settings::{ self, Configurable };
+use cranelift_frontend::{ FunctionBuilder, FunctionBuilderContext, Variable };
+use cranelift_jit::{ JITBuilder, JITModule };
+use cranelift_module::{ default_libcall_names, FuncId, Linkage, Module };
+
+use std::collections::HashMap;
+
+/* ---------- ---------- 常量 ---------- ---------- */
+
+pub const MAX_ARITY: usize = 8; // 参数多的函数不编译，调用的时候参数放在栈上的定长数组里
+const MAX_DEPTH: i64 = 1000; // 本地代码里自己调自己的最大深度，再深就退回解释器
+const MAX_BAILS: usize = 8; // 退回解释器这么多次以后就不再走本地代码了
+const DEFAULT_THRESHOLD: usize = 100;
+
+/* ---------- ---------- 类型 ---------- ---------- */
+
+/**
+ * 编译期推出来的栈上每个位置的类型，布尔值在本地代码里用 0 和 1 表示
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ty {
+    Int,
+    Bool,
+    SelfFn, // GetGlobal 拿到的函数自己，只能马上被调用
+}
+
+impl Ty {
+    fn to_value(self, raw: i64, heap: &mut gc::Heap) -> value::Value {
+        match self {
+            Ty::Bool => value::Value::bool(raw != 0),
+            _ => heap.integer(raw),
+        }
+    }
+}
+
+/**
+ * 每个函数的 JIT 状态，函数的拷贝共用一份
+ */
+#[derive(Debug)]
+pub enum FunctionState {
+    Cold(/*调用次数*/ usize),
+    Compiled(Compiled),
+    Interpreted, // 编译不了，或者退回解释器的次数太多
+}
+
+impl Default for FunctionState {
+    fn default() -> FunctionState {
+        FunctionState::Cold(0)
+    }
+}
+
+/**
+ * 编译好的本地代码
+ */
+#[derive(Debug, Clone, Copy)]
+pub struct Compiled {
+    code: *const u8,
+    pub returns: Ty,
+    pub recursive: bool, // 函数体里通过全局变量调用了自己，调用前要确认全局变量没有被换掉
+    bails: usize,
+}
+
+type NativeFn = extern "C" fn(/*args*/ *const i64, /*status*/ *mut i64, /*depth*/ i64) -> i64;
+
+impl Compiled {
+    /**
+     * 执行本地代码，返回 None 说明有守卫失败了，要交给解释器重新执行
+     */
+    pub fn run(&self, args: &[i64]) -> Option<i64> {
+        let mut status = 0i64;
+        let native = unsafe { std::mem::transmute::<*const u8, NativeFn>(self.code) };
+        let raw = native(args.as_ptr(), &mut status, 0);
+        if status == 0 { Some(raw) } else { None }
+    }
+
+    /**
+     * 本地代码的返回值装箱，大整数要放到堆上
+     */
+    pub fn to_value(&self, raw: i64, heap: &mut gc::Heap) -> value::Value {
+        self.returns.to_value(raw, heap)
+    }
+}
+
+/* ---------- ---------- JIT ---------- ---------- */
+
+pub struct Jit {
+    module: Option<JITModule>, // 当前平台 Cranelift 不支持的时候是 None，什么都不编译
+    builder_ctx: FunctionBuilderContext,
+    threshold: usize, // 调用多少次以后编译
+    compiled: usize, // 编译成功的函数个数
+}
+
+impl Default for Jit {
+    fn default() -> Jit {
+        let threshold = std::env
+            ::var("LOX_JIT_THRESHOLD")
+            .ok()
+            .and_then(|env_str| env_str.parse::<usize>().ok())
+            .unwrap_or(DEFAULT_THRESHOLD);
+        Jit {
+            module: Jit::make_module(),
+            builder_ctx: FunctionBuilderContext::new(),
+            threshold,
+            compiled: 0,
+        }
+    }
+}
+
+impl Jit {
+    fn make_module() -> Option<JITModule> {
+        let mut flag_builder = settings::builder();
+        flag_builder.set("use_colocated_libcalls", "false").ok()?;
+        flag_builder.set("is_pic", "false").ok()?;
+        flag_builder.set("opt_level", "speed").ok()?;
+        let isa = cranelift_native::builder().ok()?.finish(settings::Flags::new(flag_builder)).ok()?;
+        Some(JITModule::new(JITBuilder::with_isa(isa, default_libcall_names())))
+    }
+
+    /**
+     * 强制打开：能编译的函数第一次调用就编译
+     */
+    pub fn force(&mut self) {
+        self.threshold = 0;
+    }
+
+    pub fn compiled_count(&self) -> usize {
+        self.compiled
+    }
+
+    /**
+     * 记一次调用，够热了就编译；已经有本地代码就返回
+     */
+    pub fn on_call(&mut self, function: &bytecode::Function) -> Option<Compiled> {
+        let mut state = function.jit.borrow_mut();
+        match &mut *state {
+            FunctionState::Compiled(compiled) => {
+                return Some(*compiled);
+            }
+            FunctionState::Interpreted => {
+                return None;
+            }
+            FunctionState::Cold(count) => {
+                *count += 1;
+                if *count <= self.threshold {
+                    return None;
+                }
+            }
+        }
+
+        match self.compile(function) {
+            Some(compiled) => {
+                self.compiled += 1;
+                *state = FunctionState::Compiled(compiled);
+                Some(compiled)
+            }
+            None => {
+                *state = FunctionState::Interpreted;
+                None
+            }
+        }
+    }
+
+    /**
+     * 本地代码退回了解释器，次数多了就不再用它
+     */
+    pub fn bail(&mut self, function: &bytecode::Function) {
+        let mut state = function.jit.borrow_mut();
+        if let FunctionState::Compiled(compiled) = &mut *state {
+            compiled.bails += 1;
+            if compiled.bails >= MAX_BAILS {
+                *state = FunctionState::Interpreted;
+            }
+        }
+    }
+
+    fn compile(&mut self, function: &bytecode::Function) -> Option<Compiled> {
+        let analysis = analyze(function)?;
+        let module = self.module.as_mut()?;
+
+        let pointer = module.target_config().pointer_type();
+        let mut signature = module.make_signature();
+        signature.params.push(AbiParam::new(pointer)); // 参数数组
+        signature.params.push(AbiParam::new(pointer)); // 状态，非 0 表示要退回解释器
+        signature.params.push(AbiParam::new(types::I64)); // 递归深度
+        signature.returns.push(AbiParam::new(types::I64));
+
+        let func_id = module
+            .declare_function(
+                &format!("lox_{}_{}", function.name, self.compiled),
+                Linkage::Local,
+                &signature
+            )
+            .ok()?;
+
+        let mut ctx = module.make_context();
+        ctx.func.signature = signature;
+        ctx.func.name = UserFuncName::user(0, func_id.as_u32());
+
+        let translated = {
+            let builder = FunctionBuilder::new(&mut ctx.func, &mut self.builder_ctx);
+            Translator::new(builder, module, func_id, pointer, function, &analysis).translate()
+        };
+        if translated.is_none() {
+            module.clear_context(&mut ctx);
+            return None;
+        }
+
+        module.define_function(func_id, &mut ctx).ok()?;
+        module.clear_context(&mut ctx);
+        module.finalize_definitions().ok()?;
+
+        Some(Compiled {
+            code: module.get_finalized_function(func_id),
+            returns: analysis.returns,
+            recursive: analysis.recursive,
+            bails: 0,
+        })
+    }
+}
+
+/* ---------- ---------- 类型推导 ---------- ---------- */
+
+/**
+ * 把每条指令执行之前栈上每个位置的类型推出来，推不出来（或者遇到不支持的指令）就不编译。
+ * 位置从 1 开始，对应 GetLocal 的下标，0 号槽位不在里面。
+ */
+struct Analysis {
+    states: Vec<Option<Vec<Ty>>>,
+    returns: Ty,
+    recursive: bool,
+    max_depth: usize,
+}
+
+fn jump_target(ip: usize, offset: usize, forward: bool) -> Option<usize> {
+    if forward { Some(ip + 1 + offset) } else { (ip + 1).checked_sub(offset) }
+}
+
+fn analyze(function: &bytecode::Function) -> Option<Analysis> {
+    let arity = usize::from(function.arity);
+    if
+        arity > MAX_ARITY ||
+        function.optional_args > 0 ||
+        function.has_rest ||
+        function.is_generator ||
+        function.is_async ||
+        function.name.is_empty()
+    {
+        return None;
+    }
+
+    let chunk = &function.chunk;
+    let mut states: Vec<Option<Vec<Ty>>> = vec![None; chunk.code.len()];
+    let mut worklist = vec![0];
+    states[0] = Some(vec![Ty::Int; arity]);
+    let mut returns = None;
+    let mut recursive = false;
+    let mut max_depth = arity;
+
+    // 把状态传给后继指令，已经有状态的话必须一样
+    fn flow(
+        states: &mut [Option<Vec<Ty>>],
+        worklist: &mut Vec<usize>,
+        target: Option<usize>,
+        stack: &[Ty]
+    ) -> Option<()> {
+        let target = target?;
+        match states.get(target)? {
+            Some(existing) => {
+                if existing.as_slice() != stack {
+                    return None;
+                }
+            }
+            None => {
+                states[target] = Some(stack.to_vec());
+                worklist.push(target);
+            }
+        }
+        Some(())
+    }
+
+    while let Some(ip) = worklist.pop() {
+        let mut stack = states[ip].clone()?;
+        let depth = stack.len();
+        let peek = |n: usize| -> Option<Ty> { stack.get(depth.checked_sub(n + 1)?).copied() };
+
+        let mut next = Some(ip + 1);
+        match &chunk.code[ip].0 {
+            bytecode::Op::Constant(idx) => {
+                match chunk.constants.get(*idx)? {
+                    bytecode::Constant::Integer(_) => stack.push(Ty::Int),
+                    _ => {
+                        return None;
+                    }
+                }
+            }
+            bytecode::Op::True | bytecode::Op::False => stack.push(Ty::Bool),
+            bytecode::Op::Negate => {
+                if peek(0)? != Ty::Int {
+                    return None;
+                }
+            }
+            bytecode::Op::Add | bytecode::Op::Subtract | bytecode::Op::Multiply => {
+                if peek(0)? != Ty::Int || peek(1)? != Ty::Int {
+                    return None;
+                }
+                stack.pop();
+            }
+            bytecode::Op::Less | bytecode::Op::Greater => {
+                if peek(0)? != Ty::Int || peek(1)? != Ty::Int {
+                    return None;
+                }
+                stack.truncate(depth - 2);
+                stack.push(Ty::Bool);
+            }
+            bytecode::Op::Equal => {
+                if peek(0)? != peek(1)? || peek(0)? == Ty::SelfFn {
+                    return None;
+                }
+                stack.truncate(depth - 2);
+                stack.push(Ty::Bool);
+            }
+            bytecode::Op::Not => {
+                if peek(0)? != Ty::Bool {
+                    return None;
+                }
+            }
+            bytecode::Op::Pop => {
+                stack.pop()?;
+            }
+            bytecode::Op::GetLocal(idx) => {
+                let ty = *stack.get(idx.checked_sub(1)?)?;
+                if ty == Ty::SelfFn {
+                    return None;
+                }
+                stack.push(ty);
+            }
+            bytecode::Op::SetLocal(idx) => {
+                let ty = peek(0)?;
+                if ty == Ty::SelfFn || *idx == 0 || *idx > depth {
+                    return None;
+                }
+                stack[idx - 1] = ty;
+            }
+            bytecode::Op::Dup(n) => {
+                let ty = peek(*n)?;
+                if ty == Ty::SelfFn {
+                    return None;
+                }
+                stack.push(ty);
+            }
+            bytecode::Op::Rot(n) => {
+                if *n >= depth {
+                    return None;
+                }
+                let ty = stack.pop()?;
+                stack.insert(depth - 1 - n, ty);
+            }
+            bytecode::Op::GetGlobal(idx) => {
+                match chunk.constants.get(*idx)? {
+                    bytecode::Constant::String(name) if *name == function.name => {
+                        stack.push(Ty::SelfFn);
+                    }
+                    _ => {
+                        return None;
+                    }
+                }
+            }
+            bytecode::Op::Call(arg_count) | bytecode::Op::TailCall(arg_count, _) => {
+                if let bytecode::Op::TailCall(_, names) = &chunk.code[ip].0 {
+                    if !names.is_empty() {
+                        return None;
+                    }
+                }
+                let arg_count = usize::from(*arg_count);
+                if arg_count != arity || peek(arg_count)? != Ty::SelfFn {
+                    return None;
+                }
+                if stack[depth - arg_count..].iter().any(|ty| *ty != Ty::Int) {
+                    return None;
+                }
+                // 先假设自己返回整数，最后再检查
+                stack.truncate(depth - arg_count - 1);
+                stack.push(Ty::Int);
+                recursive = true;
+            }
+            bytecode::Op::Return => {
+                let ty = peek(0)?;
+                if ty == Ty::SelfFn || returns.is_some_and(|returns| returns != ty) {
+                    return None;
+                }
+                returns = Some(ty);
+                next = None;
+            }
+            bytecode::Op::JumpIfFalse(offset) => {
+                if peek(0)? == Ty::SelfFn {
+                    return None;
+                }
+                flow(&mut states, &mut worklist, jump_target(ip, *offset, true), &stack)?;
+            }
+            bytecode::Op::Jump(offset) => {
+                next = jump_target(ip, *offset, true);
+                next?;
+            }
+            bytecode::Op::Loop(offset) => {
+                next = jump_target(ip, *offset, false);
+                next?;
+            }
+            _ => {
+                return None;
+            }
+        }
+
+        max_depth = max_depth.max(stack.len());
+        if next.is_some() {
+            flow(&mut states, &mut worklist, next, &stack)?;
+        }
+    }
+
+    let returns = returns?;
+    if recursive && returns != Ty::Int {
+        return None;
+    }
+
+    Some(Analysis { states, returns, recursive, max_depth })
+}
+
+/* ---------- ---------- 生成 Cranelift IR ---------- ---------- */
+
+/**
+ * 栈上的每个位置对应一个 Cranelift 变量，跳转目标各自是一个基本块
+ */
+struct Translator<'a> {
+    builder: FunctionBuilder<'a>,
+    module: &'a mut JITModule,
+    func_id: FuncId,
+    pointer: types::Type,
+    function: &'a bytecode::Function,
+    analysis: &'a Analysis,
+}
+
+impl<'a> Translator<'a> {
+    fn new(
+        builder: FunctionBuilder<'a>,
+        module: &'a mut JITModule,
+        func_id: FuncId,
+        pointer: types::Type,
+        function: &'a bytecode::Function,
+        analysis: &'a Analysis
+    ) -> Translator<'a> {
+        Translator { builder, module, func_id, pointer, function, analysis }
+    }
+
+    fn var(pos: usize) -> Variable {
+        Variable::from_u32(pos as u32)
+    }
+
+    fn translate(mut self) -> Option<()> {
+        let code = &self.function.chunk.code;
+        let states = &self.analysis.states;
+
+        for pos in 1..=self.analysis.max_depth {
+            self.builder.declare_var(Translator::var(pos), types::I64);
+        }
+
+        // 基本块从跳转目标、条件跳转的下一条开始
+        let mut blocks: HashMap<usize, Block> = HashMap::new();
+        blocks.insert(0, self.builder.create_block());
+        for (ip, (op, _)) in code.iter().enumerate() {
+            if states[ip].is_none() {
+                continue;
+            }
+            let targets = match op {
+                bytecode::Op::JumpIfFalse(offset) =>
+                    vec![Some(ip + 1), jump_target(ip, *offset, true)],
+                bytecode::Op::Jump(offset) => vec![jump_target(ip, *offset, true)],
+                bytecode::Op::Loop(offset) => vec![jump_target(ip, *offset, false)],
+                _ => vec![],
+            };
+            for target in targets.into_iter().flatten() {
+                blocks.entry(target).or_insert_with(|| self.builder.create_block());
+            }
+        }
+
+        // 入口：读参数，检查递归深度
+        let entry = self.builder.create_block();
+        let bail = self.builder.create_block();
+        self.builder.append_block_params_for_function_params(entry);
+        self.builder.switch_to_block(entry);
+        let params = self.builder.block_params(entry).to_vec();
+        let (args_ptr, status_ptr, depth) = (params[0], params[1], params[2]);
+        for pos in 1..=usize::from(self.function.arity) {
+            let offset = ((pos - 1) * 8) as i32;
+            let arg = self.builder.ins().load(types::I64, MemFlags::trusted(), args_ptr, offset);
+            self.builder.def_var(Translator::var(pos), arg);
+        }
+        let too_deep = self.builder.ins().icmp_imm(IntCC::SignedGreaterThan, depth, MAX_DEPTH);
+        self.builder.ins().brif(too_deep, bail, &[], blocks[&0], &[]);
+
+        // 守卫失败：把状态设成 1，返回值没有意义
+        self.builder.switch_to_block(bail);
+        let one = self.builder.ins().iconst(types::I64, 1);
+        self.builder.ins().store(MemFlags::trusted(), one, status_ptr, 0);
+        let zero = self.builder.ins().iconst(types::I64, 0);
+        self.builder.ins().return_(&[zero]);
+
+        let self_ref = self.module.declare_func_in_func(self.func_id, self.builder.func);
+        let mut terminated = true;
+        for (ip, (op, _)) in code.iter().enumerate() {
+            let Some(stack) = &states[ip] else {
+                continue;
+            };
+            if let Some(block) = blocks.get(&ip) {
+                if !terminated {
+                    self.builder.ins().jump(*block, &[]);
+                }
+                self.builder.switch_to_block(*block);
+                terminated = false;
+            }
+            if terminated {
+                return None;
+            }
+
+            let d = stack.len();
+            match op {
+                bytecode::Op::Constant(idx) => {
+                    let bytecode::Constant::Integer(n) = self.function.chunk.constants[*idx] else {
+                        return None;
+                    };
+                    let val = self.builder.ins().iconst(types::I64, n);
+                    self.builder.def_var(Translator::var(d + 1), val);
+                }
+                bytecode::Op::True | bytecode::Op::False => {
+                    let b = matches!(op, bytecode::Op::True);
+                    let val = self.builder.ins().iconst(types::I64, i64::from(b));
+                    self.builder.def_var(Translator::var(d + 1), val);
+                }
+                bytecode::Op::Negate => {
+                    let val = self.builder.use_var(Translator::var(d));
+                    let zero = self.builder.ins().iconst(types::I64, 0);
+                    let (res, overflow) = self.builder.ins().ssub_overflow(zero, val);
+                    self.guard(overflow, bail);
+                    self.builder.def_var(Translator::var(d), res);
+                }
+                bytecode::Op::Add | bytecode::Op::Subtract | bytecode::Op::Multiply => {
+                    let lhs = self.builder.use_var(Translator::var(d - 1));
+                    let rhs = self.builder.use_var(Translator::var(d));
+                    let (res, overflow) = match op {
+                        bytecode::Op::Add => self.builder.ins().sadd_overflow(lhs, rhs),
+                        bytecode::Op::Subtract => self.builder.ins().ssub_overflow(lhs, rhs),
+                        _ => self.builder.ins().smul_overflow(lhs, rhs),
+                    };
+                    self.guard(overflow, bail);
+                    self.builder.def_var(Translator::var(d - 1), res);
+                }
+                bytecode::Op::Less | bytecode::Op::Greater | bytecode::Op::Equal => {
+                    let cc = match op {
+                        bytecode::Op::Less => IntCC::SignedLessThan,
+                        bytecode::Op::Greater => IntCC::SignedGreaterThan,
+                        _ => IntCC::Equal,
+                    };
+                    let lhs = self.builder.use_var(Translator::var(d - 1));
+                    let rhs = self.builder.use_var(Translator::var(d));
+                    let cmp = self.builder.ins().icmp(cc, lhs, rhs);
+                    let res = self.builder.ins().uextend(types::I64, cmp);
+                    self.builder.def_var(Translator::var(d - 1), res);
+                }
+                bytecode::Op::Not => {
+                    let val = self.builder.use_var(Translator::var(d));
+                    let res = self.builder.ins().bxor_imm(val, 1);
+                    self.builder.def_var(Translator::var(d), res);
+                }
+                bytecode::Op::Pop | bytecode::Op::GetGlobal(_) => {}
+                bytecode::Op::GetLocal(idx) => {
+                    let val = self.builder.use_var(Translator::var(*idx));
+                    self.builder.def_var(Translator::var(d + 1), val);
+                }
+                bytecode::Op::SetLocal(idx) => {
+                    let val = self.builder.use_var(Translator::var(d));
+                    self.builder.def_var(Translator::var(*idx), val);
+                }
+                bytecode::Op::Dup(n) => {
+                    let val = self.builder.use_var(Translator::var(d - n));
+                    self.builder.def_var(Translator::var(d + 1), val);
+                }
+                bytecode::Op::Rot(n) => {
+                    let moved: Vec<_> = (d - n..=d)
+                        .map(|pos| self.builder.use_var(Translator::var(pos)))
+                        .collect();
+                    self.builder.def_var(Translator::var(d - n), moved[*n]);
+                    for (i, val) in moved[..*n].iter().enumerate() {
+                        self.builder.def_var(Translator::var(d - n + 1 + i), *val);
+                    }
+                }
+                bytecode::Op::Call(arg_count) | bytecode::Op::TailCall(arg_count, _) => {
+                    let arg_count = usize::from(*arg_count);
+                    let args_ptr = if arg_count == 0 {
+                        self.builder.ins().iconst(self.pointer, 0)
+                    } else {
+                        let slot = self.builder.create_sized_stack_slot(
+                            StackSlotData::new(StackSlotKind::ExplicitSlot, (arg_count * 8) as u32, 3)
+                        );
+                        for i in 0..arg_count {
+                            let arg = self.builder.use_var(Translator::var(d - arg_count + 1 + i));
+                            self.builder.ins().stack_store(arg, slot, (i * 8) as i32);
+                        }
+                        self.builder.ins().stack_addr(self.pointer, slot, 0)
+                    };
+                    let next_depth = self.builder.ins().iadd_imm(depth, 1);
+                    let call = self.builder.ins().call(self_ref, &[args_ptr, status_ptr, next_depth]);
+                    let res = self.builder.inst_results(call)[0];
+                    let status = self.builder.ins().load(types::I64, MemFlags::trusted(), status_ptr, 0);
+                    self.guard(status, bail);
+                    self.builder.def_var(Translator::var(d - arg_count), res);
+                }
+                bytecode::Op::Return => {
+                    let val = self.builder.use_var(Translator::var(d));
+                    self.builder.ins().return_(&[val]);
+                    terminated = true;
+                }
+                bytecode::Op::JumpIfFalse(offset) => {
+                    let cond = self.builder.use_var(Translator::var(d));
+                    let target = blocks[&jump_target(ip, *offset, true)?];
+                    self.builder.ins().brif(cond, blocks[&(ip + 1)], &[], target, &[]);
+                    terminated = true;
+                }
+                bytecode::Op::Jump(offset) => {
+                    self.builder.ins().jump(blocks[&jump_target(ip, *offset, true)?], &[]);
+                    terminated = true;
+                }
+                bytecode::Op::Loop(offset) => {
+                    self.builder.ins().jump(blocks[&jump_target(ip, *offset, false)?], &[]);
+                    terminated = true;
+                }
+                _ => {
+                    return None;
+                }
+            }
+        }
+
+        self.builder.seal_all_blocks();
+        self.builder.finalize();
+        Some(())
+    }
+
+    /**
+     * cond 非 0 就跳到 bail，否则在一个新的块里接着生成
+     */
+    fn guard(&mut self, cond: cranelift_codegen::ir::Value, bail: Block) {
+        let cont = self.builder.create_block();
+        self.builder.ins().brif(cond, bail, &[], cont, &[]);
+        self.builder.switch_to_block(cont);
+    }
+}
@@ -0,0 +1,432 @@
+//! JSON 的解析和输出，两个解释器共用。
+//! 中间是和解释器无关的 Json：解释器各自负责把自己的值转成 Json（顺便检查环），再把 Json 变回值。
+//! 错误信息末尾不带句号，由解释器加上（字节码解释器调用内置函数出错的时候统一加）
+
+/**
+ * 列表和对象最多嵌套这么多层。解析、输出和两边的转换都是递归的，再深就会把栈撑爆
+ */
+pub const MAX_DEPTH: usize = 512;
+
+/**
+ * 整数和小数分开：没有小数点和指数的数字是 Integer，输出的时候小数总是带着小数点，来回一趟类型不变
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Integer(i64),
+    Number(f64),
+    String(String),
+    List(Vec<Json>),
+    Object(Vec<(String, Json)>), // 按出现的顺序，重复的键后面的覆盖前面的（转成实例字段的时候）
+}
+
+/* ---------- ---------- 解析 ---------- ---------- */
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    line: usize,
+    col: usize,
+    depth: usize, // 当前在第几层列表或者对象里面
+}
+
+/**
+ * 出错的时候报告行号和列号（都从 1 开始）
+ */
+pub fn parse(text: &str) -> Result<Json, String> {
+    let mut parser = Parser {
+        chars: text.chars().collect(),
+        pos: 0,
+        line: 1,
+        col: 1,
+        depth: 0,
+    };
+    parser.skip_whitespace();
+    let json = parser.value()?;
+    parser.skip_whitespace();
+    if parser.peek().is_some() {
+        return Err(parser.unexpected());
+    }
+    Ok(json)
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn advance(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += 1;
+        if c == '\n' {
+            self.line += 1;
+            self.col = 1;
+        } else {
+            self.col += 1;
+        }
+        Some(c)
+    }
+
+    fn error(&self, message: &str) -> String {
+        format!("Invalid JSON at line {}, column {}: {}", self.line, self.col, message)
+    }
+
+    fn unexpected(&self) -> String {
+        match self.peek() {
+            Some(c) => self.error(&format!("unexpected character '{}'", c)),
+            None => self.error("unexpected end of input"),
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        if self.peek() == Some(expected) {
+            self.advance();
+            Ok(())
+        } else {
+            Err(self.unexpected())
+        }
+    }
+
+    /**
+     * 进入一层列表或者对象，太深了就报错，位置是开头的括号
+     */
+    fn enter(&mut self) -> Result<(), String> {
+        if self.depth == MAX_DEPTH {
+            return Err(self.error("nesting too deep"));
+        }
+        self.depth += 1;
+        Ok(())
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(' ' | '\t' | '\n' | '\r') = self.peek() {
+            self.advance();
+        }
+    }
+
+    fn value(&mut self) -> Result<Json, String> {
+        match self.peek() {
+            Some('n') => self.keyword("null", Json::Null),
+            Some('t') => self.keyword("true", Json::Bool(true)),
+            Some('f') => self.keyword("false", Json::Bool(false)),
+            Some('"') => Ok(Json::String(self.string()?)),
+            Some('[') => self.list(),
+            Some('{') => self.object(),
+            Some('-' | '0'..='9') => self.number(),
+            _ => Err(self.unexpected()),
+        }
+    }
+
+    fn keyword(&mut self, word: &str, json: Json) -> Result<Json, String> {
+        for expected in word.chars() {
+            self.expect(expected)?;
+        }
+        Ok(json)
+    }
+
+    fn list(&mut self) -> Result<Json, String> {
+        self.enter()?;
+        self.expect('[')?;
+        self.skip_whitespace();
+        let mut elements = Vec::new();
+        if self.peek() == Some(']') {
+            self.advance();
+            self.depth -= 1;
+            return Ok(Json::List(elements));
+        }
+        loop {
+            elements.push(self.value()?);
+            self.skip_whitespace();
+            match self.peek() {
+                Some(',') => {
+                    self.advance();
+                    self.skip_whitespace();
+                }
+                Some(']') => {
+                    self.advance();
+                    self.depth -= 1;
+                    return Ok(Json::List(elements));
+                }
+                _ => {
+                    return Err(self.unexpected());
+                }
+            }
+        }
+    }
+
+    fn object(&mut self) -> Result<Json, String> {
+        self.enter()?;
+        self.expect('{')?;
+        self.skip_whitespace();
+        let mut entries = Vec::new();
+        if self.peek() == Some('}') {
+            self.advance();
+            self.depth -= 1;
+            return Ok(Json::Object(entries));
+        }
+        loop {
+            if self.peek() != Some('"') {
+                return Err(self.unexpected());
+            }
+            let key = self.string()?;
+            self.skip_whitespace();
+            self.expect(':')?;
+            self.skip_whitespace();
+            entries.push((key, self.value()?));
+            self.skip_whitespace();
+            match self.peek() {
+                Some(',') => {
+                    self.advance();
+                    self.skip_whitespace();
+                }
+                Some('}') => {
+                    self.advance();
+                    self.depth -= 1;
+                    return Ok(Json::Object(entries));
+                }
+                _ => {
+                    return Err(self.unexpected());
+                }
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.expect('"')?;
+        let mut res = String::new();
+        loop {
+            match self.peek() {
+                None => {
+                    return Err(self.error("unterminated string"));
+                }
+                Some('"') => {
+                    self.advance();
+                    return Ok(res);
+                }
+                Some('\\') => {
+                    self.advance();
+                    res.push(self.escape()?);
+                }
+                Some(c) if (c as u32) < 0x20 => {
+                    return Err(self.error("control character in string"));
+                }
+                Some(c) => {
+                    self.advance();
+                    res.push(c);
+                }
+            }
+        }
+    }
+
+    fn escape(&mut self) -> Result<char, String> {
+        let c = match self.peek() {
+            Some('"') => '"',
+            Some('\\') => '\\',
+            Some('/') => '/',
+            Some('b') => '\u{8}',
+            Some('f') => '\u{c}',
+            Some('n') => '\n',
+            Some('r') => '\r',
+            Some('t') => '\t',
+            Some('u') => {
+                self.advance();
+                return self.unicode_escape();
+            }
+            Some(_) => {
+                return Err(self.error("invalid escape"));
+            }
+            None => {
+                return Err(self.error("unterminated string"));
+            }
+        };
+        self.advance();
+        Ok(c)
+    }
+
+    /**
+     * \uXXXX，基本平面以外的字符要用两个代理项
+     */
+    fn unicode_escape(&mut self) -> Result<char, String> {
+        let high = self.hex4()?;
+        if !(0xd800..0xdc00).contains(&high) {
+            return char::from_u32(high).ok_or_else(|| self.error("invalid unicode escape"));
+        }
+        if self.peek() != Some('\\') {
+            return Err(self.error("unpaired surrogate in unicode escape"));
+        }
+        self.advance();
+        self.expect('u')?;
+        let low = self.hex4()?;
+        if !(0xdc00..0xe000).contains(&low) {
+            return Err(self.error("unpaired surrogate in unicode escape"));
+        }
+        let code = 0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00);
+        char::from_u32(code).ok_or_else(|| self.error("invalid unicode escape"))
+    }
+
+    fn hex4(&mut self) -> Result<u32, String> {
+        let mut code = 0;
+        for _ in 0..4 {
+            match self.peek().and_then(|c| c.to_digit(16)) {
+                Some(digit) => {
+                    self.advance();
+                    code = code * 16 + digit;
+                }
+                None => {
+                    return Err(self.error("invalid unicode escape"));
+                }
+            }
+        }
+        Ok(code)
+    }
+
+    fn digits(&mut self, text: &mut String) -> Result<(), String> {
+        if !matches!(self.peek(), Some('0'..='9')) {
+            return Err(self.unexpected());
+        }
+        while let Some(c @ '0'..='9') = self.peek() {
+            self.advance();
+            text.push(c);
+        }
+        Ok(())
+    }
+
+    fn number(&mut self) -> Result<Json, String> {
+        let (line, col) = (self.line, self.col);
+        let mut text = String::new();
+        if self.peek() == Some('-') {
+            self.advance();
+            text.push('-');
+        }
+        // 0 后面不能再跟数字
+        if self.peek() == Some('0') {
+            self.advance();
+            text.push('0');
+        } else {
+            self.digits(&mut text)?;
+        }
+        let mut is_integer = true;
+        if self.peek() == Some('.') {
+            self.advance();
+            text.push('.');
+            self.digits(&mut text)?;
+            is_integer = false;
+        }
+        if let Some(c @ ('e' | 'E')) = self.peek() {
+            self.advance();
+            text.push(c);
+            if let Some(sign @ ('+' | '-')) = self.peek() {
+                self.advance();
+                text.push(sign);
+            }
+            self.digits(&mut text)?;
+            is_integer = false;
+        }
+
+        // 放不进 i64 的整数当成小数
+        if is_integer {
+            if let Ok(n) = text.parse::<i64>() {
+                return Ok(Json::Integer(n));
+            }
+        }
+        match text.parse::<f64>() {
+            Ok(n) if n.is_finite() => Ok(Json::Number(n)),
+            _ => Err(format!("Invalid JSON at line {}, column {}: number out of range", line, col)),
+        }
+    }
+}
+
+/* ---------- ---------- 输出 ---------- ---------- */
+
+/**
+ * indent 是空的就输出在一行里，否则每个元素单独一行，每一层多缩进一个 indent
+ */
+pub fn stringify(json: &Json, indent: &str) -> Result<String, String> {
+    let mut out = String::new();
+    write_value(json, indent, 0, &mut out)?;
+    Ok(out)
+}
+
+fn write_value(json: &Json, indent: &str, depth: usize, out: &mut String) -> Result<(), String> {
+    if depth == MAX_DEPTH && matches!(json, Json::List(_) | Json::Object(_)) {
+        return Err(too_deep());
+    }
+    match json {
+        Json::Null => out.push_str("null"),
+        Json::Bool(b) => out.push_str(&b.to_string()),
+        Json::Integer(n) => out.push_str(&n.to_string()),
+        Json::Number(n) => {
+            if !n.is_finite() {
+                return Err(format!("Cannot convert {} to JSON", n));
+            }
+            // Debug 格式总带着小数点或者指数，比如 100.0 和 1e21
+            out.push_str(&format!("{:?}", n));
+        }
+        Json::String(s) => write_string(s, out),
+        Json::List(elements) => {
+            out.push('[');
+            for (i, element) in elements.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                newline(indent, depth + 1, out);
+                write_value(element, indent, depth + 1, out)?;
+            }
+            if !elements.is_empty() {
+                newline(indent, depth, out);
+            }
+            out.push(']');
+        }
+        Json::Object(entries) => {
+            out.push('{');
+            for (i, (key, val)) in entries.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                newline(indent, depth + 1, out);
+                write_string(key, out);
+                out.push(':');
+                if !indent.is_empty() {
+                    out.push(' ');
+                }
+                write_value(val, indent, depth + 1, out)?;
+            }
+            if !entries.is_empty() {
+                newline(indent, depth, out);
+            }
+            out.push('}');
+        }
+    }
+    Ok(())
+}
+
+/**
+ * 两个解释器把值转成 Json 的时候也用这个检查深度
+ */
+pub fn too_deep() -> String {
+    format!("Cannot convert a structure nested more than {} levels deep to JSON", MAX_DEPTH)
+}
+
+fn newline(indent: &str, depth: usize, out: &mut String) {
+    if !indent.is_empty() {
+        out.push('\n');
+        out.push_str(&indent.repeat(depth));
+    }
+}
+
+fn write_string(s: &str, out: &mut String) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
@@ -0,0 +1,93 @@
+#[cfg(test)]
+mod tests {
+    use crate::json::*;
+
+    #[test]
+    fn test_parse_values() {
+        assert_eq!(
+            parse(" {\"a\": [null, true, false], \"b\": {}} "),
+            Ok(
+                Json::Object(
+                    vec![
+                        ("a".to_string(), Json::List(vec![Json::Null, Json::Bool(true), Json::Bool(false)])),
+                        ("b".to_string(), Json::Object(Vec::new()))
+                    ]
+                )
+            )
+        );
+        assert_eq!(parse("-12"), Ok(Json::Integer(-12)));
+        assert_eq!(parse("0.25"), Ok(Json::Number(0.25)));
+        assert_eq!(parse("1E+2"), Ok(Json::Number(100.0)));
+        // 放不进 i64 的整数变成小数
+        assert_eq!(parse("92233720368547758070"), Ok(Json::Number(92233720368547758070.0)));
+        assert_eq!(
+            parse("\"a\\\"\\\\\\/\\b\\f\\n\\r\\t\\u00e9\\ud83d\\ude00\""),
+            Ok(Json::String("a\"\\/\u{8}\u{c}\n\r\té😀".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let error = |text: &str| parse(text).unwrap_err();
+        assert_eq!(error(""), "Invalid JSON at line 1, column 1: unexpected end of input");
+        assert_eq!(error("[1,]"), "Invalid JSON at line 1, column 4: unexpected character ']'");
+        assert_eq!(error("{1: 2}"), "Invalid JSON at line 1, column 2: unexpected character '1'");
+        assert_eq!(error("[1] 2"), "Invalid JSON at line 1, column 5: unexpected character '2'");
+        assert_eq!(error("\n\n  -"), "Invalid JSON at line 3, column 4: unexpected end of input");
+        assert_eq!(error("1."), "Invalid JSON at line 1, column 3: unexpected end of input");
+        assert_eq!(error("1e999"), "Invalid JSON at line 1, column 1: number out of range");
+        assert_eq!(error("\"abc"), "Invalid JSON at line 1, column 5: unterminated string");
+        assert_eq!(error("\"\\x\""), "Invalid JSON at line 1, column 3: invalid escape");
+        assert_eq!(error("\"\\u12\""), "Invalid JSON at line 1, column 6: invalid unicode escape");
+        assert_eq!(
+            error("\"\\ud83d\""),
+            "Invalid JSON at line 1, column 8: unpaired surrogate in unicode escape"
+        );
+        assert_eq!(error("\"a\nb\""), "Invalid JSON at line 1, column 3: control character in string");
+    }
+
+    #[test]
+    fn test_nesting_limit() {
+        let nested = |depth: usize| "[".repeat(depth) + &"]".repeat(depth);
+        assert!(parse(&nested(MAX_DEPTH)).is_ok());
+        assert_eq!(
+            parse(&nested(MAX_DEPTH + 1)).unwrap_err(),
+            format!("Invalid JSON at line 1, column {}: nesting too deep", MAX_DEPTH + 1)
+        );
+        // 很深的输入也只是报错，不会把栈撑爆
+        assert_eq!(
+            parse(&"{\"a\": [".repeat(5000)).unwrap_err(),
+            "Invalid JSON at line 1, column 1793: nesting too deep"
+        );
+
+        let mut json = Json::Null;
+        for _ in 0..MAX_DEPTH {
+            json = Json::List(vec![json]);
+        }
+        assert!(stringify(&json, "").is_ok());
+        assert_eq!(stringify(&Json::List(vec![json]), ""), Err(too_deep()));
+    }
+
+    #[test]
+    fn test_stringify() {
+        let json = Json::Object(
+            vec![
+                ("xs".to_string(), Json::List(vec![Json::Integer(1), Json::Number(1.0), Json::Number(1e21)])),
+                ("s".to_string(), Json::String("\u{1}\"".to_string())),
+                ("empty".to_string(), Json::List(Vec::new()))
+            ]
+        );
+        assert_eq!(
+            stringify(&json, ""),
+            Ok("{\"xs\":[1,1.0,1e21],\"s\":\"\\u0001\\\"\",\"empty\":[]}".to_string())
+        );
+        assert_eq!(
+            stringify(&json, " "),
+            Ok(
+                "{\n \"xs\": [\n  1,\n  1.0,\n  1e21\n ],\n \"s\": \"\\u0001\\\"\",\n \"empty\": []\n}".to_string()
+            )
+        );
+        assert_eq!(parse(&stringify(&json, "\t").unwrap()), Ok(json));
+        assert_eq!(stringify(&Json::Number(f64::NAN), ""), Err("Cannot convert NaN to JSON".to_string()));
+    }
+}
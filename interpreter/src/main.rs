@@ -1,5 +1,3 @@
-use std::ops::{ Add, Sub, Mul, Div };
-
 pub mod scanner;
 pub mod parser;
 pub mod expr;
@@ -11,14 +9,45 @@ pub mod gc;
 pub mod value;
 pub mod bytecode;
 pub mod builtins;
+pub mod checker;
+pub mod compiler;
+pub mod prelude;
+pub mod aot;
+pub mod register;
+pub mod register_interpreter;
+pub mod fiber;
+pub mod event_loop;
+pub mod isolate;
+pub mod json;
+#[cfg(feature = "jit")]
+pub mod jit;
+
+#[cfg(test)]
+mod treewalk_tests;
+#[cfg(test)]
+mod bytecode_tests;
+#[cfg(test)]
+mod parser_tests;
+#[cfg(test)]
+mod scanner_tests;
+#[cfg(test)]
+mod aot_tests;
+#[cfg(test)]
+mod register_tests;
+#[cfg(test)]
+mod isolate_tests;
+#[cfg(test)]
+mod json_tests;
 
 // 表达式trait
+#[allow(dead_code)]
 trait Expression {
     fn evaluate(&self) -> i32;
 }
 
 /* ---------- ---------- 数字表达式 ---------- ---------- */
 
+#[allow(dead_code)]
 struct Number {
     value: i32,
 }
@@ -31,6 +60,7 @@ impl Expression for Number {
 
 /* ---------- ---------- 加法表达式 ---------- ---------- */
 
+#[allow(dead_code)]
 struct AddExpr {
     left: Box<dyn Expression>,
     right: Box<dyn Expression>,
@@ -44,6 +74,7 @@ impl Expression for AddExpr {
 
 /* ---------- ---------- 减法表达式 ---------- ---------- */
 
+#[allow(dead_code)]
 struct SubExpr {
     left: Box<dyn Expression>,
     right: Box<dyn Expression>,
@@ -57,6 +88,7 @@ impl Expression for SubExpr {
 
 /* ---------- ---------- 乘法表达式 ---------- ---------- */
 
+#[allow(dead_code)]
 struct MulExpr {
     left: Box<dyn Expression>,
     right: Box<dyn Expression>,
@@ -70,6 +102,7 @@ impl Expression for MulExpr {
 
 /* ---------- ---------- 除法表达式 ---------- ---------- */
 
+#[allow(dead_code)]
 struct DivExpr {
     left: Box<dyn Expression>,
     right: Box<dyn Expression>,
@@ -117,6 +150,95 @@ fn parse_expr(input: &str) -> IResult<&str, i32> {
 }
 
 fn main() {
+    // lox build script.lox -o script：编译成可执行文件
+    let matches = clap::App
+        ::new("lox")
+        .subcommand(
+            clap::SubCommand
+                ::with_name("build")
+                .about("Compile a lox script to a native executable")
+                .arg(clap::Arg::with_name("script").required(true))
+                .arg(clap::Arg::with_name("output").short("o").takes_value(true))
+        )
+        .subcommand(
+            clap::SubCommand
+                ::with_name("run")
+                .about("Run a lox script on the bytecode virtual machine")
+                .arg(clap::Arg::with_name("script").required(true))
+                .arg(
+                    clap::Arg
+                        ::with_name("backend")
+                        .long("backend")
+                        .takes_value(true)
+                        .possible_values(&["stack", "register"])
+                        .default_value("stack")
+                        .help("Instruction set to execute")
+                )
+                .arg(
+                    clap::Arg
+                        ::with_name("count")
+                        .long("count-instructions")
+                        .help("Print the number of executed instructions to stderr")
+                )
+                .arg(
+                    clap::Arg
+                        ::with_name("fake-clock")
+                        .long("fake-clock")
+                        .help("Let timers and sleep skip ahead instead of waiting")
+                )
+        )
+        .get_matches();
+    // lox run script.lox --backend register：用寄存器指令执行
+    if let Some(run) = matches.subcommand_matches("run") {
+        let script = run.value_of("script").unwrap();
+        let source = std::fs::read_to_string(script).unwrap_or_else(|err| {
+            eprintln!("lox run: could not read {}: {}", script, err);
+            std::process::exit(1);
+        });
+        let extensions = extensions::Extensions { lists: true, lambdas: true };
+        // 有类型标注的地方在执行之前先检查一遍
+        if let Err(errs) = checker::check_source(&source, extensions) {
+            for err in errs {
+                eprintln!("lox run: {}", err.what);
+            }
+            std::process::exit(65);
+        }
+        let func = compiler::Compiler::compile(source, extensions).unwrap_or_else(|err| {
+            eprintln!("lox run: {}", err);
+            std::process::exit(65);
+        });
+        let mut interp = bytecode_interpreter::Interpreter::default();
+        interp.registers = run.value_of("backend") == Some("register");
+        if run.is_present("fake-clock") {
+            interp.event_loop.use_fake_clock();
+        }
+        let res = interp.interpret(func);
+        if run.is_present("count") {
+            eprintln!("{} instructions", interp.instruction_count);
+        }
+        if let Err(err) = res {
+            eprintln!("{}\n\n{}", err, interp.format_backtrace());
+            std::process::exit(70);
+        }
+        return;
+    }
+    if let Some(build) = matches.subcommand_matches("build") {
+        let script = build.value_of("script").unwrap();
+        let output = match build.value_of("output") {
+            Some(output) => std::path::PathBuf::from(output),
+            None => std::path::Path::new(script).with_extension(""),
+        };
+        let res = std::fs
+            ::read_to_string(script)
+            .map_err(|err| format!("could not read {}: {}", script, err))
+            .and_then(|source| aot::build(&source, &output));
+        if let Err(err) = res {
+            eprintln!("lox build: {}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
+
     let test_expr = "3 + (2 - 1)";
     match parse_expr(test_expr) {
         Ok((_, result)) => println!("结果是: {}", result),
@@ -9,6 +9,9 @@ struct Parser {
     tokens: Vec<scanner::Token>,
     current: usize, // 下表，指针
     in_fundec: bool, // in rust, booleans default to false: https://doc.rust-lang.org/std/primitive.bool.html#impl-Default
+    loop_depth: usize, // 嵌套了几层循环，break / continue 只能出现在循环里面
+    saw_yield: bool, // 当前的函数体里面有没有 yield
+    value_return: Option<expr::SourceLocation>, // 当前的函数体里面第一个带返回值的 return
     extensions: extensions::Extensions,
 }
 
@@ -30,6 +33,60 @@ pub enum Error {
         line: usize,
         col: i64,
     },
+    LoopControlNotInLoop {
+        keyword: String,
+        line: usize,
+        col: i64,
+    },
+    YieldNotInFun {
+        line: usize,
+        col: i64,
+    },
+    ReturnValueInGenerator {
+        line: usize,
+        col: i64,
+    },
+    GeneratorInitializer {
+        line: usize,
+        col: i64,
+    },
+    DuplicateBinding {
+        name: String,
+        line: usize,
+        col: i64,
+    },
+    DuplicateVariant {
+        name: String,
+        line: usize,
+        col: i64,
+    },
+    DuplicateField {
+        name: String,
+        line: usize,
+        col: i64,
+    },
+    SetterArity {
+        line: usize,
+        col: i64,
+    },
+    RequiredAfterDefault {
+        name: String,
+        line: usize,
+        col: i64,
+    },
+    RestNotLast {
+        line: usize,
+        col: i64,
+    },
+    PositionalAfterNamed {
+        line: usize,
+        col: i64,
+    },
+    DuplicateNamedArgument {
+        name: String,
+        line: usize,
+        col: i64,
+    },
     InvalidAssignment {
         line: usize,
         col: i64,
@@ -89,6 +146,62 @@ impl fmt::Debug for Error {
                     line,
                     col
                 ),
+            Error::LoopControlNotInLoop { keyword, line, col } =>
+                write!(
+                    f,
+                    "{} statement not enclosed in a loop at line={},col={}",
+                    keyword,
+                    line,
+                    col
+                ),
+            Error::YieldNotInFun { line, col } =>
+                write!(
+                    f,
+                    "yield statement not enclosed in a FunDecl at line={},col={}",
+                    line,
+                    col
+                ),
+            Error::ReturnValueInGenerator { line, col } =>
+                write!(
+                    f,
+                    "cannot return a value from a generator at line={},col={}",
+                    line,
+                    col
+                ),
+            Error::GeneratorInitializer { line, col } =>
+                write!(f, "init cannot be a generator at line={},col={}", line, col),
+            Error::DuplicateBinding { name, line, col } =>
+                write!(f, "duplicate binding '{}' in pattern at line={},col={}", name, line, col),
+            Error::DuplicateVariant { name, line, col } =>
+                write!(f, "duplicate variant '{}' in enum at line={},col={}", name, line, col),
+            Error::DuplicateField { name, line, col } =>
+                write!(f, "duplicate field '{}' in variant at line={},col={}", name, line, col),
+            Error::SetterArity { line, col } =>
+                write!(
+                    f,
+                    "a setter must take exactly one parameter at line={},col={}",
+                    line,
+                    col
+                ),
+            Error::RequiredAfterDefault { name, line, col } =>
+                write!(
+                    f,
+                    "parameter '{}' without a default follows a parameter with a default at line={},col={}",
+                    name,
+                    line,
+                    col
+                ),
+            Error::RestNotLast { line, col } =>
+                write!(f, "rest parameter must be the last parameter at line={},col={}", line, col),
+            Error::PositionalAfterNamed { line, col } =>
+                write!(
+                    f,
+                    "positional argument follows named arguments at line={},col={}",
+                    line,
+                    col
+                ),
+            Error::DuplicateNamedArgument { name, line, col } =>
+                write!(f, "argument '{}' passed more than once at line={},col={}", name, line, col),
             Error::InvalidAssignment { line, col } => {
                 write!(f, "invalid assignment target at line={},col={}", line, col)
             }
@@ -127,6 +240,17 @@ impl fmt::Debug for Error {
     }
 }
 
+/**
+ * 解析好的参数列表，还没有函数体
+ */
+#[derive(Default)]
+struct ParameterList {
+    params: Vec<expr::Symbol>,
+    defaults: Vec<expr::Expr>, // 对应 params 的最后几个
+    rest: Option<expr::Symbol>,
+    return_ty: Option<expr::TypeAnnotation>,
+}
+
 #[derive(Debug)]
 pub enum FunctionKind {
     Function,
@@ -173,7 +297,9 @@ impl Parser {
     /**
      * declaration → classDecl
      *             | funDecl
+     *             | enumDecl
      *             | varDecl
+     *             | constDecl
      *             | statement ;
      */
     fn declaration(&mut self) -> Result<expr::Stmt, Error> {
@@ -181,6 +307,10 @@ impl Parser {
             return self.var_decl();
         }
 
+        if self.matches(scanner::TokenType::Const) || self.matches(scanner::TokenType::Let) {
+            return self.const_decl();
+        }
+
         if self.matches(scanner::TokenType::Fun) {
             return Ok(expr::Stmt::FunDecl(self.fun_decl(FunctionKind::Function)?));
         }
@@ -189,17 +319,27 @@ impl Parser {
             return self.class_decl();
         }
 
+        if self.matches(scanner::TokenType::Trait) {
+            return self.trait_decl();
+        }
+
+        if self.matches(scanner::TokenType::Enum) {
+            return self.enum_decl();
+        }
+
         self.statement()
     }
 
     /**
-     * varDecl → "var" IDENTIFIER ( "=" expression )? ";" ;
+     * varDecl → "var" IDENTIFIER ( ":" type )? ( "=" expression )? ";" ;
      */
     fn var_decl(&mut self) -> Result<expr::Stmt, Error> {
         let name_token = self
             .consume(scanner::TokenType::Identifier, "Expected variable name")?
             .clone();
 
+        let maybe_ty = self.maybe_type_annotation()?;
+
         let maybe_initializer = if self.matches(scanner::TokenType::Equal) {
             Some(self.expression()?)
         } else {
@@ -214,19 +354,53 @@ impl Parser {
                     name: String::from_utf8(name_token.lexeme).unwrap(),
                     line: name_token.line,
                     col: name_token.col,
+                    ty: maybe_ty,
                 },
                 maybe_initializer
             )
         )
     }
 
+    /**
+     * constDecl → ( "const" | "let" ) IDENTIFIER ( ":" type )? "=" expression ";" ;
+     */
+    fn const_decl(&mut self) -> Result<expr::Stmt, Error> {
+        let name_token = self
+            .consume(scanner::TokenType::Identifier, "Expected constant name")?
+            .clone();
+
+        let maybe_ty = self.maybe_type_annotation()?;
+
+        self.consume(scanner::TokenType::Equal, "Constants must be initialized")?;
+        let initializer = self.expression()?;
+
+        self.consume(scanner::TokenType::Semicolon, "Expected ; after constant declaration")?;
+
+        Ok(
+            expr::Stmt::ConstDecl(
+                expr::Symbol {
+                    name: String::from_utf8(name_token.lexeme).unwrap(),
+                    line: name_token.line,
+                    col: name_token.col,
+                    ty: maybe_ty,
+                },
+                initializer
+            )
+        )
+    }
+
     /**
      * statement → exprStmt
      *           | forStmt
+     *           | forInStmt
      *           | ifStmt
      *           | printStmt
      *           | returnStmt
      *           | whileStmt
+     *           | doWhileStmt
+     *           | breakStmt
+     *           | continueStmt
+     *           | yieldStmt
      *           | block ;
      */
     fn statement(&mut self) -> Result<expr::Stmt, Error> {
@@ -255,6 +429,22 @@ impl Parser {
             return self.return_statement();
         }
 
+        if self.matches(scanner::TokenType::Do) {
+            return self.do_while_statement();
+        }
+
+        if self.match_one_of(vec![scanner::TokenType::Break, scanner::TokenType::Continue]) {
+            return self.loop_control_statement();
+        }
+
+        if self.matches(scanner::TokenType::Yield) {
+            return self.yield_statement();
+        }
+
+        if self.matches(scanner::TokenType::Match) {
+            return self.match_statement();
+        }
+
         // expression statements
         self.expression_statement()
     }
@@ -289,7 +479,7 @@ impl Parser {
     }
 
     /**
-     * assignment → ( call "." )? IDENTIFIER "=" assignment
+     * assignment → ( call "." )? IDENTIFIER ( "=" | "+=" | "-=" | "*=" | "/=" ) assignment
      *            | logic_or;
      * 其实这里有点意思，equality < logic_and < logic_or < assignment
      */
@@ -310,7 +500,14 @@ impl Parser {
             }
 
             // 下表访问
-            if let expr::Expr::Subscript { value, slice, source_location } = expr {
+            // 切片不能赋值
+            if
+                let expr::Expr::Subscript {
+                    value,
+                    slice: expr::Slice::Index(slice),
+                    source_location,
+                } = expr
+            {
                 return Ok(expr::Expr::SetItem {
                     lhs: value,
                     slice,
@@ -326,6 +523,21 @@ impl Parser {
             });
         }
 
+        if
+            self.match_one_of(
+                vec![
+                    scanner::TokenType::PlusEqual,
+                    scanner::TokenType::MinusEqual,
+                    scanner::TokenType::StarEqual,
+                    scanner::TokenType::SlashEqual
+                ]
+            )
+        {
+            let operator_token = self.previous().clone();
+            let rhs = self.assignment()?;
+            return Parser::compound_assign(expr, &operator_token, rhs, false);
+        }
+
         Ok(expr)
     }
 
@@ -366,8 +578,84 @@ impl Parser {
         self.consume(scanner::TokenType::LeftParen, "Expected ( after while")?;
         let cond = self.expression()?;
         self.consume(scanner::TokenType::RightParen, "Expected ) after while condition")?;
-        let body = Box::new(self.statement()?);
-        Ok(expr::Stmt::While(cond, body))
+        let body = Box::new(self.loop_body()?);
+        Ok(expr::Stmt::While(cond, body, None))
+    }
+
+    /**
+     * doWhileStmt → "do" statement "while" "(" expression ")" ";" ;
+     */
+    fn do_while_statement(&mut self) -> Result<expr::Stmt, Error> {
+        let body = Box::new(self.loop_body()?);
+        self.consume(scanner::TokenType::While, "Expected while after do body")?;
+        self.consume(scanner::TokenType::LeftParen, "Expected ( after while")?;
+        let cond = self.expression()?;
+        self.consume(scanner::TokenType::RightParen, "Expected ) after while condition")?;
+        self.consume(scanner::TokenType::Semicolon, "Expected ; after do-while")?;
+        Ok(expr::Stmt::DoWhile(body, cond))
+    }
+
+    /**
+     * breakStmt → "break" ";" ;
+     * continueStmt → "continue" ";" ;
+     */
+    fn loop_control_statement(&mut self) -> Result<expr::Stmt, Error> {
+        let keyword_tok = self.previous().clone();
+        if self.loop_depth == 0 {
+            return Err(Error::LoopControlNotInLoop {
+                keyword: String::from_utf8(keyword_tok.lexeme).unwrap(),
+                line: keyword_tok.line,
+                col: keyword_tok.col,
+            });
+        }
+        self.consume(scanner::TokenType::Semicolon, "Expected ; after loop control")?;
+
+        let source_location = expr::SourceLocation {
+            line: keyword_tok.line,
+            col: keyword_tok.col,
+        };
+        if keyword_tok.ty == scanner::TokenType::Break {
+            Ok(expr::Stmt::Break(source_location))
+        } else {
+            Ok(expr::Stmt::Continue(source_location))
+        }
+    }
+
+    /**
+     * yieldStmt → "yield" expression ";" ;
+     */
+    fn yield_statement(&mut self) -> Result<expr::Stmt, Error> {
+        let yield_tok = self.previous().clone();
+        if !self.in_fundec {
+            return Err(Error::YieldNotInFun {
+                line: yield_tok.line,
+                col: yield_tok.col,
+            });
+        }
+        self.saw_yield = true;
+
+        let val = self.expression()?;
+        self.consume(scanner::TokenType::Semicolon, "Expected ; after yield value")?;
+
+        Ok(
+            expr::Stmt::Yield(
+                expr::SourceLocation {
+                    line: yield_tok.line,
+                    col: yield_tok.col,
+                },
+                val
+            )
+        )
+    }
+
+    /**
+     * 循环体里面才可以 break / continue
+     */
+    fn loop_body(&mut self) -> Result<expr::Stmt, Error> {
+        self.loop_depth += 1;
+        let body = self.statement();
+        self.loop_depth -= 1;
+        body
     }
 
     /* ---------- ---------- block ---------- ---------- */
@@ -393,11 +681,20 @@ impl Parser {
      * forStmt   → "for" "(" ( varDecl | exprStmt | ";" )
      *                expression? ";"
      *                expression? ")" statement ;
+     * forInStmt → "for" "(" "var" IDENTIFIER "in" expression ")" statement ;
      * 我们的 for 转换为 while 处理
      */
     fn for_statement(&mut self) -> Result<expr::Stmt, Error> {
         self.consume(scanner::TokenType::LeftParen, "Expected ( after for.")?;
 
+        if
+            self.check(scanner::TokenType::Var) &&
+            self.check_next(scanner::TokenType::Identifier) &&
+            self.check_nth(2, scanner::TokenType::In)
+        {
+            return self.for_in_statement();
+        }
+
         let mut maybe_initializer: Option<expr::Stmt> = None;
         if self.matches(scanner::TokenType::Semicolon) {
             // nothing to do
@@ -424,18 +721,14 @@ impl Parser {
 
         self.consume(scanner::TokenType::RightParen, "Expected ) after for clauses")?;
 
-        let mut body = self.statement()?;
-
-        if let Some(increment) = maybe_increment {
-            body = expr::Stmt::Block(vec![body, expr::Stmt::Expr(increment)]);
-            // 我们这里的处理是将：增量放到了 body 最后面
-        }
+        let mut body = self.loop_body()?;
 
         let condition = match maybe_condition {
             Some(cond) => cond,
             None => expr::Expr::Literal(expr::Literal::True),
         };
-        body = expr::Stmt::While(condition, Box::new(body)); // 我们是将 for 转换为 while 处理
+        // 我们是将 for 转换为 while 处理，增量单独放着，continue 以后也要执行
+        body = expr::Stmt::While(condition, Box::new(body), maybe_increment);
 
         if let Some(initializer) = maybe_initializer {
             body = expr::Stmt::Block(vec![initializer, body]);
@@ -445,6 +738,191 @@ impl Parser {
         Ok(body)
     }
 
+    fn for_in_statement(&mut self) -> Result<expr::Stmt, Error> {
+        self.consume(scanner::TokenType::Var, "Expected var in for-in loop")?;
+        let name_tok = self
+            .consume(scanner::TokenType::Identifier, "Expected loop variable name")?
+            .clone();
+        self.consume(scanner::TokenType::In, "Expected in after loop variable")?;
+        let iterable = self.expression()?;
+        self.consume(scanner::TokenType::RightParen, "Expected ) after for-in clause")?;
+        let body = self.loop_body()?;
+
+        Ok(
+            expr::Stmt::ForIn(
+                expr::Symbol {
+                    name: String::from_utf8(name_tok.lexeme).unwrap(),
+                    line: name_tok.line,
+                    col: name_tok.col,
+                    ty: None,
+                },
+                iterable,
+                Box::new(body)
+            )
+        )
+    }
+
+    /* ---------- ---------- match ---------- ---------- */
+
+    /**
+     * matchStmt → "match" "(" expression ")" "{" ( "case" pattern ( "if" expression )? "=>" statement )* "}" ;
+     */
+    fn match_statement(&mut self) -> Result<expr::Stmt, Error> {
+        self.consume(scanner::TokenType::LeftParen, "Expected ( after match")?;
+        let subject = self.expression()?;
+        self.consume(scanner::TokenType::RightParen, "Expected ) after match subject")?;
+        self.consume(scanner::TokenType::LeftBrace, "Expected { before match arms")?;
+
+        let mut arms = Vec::new();
+        while self.matches(scanner::TokenType::Case) {
+            let pattern = self.pattern()?;
+            let mut bindings = Vec::new();
+            Parser::check_bindings(&pattern, &mut bindings)?;
+
+            let guard = if self.matches(scanner::TokenType::If) {
+                Some(self.expression()?)
+            } else {
+                None
+            };
+            self.consume(scanner::TokenType::FatArrow, "Expected => after pattern")?;
+            let body = self.statement()?;
+            arms.push(expr::MatchArm { pattern, guard, body });
+        }
+        self.consume(scanner::TokenType::RightBrace, "Expected } after match arms")?;
+
+        Ok(expr::Stmt::Match(subject, arms))
+    }
+
+    /**
+     * pattern → "_" | literal | "-" NUMBER | IDENTIFIER
+     *         | "[" ( pattern ( "," pattern )* )? "]"
+     *         | IDENTIFIER "(" ( IDENTIFIER ( ":" pattern )? ( "," ... )* )? ")" ;
+     * Point(x, y: 0) 里面只写字段名的，等于把字段绑定到同名的变量上
+     */
+    fn pattern(&mut self) -> Result<expr::Pattern, Error> {
+        let tok = self.advance().clone();
+        let loc = expr::SourceLocation {
+            line: tok.line,
+            col: tok.col,
+        };
+        match tok.ty {
+            scanner::TokenType::Identifier if tok.lexeme == b"_" => Ok(expr::Pattern::Wildcard),
+            scanner::TokenType::Identifier => {
+                let sym = expr::Symbol {
+                    name: String::from_utf8(tok.lexeme).unwrap(),
+                    line: tok.line,
+                    col: tok.col,
+                    ty: None,
+                };
+                if !self.matches(scanner::TokenType::LeftParen) {
+                    return Ok(expr::Pattern::Binding(sym));
+                }
+                let mut fields = Vec::new();
+                if !self.check(scanner::TokenType::RightParen) {
+                    loop {
+                        let field_tok = self
+                            .consume(scanner::TokenType::Identifier, "Expected field name in pattern")?
+                            .clone();
+                        let field = expr::Symbol {
+                            name: String::from_utf8(field_tok.lexeme).unwrap(),
+                            line: field_tok.line,
+                            col: field_tok.col,
+                            ty: None,
+                        };
+                        let sub_pattern = if self.matches(scanner::TokenType::Colon) {
+                            self.pattern()?
+                        } else {
+                            expr::Pattern::Binding(field.clone())
+                        };
+                        fields.push((field, sub_pattern));
+                        if !self.matches(scanner::TokenType::Comma) {
+                            break;
+                        }
+                    }
+                }
+                self.consume(scanner::TokenType::RightParen, "Expected ) after field patterns")?;
+                Ok(expr::Pattern::Instance(sym, fields))
+            }
+            scanner::TokenType::LeftBracket => {
+                let mut elements = Vec::new();
+                if !self.check(scanner::TokenType::RightBracket) {
+                    loop {
+                        elements.push(self.pattern()?);
+                        if !self.matches(scanner::TokenType::Comma) {
+                            break;
+                        }
+                    }
+                }
+                self.consume(scanner::TokenType::RightBracket, "Expected ] after list pattern")?;
+                Ok(expr::Pattern::List(elements))
+            }
+            scanner::TokenType::Minus if self.check(scanner::TokenType::Number) => {
+                match self.advance().literal {
+                    Some(scanner::Literal::Number(n)) => {
+                        Ok(expr::Pattern::Literal(loc, expr::Literal::Number(-n)))
+                    }
+                    Some(scanner::Literal::Integer(n)) => {
+                        Ok(expr::Pattern::Literal(loc, expr::Literal::Integer(-n)))
+                    }
+                    _ => panic!("internal error in parser: when parsing number, found no literal"),
+                }
+            }
+            scanner::TokenType::Number =>
+                match tok.literal {
+                    Some(scanner::Literal::Number(n)) => {
+                        Ok(expr::Pattern::Literal(loc, expr::Literal::Number(n)))
+                    }
+                    Some(scanner::Literal::Integer(n)) => {
+                        Ok(expr::Pattern::Literal(loc, expr::Literal::Integer(n)))
+                    }
+                    _ => panic!("internal error in parser: when parsing number, found no literal"),
+                }
+            scanner::TokenType::String =>
+                match tok.literal {
+                    Some(scanner::Literal::Str(s)) => {
+                        Ok(expr::Pattern::Literal(loc, expr::Literal::String(s)))
+                    }
+                    _ => panic!("internal error in parser: when parsing string, found no literal"),
+                }
+            scanner::TokenType::True => Ok(expr::Pattern::Literal(loc, expr::Literal::True)),
+            scanner::TokenType::False => Ok(expr::Pattern::Literal(loc, expr::Literal::False)),
+            scanner::TokenType::Nil => Ok(expr::Pattern::Literal(loc, expr::Literal::Nil)),
+            _ => Err(Error::UnexpectedToken(tok)),
+        }
+    }
+
+    /**
+     * 同一个模式里面不能把两个值绑定到同一个名字上
+     */
+    fn check_bindings(pattern: &expr::Pattern, seen: &mut Vec<String>) -> Result<(), Error> {
+        match pattern {
+            expr::Pattern::Wildcard | expr::Pattern::Literal(_, _) => Ok(()),
+            expr::Pattern::Binding(sym) => {
+                if seen.contains(&sym.name) {
+                    return Err(Error::DuplicateBinding {
+                        name: sym.name.clone(),
+                        line: sym.line,
+                        col: sym.col,
+                    });
+                }
+                seen.push(sym.name.clone());
+                Ok(())
+            }
+            expr::Pattern::List(elements) => {
+                for element in elements {
+                    Parser::check_bindings(element, seen)?;
+                }
+                Ok(())
+            }
+            expr::Pattern::Instance(_, fields) => {
+                for (_, field_pattern) in fields {
+                    Parser::check_bindings(field_pattern, seen)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
     /* ---------- ---------- if ---------- ---------- */
 
     /**
@@ -485,16 +963,21 @@ impl Parser {
             None
         };
 
+        let source_location = expr::SourceLocation {
+            line: prev_tok.line,
+            col: prev_tok.col,
+        };
+
         if maybe_retval.is_some() {
             self.consume(scanner::TokenType::Semicolon, "Expected ; after return value")?;
+            if self.value_return.is_none() {
+                self.value_return = Some(source_location);
+            }
         }
 
         Ok(
             expr::Stmt::Return(
-                expr::SourceLocation {
-                    line: prev_tok.line,
-                    col: prev_tok.col,
-                },
+                source_location,
                 maybe_retval
             )
         )
@@ -530,10 +1013,10 @@ impl Parser {
     }
 
     /**
-     * comparison → addition ( ( ">" | ">=" | "<" | "<=" ) addition )* ;
+     * comparison → bit_or ( ( ">" | ">=" | "<" | "<=" ) bit_or )* ;
      */
     fn comparison(&mut self) -> Result<expr::Expr, Error> {
-        let mut expr = self.addition()?;
+        let mut expr = self.bit_or()?;
 
         while
             self.match_one_of(
@@ -541,12 +1024,13 @@ impl Parser {
                     scanner::TokenType::Greater,
                     scanner::TokenType::GreaterEqual,
                     scanner::TokenType::Less,
-                    scanner::TokenType::LessEqual
+                    scanner::TokenType::LessEqual,
+                    scanner::TokenType::InstanceOf
                 ]
             )
         {
             let operator_token = self.previous().clone();
-            let right = Box::new(self.addition()?);
+            let right = Box::new(self.bit_or()?);
             let binop_maybe = Parser::op_token_to_binop(&operator_token);
 
             match binop_maybe {
@@ -562,6 +1046,57 @@ impl Parser {
         Ok(expr)
     }
 
+    /**
+     * 位运算的优先级和 python 一样，比比较运算高：x & 1 == 0 就是 (x & 1) == 0
+     * bit_or → bit_xor ( "|" bit_xor )* ;
+     */
+    fn bit_or(&mut self) -> Result<expr::Expr, Error> {
+        self.left_assoc_binary(vec![scanner::TokenType::Pipe], Parser::bit_xor)
+    }
+
+    /**
+     * bit_xor → bit_and ( "^" bit_and )* ;
+     */
+    fn bit_xor(&mut self) -> Result<expr::Expr, Error> {
+        self.left_assoc_binary(vec![scanner::TokenType::Caret], Parser::bit_and)
+    }
+
+    /**
+     * bit_and → shift ( "&" shift )* ;
+     */
+    fn bit_and(&mut self) -> Result<expr::Expr, Error> {
+        self.left_assoc_binary(vec![scanner::TokenType::Ampersand], Parser::shift)
+    }
+
+    /**
+     * shift → addition ( ( "<<" | ">>" ) addition )* ;
+     */
+    fn shift(&mut self) -> Result<expr::Expr, Error> {
+        self.left_assoc_binary(
+            vec![scanner::TokenType::LessLess, scanner::TokenType::GreaterGreater],
+            Parser::addition
+        )
+    }
+
+    /**
+     * 左结合的二元操作符：operand ( op operand )*
+     */
+    fn left_assoc_binary(
+        &mut self,
+        types: Vec<scanner::TokenType>,
+        operand: fn(&mut Parser) -> Result<expr::Expr, Error>
+    ) -> Result<expr::Expr, Error> {
+        let mut expr = operand(self)?;
+
+        while self.match_one_of(types.clone()) {
+            let operator_token = self.previous().clone();
+            let right = Box::new(operand(self)?);
+            let binop = Parser::op_token_to_binop(&operator_token)?;
+            expr = expr::Expr::Binary(Box::new(expr), binop, right);
+        }
+        Ok(expr)
+    }
+
     /**
      * addition → multiplication ( ( "-" | "+" ) multiplication )* ;
      */
@@ -587,12 +1122,21 @@ impl Parser {
     }
 
     /**
-     * multiplication → unary ( ( "/" | "*" ) unary )* ;
+     * multiplication → unary ( ( "/" | "*" | "%" | "~/" ) unary )* ;
      */
     fn multiplication(&mut self) -> Result<expr::Expr, Error> {
         let mut expr = self.unary()?; // 左操作数
 
-        while self.match_one_of(vec![scanner::TokenType::Slash, scanner::TokenType::Star]) {
+        while
+            self.match_one_of(
+                vec![
+                    scanner::TokenType::Slash,
+                    scanner::TokenType::Star,
+                    scanner::TokenType::Percent,
+                    scanner::TokenType::TildeSlash
+                ]
+            )
+        {
             let operator_token = self.previous().clone(); // 操作符
             let right = Box::new(self.unary()?); // 右操作数
             let binop_maybe = Parser::op_token_to_binop(&operator_token);
@@ -611,86 +1155,53 @@ impl Parser {
     }
 
     /**
-     * 将 词素 转化成语义
+     * 将 词素 转化成语义，复合赋值的 += 之类的也转成对应的二元操作符
      */
     fn op_token_to_binop(tok: &scanner::Token) -> Result<expr::BinaryOp, Error> {
-        match tok.ty {
-            // ==
-            scanner::TokenType::EqualEqual =>
-                Ok(expr::BinaryOp {
-                    ty: expr::BinaryOpTy::EqualEqual,
-                    line: tok.line,
-                    col: tok.col,
-                }),
-            // !=
-            scanner::TokenType::BangEqual =>
-                Ok(expr::BinaryOp {
-                    ty: expr::BinaryOpTy::NotEqual,
-                    line: tok.line,
-                    col: tok.col,
-                }),
-            // <
-            scanner::TokenType::Less =>
-                Ok(expr::BinaryOp {
-                    ty: expr::BinaryOpTy::Less,
-                    line: tok.line,
-                    col: tok.col,
-                }),
-            scanner::TokenType::LessEqual =>
-                Ok(expr::BinaryOp {
-                    ty: expr::BinaryOpTy::LessEqual,
-                    line: tok.line,
-                    col: tok.col,
-                }),
-            scanner::TokenType::Greater =>
-                Ok(expr::BinaryOp {
-                    ty: expr::BinaryOpTy::Greater,
-                    line: tok.line,
-                    col: tok.col,
-                }),
-            scanner::TokenType::GreaterEqual =>
-                Ok(expr::BinaryOp {
-                    ty: expr::BinaryOpTy::GreaterEqual,
-                    line: tok.line,
-                    col: tok.col,
-                }),
-            scanner::TokenType::Plus =>
-                Ok(expr::BinaryOp {
-                    ty: expr::BinaryOpTy::Plus,
-                    line: tok.line,
-                    col: tok.col,
-                }),
-            scanner::TokenType::Minus =>
-                Ok(expr::BinaryOp {
-                    ty: expr::BinaryOpTy::Minus,
-                    line: tok.line,
-                    col: tok.col,
-                }),
-            scanner::TokenType::Star =>
-                Ok(expr::BinaryOp {
-                    ty: expr::BinaryOpTy::Star,
-                    line: tok.line,
-                    col: tok.col,
-                }),
-            scanner::TokenType::Slash =>
-                Ok(expr::BinaryOp {
-                    ty: expr::BinaryOpTy::Slash,
-                    line: tok.line,
-                    col: tok.col,
-                }),
-            _ =>
-                Err(Error::InvalidTokenInBinaryOp {
+        let ty = match tok.ty {
+            scanner::TokenType::EqualEqual => expr::BinaryOpTy::EqualEqual,
+            scanner::TokenType::BangEqual => expr::BinaryOpTy::NotEqual,
+            scanner::TokenType::Less => expr::BinaryOpTy::Less,
+            scanner::TokenType::LessEqual => expr::BinaryOpTy::LessEqual,
+            scanner::TokenType::InstanceOf => expr::BinaryOpTy::InstanceOf,
+            scanner::TokenType::Greater => expr::BinaryOpTy::Greater,
+            scanner::TokenType::GreaterEqual => expr::BinaryOpTy::GreaterEqual,
+            | scanner::TokenType::Plus
+            | scanner::TokenType::PlusEqual
+            | scanner::TokenType::PlusPlus => expr::BinaryOpTy::Plus,
+            | scanner::TokenType::Minus
+            | scanner::TokenType::MinusEqual
+            | scanner::TokenType::MinusMinus => expr::BinaryOpTy::Minus,
+            scanner::TokenType::Star | scanner::TokenType::StarEqual => expr::BinaryOpTy::Star,
+            scanner::TokenType::Slash | scanner::TokenType::SlashEqual => expr::BinaryOpTy::Slash,
+            scanner::TokenType::Percent => expr::BinaryOpTy::Percent,
+            scanner::TokenType::StarStar => expr::BinaryOpTy::StarStar,
+            scanner::TokenType::TildeSlash => expr::BinaryOpTy::TildeSlash,
+            scanner::TokenType::Ampersand => expr::BinaryOpTy::Ampersand,
+            scanner::TokenType::Pipe => expr::BinaryOpTy::Pipe,
+            scanner::TokenType::Caret => expr::BinaryOpTy::Caret,
+            scanner::TokenType::LessLess => expr::BinaryOpTy::LessLess,
+            scanner::TokenType::GreaterGreater => expr::BinaryOpTy::GreaterGreater,
+            _ => {
+                return Err(Error::InvalidTokenInBinaryOp {
                     token_type: tok.ty,
                     line: tok.line,
                     col: tok.col,
-                }),
-        }
+                });
+            }
+        };
+        Ok(expr::BinaryOp {
+            ty,
+            line: tok.line,
+            col: tok.col,
+        })
     }
 
     /**
-     * unary → ( "!" | "-" ) unary | call ;
+     * unary → ( "!" | "-" ) unary | power ;
      */
     fn unary(&mut self) -> Result<expr::Expr, Error> {
+
         if self.match_one_of(vec![scanner::TokenType::Bang, scanner::TokenType::Minus]) {
             let operator_token = self.previous().clone();
             let right = Box::new(self.unary()?); // 递归了，这里一条链的递归，相当于是循环
@@ -703,7 +1214,82 @@ impl Parser {
                 Err(err) => Err(err),
             };
         }
-        self.call() // 调用函数
+        self.power()
+    }
+
+    /**
+     * power → postfix ( "**" unary )? ;
+     * 右结合，并且比一元负号结合得更紧：-2 ** 2 == -4，2 ** -1 == 0.5
+     */
+    fn power(&mut self) -> Result<expr::Expr, Error> {
+        let expr = self.postfix()?;
+
+        if self.matches(scanner::TokenType::StarStar) {
+            let operator_token = self.previous().clone();
+            let right = Box::new(self.unary()?);
+            let binop = Parser::op_token_to_binop(&operator_token)?;
+            return Ok(expr::Expr::Binary(Box::new(expr), binop, right));
+        }
+        Ok(expr)
+    }
+
+    /**
+     * postfix → ( "++" | "--" ) call | call ( "++" | "--" )? ;
+     */
+    fn postfix(&mut self) -> Result<expr::Expr, Error> {
+        if self.match_one_of(vec![scanner::TokenType::PlusPlus, scanner::TokenType::MinusMinus]) {
+            // ++x 就是 x += 1
+            let operator_token = self.previous().clone();
+            let target = self.call()?;
+            return Parser::increment(target, &operator_token, false);
+        }
+
+        let expr = self.call()?; // 调用函数
+
+        if self.match_one_of(vec![scanner::TokenType::PlusPlus, scanner::TokenType::MinusMinus]) {
+            let operator_token = self.previous().clone();
+            return Parser::increment(expr, &operator_token, true);
+        }
+        Ok(expr)
+    }
+
+    /**
+     * ++ / -- 统一转成 CompoundAssign，加减的都是 1
+     */
+    fn increment(
+        target: expr::Expr,
+        operator_token: &scanner::Token,
+        postfix: bool
+    ) -> Result<expr::Expr, Error> {
+        let one = expr::Expr::Literal(expr::Literal::Integer(1));
+        Parser::compound_assign(target, operator_token, one, postfix)
+    }
+
+    /**
+     * 只有 变量、字段、下标 可以作为复合赋值的目标
+     */
+    fn compound_assign(
+        target: expr::Expr,
+        operator_token: &scanner::Token,
+        rhs: expr::Expr,
+        postfix: bool
+    ) -> Result<expr::Expr, Error> {
+        match target {
+            | expr::Expr::Variable(_)
+            | expr::Expr::Get(_, _)
+            | expr::Expr::Subscript { slice: expr::Slice::Index(_), .. } =>
+                Ok(expr::Expr::CompoundAssign {
+                    target: Box::new(target),
+                    op: Parser::op_token_to_binop(operator_token)?,
+                    rhs: Box::new(rhs),
+                    postfix,
+                }),
+            _ =>
+                Err(Error::InvalidAssignment {
+                    line: operator_token.line,
+                    col: operator_token.col,
+                }),
+        }
     }
 
     /**
@@ -749,35 +1335,58 @@ impl Parser {
             name: String::from_utf8(name_tok.lexeme).unwrap(),
             line: name_tok.line,
             col: name_tok.col,
+            ty: None,
         };
 
-        let (parameters, body) = self.params_and_body(kind)?;
+        let is_method = matches!(kind, FunctionKind::Method);
+        let expr::LambdaDecl { params, defaults, rest, return_ty, body, is_generator } =
+            self.params_and_body(kind)?;
+
+        if is_method && is_generator && fun_symbol.name == "init" {
+            return Err(Error::GeneratorInitializer {
+                line: fun_symbol.line,
+                col: fun_symbol.col,
+            });
+        }
 
         Ok(expr::FunDecl {
             name: fun_symbol,
-            params: parameters,
+            params,
+            defaults,
+            rest,
+            return_ty,
             body,
+            is_generator,
         })
     }
 
     /**
-     * parameters  → IDENTIFIER ( "," IDENTIFIER )* ;
-     * @return (parameters, body)
+     * parameters  → param ( "," param )* ( "," "..." IDENTIFIER ( ":" type )? )?
+     *             | "..." IDENTIFIER ( ":" type )? ;
+     * param       → IDENTIFIER ( ":" type )? ( "=" expression )? ;
+     * 参数列表后面还可以跟上返回值类型 ( ":" type )?
+     * @return 没有名字的函数 (parameters, return_ty, body)
      */
-    fn params_and_body(
-        &mut self,
-        kind: FunctionKind
-    ) -> Result<(Vec<expr::Symbol>, Vec<expr::Stmt>), Error> {
+    fn params_and_body(&mut self, kind: FunctionKind) -> Result<expr::LambdaDecl, Error> {
+        let parameters = self.parameter_list(kind)?;
+        self.function_body(parameters)
+    }
+
+    /**
+     * ( parameters? ) ( ":" type )?
+     * 有默认值的参数后面不能再有必填的参数，...rest 只能放在最后
+     */
+    fn parameter_list(&mut self, kind: FunctionKind) -> Result<ParameterList, Error> {
         self.consume(
             scanner::TokenType::LeftParen,
             format!("Expected ( after {:?} name", kind).as_ref()
         )?;
 
-        let mut parameters = Vec::new();
+        let mut parameters = ParameterList::default();
 
         if !self.check(scanner::TokenType::RightParen) {
             loop {
-                if parameters.len() >= 255 {
+                if parameters.params.len() >= 255 {
                     // 函数的参数太多了
                     let peek_tok = self.peek();
                     return Err(Error::MaxParamsExceeded {
@@ -787,31 +1396,128 @@ impl Parser {
                     });
                 }
 
+                let is_rest = self.matches(scanner::TokenType::DotDotDot);
                 let tok = self
                     .consume(scanner::TokenType::Identifier, "Expected parameter name")?
                     .clone();
-
-                parameters.push(expr::Symbol {
+                let maybe_ty = self.maybe_type_annotation()?;
+                let param = expr::Symbol {
                     name: String::from_utf8(tok.lexeme).unwrap(),
                     line: tok.line,
                     col: tok.col,
-                });
+                    ty: maybe_ty,
+                };
+
+                if is_rest {
+                    parameters.rest = Some(param);
+                    if !self.check(scanner::TokenType::RightParen) {
+                        return Err(Error::RestNotLast {
+                            line: tok.line,
+                            col: tok.col,
+                        });
+                    }
+                    break;
+                }
+
+                if self.matches(scanner::TokenType::Equal) {
+                    parameters.defaults.push(self.expression()?);
+                } else if !parameters.defaults.is_empty() {
+                    return Err(Error::RequiredAfterDefault {
+                        name: param.name,
+                        line: param.line,
+                        col: param.col,
+                    });
+                }
+                parameters.params.push(param);
 
                 if !self.matches(scanner::TokenType::Comma) {
                     break;
                 } // 如果最后面不是逗号，那么就退出
             }
         } // 否则就是没有参数
-        let parameters = parameters;
 
         self.consume(scanner::TokenType::RightParen, "Expected ) after parameter list")?;
+        parameters.return_ty = self.maybe_type_annotation()?;
+        Ok(parameters)
+    }
+
+    /**
+     * 函数体 block，参数列表已经解析好了（getter 没有参数列表）
+     */
+    fn function_body(&mut self, parameters: ParameterList) -> Result<expr::LambdaDecl, Error> {
         self.consume(scanner::TokenType::LeftBrace, "Expected { before function body")?;
         let saved_is_in_fundec = self.in_fundec; // 我们的 lox 是可以闭包定义的
+        let saved_loop_depth = self.loop_depth; // 函数体里面不能 break 外面的循环
+        let saved_saw_yield = std::mem::take(&mut self.saw_yield); // 里面的函数是不是生成器和外面没关系
+        let saved_value_return = self.value_return.take();
         self.in_fundec = true;
-        let body = self.block()?;
+        self.loop_depth = 0;
+        let body = self.block();
+        let is_generator = self.saw_yield;
+        let value_return = self.value_return;
         self.in_fundec = saved_is_in_fundec;
+        self.loop_depth = saved_loop_depth;
+        self.saw_yield = saved_saw_yield;
+        self.value_return = saved_value_return;
+        let body = body?;
+
+        if let (true, Some(loc)) = (is_generator, value_return) {
+            return Err(Error::ReturnValueInGenerator {
+                line: loc.line,
+                col: loc.col,
+            });
+        }
+
+        Ok(expr::LambdaDecl {
+            params: parameters.params,
+            defaults: parameters.defaults,
+            rest: parameters.rest,
+            return_ty: parameters.return_ty,
+            body,
+            is_generator,
+        })
+    }
+
+    /* ---------- ---------- type annotation ---------- ---------- */
+
+    /**
+     * 如果下一个 token 是 ":"，那么解析一个类型标注
+     */
+    fn maybe_type_annotation(&mut self) -> Result<Option<expr::TypeAnnotation>, Error> {
+        if self.matches(scanner::TokenType::Colon) {
+            Ok(Some(self.type_annotation()?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /**
+     * type → "nil" | "fun" | IDENTIFIER ;
+     * IDENTIFIER 可以是 number、string、bool、list、any，其他的都当作类名
+     */
+    fn type_annotation(&mut self) -> Result<expr::TypeAnnotation, Error> {
+        if self.matches(scanner::TokenType::Nil) {
+            return Ok(expr::TypeAnnotation::Nil);
+        }
+        if self.matches(scanner::TokenType::Fun) {
+            return Ok(expr::TypeAnnotation::Function);
+        }
 
-        Ok((parameters, body))
+        let tok = self.consume(scanner::TokenType::Identifier, "Expected type after ':'")?;
+        let name = String::from_utf8(tok.lexeme.clone()).unwrap();
+        let loc = expr::SourceLocation {
+            line: tok.line,
+            col: tok.col,
+        };
+
+        Ok(match name.as_str() {
+            "any" => expr::TypeAnnotation::Any,
+            "number" => expr::TypeAnnotation::Number,
+            "string" => expr::TypeAnnotation::String,
+            "bool" => expr::TypeAnnotation::Bool,
+            "list" => expr::TypeAnnotation::List,
+            _ => expr::TypeAnnotation::Class(name, loc),
+        })
     }
 
     /* ---------- ---------- function call ---------- ---------- */
@@ -835,17 +1541,18 @@ impl Parser {
                     name: String::from_utf8(name_tok.lexeme).unwrap(),
                     line: name_tok.line,
                     col: name_tok.col,
+                    ty: None,
                 }); // expr 现在是 Get(对象, 成员函数) 了
             } else if self.extensions.lists && self.matches(scanner::TokenType::LeftBracket) {
                 // 如果是 [ ，并且开启了 lists 拓展的话
-                let slice_expr = self.expression()?;
+                let slice = self.slice()?;
                 let token = self.consume(
                     scanner::TokenType::RightBracket,
                     "Expected ] after subscript"
                 )?;
                 expr = expr::Expr::Subscript {
                     value: Box::new(expr),
-                    slice: Box::new(slice_expr),
+                    slice,
                     source_location: expr::SourceLocation {
                         line: token.line,
                         col: token.col,
@@ -859,21 +1566,103 @@ impl Parser {
     }
 
     /**
-     * arguments → expression ( "," expression )* ;
+     * interpolation → ( INTERPOLATION expression )+ STRING ;
+     * 空的字符串片段就不放进去了
+     */
+    fn interpolation(&mut self) -> Result<expr::Expr, Error> {
+        let mut parts = Vec::new();
+        loop {
+            if let Some(scanner::Literal::Str(s)) = &self.previous().literal {
+                if !s.is_empty() {
+                    parts.push(expr::Expr::Literal(expr::Literal::String(s.clone())));
+                }
+            }
+            parts.push(self.expression()?);
+
+            if self.matches(scanner::TokenType::Interpolation) {
+                continue;
+            }
+            self.consume(scanner::TokenType::String, "Expected end of string interpolation.")?;
+            if let Some(scanner::Literal::Str(s)) = &self.previous().literal {
+                if !s.is_empty() {
+                    parts.push(expr::Expr::Literal(expr::Literal::String(s.clone())));
+                }
+            }
+            return Ok(expr::Expr::Interpolate(parts));
+        }
+    }
+
+    /**
+     * slice → expression | expression? ":" expression? ;
+     */
+    fn slice(&mut self) -> Result<expr::Slice, Error> {
+        let start = if self.check(scanner::TokenType::Colon) {
+            None
+        } else {
+            Some(Box::new(self.expression()?))
+        };
+
+        if !self.matches(scanner::TokenType::Colon) {
+            // 上面没有匹配到冒号的话 start 一定有值
+            return Ok(expr::Slice::Index(start.unwrap()));
+        }
+
+        let stop = if self.check(scanner::TokenType::RightBracket) {
+            None
+        } else {
+            Some(Box::new(self.expression()?))
+        };
+        Ok(expr::Slice::Range(start, stop))
+    }
+
+    /**
+     * arguments → expression ( "," expression )* ( "," IDENTIFIER ":" expression )*
+     *           | IDENTIFIER ":" expression ( "," IDENTIFIER ":" expression )* ;
+     * 命名参数只能放在位置参数后面
      */
     fn finish_call(&mut self, callee: expr::Expr) -> Result<expr::Expr, Error> {
         // 设置实参列表
         let mut arguments = Vec::new();
+        let mut named_arguments: Vec<(expr::Symbol, expr::Expr)> = Vec::new();
         if !self.check(scanner::TokenType::RightParen) {
             loop {
-                if arguments.len() >= 255 {
+                if arguments.len() + named_arguments.len() >= 255 {
                     let peek_tok = self.peek();
                     return Err(Error::TooManyArguments {
                         line: peek_tok.line,
                         col: peek_tok.col,
                     });
                 }
-                arguments.push(self.expression()?);
+                if
+                    self.check(scanner::TokenType::Identifier) &&
+                    self.check_next(scanner::TokenType::Colon)
+                {
+                    let name_tok = self.advance().clone();
+                    self.advance();
+                    let name = String::from_utf8(name_tok.lexeme).unwrap();
+                    if named_arguments.iter().any(|(sym, _)| sym.name == name) {
+                        return Err(Error::DuplicateNamedArgument {
+                            name,
+                            line: name_tok.line,
+                            col: name_tok.col,
+                        });
+                    }
+                    let sym = expr::Symbol {
+                        name,
+                        line: name_tok.line,
+                        col: name_tok.col,
+                        ty: None,
+                    };
+                    named_arguments.push((sym, self.expression()?));
+                } else if !named_arguments.is_empty() {
+                    let peek_tok = self.peek();
+                    return Err(Error::PositionalAfterNamed {
+                        line: peek_tok.line,
+                        col: peek_tok.col,
+                    });
+                } else {
+                    arguments.push(self.expression()?);
+                }
                 if !self.matches(scanner::TokenType::Comma) {
                     break;
                 }
@@ -889,7 +1678,8 @@ impl Parser {
                     line: token.line,
                     col: token.col,
                 },
-                arguments
+                arguments,
+                named_arguments
             )
         )
     }
@@ -930,6 +1720,7 @@ impl Parser {
                         name: String::from_utf8(method_tok.lexeme.clone()).unwrap(),
                         line: method_tok.line,
                         col: method_tok.col,
+                        ty: None,
                     }
                 )
             );
@@ -939,6 +1730,9 @@ impl Parser {
                 Some(scanner::Literal::Number(n)) => {
                     return Ok(expr::Expr::Literal(expr::Literal::Number(*n)));
                 }
+                Some(scanner::Literal::Integer(n)) => {
+                    return Ok(expr::Expr::Literal(expr::Literal::Integer(*n)));
+                }
                 Some(l) =>
                     panic!("internal error in parser: when parsing number, found literal {:?}", l),
                 None => panic!("internal error in parser: when parsing number, found no literal"),
@@ -954,6 +1748,9 @@ impl Parser {
                 None => panic!("internal error in parser: when parsing string, found no literal"),
             }
         }
+        if self.matches(scanner::TokenType::Interpolation) {
+            return self.interpolation();
+        }
         if self.matches(scanner::TokenType::This) {
             let prev = self.previous();
             return Ok(
@@ -971,6 +1768,7 @@ impl Parser {
                             name: s.clone(),
                             line: self.previous().line,
                             col: self.previous().col,
+                            ty: None,
                         })
                     );
                 }
@@ -1010,8 +1808,7 @@ impl Parser {
 
         /* 支持 (xxx, xxxx, xxxxx) { y; yy; yyy; } */
         if self.extensions.lambdas && self.matches(scanner::TokenType::Lambda) {
-            let (params, body) = self.params_and_body(FunctionKind::Lambda)?;
-            return Ok(expr::Expr::Lambda(expr::LambdaDecl { params, body }));
+            return Ok(expr::Expr::Lambda(self.params_and_body(FunctionKind::Lambda)?));
         }
 
         Err(Error::ExpectedExpression {
@@ -1024,7 +1821,8 @@ impl Parser {
     /* ---------- ---------- class declare ---------- ---------- */
 
     /**
-     * classDecl → "class" IDENTIFIER ( "<" IDENTIFIER )? "{" function* "}" ;
+     * classDecl → "class" IDENTIFIER ( "<" IDENTIFIER )? "{" ( field | function )* "}" ;
+     * field → IDENTIFIER ":" type ";" ;
      */
     fn class_decl(&mut self) -> Result<expr::Stmt, Error> {
         let name_tok = self.consume(scanner::TokenType::Identifier, "Expected class name")?.clone();
@@ -1033,6 +1831,7 @@ impl Parser {
             name: String::from_utf8(name_tok.lexeme).unwrap(),
             line: name_tok.line,
             col: name_tok.col,
+            ty: None,
         };
 
         /* 我们这里只允许 单继承 */
@@ -1045,17 +1844,80 @@ impl Parser {
                 name: String::from_utf8(superclass_tok.lexeme.clone()).unwrap(),
                 line: superclass_tok.line,
                 col: superclass_tok.col,
+                ty: None,
             })
         } else {
             None
         };
 
+        // 混入的 trait：with T1, T2
+        let mut traits = Vec::new();
+        if self.matches(scanner::TokenType::With) {
+            loop {
+                let trait_tok = self.consume(scanner::TokenType::Identifier, "Expected trait name.")?;
+                traits.push(expr::Symbol {
+                    name: String::from_utf8(trait_tok.lexeme.clone()).unwrap(),
+                    line: trait_tok.line,
+                    col: trait_tok.col,
+                    ty: None,
+                });
+                if !self.matches(scanner::TokenType::Comma) {
+                    break;
+                }
+            }
+        }
+
         self.consume(scanner::TokenType::LeftBrace, "Expected { after class name")?;
 
+        let mut fields = Vec::new();
         let mut methods = Vec::new();
+        let mut class_methods = Vec::new();
+        let mut getters = Vec::new();
+        let mut setters = Vec::new();
         while !self.check(scanner::TokenType::RightBrace) && !self.is_at_end() {
-            methods.push(self.fun_decl(FunctionKind::Method)?);
+            if
+                self.check(scanner::TokenType::Identifier) &&
+                self.check_next(scanner::TokenType::Colon)
+            {
+                // 字段声明 x: number;
+                let field_tok = self.advance().clone();
+                let ty = self.maybe_type_annotation()?;
+                self.consume(scanner::TokenType::Semicolon, "Expected ; after field declaration")?;
+                fields.push(expr::Symbol {
+                    name: String::from_utf8(field_tok.lexeme).unwrap(),
+                    line: field_tok.line,
+                    col: field_tok.col,
+                    ty,
+                });
+            } else if self.matches(scanner::TokenType::Class) {
+                // 静态方法 class name() {}
+                class_methods.push(self.fun_decl(FunctionKind::Method)?);
+            } else if
+                self.check(scanner::TokenType::Identifier) &&
+                self.peek().lexeme == b"set" &&
+                self.check_next(scanner::TokenType::Identifier)
+            {
+                // setter set name(v) {}，set 不是关键字
+                self.advance();
+                let setter = self.fun_decl(FunctionKind::Method)?;
+                if setter.params.len() != 1 || setter.rest.is_some() {
+                    return Err(Error::SetterArity {
+                        line: setter.name.line,
+                        col: setter.name.col,
+                    });
+                }
+                setters.push(setter);
+            } else if
+                self.check(scanner::TokenType::Identifier) &&
+                self.check_next(scanner::TokenType::LeftBrace)
+            {
+                // getter name {}
+                getters.push(self.getter_decl()?);
+            } else {
+                methods.push(self.fun_decl(FunctionKind::Method)?);
+            }
         }
+        let fields = fields;
         let methods = methods; // 锁定为 不可变
 
         self.consume(scanner::TokenType::RightBrace, "Expected } after class body")?;
@@ -1064,11 +1926,173 @@ impl Parser {
             expr::Stmt::ClassDecl(expr::ClassDecl {
                 name: class_symbol,
                 superclass: superclass_maybe,
+                traits,
+                fields,
                 methods,
+                class_methods,
+                getters,
+                setters,
             })
         )
     }
 
+    /**
+     * enumDecl → "enum" IDENTIFIER "{" ( variant ( "," variant )* ","? )? "}" ;
+     * variant  → IDENTIFIER ( "(" ( IDENTIFIER ( "," IDENTIFIER )* )? ")" )? ;
+     */
+    fn enum_decl(&mut self) -> Result<expr::Stmt, Error> {
+        let name_tok = self.consume(scanner::TokenType::Identifier, "Expected enum name")?.clone();
+        self.consume(scanner::TokenType::LeftBrace, "Expected { after enum name")?;
+
+        let mut variants: Vec<expr::EnumVariant> = Vec::new();
+        while !self.check(scanner::TokenType::RightBrace) && !self.is_at_end() {
+            let variant_tok = self
+                .consume(scanner::TokenType::Identifier, "Expected variant name")?
+                .clone();
+            let variant_symbol = expr::Symbol {
+                name: String::from_utf8(variant_tok.lexeme).unwrap(),
+                line: variant_tok.line,
+                col: variant_tok.col,
+                ty: None,
+            };
+            if variants.iter().any(|variant| variant.name.name == variant_symbol.name) {
+                return Err(Error::DuplicateVariant {
+                    name: variant_symbol.name,
+                    line: variant_symbol.line,
+                    col: variant_symbol.col,
+                });
+            }
+
+            let mut fields: Vec<expr::Symbol> = Vec::new();
+            if self.matches(scanner::TokenType::LeftParen) {
+                if !self.check(scanner::TokenType::RightParen) {
+                    loop {
+                        let field_tok = self
+                            .consume(scanner::TokenType::Identifier, "Expected field name")?
+                            .clone();
+                        let field = expr::Symbol {
+                            name: String::from_utf8(field_tok.lexeme).unwrap(),
+                            line: field_tok.line,
+                            col: field_tok.col,
+                            ty: None,
+                        };
+                        if fields.iter().any(|existing| existing.name == field.name) {
+                            return Err(Error::DuplicateField {
+                                name: field.name,
+                                line: field.line,
+                                col: field.col,
+                            });
+                        }
+                        fields.push(field);
+                        if !self.matches(scanner::TokenType::Comma) {
+                            break;
+                        }
+                    }
+                }
+                self.consume(scanner::TokenType::RightParen, "Expected ) after variant fields")?;
+            }
+
+            variants.push(expr::EnumVariant {
+                name: variant_symbol,
+                fields,
+            });
+            if !self.matches(scanner::TokenType::Comma) {
+                break;
+            }
+        }
+
+        self.consume(scanner::TokenType::RightBrace, "Expected } after enum body")?;
+
+        Ok(
+            expr::Stmt::EnumDecl(expr::EnumDecl {
+                name: expr::Symbol {
+                    name: String::from_utf8(name_tok.lexeme).unwrap(),
+                    line: name_tok.line,
+                    col: name_tok.col,
+                    ty: None,
+                },
+                variants,
+            })
+        )
+    }
+
+    /**
+     * traitDecl → "trait" IDENTIFIER "{" ( function | IDENTIFIER "(" parameters? ")" ";" )* "}" ;
+     * 只有签名没有函数体的方法，是要求混入它的类提供的
+     */
+    fn trait_decl(&mut self) -> Result<expr::Stmt, Error> {
+        let name_tok = self.consume(scanner::TokenType::Identifier, "Expected trait name")?.clone();
+        self.consume(scanner::TokenType::LeftBrace, "Expected { after trait name")?;
+
+        let mut methods = Vec::new();
+        let mut required = Vec::new();
+        while !self.check(scanner::TokenType::RightBrace) && !self.is_at_end() {
+            let method_tok = self
+                .consume(scanner::TokenType::Identifier, "Expected method name")?
+                .clone();
+            let method_symbol = expr::Symbol {
+                name: String::from_utf8(method_tok.lexeme).unwrap(),
+                line: method_tok.line,
+                col: method_tok.col,
+                ty: None,
+            };
+            let parameters = self.parameter_list(FunctionKind::Method)?;
+            if self.matches(scanner::TokenType::Semicolon) {
+                required.push(method_symbol);
+                continue;
+            }
+            let expr::LambdaDecl { params, defaults, rest, return_ty, body, is_generator } =
+                self.function_body(parameters)?;
+            methods.push(expr::FunDecl {
+                name: method_symbol,
+                params,
+                defaults,
+                rest,
+                return_ty,
+                body,
+                is_generator,
+            });
+        }
+
+        self.consume(scanner::TokenType::RightBrace, "Expected } after trait body")?;
+
+        Ok(
+            expr::Stmt::TraitDecl(expr::TraitDecl {
+                name: expr::Symbol {
+                    name: String::from_utf8(name_tok.lexeme).unwrap(),
+                    line: name_tok.line,
+                    col: name_tok.col,
+                    ty: None,
+                },
+                methods,
+                required,
+            })
+        )
+    }
+
+    /**
+     * getter → IDENTIFIER block ;
+     */
+    fn getter_decl(&mut self) -> Result<expr::FunDecl, Error> {
+        let name_tok = self.advance().clone();
+        let expr::LambdaDecl { params, defaults, rest, return_ty, body, is_generator } =
+            self.function_body(ParameterList::default())?;
+        Ok(expr::FunDecl {
+            name: expr::Symbol {
+                name: String::from_utf8(name_tok.lexeme).unwrap(),
+                line: name_tok.line,
+                col: name_tok.col,
+                ty: None,
+            },
+            params,
+            defaults,
+            rest,
+            return_ty,
+            body,
+            is_generator,
+        })
+    }
+
     /* ---------- ---------- 下面就是 辅助函数了 ---------- ---------- */
 
     /**
@@ -1115,6 +2139,23 @@ impl Parser {
         self.peek().ty == ty
     }
 
+    /**
+     * 看一下 下一个 token 之后的那个 token
+     */
+    fn check_next(&self, ty: scanner::TokenType) -> bool {
+        self.check_nth(1, ty)
+    }
+
+    /**
+     * 往后看第 n 个 token
+     */
+    fn check_nth(&self, n: usize, ty: scanner::TokenType) -> bool {
+        match self.tokens.get(self.current + n) {
+            Some(tok) => tok.ty == ty,
+            None => false,
+        }
+    }
+
     fn advance(&mut self) -> &scanner::Token {
         if !self.is_at_end() {
             self.current += 1;
@@ -0,0 +1,163 @@
+#[cfg(test)]
+mod tests {
+    use crate::expr;
+    use crate::extensions;
+    use crate::parser;
+    use crate::scanner;
+
+    /**
+     * 把表达式打印成 s-expression，括号的位置就是 parser 决定的结合方式
+     */
+    fn sexp(e: &expr::Expr) -> String {
+        match e {
+            expr::Expr::Literal(expr::Literal::Number(n)) => format!("{}", n),
+            expr::Expr::Literal(expr::Literal::Integer(n)) => format!("{}", n),
+            expr::Expr::Variable(sym) => sym.name.clone(),
+            expr::Expr::Grouping(inner) => sexp(inner),
+            expr::Expr::Unary(op, operand) => {
+                let op_str = match op.ty {
+                    expr::UnaryOpTy::Minus => "-",
+                    expr::UnaryOpTy::Bang => "!",
+                };
+                format!("({} {})", op_str, sexp(operand))
+            }
+            expr::Expr::Binary(lhs, op, rhs) =>
+                format!("({} {} {})", binop_str(op.ty), sexp(lhs), sexp(rhs)),
+            expr::Expr::Logical(lhs, op, rhs) => {
+                let op_str = match op {
+                    expr::LogicalOp::And => "and",
+                    expr::LogicalOp::Or => "or",
+                };
+                format!("({} {} {})", op_str, sexp(lhs), sexp(rhs))
+            }
+            expr::Expr::Assign(sym, rhs) => format!("(= {} {})", sym.name, sexp(rhs)),
+            expr::Expr::Get(lhs, attr) => format!("(. {} {})", sexp(lhs), attr.name),
+            expr::Expr::CompoundAssign { target, op, rhs, postfix } => {
+                if *postfix {
+                    format!("(post{} {})", binop_str(op.ty), sexp(target))
+                } else {
+                    format!("({}= {} {})", binop_str(op.ty), sexp(target), sexp(rhs))
+                }
+            }
+            _ => panic!("sexp doesn't handle {:?}", e),
+        }
+    }
+
+    fn binop_str(ty: expr::BinaryOpTy) -> &'static str {
+        match ty {
+            expr::BinaryOpTy::EqualEqual => "==",
+            expr::BinaryOpTy::NotEqual => "!=",
+            expr::BinaryOpTy::Less => "<",
+            expr::BinaryOpTy::LessEqual => "<=",
+            expr::BinaryOpTy::Greater => ">",
+            expr::BinaryOpTy::GreaterEqual => ">=",
+            expr::BinaryOpTy::InstanceOf => "instanceof",
+            expr::BinaryOpTy::Plus => "+",
+            expr::BinaryOpTy::Minus => "-",
+            expr::BinaryOpTy::Star => "*",
+            expr::BinaryOpTy::Slash => "/",
+            expr::BinaryOpTy::Percent => "%",
+            expr::BinaryOpTy::StarStar => "**",
+            expr::BinaryOpTy::TildeSlash => "~/",
+            expr::BinaryOpTy::Ampersand => "&",
+            expr::BinaryOpTy::Pipe => "|",
+            expr::BinaryOpTy::Caret => "^",
+            expr::BinaryOpTy::LessLess => "<<",
+            expr::BinaryOpTy::GreaterGreater => ">>",
+        }
+    }
+
+    fn parse_expr(code: &str) -> Result<String, String> {
+        let tokens = scanner::scan_tokens(format!("{};", code)).unwrap();
+        match parser::parse(extensions::Extensions::default(), tokens) {
+            Ok(stmts) =>
+                match &stmts[..] {
+                    [expr::Stmt::Expr(e)] => Ok(sexp(e)),
+                    _ => Err(format!("expected a single expression statement, got {:?}", stmts)),
+                }
+            Err(err) => Err(format!("{:?}", err)),
+        }
+    }
+
+    fn check_parse(code: &str, expected: &str) {
+        match parse_expr(code) {
+            Ok(res) => assert_eq!(res, expected),
+            Err(err) => panic!("{}", err),
+        }
+    }
+
+    /*
+     * 优先级从低到高：
+     *   = += -= *= /=   （右结合）
+     *   or
+     *   and
+     *   == !=
+     *   < <= > >=
+     *   |
+     *   ^
+     *   &
+     *   << >>
+     *   + -
+     *   * / % ~/
+     *   ! -             （一元）
+     *   **              （右结合，比一元负号结合得更紧）
+     *   ++ -- . ()      （前缀 / 后缀 ++ --，调用，字段）
+     */
+
+    #[test]
+    fn test_factor_binds_tighter_than_term() {
+        check_parse("1 + 2 * 3 - 4 % 5", "(- (+ 1 (* 2 3)) (% 4 5))");
+        check_parse("7 ~/ 2 * 3", "(* (~/ 7 2) 3)");
+    }
+
+    #[test]
+    fn test_power_is_right_associative() {
+        check_parse("2 ** 3 ** 2", "(** 2 (** 3 2))");
+        check_parse("2 * 3 ** 2", "(* 2 (** 3 2))");
+    }
+
+    #[test]
+    fn test_power_binds_tighter_than_unary_minus() {
+        check_parse("-2 ** 2", "(- (** 2 2))");
+        check_parse("2 ** -1", "(** 2 (- 1))");
+    }
+
+    #[test]
+    fn test_shift_is_below_term() {
+        check_parse("1 + 1 << 2 - 1", "(<< (+ 1 1) (- 2 1))");
+        check_parse("a >> 1 << 2", "(<< (>> a 1) 2)");
+    }
+
+    #[test]
+    fn test_bitwise_ordering() {
+        check_parse("a | b ^ c & d", "(| a (^ b (& c d)))");
+        check_parse("a & b << 1", "(& a (<< b 1))");
+    }
+
+    #[test]
+    fn test_bitwise_binds_tighter_than_comparison() {
+        check_parse("x & 1 == 0", "(== (& x 1) 0)");
+        check_parse("a | b < c", "(< (| a b) c)");
+        check_parse("a and b | c", "(and a (| b c))");
+    }
+
+    #[test]
+    fn test_compound_assignment_is_right_associative() {
+        check_parse("a += b -= 1", "(+= a (-= b 1))");
+        check_parse("a.b *= 2 + 3", "(*= (. a b) (+ 2 3))");
+        check_parse("a /= b or c", "(/= a (or b c))");
+    }
+
+    #[test]
+    fn test_increment_decrement() {
+        check_parse("x++ + ++y", "(+ (post+ x) (+= y 1))");
+        check_parse("-x--", "(- (post- x))");
+        check_parse("++a.b ** 2", "(** (+= (. a b) 1) 2)");
+    }
+
+    #[test]
+    fn test_invalid_compound_assignment_target() {
+        assert!(parse_expr("a + b += 1").unwrap_err().starts_with("invalid assignment target"));
+        assert!(parse_expr("++(a)").unwrap_err().starts_with("invalid assignment target"));
+    }
+}
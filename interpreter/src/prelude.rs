@@ -0,0 +1,28 @@
+/// 用 lox 自己写的内置函数，两个解释器启动的时候都会先执行一遍
+/// 惰性的 range / map / filter 都是生成器，内置函数里面就不需要重新进入解释器的主循环
+pub static SOURCE: &str =
+    "
+fun range(start, stop) {
+  for (var i = start; i < stop; i = i + 1) yield i;
+}
+
+fun lazyMap(f, iterable) {
+  for (var x in iterable) yield f(x);
+}
+
+fun lazyFilter(f, iterable) {
+  for (var x in iterable) if (f(x)) yield x;
+}
+
+// json.parse 得到的对象都是这个类的实例，字段就是对象的键
+class JsonObject {}
+
+// json.parse / json.stringify 是内置函数，解释器启动的时候放到字段上
+class Json {}
+var json = Json();
+";
+
+/**
+ * 这些名字放到全局变量里，剩下的只给内置函数用
+ */
+pub static EXPORTS: &[&str] = &["range", "json"];
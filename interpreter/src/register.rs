@@ -0,0 +1,541 @@
+//! 寄存器后端：把编译器生成的栈式指令翻译成寄存器指令
+//!
+//! 一个调用帧的寄存器就是它在解释器栈上的那一段：r0 是 0 号槽位，后面是参数、局部变量，
+//! 再后面是表达式求值用的临时值。栈式指令执行到某一处的时候栈深度是固定的，
+//! 深度为 d 的栈顶就是寄存器 r(d-1)，所以两种指令可以混着在同一个栈上跑。
+//!
+//! 翻译的时候模拟一遍操作数栈：常量和局部变量先不搬到栈顶，等到被用的时候直接当操作数，
+//! `n - 1` 这样的表达式就只要一条指令。调用、类、生成器这些没有寄存器形式的指令，
+//! 先把操作数都写回它们在栈上的位置，再原样交给栈式解释器执行。
+
+use crate::bytecode;
+
+use std::collections::HashSet;
+use std::fmt;
+
+/**
+ * 指令的操作数：寄存器或者常量
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Arg {
+    Reg(usize),
+    Const(usize), // 常量池的下标
+    Nil,
+    True,
+    False,
+}
+
+impl fmt::Display for Arg {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Arg::Reg(reg) => write!(f, "r{}", reg),
+            Arg::Const(idx) => write!(f, "k{}", idx),
+            Arg::Nil => write!(f, "nil"),
+            Arg::True => write!(f, "true"),
+            Arg::False => write!(f, "false"),
+        }
+    }
+}
+
+/**
+ * 寄存器指令，写结果的寄存器都放在第一个
+ */
+#[derive(Debug, Clone)]
+pub enum RegOp {
+    Move(usize, Arg),
+    GetGlobal(usize, /*变量名*/ usize),
+    SetGlobal(/*变量名*/ usize, Arg),
+    GetUpval(usize, /*upvalue idx*/ usize),
+    SetUpval(/*upvalue idx*/ usize, Arg),
+    Unary(bytecode::Op, usize, Arg), // Negate 和 Not
+    Binary(bytecode::Op, usize, Arg, Arg), // 算术、位运算和比较
+    Print(Arg),
+    // 跳转的目标都是指令的绝对位置
+    Jump(usize),
+    JumpIfFalse(usize, usize),
+    JumpIfNil(usize, usize),
+    // 从这个寄存器开始依次是迭代器、下标、元素。列表直接取下一个元素跳进循环体，取完了跳到出口；
+    // 其他迭代器调用 next()，返回值交给下一条 JumpIfNil
+    ForIter(usize, /*循环体*/ usize, /*出口*/ usize),
+    Return(Arg, /*栈深度*/ usize),
+    Stack(bytecode::Op, /*执行之前的栈深度*/ usize), // 交给栈式解释器执行
+}
+
+impl fmt::Display for RegOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RegOp::Move(dst, arg) => write!(f, "MOVE r{} {}", dst, arg),
+            RegOp::GetGlobal(dst, idx) => write!(f, "GET_GLOBAL r{} k{}", dst, idx),
+            RegOp::SetGlobal(idx, arg) => write!(f, "SET_GLOBAL k{} {}", idx, arg),
+            RegOp::GetUpval(dst, idx) => write!(f, "GET_UPVAL r{} u{}", dst, idx),
+            RegOp::SetUpval(idx, arg) => write!(f, "SET_UPVAL u{} {}", idx, arg),
+            RegOp::Unary(op, dst, arg) => {
+                write!(f, "{} r{} {}", format!("{:?}", op).to_uppercase(), dst, arg)
+            }
+            RegOp::Binary(op, dst, left, right) => {
+                write!(f, "{} r{} {} {}", format!("{:?}", op).to_uppercase(), dst, left, right)
+            }
+            RegOp::Print(arg) => write!(f, "PRINT {}", arg),
+            RegOp::Jump(target) => write!(f, "JUMP {}", target),
+            RegOp::JumpIfFalse(reg, target) => write!(f, "JUMP_IF_FALSE r{} {}", reg, target),
+            RegOp::JumpIfNil(reg, target) => write!(f, "JUMP_IF_NIL r{} {}", reg, target),
+            RegOp::ForIter(reg, body, exit) => write!(f, "FOR_ITER r{} {} {}", reg, body, exit),
+            RegOp::Return(arg, _) => write!(f, "RETURN {}", arg),
+            RegOp::Stack(op, depth) => write!(f, "STACK {:?} (depth={})", op, depth),
+        }
+    }
+}
+
+/**
+ * 一个函数翻译出来的寄存器指令，num_registers 是调用帧要占多少个栈槽位
+ */
+#[derive(Debug, Default)]
+pub struct Code {
+    pub code: Vec<(RegOp, bytecode::Lineno)>,
+    pub num_registers: usize,
+}
+
+/**
+ * 反汇编，格式和栈式指令的一样
+ */
+pub fn disassemble(code: &Code) -> Vec<String> {
+    code.code
+        .iter()
+        .enumerate()
+        .map(|(idx, (op, lineno))| {
+            format!("{0: <04}   {1: <50} line {2: <50}", idx, op.to_string(), lineno.value)
+        })
+        .collect()
+}
+
+/* ---------- ---------- 栈深度 ---------- ---------- */
+
+/**
+ * 指令执行完以后栈深度的变化，跳转类的指令在 successors 里面单独处理
+ */
+fn stack_effect(op: &bytecode::Op) -> isize {
+    match op {
+        bytecode::Op::Constant(_)
+        | bytecode::Op::Closure(_, _)
+        | bytecode::Op::Nil
+        | bytecode::Op::True
+        | bytecode::Op::False
+        | bytecode::Op::Dup(_)
+        | bytecode::Op::GetGlobal(_)
+        | bytecode::Op::GetLocal(_)
+        | bytecode::Op::GetUpval(_)
+        | bytecode::Op::Class(_)
+        | bytecode::Op::Enum(_)
+        | bytecode::Op::Trait(_) => 1,
+        bytecode::Op::Return
+        | bytecode::Op::Negate
+        | bytecode::Op::Not
+        | bytecode::Op::Print
+        | bytecode::Op::Rot(_)
+        | bytecode::Op::SetGlobal(_)
+        | bytecode::Op::SetLocal(_)
+        | bytecode::Op::SetUpval(_)
+        | bytecode::Op::JumpIfFalse(_)
+        | bytecode::Op::Jump(_)
+        | bytecode::Op::Loop(_)
+        | bytecode::Op::GetIter
+        | bytecode::Op::ForIter(_)
+        | bytecode::Op::JumpIfNil(_)
+        | bytecode::Op::GetProperty(_, _)
+        | bytecode::Op::EnumVariant(_, _)
+        | bytecode::Op::RequireMethod(_)
+        | bytecode::Op::MatchList(_)
+        | bytecode::Op::HasProperty(_)
+        | bytecode::Op::YieldFiber
+        | bytecode::Op::Await => 0,
+        bytecode::Op::Add
+        | bytecode::Op::Subtract
+        | bytecode::Op::Multiply
+        | bytecode::Op::Divide
+        | bytecode::Op::Modulo
+        | bytecode::Op::Power
+        | bytecode::Op::IntDivide
+        | bytecode::Op::BitAnd
+        | bytecode::Op::BitOr
+        | bytecode::Op::BitXor
+        | bytecode::Op::ShiftLeft
+        | bytecode::Op::ShiftRight
+        | bytecode::Op::Equal
+        | bytecode::Op::Greater
+        | bytecode::Op::Less
+        | bytecode::Op::InstanceOf
+        | bytecode::Op::Pop
+        | bytecode::Op::DefineGlobal(_)
+        | bytecode::Op::DefineConstGlobal(_)
+        | bytecode::Op::CloseUpvalue
+        | bytecode::Op::SetProperty(_, _)
+        | bytecode::Op::Method(_)
+        | bytecode::Op::ClassMethod(_)
+        | bytecode::Op::Getter(_)
+        | bytecode::Op::Setter(_)
+        | bytecode::Op::Inherit
+        | bytecode::Op::TraitMethod(_)
+        | bytecode::Op::GetSuper(_)
+        | bytecode::Op::Subscr
+        | bytecode::Op::Yield => -1,
+        bytecode::Op::Slice | bytecode::Op::SetItem => -2,
+        // 被调用的值和参数换成返回值
+        bytecode::Op::Call(arg_count)
+        | bytecode::Op::CallNamed(arg_count, _)
+        | bytecode::Op::TailCall(arg_count, _)
+        | bytecode::Op::Invoke(_, arg_count, _)
+        | bytecode::Op::InvokeNamed(_, arg_count, _, _)
+        | bytecode::Op::Mixin(arg_count) => -isize::from(*arg_count),
+        // 被调用的值和参数都搬到新的 fiber 上
+        bytecode::Op::Spawn(arg_count, _) | bytecode::Op::SpawnInvoke(_, arg_count, _) => {
+            -isize::from(*arg_count) - 1
+        }
+        // 另外还要弹出超类
+        bytecode::Op::SuperInvoke(_, arg_count) | bytecode::Op::SuperInvokeNamed(_, arg_count, _) => {
+            -isize::from(*arg_count) - 1
+        }
+        bytecode::Op::BuildList(size) | bytecode::Op::Interpolate(size) => 1 - (*size as isize),
+    }
+}
+
+/**
+ * 指令执行完以后可能到的位置，以及到那里的时候的栈深度
+ */
+fn successors(ip: usize, op: &bytecode::Op, depth: usize) -> Vec<(usize, usize)> {
+    match op {
+        bytecode::Op::Return => vec![],
+        bytecode::Op::Jump(offset) => vec![(ip + 1 + offset, depth)],
+        bytecode::Op::Loop(offset) => vec![(ip + 1 - offset, depth)],
+        bytecode::Op::JumpIfFalse(offset) => vec![(ip + 1, depth), (ip + 1 + offset, depth)],
+        bytecode::Op::JumpIfNil(offset) => vec![(ip + 1, depth), (ip + 1 + offset, depth - 1)],
+        // 列表跳过后面的 JumpIfNil 直接进循环体
+        bytecode::Op::ForIter(offset) => {
+            vec![(ip + 1, depth + 1), (ip + 2, depth + 1), (ip + 1 + offset, depth)]
+        }
+        _ => vec![(ip + 1, ((depth as isize) + stack_effect(op)) as usize)],
+    }
+}
+
+/**
+ * 每条指令执行之前的栈深度（包括 0 号槽位），走不到的指令是 None
+ */
+fn stack_depths(func: &bytecode::Function) -> Vec<Option<usize>> {
+    let code = &func.chunk.code;
+    // 参数的槽位和 bind_args 摆的一样：可选参数后面跟一个是否省略的 bool，最后是 rest 列表
+    let entry_depth =
+        1 +
+        usize::from(func.arity) +
+        2 * usize::from(func.optional_args) +
+        usize::from(func.has_rest);
+
+    let mut depths = vec![None; code.len()];
+    let mut worklist = vec![(0, entry_depth)];
+    while let Some((ip, depth)) = worklist.pop() {
+        if ip >= code.len() {
+            continue;
+        }
+        match depths[ip] {
+            Some(known) => {
+                debug_assert_eq!(known, depth, "inconsistent stack depth at {} in {}", ip, func.name);
+            }
+            None => {
+                depths[ip] = Some(depth);
+                worklist.extend(successors(ip, &code[ip].0, depth));
+            }
+        }
+    }
+    depths
+}
+
+/* ---------- ---------- 翻译 ---------- ---------- */
+
+/**
+ * 模拟的操作数栈上的一项：已经在自己的寄存器里了，或者还没搬过去，用的时候直接读 Arg
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Operand {
+    Live,
+    Deferred(Arg),
+}
+
+struct Translator {
+    code: Vec<(RegOp, bytecode::Lineno)>,
+    operands: Vec<Operand>,
+    captured: HashSet<usize>, // 被闭包捕获的局部变量
+    block_start: usize, // 最近一个跳转目标在 code 里的位置
+    lineno: bytecode::Lineno,
+}
+
+/**
+ * 把一个函数的栈式指令翻译成寄存器指令
+ */
+pub fn translate(func: &bytecode::Function) -> Code {
+    let chunk_code = &func.chunk.code;
+    let depths = stack_depths(func);
+
+    let mut labels = HashSet::new();
+    for (ip, (op, _)) in chunk_code.iter().enumerate() {
+        let targets = match op {
+            bytecode::Op::Jump(offset)
+            | bytecode::Op::JumpIfFalse(offset)
+            | bytecode::Op::JumpIfNil(offset) => vec![ip + 1 + offset],
+            bytecode::Op::Loop(offset) => vec![ip + 1 - offset],
+            bytecode::Op::ForIter(offset) => vec![ip + 2, ip + 1 + offset],
+            _ => vec![],
+        };
+        labels.extend(targets);
+    }
+
+    // 被捕获的局部变量可能被闭包改掉，读的时候要马上拷一份
+    let captured = chunk_code
+        .iter()
+        .flat_map(|(op, _)| {
+            match op {
+                bytecode::Op::Closure(_, upvals) => upvals.clone(),
+                _ => vec![],
+            }
+        })
+        .filter_map(|upval| {
+            match upval {
+                bytecode::UpvalueLoc::Local(idx) => Some(idx),
+                bytecode::UpvalueLoc::Upvalue(_) => None,
+            }
+        })
+        .collect();
+
+    let mut translator = Translator {
+        code: Vec::new(),
+        operands: Vec::new(),
+        captured,
+        block_start: 0,
+        lineno: bytecode::Lineno(0),
+    };
+    // 栈式指令的位置对应到寄存器指令的位置，跳转目标最后统一换过来
+    let mut positions = vec![0; chunk_code.len() + 1];
+    let mut falls_through = false;
+    for (ip, (op, lineno)) in chunk_code.iter().enumerate() {
+        let Some(depth) = depths[ip] else {
+            falls_through = false;
+            continue;
+        };
+        translator.lineno = *lineno;
+        if !falls_through {
+            translator.operands = vec![Operand::Live; depth];
+        }
+        if labels.contains(&ip) {
+            translator.flush();
+            translator.block_start = translator.code.len();
+        }
+        positions[ip] = translator.code.len();
+        falls_through = translator.translate_op(ip, op, depth);
+    }
+
+    for (op, _) in translator.code.iter_mut() {
+        match op {
+            RegOp::Jump(target)
+            | RegOp::JumpIfFalse(_, target)
+            | RegOp::JumpIfNil(_, target) => {
+                *target = positions[*target];
+            }
+            RegOp::ForIter(_, body, exit) => {
+                *body = positions[*body];
+                *exit = positions[*exit];
+            }
+            _ => {}
+        }
+    }
+
+    Code {
+        code: translator.code,
+        num_registers: depths.iter().flatten().copied().max().unwrap_or(0),
+    }
+}
+
+impl Translator {
+    fn emit(&mut self, op: RegOp) {
+        self.code.push((op, self.lineno));
+    }
+
+    fn arg(&self, pos: usize) -> Arg {
+        match self.operands[pos] {
+            Operand::Live => Arg::Reg(pos),
+            Operand::Deferred(arg) => arg,
+        }
+    }
+
+    fn top(&self) -> Arg {
+        self.arg(self.operands.len() - 1)
+    }
+
+    fn pop(&mut self) -> Arg {
+        let arg = self.top();
+        self.operands.pop();
+        arg
+    }
+
+    /**
+     * 还没搬到自己寄存器里的值搬过去
+     */
+    fn materialize(&mut self, pos: usize) {
+        if let Operand::Deferred(arg) = self.operands[pos] {
+            self.emit(RegOp::Move(pos, arg));
+            self.operands[pos] = Operand::Live;
+        }
+    }
+
+    /**
+     * 全部搬好，栈上的值和栈式解释器执行到这里的时候一模一样
+     */
+    fn flush(&mut self) {
+        for pos in 0..self.operands.len() {
+            self.materialize(pos);
+        }
+    }
+
+    /**
+     * 翻译一条指令，返回执行完以后会不会接着执行下一条
+     */
+    fn translate_op(&mut self, ip: usize, op: &bytecode::Op, depth: usize) -> bool {
+        match op {
+            bytecode::Op::Constant(idx) => self.operands.push(Operand::Deferred(Arg::Const(*idx))),
+            bytecode::Op::Nil => self.operands.push(Operand::Deferred(Arg::Nil)),
+            bytecode::Op::True => self.operands.push(Operand::Deferred(Arg::True)),
+            bytecode::Op::False => self.operands.push(Operand::Deferred(Arg::False)),
+            bytecode::Op::GetLocal(idx) => {
+                self.materialize(*idx);
+                if self.captured.contains(idx) {
+                    self.emit(RegOp::Move(depth, Arg::Reg(*idx)));
+                    self.operands.push(Operand::Live);
+                } else {
+                    self.operands.push(Operand::Deferred(Arg::Reg(*idx)));
+                }
+            }
+            bytecode::Op::SetLocal(idx) => self.set_local(*idx),
+            bytecode::Op::GetGlobal(idx) => {
+                self.emit(RegOp::GetGlobal(depth, *idx));
+                self.operands.push(Operand::Live);
+            }
+            bytecode::Op::SetGlobal(idx) => self.emit(RegOp::SetGlobal(*idx, self.top())),
+            bytecode::Op::GetUpval(idx) => {
+                self.emit(RegOp::GetUpval(depth, *idx));
+                self.operands.push(Operand::Live);
+            }
+            bytecode::Op::SetUpval(idx) => self.emit(RegOp::SetUpval(*idx, self.top())),
+            bytecode::Op::Negate | bytecode::Op::Not => {
+                let arg = self.pop();
+                self.emit(RegOp::Unary(op.clone(), depth - 1, arg));
+                self.operands.push(Operand::Live);
+            }
+            bytecode::Op::Add
+            | bytecode::Op::Subtract
+            | bytecode::Op::Multiply
+            | bytecode::Op::Divide
+            | bytecode::Op::Modulo
+            | bytecode::Op::Power
+            | bytecode::Op::IntDivide
+            | bytecode::Op::BitAnd
+            | bytecode::Op::BitOr
+            | bytecode::Op::BitXor
+            | bytecode::Op::ShiftLeft
+            | bytecode::Op::ShiftRight
+            | bytecode::Op::Equal
+            | bytecode::Op::Greater
+            | bytecode::Op::Less => {
+                let right = self.pop();
+                let left = self.pop();
+                self.emit(RegOp::Binary(op.clone(), depth - 2, left, right));
+                self.operands.push(Operand::Live);
+            }
+            bytecode::Op::Print => self.emit(RegOp::Print(self.top())),
+            bytecode::Op::Pop => {
+                self.operands.pop();
+            }
+            bytecode::Op::Dup(offset) => {
+                let arg = self.arg(depth - 1 - offset);
+                self.operands.push(Operand::Deferred(arg));
+            }
+            // 跳转的两头栈上的值都要在自己的寄存器里
+            bytecode::Op::Jump(offset) => {
+                self.flush();
+                self.emit(RegOp::Jump(ip + 1 + offset));
+                return false;
+            }
+            bytecode::Op::Loop(offset) => {
+                self.flush();
+                self.emit(RegOp::Jump(ip + 1 - offset));
+                return false;
+            }
+            bytecode::Op::JumpIfFalse(offset) => {
+                self.flush();
+                self.emit(RegOp::JumpIfFalse(depth - 1, ip + 1 + offset));
+            }
+            bytecode::Op::JumpIfNil(offset) => {
+                self.flush();
+                self.emit(RegOp::JumpIfNil(depth - 1, ip + 1 + offset));
+            }
+            bytecode::Op::ForIter(offset) => {
+                self.flush();
+                self.emit(RegOp::ForIter(depth - 2, ip + 2, ip + 1 + offset));
+                self.operands.push(Operand::Live);
+            }
+            bytecode::Op::Return => {
+                self.emit(RegOp::Return(self.top(), depth));
+                return false;
+            }
+            _ => {
+                self.flush();
+                self.emit(RegOp::Stack(op.clone(), depth));
+                let after = ((depth as isize) + stack_effect(op)) as usize;
+                self.operands.resize(after, Operand::Live);
+                self.operands.truncate(after);
+            }
+        }
+        true
+    }
+
+    /**
+     * 给局部变量赋值，赋的值还留在栈顶
+     */
+    fn set_local(&mut self, idx: usize) {
+        let top = self.operands.len() - 1;
+        if self.operands[top] == Operand::Deferred(Arg::Reg(idx)) {
+            return; // x = x
+        }
+
+        // 还引用着旧值的操作数先搬走
+        let mut moved = false;
+        for pos in 0..top {
+            if self.operands[pos] == Operand::Deferred(Arg::Reg(idx)) {
+                self.materialize(pos);
+                moved = true;
+            }
+        }
+
+        // 栈顶的值是上一条指令刚算出来的，直接让它写到局部变量里
+        let retarget = !moved && self.operands[top] == Operand::Live && self.code.len() > self.block_start;
+        let last_dst = match self.code.last_mut() {
+            Some(
+                (
+                    RegOp::Move(dst, _)
+                    | RegOp::GetGlobal(dst, _)
+                    | RegOp::GetUpval(dst, _)
+                    | RegOp::Unary(_, dst, _)
+                    | RegOp::Binary(_, dst, _, _),
+                    _,
+                ),
+            ) if retarget && *dst == top => Some(dst),
+            _ => None,
+        };
+        match last_dst {
+            Some(dst) => {
+                *dst = idx;
+                self.operands[top] = Operand::Deferred(Arg::Reg(idx));
+            }
+            None => {
+                let arg = self.top();
+                self.emit(RegOp::Move(idx, arg));
+            }
+        }
+        self.operands[idx] = Operand::Live;
+    }
+}
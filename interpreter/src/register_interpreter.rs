@@ -0,0 +1,133 @@
+//! 寄存器后端的执行。和栈式解释器共用同一个 Interpreter：栈、堆、全局变量和内置函数都一样，
+//! 只是每一步从 register::Code 里取指令，调用帧的 ip 也是寄存器指令的位置
+
+use crate::bytecode;
+use crate::bytecode_interpreter::{ Interpreter, InterpreterError };
+use crate::register;
+use crate::value;
+
+impl Interpreter {
+    /**
+     * 执行一条寄存器指令
+     */
+    pub fn step_registers(&mut self) -> Result<(), InterpreterError> {
+        let code = self.frame().closure.function.registers.clone();
+        let code = code.get_or_init(|| register::translate(&self.frame().closure.function));
+
+        let frame = self.frame_mut();
+        let (op, lineno) = code.code[frame.ip].clone();
+        frame.ip += 1;
+        let base = frame.slots_offset - 1; // r0 是 0 号槽位
+
+        // 栈式指令执行完栈可能比寄存器少（比如刚返回），补齐
+        if self.stack.len() < base + code.num_registers {
+            self.stack.resize(base + code.num_registers, value::Value::NIL);
+        }
+
+        match op {
+            register::RegOp::Move(dst, arg) => {
+                self.stack[base + dst] = self.read_arg(base, arg);
+            }
+            register::RegOp::GetGlobal(dst, idx) => {
+                self.stack[base + dst] = self.get_global(idx, lineno)?;
+            }
+            register::RegOp::SetGlobal(idx, arg) => {
+                let val = self.read_arg(base, arg);
+                self.set_global(idx, val, lineno)?;
+            }
+            register::RegOp::GetUpval(dst, idx) => {
+                self.stack[base + dst] = self.get_upval(idx);
+            }
+            register::RegOp::SetUpval(idx, arg) => {
+                let val = self.read_arg(base, arg);
+                self.set_upval(idx, val);
+            }
+            register::RegOp::Unary(op, dst, arg) => {
+                let val = self.read_arg(base, arg);
+                self.stack[base + dst] = self.unary_op(&op, val, lineno)?;
+            }
+            register::RegOp::Binary(op, dst, left, right) => {
+                let left = self.read_arg(base, left);
+                let right = self.read_arg(base, right);
+                // 实例上的运算符可能要调特殊方法，操作数摆回栈上，按栈式指令执行
+                let is_instance = |val: &value::Value| value::type_of(val) == value::Type::Instance;
+                if
+                    Interpreter::operator_method(&op).is_some() &&
+                    (is_instance(&left) || is_instance(&right))
+                {
+                    self.stack.truncate(base + dst);
+                    self.stack.push(left);
+                    self.stack.push(right);
+                    return self.execute((op, lineno));
+                }
+                self.stack[base + dst] = self.binary_op(&op, left, right, lineno)?;
+            }
+            register::RegOp::Print(arg) => {
+                let val = self.read_arg(base, arg);
+                self.print_val(&val)?;
+            }
+            register::RegOp::Jump(target) => {
+                self.frame_mut().ip = target;
+            }
+            register::RegOp::JumpIfFalse(reg, target) => {
+                if self.is_falsey(&self.stack[base + reg]) {
+                    self.frame_mut().ip = target;
+                }
+            }
+            register::RegOp::JumpIfNil(reg, target) => {
+                // 迭代器在 next() 返回值前面两个寄存器
+                if self.iteration_done(self.stack[base + reg - 2], self.stack[base + reg]) {
+                    self.frame_mut().ip = target;
+                }
+            }
+            register::RegOp::ForIter(reg, body, exit) => {
+                match self.stack[base + reg].unbox(&self.heap) {
+                    value::Unboxed::List(list_id) => {
+                        let idx = match self.stack[base + reg + 1].unbox(&self.heap) {
+                            value::Unboxed::Integer(n) => n as usize,
+                            _ => panic!("expected the for-in index after the iterator"),
+                        };
+                        match self.get_list_elements(list_id).get(idx).copied() {
+                            Some(elt) => {
+                                self.stack[base + reg + 1] = self.heap.integer((idx + 1) as i64);
+                                self.stack[base + reg + 2] = elt;
+                                self.frame_mut().ip = body;
+                            }
+                            None => {
+                                self.frame_mut().ip = exit;
+                            }
+                        }
+                    }
+                    _ => {
+                        // 返回值落在元素的寄存器上，交给后面的 JumpIfNil
+                        let iterator = self.stack[base + reg];
+                        self.stack.truncate(base + reg + 2);
+                        self.stack.push(iterator);
+                        self.invoke("next", 0, &[], None)?;
+                    }
+                }
+            }
+            register::RegOp::Return(arg, depth) => {
+                let val = self.read_arg(base, arg);
+                self.stack.truncate(base + depth - 1);
+                self.stack.push(val);
+                return self.execute((bytecode::Op::Return, lineno));
+            }
+            register::RegOp::Stack(op, depth) => {
+                self.stack.truncate(base + depth);
+                return self.execute((op, lineno));
+            }
+        }
+        Ok(())
+    }
+
+    fn read_arg(&mut self, base: usize, arg: register::Arg) -> value::Value {
+        match arg {
+            register::Arg::Reg(reg) => self.stack[base + reg],
+            register::Arg::Const(idx) => self.read_constant(idx),
+            register::Arg::Nil => value::Value::NIL,
+            register::Arg::True => value::Value::bool(true),
+            register::Arg::False => value::Value::bool(false),
+        }
+    }
+}
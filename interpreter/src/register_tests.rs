@@ -0,0 +1,193 @@
+#[cfg(test)]
+mod tests {
+    use crate::bytecode;
+    use crate::bytecode_interpreter::*;
+    use crate::compiler::*;
+    use crate::extensions;
+    use crate::register;
+
+    fn compile(code: &str) -> bytecode::Function {
+        let extensions = extensions::Extensions { lists: true, lambdas: true };
+        match Compiler::compile(String::from(code), extensions) {
+            Ok(func) => func,
+            Err(err) => panic!("{:?}", err),
+        }
+    }
+
+    /**
+     * 返回输出（或者报错）和执行了多少条指令
+     */
+    fn run(code: &str, registers: bool) -> (Result<Vec<String>, String>, usize) {
+        let mut interp = Interpreter::default();
+        interp.registers = registers;
+        let res = match interp.interpret(compile(code)) {
+            Ok(()) => Ok(interp.output.clone()),
+            Err(InterpreterError::Runtime(err)) => Err(err),
+        };
+        (res, interp.instruction_count)
+    }
+
+    fn check_same_output(code: &str, expected: &[&str]) {
+        let (stack_res, _) = run(code, false);
+        let (register_res, _) = run(code, true);
+        let expected: Vec<String> = expected.iter().map(|line| line.to_string()).collect();
+        assert_eq!(stack_res, Ok(expected));
+        assert_eq!(register_res, stack_res);
+    }
+
+    fn find_function(script: &bytecode::Function, name: &str) -> bytecode::Function {
+        script.chunk.constants
+            .iter()
+            .find_map(|constant| {
+                match constant {
+                    bytecode::Constant::Function(closure) if closure.function.name == name => {
+                        Some(closure.function.clone())
+                    }
+                    _ => None,
+                }
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn test_translate_fib() {
+        let script = compile(
+            "fun fib(n) {\n\
+               if (n < 2) return n;\n\
+               return fib(n - 1) + fib(n - 2);\n\
+             }"
+        );
+        // 最后面编译器补的 return nil 走不到，不会翻译
+        let code = register::translate(&find_function(&script, "fib"));
+        let ops: Vec<String> = code.code
+            .iter()
+            .map(|(op, _)| op.to_string())
+            .collect();
+        assert_eq!(
+            ops,
+            vec![
+                "LESS r2 r1 k0",
+                "JUMP_IF_FALSE r2 3",
+                "RETURN r1",
+                "GET_GLOBAL r2 k1",
+                "SUBTRACT r3 r1 k2",
+                "STACK Call(1) (depth=4)",
+                "GET_GLOBAL r3 k1",
+                "SUBTRACT r4 r1 k0",
+                "STACK Call(1) (depth=5)",
+                "ADD r2 r2 r3",
+                "RETURN r2"
+            ]
+        );
+        assert_eq!(code.num_registers, 6);
+        assert_eq!(register::disassemble(&code).len(), code.code.len());
+    }
+
+    #[test]
+    fn test_fewer_instructions() {
+        let code =
+            "fun fib(n) { if (n < 2) return n; return fib(n - 1) + fib(n - 2); }\n\
+             var total = 0;\n\
+             for (var i = 0; i < 100; i = i + 1) { total = total + i * 2; }\n\
+             print fib(15) + total;";
+        let (stack_res, stack_count) = run(code, false);
+        let (register_res, register_count) = run(code, true);
+        assert_eq!(stack_res, Ok(vec!["10510".to_string()]));
+        assert_eq!(register_res, stack_res);
+        assert!(
+            register_count * 10 < stack_count * 7,
+            "stack: {}, register: {}",
+            stack_count,
+            register_count
+        );
+    }
+
+    #[test]
+    fn test_assignment_while_operand_is_pending() {
+        // 局部变量还在模拟的操作数栈上没搬的时候被赋值，读到的得是旧值
+        check_same_output(
+            "fun f() {\n\
+               var x = 1;\n\
+               var y = x;\n\
+               x = 2;\n\
+               print x + (x = 5) + x + y;\n\
+               x = x + 1;\n\
+               print x;\n\
+               var z = x;\n\
+               z = z * z;\n\
+               print z + x;\n\
+             }\n\
+             f();",
+            &["13", "6", "42"]
+        );
+    }
+
+    #[test]
+    fn test_captured_local_changed_by_call() {
+        check_same_output(
+            "fun f() {\n\
+               var x = 1;\n\
+               fun g() { x = 10; return 0; }\n\
+               return x + g() + x;\n\
+             }\n\
+             print f();",
+            &["11"]
+        );
+    }
+
+    #[test]
+    fn test_operator_methods_fall_back_to_stack() {
+        check_same_output(
+            "class V {\n\
+               init(x) { this.x = x; }\n\
+               __add__(other) { return V(this.x + other.x); }\n\
+               __lt__(other) { return this.x < other.x; }\n\
+             }\n\
+             fun f() {\n\
+               var a = V(1);\n\
+               var b = V(2);\n\
+               var c = a + b;\n\
+               print c.x;\n\
+               print a < b;\n\
+               print b > a;\n\
+               print a == a;\n\
+             }\n\
+             f();",
+            &["3", "true", "true", "false"]
+        );
+    }
+
+    #[test]
+    fn test_generators_and_iterators() {
+        check_same_output(
+            "fun count(n) { for (var i = 0; i < n; i = i + 1) yield i * i; }\n\
+             fun f() {\n\
+               var total = 0;\n\
+               for (var x in count(4)) total = total + x;\n\
+               for (var x in [10, 20]) { if (x > 10) break; total = total + x; }\n\
+               return total;\n\
+             }\n\
+             print f();",
+            &["24"]
+        );
+    }
+
+    #[test]
+    fn test_register_backtrace() {
+        let code =
+            "fun down(n) { if (n == 0) return nil + 1; return down(n - 1); }\n\
+             fun start() { var x = down(5); return x; }\n\
+             start();";
+        let mut interp = Interpreter::default();
+        interp.registers = true;
+        assert!(interp.interpret(compile(code)).is_err());
+        assert_eq!(
+            interp.format_backtrace(),
+            "Backtrace (most recent call last):\n\n\
+             [line 3] in script\n\
+             [line 2] in start()\n\
+             ... 5 frames elided by tail calls ...\n\
+             [line 1] in down()"
+        );
+    }
+}
@@ -15,12 +15,18 @@ pub enum TokenType {
     RightBracket,
 
     Comma,
+    Colon,
     Dot,
+    DotDotDot, // ...rest 参数
     Minus,
     Plus,
     Semicolon,
     Slash,
     Star,
+    Percent,
+    Ampersand,
+    Pipe,
+    Caret,
 
     // One or two character tokens.
     Bang,
@@ -31,10 +37,22 @@ pub enum TokenType {
     GreaterEqual,
     Less,
     LessEqual,
+    StarStar, // **
+    TildeSlash, // ~/ 整除
+    LessLess, // <<
+    GreaterGreater, // >>
+    FatArrow, // => match 分支
+    PlusEqual,
+    MinusEqual,
+    StarEqual,
+    SlashEqual,
+    PlusPlus,
+    MinusMinus,
 
     // Literals.
     Identifier, // 标识符
     String,
+    Interpolation, // 插值字符串里 ${ 前面的一段，后面跟着表达式的 token，最后以 String 结尾
     Number,
 
     // Keywords.
@@ -53,8 +71,24 @@ pub enum TokenType {
     This,
     True,
     Var,
+    Const,
+    Let,
     While,
     Lambda,
+    Break,
+    Continue,
+    Do,
+    In,
+    Yield,
+    InstanceOf,
+    Trait,
+    With,
+    Match,
+    Case,
+    Enum,
+    Spawn,
+    Async,
+    Await,
 
     Eof,
 }
@@ -64,6 +98,7 @@ pub enum Literal {
     Identifier(String), // 字面量的枚举类型，附带有 字段
     Str(String),
     Number(f64),
+    Integer(i64), // 没有小数点和指数的数字
 }
 
 #[derive(Clone)]
@@ -93,7 +128,7 @@ impl fmt::Debug for Token {
 }
 
 /**
- *
+ * 将源码切分成 Token 序列
  */
 pub fn scan_tokens(input: String) -> Result<Vec<Token>, Error> {
     let mut scanner: Scanner = Default::default();
@@ -114,7 +149,7 @@ pub struct Error {
 }
 
 struct Scanner {
-    source: Vec<u8>,
+    source: Vec<char>, // 按字符而不是字节扫描，非 ASCII 的标识符和字符串才不会乱掉
     tokens: Vec<Token>,
     err: Option<Error>,
     start: usize,
@@ -122,6 +157,8 @@ struct Scanner {
     line: usize,
     col: i64,
     keywords: HashMap<String, TokenType>,
+    // 正在扫描的插值表达式：表达式里面还没闭合的 { 的个数，以及外面的字符串是不是 """
+    interpolations: Vec<(usize, bool)>,
 }
 
 /**
@@ -137,6 +174,7 @@ impl Default for Scanner {
             current: 0,
             line: 1,
             col: -1,
+            interpolations: Vec::new(),
             keywords: vec![
                 ("and", TokenType::And),
                 ("class", TokenType::Class),
@@ -153,8 +191,24 @@ impl Default for Scanner {
                 ("this", TokenType::This),
                 ("true", TokenType::True),
                 ("var", TokenType::Var),
+                ("const", TokenType::Const),
+                ("let", TokenType::Let),
                 ("while", TokenType::While),
-                ("lambda", TokenType::Lambda)
+                ("lambda", TokenType::Lambda),
+                ("break", TokenType::Break),
+                ("continue", TokenType::Continue),
+                ("do", TokenType::Do),
+                ("in", TokenType::In),
+                ("yield", TokenType::Yield),
+                ("instanceof", TokenType::InstanceOf),
+                ("trait", TokenType::Trait),
+                ("with", TokenType::With),
+                ("match", TokenType::Match),
+                ("case", TokenType::Case),
+                ("enum", TokenType::Enum),
+                ("spawn", TokenType::Spawn),
+                ("async", TokenType::Async),
+                ("await", TokenType::Await)
             ]
                 .into_iter()
                 .map(|(k, v)| (String::from(k), v))
@@ -165,13 +219,17 @@ impl Default for Scanner {
 
 impl Scanner {
     fn scan_tokens(&mut self, input: String) {
-        self.source = input.into_bytes();
+        self.source = input.chars().collect();
 
         while !self.done() {
             self.start = self.current;
             self.scan_token();
         }
 
+        if self.err.is_none() && !self.interpolations.is_empty() {
+            self.error("Unterminated string interpolation".to_string());
+        }
+
         match self.err {
             Some(_) => {} // 如果有错误，啥也不干
             None =>
@@ -192,7 +250,7 @@ impl Scanner {
         self.current += 1;
         self.col += 1;
 
-        char::from(self.source[self.current - 1])
+        self.source[self.current - 1]
     }
 
     fn scan_token(&mut self) {
@@ -201,45 +259,127 @@ impl Scanner {
         match c {
             '(' => self.add_token(TokenType::LeftParen),
             ')' => self.add_token(TokenType::RightParen),
-            '{' => self.add_token(TokenType::LeftBrace),
-            '}' => self.add_token(TokenType::RightBrace),
+            '{' => {
+                if let Some((depth, _)) = self.interpolations.last_mut() {
+                    *depth += 1;
+                }
+                self.add_token(TokenType::LeftBrace)
+            }
+            '}' =>
+                match self.interpolations.last_mut() {
+                    // 插值表达式结束了，接着扫描外面的字符串
+                    Some((0, triple)) => {
+                        let triple = *triple;
+                        self.interpolations.pop();
+                        self.string_body(false, triple)
+                    }
+                    Some((depth, _)) => {
+                        *depth -= 1;
+                        self.add_token(TokenType::RightBrace)
+                    }
+                    None => self.add_token(TokenType::RightBrace),
+                }
             '[' => self.add_token(TokenType::LeftBracket),
             ']' => self.add_token(TokenType::RightBracket),
             ',' => self.add_token(TokenType::Comma),
-            '.' => self.add_token(TokenType::Dot),
-            '-' => self.add_token(TokenType::Minus),
-            '+' => self.add_token(TokenType::Plus),
+            ':' => self.add_token(TokenType::Colon),
+            '.' => {
+                let token_type = if self.peek() == '.' && self.peek_next() == '.' {
+                    self.matches('.');
+                    self.matches('.');
+                    TokenType::DotDotDot
+                } else {
+                    TokenType::Dot
+                };
+                self.add_token(token_type)
+            }
             ';' => self.add_token(TokenType::Semicolon),
-            '*' => self.add_token(TokenType::Star),
+            '%' => self.add_token(TokenType::Percent),
+            '&' => self.add_token(TokenType::Ampersand),
+            '|' => self.add_token(TokenType::Pipe),
+            '^' => self.add_token(TokenType::Caret),
 
             // 可能会有两个字符的 符号
+            '-' => {
+                let token_type = if self.matches('-') {
+                    TokenType::MinusMinus
+                } else if self.matches('=') {
+                    TokenType::MinusEqual
+                } else {
+                    TokenType::Minus
+                };
+                self.add_token(token_type)
+            }
+            '+' => {
+                let token_type = if self.matches('+') {
+                    TokenType::PlusPlus
+                } else if self.matches('=') {
+                    TokenType::PlusEqual
+                } else {
+                    TokenType::Plus
+                };
+                self.add_token(token_type)
+            }
+            '*' => {
+                let token_type = if self.matches('*') {
+                    TokenType::StarStar
+                } else if self.matches('=') {
+                    TokenType::StarEqual
+                } else {
+                    TokenType::Star
+                };
+                self.add_token(token_type)
+            }
+            '~' => {
+                if self.matches('/') {
+                    self.add_token(TokenType::TildeSlash)
+                } else {
+                    self.error("scanner can't handle ~, did you mean ~/ ?".to_string());
+                }
+            }
             '!' => {
                 let matches_eq = self.matches('=');
                 self.add_token(if matches_eq { TokenType::BangEqual } else { TokenType::Bang })
             }
             '=' => {
-                let matches_eq = self.matches('=');
-                self.add_token(if matches_eq { TokenType::EqualEqual } else { TokenType::Equal })
+                let token_type = if self.matches('=') {
+                    TokenType::EqualEqual
+                } else if self.matches('>') {
+                    TokenType::FatArrow
+                } else {
+                    TokenType::Equal
+                };
+                self.add_token(token_type)
             }
             '<' => {
-                let matches_eq = self.matches('=');
-                self.add_token(if matches_eq { TokenType::LessEqual } else { TokenType::Less })
+                let token_type = if self.matches('=') {
+                    TokenType::LessEqual
+                } else if self.matches('<') {
+                    TokenType::LessLess
+                } else {
+                    TokenType::Less
+                };
+                self.add_token(token_type)
             }
             '>' => {
-                let matches_eq = self.matches('=');
-                self.add_token(
-                    if matches_eq {
-                        TokenType::GreaterEqual
-                    } else {
-                        TokenType::Greater
-                    }
-                )
+                let token_type = if self.matches('=') {
+                    TokenType::GreaterEqual
+                } else if self.matches('>') {
+                    TokenType::GreaterGreater
+                } else {
+                    TokenType::Greater
+                };
+                self.add_token(token_type)
             }
             '/' => {
                 if self.matches('/') {
                     while self.peek() != '\n' && !self.is_at_end() {
                         self.advance();
                     }
+                } else if self.matches('*') {
+                    self.block_comment()
+                } else if self.matches('=') {
+                    self.add_token(TokenType::SlashEqual)
                 } else {
                     self.add_token(TokenType::Slash)
                 }
@@ -249,7 +389,12 @@ impl Scanner {
                 self.line += 1;
                 self.col = 0;
             }
-            '"' => self.string(), // 如果出现了 引号，那么获取 字符串
+            '"' => self.string(false), // 如果出现了 引号，那么获取 字符串
+            'r' if self.peek() == '"' => {
+                // r"..." 原始字符串，不处理转义
+                self.advance();
+                self.string(true)
+            }
             _ => {
                 // 如果不是符号
                 if Scanner::is_decimal_digit(c) {
@@ -257,18 +402,14 @@ impl Scanner {
                 } else if Scanner::is_alpha(c) {
                     self.identifier()
                 } else {
-                    self.err = Some(Error {
-                        what: format!("scanner can't handle {}", c),
-                        line: self.line,
-                        col: self.col,
-                    });
+                    self.error(format!("scanner can't handle {}", c));
                 }
             }
         }
     }
 
     fn is_alpha(c: char) -> bool {
-        c.is_alphabetic()
+        c.is_alphabetic() || c == '_'
     }
 
     fn is_decimal_digit(c: char) -> bool {
@@ -280,7 +421,7 @@ impl Scanner {
     }
 
     /**
-     *
+     * 标识符 或者 关键字
      */
     fn identifier(&mut self) {
         // 前进，直到不是 字母
@@ -288,9 +429,7 @@ impl Scanner {
             self.advance();
         }
 
-        let literal_val = String::from_utf8(
-            self.source[self.start..self.current].to_vec()
-        ).unwrap();
+        let literal_val = self.lexeme();
 
         let token_type = match self.keywords.get(&literal_val) {
             Some(kw_token_type) => *kw_token_type,
@@ -307,70 +446,235 @@ impl Scanner {
         }
     }
 
+    /**
+     * 数字：十进制（可以有小数和指数）、0x 十六进制、0b 二进制，数字之间可以用 _ 分隔
+     */
     fn number(&mut self) {
-        while Scanner::is_decimal_digit(self.peek()) {
+        let first = self.source[self.start];
+        if first == '0' && (self.peek() == 'x' || self.peek() == 'X') {
             self.advance();
+            return self.radix_number(16);
         }
+        if first == '0' && (self.peek() == 'b' || self.peek() == 'B') {
+            self.advance();
+            return self.radix_number(2);
+        }
+
+        self.digits();
 
         if self.peek() == '.' && Scanner::is_decimal_digit(self.peek_next()) {
             self.advance();
+            self.digits();
         }
 
-        while Scanner::is_decimal_digit(self.peek()) {
-            self.advance();
+        // 指数部分，e 后面必须跟着数字，不然就不算
+        if self.peek() == 'e' || self.peek() == 'E' {
+            let sign = self.peek_next() == '+' || self.peek_next() == '-';
+            let digit_at = if sign { self.current + 2 } else { self.current + 1 };
+            if digit_at < self.source.len() && Scanner::is_decimal_digit(self.source[digit_at]) {
+                self.advance();
+                if sign {
+                    self.advance();
+                }
+                self.digits();
+            }
+        }
+
+        if self.source[self.current - 1] == '_' {
+            return self.error("numeric literal can't end with _".to_string());
         }
 
-        let val: f64 = String::from_utf8(self.source[self.start..self.current].to_vec())
-            .unwrap()
-            .parse()
-            .unwrap();
+        let text: String = self.lexeme().chars().filter(|c| *c != '_').collect();
+
+        // 整数放不下 i64 的时候退回到浮点数
+        if !text.contains(['.', 'e', 'E']) {
+            if let Ok(val) = text.parse::<i64>() {
+                return self.add_token_literal(TokenType::Number, Some(Literal::Integer(val)));
+            }
+        }
+        let val: f64 = text.parse().unwrap();
 
         self.add_token_literal(TokenType::Number, Some(Literal::Number(val)))
     }
 
-    fn string(&mut self) {
-        while self.peek() != '"' && !self.is_at_end() {
-            if self.peek() == '\n' {
-                self.line += 1;
-            }
+    fn digits(&mut self) {
+        while Scanner::is_decimal_digit(self.peek()) || self.peek() == '_' {
             self.advance();
         }
+    }
 
-        // 如果没有 右边的引号，那么有问题
-        if self.is_at_end() {
-            self.err = Some(Error {
-                what: "Unterminated string".to_string(),
-                line: self.line,
-                col: self.col,
-            });
+    fn radix_number(&mut self, radix: u32) {
+        while self.peek().is_digit(radix) || self.peek() == '_' {
+            self.advance();
         }
 
-        assert!(self.peek() == '"');
+        // 跳过前面的 0x / 0b
+        let digits: String = self.source[self.start + 2..self.current]
+            .iter()
+            .filter(|c| **c != '_')
+            .collect();
+        if digits.is_empty() || self.source[self.current - 1] == '_' {
+            return self.error(format!("invalid numeric literal {}", self.lexeme()));
+        }
+
+        match i64::from_str_radix(&digits, radix) {
+            Ok(val) => self.add_token_literal(TokenType::Number, Some(Literal::Integer(val))),
+            Err(_) => self.error(format!("numeric literal {} is too large", self.lexeme())),
+        }
+    }
+
+    /**
+     * 字符串，开头的引号已经吃掉了；""" 开头的字符串到 """ 才结束，中间可以直接写 "
+     * raw 为 true 的时候不处理转义，也没有插值
+     */
+    fn string(&mut self, raw: bool) {
+        let triple = self.peek() == '"' && self.peek_next() == '"';
+        if triple {
+            self.advance();
+            self.advance();
+        }
+        self.string_body(raw, triple)
+    }
+
+    /**
+     * 扫描到字符串结尾，或者遇到 ${ 的时候先把前面这一段作为 Interpolation 交出去
+     */
+    fn string_body(&mut self, raw: bool, triple: bool) {
+        let mut val = String::new();
+        loop {
+            if self.is_at_end() {
+                // 如果没有 右边的引号，那么有问题
+                return self.error("Unterminated string".to_string());
+            }
+
+            if self.peek() == '"' {
+                if !triple {
+                    break;
+                }
+                if self.peek_next() == '"' && self.peek_at(2) == '"' {
+                    self.advance();
+                    self.advance();
+                    break;
+                }
+            }
+
+            if !raw && self.peek() == '$' && self.peek_next() == '{' {
+                self.advance();
+                self.advance();
+                self.interpolations.push((0, triple));
+                return self.add_token_literal(TokenType::Interpolation, Some(Literal::Str(val)));
+            }
+
+            let c = self.advance();
+            match c {
+                '\n' => {
+                    self.line += 1;
+                    self.col = 0;
+                    val.push(c);
+                }
+                '\\' if !raw =>
+                    match self.escape() {
+                        Some(escaped) => val.push(escaped),
+                        None => {
+                            return;
+                        }
+                    }
+                _ => val.push(c),
+            }
+        }
 
         self.advance(); // 跨过 右边的 "
 
-        self.add_token_literal(
-            TokenType::String,
-            Some(
-                Literal::Str(
-                    String::from_utf8(
-                        self.source[self.start + 1..self.current - 1].to_vec()
-                    ).unwrap()
-                )
-            )
-        )
+        self.add_token_literal(TokenType::String, Some(Literal::Str(val)))
+    }
+
+    /**
+     * 反斜杠后面的转义，出错的时候记录错误并返回 None
+     */
+    fn escape(&mut self) -> Option<char> {
+        if self.is_at_end() {
+            self.error("Unterminated string".to_string());
+            return None;
+        }
+
+        match self.advance() {
+            'n' => Some('\n'),
+            't' => Some('\t'),
+            'r' => Some('\r'),
+            '0' => Some('\0'),
+            '"' => Some('"'),
+            '\\' => Some('\\'),
+            '$' => Some('$'), // \${ 不是插值
+            'u' => self.unicode_escape(),
+            c => {
+                self.error(format!("unknown escape sequence \\{}", c));
+                None
+            }
+        }
+    }
+
+    /**
+     * \u{1F600} 这种，花括号里面是十六进制的码点
+     */
+    fn unicode_escape(&mut self) -> Option<char> {
+        if !self.matches('{') {
+            self.error("expected { after \\u".to_string());
+            return None;
+        }
+
+        let mut digits = String::new();
+        while self.peek().is_ascii_hexdigit() {
+            digits.push(self.advance());
+        }
+
+        if !self.matches('}') || digits.is_empty() || digits.len() > 6 {
+            self.error("invalid unicode escape, expected \\u{XXXX}".to_string());
+            return None;
+        }
+
+        let escaped = u32::from_str_radix(&digits, 16).ok().and_then(char::from_u32);
+        if escaped.is_none() {
+            self.error(format!("invalid unicode code point {}", digits));
+        }
+        escaped
+    }
+
+    /**
+     * 块注释，可以嵌套；开头的斜杠和星号已经吃掉了
+     */
+    fn block_comment(&mut self) {
+        let mut depth = 1;
+        while depth > 0 {
+            if self.is_at_end() {
+                return self.error("Unterminated block comment".to_string());
+            }
+
+            let c = self.advance();
+            if c == '\n' {
+                self.line += 1;
+                self.col = 0;
+            } else if c == '/' && self.matches('*') {
+                depth += 1;
+            } else if c == '*' && self.matches('/') {
+                depth -= 1;
+            }
+        }
     }
 
     fn peek_next(&self) -> char {
-        if self.current + 1 >= self.source.len() {
+        self.peek_at(1)
+    }
+
+    fn peek_at(&self, offset: usize) -> char {
+        if self.current + offset >= self.source.len() {
             '\0'
         } else {
-            char::from(self.source[self.current + 1])
+            self.source[self.current + offset]
         }
     }
 
     fn peek(&self) -> char {
-        if self.is_at_end() { '\0' } else { char::from(self.source[self.current]) }
+        if self.is_at_end() { '\0' } else { self.source[self.current] }
     }
 
     /**
@@ -378,10 +682,10 @@ impl Scanner {
      */
     fn matches(&mut self, c: char) -> bool {
         if self.is_at_end() {
-            return true;
+            return false;
         }
 
-        if char::from(self.source[self.current]) != c {
+        if self.source[self.current] != c {
             false
         } else {
             self.current += 1;
@@ -391,24 +695,32 @@ impl Scanner {
     }
 
     /**
-     * 添加 Token
+     * 当前 token 的原文
      */
-    fn add_token(&mut self, token_type: TokenType) {
-        let text = self.source[self.start..self.current].to_vec();
-        self.tokens.push(Token {
-            ty: token_type,
-            lexeme: text,
-            literal: None,
+    fn lexeme(&self) -> String {
+        self.source[self.start..self.current].iter().collect()
+    }
+
+    fn error(&mut self, what: String) {
+        self.err = Some(Error {
+            what,
             line: self.line,
             col: self.col,
-        })
+        });
+    }
+
+    /**
+     * 添加 Token
+     */
+    fn add_token(&mut self, token_type: TokenType) {
+        self.add_token_literal(token_type, None)
     }
 
     /**
      * 将 literal 添加 TOken 到表中
      */
     fn add_token_literal(&mut self, token_type: TokenType, literal: Option<Literal>) {
-        let text = self.source[self.start..self.current].to_vec();
+        let text = self.lexeme().into_bytes();
 
         self.tokens.push(Token {
             ty: token_type,
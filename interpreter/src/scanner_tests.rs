@@ -0,0 +1,167 @@
+#[cfg(test)]
+mod tests {
+    use crate::scanner;
+
+    fn scan(code: &str) -> Vec<scanner::Token> {
+        match scanner::scan_tokens(code.to_string()) {
+            Ok(tokens) => tokens,
+            Err(err) => panic!("{:?}", err),
+        }
+    }
+
+    /**
+     * 取出所有字符串字面量
+     */
+    fn strings(code: &str) -> Vec<String> {
+        scan(code)
+            .into_iter()
+            .filter_map(|tok| {
+                match tok.literal {
+                    Some(scanner::Literal::Str(s)) => Some(s),
+                    _ => None,
+                }
+            })
+            .collect()
+    }
+
+    /**
+     * 取出所有数字字面量，整数也转成浮点数
+     */
+    fn numbers(code: &str) -> Vec<f64> {
+        scan(code)
+            .into_iter()
+            .filter_map(|tok| {
+                match tok.literal {
+                    Some(scanner::Literal::Number(n)) => Some(n),
+                    Some(scanner::Literal::Integer(n)) => Some(n as f64),
+                    _ => None,
+                }
+            })
+            .collect()
+    }
+
+    fn integers(code: &str) -> Vec<i64> {
+        scan(code)
+            .into_iter()
+            .filter_map(|tok| {
+                match tok.literal {
+                    Some(scanner::Literal::Integer(n)) => Some(n),
+                    _ => None,
+                }
+            })
+            .collect()
+    }
+
+    fn check_error(code: &str, expected: &str) {
+        match scanner::scan_tokens(code.to_string()) {
+            Ok(tokens) => panic!("{:?}", tokens),
+            Err(err) => assert_eq!(err.what, expected),
+        }
+    }
+
+    #[test]
+    fn test_unicode_identifiers_and_strings() {
+        let tokens = scan("var 名字 = \"你好, wörld\"; var _x1 = 1;");
+        let idents: Vec<_> = tokens
+            .iter()
+            .filter(|tok| tok.ty == scanner::TokenType::Identifier)
+            .map(|tok| String::from_utf8(tok.lexeme.clone()).unwrap())
+            .collect();
+        assert_eq!(idents, vec!["名字", "_x1"]);
+        assert_eq!(strings("\"你好, wörld\""), vec!["你好, wörld"]);
+        // 列号按字符计算
+        assert_eq!(tokens[1].col, 5);
+    }
+
+    #[test]
+    fn test_escape_sequences() {
+        assert_eq!(
+            strings(r#""a\nb\tc\"d\\e\u{48}\u{1F600}""#),
+            vec!["a\nb\tc\"d\\eH\u{1F600}"]
+        );
+    }
+
+    #[test]
+    fn test_raw_and_multiline_strings() {
+        assert_eq!(strings(r#"r"C:\new\dir""#), vec![r"C:\new\dir"]);
+        let tokens = scan("\"\"\"first \"line\"\nsecond\"\"\" x");
+        assert_eq!(strings("\"\"\"first \"line\"\nsecond\"\"\""), vec!["first \"line\"\nsecond"]);
+        assert_eq!(tokens[1].line, 2);
+        assert_eq!(strings("\"\"\"\"\"\" \"\""), vec!["", ""]);
+    }
+
+    #[test]
+    fn test_number_literals() {
+        assert_eq!(
+            numbers("0xFF 0b1010 1_000_000 1.5e3 2E-2 3e+1 0.25"),
+            vec![255.0, 10.0, 1000000.0, 1500.0, 0.02, 30.0, 0.25]
+        );
+    }
+
+    #[test]
+    fn test_integer_literals() {
+        // 带小数点或者指数的是浮点数，其他的都是整数
+        assert_eq!(integers("0xFF 0b1010 1_000_000 1.5e3 3.0 1e2 42"), vec![255, 10, 1000000, 42]);
+        assert_eq!(integers("9223372036854775807"), vec![i64::MAX]);
+        // 放不下 i64 的十进制数退回到浮点数
+        assert_eq!(integers("9223372036854775808"), vec![]);
+        assert_eq!(numbers("9223372036854775808"), vec![9223372036854775808.0]);
+    }
+
+    #[test]
+    fn test_block_comments() {
+        let tokens = scan("1 /* a /* nested */ comment\n */ 2");
+        assert_eq!(numbers("1 /* a /* nested */ comment\n */ 2"), vec![1.0, 2.0]);
+        assert_eq!(tokens[1].line, 2);
+    }
+
+    #[test]
+    fn test_scanner_errors() {
+        check_error("\"abc", "Unterminated string");
+        check_error("/* abc", "Unterminated block comment");
+        check_error("\"\\q\"", "unknown escape sequence \\q");
+        check_error("\"\\u{110000}\"", "invalid unicode code point 110000");
+        check_error("0x", "invalid numeric literal 0x");
+        check_error("1_", "numeric literal can't end with _");
+    }
+
+    #[test]
+    fn test_interpolation_segments() {
+        let tys: Vec<_> = scan("\"a ${x} b ${ {} } c\"")
+            .iter()
+            .map(|tok| tok.ty)
+            .collect();
+        assert_eq!(tys, vec![
+            scanner::TokenType::Interpolation,
+            scanner::TokenType::Identifier,
+            scanner::TokenType::Interpolation,
+            scanner::TokenType::LeftBrace,
+            scanner::TokenType::RightBrace,
+            scanner::TokenType::String,
+            scanner::TokenType::Eof
+        ]);
+        assert_eq!(strings("\"cost: \\${x}\""), vec!["cost: ${x}"]);
+        assert_eq!(strings(r#"r"${x}""#), vec!["${x}"]);
+        check_error("\"a ${x", "Unterminated string interpolation");
+    }
+
+    #[test]
+    fn test_rest_parameter_dots() {
+        let tys: Vec<_> = scan("(a, ...rest) x.y")
+            .iter()
+            .map(|tok| tok.ty)
+            .collect();
+        assert_eq!(tys, vec![
+            scanner::TokenType::LeftParen,
+            scanner::TokenType::Identifier,
+            scanner::TokenType::Comma,
+            scanner::TokenType::DotDotDot,
+            scanner::TokenType::Identifier,
+            scanner::TokenType::RightParen,
+            scanner::TokenType::Identifier,
+            scanner::TokenType::Dot,
+            scanner::TokenType::Identifier,
+            scanner::TokenType::Eof
+        ]);
+    }
+}
@@ -1,10 +1,16 @@
-use std::collections::HashMap;
+use std::collections::{ HashMap, HashSet };
 use std::convert::TryInto;
 use std::sync::atomic::{ AtomicBool, Ordering };
 use std::sync::Arc;
 use std::time::{ SystemTime, UNIX_EPOCH };
 
+use crate::builtins;
 use crate::expr;
+use crate::extensions;
+use crate::json;
+use crate::parser;
+use crate::prelude;
+use crate::scanner;
 
 use std::fmt;
 use std::fmt::Write;
@@ -16,7 +22,32 @@ static INIT: &str = "init";
  */
 trait Callable {
     fn arity(&self, interpreter: &Interpreter) -> u8;
+    // 末尾可以省略的参数个数，只有内置函数会用到
+    fn optional_args(&self, _interpreter: &Interpreter) -> u8 {
+        0
+    }
+    // 有 ...rest 参数的时候，多出来的位置参数都收进一个列表
+    fn has_rest(&self, _interpreter: &Interpreter) -> bool {
+        false
+    }
+    // 形参的名字，命名参数和报错信息要用，内置函数没有
+    fn param_names(&self, _interpreter: &Interpreter) -> Vec<String> {
+        Vec::new()
+    }
     fn call(&self, interpreter: &mut Interpreter, args: &[Value]) -> Result<Value, String>;
+    /**
+     * 实参已经按形参的顺序排好了：没传的可选参数是 None，多出来的位置参数放在 rest 里面。
+     * 内置函数没有命名参数，没传的只会是末尾的可选参数
+     */
+    fn call_bound(
+        &self,
+        interpreter: &mut Interpreter,
+        args: Vec<Option<Value>>,
+        _rest: Vec<Value>
+    ) -> Result<Value, String> {
+        let args: Vec<Value> = args.into_iter().flatten().collect();
+        self.call(interpreter, &args)
+    }
 }
 
 /**
@@ -26,10 +57,13 @@ trait Callable {
 pub struct NativeFunction {
     pub name: String,
     pub arity: u8,
+    pub optional_args: u8, // 末尾可以省略的参数个数，比如 sort 的比较函数
     // interpreter 存储环境，[Value] 存储参数信息
-    pub callable: fn(&mut Interpreter, &[Value]) -> Result<Value, String>,
+    pub callable: NativeCallable,
 }
 
+type NativeCallable = fn(&mut Interpreter, &[Value]) -> Result<Value, String>;
+
 impl fmt::Debug for NativeFunction {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "NativeFunction({})", self.name)
@@ -43,6 +77,9 @@ impl Callable for NativeFunction {
     fn arity(&self, _interpreter: &Interpreter) -> u8 {
         self.arity
     }
+    fn optional_args(&self, _interpreter: &Interpreter) -> u8 {
+        self.optional_args
+    }
     fn call(&self, interpreter: &mut Interpreter, args: &[Value]) -> Result<Value, String> {
         (self.callable)(interpreter, args)
     }
@@ -56,75 +93,56 @@ pub struct LoxFunction {
     pub id: u64, // 在解释器运行期间，会给每一个 函数分配一个 id
     pub name: expr::Symbol,
     pub parameters: Vec<expr::Symbol>,
+    pub defaults: Vec<expr::Expr>, // 最后几个参数的默认值，在函数的环境里面求值
+    pub rest: Option<expr::Symbol>,
     pub body: Vec<expr::Stmt>,
     pub closure: Environment, // 函数被创建的时候，环境快照
     pub this_binding: Option<Box<Value>>, // this 对应的 instance_id
     pub superclass: Option<u64>, // 父类可选
     pub is_initializer: bool,
+    pub is_generator: bool, // 调用的时候不执行函数体，而是返回一个生成器
 }
 
 impl Callable for LoxFunction {
     fn arity(&self, _interpreter: &Interpreter) -> u8 {
-        // 返回参数的数量
-        self.parameters.len().try_into().unwrap()
+        // 返回必须传的参数的数量
+        (self.parameters.len() - self.defaults.len()).try_into().unwrap()
+    }
+    fn optional_args(&self, _interpreter: &Interpreter) -> u8 {
+        self.defaults.len().try_into().unwrap()
+    }
+    fn has_rest(&self, _interpreter: &Interpreter) -> bool {
+        self.rest.is_some()
+    }
+    fn param_names(&self, _interpreter: &Interpreter) -> Vec<String> {
+        self.parameters
+            .iter()
+            .map(|param| param.name.clone())
+            .collect()
     }
     fn call(&self, interpreter: &mut Interpreter, args: &[Value]) -> Result<Value, String> {
-        /* ---------- 形参与实参做映射 ---------- */
-
-        let args_env: HashMap<_, _> = self.parameters
-            .iter() // parameters 与 arguments 对应
-            .zip(args.iter())
-            .map(|(param, arg)| {
-                (
-                    param.name.clone(),
-                    (
-                        Some(arg.clone()),
-                        SourceLocation {
-                            line: param.line,
-                            col: param.col,
-                        },
-                    ),
-                )
-            })
-            .collect();
-
+        let num_params = self.parameters.len().min(args.len());
+        let bound = args[..num_params].iter().cloned().map(Some).collect();
+        self.call_bound(interpreter, bound, args[num_params..].to_vec())
+    }
+    fn call_bound(
+        &self,
+        interpreter: &mut Interpreter,
+        args: Vec<Option<Value>>,
+        rest: Vec<Value>
+    ) -> Result<Value, String> {
         /* ---------- 保存环境 ---------- */
 
         let saved_env = interpreter.env.clone();
         let saved_retval = interpreter.retval.clone();
         let saved_enclosing_function = interpreter.enclosing_function;
 
-        /* ---------- fork 出新的 执行环境 ---------- */
-
-        let mut env = self.closure.clone();
-        env.venv.extend(saved_env.venv.clone()); // fork 执行环境
-        env.venv.extend(args_env); // 形参 执行环境
+        let env = self.bind_env(interpreter, &saved_env, args, rest)?;
 
-        if let Some(this_val) = &self.this_binding {
-            //
-            // this is just used for lookup on name. source location is meaningless and unused`
-            let this_symbol = Interpreter::this_symbol(0, -1);
-            env.venv.insert(this_symbol.name, (
-                // 将 this 加入到环境中
-                Some(*this_val.clone()),
-                SourceLocation {
-                    line: this_symbol.line,
-                    col: this_symbol.col,
-                },
-            ));
-        } else if let Ok(this_val) = interpreter.lookup(&Interpreter::this_symbol(0, -1)) {
-            // this is just used for lookup on name. source location is meaningless and unused`
-            // 找一下有没有 this
-            let this_symbol = Interpreter::this_symbol(0, -1);
-            env.venv.insert(this_symbol.name, (
-                Some(this_val.clone()),
-                SourceLocation {
-                    line: this_symbol.line,
-                    col: this_symbol.col,
-                },
-            ));
+        // 生成器函数先不执行，环境留给第一次 next() 的时候用
+        if self.is_generator {
+            return Ok(interpreter.create_generator(self.clone(), env));
         }
-        let env = env; // 固定为不可变
 
         /* ---------- 将执行环境放入 interpreter 中 ---------- */
 
@@ -133,6 +151,19 @@ impl Callable for LoxFunction {
         interpreter.backtrace.push((0, self.name.name.clone()));
         interpreter.interpret(&self.body)?; //
 
+        // return f(...) 留下来的调用在这一层接着做，Rust 的栈不会越来越深
+        while let Some(tail_call) = interpreter.tail_call.take() {
+            let TailCall { function, args, rest, caller_env } = tail_call;
+            interpreter.retval = None;
+            interpreter.env = function.bind_env(interpreter, &caller_env, args, rest)?;
+            interpreter.enclosing_function = Some(function.id);
+            if let Some((elided, name)) = interpreter.backtrace.last_mut() {
+                *elided += 1;
+                *name = function.name.name.clone();
+            }
+            interpreter.interpret(&function.body)?;
+        }
+
         let retval = interpreter.retval.clone();
 
         interpreter.backtrace.pop();
@@ -170,12 +201,96 @@ impl Callable for LoxFunction {
     }
 }
 
+impl LoxFunction {
+    /**
+     * 给调用准备好环境：闭包的快照 + 调用者的环境，再把参数绑定上去
+     */
+    fn bind_env(
+        &self,
+        interpreter: &mut Interpreter,
+        caller_env: &Environment,
+        args: Vec<Option<Value>>,
+        rest: Vec<Value>
+    ) -> Result<Environment, String> {
+        /* ---------- fork 出新的 执行环境 ---------- */
+
+        let mut env = self.closure.clone();
+        env.venv.extend(caller_env.venv.clone()); // fork 执行环境
+        env.consts.retain(|name| !caller_env.venv.contains_key(name));
+        env.consts.extend(caller_env.consts.iter().cloned());
+
+        if let Some(this_val) = &self.this_binding {
+            //
+            // this is just used for lookup on name. source location is meaningless and unused`
+            let this_symbol = Interpreter::this_symbol(0, -1);
+            env.venv.insert(this_symbol.name, (
+                // 将 this 加入到环境中
+                Some(*this_val.clone()),
+                SourceLocation {
+                    line: this_symbol.line,
+                    col: this_symbol.col,
+                },
+            ));
+        } else if let Ok(this_val) = caller_env.get(&Interpreter::this_symbol(0, -1)) {
+            // this is just used for lookup on name. source location is meaningless and unused`
+            // 找一下有没有 this
+            let this_symbol = Interpreter::this_symbol(0, -1);
+            env.venv.insert(this_symbol.name, (
+                Some(this_val.clone()),
+                SourceLocation {
+                    line: this_symbol.line,
+                    col: this_symbol.col,
+                },
+            ));
+        }
+
+        /* ---------- 形参与实参做映射 ---------- */
+
+        // 没传的参数用默认值，默认值里面可以用到前面的参数
+        let num_required = self.parameters.len() - self.defaults.len();
+        for (idx, param) in self.parameters.iter().enumerate() {
+            let arg = match args.get(idx).cloned().flatten() {
+                Some(arg) => arg,
+                None if idx >= num_required => {
+                    interpreter.env = env.clone();
+                    let res = interpreter.interpret_expr(&self.defaults[idx - num_required]);
+                    interpreter.env = caller_env.clone();
+                    res?
+                }
+                None => {
+                    continue;
+                }
+            };
+            env.define(param.clone(), Some(arg));
+        }
+        if let Some(rest_param) = &self.rest {
+            let rest_list = interpreter.create_list(rest);
+            env.define(rest_param.clone(), Some(rest_list));
+        }
+        Ok(env)
+    }
+}
+
+/**
+ * return f(...) 的时候先不调用，算好的参数放在这里，由外层的 LoxFunction::call_bound 接着调用
+ */
+pub struct TailCall {
+    function: LoxFunction,
+    args: Vec<Option<Value>>,
+    rest: Vec<Value>,
+    caller_env: Environment, // 和普通调用一样，调用者的环境要合并进去
+}
+
 #[derive(Clone, Debug)]
 pub struct LoxClass {
     pub name: expr::Symbol,
     pub superclass: Option<u64>,
     pub id: u64,
     pub methods: HashMap<String, u64>,
+    pub class_methods: HashMap<String, u64>, // 静态方法，this 绑定的是类本身
+    pub getters: HashMap<String, u64>,
+    pub setters: HashMap<String, u64>,
+    pub traits: Vec<u64>, // 混入的 trait，instanceof 也认它们
 }
 
 /**
@@ -184,10 +299,28 @@ pub struct LoxClass {
 impl Callable for LoxClass {
     fn arity(&self, interpreter: &Interpreter) -> u8 {
         match self.init(interpreter) {
-            Some(initializer) => initializer.parameters.len().try_into().unwrap(),
+            Some(initializer) => initializer.arity(interpreter),
+            None => 0,
+        }
+    }
+    fn optional_args(&self, interpreter: &Interpreter) -> u8 {
+        match self.init(interpreter) {
+            Some(initializer) => initializer.optional_args(interpreter),
             None => 0,
         }
     }
+    fn has_rest(&self, interpreter: &Interpreter) -> bool {
+        match self.init(interpreter) {
+            Some(initializer) => initializer.has_rest(interpreter),
+            None => false,
+        }
+    }
+    fn param_names(&self, interpreter: &Interpreter) -> Vec<String> {
+        match self.init(interpreter) {
+            Some(initializer) => initializer.param_names(interpreter),
+            None => Vec::new(),
+        }
+    }
     fn call(&self, interpreter: &mut Interpreter, args: &[Value]) -> Result<Value, String> {
         let instance = interpreter.create_instance(&self.name, self.id);
 
@@ -196,6 +329,21 @@ impl Callable for LoxClass {
             initializer.call(interpreter, args)?;
         }
 
+        Ok(instance)
+    }
+    fn call_bound(
+        &self,
+        interpreter: &mut Interpreter,
+        args: Vec<Option<Value>>,
+        rest: Vec<Value>
+    ) -> Result<Value, String> {
+        let instance = interpreter.create_instance(&self.name, self.id);
+
+        if let Some(mut initializer) = self.init(interpreter) {
+            initializer.this_binding = Some(Box::new(instance.clone()));
+            initializer.call_bound(interpreter, args, rest)?;
+        }
+
         Ok(instance)
     }
 }
@@ -218,17 +366,77 @@ impl LoxClass {
         method_name: &str,
         interpreter: &Interpreter
     ) -> Option<(expr::Symbol, u64)> {
-        if let Some(method_id) = self.methods.get(method_name) {
+        self.find_member(method_name, |cls| &cls.methods, interpreter)
+    }
+
+    /**
+     * 在某一张方法表（方法、静态方法、getter、setter）里面找，找不到就去父类找
+     */
+    fn find_member(
+        &self,
+        name: &str,
+        members: fn(&LoxClass) -> &HashMap<String, u64>,
+        interpreter: &Interpreter
+    ) -> Option<(expr::Symbol, u64)> {
+        if let Some(method_id) = members(self).get(name) {
             let lox_fn = interpreter.get_lox_function(*method_id);
             return Some((lox_fn.name.clone(), *method_id));
         }
         if let Some(superclass_id) = self.superclass {
-            return interpreter.get_lox_class(superclass_id).find_method(method_name, interpreter);
+            return interpreter.get_lox_class(superclass_id).find_member(name, members, interpreter);
         }
         None
     }
 }
 
+/**
+ * trait 不能实例化，只能混入到类里面：方法直接放进类的方法表
+ */
+#[derive(Clone, Debug)]
+pub struct LoxTrait {
+    pub name: expr::Symbol,
+    pub id: u64,
+    pub methods: HashMap<String, u64>,
+    pub required: Vec<String>, // 混入它的类必须提供的方法
+}
+
+/**
+ * 枚举：getattr 取出来的是变体的值（没有字段）或者变体的构造函数
+ */
+#[derive(Clone, Debug)]
+pub struct LoxEnum {
+    pub name: expr::Symbol,
+    pub id: u64,
+    pub variants: Vec<expr::EnumVariant>,
+}
+
+impl LoxEnum {
+    fn variant_name(&self, tag: usize) -> String {
+        format!("{}.{}", self.name.name, self.variants[tag].name.name)
+    }
+}
+
+/**
+ * 变体的构造函数，字段名就是参数名
+ */
+struct VariantConstructor {
+    enum_id: u64,
+    tag: usize,
+    fields: Vec<String>,
+}
+
+impl Callable for VariantConstructor {
+    fn arity(&self, _interpreter: &Interpreter) -> u8 {
+        self.fields.len().try_into().unwrap()
+    }
+    fn param_names(&self, _interpreter: &Interpreter) -> Vec<String> {
+        self.fields.clone()
+    }
+    fn call(&self, _interpreter: &mut Interpreter, args: &[Value]) -> Result<Value, String> {
+        Ok(Value::Variant(self.enum_id, self.tag, args.to_vec()))
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct LoxInstance {
     pub class_name: expr::Symbol,
@@ -264,20 +472,90 @@ impl LoxInstance {
     }
 }
 
+/* ---------- ---------- 生成器 ---------- ---------- */
+
+/**
+ * 生成器在 yield 的时候，从 yield 一路往外返回，每一层语句都记下自己执行到了哪里，
+ * 下一次 next() 的时候再按照这条路径走回 yield 的位置继续执行
+ */
+#[derive(Debug, Clone)]
+pub enum ResumePoint {
+    Block(usize), // 正在执行第几条语句
+    Branch(bool), // if 走的是哪个分支，条件不能再求值一次
+    Loop, // while / do-while 的循环体
+    ForIn(ForInCursor), // for-in 的循环体，以及迭代到了哪里
+    MatchArm(usize), // match 走的是第几个分支，模式不能再匹配一次
+    Yield,
+}
+
+/**
+ * for-in 的迭代状态：列表记下标，其他的都是迭代器
+ */
+#[derive(Debug, Clone)]
+pub enum ForInCursor {
+    List(/*id*/ u64, /*下一个下标*/ usize),
+    Iterator(Value),
+}
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum GeneratorState {
+    Suspended, // 还没开始，或者停在某个 yield 上
+    Running,
+    Done,
+}
+
+#[derive(Debug, Clone)]
+pub struct Generator {
+    pub function: LoxFunction,
+    pub env: Environment, // 暂停的时候最里层的环境
+    pub resume_path: Vec<ResumePoint>, // 从外往里依次 pop
+    pub state: GeneratorState,
+}
+
+/**
+ * 生成器上的方法，gen.next 取出来以后也可以单独调用
+ */
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum GeneratorMethod {
+    Next,
+    Iter,
+}
+
+impl Callable for (u64, GeneratorMethod) {
+    fn arity(&self, _interpreter: &Interpreter) -> u8 {
+        0
+    }
+    fn call(&self, interpreter: &mut Interpreter, _args: &[Value]) -> Result<Value, String> {
+        let (generator_id, method) = *self;
+        match method {
+            GeneratorMethod::Next => interpreter.resume_generator(generator_id),
+            GeneratorMethod::Iter => Ok(Value::Generator(generator_id)),
+        }
+    }
+}
+
 /**
  * value 是一个枚举类型
  */
 #[derive(Debug, Clone)]
 pub enum Value {
     Number(f64),
+    Integer(i64),
     String(String),
     Bool(bool),
     Nil,
     NativeFunction(NativeFunction), // rust 的函数
     LoxFunction(expr::Symbol, /*id*/ u64, /*this binding*/ Option<Box<Value>>),
     LoxClass(expr::Symbol, /*id*/ u64),
+    LoxTrait(expr::Symbol, /*id*/ u64),
     LoxInstance(expr::Symbol, /*id*/ u64),
     List(/*id*/ u64), // 列表的编号是多少？
+    Generator(/*id*/ u64),
+    GeneratorMethod(/*generator id*/ u64, GeneratorMethod),
+    LoxEnum(expr::Symbol, /*id*/ u64),
+    // 变体的值直接带着字段，和数字、字符串一样按值比较，创建以后不能修改
+    Variant(/*enum id*/ u64, /*tag*/ usize, Vec<Value>),
+    VariantConstructor(/*enum id*/ u64, /*tag*/ usize),
 }
 
 /**
@@ -293,93 +571,388 @@ fn as_callable(interpreter: &Interpreter, value: &Value) -> Option<Box<dyn Calla
             Some(Box::new(f_copy))
         }
         Value::LoxClass(_, id) => Some(Box::new(interpreter.get_lox_class(*id).clone())),
+        Value::GeneratorMethod(id, method) => Some(Box::new((*id, *method))),
+        Value::VariantConstructor(enum_id, tag) => {
+            let fields = interpreter.get_lox_enum(*enum_id).variants[*tag].fields
+                .iter()
+                .map(|field| field.name.clone())
+                .collect();
+            Some(Box::new(VariantConstructor { enum_id: *enum_id, tag: *tag, fields }))
+        }
+        // 定义了 __call__ 的实例也可以调用
+        Value::LoxInstance(_, _) =>
+            interpreter
+                .special_method(value, "__call__")
+                .and_then(|method| as_callable(interpreter, &method)),
         _ => None,
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Copy, Clone)]
-pub enum Type {
-    Number,
-    String,
-    Bool,
-    Nil,
-    NativeFunction,
-    LoxFunction,
-    LoxClass,
-    LoxInstance,
-    List,
+/**
+ * 可以省略参数的时候，报错信息里写成 1-2 这样
+ */
+fn format_arity(arity: u8, max_arity: u8) -> String {
+    if arity == max_arity { arity.to_string() } else { format!("{}-{}", arity, max_arity) }
 }
 
-pub fn type_of(val: &Value) -> Type {
+/* ---------- ---------- 列表相关的内置函数 ---------- ---------- */
+
+/**
+ * 取出列表参数的 id，不是列表就报错
+ */
+fn list_arg(name: &str, val: &Value) -> Result<u64, String> {
     match val {
-        Value::Number(_) => Type::Number,
-        Value::String(_) => Type::String,
-        Value::Bool(_) => Type::Bool,
-        Value::Nil => Type::Nil,
-        Value::NativeFunction(_) => Type::NativeFunction,
-        Value::LoxFunction(_, _, _) => Type::LoxFunction,
-        Value::LoxClass(_, _) => Type::LoxClass,
-        Value::LoxInstance(_, _) => Type::LoxInstance,
-        Value::List(_) => Type::List,
+        Value::List(list_id) => Ok(*list_id),
+        _ => Err(format!("Can't call {} on value of type {:?}.", name, type_of(val))),
     }
 }
 
-#[derive(Debug, Clone)]
-pub struct SourceLocation {
-    line: usize,
-    col: i64,
+/*
+ * push / pop / insert / remove 会修改列表，冻结了的列表不行
+ */
+fn mutable_list_arg(interpreter: &Interpreter, name: &str, val: &Value) -> Result<u64, String> {
+    let list_id = list_arg(name, val)?;
+    if interpreter.frozen.contains(&list_id) {
+        return Err(format!("Can't call {} on a frozen list.", name));
+    }
+    Ok(list_id)
 }
 
-/* ---------- ---------- environment ---------- ---------- */
-
-#[derive(Debug, Default, Clone)]
-pub struct Environment {
-    enclosing: Option<Box<Environment>>,
-    // SourceLocation is the location of a declaration
-    venv: HashMap<String, (Option<Value>, SourceLocation)>,
+fn callable_arg(
+    interpreter: &Interpreter,
+    name: &str,
+    position: &str,
+    val: &Value
+) -> Result<Box<dyn Callable>, String> {
+    match as_callable(interpreter, val) {
+        Some(callable) => Ok(callable),
+        None =>
+            Err(
+                format!(
+                    "The {} argument to {} must be callable. Found {:?}.",
+                    position,
+                    name,
+                    type_of(val)
+                )
+            ),
+    }
 }
 
 /**
- *  找到了
- *  未定义，但是声明了
- *  未定义、未声明
+ * 和下标访问一样，负数从右往左数；insert 的时候 index 可以等于长度
  */
-pub enum LookupResult<'a> {
-    Ok(&'a Value),
-    UndefButDeclared(SourceLocation),
-    UndefAndNotDeclared,
-}
-
-impl Environment {
-    /**
-     * retval -> enclosing，创建了一个指向 enclosing 的节点
-     */
-    pub fn with_enclosing(enclosing: Environment) -> Environment {
-        Environment {
-            enclosing: Some(Box::new(enclosing)), // 列表链接了起来，不过这里所有权从外部转移到了字段上
-            venv: HashMap::new(),
+fn list_index(name: &str, len: usize, val: &Value, allow_end: bool) -> Result<usize, String> {
+    let index = match val {
+        Value::Integer(n) => *n,
+        // 带小数的下标不会悄悄截断
+        Value::Number(n) if n.fract() == 0.0 => *n as i64,
+        Value::Number(n) => {
+            return Err(format!("Invalid index {} in {}.", n, name));
+        }
+        _ => {
+            return Err(format!("Invalid index of type {:?} in {}.", type_of(val), name));
         }
+    };
+    let len = len as i64;
+    let upper = if allow_end { len } else { len - 1 };
+    let index = if index < 0 { index + len } else { index };
+    if 0 <= index && index <= upper {
+        Ok(index as usize)
+    } else {
+        Err(format!("List index out of range in {}.", name))
     }
+}
 
-    /**
-     * 在当前节点的 hash 表上加入 item
-     */
-    pub fn define(&mut self, sym: expr::Symbol, maybe_val: Option<Value>) {
-        self.venv.insert(sym.name, (
-            maybe_val,
-            SourceLocation {
-                line: sym.line,
-                col: sym.col,
-            },
-        ));
+fn list_reduce(interpreter: &mut Interpreter, values: &[Value]) -> Result<Value, String> {
+    let list_id = list_arg("reduce", &values[1])?;
+    let callable = callable_arg(interpreter, "reduce", "first", &values[0])?;
+    let elts = interpreter.get_list_elts(list_id).clone();
+    let mut acc = values[2].clone();
+    for elt in elts {
+        acc = callable.call(interpreter, &[acc, elt])?;
     }
+    Ok(acc)
+}
 
-    /**
-     * 查看变量声明、定义的情况
-     */
-    pub fn lookup(&self, sym: &expr::Symbol) -> LookupResult {
-        match self.venv.get(&sym.name) {
-            Some((maybe_val, defn_source_location)) =>
+/**
+ * sort(xs) 或者 sort(xs, cmp)，返回一个新的列表；cmp(a, b) 返回负数表示 a 排在 b 前面
+ */
+fn list_sort(interpreter: &mut Interpreter, values: &[Value]) -> Result<Value, String> {
+    let list_id = list_arg("sort", &values[0])?;
+    let elts = interpreter.get_list_elts(list_id).clone();
+    let sorted = match values.get(1) {
+        Some(comparator) => {
+            let callable = callable_arg(interpreter, "sort", "second", comparator)?;
+            builtins::merge_sort(elts, &mut (|a: &Value, b: &Value| {
+                match callable.call(interpreter, &[a.clone(), b.clone()])? {
+                    Value::Number(n) => Ok(n <= 0.0),
+                    Value::Integer(n) => Ok(n <= 0),
+                    val =>
+                        Err(
+                            format!(
+                                "The comparator passed to sort must return a number. Found {:?}.",
+                                type_of(&val)
+                            )
+                        ),
+                }
+            }))?
+        }
+        None =>
+            builtins::merge_sort(elts, &mut (|a: &Value, b: &Value| {
+                match (a, b) {
+                    (Value::Number(n1), Value::Number(n2)) => Ok(n1 <= n2),
+                    (Value::Integer(n1), Value::Integer(n2)) => Ok(n1 <= n2),
+                    (Value::Integer(n1), Value::Number(n2)) => Ok((*n1 as f64) <= *n2),
+                    (Value::Number(n1), Value::Integer(n2)) => Ok(*n1 <= (*n2 as f64)),
+                    (Value::String(s1), Value::String(s2)) => Ok(s1 <= s2),
+                    _ =>
+                        Err(
+                            format!(
+                                "Can't compare values of type {:?} and {:?} in sort.",
+                                type_of(a),
+                                type_of(b)
+                            )
+                        ),
+                }
+            }))?,
+    };
+    Ok(interpreter.create_list(sorted))
+}
+
+fn list_reverse(interpreter: &mut Interpreter, values: &[Value]) -> Result<Value, String> {
+    let list_id = list_arg("reverse", &values[0])?;
+    let mut elts = interpreter.get_list_elts(list_id).clone();
+    elts.reverse();
+    Ok(interpreter.create_list(elts))
+}
+
+fn list_push(interpreter: &mut Interpreter, values: &[Value]) -> Result<Value, String> {
+    let list_id = mutable_list_arg(interpreter, "push", &values[0])?;
+    interpreter.get_list_elts_mut(list_id).push(values[1].clone());
+    Ok(Value::Nil)
+}
+
+fn list_pop(interpreter: &mut Interpreter, values: &[Value]) -> Result<Value, String> {
+    let list_id = mutable_list_arg(interpreter, "pop", &values[0])?;
+    match interpreter.get_list_elts_mut(list_id).pop() {
+        Some(val) => Ok(val),
+        None => Err("Can't pop from an empty list.".to_string()),
+    }
+}
+
+fn list_insert(interpreter: &mut Interpreter, values: &[Value]) -> Result<Value, String> {
+    let list_id = mutable_list_arg(interpreter, "insert", &values[0])?;
+    let elts = interpreter.get_list_elts_mut(list_id);
+    let index = list_index("insert", elts.len(), &values[1], true)?;
+    elts.insert(index, values[2].clone());
+    Ok(Value::Nil)
+}
+
+fn list_remove(interpreter: &mut Interpreter, values: &[Value]) -> Result<Value, String> {
+    let list_id = mutable_list_arg(interpreter, "remove", &values[0])?;
+    let elts = interpreter.get_list_elts_mut(list_id);
+    let index = list_index("remove", elts.len(), &values[1], false)?;
+    Ok(elts.remove(index))
+}
+
+/*
+ * freeze(obj)：以后不能再修改实例的字段或者列表的元素，返回 obj 本身
+ */
+fn freeze(interpreter: &mut Interpreter, values: &[Value]) -> Result<Value, String> {
+    match &values[0] {
+        Value::LoxInstance(_, id) | Value::List(id) => {
+            interpreter.frozen.insert(*id);
+            Ok(values[0].clone())
+        }
+        val => Err(format!("Can't freeze a value of type {:?}.", type_of(val))),
+    }
+}
+
+/*
+ * tag(v)：变体的名字，比如 Shape.Circle(1) 的 tag 是 'Circle'
+ */
+fn variant_tag(interpreter: &mut Interpreter, values: &[Value]) -> Result<Value, String> {
+    match &values[0] {
+        Value::Variant(enum_id, tag, _) =>
+            Ok(Value::String(interpreter.get_lox_enum(*enum_id).variants[*tag].name.name.clone())),
+        val => Err(format!("Can't get the tag of a value of type {:?}.", type_of(val))),
+    }
+}
+
+/*
+ * json.parse / json.stringify，和字节码解释器的行为一致：JSON 对象变成 JsonObject 的实例，实例的字段按名字排序输出
+ */
+fn json_parse(interpreter: &mut Interpreter, values: &[Value]) -> Result<Value, String> {
+    let parsed = match &values[0] {
+        // json.rs 的错误信息不带句号
+        Value::String(text) => json::parse(text).map_err(|err| format!("{}.", err))?,
+        val => {
+            return Err(format!("Expected a JSON string, got {:?}.", type_of(val)));
+        }
+    };
+    let class = match interpreter.prelude.get("JsonObject") {
+        Some(Value::LoxClass(sym, class_id)) => (sym.clone(), *class_id),
+        _ => panic!("Internal interpreter error: JsonObject is not defined in the prelude."),
+    };
+    Ok(interpreter.json_to_value(parsed, &class))
+}
+
+fn json_stringify(interpreter: &mut Interpreter, values: &[Value]) -> Result<Value, String> {
+    let indent = match values.get(1) {
+        None | Some(Value::Nil) => String::new(),
+        Some(Value::Integer(n)) if *n >= 0 => " ".repeat(*n as usize),
+        Some(Value::String(indent)) => indent.clone(),
+        Some(val) => {
+            return Err(
+                format!(
+                    "Expected the indent to be a number of spaces or a string, got {:?}.",
+                    type_of(val)
+                )
+            );
+        }
+    };
+    let converted = interpreter.value_to_json(&values[0], &mut HashSet::new())?;
+    let text = json::stringify(&converted, &indent).map_err(|err| format!("{}.", err))?;
+    Ok(Value::String(text))
+}
+
+fn list_contains(interpreter: &mut Interpreter, values: &[Value]) -> Result<Value, String> {
+    let list_id = list_arg("contains", &values[0])?;
+    let found = interpreter
+        .get_list_elts(list_id)
+        .iter()
+        .any(|elt| Interpreter::equals(elt, &values[1]));
+    Ok(Value::Bool(found))
+}
+
+fn list_index_of(interpreter: &mut Interpreter, values: &[Value]) -> Result<Value, String> {
+    let list_id = list_arg("indexOf", &values[0])?;
+    let index = interpreter
+        .get_list_elts(list_id)
+        .iter()
+        .position(|elt| Interpreter::equals(elt, &values[1]));
+    Ok(Value::Integer(index.map_or(-1, |index| index as i64)))
+}
+
+fn list_concat(interpreter: &mut Interpreter, values: &[Value]) -> Result<Value, String> {
+    let list_id1 = list_arg("concat", &values[0])?;
+    let list_id2 = list_arg("concat", &values[1])?;
+    let mut elts = interpreter.get_list_elts(list_id1).clone();
+    elts.extend(interpreter.get_list_elts(list_id2).iter().cloned());
+    Ok(interpreter.create_list(elts))
+}
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum Type {
+    Number,
+    Integer,
+    String,
+    Bool,
+    Nil,
+    NativeFunction,
+    LoxFunction,
+    LoxClass,
+    LoxTrait,
+    LoxInstance,
+    List,
+    Generator,
+    LoxEnum,
+    Variant,
+}
+
+pub fn type_of(val: &Value) -> Type {
+    match val {
+        Value::Number(_) => Type::Number,
+        Value::Integer(_) => Type::Integer,
+        Value::String(_) => Type::String,
+        Value::Bool(_) => Type::Bool,
+        Value::Nil => Type::Nil,
+        Value::NativeFunction(_) => Type::NativeFunction,
+        Value::LoxFunction(_, _, _) => Type::LoxFunction,
+        Value::LoxClass(_, _) => Type::LoxClass,
+        Value::LoxTrait(_, _) => Type::LoxTrait,
+        Value::LoxInstance(_, _) => Type::LoxInstance,
+        Value::List(_) => Type::List,
+        Value::Generator(_) => Type::Generator,
+        Value::GeneratorMethod(_, _) => Type::NativeFunction,
+        Value::LoxEnum(_, _) => Type::LoxEnum,
+        Value::Variant(_, _, _) => Type::Variant,
+        Value::VariantConstructor(_, _) => Type::NativeFunction,
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SourceLocation {
+    line: usize,
+    col: i64,
+}
+
+/* ---------- ---------- environment ---------- ---------- */
+
+#[derive(Debug, Default, Clone)]
+pub struct Environment {
+    enclosing: Option<Box<Environment>>,
+    // SourceLocation is the location of a declaration
+    venv: HashMap<String, (Option<Value>, SourceLocation)>,
+    consts: HashSet<String>, // const / let 定义的名字，不能再赋值
+}
+
+/**
+ *  找到了
+ *  未定义，但是声明了
+ *  未定义、未声明
+ */
+pub enum LookupResult<'a> {
+    Ok(&'a Value),
+    UndefButDeclared(SourceLocation),
+    UndefAndNotDeclared,
+}
+
+impl Environment {
+    /**
+     * retval -> enclosing，创建了一个指向 enclosing 的节点
+     */
+    pub fn with_enclosing(enclosing: Environment) -> Environment {
+        Environment {
+            enclosing: Some(Box::new(enclosing)), // 列表链接了起来，不过这里所有权从外部转移到了字段上
+            venv: HashMap::new(),
+            consts: HashSet::new(),
+        }
+    }
+
+    /**
+     * 在当前节点的 hash 表上加入 item
+     */
+    pub fn define(&mut self, sym: expr::Symbol, maybe_val: Option<Value>) {
+        self.consts.remove(&sym.name);
+        self.venv.insert(sym.name, (
+            maybe_val,
+            SourceLocation {
+                line: sym.line,
+                col: sym.col,
+            },
+        ));
+    }
+
+    pub fn define_const(&mut self, sym: expr::Symbol, val: Value) {
+        let name = sym.name.clone();
+        self.define(sym, Some(val));
+        self.consts.insert(name);
+    }
+
+    /**
+     * 只看当前这一层，外层的常量可以被里层的同名变量遮住
+     */
+    pub fn is_const(&self, name: &str) -> bool {
+        self.consts.contains(name)
+    }
+
+    /**
+     * 查看变量声明、定义的情况
+     */
+    pub fn lookup(&self, sym: &expr::Symbol) -> LookupResult<'_> {
+        match self.venv.get(&sym.name) {
+            Some((maybe_val, defn_source_location)) =>
                 match maybe_val {
                     Some(val) => LookupResult::Ok(val),
                     None =>
@@ -434,6 +1007,16 @@ impl Environment {
      */
     pub fn assign(&mut self, sym: expr::Symbol, val: &Value) -> Result<(), String> {
         if self.venv.contains_key(&sym.name) {
+            if self.consts.contains(&sym.name) {
+                return Err(
+                    format!(
+                        "Cannot assign to constant {} at line={},col={}.",
+                        sym.name,
+                        sym.line,
+                        sym.col
+                    )
+                );
+            }
             self.define(sym, Some(val.clone()));
             return Ok(());
         }
@@ -454,25 +1037,44 @@ impl Environment {
 
 /* ---------- ---------- Interpreter 默认构造 ---------- ---------- */
 
+/**
+ * break / continue 和 retval 一样：设置以后剩下的语句都不执行，直到被外层的循环接住
+ */
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum LoopSignal {
+    Break,
+    Continue,
+}
+
 pub struct Interpreter {
     pub counter: u64, // 用来生成 id
     pub lambda_counter: u64, // 专门管理 匿名函数的 id
     pub lox_functions: HashMap<u64, LoxFunction>,
     pub lox_instances: HashMap<u64, LoxInstance>, // 对象的 id，对象中有 class_id
     pub lox_classes: HashMap<u64, LoxClass>,
+    pub lox_traits: HashMap<u64, LoxTrait>,
+    pub lox_enums: HashMap<u64, LoxEnum>,
     pub lists: HashMap<u64, Vec<Value>>, // 列表对象 id 与映射
+    pub frozen: HashSet<u64>, // freeze 过的实例和列表，id 不会重复使用
     pub env: Environment, // 用来存储当前作用于的 环境与变量
     pub globals: Environment,
     pub retval: Option<Value>, // 用来存储函数调用以后的返回值，直到下一个函数覆盖它
+    pub tail_call: Option<TailCall>, // return f(...) 还没做的调用，retval 这时候只是个占位
+    pub loop_signal: Option<LoopSignal>, // 执行了 break / continue，还没有回到循环
+    pub generators: HashMap<u64, Generator>,
+    pub yielded: Option<Value>, // 执行了 yield，正在往外退到生成器的入口
+    pub suspend_path: Vec<ResumePoint>, // 退出去的时候记下来的路径，最里层的在前面
+    pub resume_path: Vec<ResumePoint>, // 恢复执行的时候还没有走完的路径，最外层的在后面
+    pub prelude: HashMap<String, Value>, // prelude 里面定义的惰性 map / filter，用户覆盖同名变量也不受影响
     pub output: Vec<String>, // 用来存储输出，例如 print 之类的
     pub enclosing_function: Option<u64>, // 正在处理的函数的 id
     pub interrupted: Arc<AtomicBool>, // 当前解释的任务是否要中断
-    pub backtrace: Vec<(u64, String)>, // 用来存储函数调用的 回溯信息
+    pub backtrace: Vec<(/*尾调用省略的帧数*/ u64, String)>, // 用来存储函数调用的 回溯信息
 }
 
 impl Default for Interpreter {
     /**
-     * 添加一些内置的函数 clock、len、iota、foreach、map
+     * 添加一些内置的函数 clock、len、forEach、map 以及列表相关的函数
      */
     fn default() -> Interpreter {
         /* ---------- 获取时间 ---------- */
@@ -482,6 +1084,7 @@ impl Default for Interpreter {
                 Value::NativeFunction(NativeFunction {
                     name: String::from("clock"),
                     arity: 0,
+                    optional_args: 0,
                     callable: |_, _| {
                         let start = SystemTime::now();
                         let since_the_epoch = start.duration_since(UNIX_EPOCH).unwrap();
@@ -502,12 +1105,16 @@ impl Default for Interpreter {
                 Value::NativeFunction(NativeFunction {
                     name: String::from("len"),
                     arity: 1,
+                    optional_args: 0,
                     callable: |interp, values| {
+                        if let Some(method) = interp.special_method(&values[0], "__len__") {
+                            return interp.call_special_method(method, &[]);
+                        }
                         match &values[0] {
-                            Value::String(s) => Ok(Value::Number(s.len() as f64)),
+                            Value::String(s) => Ok(Value::Integer(s.chars().count() as i64)),
                             Value::List(list_id) => {
                                 let elts = interp.get_list_elts(*list_id);
-                                Ok(Value::Number(elts.len() as f64))
+                                Ok(Value::Integer(elts.len() as i64))
                             }
                             val => Err(format!("Object of type {:?} has no len.", type_of(val))),
                         }
@@ -520,35 +1127,15 @@ impl Default for Interpreter {
             },
         ));
 
-        /* ---------- 用于生成一个数字序列。(1, 5) 那么就是 [1, 2, 3, 4] ---------- */
-        globals_venv.insert(String::from("iota"), (
+        /* ---------- 判断对象是不是某个类（或者它的子类）的实例 ---------- */
+        globals_venv.insert(String::from("isInstance"), (
             Some(
                 Value::NativeFunction(NativeFunction {
-                    name: String::from("iota"),
+                    name: String::from("isInstance"),
                     arity: 2,
-                    callable: |interpreter, values| {
-                        match (&values[0], &values[1]) {
-                            (Value::Number(low), Value::Number(high)) => {
-                                let elts: Vec<_> = (*low as i64..*high as i64)
-                                    .map(|x| Value::Number(x as f64))
-                                    .collect();
-                                Ok(interpreter.create_list(elts))
-                            }
-                            (Value::Number(_), high) =>
-                                Err(
-                                    format!(
-                                        "invalid high argument of type {:?} in iota expression.",
-                                        type_of(high)
-                                    )
-                                ),
-                            (low, _) =>
-                                Err(
-                                    format!(
-                                        "invalid low argument of type {:?} in iota expression.",
-                                        type_of(low)
-                                    )
-                                ),
-                        }
+                    optional_args: 0,
+                    callable: |interp, values| {
+                        interp.is_instance(&values[0], &values[1]).map(Value::Bool)
                     },
                 })
             ),
@@ -564,6 +1151,7 @@ impl Default for Interpreter {
                 Value::NativeFunction(NativeFunction {
                     name: String::from("forEach"),
                     arity: 2,
+                    optional_args: 0,
                     callable: |interpreter, values| {
                         match &values[0] {
                             Value::List(list_id) => {
@@ -609,6 +1197,7 @@ impl Default for Interpreter {
                 Value::NativeFunction(NativeFunction {
                     name: String::from("map"),
                     arity: 2,
+                    optional_args: 0,
                     callable: |interpreter, values| {
                         match &values[1] {
                             Value::List(list_id) => {
@@ -619,7 +1208,7 @@ impl Default for Interpreter {
                                         let elts = interpreter.get_list_elts(*list_id).clone();
                                         for elt in elts {
                                             res_elts.push(
-                                                callable.call(interpreter, &[elt.clone()])?
+                                                callable.call(interpreter, std::slice::from_ref(&elt))?
                                             );
                                         }
                                         Ok(interpreter.create_list(res_elts))
@@ -633,6 +1222,9 @@ impl Default for Interpreter {
                                         ),
                                 }
                             }
+                            // 其他可以迭代的值：返回一个惰性的生成器
+                            Value::Generator(_) | Value::LoxInstance(_, _) =>
+                                interpreter.call_prelude("lazyMap", values),
                             val =>
                                 Err(
                                     format!(
@@ -650,26 +1242,146 @@ impl Default for Interpreter {
             },
         ));
 
+        /* ---------- 过滤列表的元素，和 map 一样，对迭代器是惰性的 ---------- */
+        globals_venv.insert(String::from("filter"), (
+            Some(
+                Value::NativeFunction(NativeFunction {
+                    name: String::from("filter"),
+                    arity: 2,
+                    optional_args: 0,
+                    callable: |interpreter, values| {
+                        match &values[1] {
+                            Value::List(list_id) => {
+                                let callable = match as_callable(interpreter, &values[0]) {
+                                    Some(callable) => callable,
+                                    None => {
+                                        return Err(
+                                            format!(
+                                                "The first argument to filter must be callable. Found {:?}.",
+                                                type_of(&values[0])
+                                            )
+                                        );
+                                    }
+                                };
+                                let mut res_elts = Vec::new();
+                                let elts = interpreter.get_list_elts(*list_id).clone();
+                                for elt in elts {
+                                    let keep = callable.call(interpreter, std::slice::from_ref(&elt))?;
+                                    if Interpreter::is_truthy(&keep) {
+                                        res_elts.push(elt);
+                                    }
+                                }
+                                Ok(interpreter.create_list(res_elts))
+                            }
+                            Value::Generator(_) | Value::LoxInstance(_, _) =>
+                                interpreter.call_prelude("lazyFilter", values),
+                            val =>
+                                Err(
+                                    format!("Can't call filter on value of type {:?}.", type_of(val))
+                                ),
+                        }
+                    },
+                })
+            ),
+            SourceLocation {
+                line: 1337,
+                col: 1337,
+            },
+        ));
+
+        /* ---------- 列表相关的内置函数，报错信息和字节码解释器保持一致 ---------- */
+        let list_builtins: [(&str, u8, u8, NativeCallable); 10] = [
+            ("reduce", 3, 0, list_reduce),
+            ("sort", 1, 1, list_sort),
+            ("reverse", 1, 0, list_reverse),
+            ("push", 2, 0, list_push),
+            ("pop", 1, 0, list_pop),
+            ("insert", 3, 0, list_insert),
+            ("remove", 2, 0, list_remove),
+            ("contains", 2, 0, list_contains),
+            ("indexOf", 2, 0, list_index_of),
+            ("concat", 2, 0, list_concat),
+        ];
+        for (name, arity, optional_args, callable) in list_builtins {
+            globals_venv.insert(String::from(name), (
+                Some(
+                    Value::NativeFunction(NativeFunction {
+                        name: String::from(name),
+                        arity,
+                        optional_args,
+                        callable,
+                    })
+                ),
+                SourceLocation {
+                    line: 1337,
+                    col: 1337,
+                },
+            ));
+        }
+
+        globals_venv.insert(String::from("tag"), (
+            Some(
+                Value::NativeFunction(NativeFunction {
+                    name: String::from("tag"),
+                    arity: 1,
+                    optional_args: 0,
+                    callable: variant_tag,
+                })
+            ),
+            SourceLocation {
+                line: 1337,
+                col: 1337,
+            },
+        ));
+        globals_venv.insert(String::from("freeze"), (
+            Some(
+                Value::NativeFunction(NativeFunction {
+                    name: String::from("freeze"),
+                    arity: 1,
+                    optional_args: 0,
+                    callable: freeze,
+                })
+            ),
+            SourceLocation {
+                line: 1337,
+                col: 1337,
+            },
+        ));
+
         let globals = Environment {
             enclosing: None,
             venv: globals_venv, // variable environment，存放：(String, Option<Value>)
+            consts: HashSet::new(),
         };
 
-        Interpreter {
+        let mut interpreter = Interpreter {
             counter: 0,
             lambda_counter: 0,
             lox_functions: Default::default(),
             lox_instances: Default::default(),
             lox_classes: Default::default(),
+            lox_traits: Default::default(),
+            lox_enums: Default::default(),
             lists: Default::default(),
+            frozen: Default::default(),
             env: Default::default(),
             globals,
             retval: None,
+            tail_call: None,
+            loop_signal: None,
+            generators: Default::default(),
+            yielded: None,
+            suspend_path: Vec::new(),
+            resume_path: Vec::new(),
+            prelude: Default::default(),
             output: Default::default(),
             enclosing_function: None,
             interrupted: Arc::new(AtomicBool::new(false)),
             backtrace: vec![(0, "script".to_string())],
-        }
+        };
+        interpreter.load_prelude();
+        interpreter.define_json();
+        interpreter
     }
 }
 
@@ -683,6 +1395,138 @@ impl Interpreter {
         Ok(())
     }
 
+    /**
+     * 执行 prelude，导出的名字放到全局变量里，剩下的留给内置函数用
+     */
+    fn load_prelude(&mut self) {
+        let tokens = scanner::scan_tokens(prelude::SOURCE.to_string()).unwrap();
+        let stmts = match parser::parse(extensions::Extensions::default(), tokens) {
+            Ok(stmts) => stmts,
+            Err(err) => panic!("Internal interpreter error: could not parse prelude: {:?}", err),
+        };
+        if let Err(err) = self.interpret(&stmts) {
+            panic!("Internal interpreter error: could not run prelude: {}", err);
+        }
+
+        for (name, (maybe_val, source_location)) in std::mem::take(&mut self.env.venv) {
+            if prelude::EXPORTS.contains(&name.as_str()) {
+                self.globals.venv.insert(name, (maybe_val, source_location));
+            } else if let Some(val) = maybe_val {
+                self.prelude.insert(name, val);
+            }
+        }
+    }
+
+    /**
+     * prelude 里的 json 是个空的实例，把 parse / stringify 放到它的字段上，然后冻结
+     */
+    fn define_json(&mut self) {
+        let json_id = match self.globals.venv.get("json") {
+            Some((Some(Value::LoxInstance(_, json_id)), _)) => *json_id,
+            _ => panic!("Internal interpreter error: json is not defined in the prelude."),
+        };
+        let natives: [(&str, u8, u8, NativeCallable); 2] = [
+            ("parse", 1, 0, json_parse),
+            ("stringify", 1, 1, json_stringify),
+        ];
+        for (name, arity, optional_args, callable) in natives {
+            let native = Value::NativeFunction(NativeFunction {
+                name: format!("json.{}", name),
+                arity,
+                optional_args,
+                callable,
+            });
+            self.get_lox_instance_mut(json_id).fields.insert(name.to_string(), native);
+        }
+        self.frozen.insert(json_id);
+    }
+
+    /**
+     * path 是从最外层到当前位置的列表和实例，用来发现环，也限制了嵌套的深度
+     */
+    fn value_to_json(&self, val: &Value, path: &mut HashSet<u64>) -> Result<json::Json, String> {
+        match val {
+            Value::Nil => Ok(json::Json::Null),
+            Value::Bool(b) => Ok(json::Json::Bool(*b)),
+            Value::Integer(n) => Ok(json::Json::Integer(*n)),
+            Value::Number(n) => Ok(json::Json::Number(*n)),
+            Value::String(s) => Ok(json::Json::String(s.clone())),
+            Value::List(id) | Value::LoxInstance(_, id) => {
+                if path.len() == json::MAX_DEPTH {
+                    return Err(format!("{}.", json::too_deep()));
+                }
+                if !path.insert(*id) {
+                    return Err("Cannot convert a cyclic structure to JSON.".to_string());
+                }
+                let res = match val {
+                    Value::List(_) =>
+                        json::Json::List(
+                            self
+                                .get_list_elts(*id)
+                                .iter()
+                                .map(|elt| self.value_to_json(elt, path))
+                                .collect::<Result<Vec<json::Json>, String>>()?
+                        ),
+                    _ => {
+                        let mut fields: Vec<(&String, &Value)> = self
+                            .get_lox_instance(*id)
+                            .fields.iter()
+                            .collect();
+                        fields.sort_by_key(|(name, _)| *name);
+                        json::Json::Object(
+                            fields
+                                .into_iter()
+                                .map(|(name, field)| Ok((name.clone(), self.value_to_json(field, path)?)))
+                                .collect::<Result<Vec<(String, json::Json)>, String>>()?
+                        )
+                    }
+                };
+                path.remove(id);
+                Ok(res)
+            }
+            _ => Err(format!("Cannot convert a value of type {:?} to JSON.", type_of(val))),
+        }
+    }
+
+    fn json_to_value(&mut self, parsed: json::Json, class: &(expr::Symbol, u64)) -> Value {
+        match parsed {
+            json::Json::Null => Value::Nil,
+            json::Json::Bool(b) => Value::Bool(b),
+            json::Json::Integer(n) => Value::Integer(n),
+            json::Json::Number(n) => Value::Number(n),
+            json::Json::String(s) => Value::String(s),
+            json::Json::List(elts) => {
+                let elts = elts
+                    .into_iter()
+                    .map(|elt| self.json_to_value(elt, class))
+                    .collect();
+                self.create_list(elts)
+            }
+            json::Json::Object(entries) => {
+                let fields: Vec<(String, Value)> = entries
+                    .into_iter()
+                    .map(|(name, field)| (name, self.json_to_value(field, class)))
+                    .collect();
+                let instance = self.create_instance(&class.0, class.1);
+                if let Value::LoxInstance(_, id) = &instance {
+                    self.get_lox_instance_mut(*id).fields.extend(fields);
+                }
+                instance
+            }
+        }
+    }
+
+    fn call_prelude(&mut self, name: &str, args: &[Value]) -> Result<Value, String> {
+        let func = match self.prelude.get(name) {
+            Some(func) => func.clone(),
+            None => panic!("Internal interpreter error: {} is not defined in the prelude.", name),
+        };
+        match as_callable(self, &func) {
+            Some(callable) => callable.call(self, args),
+            None => panic!("Internal interpreter error: {} in the prelude is not callable.", name),
+        }
+    }
+
     pub fn get_lox_function(&self, id: u64) -> &LoxFunction {
         match self.lox_functions.get(&id) {
             Some(func) => func,
@@ -697,6 +1541,20 @@ impl Interpreter {
         }
     }
 
+    pub fn get_lox_trait(&self, id: u64) -> &LoxTrait {
+        match self.lox_traits.get(&id) {
+            Some(lox_trait) => lox_trait,
+            None => panic!("Internal interpreter error! couldn't find trait with id {}.", id),
+        }
+    }
+
+    pub fn get_lox_enum(&self, id: u64) -> &LoxEnum {
+        match self.lox_enums.get(&id) {
+            Some(lox_enum) => lox_enum,
+            None => panic!("Internal interpreter error! couldn't find enum with id {}.", id),
+        }
+    }
+
     pub fn get_lox_instance(&self, id: u64) -> &LoxInstance {
         match self.lox_instances.get(&id) {
             Some(inst) => inst,
@@ -705,10 +1563,23 @@ impl Interpreter {
         }
     }
 
+    fn get_lox_instance_mut(&mut self, id: u64) -> &mut LoxInstance {
+        match self.lox_instances.get_mut(&id) {
+            Some(inst) => inst,
+            None =>
+                panic!("Internal interpreter error: could not find an instance with id {}.", id),
+        }
+    }
+
     pub fn format_backtrace(&self) -> String {
         let lines: Vec<_> = self.backtrace
             .iter()
-            .map(|(_, funname)| format!("[line ??] in {}", funname))
+            .flat_map(|(elided, funname)| {
+                let elided = (*elided > 0).then(|| {
+                    format!("... {} frames elided by tail calls ...", elided)
+                });
+                elided.into_iter().chain(std::iter::once(format!("[line ??] in {}", funname)))
+            })
             .collect();
         format!("Backtrace (most recent call last):\n\n{}", lines.join("\n"))
     }
@@ -735,6 +1606,18 @@ impl Interpreter {
         }
     }
 
+    /**
+     * 同一个环境里面的常量不能再定义一次，顶层定义的常量也就不会被覆盖
+     */
+    fn check_redefinition(&self, sym: &expr::Symbol) -> Result<(), String> {
+        if self.env.is_const(&sym.name) {
+            return Err(
+                format!("Cannot redefine constant {} at line={},col={}.", sym.name, sym.line, sym.col)
+            );
+        }
+        Ok(())
+    }
+
     /**
      * 分配一个 id
      */
@@ -769,171 +1652,715 @@ impl Interpreter {
     }
 
     /**
-     * 这个 execute 会递归的调用自己
+     * 这个 execute 会递归的调用自己
+     */
+    fn execute(&mut self, stmt: &expr::Stmt) -> Result<(), String> {
+        if self.retval.is_some() || self.loop_signal.is_some() || self.yielded.is_some() {
+            // 如果已经有返回值了，或者要跳出 / 跳过循环，或者生成器暂停了，返回 ok
+            return Ok(());
+        }
+
+        match stmt {
+            // 解释表达式
+            expr::Stmt::Expr(e) =>
+                match self.interpret_expr(e) {
+                    Ok(_) => Ok(()), // statement 的返回值就是 ()
+                    Err(err) => Err(err),
+                }
+
+            // class 声明
+            expr::Stmt::ClassDecl( // expr::Stmt::ClassDecl(expr::ClassDecl)
+                expr::ClassDecl {
+                    name: sym,
+                    superclass: maybe_superclass,
+                    traits: stmt_traits,
+                    methods: stmt_methods,
+                    class_methods: stmt_class_methods,
+                    getters: stmt_getters,
+                    setters: stmt_setters,
+                    ..
+                },
+            ) => {
+                self.check_redefinition(sym)?;
+                let class_id = self.alloc_id();
+                self.env.define(sym.clone(), Some(Value::LoxClass(sym.clone(), class_id)));
+
+                let superclass_id = if let Some(superclass_var) = maybe_superclass {
+                    // 不能自己继承自己（这会让类爆炸）
+                    if superclass_var.name == sym.name {
+                        return Err(
+                            format!(
+                                "A class cannot inerit from itself (line={}, col={})",
+                                sym.line,
+                                sym.col
+                            )
+                        );
+                    }
+
+                    let superclass_val = self.interpret_expr(
+                        &expr::Expr::Variable(superclass_var.clone())
+                    )?;
+                    if let Value::LoxClass(_, id) = superclass_val {
+                        Some(id)
+                    } else {
+                        return Err(
+                            format!(
+                                "Only classes should appear as superclasses. Found {:?}.",
+                                type_of(&superclass_val)
+                            )
+                        );
+                    }
+                } else {
+                    None
+                };
+
+                let mut methods = self.define_methods(stmt_methods, superclass_id, true);
+                let traits = self.mix_in_traits(sym, stmt_traits, superclass_id, &mut methods)?;
+
+                let cls = LoxClass {
+                    name: sym.clone(),
+                    superclass: superclass_id,
+                    id: class_id,
+                    methods,
+                    class_methods: self.define_methods(stmt_class_methods, superclass_id, false),
+                    getters: self.define_methods(stmt_getters, superclass_id, false),
+                    setters: self.define_methods(stmt_setters, superclass_id, false),
+                    traits,
+                };
+
+                self.lox_classes.insert(class_id, cls);
+                Ok(())
+            }
+            expr::Stmt::EnumDecl(expr::EnumDecl { name, variants }) => {
+                self.check_redefinition(name)?;
+                let enum_id = self.alloc_id();
+                self.env.define(name.clone(), Some(Value::LoxEnum(name.clone(), enum_id)));
+                self.lox_enums.insert(enum_id, LoxEnum {
+                    name: name.clone(),
+                    id: enum_id,
+                    variants: variants.clone(),
+                });
+                Ok(())
+            }
+            expr::Stmt::TraitDecl(expr::TraitDecl { name, methods, required }) => {
+                self.check_redefinition(name)?;
+                let trait_id = self.alloc_id();
+                self.env.define(name.clone(), Some(Value::LoxTrait(name.clone(), trait_id)));
+
+                let lox_trait = LoxTrait {
+                    name: name.clone(),
+                    id: trait_id,
+                    // 混入以后 init 就是类的初始化函数
+                    methods: self.define_methods(methods, None, true),
+                    required: required
+                        .iter()
+                        .map(|sym| sym.name.clone())
+                        .collect(),
+                };
+
+                self.lox_traits.insert(trait_id, lox_trait);
+                Ok(())
+            }
+            expr::Stmt::FunDecl(
+                expr::FunDecl { name, params: parameters, defaults, rest, body, is_generator, .. },
+            ) => {
+                self.check_redefinition(name)?;
+                let func_id = self.alloc_id();
+                self.env.define(
+                    name.clone(),
+                    Some(Value::LoxFunction(name.clone(), func_id, None))
+                );
+
+                let lox_function = LoxFunction {
+                    id: func_id,
+                    name: name.clone(),
+                    parameters: parameters.clone(),
+                    defaults: defaults.clone(),
+                    rest: rest.clone(),
+                    body: body.clone(),
+                    closure: self.env.clone(),
+                    this_binding: None,
+                    superclass: None,
+                    is_initializer: false,
+                    is_generator: *is_generator,
+                };
+
+                self.lox_functions.insert(func_id, lox_function);
+
+                Ok(())
+            }
+            expr::Stmt::If(cond, if_true, maybe_if_false) => {
+                let branch = match self.take_resume_point() {
+                    Some(ResumePoint::Branch(branch)) => branch,
+                    None => Interpreter::is_truthy(&self.interpret_expr(cond)?),
+                    Some(point) => Interpreter::bad_resume_point(&point, stmt),
+                };
+                if branch {
+                    self.execute(if_true)?;
+                } else if let Some(if_false) = maybe_if_false {
+                    self.execute(if_false)?;
+                }
+                if self.yielded.is_some() {
+                    self.suspend_path.push(ResumePoint::Branch(branch));
+                }
+                Ok(())
+            }
+            expr::Stmt::Print(e) =>
+                // execute --> interpret_expr
+                match self.interpret_expr(e) {
+                    Ok(val) => {
+                        let output = self.stringify(&val)?;
+                        println!("{}", output);
+                        self.output.push(output);
+                        Ok(())
+                    }
+                    Err(err) => Err(err),
+                }
+            expr::Stmt::VarDecl(sym, maybe_expr) => {
+                let maybe_val = match maybe_expr {
+                    Some(expr) => Some(self.interpret_expr(expr)?),
+                    None => None,
+                };
+                self.check_redefinition(sym)?;
+                self.env.define(sym.clone(), maybe_val);
+                Ok(())
+            }
+            expr::Stmt::ConstDecl(sym, expr) => {
+                let val = self.interpret_expr(expr)?;
+                self.check_redefinition(sym)?;
+                self.env.define_const(sym.clone(), val);
+                Ok(())
+            }
+            expr::Stmt::Block(stmts) => {
+                // 恢复执行的时候，暂停时的环境已经整个恢复了
+                if self.resume_path.is_empty() {
+                    self.env = Environment::with_enclosing(self.env.clone());
+                }
+
+                self.execute_stmts(stmts)?;
+
+                if self.yielded.is_some() {
+                    return Ok(()); // 暂停的时候环境要留着
+                }
+
+                if let Some(enclosing) = self.env.enclosing.clone() {
+                    self.env = *enclosing; // 恢复环境
+                } else {
+                    // TODO: how to do this without a runtime check?
+                    panic!("impossible");
+                }
+
+                Ok(())
+            }
+            expr::Stmt::While(cond, body, maybe_increment) => {
+                // 从循环体里面恢复执行的时候，条件已经判断过了
+                let mut resuming = self.resuming_loop(stmt);
+                while resuming || Interpreter::is_truthy(&self.interpret_expr(cond)?) {
+                    resuming = false;
+                    self.execute(body)?;
+                    if self.yielded.is_some() {
+                        self.suspend_path.push(ResumePoint::Loop);
+                        break;
+                    }
+                    if self.loop_should_exit() {
+                        break;
+                    }
+                    if let Some(increment) = maybe_increment {
+                        self.interpret_expr(increment)?;
+                    }
+                }
+                Ok(())
+            }
+            expr::Stmt::DoWhile(body, cond) => {
+                self.resuming_loop(stmt);
+                loop {
+                    self.execute(body)?;
+                    if self.yielded.is_some() {
+                        self.suspend_path.push(ResumePoint::Loop);
+                        break;
+                    }
+                    if self.loop_should_exit() {
+                        break;
+                    }
+                    if !Interpreter::is_truthy(&self.interpret_expr(cond)?) {
+                        break;
+                    }
+                }
+                Ok(())
+            }
+            expr::Stmt::ForIn(sym, iterable, body) => self.for_in(sym, iterable, body),
+            expr::Stmt::Match(subject, arms) => self.execute_match(stmt, subject, arms),
+            expr::Stmt::Break(_) => {
+                self.loop_signal = Some(LoopSignal::Break);
+                Ok(())
+            }
+            expr::Stmt::Continue(_) => {
+                self.loop_signal = Some(LoopSignal::Continue);
+                Ok(())
+            }
+            expr::Stmt::Yield(_, e) => {
+                if let Some(point) = self.take_resume_point() {
+                    // 生成器从这里恢复执行
+                    if !matches!(point, ResumePoint::Yield) {
+                        Interpreter::bad_resume_point(&point, stmt);
+                    }
+                    return Ok(());
+                }
+                self.yielded = Some(self.interpret_expr(e)?);
+                self.suspend_path.push(ResumePoint::Yield);
+                Ok(())
+            }
+            // 尾调用：参数算好以后先停下来，交给外层的 call_bound 去调用
+            expr::Stmt::Return(_, Some(expr::Expr::Call(callee, loc, args, named_args))) => {
+                let callee = self.interpret_expr(callee)?;
+                match self.tail_callee(&callee) {
+                    Some(function) => {
+                        let (args, rest) = self.bind_call_args(&function, loc, args, named_args)?;
+                        self.tail_call = Some(TailCall {
+                            function,
+                            args,
+                            rest,
+                            caller_env: self.env.clone(),
+                        });
+                        self.retval = Some(Value::Nil);
+                    }
+                    None => {
+                        self.retval = Some(self.call(callee, loc, args, named_args)?);
+                    }
+                }
+                Ok(())
+            }
+            expr::Stmt::Return(_, maybe_res) => {
+                self.retval = Some(
+                    if let Some(res) = maybe_res {
+                        self.interpret_expr(res)?
+                    } else {
+                        Value::Nil
+                    }
+                );
+                Ok(())
+            }
+        }
+    }
+
+    /**
+     * 循环体执行完以后，看一下要不要跳出循环；continue 在这里就被消化掉了
+     */
+    fn loop_should_exit(&mut self) -> bool {
+        match self.loop_signal.take() {
+            Some(LoopSignal::Break) => true,
+            Some(LoopSignal::Continue) | None => self.retval.is_some(),
+        }
+    }
+
+    /**
+     * for (var x in iterable) body
+     * 列表按下标遍历；实例走迭代器协议：有 iter() 就先调用它拿到迭代器，
+     * 然后不停地调用 next()，返回 nil 的时候结束
+     */
+    fn for_in(
+        &mut self,
+        sym: &expr::Symbol,
+        iterable_expr: &expr::Expr,
+        body: &expr::Stmt
+    ) -> Result<(), String> {
+        // 从循环体里面恢复执行的时候，迭代状态是暂停的时候记下来的
+        let (mut cursor, mut resuming) = match self.take_resume_point() {
+            Some(ResumePoint::ForIn(cursor)) => (cursor, true),
+            None => {
+                let iterable = self.interpret_expr(iterable_expr)?;
+                (self.for_in_cursor(iterable, sym)?, false)
+            }
+            Some(point) => Interpreter::bad_resume_point(&point, body),
+        };
+
+        loop {
+            let maybe_val = if resuming {
+                None
+            } else {
+                match self.advance_cursor(&mut cursor, sym)? {
+                    Some(val) => Some(val),
+                    None => {
+                        break;
+                    }
+                }
+            };
+            resuming = false;
+
+            self.execute_for_in_body(sym, maybe_val, body)?;
+            if self.yielded.is_some() {
+                self.suspend_path.push(ResumePoint::ForIn(cursor));
+                break;
+            }
+            if self.loop_should_exit() {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /**
+     * 实例有 iter() 就先调用它拿到迭代器，否则实例自己就是迭代器
+     */
+    fn for_in_cursor(&mut self, iterable: Value, sym: &expr::Symbol) -> Result<ForInCursor, String> {
+        match iterable {
+            Value::List(list_id) => Ok(ForInCursor::List(list_id, 0)),
+            Value::Generator(_) => Ok(ForInCursor::Iterator(iterable)),
+            Value::LoxInstance(_, _) => {
+                let iterator = match self.getattr_value(&iterable, "iter") {
+                    Ok(iter_method) => self.call_value(&iter_method, &[], sym)?,
+                    Err(_) => iterable,
+                };
+                Ok(ForInCursor::Iterator(iterator))
+            }
+            _ =>
+                Err(
+                    format!(
+                        "Cannot iterate over value of type {:?} at line={},col={}",
+                        type_of(&iterable),
+                        sym.line,
+                        sym.col
+                    )
+                ),
+        }
+    }
+
+    /**
+     * 取出下一个元素，迭代完了返回 None
+     */
+    fn advance_cursor(
+        &mut self,
+        cursor: &mut ForInCursor,
+        sym: &expr::Symbol
+    ) -> Result<Option<Value>, String> {
+        match cursor {
+            ForInCursor::List(list_id, idx) => {
+                // 每次都重新取列表的长度，循环体里面修改列表也没有问题
+                let maybe_val = self.get_list_elts(*list_id).get(*idx).cloned();
+                *idx += 1;
+                Ok(maybe_val)
+            }
+            ForInCursor::Iterator(iterator) => {
+                let next_method = self.getattr_value(iterator, "next")?;
+                let val = self.call_value(&next_method, &[], sym)?;
+                // 生成器要跑完了才算结束，中间 yield 出来的 nil 也是元素；其他迭代器的 next() 返回 nil 就是结束
+                let done = match iterator {
+                    Value::Generator(generator_id) => {
+                        self.get_generator_mut(*generator_id).state == GeneratorState::Done
+                    }
+                    _ => matches!(val, Value::Nil),
+                };
+                Ok(if done { None } else { Some(val) })
+            }
+        }
+    }
+
+    /**
+     * 每一轮循环都有一个新的作用域；恢复执行的时候 val 是 None，环境已经恢复好了
      */
-    fn execute(&mut self, stmt: &expr::Stmt) -> Result<(), String> {
-        if self.retval.is_some() {
-            // 如果已经有返回值了，返回 ok
-            return Ok(());
+    fn execute_for_in_body(
+        &mut self,
+        sym: &expr::Symbol,
+        maybe_val: Option<Value>,
+        body: &expr::Stmt
+    ) -> Result<(), String> {
+        if let Some(val) = maybe_val {
+            self.env = Environment::with_enclosing(self.env.clone());
+            self.env.define(sym.clone(), Some(val));
         }
 
-        match stmt {
-            // 解释表达式
-            expr::Stmt::Expr(e) =>
-                match self.interpret_expr(e) {
-                    Ok(_) => Ok(()), // statement 的返回值就是 ()
-                    Err(err) => Err(err),
-                }
+        let res = self.execute(body);
 
-            // class 声明
-            expr::Stmt::ClassDecl( // expr::Stmt::ClassDecl(expr::ClassDecl)
-                expr::ClassDecl { name: sym, superclass: maybe_superclass, methods: stmt_methods },
-            ) => {
-                let class_id = self.alloc_id();
-                self.env.define(sym.clone(), Some(Value::LoxClass(sym.clone(), class_id)));
+        if self.yielded.is_some() {
+            return res; // 暂停的时候环境要留着
+        }
 
-                let superclass_id = if let Some(superclass_var) = maybe_superclass {
-                    // 不能自己继承自己（这会让类爆炸）
-                    if superclass_var.name == sym.name {
-                        return Err(
-                            format!(
-                                "A class cannot inerit from itself (line={}, col={})",
-                                sym.line,
-                                sym.col
-                            )
-                        );
-                    }
+        if let Some(enclosing) = self.env.enclosing.clone() {
+            self.env = *enclosing; // 恢复环境
+        }
 
-                    let superclass_val = self.interpret_expr(
-                        &expr::Expr::Variable(superclass_var.clone())
-                    )?;
-                    if let Value::LoxClass(_, id) = superclass_val {
-                        Some(id)
-                    } else {
-                        return Err(
-                            format!(
-                                "Only classes should appear as superclasses. Found {:?}.",
-                                type_of(&superclass_val)
-                            )
-                        );
-                    }
-                } else {
-                    None
-                };
+        res
+    }
 
-                let mut methods = HashMap::new();
-                for method in stmt_methods.iter() {
-                    let func_id = self.alloc_id();
+    /**
+     * match：分支的绑定放在一个新的环境里，guard 和分支的语句都在这个环境里执行
+     */
+    fn execute_match(
+        &mut self,
+        stmt: &expr::Stmt,
+        subject: &expr::Expr,
+        arms: &[expr::MatchArm]
+    ) -> Result<(), String> {
+        // 恢复执行的时候，分支的环境已经整个恢复了
+        let arm_idx = match self.take_resume_point() {
+            Some(ResumePoint::MatchArm(arm_idx)) => arm_idx,
+            None => {
+                let val = self.interpret_expr(subject)?;
+                match self.select_match_arm(&val, arms)? {
+                    Some(arm_idx) => arm_idx,
+                    None => {
+                        return Ok(());
+                    }
+                }
+            }
+            Some(point) => Interpreter::bad_resume_point(&point, stmt),
+        };
 
-                    methods.insert(method.name.name.clone(), func_id);
+        let res = self.execute(&arms[arm_idx].body);
 
-                    let is_initializer = method.name.name == INIT;
+        if self.yielded.is_some() {
+            self.suspend_path.push(ResumePoint::MatchArm(arm_idx));
+            return res; // 暂停的时候环境要留着
+        }
 
-                    let lox_function = LoxFunction {
-                        id: func_id,
-                        name: method.name.clone(),
-                        parameters: method.params.clone(),
-                        body: method.body.clone(),
-                        closure: self.env.clone(),
-                        this_binding: None,
-                        superclass: superclass_id,
-                        is_initializer,
-                    };
+        if let Some(enclosing) = self.env.enclosing.clone() {
+            self.env = *enclosing; // 恢复环境
+        }
 
-                    self.lox_functions.insert(func_id, lox_function);
-                }
+        res
+    }
 
-                let cls = LoxClass {
-                    name: sym.clone(),
-                    superclass: superclass_id,
-                    id: class_id,
-                    methods,
-                };
+    /**
+     * 找到第一个模式匹配、guard 也成立的分支，这时候环境里已经有了它的绑定
+     */
+    fn select_match_arm(
+        &mut self,
+        val: &Value,
+        arms: &[expr::MatchArm]
+    ) -> Result<Option<usize>, String> {
+        for (arm_idx, arm) in arms.iter().enumerate() {
+            let mut bindings = Vec::new();
+            if !self.match_pattern(&arm.pattern, val, &mut bindings)? {
+                continue;
+            }
 
-                self.lox_classes.insert(class_id, cls);
-                Ok(())
+            self.env = Environment::with_enclosing(self.env.clone());
+            for (sym, bound) in bindings {
+                self.env.define(sym, Some(bound));
             }
-            expr::Stmt::FunDecl(expr::FunDecl { name, params: parameters, body }) => {
-                let func_id = self.alloc_id();
-                self.env.define(
-                    name.clone(),
-                    Some(Value::LoxFunction(name.clone(), func_id, None))
-                );
 
-                let lox_function = LoxFunction {
-                    id: func_id,
-                    name: name.clone(),
-                    parameters: parameters.clone(),
-                    body: body.clone(),
-                    closure: self.env.clone(),
-                    this_binding: None,
-                    superclass: None,
-                    is_initializer: false,
-                };
+            let guard_passed = match &arm.guard {
+                Some(guard) => Interpreter::is_truthy(&self.interpret_expr(guard)?),
+                None => true,
+            };
+            if guard_passed {
+                return Ok(Some(arm_idx));
+            }
 
-                self.lox_functions.insert(func_id, lox_function);
+            if let Some(enclosing) = self.env.enclosing.clone() {
+                self.env = *enclosing;
+            }
+        }
+        Ok(None)
+    }
 
-                Ok(())
+    fn match_pattern(
+        &mut self,
+        pattern: &expr::Pattern,
+        val: &Value,
+        bindings: &mut Vec<(expr::Symbol, Value)>
+    ) -> Result<bool, String> {
+        match pattern {
+            expr::Pattern::Wildcard => Ok(true),
+            expr::Pattern::Binding(sym) => {
+                bindings.push((sym.clone(), val.clone()));
+                Ok(true)
             }
-            expr::Stmt::If(cond, if_true, maybe_if_false) => {
-                if Interpreter::is_truthy(&self.interpret_expr(cond)?) {
-                    return self.execute(if_true);
+            expr::Pattern::Literal(loc, lit) => {
+                let op = expr::BinaryOp {
+                    ty: expr::BinaryOpTy::EqualEqual,
+                    line: loc.line,
+                    col: loc.col,
+                };
+                let res = self.binary_op(val, op, &Interpreter::interpret_literal(lit))?;
+                Ok(Interpreter::is_truthy(&res))
+            }
+            expr::Pattern::List(patterns) => {
+                let elements = match val {
+                    Value::List(list_id) => self.get_list_elts(*list_id).clone(),
+                    _ => {
+                        return Ok(false);
+                    }
+                };
+                if elements.len() != patterns.len() {
+                    return Ok(false);
                 }
-                if let Some(if_false) = maybe_if_false {
-                    return self.execute(if_false);
+                for (element_pattern, element) in patterns.iter().zip(elements.iter()) {
+                    if !self.match_pattern(element_pattern, element, bindings)? {
+                        return Ok(false);
+                    }
                 }
-                Ok(())
+                Ok(true)
             }
-            expr::Stmt::Print(e) =>
-                // execute --> interpret_expr
-                match self.interpret_expr(e) {
-                    Ok(val) => {
-                        println!("{}", self.format_val(&val));
-                        self.output.push(self.format_val(&val));
-                        Ok(())
+            expr::Pattern::Instance(class_sym, fields) => {
+                let cls = self.lookup(class_sym)?.clone();
+                if !self.is_instance(val, &cls)? {
+                    return Ok(false);
+                }
+                for (field, field_pattern) in fields {
+                    // 没有这个字段的实例匹配失败，不报错
+                    if !self.has_attribute(val, &field.name) {
+                        return Ok(false);
+                    }
+                    let field_val = self.getattr_value(val, &field.name)?;
+                    if !self.match_pattern(field_pattern, &field_val, bindings)? {
+                        return Ok(false);
                     }
-                    Err(err) => Err(err),
                 }
-            expr::Stmt::VarDecl(sym, maybe_expr) => {
-                let maybe_val = match maybe_expr {
-                    Some(expr) => Some(self.interpret_expr(expr)?),
-                    None => None,
-                };
-                self.env.define(sym.clone(), maybe_val);
-                Ok(())
+                Ok(true)
             }
-            expr::Stmt::Block(stmts) => {
-                self.env = Environment::with_enclosing(self.env.clone());
-
-                for stmt in stmts.iter() {
-                    self.execute(stmt)?;
-                }
+        }
+    }
 
-                if let Some(enclosing) = self.env.enclosing.clone() {
-                    self.env = *enclosing; // 恢复环境
-                } else {
-                    // TODO: how to do this without a runtime check?
-                    panic!("impossible");
-                }
+    /**
+     * 依次执行语句，生成器暂停的时候记下执行到了第几条
+     */
+    fn execute_stmts(&mut self, stmts: &[expr::Stmt]) -> Result<(), String> {
+        let start = match self.take_resume_point() {
+            Some(ResumePoint::Block(idx)) => idx,
+            None => 0,
+            Some(point) => panic!("Internal interpreter error: cannot resume a block at {:?}.", point),
+        };
 
-                Ok(())
+        for (idx, stmt) in stmts.iter().enumerate().skip(start) {
+            self.execute(stmt)?;
+            if self.yielded.is_some() {
+                self.suspend_path.push(ResumePoint::Block(idx));
+                break;
             }
-            expr::Stmt::While(cond, body) => {
-                while Interpreter::is_truthy(&self.interpret_expr(cond)?) {
-                    self.execute(body)?;
-                }
-                Ok(())
+        }
+        Ok(())
+    }
+
+    /**
+     * 恢复执行的时候取出这一层语句的恢复点，正常执行的时候是 None
+     */
+    fn take_resume_point(&mut self) -> Option<ResumePoint> {
+        self.resume_path.pop()
+    }
+
+    fn resuming_loop(&mut self, stmt: &expr::Stmt) -> bool {
+        match self.take_resume_point() {
+            Some(ResumePoint::Loop) => true,
+            None => false,
+            Some(point) => Interpreter::bad_resume_point(&point, stmt),
+        }
+    }
+
+    fn bad_resume_point(point: &ResumePoint, stmt: &expr::Stmt) -> ! {
+        panic!("Internal interpreter error: cannot resume {:?} at {:?}.", stmt, point)
+    }
+
+    /* ---------- ---------- 生成器 ---------- ---------- */
+
+    fn create_generator(&mut self, function: LoxFunction, env: Environment) -> Value {
+        let generator_id = self.alloc_id();
+        self.generators.insert(generator_id, Generator {
+            function,
+            env,
+            resume_path: Vec::new(),
+            state: GeneratorState::Suspended,
+        });
+        Value::Generator(generator_id)
+    }
+
+    fn get_generator_mut(&mut self, id: u64) -> &mut Generator {
+        match self.generators.get_mut(&id) {
+            Some(generator) => generator,
+            None => panic!("Internal interpreter error: could not find generator with id {}.", id),
+        }
+    }
+
+    /**
+     * next()：从上次暂停的地方继续执行，到下一个 yield 为止；执行完了以后一直返回 nil
+     */
+    fn resume_generator(&mut self, generator_id: u64) -> Result<Value, String> {
+        let generator = self.get_generator_mut(generator_id);
+        match generator.state {
+            GeneratorState::Done => {
+                return Ok(Value::Nil);
             }
-            expr::Stmt::Return(_, maybe_res) => {
-                self.retval = Some(
-                    if let Some(res) = maybe_res {
-                        self.interpret_expr(res)?
-                    } else {
-                        Value::Nil
-                    }
+            GeneratorState::Running => {
+                return Err(
+                    format!("generator {} is already running", generator.function.name.name)
                 );
-                Ok(())
             }
+            GeneratorState::Suspended => {}
+        }
+        generator.state = GeneratorState::Running;
+        let function = generator.function.clone();
+        let env = std::mem::take(&mut generator.env);
+        let resume_path = std::mem::take(&mut generator.resume_path);
+
+        /* ---------- 保存调用者的状态，换成生成器的 ---------- */
+
+        let saved_env = std::mem::replace(&mut self.env, env);
+        let saved_retval = self.retval.take();
+        let saved_loop_signal = self.loop_signal.take();
+        let saved_enclosing_function = self.enclosing_function.replace(function.id);
+        self.resume_path = resume_path;
+        self.backtrace.push((0, function.name.name.clone()));
+
+        let res = self.execute_stmts(&function.body);
+
+        /* ---------- 恢复调用者的状态 ---------- */
+
+        self.backtrace.pop();
+        let env = std::mem::replace(&mut self.env, saved_env);
+        let yielded = self.yielded.take();
+        let suspend_path = std::mem::take(&mut self.suspend_path);
+        self.resume_path.clear();
+        self.retval = saved_retval;
+        self.loop_signal = saved_loop_signal;
+        self.enclosing_function = saved_enclosing_function;
+
+        let generator = self.get_generator_mut(generator_id);
+        match (res, yielded) {
+            (Ok(()), Some(val)) => {
+                generator.env = env;
+                generator.resume_path = suspend_path;
+                generator.state = GeneratorState::Suspended;
+                Ok(val)
+            }
+            (Ok(()), None) => {
+                // return 或者执行到了函数末尾
+                generator.state = GeneratorState::Done;
+                Ok(Value::Nil)
+            }
+            (Err(err), _) => {
+                generator.state = GeneratorState::Done;
+                Err(err)
+            }
+        }
+    }
+
+    /**
+     * 在解释器内部调用一个可调用的值，比如迭代器的 next()
+     */
+    fn call_value(
+        &mut self,
+        callee: &Value,
+        args: &[Value],
+        sym: &expr::Symbol
+    ) -> Result<Value, String> {
+        match as_callable(self, callee) {
+            Some(callable) => {
+                let (bound, rest) = self
+                    .bind_args(&*callable, args.to_vec(), Vec::new())
+                    .map_err(|err| {
+                        format!("Invalid call at line={},col={}: {}", sym.line, sym.col, err)
+                    })?;
+                callable.call_bound(self, bound, rest)
+            }
+            None =>
+                Err(
+                    format!(
+                        "value {:?} is not callable at line={},col={}",
+                        callee,
+                        sym.line,
+                        sym.col
+                    )
+                ),
         }
     }
 
@@ -955,6 +2382,7 @@ impl Interpreter {
             name: String::from("this"),
             line,
             col,
+            ty: None,
         }
     }
 
@@ -983,7 +2411,10 @@ impl Interpreter {
             expr::Expr::Binary(lhs, op, rhs) => self.interpret_binary(lhs, *op, rhs),
 
             // 调用函数
-            expr::Expr::Call(callee, loc, args) => self.call(callee, loc, args),
+            expr::Expr::Call(callee, loc, args, named_args) => {
+                let callee = self.interpret_expr(callee)?;
+                self.call(callee, loc, args, named_args)
+            }
             expr::Expr::Get(lhs, attr) => self.getattr(lhs, &attr.name),
             expr::Expr::Set(lhs, attr, rhs) => self.setattr(lhs, attr, rhs),
             expr::Expr::Grouping(e) => self.interpret_expr(e),
@@ -1026,6 +2457,21 @@ impl Interpreter {
                             // 父类
                             Some(superclass_id) => {
                                 let superclass = self.get_lox_class(*superclass_id);
+                                // super.name 是 getter 的话直接调用它，和 this.name 一样
+                                if
+                                    let Some((func_name, getter_id)) = superclass.find_member(
+                                        &sym.name,
+                                        |cls| &cls.getters,
+                                        self
+                                    )
+                                {
+                                    let getter = Value::LoxFunction(
+                                        func_name,
+                                        getter_id,
+                                        func.this_binding.clone()
+                                    );
+                                    return self.call_special_method(getter, &[]);
+                                }
                                 // superclass
                                 if
                                     let Some((func_name, method_id)) = superclass.find_method(
@@ -1073,22 +2519,30 @@ impl Interpreter {
                         ),
                 }
             expr::Expr::List(elements) => self.list(elements),
+            expr::Expr::Interpolate(parts) => self.interpolate(parts),
             expr::Expr::Subscript { value, slice, source_location } =>
                 self.subscript(value, slice, source_location),
             expr::Expr::SetItem { lhs, slice, rhs, source_location } =>
                 self.setitem(lhs, slice, rhs, source_location),
+            expr::Expr::CompoundAssign { target, op, rhs, postfix } =>
+                self.compound_assign(target, *op, rhs, *postfix),
             expr::Expr::Lambda(lambda_decl) => {
                 let lambda_sym = expr::Symbol {
                     name: self.lambda_name(),
                     line: 0,
                     col: 0,
+                    ty: None,
                 };
                 let maybe_err = self.execute(
                     // 执行 lambda 表达式
                     &expr::Stmt::FunDecl(expr::FunDecl {
                         name: lambda_sym.clone(),
                         params: lambda_decl.params.clone(),
+                        defaults: lambda_decl.defaults.clone(),
+                        rest: lambda_decl.rest.clone(),
+                        return_ty: lambda_decl.return_ty.clone(),
                         body: lambda_decl.body.clone(),
+                        is_generator: lambda_decl.is_generator,
                     })
                 );
                 match maybe_err {
@@ -1121,17 +2575,34 @@ impl Interpreter {
         let lhs = self.interpret_expr(lhs_expr)?;
         let slice = self.interpret_expr(slice_expr)?;
         let rhs = self.interpret_expr(rhs_expr)?;
-        if let Value::List(list_id) = lhs {
+        self.setitem_value(&lhs, &slice, rhs, source_location)
+    }
+
+    fn setitem_value(
+        &mut self,
+        lhs: &Value,
+        slice: &Value,
+        rhs: Value,
+        source_location: &expr::SourceLocation
+    ) -> Result<Value, String> {
+        if let Some(method) = self.special_method(lhs, "__setitem__") {
+            self.call_special_method(method, &[slice.clone(), rhs.clone()])?;
+            return Ok(rhs);
+        }
+        if let Value::List(list_id) = *lhs {
+            if self.frozen.contains(&list_id) {
+                return Err("Can't modify a frozen list.".to_string());
+            }
             let elements = self.get_list_elts_mut(list_id);
             let subscript_index = Interpreter::subscript_to_inbound_index(
                 elements.len(),
-                &slice,
+                slice,
                 source_location
             )?;
             elements[subscript_index] = rhs.clone();
             Ok(rhs)
         } else {
-            Err(format!("Invalid value of type {:?} in setitem expr.", type_of(&lhs)))
+            Err(format!("Invalid value of type {:?} in setitem expr.", type_of(lhs)))
         }
     }
 
@@ -1141,21 +2612,113 @@ impl Interpreter {
     fn subscript(
         &mut self,
         value_expr: &expr::Expr,
-        slice_expr: &expr::Expr,
+        slice: &expr::Slice,
         source_location: &expr::SourceLocation
     ) -> Result<Value, String> {
         let value = self.interpret_expr(value_expr)?;
-        let slice = self.interpret_expr(slice_expr)?;
-        if let Value::List(list_id) = value {
+        match slice {
+            expr::Slice::Index(index_expr) => {
+                let index = self.interpret_expr(index_expr)?;
+                self.subscript_value(&value, &index, source_location)
+            }
+            expr::Slice::Range(start_expr, stop_expr) => {
+                // 省略的一端当成 nil
+                let mut bounds = Vec::new();
+                for bound_expr in [start_expr, stop_expr] {
+                    bounds.push(match bound_expr {
+                        Some(bound_expr) => self.interpret_expr(bound_expr)?,
+                        None => Value::Nil,
+                    });
+                }
+                self.slice_value(&value, &bounds[0], &bounds[1], source_location)
+            }
+        }
+    }
+
+    /**
+     * 切片 value[start:stop]，和 python 一样，越界的时候截断到列表的范围
+     */
+    fn slice_value(
+        &mut self,
+        value: &Value,
+        start: &Value,
+        stop: &Value,
+        source_location: &expr::SourceLocation
+    ) -> Result<Value, String> {
+        if let Value::List(list_id) = *value {
+            let elements = self.get_list_elts(list_id);
+            let len = elements.len();
+            let start = Interpreter::slice_bound(len, start, 0, source_location)?;
+            let stop = Interpreter::slice_bound(len, stop, len, source_location)?;
+            let res = if start < stop { elements[start..stop].to_vec() } else { Vec::new() };
+            Ok(self.create_list(res))
+        } else {
+            Err(format!("Invalid value of type {:?} in slice expression", type_of(value)))
+        }
+    }
+
+    /**
+     * 切片的一端：nil 表示省略，负数从右往左数
+     */
+    fn slice_bound(
+        list_len: usize,
+        bound: &Value,
+        default: usize,
+        source_location: &expr::SourceLocation
+    ) -> Result<usize, String> {
+        match bound {
+            Value::Nil => Ok(default),
+            Value::Number(_) | Value::Integer(_) => {
+                let len = list_len as i64;
+                let index_int = match bound {
+                    Value::Integer(n) => *n,
+                    Value::Number(n) if n.fract() == 0.0 => *n as i64,
+                    Value::Number(n) => {
+                        return Err(
+                            format!(
+                                "Invalid slice bound {} in slice expression at line={},col={}",
+                                n,
+                                source_location.line,
+                                source_location.col
+                            )
+                        );
+                    }
+                    _ => unreachable!(),
+                };
+                let index_int = if index_int < 0 { index_int + len } else { index_int };
+                Ok(index_int.clamp(0, len) as usize)
+            }
+            _ =>
+                Err(
+                    format!(
+                        "Invalid slice bound of type {:?} in slice expression at line={},col={}",
+                        type_of(bound),
+                        source_location.line,
+                        source_location.col
+                    )
+                ),
+        }
+    }
+
+    fn subscript_value(
+        &mut self,
+        value: &Value,
+        slice: &Value,
+        source_location: &expr::SourceLocation
+    ) -> Result<Value, String> {
+        if let Some(method) = self.special_method(value, "__getitem__") {
+            return self.call_special_method(method, std::slice::from_ref(slice));
+        }
+        if let Value::List(list_id) = *value {
             let elements = self.get_list_elts(list_id); // 通过 id 获取 列表
             let subscript_index = Interpreter::subscript_to_inbound_index(
                 elements.len(),
-                &slice,
+                slice,
                 source_location
             )?;
             Ok(elements[subscript_index].clone())
         } else {
-            Err(format!("Invalid value of type {:?} in subscript expr.", type_of(&value)))
+            Err(format!("Invalid value of type {:?} in subscript expr.", type_of(value)))
         }
     }
 
@@ -1167,8 +2730,16 @@ impl Interpreter {
         slice: &Value,
         source_location: &expr::SourceLocation
     ) -> Result<usize, String> {
-        if let Value::Number(index_float) = slice {
-            let index_int = *index_float as i64;
+        let index_int = match slice {
+            // 带小数的下标不会悄悄截断
+            Value::Number(index_float) if index_float.fract() != 0.0 => {
+                return Err(format!("Invalid subscript {} in subscript expression", index_float));
+            }
+            Value::Number(index_float) => Some(*index_float as i64),
+            Value::Integer(index_int) => Some(*index_int),
+            _ => None,
+        };
+        if let Some(index_int) = index_int {
             if 0 <= index_int && index_int < (list_len as i64) {
                 return Ok(index_int as usize);
             }
@@ -1176,10 +2747,24 @@ impl Interpreter {
             if index_int < 0 && -index_int <= (list_len as i64) {
                 return Ok(((list_len as i64) + index_int) as usize);
             }
-            Err(format!("List subscript index out of range at {:?}", source_location))
-        } else {
-            Err(format!("Invalid subscript of type {:?} in subscript expression", type_of(slice)))
+            Err(format!("List subscript index out of range at {:?}", source_location))
+        } else {
+            Err(format!("Invalid subscript of type {:?} in subscript expression", type_of(slice)))
+        }
+    }
+
+    /**
+     * 插值字符串：字符串本身原样拼进去，其他的值和 print 的格式一样
+     */
+    fn interpolate(&mut self, parts: &[expr::Expr]) -> Result<Value, String> {
+        let mut res = String::new();
+        for part in parts {
+            match self.interpret_expr(part)? {
+                Value::String(s) => res.push_str(&s),
+                val => res.push_str(&self.stringify(&val)?),
+            }
         }
+        Ok(Value::String(res))
     }
 
     /**
@@ -1197,12 +2782,223 @@ impl Interpreter {
         }
     }
 
+    /**
+     * 把类里面的一组方法声明变成 LoxFunction，返回 名字 -> 函数 id
+     */
+    fn define_methods(
+        &mut self,
+        decls: &[expr::FunDecl],
+        superclass_id: Option<u64>,
+        allow_initializer: bool
+    ) -> HashMap<String, u64> {
+        let mut methods = HashMap::new();
+        for method in decls.iter() {
+            let func_id = self.alloc_id();
+
+            methods.insert(method.name.name.clone(), func_id);
+
+            let is_initializer = allow_initializer && method.name.name == INIT;
+
+            let lox_function = LoxFunction {
+                id: func_id,
+                name: method.name.clone(),
+                parameters: method.params.clone(),
+                defaults: method.defaults.clone(),
+                rest: method.rest.clone(),
+                body: method.body.clone(),
+                closure: self.env.clone(),
+                this_binding: None,
+                superclass: superclass_id,
+                is_initializer,
+                is_generator: method.is_generator,
+            };
+
+            self.lox_functions.insert(func_id, lox_function);
+        }
+        methods
+    }
+
+    /**
+     * 把 trait 的方法放进类的方法表：类自己的方法优先，然后是 trait，最后才是父类。
+     * 两个 trait 提供了同名的方法、类自己又没有定义的时候报错
+     */
+    fn mix_in_traits(
+        &mut self,
+        class_name: &expr::Symbol,
+        traits: &[expr::Symbol],
+        superclass_id: Option<u64>,
+        methods: &mut HashMap<String, u64>
+    ) -> Result<Vec<u64>, String> {
+        let own_methods: Vec<String> = methods.keys().cloned().collect();
+        let mut providers: HashMap<String, String> = HashMap::new(); // 方法名 -> 提供它的 trait
+        let mut required = Vec::new();
+        let mut trait_ids = Vec::new();
+
+        for trait_sym in traits {
+            let trait_val = self.interpret_expr(&expr::Expr::Variable(trait_sym.clone()))?;
+            let lox_trait = match trait_val {
+                Value::LoxTrait(_, id) => self.get_lox_trait(id).clone(),
+                _ => {
+                    return Err(
+                        format!(
+                            "Only traits can be mixed into a class. Found {:?}.",
+                            type_of(&trait_val)
+                        )
+                    );
+                }
+            };
+            trait_ids.push(lox_trait.id);
+
+            let mut trait_methods: Vec<(&String, &u64)> = lox_trait.methods.iter().collect();
+            trait_methods.sort();
+            for (method_name, method_id) in trait_methods {
+                if own_methods.contains(method_name) {
+                    continue;
+                }
+                if let Some(other) = providers.get(method_name) {
+                    return Err(
+                        format!(
+                            "Conflicting method '{}' from traits {} and {} in class {}.",
+                            method_name,
+                            other,
+                            lox_trait.name.name,
+                            class_name.name
+                        )
+                    );
+                }
+                providers.insert(method_name.clone(), lox_trait.name.name.clone());
+                methods.insert(method_name.clone(), *method_id);
+            }
+            for method_name in lox_trait.required.iter() {
+                required.push((method_name.clone(), lox_trait.name.name.clone()));
+            }
+        }
+
+        for (method_name, trait_name) in required {
+            let inherited = superclass_id.is_some_and(|id| {
+                self.get_lox_class(id).find_method(&method_name, self).is_some()
+            });
+            if !methods.contains_key(&method_name) && !inherited {
+                return Err(
+                    format!(
+                        "Class {} must provide method '{}' required by trait {}.",
+                        class_name.name,
+                        method_name,
+                        trait_name
+                    )
+                );
+            }
+        }
+
+        Ok(trait_ids)
+    }
+
+    /**
+     * val instanceof cls：沿着实例的类的父类链往上找，混入的 trait 也算
+     */
+    fn is_instance(&self, val: &Value, cls: &Value) -> Result<bool, String> {
+        let target_id = match cls {
+            Value::LoxClass(_, id) | Value::LoxTrait(_, id) => *id,
+            _ => {
+                return Err(
+                    format!("Can't check instanceof against a value of type {:?}.", type_of(cls))
+                );
+            }
+        };
+        let mut maybe_class_id = match val {
+            Value::LoxInstance(_, id) => Some(self.get_lox_instance(*id).class_id),
+            _ => None,
+        };
+        while let Some(class_id) = maybe_class_id {
+            let cls = self.get_lox_class(class_id);
+            if class_id == target_id || cls.traits.contains(&target_id) {
+                return Ok(true);
+            }
+            maybe_class_id = cls.superclass;
+        }
+        Ok(false)
+    }
+
     fn getattr(&mut self, lhs: &expr::Expr, attr: &str) -> Result<Value, String> {
         let val = self.interpret_expr(lhs)?;
+        self.getattr_value(&val, attr)
+    }
+
+    /**
+     * 实例上能不能取到这个名字：字段、getter 或者方法。match 的实例模式用它，取不到就匹配失败
+     */
+    fn has_attribute(&self, val: &Value, attr: &str) -> bool {
+        match val {
+            Value::LoxInstance(_, id) =>
+                self.get_lox_instance(*id).fields.contains_key(attr) ||
+                    self.class_member(val, attr, |cls| &cls.getters).is_some() ||
+                    self.class_member(val, attr, |cls| &cls.methods).is_some(),
+            _ => false,
+        }
+    }
+
+    fn getattr_value(&mut self, val: &Value, attr: &str) -> Result<Value, String> {
         match val {
-            Value::LoxInstance(_, id) => self.get_lox_instance(id).getattr(attr, self),
+            Value::LoxInstance(_, id) => {
+                // 字段优先，然后是 getter，最后才是方法
+                if !self.get_lox_instance(*id).fields.contains_key(attr) {
+                    if let Some(getter) = self.class_member(val, attr, |cls| &cls.getters) {
+                        return self.call_special_method(getter, &[]);
+                    }
+                }
+                self.get_lox_instance(*id).getattr(attr, self)
+            }
+            Value::LoxClass(sym, id) =>
+                match self.get_lox_class(*id).find_member(attr, |cls| &cls.class_methods, self) {
+                    Some((func_name, method_id)) =>
+                        Ok(
+                            Value::LoxFunction(
+                                func_name,
+                                method_id,
+                                Some(Box::new(Value::LoxClass(sym.clone(), *id)))
+                            )
+                        ),
+                    None =>
+                        Err(
+                            format!("AttributeError: class '{}' has no '{}' attribute.", sym.name, attr)
+                        ),
+                }
+            // 没有字段的变体直接就是值，有字段的取出来是构造函数
+            Value::LoxEnum(sym, id) => {
+                let lox_enum = self.get_lox_enum(*id);
+                match lox_enum.variants.iter().position(|variant| variant.name.name == attr) {
+                    Some(tag) if lox_enum.variants[tag].fields.is_empty() => {
+                        Ok(Value::Variant(*id, tag, Vec::new()))
+                    }
+                    Some(tag) => Ok(Value::VariantConstructor(*id, tag)),
+                    None =>
+                        Err(
+                            format!("AttributeError: enum '{}' has no variant '{}'.", sym.name, attr)
+                        ),
+                }
+            }
+            Value::Variant(enum_id, tag, fields) => {
+                let lox_enum = self.get_lox_enum(*enum_id);
+                match lox_enum.variants[*tag].fields.iter().position(|field| field.name == attr) {
+                    Some(idx) => Ok(fields[idx].clone()),
+                    None =>
+                        Err(
+                            format!(
+                                "AttributeError: variant '{}' has no field '{}'.",
+                                lox_enum.variant_name(*tag),
+                                attr
+                            )
+                        ),
+                }
+            }
+            Value::Generator(id) =>
+                match attr {
+                    "next" => Ok(Value::GeneratorMethod(*id, GeneratorMethod::Next)),
+                    "iter" => Ok(Value::GeneratorMethod(*id, GeneratorMethod::Iter)),
+                    _ => Err(format!("AttributeError: generator has no '{}' attribute.", attr)),
+                }
             _ =>
-                Err(format!("Only LoxInstance values have attributes. Found {:?}.", type_of(&val))),
+                Err(format!("Only LoxInstance values have attributes. Found {:?}.", type_of(val))),
         }
     }
 
@@ -1217,7 +3013,17 @@ impl Interpreter {
     ) -> Result<Value, String> {
         let lhs = self.interpret_expr(lhs_exp)?; // lhs.attr = rhs
         let rhs = self.interpret_expr(rhs_exp)?;
-        match lhs {
+        self.setattr_value(&lhs, attr, rhs)
+    }
+
+    fn setattr_value(&mut self, lhs: &Value, attr: &expr::Symbol, rhs: Value) -> Result<Value, String> {
+        if let Some(setter) = self.class_member(lhs, &attr.name, |cls| &cls.setters) {
+            self.call_special_method(setter, std::slice::from_ref(&rhs))?;
+            return Ok(rhs);
+        }
+        match *lhs {
+            Value::LoxInstance(_ /* symbol，用不到 */, id) if self.frozen.contains(&id) =>
+                Err(format!("Can't set attribute {} on a frozen instance.", attr.name)),
             Value::LoxInstance(_ /* symbol，用不到 */, id) =>
                 match self.lox_instances.get_mut(&id) {
                     Some(inst) => {
@@ -1228,47 +3034,68 @@ impl Interpreter {
                         panic!("Internal interpreter error: could not find instance with id {}", id),
                 }
             _ =>
-                Err(format!("Only LoxInstance values have attributes. Found {:?}.", type_of(&lhs))),
+                Err(format!("Only LoxInstance values have attributes. Found {:?}.", type_of(lhs))),
         }
     }
 
+    /**
+     * 复合赋值：target 里面的子表达式（对象、下标）只求值一次
+     */
+    fn compound_assign(
+        &mut self,
+        target: &expr::Expr,
+        op: expr::BinaryOp,
+        rhs_expr: &expr::Expr,
+        postfix: bool
+    ) -> Result<Value, String> {
+        let (old, new) = match target {
+            expr::Expr::Variable(sym) => {
+                let old = self.lookup(sym)?.clone();
+                let rhs = self.interpret_expr(rhs_expr)?;
+                let new = self.binary_op(&old, op, &rhs)?;
+                self.env.assign(sym.clone(), &new)?;
+                (old, new)
+            }
+            expr::Expr::Get(lhs_expr, attr) => {
+                let lhs = self.interpret_expr(lhs_expr)?;
+                let old = self.getattr_value(&lhs, &attr.name)?;
+                let rhs = self.interpret_expr(rhs_expr)?;
+                let new = self.binary_op(&old, op, &rhs)?;
+                self.setattr_value(&lhs, attr, new.clone())?;
+                (old, new)
+            }
+            expr::Expr::Subscript { value, slice: expr::Slice::Index(slice), source_location } => {
+                let lhs = self.interpret_expr(value)?;
+                let slice = self.interpret_expr(slice)?;
+                let old = self.subscript_value(&lhs, &slice, source_location)?;
+                let rhs = self.interpret_expr(rhs_expr)?;
+                let new = self.binary_op(&old, op, &rhs)?;
+                self.setitem_value(&lhs, &slice, new.clone(), source_location)?;
+                (old, new)
+            }
+            _ => {
+                return Err(
+                    format!("invalid assignment target at line={},col={}", op.line, op.col)
+                );
+            }
+        };
+        Ok(if postfix { old } else { new })
+    }
+
     /**
      * 执行函数
      */
     fn call(
         &mut self,
-        callee_expr: &expr::Expr,
+        callee: Value,
         loc: &expr::SourceLocation,
-        arg_exprs: &[expr::Expr]
+        arg_exprs: &[expr::Expr],
+        named_arg_exprs: &[(expr::Symbol, expr::Expr)]
     ) -> Result<Value, String> {
-        let callee = self.interpret_expr(callee_expr)?;
-
         match as_callable(self, &callee) {
             Some(callable) => {
-                let maybe_args: Result<Vec<_>, _> = arg_exprs
-                    .iter()
-                    .map(|arg| self.interpret_expr(arg))
-                    .collect();
-
-                match maybe_args {
-                    Ok(args) => {
-                        if args.len() != callable.arity(self).into() {
-                            Err(
-                                format!(
-                                    "Invalid call at line={},col={}: callee has arity {}, but \
-                                         was called with {} arguments",
-                                    loc.line,
-                                    loc.col,
-                                    callable.arity(self),
-                                    args.len()
-                                )
-                            )
-                        } else {
-                            callable.call(self, &args)
-                        }
-                    }
-                    Err(err) => Err(err),
-                }
+                let (bound, rest) = self.bind_call_args(&*callable, loc, arg_exprs, named_arg_exprs)?;
+                callable.call_bound(self, bound, rest)
             }
             None =>
                 Err(
@@ -1282,6 +3109,119 @@ impl Interpreter {
         }
     }
 
+    /**
+     * 求出实参的值，然后按形参排好
+     */
+    fn bind_call_args(
+        &mut self,
+        callable: &dyn Callable,
+        loc: &expr::SourceLocation,
+        arg_exprs: &[expr::Expr],
+        named_arg_exprs: &[(expr::Symbol, expr::Expr)]
+    ) -> Result<(Vec<Option<Value>>, Vec<Value>), String> {
+        let args = arg_exprs
+            .iter()
+            .map(|arg| self.interpret_expr(arg))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut named_args = Vec::new();
+        for (sym, arg) in named_arg_exprs {
+            named_args.push((sym.name.clone(), self.interpret_expr(arg)?));
+        }
+
+        self.bind_args(callable, args, named_args).map_err(|err| {
+            format!("Invalid call at line={},col={}: {}", loc.line, loc.col, err)
+        })
+    }
+
+    /**
+     * return f(...) 能不能做成尾调用：当前在普通函数里面（生成器里的 return 要结束生成器），
+     * 被调用的也是普通的 lox 函数（初始化函数的返回值要换成 this）
+     */
+    fn tail_callee(&self, callee: &Value) -> Option<LoxFunction> {
+        let current = self.lox_functions.get(&self.enclosing_function?)?;
+        if current.is_generator || current.is_initializer {
+            return None;
+        }
+        match callee {
+            Value::LoxFunction(_, id, this_binding) => {
+                let function = self.get_lox_function(*id);
+                if function.is_generator || function.is_initializer {
+                    return None;
+                }
+                let mut function = function.clone();
+                function.this_binding = this_binding.clone();
+                Some(function)
+            }
+            _ => None,
+        }
+    }
+
+    /**
+     * 把实参按形参的顺序排好：先放位置参数，多出来的给 ...rest，再按名字放命名参数。
+     * 没传的可选参数留成 None，等函数自己去算默认值
+     */
+    fn bind_args(
+        &self,
+        callable: &dyn Callable,
+        mut args: Vec<Value>,
+        named_args: Vec<(String, Value)>
+    ) -> Result<(Vec<Option<Value>>, Vec<Value>), String> {
+        let arity = callable.arity(self);
+        let max_arity = arity + callable.optional_args(self);
+        let num_args = args.len();
+        let arity_err = || {
+            format!(
+                "callee has arity {}, but was called with {} arguments",
+                format_arity(arity, max_arity),
+                num_args
+            )
+        };
+        let has_rest = callable.has_rest(self);
+        if num_args > max_arity.into() && !has_rest {
+            return Err(arity_err());
+        }
+
+        let rest = if num_args > max_arity.into() {
+            args.split_off(max_arity.into())
+        } else {
+            Vec::new()
+        };
+        let mut bound: Vec<Option<Value>> = args.into_iter().map(Some).collect();
+        bound.resize(max_arity.into(), None);
+
+        let names = callable.param_names(self);
+        for (name, arg) in named_args {
+            match names.iter().position(|param| *param == name) {
+                Some(idx) if bound[idx].is_some() => {
+                    return Err(format!("got multiple values for argument '{}'", name));
+                }
+                Some(idx) => {
+                    bound[idx] = Some(arg);
+                }
+                None => {
+                    return Err(format!("callee has no parameter named '{}'", name));
+                }
+            }
+        }
+
+        let missing: Vec<&str> = names
+            .iter()
+            .take(arity.into())
+            .zip(bound.iter())
+            .filter(|(_, arg)| arg.is_none())
+            .map(|(name, _)| name.as_str())
+            .collect();
+        if !missing.is_empty() {
+            return Err(format!("missing arguments: {}", missing.join(", ")));
+        }
+        // 内置函数没有参数名，少传了参数只能报个数
+        if bound.iter().take(arity.into()).any(|arg| arg.is_none()) {
+            return Err(arity_err());
+        }
+
+        Ok((bound, rest))
+    }
+
     /**
      * 解释处理 二元操作
      */
@@ -1293,8 +3233,28 @@ impl Interpreter {
     ) -> Result<Value, String> {
         let lhs = self.interpret_expr(lhs_expr)?;
         let rhs = self.interpret_expr(rhs_expr)?;
+        self.binary_op(&lhs, op, &rhs)
+    }
 
-        match (&lhs, op.ty, &rhs) {
+    /**
+     * 对两个已经求值的操作数做二元运算，复合赋值也用它
+     */
+    fn binary_op(&mut self, lhs: &Value, op: expr::BinaryOp, rhs: &Value) -> Result<Value, String> {
+        if let Some(res) = self.operator_method(lhs, op, rhs)? {
+            return Ok(res);
+        }
+        if let expr::BinaryOpTy::InstanceOf = op.ty {
+            return self.is_instance(lhs, rhs).map(Value::Bool);
+        }
+        match (lhs, op.ty, rhs) {
+            /* 整数之间的运算是精确的，和浮点数混在一起的时候提升成浮点数 */
+            (Value::Integer(n1), _, Value::Integer(n2)) => Interpreter::integer_op(*n1, op, *n2),
+            (Value::Integer(n1), _, Value::Number(_)) => {
+                self.binary_op(&Value::Number(*n1 as f64), op, rhs)
+            }
+            (Value::Number(_), _, Value::Integer(n2)) => {
+                self.binary_op(lhs, op, &Value::Number(*n2 as f64))
+            }
             /* 上面是对 数字操作 */
             (Value::Number(n1), expr::BinaryOpTy::Less, Value::Number(n2)) => {
                 Ok(Value::Bool(n1 < n2))
@@ -1324,6 +3284,30 @@ impl Interpreter {
                     Err(format!("division by zero at line={},col={}", op.line, op.col))
                 }
             }
+            (Value::Number(n1), expr::BinaryOpTy::Percent, Value::Number(n2)) => {
+                if *n2 != 0.0 {
+                    Ok(Value::Number(n1 % n2))
+                } else {
+                    Err(format!("division by zero at line={},col={}", op.line, op.col))
+                }
+            }
+            (Value::Number(n1), expr::BinaryOpTy::TildeSlash, Value::Number(n2)) => {
+                if *n2 != 0.0 {
+                    Ok(Value::Number((n1 / n2).floor()))
+                } else {
+                    Err(format!("division by zero at line={},col={}", op.line, op.col))
+                }
+            }
+            (Value::Number(n1), expr::BinaryOpTy::StarStar, Value::Number(n2)) => {
+                Ok(Value::Number(n1.powf(*n2)))
+            }
+            | (Value::Number(n1), expr::BinaryOpTy::Ampersand, Value::Number(n2))
+            | (Value::Number(n1), expr::BinaryOpTy::Pipe, Value::Number(n2))
+            | (Value::Number(n1), expr::BinaryOpTy::Caret, Value::Number(n2))
+            | (Value::Number(n1), expr::BinaryOpTy::LessLess, Value::Number(n2))
+            | (Value::Number(n1), expr::BinaryOpTy::GreaterGreater, Value::Number(n2)) => {
+                Interpreter::bitwise(*n1, op, *n2).map(Value::Integer)
+            }
             /* 下面是对 字符串、列表操作 */
             (Value::String(s1), expr::BinaryOpTy::Plus, Value::String(s2)) => {
                 Ok(Value::String(format!("{}{}", s1, s2)))
@@ -1336,16 +3320,16 @@ impl Interpreter {
                 Ok(self.create_list(res)) // 返回的是 列表的 Ok(&Vec<Value>)，其中 Value 是列表的 id
             }
             (_, expr::BinaryOpTy::EqualEqual, _) => {
-                Ok(Value::Bool(Interpreter::equals(&lhs, &rhs)))
+                Ok(Value::Bool(Interpreter::equals(lhs, rhs)))
             }
-            (_, expr::BinaryOpTy::NotEqual, _) => Ok(Value::Bool(!Interpreter::equals(&lhs, &rhs))),
+            (_, expr::BinaryOpTy::NotEqual, _) => Ok(Value::Bool(!Interpreter::equals(lhs, rhs))),
             _ =>
                 Err(
                     format!(
                         "invalid operands in binary operator {:?} of type {:?} and {:?} at line={},col={}",
                         op.ty,
-                        type_of(&lhs),
-                        type_of(&rhs),
+                        type_of(lhs),
+                        type_of(rhs),
                         op.line,
                         op.col
                     )
@@ -1353,15 +3337,165 @@ impl Interpreter {
         }
     }
 
+    /**
+     * 左操作数的类定义了对应的特殊方法时，运算交给这个方法；
+     * 比较都归结到 __lt__ 上：a > b 就是 b < a，a <= b 就是 !(b < a)。
+     * __eq__ 和 __lt__ 必须返回 bool，不然 != <= >= 没法取反
+     */
+    fn operator_method(
+        &mut self,
+        lhs: &Value,
+        op: expr::BinaryOp,
+        rhs: &Value
+    ) -> Result<Option<Value>, String> {
+        let (receiver, name, arg, negate) = match op.ty {
+            expr::BinaryOpTy::Plus => (lhs, "__add__", rhs, false),
+            expr::BinaryOpTy::Minus => (lhs, "__sub__", rhs, false),
+            expr::BinaryOpTy::Star => (lhs, "__mul__", rhs, false),
+            expr::BinaryOpTy::EqualEqual => (lhs, "__eq__", rhs, false),
+            expr::BinaryOpTy::NotEqual => (lhs, "__eq__", rhs, true),
+            expr::BinaryOpTy::Less => (lhs, "__lt__", rhs, false),
+            expr::BinaryOpTy::Greater => (rhs, "__lt__", lhs, false),
+            expr::BinaryOpTy::LessEqual => (rhs, "__lt__", lhs, true),
+            expr::BinaryOpTy::GreaterEqual => (lhs, "__lt__", rhs, true),
+            _ => {
+                return Ok(None);
+            }
+        };
+        match self.special_method(receiver, name) {
+            Some(method) => {
+                let res = self.call_special_method(method, std::slice::from_ref(arg))?;
+                match (name, res) {
+                    ("__eq__" | "__lt__", Value::Bool(b)) => Ok(Some(Value::Bool(b != negate))),
+                    ("__eq__" | "__lt__", res) =>
+                        Err(format!("{} must return a bool. Found {:?}.", name, type_of(&res))),
+                    (_, res) => Ok(Some(res)),
+                }
+            }
+            None => Ok(None),
+        }
+    }
+
+    /**
+     * 两个整数的二元运算：溢出报错而不是回绕，/ 和负指数的 ** 得到浮点数
+     */
+    fn integer_op(n1: i64, op: expr::BinaryOp, n2: i64) -> Result<Value, String> {
+        let overflow = || {
+            format!(
+                "integer overflow in binary operator {:?} at line={},col={}",
+                op.ty,
+                op.line,
+                op.col
+            )
+        };
+        let checked = |res: Option<i64>| res.map(Value::Integer).ok_or_else(overflow);
+        if n2 == 0 {
+            if let expr::BinaryOpTy::Slash | expr::BinaryOpTy::Percent | expr::BinaryOpTy::TildeSlash = op.ty {
+                return Err(format!("division by zero at line={},col={}", op.line, op.col));
+            }
+        }
+        match op.ty {
+            expr::BinaryOpTy::EqualEqual => Ok(Value::Bool(n1 == n2)),
+            expr::BinaryOpTy::NotEqual => Ok(Value::Bool(n1 != n2)),
+            expr::BinaryOpTy::Less => Ok(Value::Bool(n1 < n2)),
+            expr::BinaryOpTy::LessEqual => Ok(Value::Bool(n1 <= n2)),
+            expr::BinaryOpTy::Greater => Ok(Value::Bool(n1 > n2)),
+            expr::BinaryOpTy::GreaterEqual => Ok(Value::Bool(n1 >= n2)),
+            expr::BinaryOpTy::Plus => checked(n1.checked_add(n2)),
+            expr::BinaryOpTy::Minus => checked(n1.checked_sub(n2)),
+            expr::BinaryOpTy::Star => checked(n1.checked_mul(n2)),
+            expr::BinaryOpTy::Slash => Ok(Value::Number((n1 as f64) / (n2 as f64))),
+            expr::BinaryOpTy::Percent => checked(n1.checked_rem(n2)),
+            expr::BinaryOpTy::TildeSlash => {
+                let quotient = n1.checked_div(n2).ok_or_else(overflow)?;
+                // 向下取整，和浮点数的 ~/ 保持一致
+                if n1 % n2 != 0 && (n1 < 0) != (n2 < 0) {
+                    Ok(Value::Integer(quotient - 1))
+                } else {
+                    Ok(Value::Integer(quotient))
+                }
+            }
+            expr::BinaryOpTy::StarStar => {
+                if n2 < 0 {
+                    Ok(Value::Number((n1 as f64).powf(n2 as f64)))
+                } else {
+                    checked(u32::try_from(n2).ok().and_then(|exp| n1.checked_pow(exp)))
+                }
+            }
+            | expr::BinaryOpTy::Ampersand
+            | expr::BinaryOpTy::Pipe
+            | expr::BinaryOpTy::Caret
+            | expr::BinaryOpTy::LessLess
+            | expr::BinaryOpTy::GreaterGreater => {
+                Interpreter::integer_bitwise(n1, op, n2).map(Value::Integer)
+            }
+            expr::BinaryOpTy::InstanceOf => unreachable!("instanceof is handled in binary_op"),
+        }
+    }
+
+    /**
+     * 位运算只对整数有意义，浮点数先转成 i64 再算
+     */
+    fn bitwise(n1: f64, op: expr::BinaryOp, n2: f64) -> Result<i64, String> {
+        let as_int = |n: f64| -> Result<i64, String> {
+            if n.fract() == 0.0 && n >= (i64::MIN as f64) && n <= (i64::MAX as f64) {
+                Ok(n as i64)
+            } else {
+                Err(
+                    format!(
+                        "operands of bitwise operator {:?} must be integers, found {} at line={},col={}",
+                        op.ty,
+                        n,
+                        op.line,
+                        op.col
+                    )
+                )
+            }
+        };
+        Interpreter::integer_bitwise(as_int(n1)?, op, as_int(n2)?)
+    }
+
+    fn integer_bitwise(i1: i64, op: expr::BinaryOp, i2: i64) -> Result<i64, String> {
+        let shift_amount = || -> Result<u32, String> {
+            if (0..64).contains(&i2) {
+                Ok(i2 as u32)
+            } else {
+                Err(format!("shift amount {} out of range at line={},col={}", i2, op.line, op.col))
+            }
+        };
+        let res = match op.ty {
+            expr::BinaryOpTy::Ampersand => i1 & i2,
+            expr::BinaryOpTy::Pipe => i1 | i2,
+            expr::BinaryOpTy::Caret => i1 ^ i2,
+            expr::BinaryOpTy::LessLess => i1 << shift_amount()?,
+            expr::BinaryOpTy::GreaterGreater => i1 >> shift_amount()?,
+            _ => panic!("Internal interpreter error: {:?} is not a bitwise operator", op.ty),
+        };
+        Ok(res)
+    }
+
     /**
      * 判断是否相等
      */
     fn equals(lhs: &Value, rhs: &Value) -> bool {
         match (lhs, rhs) {
             (Value::Number(n1), Value::Number(n2)) => (n1 - n2).abs() < f64::EPSILON,
+            (Value::Integer(n1), Value::Integer(n2)) => n1 == n2,
+            (Value::Integer(n1), Value::Number(n2)) | (Value::Number(n2), Value::Integer(n1)) => {
+                ((*n1 as f64) - n2).abs() < f64::EPSILON
+            }
             (Value::String(s1), Value::String(s2)) => s1 == s2,
             (Value::Bool(b1), Value::Bool(b2)) => b1 == b2,
             (Value::Nil, Value::Nil) => true,
+            // 同一个枚举的同一个变体，字段也都相等
+            (Value::Variant(enum_id1, tag1, fields1), Value::Variant(enum_id2, tag2, fields2)) => {
+                enum_id1 == enum_id2 &&
+                    tag1 == tag2 &&
+                    fields1
+                        .iter()
+                        .zip(fields2.iter())
+                        .all(|(field1, field2)| Interpreter::equals(field1, field2))
+            }
             (_, _) => false,
         }
     }
@@ -1374,6 +3508,13 @@ impl Interpreter {
 
         match (op.ty, &val) {
             (expr::UnaryOpTy::Minus, Value::Number(n)) => Ok(Value::Number(-n)),
+            (expr::UnaryOpTy::Minus, Value::Integer(n)) =>
+                n
+                    .checked_neg()
+                    .map(Value::Integer)
+                    .ok_or_else(|| {
+                        format!("integer overflow in unary op Minus at line={},col={}", op.line, op.col)
+                    }),
             (expr::UnaryOpTy::Bang, _) => Ok(Value::Bool(!Interpreter::is_truthy(&val))),
 
             /* ---------- ---------- 不能对其他类型的元素取反 ---------- ---------- */
@@ -1450,6 +3591,24 @@ impl Interpreter {
                         op.col
                     )
                 ),
+            (
+                _,
+                | Value::Generator(_)
+                | Value::GeneratorMethod(_, _)
+                | Value::LoxTrait(_, _)
+                | Value::LoxEnum(_, _)
+                | Value::Variant(_, _, _)
+                | Value::VariantConstructor(_, _),
+            ) =>
+                Err(
+                    format!(
+                        "invalid application of unary op {:?} to object of type {:?} at line={},col={}",
+                        op.ty,
+                        type_of(&val),
+                        op.line,
+                        op.col
+                    )
+                ),
         }
     }
 
@@ -1470,6 +3629,7 @@ impl Interpreter {
     fn interpret_literal(lit: &expr::Literal) -> Value {
         match lit {
             expr::Literal::Number(n) => Value::Number(*n),
+            expr::Literal::Integer(n) => Value::Integer(*n),
             expr::Literal::String(s) => Value::String(s.clone()),
             expr::Literal::True => Value::Bool(true),
             expr::Literal::False => Value::Bool(false),
@@ -1477,19 +3637,123 @@ impl Interpreter {
         }
     }
 
+    /* ---------- ---------- 特殊方法 ---------- ---------- */
+
+    /**
+     * 实例的类（或者父类）上定义了 __add__ 这样的特殊方法，就返回绑定到这个实例上的方法
+     */
+    fn special_method(&self, val: &Value, name: &str) -> Option<Value> {
+        self.class_member(val, name, |cls| &cls.methods)
+    }
+
+    /**
+     * 在实例的类（或者父类）的某张方法表里找，返回绑定到这个实例上的方法
+     */
+    fn class_member(
+        &self,
+        val: &Value,
+        name: &str,
+        members: fn(&LoxClass) -> &HashMap<String, u64>
+    ) -> Option<Value> {
+        match val {
+            Value::LoxInstance(class_name, id) => {
+                let cls = self.get_lox_class(self.get_lox_instance(*id).class_id);
+                cls.find_member(name, members, self).map(|(func_name, method_id)| {
+                    Value::LoxFunction(
+                        func_name,
+                        method_id,
+                        Some(Box::new(Value::LoxInstance(class_name.clone(), *id)))
+                    )
+                })
+            }
+            _ => None,
+        }
+    }
+
+    fn call_special_method(&mut self, method: Value, args: &[Value]) -> Result<Value, String> {
+        let callable = match as_callable(self, &method) {
+            Some(callable) => callable,
+            None => panic!("Internal interpreter error: special method {:?} is not callable", method),
+        };
+        let arity = callable.arity(self);
+        if usize::from(arity) != args.len() {
+            return Err(format!("Expected {} arguments but found {}.", arity, args.len()));
+        }
+        callable.call(self, args)
+    }
+
+    /**
+     * print 和插值字符串用的格式：定义了 __str__ 的实例用它的返回值，列表里面的元素也一样
+     */
+    fn stringify(&mut self, val: &Value) -> Result<String, String> {
+        match val {
+            Value::LoxInstance(_, _) => {
+                match self.special_method(val, "__str__") {
+                    Some(method) =>
+                        match self.call_special_method(method, &[])? {
+                            Value::String(s) => Ok(s),
+                            res =>
+                                Err(format!("__str__ must return a string. Found {:?}.", type_of(&res))),
+                        }
+                    None => Ok(self.format_val(val)),
+                }
+            }
+            Value::List(list_id) => {
+                let elements = self.get_list_elts(*list_id).clone();
+                let mut parts = Vec::new();
+                for element in elements.iter() {
+                    parts.push(self.stringify(element)?);
+                }
+                Ok(format!("[{}]", parts.join(", ")))
+            }
+            Value::Variant(enum_id, tag, fields) => {
+                let mut parts = Vec::new();
+                for field in fields.iter() {
+                    parts.push(self.stringify(field)?);
+                }
+                Ok(self.format_variant(*enum_id, *tag, &parts))
+            }
+            _ => Ok(self.format_val(val)),
+        }
+    }
+
+    /**
+     * 和构造的时候一样的写法：Shape.Circle(1)，没有字段的就是 Shape.Empty
+     */
+    fn format_variant(&self, enum_id: u64, tag: usize, fields: &[String]) -> String {
+        let name = self.get_lox_enum(enum_id).variant_name(tag);
+        if fields.is_empty() { name } else { format!("{}({})", name, fields.join(", ")) }
+    }
+
     /**
      * 返回 Value -> String （转换为可读的形式）
      */
     fn format_val(&self, val: &Value) -> String {
         match val {
             Value::Number(n) => format!("{}", n),
+            Value::Integer(n) => format!("{}", n),
             Value::String(s) => format!("'{}'", s),
             Value::Bool(b) => format!("{}", b),
             Value::Nil => "nil".to_string(),
             Value::NativeFunction(func) => format!("NativeFunction({})", func.name),
             Value::LoxFunction(sym, _, _) => format!("LoxFunction({})", sym.name),
             Value::LoxClass(sym, _) => format!("LoxClass({})", sym.name),
+            Value::LoxTrait(sym, _) => format!("LoxTrait({})", sym.name),
             Value::LoxInstance(sym, _) => format!("LoxInstance({})", sym.name),
+            Value::Generator(id) =>
+                format!("Generator({})", self.generators[id].function.name.name),
+            Value::GeneratorMethod(_, method) => format!("GeneratorMethod({:?})", method),
+            Value::LoxEnum(sym, _) => format!("LoxEnum({})", sym.name),
+            Value::VariantConstructor(enum_id, tag) => {
+                format!("VariantConstructor({})", self.get_lox_enum(*enum_id).variant_name(*tag))
+            }
+            Value::Variant(enum_id, tag, fields) => {
+                let parts: Vec<String> = fields
+                    .iter()
+                    .map(|field| self.format_val(field))
+                    .collect();
+                self.format_variant(*enum_id, *tag, &parts)
+            }
             Value::List(list_id) => {
                 let mut res = String::new();
                 write!(&mut res, "[").unwrap();
@@ -1,5 +1,6 @@
 #[cfg(test)]
 mod tests {
+    use crate::checker;
     use crate::extensions;
     use crate::parser;
     use crate::scanner;
@@ -10,6 +11,14 @@ mod tests {
 
         match parser::parse(options, tokens) {
             Ok(stmts) => {
+                if let Err(errs) = checker::check(&stmts) {
+                    let whats: Vec<_> = errs
+                        .into_iter()
+                        .map(|err| err.what)
+                        .collect();
+                    return Err(whats.join("\n"));
+                }
+
                 let mut interp = treewalk_interpreter::Interpreter::default();
                 let res = interp.interpret(&stmts);
                 match res {
@@ -59,7 +68,7 @@ mod tests {
         check_output(code, expected_output, extensions::Extensions::default())
     }
 
-    fn check_error(code: &str, f: &dyn Fn(&str) -> ()) {
+    fn check_error(code: &str, f: &dyn Fn(&str)) {
         let res = evaluate_default(code);
 
         match res {
@@ -68,13 +77,25 @@ mod tests {
         }
     }
 
+    fn check_error_lists(code: &str, f: &dyn Fn(&str)) {
+        let res = evaluate(code, extensions::Extensions {
+            lists: true,
+            ..Default::default()
+        });
+
+        match res {
+            Ok(output) => panic!("{}", output),
+            Err(err) => f(&err),
+        }
+    }
+
     #[test]
     fn test_fact() {
         fn fact(n: i32) -> i32 {
             if n <= 1 {
                 return 1;
             }
-            return n * fact(n - 1);
+            n * fact(n - 1)
         }
 
         check_output_default(
@@ -423,7 +444,7 @@ mod tests {
             &(|err: &str| {
                 assert_eq!(
                     err,
-                    "TypeError: init should only return nil (perhaps implicitly), not Number"
+                    "TypeError: init should only return nil (perhaps implicitly), not Integer"
                 )
             })
         )
@@ -723,4 +744,1393 @@ mod tests {
             "[1, 3, 5]"
         )
     }
+
+    #[test]
+    fn test_type_annotations_run() {
+        check_output_default(
+            "var x: number = 1;\n\
+             fun greet(name: string): string {\n\
+               return \"hello \" + name;\n\
+             }\n\
+             class Point {\n\
+               x: number;\n\
+               y: number;\n\
+               init(x: number, y: number) {\n\
+                 this.x = x;\n\
+                 this.y = y;\n\
+               }\n\
+             }\n\
+             var p: Point = Point(x, 2);\n\
+             print greet(\"bob\");\n\
+             print p.x + p.y;",
+            "'hello bob'\n3"
+        )
+    }
+
+    #[test]
+    fn test_unannotated_stays_dynamic() {
+        check_output_default("var x = 1;\n\
+             x = \"cat\";\n\
+             print x;", "'cat'")
+    }
+
+    #[test]
+    fn test_type_mismatch_var_decl() {
+        check_error(
+            "var x: number = \"cat\";",
+            &(|err: &str| {
+                assert_eq!(
+                    err,
+                    "TypeError: variable 'x' declared as number but initialized with string at line=1,col=4"
+                )
+            })
+        )
+    }
+
+    #[test]
+    fn test_type_mismatch_assign() {
+        check_error(
+            "var x: string = \"cat\";\n\
+             x = 1 + 2;",
+            &(|err: &str| {
+                assert!(err.starts_with("TypeError: variable 'x' declared as string but assigned number"))
+            })
+        )
+    }
+
+    #[test]
+    fn test_type_mismatch_argument() {
+        check_error(
+            "fun f(a: string, b: number) { return a; }\n\
+             f(\"cat\", \"dog\");",
+            &(|err: &str| { assert!(err.starts_with("TypeError: argument 2 expected number but found string")) })
+        )
+    }
+
+    #[test]
+    fn test_type_mismatch_return() {
+        check_error(
+            "fun f(): list { return true; }",
+            &(|err: &str| {
+                assert!(err.starts_with("TypeError: function declared to return list but returns bool"))
+            })
+        )
+    }
+
+    #[test]
+    fn test_type_mismatch_inferred_through_call() {
+        check_error(
+            "fun f(): number { return 1; }\n\
+             var s: string = f();",
+            &(|err: &str| {
+                assert!(err.starts_with("TypeError: variable 's' declared as string but initialized with number"))
+            })
+        )
+    }
+
+    #[test]
+    fn test_type_mismatch_field() {
+        check_error(
+            "class Point {\n\
+               x: number;\n\
+             }\n\
+             var p: Point = Point();\n\
+             p.x = \"one\";",
+            &(|err: &str| {
+                assert!(err.starts_with("TypeError: field 'x' of Point declared as number but assigned string"))
+            })
+        )
+    }
+
+    #[test]
+    fn test_type_subclass_is_assignable() {
+        check_output_default(
+            "class A {}\n\
+             class B < A {}\n\
+             fun f(a: A): bool { return true; }\n\
+             print f(B());",
+            "true"
+        )
+    }
+
+    #[test]
+    fn test_unannotated_declarations_stay_dynamic() {
+        check_output_lambdas(
+            "fun f() { return 1; }\n\
+             f = 2;\n\
+             print f;\n\
+             class C {}\n\
+             C = \"c\";\n\
+             print C;\n\
+             fun g(a: number) {}\n\
+             g = lambda(x) { return x; };\n\
+             print g(\"not a number\");",
+            "2\n'c'\n'not a number'"
+        )
+    }
+
+    #[test]
+    fn test_check_source_before_running() {
+        let lists = extensions::Extensions { lists: true, ..Default::default() };
+        assert!(checker::check_source("fun f() {}\nf = 1;", lists).is_ok());
+        // 语法错误不在这里报
+        assert!(checker::check_source("var x = ;", lists).is_ok());
+        let errs = checker::check_source("var x: number = 1;\nx = \"one\";", lists).unwrap_err();
+        assert_eq!(errs.len(), 1);
+        assert!(errs[0].what.starts_with("TypeError: variable 'x' declared as number but assigned string"));
+    }
+
+    #[test]
+    fn test_unknown_type_annotation() {
+        let lists = extensions::Extensions { lists: true, ..Default::default() };
+        let errs = checker::check_source("fun f(a: Strng) {}", lists).unwrap_err();
+        assert_eq!(errs.len(), 1);
+        assert_eq!(errs[0].what, "TypeError: Unknown type 'Strng' at line=1,col=13");
+        // 类和 enum 可以在声明之前用在标注里
+        assert!(
+            checker::check_source(
+                "fun f(a: A, s: Shape): A { return a; }\n\
+                 class A { clone(): A { return this; } }\n\
+                 enum Shape { Circle(r) }",
+                lists
+            ).is_ok()
+        );
+    }
+
+    #[test]
+    fn test_arithmetic_operators() {
+        check_output_default(
+            "print 7 % 3;\n\
+             print 2 ** 10;\n\
+             print 7 ~/ 2;\n\
+             print -7 ~/ 2;\n\
+             print 2 ** -1;",
+            "1\n1024\n3\n-4\n0.5"
+        )
+    }
+
+    #[test]
+    fn test_modulo_by_zero() {
+        check_error(
+            "print 1 % 0;",
+            &(|err: &str| assert!(err.starts_with("division by zero")))
+        )
+    }
+
+    #[test]
+    fn test_bitwise_operators() {
+        check_output_default(
+            "print 6 & 3;\n\
+             print 6 | 3;\n\
+             print 6 ^ 3;\n\
+             print 1 << 4;\n\
+             print 256 >> 2;\n\
+             print -8 >> 1;",
+            "2\n7\n5\n16\n64\n-4"
+        )
+    }
+
+    #[test]
+    fn test_bitwise_requires_integers() {
+        check_error(
+            "print 1.5 & 1;",
+            &(|err: &str| assert!(err.starts_with("operands of bitwise operator Ampersand must be integers")))
+        )
+    }
+
+    #[test]
+    fn test_operator_precedence() {
+        check_output_default(
+            "print 2 + 3 * 4 ** 2;\n\
+             print -2 ** 2;\n\
+             print 2 ** 3 ** 2;\n\
+             print 1 | 2 ^ 3 & 4 << 1;\n\
+             print 6 & 3 == 2;\n\
+             print 1 + 1 << 2;\n\
+             print 10 - 7 % 4 * 2;",
+            "50\n-4\n512\n3\ntrue\n8\n4"
+        )
+    }
+
+    #[test]
+    fn test_compound_assignment() {
+        check_output_default(
+            "var x = 10;\n\
+             x += 5;\n\
+             x -= 3;\n\
+             x *= 2;\n\
+             print x /= 4;\n\
+             var s = \"foo\";\n\
+             s += \"bar\";\n\
+             print s;",
+            "6\n'foobar'"
+        )
+    }
+
+    #[test]
+    fn test_compound_assignment_property() {
+        check_output_default(
+            "class Counter {\n\
+               init() { this.n = 1; }\n\
+               bump() { this.n *= 3; return this; }\n\
+             }\n\
+             var c = Counter();\n\
+             c.bump().n += 2;\n\
+             print c.n;",
+            "5"
+        )
+    }
+
+    #[test]
+    fn test_compound_assignment_subscript() {
+        check_output_lists(
+            "class Counter {}\n\
+             var calls = Counter();\n\
+             calls.n = 0;\n\
+             fun idx() { calls.n += 1; return 1; }\n\
+             var xs = [1, 2, 3];\n\
+             xs[idx()] += 10;\n\
+             print xs;\n\
+             print calls.n;",
+            "[1, 12, 3]\n1"
+        )
+    }
+
+    #[test]
+    fn test_increment_decrement() {
+        check_output_default(
+            "var x = 1;\n\
+             print x++;\n\
+             print x;\n\
+             print ++x;\n\
+             print x--;\n\
+             print --x;",
+            "1\n2\n3\n3\n1"
+        )
+    }
+
+    #[test]
+    fn test_increment_property_and_subscript() {
+        check_output_lists(
+            "class Box {}\n\
+             var b = Box();\n\
+             b.v = 1;\n\
+             print b.v++;\n\
+             print ++b.v;\n\
+             var xs = [5];\n\
+             print xs[0]--;\n\
+             print --xs[0];",
+            "1\n3\n5\n3"
+        )
+    }
+
+    #[test]
+    fn test_invalid_compound_assignment_target() {
+        check_error(
+            "var a = 1; var b = 2; a + b += 1;",
+            &(|err: &str| assert!(err.starts_with("invalid assignment target")))
+        )
+    }
+
+    #[test]
+    fn test_break_and_continue() {
+        check_output_default(
+            "var i = 0;\n\
+             while (true) {\n\
+               i = i + 1;\n\
+               if (i == 2) continue;\n\
+               if (i > 4) break;\n\
+               print i;\n\
+             }\n\
+             for (var j = 0; j < 10; j = j + 1) {\n\
+               if (j % 2 == 0) continue;\n\
+               if (j > 5) break;\n\
+               print j;\n\
+             }",
+            "1\n3\n4\n1\n3\n5"
+        )
+    }
+
+    #[test]
+    fn test_nested_loops_break_inner_only() {
+        check_output_default(
+            "for (var i = 0; i < 3; i = i + 1) {\n\
+               for (var j = 0; j < 3; j = j + 1) {\n\
+                 if (j == 1) break;\n\
+                 print i * 10 + j;\n\
+               }\n\
+             }",
+            "0\n10\n20"
+        )
+    }
+
+    #[test]
+    fn test_return_from_inside_loop() {
+        check_output_default(
+            "fun find(n) {\n\
+               var i = 0;\n\
+               while (true) {\n\
+                 if (i * i >= n) return i;\n\
+                 i = i + 1;\n\
+               }\n\
+             }\n\
+             print find(10);",
+            "4"
+        )
+    }
+
+    #[test]
+    fn test_do_while() {
+        check_output_default(
+            "var i = 10;\n\
+             do {\n\
+               print i;\n\
+               i = i + 1;\n\
+             } while (i < 3);\n\
+             var j = 0;\n\
+             do {\n\
+               j = j + 1;\n\
+               if (j == 2) continue;\n\
+               print j;\n\
+             } while (j < 4);",
+            "10\n1\n3\n4"
+        )
+    }
+
+    #[test]
+    fn test_for_in_list() {
+        check_output_lists(
+            "var total = 0;\n\
+             for (var x in [1, 2, 3, 4]) {\n\
+               if (x == 3) continue;\n\
+               total = total + x;\n\
+             }\n\
+             print total;\n\
+             for (var s in []) print s;",
+            "7"
+        )
+    }
+
+    #[test]
+    fn test_for_in_iterator_protocol() {
+        check_output_default(
+            "class Countdown {\n\
+               init(n) { this.n = n; }\n\
+               next() {\n\
+                 if (this.n == 0) return nil;\n\
+                 this.n = this.n - 1;\n\
+                 return this.n + 1;\n\
+               }\n\
+             }\n\
+             class Range {\n\
+               init(n) { this.n = n; }\n\
+               iter() { return Countdown(this.n); }\n\
+             }\n\
+             for (var i in Range(3)) print i;\n\
+             for (var i in Countdown(5)) {\n\
+               if (i == 3) break;\n\
+               print i;\n\
+             }",
+            "3\n2\n1\n5\n4"
+        )
+    }
+
+    #[test]
+    fn test_for_in_closures_capture_each_iteration() {
+        check_output_lists(
+            "var fns = [nil, nil, nil];\n\
+             for (var x in [1, 2, 3]) {\n\
+               fun get() { return x; }\n\
+               fns[x - 1] = get;\n\
+             }\n\
+             for (var f in fns) print f();",
+            "1\n2\n3"
+        )
+    }
+
+    #[test]
+    fn test_for_in_not_iterable() {
+        check_error(
+            "for (var x in 42) print x;",
+            &(|err: &str| assert_eq!(err, "Cannot iterate over value of type Integer at line=1,col=9"))
+        )
+    }
+
+    #[test]
+    fn test_break_outside_loop() {
+        check_error(
+            "fun f() { break; }",
+            &(|err: &str| assert!(err.starts_with("break statement not enclosed in a loop")))
+        );
+        check_error(
+            "while (true) { fun f() { continue; } }",
+            &(|err: &str| assert!(err.starts_with("continue statement not enclosed in a loop")))
+        )
+    }
+
+    #[test]
+    fn test_generator_next() {
+        check_output_default(
+            "fun count(n) {\n\
+               print \"start\";\n\
+               for (var i = 0; i < n; i = i + 1) yield i;\n\
+             }\n\
+             var g = count(2);\n\
+             print \"created\";\n\
+             print g.next();\n\
+             print g.next();\n\
+             print g.next();\n\
+             print g.next();",
+            "'created'\n'start'\n0\n1\nnil\nnil"
+        )
+    }
+
+    #[test]
+    fn test_generator_resumes_nested_statements() {
+        check_output_default(
+            "fun gen() {\n\
+               var total = 0;\n\
+               while (true) {\n\
+                 total = total + 1;\n\
+                 if (total > 4) return;\n\
+                 {\n\
+                   var doubled = total * 2;\n\
+                   if (total % 2 == 0) {\n\
+                     yield doubled;\n\
+                   } else {\n\
+                     yield -doubled;\n\
+                   }\n\
+                   doubled = doubled + 100;\n\
+                   yield doubled;\n\
+                 }\n\
+               }\n\
+             }\n\
+             for (var x in gen()) print x;",
+            "-2\n102\n4\n104\n-6\n106\n8\n108"
+        )
+    }
+
+    #[test]
+    fn test_generator_for_in_nested() {
+        check_output_lists(
+            "fun pairs(xs) {\n\
+               for (var x in xs) {\n\
+                 for (var y in xs) {\n\
+                   if (x < y) yield x * 10 + y;\n\
+                 }\n\
+               }\n\
+             }\n\
+             for (var p in pairs([1, 2, 3])) print p;",
+            "12\n13\n23"
+        )
+    }
+
+    #[test]
+    fn test_generator_methods_and_iter_protocol() {
+        check_output_lists(
+            "class Bag {\n\
+               init(items) { this.items = items; }\n\
+               iter() {\n\
+                 for (var item in this.items) yield item;\n\
+               }\n\
+             }\n\
+             for (var x in Bag([3, 1, 2])) print x;\n\
+             var g = Bag([7]).iter();\n\
+             var next = g.next;\n\
+             print next();\n\
+             print next();",
+            "3\n1\n2\n7\nnil"
+        )
+    }
+
+    #[test]
+    fn test_infinite_generator_with_break() {
+        check_output_default(
+            "fun naturals() {\n\
+               var n = 0;\n\
+               while (true) {\n\
+                 yield n;\n\
+                 n = n + 1;\n\
+               }\n\
+             }\n\
+             for (var n in naturals()) {\n\
+               if (n == 3) break;\n\
+               print n;\n\
+             }",
+            "0\n1\n2"
+        )
+    }
+
+    #[test]
+    fn test_lazy_builtins() {
+        check_output_lambdas_lists(
+            "for (var x in range(0, 3)) print x;\n\
+             var evens = filter(lambda(x) { return x % 2 == 0; }, range(0, 100000000));\n\
+             var squares = map(lambda(x) { return x * x; }, evens);\n\
+             print squares.next();\n\
+             print squares.next();\n\
+             print squares.next();\n\
+             print filter(lambda(x) { return x > 1; }, [1, 2, 3]);\n\
+             print map(lambda(x) { return x + 1; }, [1, 2]);",
+            "0\n1\n2\n0\n4\n16\n[2, 3]\n[2, 3]"
+        )
+    }
+
+    #[test]
+    fn test_nil_elements_do_not_end_iteration() {
+        check_output_lambdas_lists(
+            "fun gen() { yield 1; yield nil; yield 3; }\n\
+             for (var x in gen()) print x;\n\
+             for (var x in map(lambda(x) { if (x != 2) return x; }, range(1, 4))) print x;",
+            "1\nnil\n3\n1\nnil\n3"
+        )
+    }
+
+    #[test]
+    fn test_generator_already_running() {
+        check_error(
+            "class Box {}\n\
+             var box = Box();\n\
+             fun gen() { yield box.g.next(); }\n\
+             box.g = gen();\n\
+             box.g.next();",
+            &(|err: &str| assert!(err.starts_with("generator gen is already running")))
+        )
+    }
+
+    #[test]
+    fn test_invalid_yield() {
+        check_error(
+            "yield 1;",
+            &(|err: &str| assert!(err.starts_with("yield statement not enclosed in a FunDecl")))
+        );
+        check_error(
+            "fun f() { yield 1; return 2; }",
+            &(|err: &str| assert!(err.starts_with("cannot return a value from a generator")))
+        );
+        check_error(
+            "class A { init() { yield 1; } }",
+            &(|err: &str| assert!(err.starts_with("init cannot be a generator")))
+        )
+    }
+
+    #[test]
+    fn test_list_mutation() {
+        check_output_lists(
+            "var xs = [1, 2];\n\
+             push(xs, 3);\n\
+             print xs;\n\
+             print pop(xs);\n\
+             insert(xs, 0, 0);\n\
+             insert(xs, -1, 9);\n\
+             print xs;\n\
+             print remove(xs, 2);\n\
+             print xs;\n\
+             print contains(xs, 2);\n\
+             print contains(xs, 5);\n\
+             print indexOf(xs, 1);\n\
+             print indexOf(xs, 5);\n\
+             print concat(xs, [7]);\n\
+             print reverse(xs);\n\
+             print xs;",
+            "[1, 2, 3]\n3\n[0, 1, 9, 2]\n9\n[0, 1, 2]\ntrue\nfalse\n1\n-1\n[0, 1, 2, 7]\n[2, 1, 0]\n[0, 1, 2]"
+        )
+    }
+
+    #[test]
+    fn test_reduce_and_sort() {
+        check_output_lambdas_lists(
+            "var xs = [3, 1, 2];\n\
+             print reduce(lambda(acc, x) { return acc + x; }, xs, 0);\n\
+             print sort(xs);\n\
+             print sort(xs, lambda(a, b) { return b - a; });\n\
+             print xs;\n\
+             print sort([\"pear\", \"apple\"]);\n\
+             var pairs = [[1, \"b\"], [0, \"a\"], [1, \"a\"]];\n\
+             print sort(pairs, lambda(p, q) { return p[0] - q[0]; });",
+            "6\n[1, 2, 3]\n[3, 2, 1]\n[3, 1, 2]\n['apple', 'pear']\n[[0, 'a'], [1, 'b'], [1, 'a']]"
+        )
+    }
+
+    #[test]
+    fn test_list_slices() {
+        check_output_lists(
+            "var xs = [0, 1, 2, 3, 4];\n\
+             print xs[1:3];\n\
+             print xs[:2];\n\
+             print xs[3:];\n\
+             print xs[:];\n\
+             print xs[-2:];\n\
+             print xs[3:1];\n\
+             print xs[2:100];",
+            "[1, 2]\n[0, 1]\n[3, 4]\n[0, 1, 2, 3, 4]\n[3, 4]\n[]\n[2, 3, 4]"
+        )
+    }
+
+    #[test]
+    fn test_list_library_errors() {
+        check_error_lists(
+            "pop([]);",
+            &(|err: &str| assert_eq!(err, "Can't pop from an empty list."))
+        );
+        check_error_lists(
+            "sort([1, \"a\"]);",
+            &(|err: &str| assert_eq!(err, "Can't compare values of type Integer and String in sort."))
+        );
+        check_error_lists(
+            "remove([1], 1);",
+            &(|err: &str| assert_eq!(err, "List index out of range in remove."))
+        );
+        check_error_lists(
+            "push(1, 2);",
+            &(|err: &str| assert_eq!(err, "Can't call push on value of type Integer."))
+        );
+        check_error_lists(
+            "sort([1], 2);",
+            &(|err: &str| assert_eq!(err, "The second argument to sort must be callable. Found Integer."))
+        );
+        check_error_lists(
+            "insert([1, 2], 1.5, 0);",
+            &(|err: &str| assert_eq!(err, "Invalid index 1.5 in insert."))
+        );
+        check_error_lists(
+            "var xs = [1, 2]; print xs[1.0]; print xs[1.7];",
+            &(|err: &str| assert_eq!(err, "Invalid subscript 1.7 in subscript expression"))
+        );
+        check_error_lists(
+            "var xs = [1, 2]; print xs[0.5:];",
+            &(|err: &str| assert_eq!(err, "Invalid slice bound 0.5 in slice expression at line=1,col=30"))
+        );
+        check_error_lists(
+            "sort([], nil, nil);",
+            &(|err: &str| assert!(err.contains("callee has arity 1-2, but was called with 3 arguments")))
+        );
+        check_error_lists(
+            "var xs = [1]; xs[0:1] = 2;",
+            &(|err: &str| assert!(err.starts_with("invalid assignment target")))
+        );
+    }
+
+    #[test]
+    fn test_unicode_source() {
+        check_output_default(
+            "var 问候 = \"你好\\t世界\";\n\
+             /* 块注释 */\n\
+             print 问候;\n\
+             print len(问候);\n\
+             print 0xff + 1_000;",
+            "'你好\t世界'\n5\n1255"
+        )
+    }
+
+    #[test]
+    fn test_string_interpolation() {
+        check_output_lambdas_lists(
+            "var name = \"bob\";\n\
+             var items = [1, 2, 3];\n\
+             print \"hello ${name}, you have ${len(items)} items\";\n\
+             print \"${1 + 2}${nil} ${true}\";\n\
+             print \"outer ${\"inner ${name + \"!\"}\"} done\";\n\
+             print \"not \\${name}\";\n\
+             var f = lambda(x) { return x * 2; };\n\
+             print \"\"\"say \"${f(21)}\" now\"\"\";\n\
+             print \"${items}\";",
+            "'hello bob, you have 3 items'\n'3nil true'\n'outer inner bob! done'\n'not ${name}'\n'say \"42\" now'\n'[1, 2, 3]'"
+        )
+    }
+
+    #[test]
+    fn test_integer_arithmetic() {
+        check_output_default(
+            "print 9007199254740993 + 2;\n\
+             print 7 / 2;\n\
+             print 7 ~/ 2;\n\
+             print -7 ~/ 2;\n\
+             print -7 % 2;\n\
+             print 2 ** 62;\n\
+             print 2 ** -1;\n\
+             print 1 + 0.5;\n\
+             print 3 == 3.0;\n\
+             print 2 < 2.5;\n\
+             print 6.0 & 3;",
+            "9007199254740995\n3.5\n3\n-4\n-1\n4611686018427387904\n0.5\n1.5\ntrue\ntrue\n2"
+        )
+    }
+
+    #[test]
+    fn test_integer_overflow() {
+        check_error(
+            "print 9223372036854775807 + 1;",
+            &(|err: &str| assert!(err.starts_with("integer overflow in binary operator Plus")))
+        );
+        check_error(
+            "print 2 ** 63;",
+            &(|err: &str| assert!(err.starts_with("integer overflow in binary operator StarStar")))
+        );
+        check_error("print 7 % 0;", &(|err: &str| assert!(err.starts_with("division by zero"))))
+    }
+
+    #[test]
+    fn test_operator_overloading() {
+        check_output_lists(
+            "class Vec {\n\
+               init(x, y) { this.x = x; this.y = y; }\n\
+               __add__(other) { return Vec(this.x + other.x, this.y + other.y); }\n\
+               __sub__(other) { return Vec(this.x - other.x, this.y - other.y); }\n\
+               __mul__(k) { return Vec(this.x * k, this.y * k); }\n\
+               __eq__(other) { return this.x == other.x and this.y == other.y; }\n\
+               __lt__(other) { return this.x + this.y < other.x + other.y; }\n\
+               __str__() { return \"Vec(${this.x}, ${this.y})\"; }\n\
+             }\n\
+             var a = Vec(1, 2);\n\
+             var b = Vec(3, 4);\n\
+             print a + b;\n\
+             print b - a;\n\
+             print a * 3;\n\
+             print a == Vec(1, 2);\n\
+             print a != b;\n\
+             print a < b;\n\
+             print a > b;\n\
+             print a <= a;\n\
+             print b >= a;\n\
+             a += b;\n\
+             print [a, b];\n\
+             print \"sum is ${a + b}\";",
+            "Vec(4, 6)\nVec(2, 2)\nVec(3, 6)\ntrue\ntrue\ntrue\nfalse\ntrue\ntrue\n[Vec(4, 6), Vec(3, 4)]\n'sum is Vec(7, 10)'"
+        )
+    }
+
+    #[test]
+    fn test_container_special_methods() {
+        check_output_lists("class Bag {\n\
+               __getitem__(i) { return this.items[i]; }\n\
+               __setitem__(i, v) { this.items[i] = v; }\n\
+               __len__() { return len(this.items); }\n\
+               __call__(x) { return x * 10; }\n\
+             }\n\
+             class Sub < Bag {}\n\
+             var bag = Sub();\n\
+             bag.items = [0, 0, 0];\n\
+             bag[1] = 5;\n\
+             bag[0] += 2;\n\
+             print bag[0] + bag[1];\n\
+             print len(bag);\n\
+             print bag(4);", "7\n3\n40")
+    }
+
+    #[test]
+    fn test_str_must_return_string() {
+        check_error(
+            "class A { __str__() { return 1; } }\n\
+             print A();",
+            &(|err: &str| assert_eq!(err, "__str__ must return a string. Found Integer."))
+        )
+    }
+
+    #[test]
+    fn test_comparison_methods_must_return_bool() {
+        // != <= >= 要把结果取反，两个引擎都要求 __eq__ 和 __lt__ 返回 bool
+        check_error(
+            "class A { __eq__(other) { return 1; } }\n\
+             print A() != A();",
+            &(|err: &str| assert_eq!(err, "__eq__ must return a bool. Found Integer."))
+        );
+        check_error(
+            "class A { __eq__(other) { return nil; } }\n\
+             print A() == A();",
+            &(|err: &str| assert_eq!(err, "__eq__ must return a bool. Found Nil."))
+        );
+        check_error(
+            "class A { __lt__(other) { return \"yes\"; } }\n\
+             print A() <= A();",
+            &(|err: &str| assert_eq!(err, "__lt__ must return a bool. Found String."))
+        );
+        check_output_default(
+            "class A { __eq__(other) { return false; } __lt__(other) { return true; } }\n\
+             print A() != A();\n\
+             print A() >= A();",
+            "true\nfalse"
+        );
+    }
+
+    #[test]
+    fn test_class_methods_getters_setters() {
+        check_output_default(
+            "class Shape {\n\
+               init(size) { this.size = size; }\n\
+               class unit() { return this(1); }\n\
+               class describe() { return \"shape\"; }\n\
+               area { return this.size * this.size; }\n\
+               set side(v) { this.size = v; }\n\
+             }\n\
+             class Square < Shape {}\n\
+             var s = Shape.unit();\n\
+             print s.area;\n\
+             s.side = 3;\n\
+             print s.area;\n\
+             print s.size;\n\
+             print Square.describe();",
+            "1\n9\n3\n'shape'"
+        )
+    }
+
+    #[test]
+    fn test_super_getter() {
+        check_output_default(
+            "class Named {\n\
+               name { return this.first; }\n\
+             }\n\
+             class Titled < Named {\n\
+               name { return \"Dr. \" + super.name; }\n\
+               plain() { return super.name; }\n\
+             }\n\
+             class Formal < Titled {\n\
+               name { return super.name + \", PhD\"; }\n\
+             }\n\
+             var t = Titled();\n\
+             t.first = \"Ada\";\n\
+             print t.name;\n\
+             print t.plain();\n\
+             var f = Formal();\n\
+             f.first = \"Ada\";\n\
+             print f.name;",
+            "'Dr. Ada'\n'Ada'\n'Dr. Ada, PhD'"
+        )
+    }
+
+    #[test]
+    fn test_instanceof() {
+        check_output_default(
+            "class A {}\n\
+             class B < A {}\n\
+             class C {}\n\
+             var b = B();\n\
+             print b instanceof B;\n\
+             print b instanceof A;\n\
+             print b instanceof C;\n\
+             print A() instanceof B;\n\
+             print 1 instanceof A;\n\
+             print isInstance(b, A);\n\
+             print isInstance(nil, A);",
+            "true\ntrue\nfalse\nfalse\nfalse\ntrue\nfalse"
+        )
+    }
+
+    #[test]
+    fn test_instanceof_non_class() {
+        check_error(
+            "class A {}\n\
+             print A() instanceof 1;",
+            &(|err: &str| assert_eq!(err, "Can't check instanceof against a value of type Integer."))
+        )
+    }
+
+    #[test]
+    fn test_setter_arity() {
+        check_error(
+            "class A { set x(a, b) {} }",
+            &(|err: &str| assert!(err.starts_with("a setter must take exactly one parameter")))
+        )
+    }
+
+    #[test]
+    fn test_traits() {
+        check_output_default(
+            "trait Greets {\n\
+               greet() { return \"hello from \" + this.name(); }\n\
+               name();\n\
+             }\n\
+             trait Shouts {\n\
+               shout() { return this.greet() + \"!\"; }\n\
+             }\n\
+             class Base { name() { return \"base\"; } }\n\
+             class Dog < Base with Greets, Shouts {}\n\
+             class Puppy < Dog { name() { return \"puppy\"; } }\n\
+             print Dog().greet();\n\
+             print Puppy().shout();\n\
+             print Puppy() instanceof Greets;\n\
+             print isInstance(Dog(), Shouts);\n\
+             print Base() instanceof Greets;",
+            "'hello from base'\n'hello from puppy!'\ntrue\ntrue\nfalse"
+        )
+    }
+
+    #[test]
+    fn test_trait_method_resolution_order() {
+        check_output_default(
+            "trait Loud { speak() { return \"LOUD\"; } }\n\
+             class Animal { speak() { return \"animal\"; } }\n\
+             class Cat < Animal with Loud {}\n\
+             class Lion < Animal with Loud { speak() { return \"roar\"; } }\n\
+             trait A { f() { return \"A\"; } }\n\
+             trait B { f() { return \"B\"; } }\n\
+             class C with A, B { f() { return \"C\"; } }\n\
+             print Cat().speak();\n\
+             print Lion().speak();\n\
+             print C().f();",
+            "'LOUD'\n'roar'\n'C'"
+        )
+    }
+
+    #[test]
+    fn test_trait_init_returns_this() {
+        check_output_default(
+            "trait Counted { init(n) { this.n = n; } }\n\
+             class C with Counted {}\n\
+             var c = C(2);\n\
+             print c instanceof C;\n\
+             print c.n;\n\
+             print c.init(3) instanceof C;\n\
+             print c.n;",
+            "true\n2\ntrue\n3"
+        )
+    }
+
+    #[test]
+    fn test_trait_conflict() {
+        check_error(
+            "trait A { f() {} }\n\
+             trait B { f() {} }\n\
+             class C with A, B {}",
+            &(|err: &str| assert_eq!(err, "Conflicting method 'f' from traits A and B in class C."))
+        )
+    }
+
+    #[test]
+    fn test_trait_required_method() {
+        check_error(
+            "trait T { g(x); }\n\
+             class C with T {}",
+            &(|err: &str| assert_eq!(err, "Class C must provide method 'g' required by trait T."))
+        )
+    }
+
+    #[test]
+    fn test_match_literals_and_wildcard() {
+        check_output_default(
+            "fun describe(x) {\n\
+               match (x) {\n\
+                 case 0 => return \"zero\";\n\
+                 case -1 => return \"minus one\";\n\
+                 case \"hi\" => return \"greeting\";\n\
+                 case nil => return \"nothing\";\n\
+                 case _ => return \"other\";\n\
+               }\n\
+             }\n\
+             print describe(0);\n\
+             print describe(-1);\n\
+             print describe(\"hi\");\n\
+             print describe(nil);\n\
+             print describe(true);",
+            "'zero'\n'minus one'\n'greeting'\n'nothing'\n'other'"
+        )
+    }
+
+    #[test]
+    fn test_match_no_arm_matches() {
+        check_output_default(
+            "match (3) {\n\
+               case 1 => print \"one\";\n\
+               case 2 => print \"two\";\n\
+             }\n\
+             print \"done\";",
+            "'done'"
+        )
+    }
+
+    #[test]
+    fn test_match_list_patterns() {
+        check_output_lists(
+            "fun f(xs) {\n\
+               match (xs) {\n\
+                 case [] => print \"empty\";\n\
+                 case [x] => print x;\n\
+                 case [1, [a, b]] => print a + b;\n\
+                 case [first, _, last] => print first + last;\n\
+                 case _ => print \"no match\";\n\
+               }\n\
+             }\n\
+             f([]);\n\
+             f([7]);\n\
+             f([1, [2, 3]]);\n\
+             f([10, 20, 30]);\n\
+             f([1, 2, 3, 4]);\n\
+             f(5);",
+            "'empty'\n7\n5\n40\n'no match'\n'no match'"
+        )
+    }
+
+    #[test]
+    fn test_match_instance_and_guard() {
+        check_output_default(
+            "class Point { init(x, y) { this.x = x; this.y = y; } }\n\
+             fun where(p) {\n\
+               match (p) {\n\
+                 case Point(x: 0, y: 0) => print \"origin\";\n\
+                 case Point(x, y: 0) => print x;\n\
+                 case Point(x, y) if x == y => print \"diagonal\";\n\
+                 case Point(x, y) => print x * y;\n\
+                 case other => print other;\n\
+               }\n\
+             }\n\
+             where(Point(0, 0));\n\
+             where(Point(4, 0));\n\
+             where(Point(2, 2));\n\
+             where(Point(2, 3));\n\
+             where(\"else\");",
+            "'origin'\n4\n'diagonal'\n6\n'else'"
+        )
+    }
+
+    #[test]
+    fn test_match_missing_field_fails_arm() {
+        check_output_default(
+            "class Shape { area { return 1; } }\n\
+             fun describe(s) {\n\
+               match (s) {\n\
+                 case Shape(radius) => print radius;\n\
+                 case Shape(w, h: 2) => print w;\n\
+                 case Shape(area) => print area;\n\
+               }\n\
+             }\n\
+             var circle = Shape();\n\
+             circle.radius = 3;\n\
+             describe(circle);\n\
+             var rect = Shape();\n\
+             rect.w = 4;\n\
+             rect.h = 2;\n\
+             describe(rect);\n\
+             describe(Shape());",
+            "3\n4\n1"
+        )
+    }
+
+    #[test]
+    fn test_match_duplicate_binding() {
+        check_error_lists(
+            "match ([1, 2]) { case [x, x] => print x; }",
+            &(|err: &str| assert!(err.starts_with("duplicate binding 'x' in pattern")))
+        )
+    }
+
+    #[test]
+    fn test_default_parameters() {
+        check_output_default(
+            "fun triple(x) { return 3 * x; }\n\
+             fun greet(name, greeting = \"hello\") { return greeting + \" \" + name; }\n\
+             fun f(a, b = a * 2, c = triple(a)) { print a + b + c; }\n\
+             print greet(\"bob\");\n\
+             print greet(\"bob\", \"hi\");\n\
+             f(1);\n\
+             f(1, 1);\n\
+             f(1, 1, 100);\n\
+             f(2, c: 0);",
+            "'hello bob'\n'hi bob'\n6\n5\n102\n6"
+        )
+    }
+
+    #[test]
+    fn test_rest_parameters() {
+        check_output_default(
+            "fun sum(first, ...rest) {\n\
+               var total = first;\n\
+               for (var x in rest) total = total + x;\n\
+               return total;\n\
+             }\n\
+             fun count(...xs) { var n = 0; for (var x in xs) n = n + 1; return n; }\n\
+             print sum(1);\n\
+             print sum(1, 2, 3);\n\
+             print count();\n\
+             print count(nil, nil);",
+            "1\n6\n0\n2"
+        )
+    }
+
+    #[test]
+    fn test_named_arguments() {
+        check_output_default(
+            "fun volume(width, height = 1, depth = 1) { return width * height * depth; }\n\
+             class Point {\n\
+               init(x = 0, y = 0) { this.x = x; this.y = y; }\n\
+               moved(dx = 0, dy = 0) { return Point(x: this.x + dx, y: this.y + dy); }\n\
+             }\n\
+             print volume(2, depth: 3);\n\
+             print volume(height: 2, width: 5);\n\
+             var p = Point(y: 5).moved(dy: 1);\n\
+             print p.x;\n\
+             print p.y;",
+            "6\n10\n0\n6"
+        )
+    }
+
+    #[test]
+    fn test_argument_errors() {
+        check_error(
+            "fun f(a, b, c = 1) {}\nf(c: 2);",
+            &(|err: &str| assert!(err.ends_with("missing arguments: a, b"), "{}", err))
+        );
+        check_error(
+            "fun f(a, b = 1) {}\nf(1, 2, 3);",
+            &(|err: &str| assert!(err.ends_with("callee has arity 1-2, but was called with 3 arguments")))
+        );
+        check_error(
+            "fun f(a) {}\nf(1, b: 2);",
+            &(|err: &str| assert!(err.ends_with("callee has no parameter named 'b'")))
+        );
+        check_error(
+            "fun f(a) {}\nf(1, a: 2);",
+            &(|err: &str| assert!(err.ends_with("got multiple values for argument 'a'")))
+        );
+        check_error(
+            "fun f(a = 1, b) {}",
+            &(|err: &str| {
+                assert!(err.starts_with("parameter 'b' without a default follows a parameter with a default"))
+            })
+        );
+        check_error(
+            "fun f(...a, b) {}",
+            &(|err: &str| assert!(err.starts_with("rest parameter must be the last parameter")))
+        );
+        check_error(
+            "fun f(a, b) {}\nf(a: 1, 2);",
+            &(|err: &str| assert!(err.starts_with("positional argument follows named arguments")))
+        );
+    }
+
+    #[test]
+    fn test_const_declarations() {
+        check_output_default(
+            "const x = 1;\n\
+             let y: number = 2;\n\
+             fun f() { return x + y; }\n\
+             {\n\
+               var x = 10;\n\
+               x = x + 1;\n\
+               print x;\n\
+             }\n\
+             fun g(y) { y = y * 2; return y; }\n\
+             print f();\n\
+             print g(5);",
+            "11\n3\n10"
+        )
+    }
+
+    #[test]
+    fn test_const_errors() {
+        check_error(
+            "const x = 1;\nx = 2;",
+            &(|err: &str| assert_eq!(err, "cannot assign to constant 'x' at line=2,col=1"))
+        );
+        check_error(
+            "let x = 1;\nfun f() { x = 2; }",
+            &(|err: &str| assert!(err.starts_with("cannot assign to constant 'x'"), "{}", err))
+        );
+        check_error(
+            "const x = 1;\nfun f() { fun g() { x += 1; } return g; }",
+            &(|err: &str| assert!(err.starts_with("cannot assign to constant 'x'"), "{}", err))
+        );
+        check_error(
+            "const x = 1;\nx++;",
+            &(|err: &str| assert!(err.starts_with("cannot assign to constant 'x'"), "{}", err))
+        );
+        check_error(
+            "const x = 1;\nvar x = 2;",
+            &(|err: &str| assert!(err.starts_with("cannot redefine constant 'x'"), "{}", err))
+        );
+        check_error(
+            "const x;",
+            &(|err: &str| assert!(err.ends_with("Constants must be initialized"), "{}", err))
+        );
+    }
+
+    #[test]
+    fn test_freeze() {
+        check_output_lists(
+            "class Point { init(x, y) { this.x = x; this.y = y; } }\n\
+             var p = freeze(Point(1, 2));\n\
+             var xs = freeze([1, 2, 3]);\n\
+             print p.x + p.y;\n\
+             print xs[1];\n\
+             print reverse(xs);",
+            "3\n2\n[3, 2, 1]"
+        );
+        check_error_lists(
+            "class Point { init(x) { this.x = x; } }\nvar p = freeze(Point(1));\np.x = 2;",
+            &(|err: &str| assert_eq!(err, "Can't set attribute x on a frozen instance."))
+        );
+        check_error_lists(
+            "var xs = freeze([1, 2]);\nxs[0] = 3;",
+            &(|err: &str| assert_eq!(err, "Can't modify a frozen list."))
+        );
+        check_error_lists(
+            "var xs = freeze([1, 2]);\npush(xs, 3);",
+            &(|err: &str| assert_eq!(err, "Can't call push on a frozen list."))
+        );
+        check_error_lists(
+            "freeze(1);",
+            &(|err: &str| assert_eq!(err, "Can't freeze a value of type Integer."))
+        );
+    }
+
+    #[test]
+    fn test_enums() {
+        check_output_default(
+            "enum Shape { Circle(r), Rect(w, h), Empty }\n\
+             var c = Shape.Circle(1);\n\
+             var r = Shape.Rect(h: 3, w: 2);\n\
+             print c;\n\
+             print r.w * r.h;\n\
+             print Shape.Empty;\n\
+             print tag(r);\n\
+             print c == Shape.Circle(1);\n\
+             print c == Shape.Circle(2);\n\
+             print Shape.Empty == Shape.Empty;\n\
+             print Shape.Empty == Shape.Circle(1);",
+            "Shape.Circle(1)\n6\nShape.Empty\n'Rect'\ntrue\nfalse\ntrue\nfalse"
+        );
+        check_output_default(
+            "enum Color { Red, Green, }\n\
+             enum Other { Red }\n\
+             print Color.Red == Other.Red;\n\
+             print Color.Green;",
+            "false\nColor.Green"
+        );
+    }
+
+    #[test]
+    fn test_enum_errors() {
+        check_error(
+            "enum Shape { Circle(r) }\nShape.Square;",
+            &(|err: &str| assert_eq!(err, "AttributeError: enum 'Shape' has no variant 'Square'."))
+        );
+        check_error(
+            "enum Shape { Circle(r) }\nShape.Circle(1).x;",
+            &(|err: &str| assert_eq!(err, "AttributeError: variant 'Shape.Circle' has no field 'x'."))
+        );
+        check_error(
+            "enum Shape { Circle(r) }\nShape.Circle(1, 2);",
+            &(|err: &str| assert!(err.contains("callee has arity 1, but was called with 2 arguments"), "{}", err))
+        );
+        check_error(
+            "enum Shape { Circle(r), Circle }",
+            &(|err: &str| assert!(err.contains("duplicate variant 'Circle'"), "{}", err))
+        );
+        check_error(
+            "enum Shape { Rect(w, w) }",
+            &(|err: &str| assert!(err.contains("duplicate field 'w'"), "{}", err))
+        );
+        check_error(
+            "tag(1);",
+            &(|err: &str| assert_eq!(err, "Can't get the tag of a value of type Integer."))
+        );
+    }
+
+    #[test]
+    fn test_tail_calls() {
+        check_output_default(
+            "fun sum(n, acc) { if (n == 0) return acc; return sum(n - 1, acc + n); }\n\
+             print sum(20000, 0);\n\
+             fun isEven(n) { if (n == 0) return true; return isOdd(n - 1); }\n\
+             fun isOdd(n) { if (n == 0) return false; return isEven(n - 1); }\n\
+             print isEven(20001);\n\
+             fun countdown(n, step = 1) { if (n <= 0) return n; return countdown(step: step, n: n - step); }\n\
+             print countdown(10, 3);\n\
+             fun outer() { var x = 10; fun inner() { return x; } return inner(); }\n\
+             print outer();\n\
+             fun size(s) { return len(s); }\n\
+             print size(\"abc\");",
+            "200010000\nfalse\n-2\n10\n3"
+        );
+        check_output_default(
+            "class Counter {\n\
+               count(n, acc) { if (n == 0) return acc; return this.count(n - 1, acc + 1); }\n\
+             }\n\
+             print Counter().count(5000, 0);",
+            "5000"
+        );
+    }
+
+    #[test]
+    fn test_tail_call_backtrace() {
+        let code = "fun down(n) { if (n == 0) return nil + 1; return down(n - 1); }\n\
+                    fun start() { var x = down(5); return x; }\n\
+                    start();";
+        let tokens = scanner::scan_tokens(code.to_string()).unwrap();
+        let stmts = parser::parse(extensions::Extensions::default(), tokens).unwrap();
+        let mut interp = treewalk_interpreter::Interpreter::default();
+        assert!(interp.interpret(&stmts).is_err());
+        assert_eq!(
+            interp.format_backtrace(),
+            "Backtrace (most recent call last):\n\n\
+             [line ??] in script\n\
+             [line ??] in start\n\
+             ... 5 frames elided by tail calls ...\n\
+             [line ??] in down"
+        );
+    }
+
+    #[test]
+    fn test_json_round_trip() {
+        check_output_lists(
+            "var o = json.parse(r\"\"\"{\"name\": \"lox\", \"tags\": [\"a\", \"b\"], \"version\": 1.5,\n\
+               \"count\": 3, \"ok\": true, \"none\": null, \"nested\": {\"empty\": [], \"obj\": {}}}\"\"\");\n\
+             print o.name;\n\
+             print o.tags;\n\
+             print o.count + 1;\n\
+             print o.version;\n\
+             print json.stringify(o);\n\
+             print json.stringify(json.parse(json.stringify(o))) == json.stringify(o);\n\
+             class Point { init(x, y) { this.x = x; this.y = y; } }\n\
+             print json.stringify([Point(1, 2), nil, 2.0, [\"\\\"q\\\"\\n\"]], 2);\n\
+             print json.stringify([[]], \"\\t\");\n\
+             print json.parse(\" [1e2, -0.5, \\\"\\\\u00e9\\\"] \");",
+            "'lox'\n\
+             ['a', 'b']\n\
+             4\n\
+             1.5\n\
+             '{\"count\":3,\"name\":\"lox\",\"nested\":{\"empty\":[],\"obj\":{}},\"none\":null,\"ok\":true,\"tags\":[\"a\",\"b\"],\"version\":1.5}'\n\
+             true\n\
+             '[\n  {\n    \"x\": 1,\n    \"y\": 2\n  },\n  null,\n  2.0,\n  [\n    \"\\\"q\\\"\\n\"\n  ]\n]'\n\
+             '[\n\t[]\n]'\n\
+             [100, -0.5, 'é']"
+        );
+    }
+
+    #[test]
+    fn test_json_errors() {
+        let check = |code: &str, expected: &str| {
+            match evaluate(code, extensions::Extensions { lists: true, ..Default::default() }) {
+                Ok(output) => panic!("{:?}", output),
+                Err(err) => assert_eq!(err, expected),
+            }
+        };
+        check(
+            "json.parse(\"{\\\"a\\\": 1,\\n  \\\"b\\\": tru}\");",
+            "Invalid JSON at line 2, column 11: unexpected character '}'."
+        );
+        check("json.parse(\"[1, 2\");", "Invalid JSON at line 1, column 6: unexpected end of input.");
+        check("json.parse(\"01\");", "Invalid JSON at line 1, column 2: unexpected character '1'.");
+        check(
+            "var s = \"\";\nfor (var i = 0; i < 5000; i = i + 1) s = s + \"[\";\njson.parse(s);",
+            "Invalid JSON at line 1, column 513: nesting too deep."
+        );
+        check(
+            "var xs = [1];\npush(xs, xs);\njson.stringify(xs);",
+            "Cannot convert a cyclic structure to JSON."
+        );
+        check(
+            "class Node {}\nvar n = Node();\nn.next = [n];\njson.stringify(n);",
+            "Cannot convert a cyclic structure to JSON."
+        );
+        check(
+            "var xs = [];\nfor (var i = 0; i < 600; i = i + 1) xs = [xs];\njson.stringify(xs);",
+            "Cannot convert a structure nested more than 512 levels deep to JSON."
+        );
+        check("json.stringify(clock);", "Cannot convert a value of type NativeFunction to JSON.");
+        check(
+            "json.stringify(1, true);",
+            "Expected the indent to be a number of spaces or a string, got Bool."
+        );
+        check("json.parse = nil;", "Can't set attribute parse on a frozen instance.");
+    }
 }
@@ -4,10 +4,10 @@ use crate::bytecode_interpreter;
 use crate::gc;
 
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::{ HashMap, VecDeque };
 use std::rc::Rc;
 
-///
+/// 上值：还在栈上（Open）或者已经被关闭、搬到堆上（Closed）
 #[derive(Clone)]
 pub enum Upvalue {
     Open(usize),
@@ -48,6 +48,7 @@ pub struct Closure {
 #[derive(Clone)]
 pub struct NativeFunction {
     pub arity: u8,
+    pub optional_args: u8, // 末尾可以省略的参数个数，比如 sort 的比较函数
     pub name: String,
     pub func: fn(&mut bytecode_interpreter::Interpreter, &[Value]) -> Result<Value, String>,
 }
@@ -55,7 +56,22 @@ pub struct NativeFunction {
 #[derive(Clone)]
 pub struct Class {
     pub name: String,
+    pub superclass: Option<gc::HeapId>, // instanceof 沿着这条链往上找
     pub methods: HashMap<String, gc::HeapId>,
+    pub class_methods: HashMap<String, gc::HeapId>, // 静态方法，this 是类本身
+    pub getters: HashMap<String, gc::HeapId>,
+    pub setters: HashMap<String, gc::HeapId>,
+    pub traits: Vec<gc::HeapId>, // 混入的 trait，instanceof 也认它们
+}
+
+/**
+ * trait 不能实例化，混入的时候方法直接放进类的方法表
+ */
+#[derive(Clone)]
+pub struct Trait {
+    pub name: String,
+    pub methods: HashMap<String, gc::HeapId>,
+    pub required: Vec<String>, // 混入它的类必须提供的方法
 }
 
 /**
@@ -68,53 +84,445 @@ pub struct Instance {
 }
 
 /**
- * BoundMethod：与特定对象实例 绑定的函数
+ * BoundMethod：与特定对象实例 绑定的函数；静态方法绑定的是类本身
  */
 #[derive(Clone)]
 pub struct BoundMethod {
-    pub instance_id: gc::HeapId,
+    pub receiver: Value,
     pub closure_id: gc::HeapId,
 }
 
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum GeneratorState {
+    Suspended, // 还没开始，或者停在某个 yield 上
+    Running,
+    Done,
+}
+
+/**
+ * 生成器：暂停的时候把调用帧（ip）和它在栈上的槽位都收起来，next() 的时候再放回栈上
+ */
 #[derive(Clone)]
-pub enum Value {
+pub struct Generator {
+    pub closure: Closure,
+    pub ip: usize,
+    pub slots: Vec<Value>, // 0 号槽位、局部变量以及还没算完的临时值
+    // 暂停的时候指向这些槽位的上值先关闭，记下相对 0 号槽位的位置，恢复的时候重新打开
+    pub upvalues: Vec<(Rc<RefCell<Upvalue>>, usize)>,
+    pub state: GeneratorState,
+}
+
+/**
+ * 生成器上的方法，gen.next 取出来以后也可以单独调用
+ */
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum GeneratorMethod {
+    Next,
+    Iter,
+}
+
+/**
+ * 通道：在 fiber 之间传值。缓冲区满了（没有缓冲区的时候就是没人在等着收）send 会阻塞当前的 fiber，
+ * 没有值可收的时候 recv 会阻塞。close 以后还能收完剩下的值，再往后 recv 得到 nil
+ */
+#[derive(Clone, Default)]
+pub struct Channel {
+    pub capacity: usize,
+    pub buffer: VecDeque<Value>,
+    pub closed: bool,
+    pub receivers: VecDeque<usize>, // 阻塞在 recv 上的 fiber
+    pub senders: VecDeque<(usize, Value)>, // 阻塞在 send 上的 fiber 和它要发的值
+}
+
+/**
+ * 通道上的方法，next 和 recv 一样，for-in 靠它遍历通道
+ */
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ChannelMethod {
+    Send,
+    Recv,
+    Close,
+}
+
+/**
+ * isolate 上的方法，isolate 本身放在堆上（isolate::Isolate）
+ */
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum IsolateMethod {
+    Send,
+    Recv,
+    Join,
+    Kill,
+}
+
+/**
+ * async 函数的返回值。函数体在自己的 fiber 上跑完才有结果，在这之前 await 它的 fiber 都阻塞着
+ */
+#[derive(Clone, Default)]
+pub struct Promise {
+    pub value: Option<Value>,
+    pub waiters: Vec<usize>,
+}
+
+/**
+ * 枚举：每个变体有自己的名字和字段名，没有字段的变体本身就是一个值
+ */
+#[derive(Clone)]
+pub struct Enum {
+    pub name: String,
+    pub variants: Vec<EnumVariant>,
+}
+
+#[derive(Clone)]
+pub struct EnumVariant {
+    pub name: String,
+    pub fields: Vec<String>,
+}
+
+/**
+ * 变体的值：记下是哪个枚举的第几个变体（tag），字段按声明的顺序存放，创建以后不能修改
+ */
+#[derive(Clone)]
+pub struct Variant {
+    pub enum_id: gc::HeapId,
+    pub tag: usize,
+    pub fields: Vec<Value>,
+}
+
+/**
+ * 值是 64 位的 NaN-boxing：普通的浮点数按位原样存放，其余的类型都塞进 quiet NaN 的负载里，
+ * 入栈、出栈、读局部变量都只是复制一个 u64。要分情况处理的时候先 unbox 成 Unboxed 再 match
+ *
+ * 负载的布局（QNAN 的这些位全是 1 才算是装了别的类型）：
+ *   符号位是 1：整数，低 50 位是补码，放不下的整数放到堆上（BIG_INTEGER）
+ *   符号位是 0：第 45-49 位是标签，第 32-44 位是附加的小整数（方法、变体的下标），低 32 位是 HeapId
+ */
+#[derive(Copy, Clone)]
+pub struct Value(u64);
+
+const QNAN: u64 = 0x7ffc_0000_0000_0000;
+const CANONICAL_NAN: u64 = 0x7ff8_0000_0000_0000; // 算出来的 NaN 都换成它，免得和装箱的值混在一起
+const SIGN_BIT: u64 = 1 << 63;
+
+const INTEGER_BITS: u32 = 50;
+const INTEGER_MASK: u64 = (1 << INTEGER_BITS) - 1;
+
+const TAG_SHIFT: u32 = 45;
+const TAG_MASK: u64 = 0x1f;
+const EXTRA_SHIFT: u32 = 32;
+const EXTRA_MASK: u64 = 0x1fff;
+const ID_MASK: u64 = 0xffff_ffff;
+
+/// HeapId 只有 32 位的位置，堆分配 id 的时候绕回来，绕一次 Heap::epoch 加一
+pub const MAX_HEAP_ID: gc::HeapId = ID_MASK as gc::HeapId;
+/// 变体的下标放在附加的 13 位里，一个枚举最多这么多个变体
+pub const MAX_VARIANTS: usize = EXTRA_MASK as usize + 1;
+
+const TAG_NIL: u64 = 0;
+const TAG_BOOL: u64 = 1;
+const TAG_BIG_INTEGER: u64 = 2;
+const TAG_STRING: u64 = 3;
+const TAG_FUNCTION: u64 = 4;
+const TAG_INSTANCE: u64 = 5;
+const TAG_BOUND_METHOD: u64 = 6;
+const TAG_CLASS: u64 = 7;
+const TAG_TRAIT: u64 = 8;
+const TAG_NATIVE_FUNCTION: u64 = 9;
+const TAG_LIST: u64 = 10;
+const TAG_GENERATOR: u64 = 11;
+const TAG_GENERATOR_METHOD: u64 = 12;
+const TAG_ENUM: u64 = 13;
+const TAG_VARIANT: u64 = 14;
+const TAG_VARIANT_CONSTRUCTOR: u64 = 15;
+const TAG_CHANNEL: u64 = 16;
+const TAG_CHANNEL_METHOD: u64 = 17;
+const TAG_PROMISE: u64 = 18;
+const TAG_ISOLATE: u64 = 19;
+const TAG_ISOLATE_METHOD: u64 = 20;
+
+// 附加位里存的是方法在这些数组里的下标
+const GENERATOR_METHODS: [GeneratorMethod; 2] = [GeneratorMethod::Next, GeneratorMethod::Iter];
+const CHANNEL_METHODS: [ChannelMethod; 3] = [ChannelMethod::Send, ChannelMethod::Recv, ChannelMethod::Close];
+const ISOLATE_METHODS: [IsolateMethod; 4] = [
+    IsolateMethod::Send,
+    IsolateMethod::Recv,
+    IsolateMethod::Join,
+    IsolateMethod::Kill,
+];
+
+impl Value {
+    pub const NIL: Value = Value::tagged(TAG_NIL, 0, 0);
+
+    const fn tagged(tag: u64, extra: u64, id: u64) -> Value {
+        Value(QNAN | (tag << TAG_SHIFT) | (extra << EXTRA_SHIFT) | id)
+    }
+
+    fn heap_object(tag: u64, extra: u64, id: gc::HeapId) -> Value {
+        debug_assert!(id <= MAX_HEAP_ID && extra <= EXTRA_MASK);
+        Value::tagged(tag, extra, id as u64)
+    }
+
+    pub fn number(n: f64) -> Value {
+        if n.is_nan() {
+            Value(CANONICAL_NAN)
+        } else {
+            Value(n.to_bits())
+        }
+    }
+
+    pub fn bool(b: bool) -> Value {
+        Value::tagged(TAG_BOOL, 0, b as u64)
+    }
+
+    /**
+     * 放得进 50 位的整数直接装箱，放不下的返回 None，要用 gc::Heap::integer 放到堆上
+     */
+    pub fn small_integer(n: i64) -> Option<Value> {
+        let min = -(1i64 << (INTEGER_BITS - 1));
+        let max = (1i64 << (INTEGER_BITS - 1)) - 1;
+        if (min..=max).contains(&n) {
+            Some(Value(SIGN_BIT | QNAN | ((n as u64) & INTEGER_MASK)))
+        } else {
+            None
+        }
+    }
+
+    pub fn big_integer(id: gc::HeapId) -> Value {
+        Value::heap_object(TAG_BIG_INTEGER, 0, id)
+    }
+
+    pub fn string(id: gc::HeapId) -> Value {
+        Value::heap_object(TAG_STRING, 0, id)
+    }
+
+    pub fn function(id: gc::HeapId) -> Value {
+        Value::heap_object(TAG_FUNCTION, 0, id)
+    }
+
+    pub fn instance(id: gc::HeapId) -> Value {
+        Value::heap_object(TAG_INSTANCE, 0, id)
+    }
+
+    pub fn bound_method(id: gc::HeapId) -> Value {
+        Value::heap_object(TAG_BOUND_METHOD, 0, id)
+    }
+
+    pub fn class(id: gc::HeapId) -> Value {
+        Value::heap_object(TAG_CLASS, 0, id)
+    }
+
+    pub fn trait_(id: gc::HeapId) -> Value {
+        Value::heap_object(TAG_TRAIT, 0, id)
+    }
+
+    pub fn native_function(id: gc::HeapId) -> Value {
+        Value::heap_object(TAG_NATIVE_FUNCTION, 0, id)
+    }
+
+    pub fn list(id: gc::HeapId) -> Value {
+        Value::heap_object(TAG_LIST, 0, id)
+    }
+
+    pub fn generator(id: gc::HeapId) -> Value {
+        Value::heap_object(TAG_GENERATOR, 0, id)
+    }
+
+    pub fn generator_method(id: gc::HeapId, method: GeneratorMethod) -> Value {
+        Value::heap_object(TAG_GENERATOR_METHOD, method as u64, id)
+    }
+
+    pub fn enum_(id: gc::HeapId) -> Value {
+        Value::heap_object(TAG_ENUM, 0, id)
+    }
+
+    pub fn variant(id: gc::HeapId) -> Value {
+        Value::heap_object(TAG_VARIANT, 0, id)
+    }
+
+    pub fn variant_constructor(enum_id: gc::HeapId, tag: usize) -> Value {
+        Value::heap_object(TAG_VARIANT_CONSTRUCTOR, tag as u64, enum_id)
+    }
+
+    pub fn channel(id: gc::HeapId) -> Value {
+        Value::heap_object(TAG_CHANNEL, 0, id)
+    }
+
+    pub fn channel_method(id: gc::HeapId, method: ChannelMethod) -> Value {
+        Value::heap_object(TAG_CHANNEL_METHOD, method as u64, id)
+    }
+
+    pub fn promise(id: gc::HeapId) -> Value {
+        Value::heap_object(TAG_PROMISE, 0, id)
+    }
+
+    pub fn isolate(id: gc::HeapId) -> Value {
+        Value::heap_object(TAG_ISOLATE, 0, id)
+    }
+
+    pub fn isolate_method(id: gc::HeapId, method: IsolateMethod) -> Value {
+        Value::heap_object(TAG_ISOLATE_METHOD, method as u64, id)
+    }
+
+    fn is_boxed(self) -> bool {
+        self.0 & QNAN == QNAN
+    }
+
+    fn is_integer(self) -> bool {
+        self.0 & (SIGN_BIT | QNAN) == SIGN_BIT | QNAN
+    }
+
+    fn tag(self) -> u64 {
+        (self.0 >> TAG_SHIFT) & TAG_MASK
+    }
+
+    fn extra(self) -> usize {
+        ((self.0 >> EXTRA_SHIFT) & EXTRA_MASK) as usize
+    }
+
+    fn id(self) -> gc::HeapId {
+        (self.0 & ID_MASK) as gc::HeapId
+    }
+
+    pub fn is_nil(self) -> bool {
+        self.0 == Value::NIL.0
+    }
+
+    /**
+     * 值指向的堆对象，垃圾回收的时候从这里往下标记
+     */
+    pub fn heap_id(self) -> Option<gc::HeapId> {
+        if !self.is_boxed() || self.is_integer() {
+            return None;
+        }
+        match self.tag() {
+            TAG_NIL | TAG_BOOL => None,
+            _ => Some(self.id()),
+        }
+    }
+
+    /**
+     * 拆箱。只有放在堆上的大整数需要读堆
+     */
+    pub fn unbox(self, heap: &gc::Heap) -> Unboxed {
+        if !self.is_boxed() {
+            return Unboxed::Number(f64::from_bits(self.0));
+        }
+        if self.is_integer() {
+            // 先左移再算术右移，把第 49 位的符号扩展开
+            let shift = 64 - INTEGER_BITS;
+            return Unboxed::Integer(((self.0 << shift) as i64) >> shift);
+        }
+        let id = self.id();
+        match self.tag() {
+            TAG_NIL => Unboxed::Nil,
+            TAG_BOOL => Unboxed::Bool(id != 0),
+            TAG_BIG_INTEGER => Unboxed::Integer(heap.get_integer(id)),
+            TAG_STRING => Unboxed::String(id),
+            TAG_FUNCTION => Unboxed::Function(id),
+            TAG_INSTANCE => Unboxed::Instance(id),
+            TAG_BOUND_METHOD => Unboxed::BoundMethod(id),
+            TAG_CLASS => Unboxed::Class(id),
+            TAG_TRAIT => Unboxed::Trait(id),
+            TAG_NATIVE_FUNCTION => Unboxed::NativeFunction(id),
+            TAG_LIST => Unboxed::List(id),
+            TAG_GENERATOR => Unboxed::Generator(id),
+            TAG_GENERATOR_METHOD => Unboxed::GeneratorMethod(id, GENERATOR_METHODS[self.extra()]),
+            TAG_ENUM => Unboxed::Enum(id),
+            TAG_VARIANT => Unboxed::Variant(id),
+            TAG_VARIANT_CONSTRUCTOR => Unboxed::VariantConstructor(id, self.extra()),
+            TAG_CHANNEL => Unboxed::Channel(id),
+            TAG_CHANNEL_METHOD => Unboxed::ChannelMethod(id, CHANNEL_METHODS[self.extra()]),
+            TAG_PROMISE => Unboxed::Promise(id),
+            TAG_ISOLATE => Unboxed::Isolate(id),
+            TAG_ISOLATE_METHOD => Unboxed::IsolateMethod(id, ISOLATE_METHODS[self.extra()]),
+            tag => panic!("bad value tag {}", tag),
+        }
+    }
+}
+
+/**
+ * 拆箱以后的值，按类型分情况处理的时候 match 它
+ */
+#[derive(Copy, Clone)]
+pub enum Unboxed {
     Number(f64),
+    Integer(i64),
     Bool(bool),
     String(gc::HeapId),
     Function(gc::HeapId),
     Instance(gc::HeapId),
     BoundMethod(gc::HeapId),
     Class(gc::HeapId),
-    NativeFunction(NativeFunction),
+    Trait(gc::HeapId),
+    NativeFunction(gc::HeapId),
     Nil,
     List(gc::HeapId),
+    Generator(gc::HeapId),
+    GeneratorMethod(gc::HeapId, GeneratorMethod),
+    Enum(gc::HeapId),
+    Variant(gc::HeapId),
+    VariantConstructor(gc::HeapId, usize), // Shape.Circle：枚举和变体的下标，调用它得到一个变体的值
+    Channel(gc::HeapId),
+    ChannelMethod(gc::HeapId, ChannelMethod),
+    Promise(gc::HeapId),
+    Isolate(gc::HeapId),
+    IsolateMethod(gc::HeapId, IsolateMethod),
 }
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum Type {
     Number,
+    Integer,
     Bool,
     String,
     Function,
     NativeFunction,
     Class,
+    Trait,
     BoundMethod,
     Instance,
     Nil,
     List,
+    Generator,
+    Enum,
+    Variant,
+    Channel,
+    Promise,
+    Isolate,
 }
 
+/**
+ * 只看标签，不用拆箱
+ */
 pub fn type_of(value: &Value) -> Type {
-    match value {
-        Value::Number(_) => Type::Number,
-        Value::Bool(_) => Type::Bool,
-        Value::String(_) => Type::String,
-        Value::Function(_) => Type::Function,
-        Value::NativeFunction(_) => Type::NativeFunction,
-        Value::BoundMethod(_) => Type::BoundMethod,
-        Value::Class(_) => Type::Class,
-        Value::Instance(_) => Type::Instance,
-        Value::Nil => Type::Nil,
-        Value::List(_) => Type::List,
+    if !value.is_boxed() {
+        return Type::Number;
+    }
+    if value.is_integer() {
+        return Type::Integer;
+    }
+    match value.tag() {
+        TAG_NIL => Type::Nil,
+        TAG_BOOL => Type::Bool,
+        TAG_BIG_INTEGER => Type::Integer,
+        TAG_STRING => Type::String,
+        TAG_FUNCTION => Type::Function,
+        TAG_INSTANCE => Type::Instance,
+        TAG_BOUND_METHOD => Type::BoundMethod,
+        TAG_CLASS => Type::Class,
+        TAG_TRAIT => Type::Trait,
+        TAG_NATIVE_FUNCTION => Type::NativeFunction,
+        TAG_LIST => Type::List,
+        TAG_GENERATOR => Type::Generator,
+        TAG_GENERATOR_METHOD => Type::NativeFunction,
+        TAG_ENUM => Type::Enum,
+        TAG_VARIANT => Type::Variant,
+        TAG_VARIANT_CONSTRUCTOR => Type::NativeFunction,
+        TAG_CHANNEL => Type::Channel,
+        TAG_CHANNEL_METHOD => Type::NativeFunction,
+        TAG_PROMISE => Type::Promise,
+        TAG_ISOLATE => Type::Isolate,
+        TAG_ISOLATE_METHOD => Type::NativeFunction,
+        tag => panic!("bad value tag {}", tag),
     }
 }